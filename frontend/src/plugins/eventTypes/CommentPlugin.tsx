@@ -67,6 +67,15 @@ const CommentPlugin: React.FC<EventPluginProps> = ({
             "Geen inhoud"
           )}
         </div>
+        {comment.edited_at && (
+          <small
+            className="text-xs sm:text-sm lg:text-sm xl:text-base"
+            style={{ color: "var(--text-tertiary)" }}
+            title={new Date(comment.edited_at).toLocaleString("nl-NL")}
+          >
+            (bewerkt)
+          </small>
+        )}
         {comment.mentions && comment.mentions.length > 0 && (
           <div className="mt-2">
             <small
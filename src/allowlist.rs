@@ -0,0 +1,195 @@
+//! IP allowlisting of admin and integration routes.
+//!
+//! The gemeente's security baseline (BIO) asks for a network-level check on top of token
+//! authentication for management and machine-to-machine routes. With `ADMIN_ALLOWED_CIDRS`
+//! set (comma-separated ranges, e.g. `10.0.0.0/8,2001:db8::/32`), requests to the routes in
+//! [`PROTECTED_PREFIXES`] from any other address are refused with 403. Without it, nothing is
+//! restricted.
+//!
+//! Behind a reverse proxy (see the Caddyfile) every request comes from the proxy, so its
+//! ranges go in `TRUSTED_PROXIES`: for requests from a trusted proxy, the client is the last
+//! `X-Forwarded-For` address that is not a trusted proxy itself.
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+
+/// Admin, metrics, import/export, webhook-ingest and integration-configuration routes
+pub const PROTECTED_PREFIXES: &[&str] = &[
+    "/admin",
+    "/metrics",
+    "/debug",
+    "/reset",
+    "/registry",
+    "/resource-types",
+    "/projections",
+    "/usage",
+    "/integrations",
+    "/hooks",
+    "/api/email/inbound",
+    "/import",
+    "/export",
+];
+
+/// Is `path` one of the [`PROTECTED_PREFIXES`] or below one?
+pub fn is_protected(path: &str) -> bool {
+    PROTECTED_PREFIXES.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Parse comma-separated ranges; a bare address is a range of one.
+pub fn parse_ranges(value: &str) -> Result<Vec<IpNet>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.parse::<IpNet>()
+                .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("invalid address range {:?}", s))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IpAllowlist {
+    /// Where protected routes may be called from; empty: anywhere
+    pub allowed: Vec<IpNet>,
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl IpAllowlist {
+    /// The allowlist in `ADMIN_ALLOWED_CIDRS` and `TRUSTED_PROXIES`. Invalid ranges are
+    /// logged and skipped.
+    pub fn from_env() -> Self {
+        let ranges = |name: &str| {
+            let value = std::env::var(name).unwrap_or_default();
+            let mut ranges = Vec::new();
+            for part in value.split(',') {
+                match parse_ranges(part) {
+                    Ok(parsed) => ranges.extend(parsed),
+                    Err(e) => eprintln!("[allowlist] {}: {}", name, e),
+                }
+            }
+            ranges
+        };
+        IpAllowlist {
+            allowed: ranges("ADMIN_ALLOWED_CIDRS"),
+            trusted_proxies: ranges("TRUSTED_PROXIES"),
+        }
+    }
+
+    fn is_trusted_proxy(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+
+    /// The address of the client, given the connecting peer and the request headers.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted_proxy(&peer) {
+            return peer;
+        }
+        let forwarded: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|ip| ip.trim().parse().ok())
+            .collect();
+        forwarded
+            .into_iter()
+            .rev()
+            .find(|ip| !self.is_trusted_proxy(ip))
+            .unwrap_or(peer)
+    }
+
+    /// May a request from `client` reach `path`?
+    pub fn allows(&self, path: &str, client: Option<IpAddr>) -> bool {
+        if self.allowed.is_empty() || !is_protected(path) {
+            return true;
+        }
+        client.is_some_and(|ip| self.allowed.iter().any(|net| net.contains(&ip)))
+    }
+}
+
+/// The address of the client a request comes from, as [`ip_allowlist_middleware`] found it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Refuse requests to protected routes from outside the allowed ranges. The client address
+/// is left in the request extensions as a [`ClientIp`], for handlers that rate limit.
+pub async fn ip_allowlist_middleware(
+    State(allowlist): State<Arc<IpAllowlist>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let client = peer.map(|peer| allowlist.client_ip(peer, request.headers()));
+    if let Some(ip) = client {
+        request.extensions_mut().insert(ClientIp(ip));
+    }
+    let path = request.uri().path();
+    if !allowlist.allows(path, client) {
+        eprintln!(
+            "[allowlist] refused {} {} from {}",
+            request.method(),
+            path,
+            client.map_or("an unknown address".to_string(), |ip| ip.to_string())
+        );
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protected_routes_and_ranges() {
+        assert!(is_protected("/debug/db"));
+        assert!(is_protected("/hooks/mollie"));
+        assert!(is_protected("/registry"));
+        // Called by the signing provider (and HMAC-verified), and open to users for their
+        // own trail; admin rights are checked by the handlers
+        assert!(!is_protected("/signing/callback"));
+        assert!(!is_protected("/audit/actors/alice@gemeente.nl"));
+        assert!(!is_protected("/connectors"));
+        assert!(!is_protected("/registryx"));
+        assert!(!is_protected("/resources/issue-1"));
+        assert!(parse_ranges("10.0.0.0/8, 192.168.1.7,").unwrap().len() == 2);
+        assert!(parse_ranges("10.0.0.0/33").is_err());
+
+        let allowlist = IpAllowlist {
+            allowed: parse_ranges("10.1.0.0/16,2001:db8::/32").unwrap(),
+            trusted_proxies: parse_ranges("127.0.0.1").unwrap(),
+        };
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(allowlist.allows("/debug/db", Some(ip("10.1.2.3"))));
+        assert!(allowlist.allows("/registry", Some(ip("2001:db8::1"))));
+        assert!(!allowlist.allows("/debug/db", Some(ip("10.2.0.1"))));
+        assert!(!allowlist.allows("/debug/db", None));
+        assert!(allowlist.allows("/resources", Some(ip("8.8.8.8"))));
+        assert!(IpAllowlist::default().allows("/debug/db", Some(ip("8.8.8.8"))));
+
+        // Through the proxy, the client is the last forwarded address that isn't a proxy
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "10.2.0.1, 10.1.2.3".parse().unwrap());
+        assert_eq!(
+            allowlist.client_ip(ip("127.0.0.1"), &headers),
+            ip("10.1.2.3")
+        );
+        // Only a trusted proxy may say where a request comes from
+        assert_eq!(allowlist.client_ip(ip("8.8.8.8"), &headers), ip("8.8.8.8"));
+    }
+}
@@ -0,0 +1,325 @@
+//! Assignment suggestions: which behandelaar should pick up an issue.
+//!
+//! Behandelaars are the users that have issues assigned to them or, for an issue assigned to
+//! a team, the team's members. For a given issue they are ranked on three signals derived
+//! from stored resources and events:
+//! - workload: their open (not closed) assigned issues, which lowers the score;
+//! - department match: tags of the issue that also occur on issues they handled, which
+//!   stands in for departments until those are modelled;
+//! - recent activity: commits they made in the last [`RECENT_ACTIVITY_DAYS`] days, so
+//!   people who are around are preferred.
+//!
+//! Behandelaars who are away (see `availability`) are not suggested.
+//!
+//! With `AUTO_ASSIGN_TENANT` set, issues created without an assignee are assigned to the
+//! top-ranked behandelaar of that tenant, with a patch commit by "system".
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::{tenant_of, AuthUser};
+use crate::availability::user_is_away;
+use crate::handlers::{authorize_issue, commit_of, submit_event, AppState, CATCHUP_PAGE_SIZE};
+use crate::schemas::{CloudEvent, CloudEventBuilder, CommitBuilder, Issue, IssueStatus};
+use crate::storage::Storage;
+use crate::teams::get_team;
+
+/// Commits within this many days count as recent activity
+pub const RECENT_ACTIVITY_DAYS: i64 = 14;
+
+/// Score per tag the issue shares with issues a behandelaar handled
+const TAG_WEIGHT: f64 = 2.0;
+
+/// Score subtracted per open issue a behandelaar already has
+const WORKLOAD_WEIGHT: f64 = 0.5;
+
+/// Recent commits beyond this count add no further score
+const ACTIVITY_CAP: usize = 20;
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct SuggestionParams {
+    /// The issue to find a behandelaar for
+    pub issue_id: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AssignmentSuggestion {
+    pub user: String,
+    /// Higher is a better fit
+    pub score: f64,
+    /// Open issues currently assigned to the user
+    pub open_issues: usize,
+    /// Tags of the issue that also occur on issues the user handled
+    pub shared_tags: Vec<String>,
+    /// Commits by the user in the last `RECENT_ACTIVITY_DAYS` days
+    pub recent_commits: usize,
+}
+
+#[derive(Default)]
+struct Behandelaar {
+    open_issues: usize,
+    tags: HashSet<String>,
+}
+
+/// Behandelaars of `tenant`, or of the issue's team, best fit for `issue_id` first.
+pub async fn suggest(
+    storage: &Storage,
+    issue_id: &str,
+    tenant: &str,
+) -> Result<Vec<AssignmentSuggestion>, Box<dyn std::error::Error + Send + Sync>> {
+    let issue: Option<Issue> = storage
+        .get_resource(issue_id)
+        .await?
+        .and_then(|value| serde_json::from_value(value).ok());
+    let issue_tags: HashSet<String> = issue
+        .as_ref()
+        .and_then(|issue| issue.tags.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let team = match issue.and_then(|issue| issue.team) {
+        Some(id) => get_team(storage, &id).await?,
+        None => None,
+    };
+
+    // A team's members are candidates even before they have any issues
+    let mut behandelaars: HashMap<String, Behandelaar> = HashMap::new();
+    if let Some(team) = &team {
+        for member in &team.members {
+            behandelaars.insert(member.clone(), Behandelaar::default());
+        }
+    }
+    let in_scope = |user: &str| match &team {
+        Some(team) => team.members.iter().any(|m| m == user),
+        None => tenant_of(user) == tenant,
+    };
+    for (id, value) in storage.list_resources(0, usize::MAX).await? {
+        let Ok(issue) = serde_json::from_value::<Issue>(value) else {
+            continue;
+        };
+        if id == issue_id {
+            continue;
+        }
+        let Some(assignee) = issue.assignee else {
+            continue;
+        };
+        if !in_scope(&assignee) {
+            continue;
+        }
+        let entry = behandelaars.entry(assignee).or_default();
+        if !matches!(issue.status, IssueStatus::Closed) {
+            entry.open_issues += 1;
+        }
+        entry.tags.extend(issue.tags.unwrap_or_default());
+    }
+
+    // Nobody who is away gets new work
+    let mut available = Vec::new();
+    for (user, behandelaar) in behandelaars {
+        if !user_is_away(storage, &user).await? {
+            available.push((user, behandelaar));
+        }
+    }
+
+    let recent = recent_commits(storage).await?;
+    let mut suggestions: Vec<AssignmentSuggestion> = available
+        .into_iter()
+        .map(|(user, b)| {
+            let mut shared_tags: Vec<String> = issue_tags.intersection(&b.tags).cloned().collect();
+            shared_tags.sort();
+            let recent_commits = recent.get(&user).copied().unwrap_or(0);
+            let score = shared_tags.len() as f64 * TAG_WEIGHT
+                + recent_commits.min(ACTIVITY_CAP) as f64 / ACTIVITY_CAP as f64
+                - b.open_issues as f64 * WORKLOAD_WEIGHT;
+            AssignmentSuggestion {
+                user,
+                score,
+                open_issues: b.open_issues,
+                shared_tags,
+                recent_commits,
+            }
+        })
+        .collect();
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.user.cmp(&b.user)));
+    Ok(suggestions)
+}
+
+/// Number of commits per actor in the last `RECENT_ACTIVITY_DAYS` days.
+async fn recent_commits(
+    storage: &Storage,
+) -> Result<HashMap<String, usize>, Box<dyn std::error::Error + Send + Sync>> {
+    let since = chrono::Utc::now() - chrono::Duration::days(RECENT_ACTIVITY_DAYS);
+    let mut counts = HashMap::new();
+    let mut after = None;
+    loop {
+        let page = storage
+            .list_events_after(after.take(), CATCHUP_PAGE_SIZE)
+            .await?;
+        for event in &page {
+            let recent = event
+                .time
+                .as_deref()
+                .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+                .is_some_and(|t| t >= since);
+            if let (true, Some(commit)) = (recent, commit_of(event)) {
+                *counts.entry(commit.actor).or_insert(0) += 1;
+            }
+        }
+        if page.len() < CATCHUP_PAGE_SIZE {
+            return Ok(counts);
+        }
+        after = page.last().and_then(|e| e.sequence.clone());
+    }
+}
+
+/// Assign a newly created, unassigned issue to the best behandelaar of the tenant in
+/// `AUTO_ASSIGN_TENANT`. Does nothing if that variable is unset.
+pub async fn auto_assign(
+    state: &AppState,
+    event: &CloudEvent,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Ok(tenant) = std::env::var("AUTO_ASSIGN_TENANT") else {
+        return Ok(());
+    };
+    let Some(commit) = commit_of(event) else {
+        return Ok(());
+    };
+    let Some(data) = commit.resource_data.as_ref() else {
+        return Ok(());
+    };
+    let is_unassigned_issue = commit.deleted != Some(true)
+        && commit.schema.trim_end_matches(".json").ends_with("/Issue")
+        && data.get("assignee").is_none_or(|a| a.is_null());
+    if !is_unassigned_issue {
+        return Ok(());
+    }
+
+    let suggestions = suggest(&state.storage, &commit.resource_id, &tenant).await?;
+    let Some(best) = suggestions.first() else {
+        return Ok(());
+    };
+    let assign =
+        CommitBuilder::patch::<Issue>(commit.resource_id.clone(), json!({ "assignee": best.user }))
+            .build();
+    submit_event(
+        state,
+        CloudEventBuilder::commit(commit.resource_id, &assign).build(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// GET /assignment/suggestions - Behandelaars ranked by fit for an issue
+#[utoipa::path(
+    get,
+    path = "/assignment/suggestions",
+    tag = "resources",
+    params(SuggestionParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Behandelaars of the caller's tenant, best fit first", body = [AssignmentSuggestion]),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn suggestions_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<SuggestionParams>,
+) -> Result<Json<Vec<AssignmentSuggestion>>, StatusCode> {
+    let user = auth_user.user_id;
+    authorize_issue(&state, &user, &params.issue_id).await?;
+    let suggestions = suggest(&state.storage, &params.issue_id, tenant_of(&user))
+        .await
+        .map_err(|e| {
+            eprintln!(
+                "[assignment] failed to rank behandelaars for {}: {}",
+                params.issue_id, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(suggestions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user, create_issue, test_state};
+
+    #[tokio::test]
+    async fn test_suggestions_rank_by_tags_and_workload() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let melder = "melder@example.com";
+
+        let issue = |assignee: Option<&str>, status: IssueStatus, tags: &[&str]| Issue {
+            status,
+            assignee: assignee.map(str::to_string),
+            tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+            ..crate::handlers::tests::issue("Zaak", &[melder])
+        };
+        for (id, issue) in [
+            (
+                "afval-1",
+                issue(Some("ans@gemeente.nl"), IssueStatus::Closed, &["afval"]),
+            ),
+            (
+                "parkeren-1",
+                issue(Some("bart@gemeente.nl"), IssueStatus::Open, &["parkeren"]),
+            ),
+            (
+                "parkeren-2",
+                issue(Some("cor@gemeente.nl"), IssueStatus::Open, &["parkeren"]),
+            ),
+            (
+                "parkeren-3",
+                issue(Some("cor@gemeente.nl"), IssueStatus::InProgress, &[]),
+            ),
+            (
+                "elders",
+                issue(Some("dirk@andere.nl"), IssueStatus::Closed, &["afval"]),
+            ),
+            ("nieuw", issue(None, IssueStatus::Open, &["parkeren"])),
+        ] {
+            let actor = issue.assignee.as_deref().unwrap_or(melder);
+            create_issue(&state, id, &issue, actor).await;
+        }
+
+        let suggestions = |who: &str| {
+            suggestions_handler(
+                State(state.clone()),
+                auth_user(who),
+                Query(SuggestionParams {
+                    issue_id: "nieuw".to_string(),
+                }),
+            )
+        };
+        // The melder's tenant has no behandelaars
+        assert!(suggestions(melder).await.unwrap().0.is_empty());
+        assert_eq!(
+            suggestions("bart@gemeente.nl").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+
+        let suggestions = suggest(&state.storage, "nieuw", "gemeente.nl")
+            .await
+            .unwrap();
+        let users: Vec<&str> = suggestions.iter().map(|s| s.user.as_str()).collect();
+        // Bart knows parking and has less work than Cor; Ans is free but handles waste
+        assert_eq!(
+            users,
+            vec!["bart@gemeente.nl", "cor@gemeente.nl", "ans@gemeente.nl"]
+        );
+        assert_eq!(suggestions[0].shared_tags, vec!["parkeren"]);
+        assert_eq!(suggestions[1].open_issues, 2);
+        assert_eq!(suggestions[2].recent_commits, 1);
+    }
+}
@@ -0,0 +1,900 @@
+//! The AsyncAPI specification of the event API, generated from the code.
+//!
+//! `GET /asyncapi-docs/asyncapi.yaml` and `/asyncapi.json` render the spec from
+//! [`get_all_schemas`] for the base URL the request came in on, so it always matches the
+//! running code. That is `BASE_URL`, or one of `TENANT_BASE_URLS` (comma-separated) when the
+//! request's host is one of those. Rendered documents are cached per base URL. The
+//! `generate_asyncapi` binary writes the same spec to files, for the HTML documentation.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde_json::{json, Value};
+
+use crate::handlers::AppState;
+use crate::schemas::{get_all_schemas, schema_base_url};
+
+/// The spec for one base URL, in both formats
+pub struct RenderedSpec {
+    pub json: String,
+    pub yaml: String,
+}
+
+/// Rendered specs per base URL
+#[derive(Default)]
+pub struct SpecCache(Mutex<HashMap<String, Arc<RenderedSpec>>>);
+
+impl SpecCache {
+    /// The spec for `base_url`, rendered on first use.
+    pub fn get(
+        &self,
+        base_url: &str,
+    ) -> Result<Arc<RenderedSpec>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut cache = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(spec) = cache.get(base_url) {
+            return Ok(spec.clone());
+        }
+        let spec = asyncapi_spec(&get_all_schemas(), base_url, false);
+        let rendered = Arc::new(RenderedSpec {
+            json: serde_json::to_string_pretty(&spec)?,
+            yaml: serde_yaml::to_string(&spec)?,
+        });
+        cache.insert(base_url.to_string(), rendered.clone());
+        Ok(rendered)
+    }
+}
+
+/// The base URLs of tenants served under their own domain (`TENANT_BASE_URLS`)
+fn tenant_base_urls() -> Vec<String> {
+    std::env::var("TENANT_BASE_URLS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+        .collect()
+}
+
+/// The one of `known` base URLs whose host the request was sent to, else `default`.
+/// Only configured URLs are used, so clients cannot put arbitrary hosts in the spec.
+pub fn request_base_url(headers: &HeaderMap, default: &str, known: &[String]) -> String {
+    let host = headers
+        .get("x-forwarded-host")
+        .or_else(|| headers.get(header::HOST))
+        .and_then(|v| v.to_str().ok())
+        .map(|host| host.trim().to_ascii_lowercase());
+    host.and_then(|host| {
+        known.iter().find(|url| {
+            let url_host = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+            url_host.trim_end_matches('/').eq_ignore_ascii_case(&host)
+        })
+    })
+    .cloned()
+    .unwrap_or_else(|| default.trim_end_matches('/').to_string())
+}
+
+fn rendered_spec(headers: &HeaderMap, cache: &SpecCache) -> Result<Arc<RenderedSpec>, StatusCode> {
+    let base_url = request_base_url(headers, &schema_base_url(), &tenant_base_urls());
+    cache.get(&base_url).map_err(|e| {
+        eprintln!(
+            "[asyncapi] failed to render the spec for {}: {}",
+            base_url, e
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// GET /asyncapi-docs/asyncapi.yaml - The AsyncAPI spec as YAML
+pub async fn asyncapi_yaml_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let spec = rendered_spec(&headers, &state.asyncapi)?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-yaml")],
+        spec.yaml.clone(),
+    )
+        .into_response())
+}
+
+/// GET /asyncapi-docs/asyncapi.json - The AsyncAPI spec as JSON
+pub async fn asyncapi_json_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let spec = rendered_spec(&headers, &state.asyncapi)?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/json")],
+        spec.json.clone(),
+    )
+        .into_response())
+}
+
+/// The AsyncAPI 3.0 document of the event API served at `base_url`. With `embed_schemas`,
+/// the message schemas are included instead of referenced by their URL under `/schemas`.
+pub fn asyncapi_spec(
+    schemas: &HashMap<String, Value>,
+    base_url: &str,
+    embed_schemas: bool,
+) -> Value {
+    let server_host = base_url
+        .trim_start_matches("http://")
+        .trim_start_matches("https://");
+    let is_https = base_url.starts_with("https://");
+
+    json!({
+        "asyncapi": "3.0.0",
+        "info": {
+            "title": "SSE Delta Snapshot API",
+            "version": "1.0.0",
+            "description": "Server-Sent Events API for real-time CloudEvents streaming with delta snapshots for Dutch municipal case management",
+            "contact": {
+                "name": "VNG Realisatie",
+                "url": "https://www.vngrealisatie.nl"
+            },
+            "license": {
+                "name": "EUPL-1.2",
+                "url": "https://opensource.org/licenses/EUPL-1.2"
+            }
+        },
+        "defaultContentType": "application/json",
+        "servers": {
+            "development": {
+                "host": server_host,
+                "protocol": if is_https { "https" } else { "http" },
+                "description": "Development/Production server"
+            }
+        },
+        "channels": {
+            "/events": {
+                "address": "/events",
+                "messages": {
+                    "CloudEvent": {
+                        "$ref": "#/components/messages/CloudEvent"
+                    }
+                },
+                "description": "Server-Sent Events stream for real-time CloudEvents delivery. Selected with `Accept: text/event-stream`; sends a snapshot of stored events first, then live events and periodic `checkpoint` events carrying the last sequence the stream considered, delivered or not.",
+                "bindings": {
+                    "http": {
+                        "type": "request",
+                        "method": "GET",
+                        "headers": {
+                            "type": "object",
+                            "properties": {
+                                "Accept": {
+                                    "type": "string",
+                                    "const": "text/event-stream"
+                                },
+                                "Cache-Control": {
+                                    "type": "string",
+                                    "const": "no-cache"
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "eventsListing": {
+                "address": "/events",
+                "title": "Event listing (JSON mode)",
+                "messages": {
+                    "CloudEventBatch": {
+                        "$ref": "#/components/messages/CloudEventBatch"
+                    }
+                },
+                "description": "The same `/events` address returns a paginated JSON (or CBOR) array of stored CloudEvents when the client does not ask for `text/event-stream`, or passes `format=json`. Used for catch-up sync with `after_seq`."
+            },
+            "/events/stream": {
+                "address": "/events/stream",
+                "title": "Legacy event stream",
+                "messages": {
+                    "CloudEvent": {
+                        "$ref": "#/components/messages/CloudEvent"
+                    }
+                },
+                "description": "Legacy Server-Sent Events endpoint, kept for backward compatibility. Only delivers live events (no snapshot) and supports the `types` filter."
+            },
+            "/api/email/inbound": {
+                "address": "/api/email/inbound",
+                "title": "Inbound email webhook",
+                "messages": {
+                    "InboundEmail": {
+                        "$ref": "#/components/messages/InboundEmail"
+                    }
+                },
+                "description": "Webhook called by Postmark for replies to notification emails. The reply is turned into a comment CloudEvent on the issue and delivered on the event channels."
+            },
+            "webPush": {
+                "address": null,
+                "title": "Web Push notifications",
+                "messages": {
+                    "PushNotification": {
+                        "$ref": "#/components/messages/PushNotification"
+                    }
+                },
+                "description": "Notifications delivered through the Web Push protocol (VAPID) to the endpoint of each registered browser push subscription."
+            }
+        },
+        "operations": {
+            "subscribeToEvents": {
+                "action": "receive",
+                "channel": {
+                    "$ref": "#/channels/~1events"
+                },
+                "title": "Subscribe to CloudEvents Stream",
+                "summary": "Receive real-time CloudEvents via Server-Sent Events",
+                "description": "Establishes a persistent SSE connection to receive real-time CloudEvents for case management updates including issues, tasks, planning, documents, and comments.",
+                "security": [
+                    { "$ref": "#/components/securitySchemes/bearer" },
+                    { "$ref": "#/components/securitySchemes/queryToken" }
+                ],
+                "bindings": {
+                    "http": {
+                        "method": "GET",
+                        "query": event_filter_query()
+                    }
+                }
+            },
+            "listEvents": {
+                "action": "receive",
+                "channel": {
+                    "$ref": "#/channels/eventsListing"
+                },
+                "title": "List CloudEvents",
+                "summary": "Fetch stored CloudEvents as a JSON array",
+                "description": "Returns stored CloudEvents in sequence order. Pass the `sequence` of the last event received as `after_seq` to fetch only newer events.",
+                "security": [
+                    { "$ref": "#/components/securitySchemes/bearer" },
+                    { "$ref": "#/components/securitySchemes/queryToken" }
+                ],
+                "bindings": {
+                    "http": {
+                        "method": "GET",
+                        "query": event_filter_query()
+                    }
+                }
+            },
+            "subscribeToLegacyStream": {
+                "action": "receive",
+                "channel": {
+                    "$ref": "#/channels/~1events~1stream"
+                },
+                "title": "Subscribe to legacy event stream",
+                "summary": "Receive live CloudEvents via the legacy SSE endpoint",
+                "bindings": {
+                    "http": {
+                        "method": "GET",
+                        "query": {
+                            "type": "object",
+                            "properties": {
+                                "types": types_filter_schema()
+                            }
+                        }
+                    }
+                }
+            },
+            "sendEvent": {
+                "action": "send",
+                "channel": {
+                    "$ref": "#/channels/~1events"
+                },
+                "title": "Send CloudEvent",
+                "summary": "Submit a CloudEvent to trigger case management actions",
+                "description": "Submit a CloudEvent via HTTP POST to create, update, or delete case management entities like issues, tasks, planning items, documents, and comments.",
+                "security": [
+                    { "$ref": "#/components/securitySchemes/bearer" }
+                ],
+                "bindings": {
+                    "http": {
+                        "method": "POST"
+                    }
+                }
+            },
+            "receiveInboundEmail": {
+                "action": "receive",
+                "channel": {
+                    "$ref": "#/channels/~1api~1email~1inbound"
+                },
+                "title": "Receive inbound email",
+                "summary": "Postmark delivers email replies to this webhook",
+                "bindings": {
+                    "http": {
+                        "method": "POST"
+                    }
+                }
+            },
+            "sendPushNotification": {
+                "action": "send",
+                "channel": {
+                    "$ref": "#/channels/webPush"
+                },
+                "title": "Send push notification",
+                "summary": "Notify subscribed browsers about new events"
+            }
+        },
+        "components": {
+            "messages": {
+                "CloudEvent": {
+                    "name": "CloudEvent",
+                    "title": "CloudEvent 1.0",
+                    "summary": "CloudEvents 1.0 compliant event for case management",
+                    "description": "A CloudEvent containing case management data following the CloudEvents specification v1.0. The event carries structured data about municipal case management operations.",
+                    "contentType": "application/json",
+                    "payload": {
+                        "$ref": if embed_schemas {
+                            "#/components/schemas/CloudEvent".to_string()
+                        } else {
+                            format!("{}/schemas/CloudEvent", base_url)
+                        }
+                    },
+                    "examples": generate_message_examples(base_url, embed_schemas)
+                },
+                "CloudEventBatch": {
+                    "name": "CloudEventBatch",
+                    "title": "CloudEvents batch",
+                    "summary": "Page of stored CloudEvents",
+                    "contentType": "application/json",
+                    "payload": {
+                        "type": "array",
+                        "items": {
+                            "$ref": if embed_schemas {
+                                "#/components/schemas/CloudEvent".to_string()
+                            } else {
+                                format!("{}/schemas/CloudEvent", base_url)
+                            }
+                        }
+                    }
+                },
+                "InboundEmail": {
+                    "name": "InboundEmail",
+                    "title": "Postmark inbound email",
+                    "summary": "Email reply forwarded by Postmark",
+                    "contentType": "application/json",
+                    "payload": {
+                        "type": "object",
+                        "properties": {
+                            "From": { "type": "string" },
+                            "OriginalRecipient": {
+                                "type": "string",
+                                "description": "Reply-to address; the issue ID follows the `+`"
+                            },
+                            "TextBody": { "type": "string" },
+                            "StrippedTextReply": { "type": "string" }
+                        },
+                        "required": ["From", "OriginalRecipient"]
+                    }
+                },
+                "PushNotification": {
+                    "name": "PushNotification",
+                    "title": "Web Push notification",
+                    "contentType": "application/json",
+                    "payload": {
+                        "type": "object",
+                        "properties": {
+                            "title": { "type": "string" },
+                            "body": { "type": "string" },
+                            "icon": { "type": "string" },
+                            "badge": { "type": "string" },
+                            "data": {
+                                "type": "object",
+                                "properties": {
+                                    "url": { "type": "string" },
+                                    "eventId": { "type": "string" },
+                                    "actor": { "type": ["string", "null"] }
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "securitySchemes": {
+                "bearer": {
+                    "type": "httpBearer",
+                    "scheme": "bearer",
+                    "bearerFormat": "JWT",
+                    "description": "JWT obtained through the magic link login flow"
+                },
+                "queryToken": {
+                    "type": "httpApiKey",
+                    "name": "token",
+                    "in": "query",
+                    "description": "The same JWT passed as `?token=`, for EventSource clients that cannot set headers"
+                }
+            },
+            "schemas": if embed_schemas {
+                generate_embedded_schemas(schemas)
+            } else {
+                generate_schema_references(base_url, schemas)
+            }
+        }
+    })
+}
+
+/// Query parameters accepted by `GET /events`, in both SSE and JSON listing mode.
+fn event_filter_query() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "topic": {
+                "type": "string",
+                "description": "Only deliver events whose subject or type contains this value"
+            },
+            "types": types_filter_schema(),
+            "after_seq": {
+                "type": "string",
+                "description": "Only deliver events after this zero-padded sequence",
+                "examples": ["00000000000000000042"]
+            },
+            "offset": { "type": "integer", "minimum": 0, "default": 0 },
+            "limit": { "type": "integer", "minimum": 1, "default": 10000 },
+            "format": {
+                "type": "string",
+                "enum": ["json"],
+                "description": "Force the JSON listing, regardless of the Accept header"
+            },
+            "token": {
+                "type": "string",
+                "description": "JWT, for clients that cannot set the Authorization header"
+            }
+        }
+    })
+}
+
+fn types_filter_schema() -> Value {
+    json!({
+        "type": "string",
+        "description": "Comma-separated list of event types to deliver; other types are skipped",
+        "examples": ["json.commit,system.reset"]
+    })
+}
+
+fn generate_schema_references(base_url: &str, schemas: &HashMap<String, Value>) -> Value {
+    let mut schema_refs = json!({});
+
+    // Create references to the actual hosted schema URLs
+    for schema_name in schemas.keys() {
+        schema_refs[schema_name] = json!({
+            "$ref": format!("{}/schemas/{}", base_url, schema_name)
+        });
+    }
+
+    schema_refs
+}
+
+fn generate_embedded_schemas(schemas: &HashMap<String, Value>) -> Value {
+    let mut embedded_schemas = json!({});
+
+    // Embed the actual schemas instead of creating references
+    for (schema_name, schema) in schemas {
+        embedded_schemas[schema_name] = schema.clone();
+    }
+
+    embedded_schemas
+}
+
+fn generate_message_examples(base_url: &str, embed_schemas: bool) -> Vec<Value> {
+    vec![
+        json!({
+            "name": "IssueCreated",
+            "summary": "New municipal case created",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7E8",
+                "source": "frontend-demo-event",
+                "subject": "1",
+                "type": "json.commit",
+                "time": "2025-01-15T10:30:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": if embed_schemas {
+                    "#/components/schemas/JSONCommit".to_string()
+                } else {
+                    format!("{}/schemas/JSONCommit", base_url)
+                },
+                "data": {
+                    "schema": format!("{}/schemas/Issue", base_url),
+                    "resource_id": "1",
+                    "actor": "user@gemeente.nl",
+                    "timestamp": "2025-01-15T10:30:00Z",
+                    "resource_data": {
+                        "id": "1",
+                        "title": "Paspoort aanvragen",
+                        "description": "Nieuwe paspoort aanvraag ingediend door burger",
+                        "status": "open",
+                        "assignee": "alice@gemeente.nl",
+                        "created_at": "2025-01-15T10:30:00Z"
+                    }
+                }
+            }
+        }),
+        json!({
+            "name": "IssueUpdated",
+            "summary": "Municipal case updated with partial changes",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7E9",
+                "source": "frontend-demo-event",
+                "subject": "1",
+                "type": "json.commit",
+                "time": "2025-01-15T11:15:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": if embed_schemas {
+                    "#/components/schemas/JSONCommit".to_string()
+                } else {
+                    format!("{}/schemas/JSONCommit", base_url)
+                },
+                "data": {
+                    "schema": format!("{}/schemas/Issue", base_url),
+                    "resource_id": "1",
+                    "actor": "alice@gemeente.nl",
+                    "timestamp": "2025-01-15T11:15:00Z",
+                    "patch": {
+                        "status": "in_progress",
+                        "assignee": "bob@gemeente.nl"
+                    }
+                }
+            }
+        }),
+        json!({
+            "name": "IssueDeleted",
+            "summary": "Municipal case deleted",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7F0",
+                "source": "frontend-demo-event",
+                "subject": "1",
+                "type": "json.commit",
+                "time": "2025-01-15T16:45:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": if embed_schemas {
+                    "#/components/schemas/JSONCommit".to_string()
+                } else {
+                    format!("{}/schemas/JSONCommit", base_url)
+                },
+                "data": {
+                    "schema": format!("{}/schemas/Issue", base_url),
+                    "resource_id": "1",
+                    "actor": "admin@gemeente.nl",
+                    "timestamp": "2025-01-15T16:45:00Z",
+                    "deleted": true,
+                    "deletion_reason": "Duplicate case - merged with case #3"
+                }
+            }
+        }),
+        json!({
+            "name": "TaskAssigned",
+            "summary": "Task assigned to case worker",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7F1",
+                "source": "workflow-engine",
+                "subject": "1",
+                "type": "json.commit",
+                "time": "2025-01-15T10:35:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": if embed_schemas {
+                    "#/components/schemas/JSONCommit".to_string()
+                } else {
+                    format!("{}/schemas/JSONCommit", base_url)
+                },
+                "data": {
+                    "item_type": "task",
+                    "item_id": "task-1001",
+                    "actor": "system@gemeente.nl",
+                    "timestamp": "2025-01-15T10:35:00Z",
+                    "resource_data": {
+                        "cta": "Documenten Controleren",
+                        "description": "Controleer de ingediende paspoort aanvraag documenten",
+                        "url": "/review/passport-1",
+                        "completed": false,
+                        "deadline": "2025-01-20"
+                    },
+                    "itemschema": format!("{}/schemas/Task", base_url)
+                }
+            }
+        }),
+        json!({
+            "name": "TaskCompleted",
+            "summary": "Task marked as completed",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7F2",
+                "source": "frontend-user-action",
+                "subject": "1",
+                "type": "json.commit",
+                "time": "2025-01-15T14:30:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": if embed_schemas {
+                    "#/components/schemas/JSONCommit".to_string()
+                } else {
+                    format!("{}/schemas/JSONCommit", base_url)
+                },
+                "data": {
+                    "item_type": "task",
+                    "item_id": "task-1001",
+                    "actor": "alice@gemeente.nl",
+                    "timestamp": "2025-01-15T14:30:00Z",
+                    "patch": {
+                        "completed": true,
+                        "completed_at": "2025-01-15T14:30:00Z"
+                    },
+                    "itemschema": format!("{}/schemas/Task", base_url)
+                }
+            }
+        }),
+        json!({
+            "name": "DocumentUploaded",
+            "summary": "Document attached to case",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7F3",
+                "source": "document-service",
+                "subject": "1",
+                "type": "json.commit",
+                "time": "2025-01-15T10:40:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": if embed_schemas {
+                    "#/components/schemas/JSONCommit".to_string()
+                } else {
+                    format!("{}/schemas/JSONCommit", base_url)
+                },
+                "data": {
+                    "item_type": "document",
+                    "item_id": "doc-1001",
+                    "actor": "user@gemeente.nl",
+                    "timestamp": "2025-01-15T10:40:00Z",
+                    "resource_data": {
+                        "title": "Paspoortfoto_Officieel.jpg",
+                        "url": "https://example.com/documents/passport-photo-12345.jpg",
+                        "size": 89765
+                    },
+                    "itemschema": format!("{}/schemas/Document", base_url)
+                }
+            }
+        }),
+        json!({
+            "name": "DocumentDeleted",
+            "summary": "Document removed from case",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7F4",
+                "source": "document-service",
+                "subject": "1",
+                "type": "json.commit",
+                "time": "2025-01-15T15:20:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": if embed_schemas {
+                    "#/components/schemas/JSONCommit".to_string()
+                } else {
+                    format!("{}/schemas/JSONCommit", base_url)
+                },
+                "data": {
+                    "schema": format!("{}/schemas/Document", base_url),
+                    "resource_id": "doc-1001",
+                    "actor": "alice@gemeente.nl",
+                    "timestamp": "2025-01-15T15:20:00Z",
+                    "deleted": true,
+                    "deletion_reason": "Incorrect document uploaded"
+                }
+            }
+        }),
+        json!({
+            "name": "PlanningCreated",
+            "summary": "Case planning timeline created",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7F5",
+                "source": "planning-service",
+                "subject": "2",
+                "type": "json.commit",
+                "time": "2025-01-15T11:00:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": if embed_schemas {
+                    "#/components/schemas/JSONCommit".to_string()
+                } else {
+                    format!("{}/schemas/JSONCommit", base_url)
+                },
+                "data": {
+                    "item_type": "planning",
+                    "item_id": "planning-2001",
+                    "actor": "specialist@gemeente.nl",
+                    "timestamp": "2025-01-15T11:00:00Z",
+                    "resource_data": {
+                        "title": "Vergunningsprocedure",
+                        "description": "Proces voor het verkrijgen van de benodigde vergunningen",
+                        "moments": [
+                            {
+                                "id": "moment-1",
+                                "date": "2025-01-10",
+                                "title": "Aanvraag indienen",
+                                "status": "completed"
+                            },
+                            {
+                                "id": "moment-2",
+                                "date": "2025-01-15",
+                                "title": "Behandeling door gemeente",
+                                "status": "current"
+                            },
+                            {
+                                "id": "moment-3",
+                                "date": "2025-01-25",
+                                "title": "Besluit gemeente",
+                                "status": "planned"
+                            }
+                        ]
+                    },
+                    "itemschema": format!("{}/schemas/Planning", base_url)
+                }
+            }
+        }),
+        json!({
+            "name": "PlanningUpdated",
+            "summary": "Case planning timeline status updated",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7F6",
+                "source": "planning-service",
+                "subject": "2",
+                "type": "json.commit",
+                "time": "2025-01-15T16:00:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": if embed_schemas {
+                    "#/components/schemas/JSONCommit".to_string()
+                } else {
+                    format!("{}/schemas/JSONCommit", base_url)
+                },
+                "data": {
+                    "item_type": "planning",
+                    "item_id": "planning-2001",
+                    "actor": "specialist@gemeente.nl",
+                    "timestamp": "2025-01-15T16:00:00Z",
+                    "patch": {
+                        "moments": [
+                            {
+                                "id": "moment-2",
+                                "date": "2025-01-15",
+                                "title": "Behandeling door gemeente",
+                                "status": "completed"
+                            },
+                            {
+                                "id": "moment-3",
+                                "date": "2025-01-25",
+                                "title": "Besluit gemeente",
+                                "status": "current"
+                            }
+                        ]
+                    },
+                    "itemschema": format!("{}/schemas/Planning", base_url)
+                }
+            }
+        }),
+        json!({
+            "name": "CommentAdded",
+            "summary": "Comment added to case",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7F7",
+                "source": "frontend-demo-event",
+                "subject": "1",
+                "type": "json.commit",
+                "time": "2025-01-15T14:20:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": if embed_schemas {
+                    "#/components/schemas/JSONCommit".to_string()
+                } else {
+                    format!("{}/schemas/JSONCommit", base_url)
+                },
+                "data": {
+                    "item_type": "comment",
+                    "item_id": "comment-1001",
+                    "actor": "alice@gemeente.nl",
+                    "timestamp": "2025-01-15T14:20:00Z",
+                    "resource_data": {
+                        "content": "Documenten zijn gecontroleerd en goedgekeurd. Zaak kan worden voortgezet.",
+                        "parent_id": null,
+                        "mentions": []
+                    },
+                    "itemschema": format!("{}/schemas/Comment", base_url)
+                }
+            }
+        }),
+        json!({
+            "name": "CommentUpdated",
+            "summary": "Comment content edited",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7F8",
+                "source": "frontend-demo-event",
+                "subject": "1",
+                "type": "json.commit",
+                "time": "2025-01-15T14:25:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": if embed_schemas {
+                    "#/components/schemas/JSONCommit".to_string()
+                } else {
+                    format!("{}/schemas/JSONCommit", base_url)
+                },
+                "data": {
+                    "item_type": "comment",
+                    "item_id": "comment-1001",
+                    "actor": "alice@gemeente.nl",
+                    "timestamp": "2025-01-15T14:25:00Z",
+                    "patch": {
+                        "content": "Documenten zijn gecontroleerd en goedgekeurd. Zaak kan worden voortgezet. Update: alle vereiste bijlagen zijn aanwezig."
+                    },
+                    "itemschema": format!("{}/schemas/Comment", base_url)
+                }
+            }
+        }),
+        json!({
+            "name": "CommentDeleted",
+            "summary": "Comment removed from case",
+            "payload": {
+                "specversion": "1.0",
+                "id": "01HF7K8QZ9X1Y2Z3A4B5C6D7F9",
+                "source": "frontend-demo-event",
+                "subject": "1",
+                "type": "json.commit",
+                "time": "2025-01-15T17:10:00Z",
+                "datacontenttype": "application/json",
+                "dataschema": format!("{}/schemas/JSONCommit", base_url),
+                "data": {
+                    "schema": format!("{}/schemas/Comment", base_url),
+                    "resource_id": "comment-1001",
+                    "actor": "alice@gemeente.nl",
+                    "timestamp": "2025-01-15T17:10:00Z",
+                    "deleted": true
+                }
+            }
+        }),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec_for_request_base_url() {
+        let known = vec!["https://utrecht.zaakchat.nl".to_string()];
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "utrecht.zaakchat.nl".parse().unwrap());
+        assert_eq!(
+            request_base_url(&headers, "https://zaakchat.nl/", &known),
+            "https://utrecht.zaakchat.nl"
+        );
+        headers.insert(header::HOST, "evil.example".parse().unwrap());
+        assert_eq!(
+            request_base_url(&headers, "https://zaakchat.nl/", &known),
+            "https://zaakchat.nl"
+        );
+
+        let cache = SpecCache::default();
+        let spec = cache.get("https://utrecht.zaakchat.nl").unwrap();
+        let parsed: Value = serde_json::from_str(&spec.json).unwrap();
+        assert_eq!(
+            parsed["servers"]["development"]["host"],
+            "utrecht.zaakchat.nl"
+        );
+        assert_eq!(
+            parsed["components"]["schemas"]["CloudEvent"]["$ref"],
+            "https://utrecht.zaakchat.nl/schemas/CloudEvent"
+        );
+        // Every built-in schema is in the spec
+        for name in get_all_schemas().keys() {
+            assert!(
+                parsed["components"]["schemas"].get(name).is_some(),
+                "{}",
+                name
+            );
+        }
+        assert!(Arc::ptr_eq(
+            &spec,
+            &cache.get("https://utrecht.zaakchat.nl").unwrap()
+        ));
+        let yaml: Value = serde_yaml::from_str(&spec.yaml).unwrap();
+        assert_eq!(yaml, parsed);
+    }
+}
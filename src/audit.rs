@@ -0,0 +1,316 @@
+//! Audit trail per actor: the commits someone made and the API requests they performed.
+//!
+//! Authenticated requests are recorded in the access log by `access_log_middleware`;
+//! `GET /audit/actors/{email}` combines those entries with the actor's commits from the
+//! event log into one timeline, for accountability questions (bezwaar, incidents).
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::AuthUser;
+use crate::handlers::{commit_of, AppState, CATCHUP_PAGE_SIZE};
+use crate::schemas::CloudEvent;
+use crate::storage::AccessLogEntry;
+
+/// Query parameters for the audit trail
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditParams {
+    /// Only include entries at or after this time (RFC 3339)
+    pub from: Option<String>,
+    /// Only include entries at or before this time (RFC 3339)
+    pub to: Option<String>,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_audit_limit")]
+    pub limit: usize,
+}
+
+fn default_audit_limit() -> usize {
+    100
+}
+
+/// One entry in an actor's audit trail
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditEntry {
+    /// A JSONCommit with this actor
+    Commit {
+        time: Option<String>,
+        event: Box<CloudEvent>,
+    },
+    /// An authenticated API request by this actor
+    Access(AccessLogEntry),
+}
+
+impl AuditEntry {
+    fn time(&self) -> Option<DateTime<Utc>> {
+        match self {
+            AuditEntry::Commit { time, .. } => time.as_deref().and_then(parse_time),
+            AuditEntry::Access(entry) => parse_time(&entry.time),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditTrail {
+    pub actor: String,
+    /// Number of entries matching the time filter (before pagination)
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    /// Entries oldest first
+    pub entries: Vec<AuditEntry>,
+}
+
+/// Timestamp format of the access log: fixed width, so string order is time order
+pub fn audit_timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// May `user` read the audit trail of `actor`? Everyone may read their own; admins (see
+/// `auth::is_admin`) may read everyone's.
+fn may_audit(user: &str, actor: &str) -> bool {
+    user == actor || crate::auth::is_admin(user)
+}
+
+/// The user a request is authenticated as: a Bearer token, or `?token=` (EventSource).
+fn request_actor(headers: &HeaderMap, query: Option<&str>) -> Option<String> {
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let query_token = query.and_then(|q| {
+        q.split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == "token")
+            .map(|(_, value)| value.to_string())
+    });
+
+    let token = bearer.or(query_token)?;
+    crate::auth::verify_session_jwt(&token)
+        .ok()
+        .map(|claims| claims.sub)
+}
+
+/// Record every authenticated request in the access log.
+pub async fn access_log_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let actor = request_actor(request.headers(), request.uri().query());
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+
+    let response = next.run(request).await;
+
+    if let Some(actor) = actor {
+        let entry = AccessLogEntry {
+            actor,
+            time: audit_timestamp(Utc::now()),
+            method,
+            path,
+            status: response.status().as_u16(),
+        };
+        // Don't hold up the response for the write
+        let storage = state.storage.clone();
+        tokio::spawn(async move {
+            if let Err(e) = storage.append_access_log(&entry).await {
+                eprintln!("[audit] failed to record access: {}", e);
+            }
+        });
+    }
+
+    response
+}
+
+/// GET /audit/actors/{email} - All commits and access log entries of an actor
+#[utoipa::path(
+    get,
+    path = "/audit/actors/{email}",
+    tag = "admin",
+    params(("email" = String, Path, description = "Actor email"), AuditParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The actor's audit trail, oldest first", body = AuditTrail),
+        (status = 400, description = "Invalid `from` or `to` timestamp"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller may not audit this actor"),
+    )
+)]
+pub async fn actor_audit_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(actor): Path<String>,
+    Query(params): Query<AuditParams>,
+) -> Result<Json<AuditTrail>, StatusCode> {
+    if !may_audit(&auth_user.user_id, &actor) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let parse_bound = |value: &Option<String>| match value {
+        Some(v) => parse_time(v).map(Some).ok_or(StatusCode::BAD_REQUEST),
+        None => Ok(None),
+    };
+    let from = parse_bound(&params.from)?;
+    let to = parse_bound(&params.to)?;
+    let in_range = |time: Option<DateTime<Utc>>| match time {
+        Some(t) => from.is_none_or(|f| t >= f) && to.is_none_or(|u| t <= u),
+        // Without a timestamp an entry can only match an unbounded query
+        None => from.is_none() && to.is_none(),
+    };
+
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("[audit] failed to build audit trail: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    let mut entries: Vec<AuditEntry> = state
+        .storage
+        .list_access_log(
+            &actor,
+            from.map(audit_timestamp).as_deref(),
+            to.map(audit_timestamp).as_deref(),
+        )
+        .await
+        .map_err(internal)?
+        .into_iter()
+        .map(AuditEntry::Access)
+        .collect();
+
+    // Commits are not indexed by actor: scan the event log
+    let mut after = None;
+    loop {
+        let page = state
+            .storage
+            .list_events_after(after, CATCHUP_PAGE_SIZE)
+            .await
+            .map_err(internal)?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = last.sequence.clone();
+
+        for event in page {
+            let Some(commit) = commit_of(&event) else {
+                continue;
+            };
+            if commit.actor != actor {
+                continue;
+            }
+            let entry = AuditEntry::Commit {
+                time: commit.timestamp.or_else(|| event.time.clone()),
+                event: Box::new(event),
+            };
+            if in_range(entry.time()) {
+                entries.push(entry);
+            }
+        }
+    }
+
+    entries.sort_by_key(|entry| entry.time());
+
+    let total = entries.len();
+    let entries = entries
+        .into_iter()
+        .skip(params.offset)
+        .take(params.limit)
+        .collect();
+
+    Ok(Json(AuditTrail {
+        actor,
+        total,
+        offset: params.offset,
+        limit: params.limit,
+        entries,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user, submit_commit_event, test_state, ADMIN};
+    use crate::schemas::{CommitBuilder, Issue};
+
+    fn params(from: Option<&str>) -> Query<AuditParams> {
+        Query(AuditParams {
+            from: from.map(str::to_string),
+            to: None,
+            offset: 0,
+            limit: 100,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_actor_audit_trail_combines_commits_and_access() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+
+        for (actor, status) in [(alice, "open"), ("bob@gemeente.nl", "closed")] {
+            let commit =
+                CommitBuilder::patch::<Issue>("issue-1", serde_json::json!({"status": status}))
+                    .actor(actor)
+                    .build();
+            submit_commit_event(&state, "issue-1", &commit)
+                .await
+                .unwrap();
+        }
+        state
+            .storage
+            .append_access_log(&AccessLogEntry {
+                actor: alice.to_string(),
+                time: audit_timestamp(Utc::now()),
+                method: "GET".to_string(),
+                path: "/resources/issue-1".to_string(),
+                status: 200,
+            })
+            .await
+            .unwrap();
+
+        let audit = |user: &str, from: Option<&str>| {
+            actor_audit_handler(
+                State(state.clone()),
+                auth_user(user),
+                Path(alice.to_string()),
+                params(from),
+            )
+        };
+        let Json(trail) = audit(alice, None).await.unwrap();
+        assert_eq!(trail.total, 2);
+        assert!(matches!(trail.entries[0], AuditEntry::Commit { .. }));
+        assert!(
+            matches!(&trail.entries[1], AuditEntry::Access(e) if e.path == "/resources/issue-1")
+        );
+
+        // A time window in the future matches nothing
+        let Json(trail) = audit(alice, Some("2999-01-01T00:00:00Z")).await.unwrap();
+        assert_eq!(trail.total, 0);
+        assert_eq!(
+            audit(alice, Some("gisteren")).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+
+        // Someone else's trail is for admins only
+        assert_eq!(
+            audit("bob@gemeente.nl", None).await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(audit(ADMIN, None).await.unwrap().total, 2);
+    }
+}
@@ -1,5 +1,5 @@
 use axum::{
-    extract::FromRequestParts,
+    extract::{FromRequestParts, OptionalFromRequestParts},
     http::{header::AUTHORIZATION, request::Parts, StatusCode},
 };
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
@@ -15,6 +15,9 @@ pub struct Claims {
     pub exp: usize,
     /// Issued at (as UTC timestamp)
     pub iat: usize,
+    /// Invite token id, for magic links sent with an invite (see `invites`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
 }
 
 /// Authenticated User Extractor
@@ -26,6 +29,26 @@ pub struct AuthUser {
     pub user_id: String,
 }
 
+/// The tenant (organisation) of a user: the domain of their email address.
+pub fn tenant_of(user: &str) -> &str {
+    user.rsplit_once('@').map_or(user, |(_, domain)| domain)
+}
+
+/// Is `user` an administrator? The emails in `ADMINS` (comma-separated) are.
+pub fn is_admin(user: &str) -> bool {
+    env::var("ADMINS")
+        .unwrap_or_default()
+        .split(',')
+        .any(|admin| admin.trim() == user)
+}
+
+impl AuthUser {
+    /// Is this user an administrator (see [`is_admin`])?
+    pub fn is_admin(&self) -> bool {
+        is_admin(&self.user_id)
+    }
+}
+
 impl<S> FromRequestParts<S> for AuthUser
 where
     S: Send + Sync,
@@ -47,20 +70,37 @@ where
 
         let token = &auth_header[7..];
 
-        // 3. Decode and validate token
-        let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string());
-        let decoding_key = DecodingKey::from_secret(secret.as_bytes());
-        let validation = Validation::default();
-
-        match decode::<Claims>(token, &decoding_key, &validation) {
-            Ok(token_data) => Ok(AuthUser {
-                user_id: token_data.claims.sub,
+        // 3. Decode and validate token; invite links are not session tokens
+        match verify_session_jwt(token) {
+            Ok(claims) => Ok(AuthUser {
+                user_id: claims.sub,
             }),
             Err(_) => Err(StatusCode::UNAUTHORIZED),
         }
     }
 }
 
+/// `Option<AuthUser>`: `None` for anonymous requests (no `Authorization` header), while a
+/// header with an invalid token is still rejected.
+impl<S> OptionalFromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        if !parts.headers.contains_key(AUTHORIZATION) {
+            return Ok(None);
+        }
+        <AuthUser as FromRequestParts<S>>::from_request_parts(parts, state)
+            .await
+            .map(Some)
+    }
+}
+
 /// Helper to create a JWT for a user with default 24h expiration
 pub fn create_jwt(user_id: &str) -> Result<String, jsonwebtoken::errors::Error> {
     create_jwt_with_expiry(user_id, chrono::Duration::hours(24))
@@ -68,6 +108,11 @@ pub fn create_jwt(user_id: &str) -> Result<String, jsonwebtoken::errors::Error>
 
 /// Helper to create a JWT for a user with custom expiration
 pub fn create_jwt_with_expiry(user_id: &str, duration: chrono::Duration) -> Result<String, jsonwebtoken::errors::Error> {
+    create_jwt_with_id(user_id, duration, None)
+}
+
+/// Helper to create a JWT carrying a token id, which can be checked (and revoked) server side
+pub fn create_jwt_with_id(user_id: &str, duration: chrono::Duration, jti: Option<String>) -> Result<String, jsonwebtoken::errors::Error> {
     let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string());
     let expiration = chrono::Utc::now()
         .checked_add_signed(duration)
@@ -78,6 +123,7 @@ pub fn create_jwt_with_expiry(user_id: &str, duration: chrono::Duration) -> Resu
         sub: user_id.to_owned(),
         iat: chrono::Utc::now().timestamp() as usize,
         exp: expiration,
+        jti,
     };
 
     encode(
@@ -97,6 +143,16 @@ pub fn verify_jwt(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
     Ok(token_data.claims)
 }
 
+/// Helper to verify a session JWT. Invite magic links carry a `jti` and are refused: they can
+/// only be exchanged for a session at `/auth/verify`, while the invite is outstanding.
+pub fn verify_session_jwt(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let claims = verify_jwt(token)?;
+    if claims.jti.is_some() {
+        return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+    }
+    Ok(claims)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -115,4 +171,24 @@ mod tests {
 
         assert_eq!(token_data.claims.sub, user_id);
     }
+
+    #[test]
+    fn test_invite_token_is_not_a_session() {
+        let token = create_jwt_with_id("bob@example.com", chrono::Duration::days(14), Some("invite-token".to_string()))
+            .expect("failed to create token");
+        assert!(verify_jwt(&token).is_ok());
+        assert!(verify_session_jwt(&token).is_err());
+        let session = create_jwt("bob@example.com").expect("failed to create token");
+        assert_eq!(verify_session_jwt(&session).unwrap().sub, "bob@example.com");
+    }
+
+    #[test]
+    fn test_admins() {
+        // Tests share the environment, so they all use the same admin
+        std::env::set_var("ADMINS", "admin@gemeente.nl");
+        assert!(is_admin("admin@gemeente.nl"));
+        assert!(!is_admin("mallory@evil.com"));
+        assert!(!is_admin(""));
+        assert!(AuthUser { user_id: "admin@gemeente.nl".to_string() }.is_admin());
+    }
 }
@@ -0,0 +1,333 @@
+//! Out-of-office: per-user availability with a delegate who takes over while someone is away.
+//!
+//! Availability is a resource `availability-{email}` that users maintain themselves with
+//! `PUT /users/me/availability`. While someone is away:
+//! - assignment suggestions and auto-assignment skip them;
+//! - a commit that assigns an issue to them is followed by a "system" commit that reassigns
+//!   it to their delegate (and adds the delegate to `involved`);
+//! - notifications about urgent issues (tagged [`URGENT_TAG`]) go to their delegate instead.
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::auth::{tenant_of, AuthUser};
+use crate::handlers::{commit_of, submit_commit, submit_event, AppState};
+use crate::schemas::{Availability, CloudEvent, CloudEventBuilder, CommitBuilder, Issue};
+use crate::storage::Storage;
+
+/// Issues with this tag are urgent: their notifications are rerouted to delegates
+pub const URGENT_TAG: &str = "spoed";
+
+/// Delegates of delegates are followed at most this many times
+const MAX_DELEGATION_HOPS: usize = 5;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AvailabilityRequest {
+    pub away: bool,
+    /// End of the absence (RFC 3339)
+    pub until: Option<String>,
+    /// Colleague who takes over while away
+    pub delegate: Option<String>,
+}
+
+/// Resource ID of a user's availability
+pub fn availability_id(user: &str) -> String {
+    format!("availability-{}", user)
+}
+
+/// Is this availability an absence right now?
+pub fn is_away(availability: &Availability, now: chrono::DateTime<chrono::Utc>) -> bool {
+    availability.away
+        && availability
+            .until
+            .as_deref()
+            .and_then(|until| chrono::DateTime::parse_from_rfc3339(until).ok())
+            .is_none_or(|until| now < until)
+}
+
+async fn get_availability(
+    storage: &Storage,
+    user: &str,
+) -> Result<Option<Availability>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(storage
+        .get_resource(&availability_id(user))
+        .await?
+        .and_then(|value| serde_json::from_value(value).ok()))
+}
+
+/// Is `user` away right now?
+pub async fn user_is_away(
+    storage: &Storage,
+    user: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let now = chrono::Utc::now();
+    Ok(get_availability(storage, user)
+        .await?
+        .is_some_and(|a| is_away(&a, now)))
+}
+
+/// Who takes over from `user`: `None` if they are available or have no delegate, otherwise
+/// the first available colleague down the chain of delegates.
+pub async fn active_delegate(
+    storage: &Storage,
+    user: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let now = chrono::Utc::now();
+    let mut current = user.to_string();
+    for _ in 0..MAX_DELEGATION_HOPS {
+        let availability = get_availability(storage, &current).await?;
+        if !availability.as_ref().is_some_and(|a| is_away(a, now)) {
+            return Ok(Some(current).filter(|c| c != user));
+        }
+        match availability.and_then(|a| a.delegate) {
+            Some(delegate) if delegate != user => current = delegate,
+            _ => return Ok(None),
+        }
+    }
+    Ok(None)
+}
+
+/// After a commit that assigns an issue to someone who is away, reassign it to their
+/// delegate with a "system" commit.
+pub async fn reroute_assignment(
+    state: &AppState,
+    event: &CloudEvent,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(commit) = commit_of(event) else {
+        return Ok(());
+    };
+    let sets_assignee = |data: &Option<Value>| {
+        data.as_ref()
+            .and_then(|d| d.get("assignee"))
+            .is_some_and(Value::is_string)
+    };
+    if commit.deleted == Some(true)
+        || !(sets_assignee(&commit.resource_data) || sets_assignee(&commit.merge_patch()))
+    {
+        return Ok(());
+    }
+    let Some(issue) = state
+        .storage
+        .get_resource(&commit.resource_id)
+        .await?
+        .and_then(|value| serde_json::from_value::<Issue>(value).ok())
+    else {
+        return Ok(());
+    };
+    let Some(assignee) = issue.assignee else {
+        return Ok(());
+    };
+    let Some(delegate) = active_delegate(&state.storage, &assignee).await? else {
+        return Ok(());
+    };
+
+    // The delegate needs access to the issue
+    let mut involved = issue.involved.unwrap_or_default();
+    if !involved.contains(&delegate) {
+        involved.push(delegate.clone());
+    }
+    let reroute = CommitBuilder::patch::<Issue>(
+        commit.resource_id.clone(),
+        json!({ "assignee": delegate, "involved": involved }),
+    )
+    .build();
+    submit_event(
+        state,
+        CloudEventBuilder::commit(commit.resource_id, &reroute).build(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// PUT /users/me/availability - Set the caller's absence and delegate
+#[utoipa::path(
+    put,
+    path = "/users/me/availability",
+    tag = "resources",
+    request_body = AvailabilityRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Availability commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Malformed end date, or a delegate from another tenant or the caller themselves"),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn update_availability_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Json(request): Json<AvailabilityRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    let valid_until = request
+        .until
+        .as_deref()
+        .is_none_or(|until| chrono::DateTime::parse_from_rfc3339(until).is_ok());
+    let valid_delegate = request
+        .delegate
+        .as_deref()
+        .is_none_or(|d| d != user && tenant_of(d) == tenant_of(&user));
+    if !valid_until || !valid_delegate {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let id = availability_id(&user);
+    let availability = Availability {
+        user: user.clone(),
+        away: request.away,
+        until: request.until,
+        delegate: request.delegate,
+    };
+    let commit = CommitBuilder::create(id.clone(), &availability)
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &id, commit).await
+}
+
+/// GET /users/{user}/availability - Whether a colleague is away, and who takes over
+#[utoipa::path(
+    get,
+    path = "/users/{user}/availability",
+    tag = "resources",
+    params(("user" = String, Path, description = "Email of the colleague")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The colleague's availability", body = Value),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The colleague is from another tenant"),
+        (status = 404, description = "The colleague never set an availability"),
+    )
+)]
+pub async fn get_availability_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(user): Path<String>,
+) -> Result<Json<Availability>, StatusCode> {
+    if tenant_of(&user) != tenant_of(&auth_user.user_id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    get_availability(&state.storage, &user)
+        .await
+        .map_err(|e| {
+            eprintln!(
+                "[availability] failed to load availability of {}: {}",
+                user, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state};
+
+    #[test]
+    fn test_is_away_until() {
+        let now = chrono::Utc::now();
+        let availability =
+            |away: bool, until: Option<chrono::DateTime<chrono::Utc>>| Availability {
+                user: "alice@gemeente.nl".to_string(),
+                away,
+                until: until.map(|u| u.to_rfc3339()),
+                delegate: None,
+            };
+        assert!(is_away(&availability(true, None), now));
+        assert!(is_away(
+            &availability(true, Some(now + chrono::Duration::days(1))),
+            now
+        ));
+        assert!(!is_away(
+            &availability(true, Some(now - chrono::Duration::days(1))),
+            now
+        ));
+        assert!(!is_away(&availability(false, None), now));
+    }
+
+    #[tokio::test]
+    async fn test_assignment_is_rerouted_to_delegate() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let bob = "bob@gemeente.nl";
+
+        let set_away = |who: &'static str, delegate: Option<&str>| {
+            update_availability_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Json(AvailabilityRequest {
+                    away: true,
+                    until: None,
+                    delegate: delegate.map(str::to_string),
+                }),
+            )
+        };
+        assert_eq!(
+            set_away(alice, Some(alice)).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            set_away(alice, Some("eve@example.com")).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            set_away(alice, Some(bob)).await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            active_delegate(&state.storage, alice).await.unwrap(),
+            Some(bob.to_string())
+        );
+        assert_eq!(active_delegate(&state.storage, bob).await.unwrap(), None);
+
+        // Colleagues can see who takes over, other tenants can't
+        let availability = |who: &str, of: &str| {
+            get_availability_handler(State(state.clone()), user(who), Path(of.to_string()))
+        };
+        let Json(away) = availability(bob, alice).await.unwrap();
+        assert_eq!(away.delegate.as_deref(), Some(bob));
+        assert_eq!(
+            availability("eve@example.com", alice).await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            availability(alice, bob).await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+
+        let issue = Issue {
+            assignee: Some(alice.to_string()),
+            ..issue("Bouwvergunning", &[alice])
+        };
+        let event = create_issue(&state, "issue-1", &issue, alice).await;
+        reroute_assignment(&state, &event).await.unwrap();
+
+        let rerouted: Issue = serde_json::from_value(
+            state
+                .storage
+                .get_resource("issue-1")
+                .await
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(rerouted.assignee.as_deref(), Some(bob));
+        assert_eq!(rerouted.involved.unwrap(), vec![alice, bob]);
+
+        // A delegate who is away too passes the work on, but never back
+        assert_eq!(
+            set_away(bob, Some(alice)).await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(active_delegate(&state.storage, alice).await.unwrap(), None);
+    }
+}
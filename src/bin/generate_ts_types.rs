@@ -0,0 +1,390 @@
+//! Generate TypeScript types for the frontend from the Rust JSON schemas.
+//!
+//! Writes `frontend/src/types/interfaces.ts` (or the path given as first argument) with an
+//! interface or type alias per schema in `get_all_schemas()`, plus a discriminated union of
+//! the CloudEvents the server emits, keyed on the CloudEvent `type`.
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use zaakchat::schemas::get_all_schemas;
+
+const DEFAULT_OUTPUT: &str = "frontend/src/types/interfaces.ts";
+
+/// CloudEvent variants: (interface name, accepted `type` values, payload type of `data`).
+const EVENT_VARIANTS: &[(&str, &[&str], Option<&str>)] = &[
+    (
+        "JSONCommitEvent",
+        &["json.commit", "nl.vng.zaken.json-commit.v1"],
+        Some("JSONCommit"),
+    ),
+    ("SystemResetEvent", &["system.reset"], None),
+    ("TypingEvent", &["chat.typing"], Some("TypingIndicator")),
+    (
+        "PresenceEvent",
+        &["presence.joined", "presence.left"],
+        Some("Presence"),
+    ),
+];
+
+const HEADER: &str = "// Auto-generated TypeScript types
+// Generated from the Rust JSON schemas by `cargo run --bin generate_ts_types`.
+// Do not edit by hand: change the Rust structs in src/schemas.rs and regenerate.
+";
+
+const HELPERS: &str = "
+// Schema metadata interface for runtime schema fetching
+export interface SchemaMetadata {
+  schemas: string[];
+  base_url: string;
+  description: string;
+}
+
+// Helper function to fetch schemas from the server
+export async function fetchSchema(schemaName: string): Promise<any> {
+  const response = await fetch(`/schemas/${schemaName}`);
+  if (!response.ok) {
+    throw new Error(`Failed to fetch schema ${schemaName}: ${response.statusText}`);
+  }
+  return response.json();
+}
+
+// Helper function to get all available schemas
+export async function fetchSchemaIndex(): Promise<SchemaMetadata> {
+  const response = await fetch('/schemas');
+  if (!response.ok) {
+    throw new Error(`Failed to fetch schema index: ${response.statusText}`);
+  }
+  return response.json();
+}
+";
+
+fn main() {
+    let output = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_OUTPUT.to_string());
+    println!("Generating TypeScript types from Rust schemas...");
+
+    let schemas: BTreeMap<String, Value> = get_all_schemas().into_iter().collect();
+    let content = generate_typescript(&schemas);
+
+    let output_path = Path::new(&output);
+    if let Some(parent) = output_path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            eprintln!("Failed to create output directory: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Err(e) = fs::write(output_path, content) {
+        eprintln!("Failed to write {}: {}", output, e);
+        std::process::exit(1);
+    }
+
+    println!("✓ Generated {} ({} schemas)", output, schemas.len());
+}
+
+fn generate_typescript(schemas: &BTreeMap<String, Value>) -> String {
+    // Nested definitions that are not exported as a top-level schema still need a declaration
+    let mut declarations = schemas.clone();
+    for schema in schemas.values() {
+        if let Some(definitions) = schema.get("definitions").and_then(|d| d.as_object()) {
+            for (name, definition) in definitions {
+                declarations
+                    .entry(name.clone())
+                    .or_insert_with(|| definition.clone());
+            }
+        }
+    }
+
+    let generator = TsGenerator::new(&declarations);
+    let mut out = String::from(HEADER);
+    for (name, schema) in &declarations {
+        out.push('\n');
+        out.push_str(&generator.declaration(name, schema));
+    }
+
+    out.push_str(
+        "\n// CloudEvents by `type`. Narrow on `event.type` to get a typed `data` payload.\n",
+    );
+    for (interface, types, payload) in EVENT_VARIANTS {
+        let type_union = types
+            .iter()
+            .map(|t| format!("\"{}\"", t))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        out.push_str(&format!(
+            "export interface {} extends CloudEvent {{\n  type: {};\n",
+            interface, type_union
+        ));
+        if let Some(payload) = payload {
+            out.push_str(&format!("  data: {};\n", payload));
+        }
+        out.push_str("}\n\n");
+    }
+    let variants: Vec<&str> = EVENT_VARIANTS.iter().map(|(name, _, _)| *name).collect();
+    out.push_str(&format!(
+        "export type KnownCloudEvent = {};\n",
+        variants.join(" | ")
+    ));
+
+    out.push_str(HELPERS);
+    out
+}
+
+/// Converts schemars output to TypeScript.
+///
+/// `get_all_schemas()` inlines every `$ref`, so nested schemas that are identical to a
+/// named schema are mapped back onto that name instead of being spelled out again.
+struct TsGenerator {
+    named: Vec<(Value, String)>,
+}
+
+impl TsGenerator {
+    fn new(declarations: &BTreeMap<String, Value>) -> Self {
+        let named = declarations
+            .iter()
+            .map(|(name, schema)| (shape(schema), name.clone()))
+            .collect();
+        Self { named }
+    }
+
+    /// `export interface` for object schemas with properties, `export type` for everything else.
+    fn declaration(&self, name: &str, schema: &Value) -> String {
+        let mut out = doc_comment(schema, "");
+        match schema.get("properties").and_then(|p| p.as_object()) {
+            Some(properties) => {
+                out.push_str(&format!("export interface {} ", name));
+                out.push_str(&self.object_body(properties, required_fields(schema), ""));
+                out.push('\n');
+            }
+            None => out.push_str(&format!(
+                "export type {} = {};\n",
+                name,
+                self.inline_type(schema, "")
+            )),
+        }
+        out
+    }
+
+    fn object_body(
+        &self,
+        properties: &serde_json::Map<String, Value>,
+        required: Vec<&str>,
+        indent: &str,
+    ) -> String {
+        let inner = format!("{}  ", indent);
+        let mut out = String::from("{\n");
+        for (prop, prop_schema) in properties {
+            out.push_str(&doc_comment(prop_schema, &inner));
+            let optional = if required.contains(&prop.as_str()) {
+                ""
+            } else {
+                "?"
+            };
+            out.push_str(&format!(
+                "{}{}{}: {};\n",
+                inner,
+                property_key(prop),
+                optional,
+                self.ts_type(prop_schema, &inner)
+            ));
+        }
+        out.push_str(indent);
+        out.push('}');
+        out
+    }
+
+    /// TypeScript type expression for a (nested) schema, preferring named types.
+    fn ts_type(&self, schema: &Value, indent: &str) -> String {
+        let schema_shape = shape(schema);
+        if let Some((_, name)) = self.named.iter().find(|(named, _)| *named == schema_shape) {
+            return name.clone();
+        }
+        self.inline_type(schema, indent)
+    }
+
+    fn inline_type(&self, schema: &Value, indent: &str) -> String {
+        let obj = match schema {
+            Value::Object(obj) => obj,
+            Value::Bool(false) => return "never".to_string(),
+            _ => return "unknown".to_string(),
+        };
+
+        if let Some(reference) = obj.get("$ref").and_then(|r| r.as_str()) {
+            return reference
+                .rsplit('/')
+                .next()
+                .unwrap_or(reference)
+                .to_string();
+        }
+        if let Some(value) = obj.get("const") {
+            return value.to_string();
+        }
+        if let Some(values) = obj.get("enum").and_then(|e| e.as_array()) {
+            return union(values.iter().map(|v| v.to_string()).collect());
+        }
+        for key in ["oneOf", "anyOf"] {
+            if let Some(variants) = obj.get(key).and_then(|v| v.as_array()) {
+                return union(variants.iter().map(|v| self.ts_type(v, indent)).collect());
+            }
+        }
+        if let Some(parts) = obj.get("allOf").and_then(|v| v.as_array()) {
+            let parts: Vec<String> = parts.iter().map(|p| self.ts_type(p, indent)).collect();
+            return parts.join(" & ");
+        }
+
+        match obj.get("type") {
+            Some(Value::String(t)) => self.primitive(t, obj, indent),
+            Some(Value::Array(types)) => {
+                // `Option<T>` of a named type: `["object", "null"]` with T's keywords
+                let non_null: Vec<&str> = types
+                    .iter()
+                    .filter_map(|t| t.as_str())
+                    .filter(|t| *t != "null")
+                    .collect();
+                let mut members: Vec<String> = if non_null.len() == 1 {
+                    let mut inner = obj.clone();
+                    inner.insert("type".to_string(), Value::String(non_null[0].to_string()));
+                    vec![self.ts_type(&Value::Object(inner), indent)]
+                } else {
+                    non_null
+                        .iter()
+                        .map(|t| self.primitive(t, obj, indent))
+                        .collect()
+                };
+                if non_null.len() < types.len() {
+                    members.push("null".to_string());
+                }
+                union(members)
+            }
+            _ => "unknown".to_string(),
+        }
+    }
+
+    fn primitive(
+        &self,
+        type_name: &str,
+        obj: &serde_json::Map<String, Value>,
+        indent: &str,
+    ) -> String {
+        match type_name {
+            "string" => "string".to_string(),
+            "integer" | "number" => "number".to_string(),
+            "boolean" => "boolean".to_string(),
+            "null" => "null".to_string(),
+            "array" => {
+                let item = obj
+                    .get("items")
+                    .map(|items| self.ts_type(items, indent))
+                    .unwrap_or_else(|| "unknown".to_string());
+                if item.contains(' ') {
+                    format!("({})[]", item)
+                } else {
+                    format!("{}[]", item)
+                }
+            }
+            "object" => match obj.get("properties").and_then(|p| p.as_object()) {
+                Some(properties) => self.object_body(
+                    properties,
+                    required_fields(&Value::Object(obj.clone())),
+                    indent,
+                ),
+                None => match obj.get("additionalProperties") {
+                    Some(value @ Value::Object(_)) => {
+                        format!("Record<string, {}>", self.ts_type(value, indent))
+                    }
+                    _ => "Record<string, unknown>".to_string(),
+                },
+            },
+            _ => "unknown".to_string(),
+        }
+    }
+}
+
+/// A schema without its documentation keywords, for structural comparison.
+fn shape(schema: &Value) -> Value {
+    let mut schema = schema.clone();
+    if let Some(obj) = schema.as_object_mut() {
+        for key in ["$schema", "definitions", "title", "description"] {
+            obj.remove(key);
+        }
+    }
+    schema
+}
+
+fn required_fields(schema: &Value) -> Vec<&str> {
+    schema
+        .get("required")
+        .and_then(|r| r.as_array())
+        .map(|r| r.iter().filter_map(|v| v.as_str()).collect())
+        .unwrap_or_default()
+}
+
+fn union(mut members: Vec<String>) -> String {
+    let mut seen = Vec::new();
+    members.retain(|m| {
+        if seen.contains(m) {
+            false
+        } else {
+            seen.push(m.clone());
+            true
+        }
+    });
+    match members.len() {
+        0 => "never".to_string(),
+        _ => members.join(" | "),
+    }
+}
+
+fn property_key(name: &str) -> String {
+    let is_identifier = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if is_identifier {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name)
+    }
+}
+
+fn doc_comment(schema: &Value, indent: &str) -> String {
+    let Some(description) = schema.get("description").and_then(|d| d.as_str()) else {
+        return String::new();
+    };
+    let description = description.replace("*/", "*\\/");
+    let lines: Vec<&str> = description.lines().collect();
+    if lines.len() == 1 {
+        return format!("{}/** {} */\n", indent, lines[0]);
+    }
+    let mut out = format!("{}/**\n", indent);
+    for line in lines {
+        out.push_str(&format!("{} * {}\n", indent, line).replace(" * \n", " *\n"));
+    }
+    out.push_str(&format!("{} */\n", indent));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generates_interfaces_and_event_union() {
+        let schemas: BTreeMap<String, Value> = get_all_schemas().into_iter().collect();
+        let ts = generate_typescript(&schemas);
+
+        assert!(ts.contains("export interface Issue {"));
+        assert!(ts.contains("  status: IssueStatus;"));
+        assert!(ts.contains("  assignee?: string | null;"));
+        assert!(ts.contains("export type IssueStatus = \"open\" | \"in_progress\" | \"closed\";"));
+        assert!(ts.contains("  moments: PlanningMoment[];"));
+        assert!(ts.contains(
+            "export type KnownCloudEvent = JSONCommitEvent | SystemResetEvent | TypingEvent | PresenceEvent;"
+        ));
+        assert!(ts.contains("export async function fetchSchema("));
+    }
+}
@@ -0,0 +1,87 @@
+//! Storage maintenance on a stopped server's data directory.
+//!
+//! Usage: `storage_maintenance [report|vacuum|compact]`, with the data directory in
+//! `DATA_DIR` (default `./data`). The server must be stopped: redb allows one process.
+//!
+//! Data migrations: `storage_maintenance version`, `migrate [--dry-run]` and
+//! `rollback <version> [--dry-run]`. The server migrates on start, so `migrate` is for
+//! seeing what it would do; `rollback` is for going back to an older build.
+use std::path::PathBuf;
+
+use zaakchat::projections::Projections;
+use zaakchat::storage::Storage;
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = args
+        .first()
+        .cloned()
+        .unwrap_or_else(|| "report".to_string());
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let data_dir = std::env::var("DATA_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("./data"));
+    // Opening normally would migrate first
+    let opened = match command.as_str() {
+        "version" | "migrate" | "rollback" => Storage::open(&data_dir).await,
+        _ => Storage::new(&data_dir).await,
+    };
+    let storage = match opened {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!(
+                "Failed to open {:?} (is the server still running?): {}",
+                data_dir, e
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let result = match command.as_str() {
+        "report" => storage
+            .storage_report()
+            .and_then(|report| Ok(serde_json::to_string_pretty(&report)?)),
+        "vacuum" => {
+            let projections = Projections::default();
+            zaakchat::maintenance::vacuum(&storage, &projections.names())
+                .await
+                .and_then(|report| Ok(serde_json::to_string_pretty(&report)?))
+        }
+        "compact" => zaakchat::maintenance::compact(&storage)
+            .and_then(|report| Ok(serde_json::to_string_pretty(&report)?)),
+        "version" => storage.schema_version().map(|version| {
+            format!(
+                "schema version {} (this build: {})",
+                version,
+                zaakchat::storage::latest_version()
+            )
+        }),
+        "migrate" => storage
+            .migrate(dry_run)
+            .and_then(|report| Ok(serde_json::to_string_pretty(&report)?)),
+        "rollback" => {
+            let Some(version) = args.get(1).and_then(|v| v.parse().ok()) else {
+                eprintln!("Usage: storage_maintenance rollback <version> [--dry-run]");
+                std::process::exit(2);
+            };
+            storage
+                .rollback(version, dry_run)
+                .and_then(|report| Ok(serde_json::to_string_pretty(&report)?))
+        }
+        other => {
+            eprintln!(
+                "Unknown command {:?}; use report, vacuum, compact, version, migrate or rollback",
+                other
+            );
+            std::process::exit(2);
+        }
+    };
+    match result {
+        Ok(output) => println!("{}", output),
+        Err(e) => {
+            eprintln!("{} failed: {}", command, e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Kanban boards: the shared column order and per-column card order of a team.
+//!
+//! There is one `Board` per tenant (see `auth::tenant_of`) and one per team (see `teams`),
+//! stored as the resource `board-{team}`. Until someone reorders something, `GET /boards/{team}`
+//! serves a default board with a column per issue status and no card order. Every reorder
+//! is a commit replacing `columns`, so the order survives refreshes and reaches the team's
+//! other members through the event stream.
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::auth::{tenant_of, AuthUser};
+use crate::handlers::{authorize_issue, submit_commit, AppState};
+use crate::schemas::{Board, BoardColumn, CloudEvent, CommitBuilder};
+use crate::teams::is_member;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ColumnOrderRequest {
+    /// All column IDs of the board, in the new order
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MoveCardRequest {
+    /// The issue to move
+    pub card: String,
+    /// The column to move it to
+    pub column: String,
+    /// Position within the column (0 = top); past the end means at the bottom
+    pub position: usize,
+}
+
+/// Resource ID of a team's board
+pub fn board_id(team: &str) -> String {
+    format!("board-{}", team)
+}
+
+/// The board before anyone reordered it: a column per issue status.
+pub fn default_board(team: &str) -> Board {
+    let column = |id: &str, title: &str| BoardColumn {
+        id: id.to_string(),
+        title: title.to_string(),
+        cards: Vec::new(),
+    };
+    Board {
+        team: team.to_string(),
+        columns: vec![
+            column("open", "Open"),
+            column("in_progress", "In behandeling"),
+            column("closed", "Gesloten"),
+        ],
+    }
+}
+
+/// Put `card` at `position` in `column`, removing it from wherever it was.
+/// Returns false if the board has no such column.
+pub fn move_card(board: &mut Board, card: &str, column: &str, position: usize) -> bool {
+    if !board.columns.iter().any(|c| c.id == column) {
+        return false;
+    }
+    for col in &mut board.columns {
+        col.cards.retain(|c| c != card);
+    }
+    let target = board
+        .columns
+        .iter_mut()
+        .find(|c| c.id == column)
+        .expect("column exists");
+    let position = position.min(target.cards.len());
+    target.cards.insert(position, card.to_string());
+    true
+}
+
+/// Reorder the columns. `order` must list every column exactly once.
+pub fn reorder_columns(board: &mut Board, order: &[String]) -> bool {
+    let mut remaining = std::mem::take(&mut board.columns);
+    let mut reordered = Vec::with_capacity(remaining.len());
+    for id in order {
+        let Some(index) = remaining.iter().position(|c| c.id == *id) else {
+            board.columns = reordered.into_iter().chain(remaining).collect();
+            return false;
+        };
+        reordered.push(remaining.remove(index));
+    }
+    let complete = remaining.is_empty();
+    reordered.extend(remaining);
+    board.columns = reordered;
+    complete
+}
+
+/// The board and whether it is stored yet. 403 unless `team` is the caller's tenant or a
+/// team they are a member of.
+async fn load_board(state: &AppState, user: &str, team: &str) -> Result<(Board, bool), StatusCode> {
+    if tenant_of(user) != team && !is_member(&state.storage, team, user).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let stored = state
+        .storage
+        .get_resource(&board_id(team))
+        .await
+        .map_err(|e| {
+            eprintln!("[boards] failed to load board of {}: {}", team, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    match stored.map(serde_json::from_value::<Board>) {
+        Some(Ok(board)) => Ok((board, true)),
+        Some(Err(e)) => {
+            eprintln!("[boards] malformed board of {}: {}", team, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        None => Ok((default_board(team), false)),
+    }
+}
+
+/// Commit the new column layout: a create for a default board, else a patch.
+async fn save_board(
+    state: &AppState,
+    headers: &HeaderMap,
+    user: String,
+    board: &Board,
+    stored: bool,
+) -> Result<Response, StatusCode> {
+    let id = board_id(&board.team);
+    let commit = if stored {
+        CommitBuilder::patch::<Board>(id.clone(), json!({ "columns": board.columns }))
+    } else {
+        CommitBuilder::create(id.clone(), board)
+    }
+    .actor(user)
+    .build();
+    submit_commit(state, headers, &id, commit).await
+}
+
+/// GET /boards/{team} - The team's kanban board
+#[utoipa::path(
+    get,
+    path = "/boards/{team}",
+    tag = "resources",
+    params(("team" = String, Path, description = "Tenant (email domain) or team ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The board; a default board if it was never reordered", body = Value),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not the caller's tenant or team"),
+    )
+)]
+pub async fn get_board_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(team): Path<String>,
+) -> Result<Json<Board>, StatusCode> {
+    let (board, _) = load_board(&state, &auth_user.user_id, &team).await?;
+    Ok(Json(board))
+}
+
+/// PUT /boards/{team}/columns - Reorder the columns of a board
+#[utoipa::path(
+    put,
+    path = "/boards/{team}/columns",
+    tag = "resources",
+    params(("team" = String, Path, description = "Tenant (email domain) or team ID")),
+    request_body = ColumnOrderRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Board commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Not every column listed exactly once"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not the caller's tenant or team"),
+    )
+)]
+pub async fn reorder_columns_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(team): Path<String>,
+    Json(request): Json<ColumnOrderRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    let (mut board, stored) = load_board(&state, &user, &team).await?;
+    if !reorder_columns(&mut board, &request.columns) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    save_board(&state, &headers, user, &board, stored).await
+}
+
+/// POST /boards/{team}/cards - Move an issue card to a position in a column
+#[utoipa::path(
+    post,
+    path = "/boards/{team}/cards",
+    tag = "resources",
+    params(("team" = String, Path, description = "Tenant (email domain) or team ID")),
+    request_body = MoveCardRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Board commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Unknown column"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Not the caller's tenant or team, or caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn move_card_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(team): Path<String>,
+    Json(request): Json<MoveCardRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    let (mut board, stored) = load_board(&state, &user, &team).await?;
+    authorize_issue(&state, &user, &request.card).await?;
+    if !move_card(&mut board, &request.card, &request.column, request.position) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    save_board(&state, &headers, user, &board, stored).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state};
+
+    fn cards(board: &Board, column: &str) -> Vec<String> {
+        let column = board.columns.iter().find(|c| c.id == column).unwrap();
+        column.cards.clone()
+    }
+
+    #[test]
+    fn test_move_and_reorder() {
+        let mut board = default_board("gemeente.nl");
+        assert!(move_card(&mut board, "a", "open", 0));
+        assert!(move_card(&mut board, "b", "open", 0));
+        assert!(move_card(&mut board, "c", "open", 99));
+        assert_eq!(cards(&board, "open"), vec!["b", "a", "c"]);
+        assert!(move_card(&mut board, "a", "closed", 0));
+        assert_eq!(cards(&board, "open"), vec!["b", "c"]);
+        assert!(!move_card(&mut board, "a", "archief", 0));
+
+        let order = |ids: &[&str]| ids.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(reorder_columns(
+            &mut board,
+            &order(&["closed", "open", "in_progress"])
+        ));
+        assert_eq!(board.columns[0].id, "closed");
+        assert_eq!(cards(&board, "closed"), vec!["a"]);
+        // Incomplete or unknown orders are rejected and leave all columns in place
+        assert!(!reorder_columns(&mut board, &order(&["open"])));
+        assert!(!reorder_columns(&mut board, &order(&["open", "archief"])));
+        assert_eq!(board.columns.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_board_order_is_persisted() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        create_issue(&state, "issue-1", &issue("Kapvergunning", &[alice]), alice).await;
+
+        let move_to = |who: &str, team: &str, column: &str| {
+            move_card_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Path(team.to_string()),
+                Json(MoveCardRequest {
+                    card: "issue-1".to_string(),
+                    column: column.to_string(),
+                    position: 0,
+                }),
+            )
+        };
+        assert_eq!(
+            move_to(alice, "gemeente.nl", "in_progress")
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            move_to(alice, "example.com", "open").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            move_to(alice, "gemeente.nl", "archief").await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        // Colleagues without access to the issue cannot move it
+        assert_eq!(
+            move_to("bob@gemeente.nl", "gemeente.nl", "open")
+                .await
+                .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+
+        let reorder = |who: &str| {
+            reorder_columns_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Path("gemeente.nl".to_string()),
+                Json(ColumnOrderRequest {
+                    columns: vec![
+                        "in_progress".to_string(),
+                        "open".to_string(),
+                        "closed".to_string(),
+                    ],
+                }),
+            )
+        };
+        assert_eq!(
+            reorder("mallory@example.com").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(reorder(alice).await.unwrap().status(), StatusCode::ACCEPTED);
+
+        // A colleague sees the same board, another tenant doesn't
+        let board = |who: &str| {
+            get_board_handler(
+                State(state.clone()),
+                user(who),
+                Path("gemeente.nl".to_string()),
+            )
+        };
+        assert_eq!(
+            board("mallory@example.com").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        let Json(board) = board("bob@gemeente.nl").await.unwrap();
+        assert_eq!(board.columns[0].id, "in_progress");
+        assert_eq!(cards(&board, "in_progress"), vec!["issue-1"]);
+    }
+}
@@ -0,0 +1,286 @@
+//! Bulk changes to resources: `POST /resources/bulk`.
+//!
+//! For changes that otherwise take dozens of requests, like closing 40 stale meldingen or
+//! reassigning the cases of someone who leaves. Each operation becomes its own commit by the
+//! caller, submitted through the event pipeline like `POST /events`, so it is validated,
+//! authorized and applied on its own: one failing operation does not stop the others. The
+//! response lists the outcome of every operation.
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::conflicts::MergeConflict;
+use crate::handlers::{latest_commit_schema, AppState};
+use crate::pipeline::{EventContext, ProcessError};
+use crate::schemas::{CloudEventBuilder, JSONCommit, PatchType};
+
+/// Most operations in one request
+pub const MAX_BULK_OPERATIONS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkAction {
+    Patch,
+    Delete,
+}
+
+/// One change in a bulk request
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct BulkOperation {
+    pub resource_id: String,
+    /// The subject the resource's events are about; defaults to the resource itself (issues)
+    #[serde(default)]
+    pub subject: Option<String>,
+    pub action: BulkAction,
+    /// With `patch`: the merge patch, or JSON Patch operations with `patch_type`
+    #[serde(default)]
+    #[schema(value_type = Option<Object>)]
+    pub patch: Option<Value>,
+    /// "merge-patch" (default) or "json-patch"
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub patch_type: Option<PatchType>,
+    /// The version the change was made on (see `conflicts`)
+    #[serde(default)]
+    pub base_sequence: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BulkRequest {
+    pub operations: Vec<BulkOperation>,
+}
+
+/// The outcome of one operation
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BulkResult {
+    pub resource_id: String,
+    /// HTTP status the operation would have had on its own (202 when applied)
+    pub status: u16,
+    /// Sequence number of the commit, when applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The conflicting fields, for a 409
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<MergeConflict>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BulkResponse {
+    pub applied: usize,
+    pub failed: usize,
+    /// One result per operation, in request order
+    pub results: Vec<BulkResult>,
+}
+
+impl BulkResult {
+    fn failed(resource_id: &str, status: StatusCode, error: impl Into<String>) -> Self {
+        BulkResult {
+            resource_id: resource_id.to_string(),
+            status: status.as_u16(),
+            sequence: None,
+            error: Some(error.into()),
+            conflict: None,
+        }
+    }
+}
+
+/// The commit `operation` makes as `actor`, or why it can't be made.
+async fn commit_for(
+    state: &AppState,
+    actor: &str,
+    subject: &str,
+    operation: &BulkOperation,
+) -> Result<JSONCommit, (StatusCode, String)> {
+    let (patch, deleted) = match (operation.action, &operation.patch) {
+        (BulkAction::Patch, Some(patch)) => (Some(patch.clone()), None),
+        (BulkAction::Patch, None) => {
+            return Err((StatusCode::BAD_REQUEST, "a patch needs `patch`".to_string()))
+        }
+        (BulkAction::Delete, None) => (None, Some(true)),
+        (BulkAction::Delete, Some(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "a delete takes no `patch`".to_string(),
+            ))
+        }
+    };
+    // The resource's schema is that of its earlier commits
+    let schema = latest_commit_schema(&state.storage, subject, &operation.resource_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let exists = state
+        .storage
+        .get_resource(&operation.resource_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .is_some();
+    let (Some(schema), true) = (schema, exists) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("no resource {} in {}", operation.resource_id, subject),
+        ));
+    };
+    Ok(JSONCommit {
+        schema,
+        resource_id: operation.resource_id.clone(),
+        actor: actor.to_string(),
+        timestamp: Some(chrono::Utc::now().to_rfc3339()),
+        resource_data: None,
+        patch,
+        patch_type: operation.patch_type,
+        deleted,
+        base_sequence: operation.base_sequence,
+        offline: None,
+    })
+}
+
+async fn apply(state: &AppState, actor: &str, operation: &BulkOperation) -> BulkResult {
+    let id = &operation.resource_id;
+    let subject = operation.subject.as_deref().unwrap_or(id);
+    let commit = match commit_for(state, actor, subject, operation).await {
+        Ok(commit) => commit,
+        Err((status, error)) => return BulkResult::failed(id, status, error),
+    };
+    let event = CloudEventBuilder::commit(subject, &commit).build();
+    let ctx = EventContext::new(event).inbound(Some(actor.to_string()));
+    match state.pipeline.submit(state, ctx).await {
+        Ok(event) => BulkResult {
+            resource_id: id.clone(),
+            status: StatusCode::ACCEPTED.as_u16(),
+            sequence: event.sequencenumber,
+            error: None,
+            conflict: None,
+        },
+        Err(ProcessError::Conflict(conflict)) => BulkResult {
+            conflict: Some(*conflict),
+            ..BulkResult::failed(id, StatusCode::CONFLICT, "stale base_sequence")
+        },
+        Err(e) => {
+            eprintln!("[bulk] operation on {} not applied: {}", id, e);
+            BulkResult::failed(id, e.status(), e.to_string())
+        }
+    }
+}
+
+/// POST /resources/bulk - Patch or delete many resources at once
+#[utoipa::path(
+    post,
+    path = "/resources/bulk",
+    tag = "resources",
+    request_body = BulkRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every operation was tried; the outcome per operation", body = BulkResponse),
+        (status = 400, description = "No operations, or more than 500"),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn bulk_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<BulkRequest>,
+) -> Result<Json<BulkResponse>, StatusCode> {
+    if request.operations.is_empty() || request.operations.len() > MAX_BULK_OPERATIONS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let mut results = Vec::with_capacity(request.operations.len());
+    for operation in &request.operations {
+        results.push(apply(&state, &auth_user.user_id, operation).await);
+    }
+    let applied = results
+        .iter()
+        .filter(|r| r.status == StatusCode::ACCEPTED.as_u16())
+        .count();
+    println!(
+        "[bulk] {} applied {} of {} operations",
+        auth_user.user_id,
+        applied,
+        results.len()
+    );
+    Ok(Json(BulkResponse {
+        applied,
+        failed: results.len() - applied,
+        results,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_bulk_operations_apply_per_item() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@example.com";
+        for (id, involved) in [("issue-1", alice), ("issue-2", alice), ("issue-3", "bob")] {
+            create_issue(&state, id, &issue(id, &[involved]), involved).await;
+        }
+
+        let operation = |id: &str, action: BulkAction, patch: Option<Value>| BulkOperation {
+            resource_id: id.to_string(),
+            subject: None,
+            action,
+            patch,
+            patch_type: None,
+            base_sequence: None,
+        };
+        let close = || Some(json!({"status": "closed"}));
+        let request = BulkRequest {
+            operations: vec![
+                operation("issue-1", BulkAction::Patch, close()),
+                operation("issue-3", BulkAction::Patch, close()),
+                operation("issue-9", BulkAction::Patch, close()),
+                operation("issue-2", BulkAction::Delete, close()),
+                operation("issue-2", BulkAction::Delete, None),
+            ],
+        };
+        let Json(response) = bulk_handler(State(state.clone()), user(alice), Json(request))
+            .await
+            .unwrap();
+        let statuses: Vec<u16> = response.results.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, vec![202, 403, 404, 400, 202]);
+        assert_eq!((response.applied, response.failed), (2, 3));
+
+        let issue = state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(issue["status"], "closed");
+        let issue = state
+            .storage
+            .get_resource("issue-3")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(issue["status"], "open");
+        assert!(state
+            .storage
+            .get_resource("issue-2")
+            .await
+            .unwrap()
+            .is_none());
+
+        let empty = bulk_handler(
+            State(state.clone()),
+            user(alice),
+            Json(BulkRequest { operations: vec![] }),
+        )
+        .await;
+        assert_eq!(empty.unwrap_err(), StatusCode::BAD_REQUEST);
+        let operations = (0..=MAX_BULK_OPERATIONS)
+            .map(|_| operation("issue-1", BulkAction::Patch, close()))
+            .collect();
+        let too_many =
+            bulk_handler(State(state), user(alice), Json(BulkRequest { operations })).await;
+        assert_eq!(too_many.unwrap_err(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,1092 @@
+//! Deadlines in behandelaars' work calendars, synchronized over CalDAV.
+//!
+//! A user's calendar entries are the deadlines of open tasks assigned to them (or of
+//! unassigned tasks on issues assigned to them) and the dated, not yet completed planning
+//! moments of issues assigned to them. Users who configure a CalDAV calendar collection with
+//! `PUT /users/me/calendar` get these pushed as one all-day event per entry. A sync job
+//! runs every [`SYNC_INTERVAL`] and compares the entries with what it pushed before (kept in
+//! storage), so commits that move a date update the event and completed or deleted items
+//! remove it. The settings hold credentials, so they are stored per user instead of as commits.
+//! The server contacts the calendar itself, so only https URLs of hosts in `CALDAV_HOSTS` (comma
+//! separated) are accepted, or without that setting, of hosts with only public addresses (see
+//! [`check_caldav_url`]).
+//!
+//! Without CalDAV, the same entries are served as iCalendar feeds to subscribe to:
+//! `GET /me/calendar.ics` and, for everything dated in one issue, `GET /issues/{id}/calendar.ics`.
+//! They are generated on every request. Calendar apps cannot set headers and keep a subscription
+//! URL for a long time, so the feeds also take a feed token as `?token=`: a JWT that lives for
+//! [`FEED_TOKEN_DAYS`], is only accepted by the feeds (not as a session) and is revoked by
+//! requesting a new one at `POST /users/me/calendar/feed-token` or by deleting it.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::AuthUser;
+use crate::handlers::{internal_error, AppState};
+use crate::schemas::{Issue, IssueStatus, Planning, PlanningStatus, Task};
+
+/// How often all configured calendars are synchronized
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(600);
+
+/// How long a calendar feed token is valid
+pub const FEED_TOKEN_DAYS: i64 = 365;
+
+/// Token ids (`jti`) of feed tokens start with this, so they can't be taken for invite links
+const FEED_TOKEN_PREFIX: &str = "calendar-feed-";
+
+/// The CalDAV calendar of a user
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CalendarSettings {
+    /// URL of the calendar collection, e.g. `https://cal.gemeente.nl/dav/alice/werk/`
+    pub url: String,
+    pub username: String,
+    pub password: String,
+}
+
+/// Calendar settings without the password
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CalendarStatus {
+    pub url: String,
+    pub username: String,
+    /// Number of events currently pushed to the calendar
+    pub entries: usize,
+}
+
+/// A token for the caller's calendar feeds
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FeedToken {
+    /// Pass as `?token=` to `/me/calendar.ics` and `/issues/{id}/calendar.ics`
+    pub token: String,
+    /// RFC 3339 timestamp after which the token is no longer accepted
+    pub expires_at: String,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct SyncReport {
+    /// Events created or updated
+    pub pushed: usize,
+    /// Events removed
+    pub removed: usize,
+}
+
+/// An all-day event for a deadline or planning moment
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarEntry {
+    pub uid: String,
+    pub date: chrono::NaiveDate,
+    pub summary: String,
+    pub description: String,
+}
+
+impl CalendarEntry {
+    /// What is compared with the previous sync to decide whether to push again
+    fn fingerprint(&self) -> String {
+        format!("{}\n{}\n{}", self.date, self.summary, self.description)
+    }
+}
+
+/// Escape a TEXT value (RFC 5545, 3.3.11).
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Fold a content line into lines of at most 75 octets (RFC 5545, 3.1).
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+/// A VCALENDAR object with one VEVENT per entry. `dtstamp` is when it was generated.
+pub fn to_ics(entries: &[CalendarEntry], dtstamp: chrono::DateTime<chrono::Utc>) -> String {
+    let mut ics = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//ZaakChat//NL\r\n");
+    for entry in entries {
+        let lines = [
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}", entry.uid),
+            format!("DTSTAMP:{}", dtstamp.format("%Y%m%dT%H%M%SZ")),
+            format!("DTSTART;VALUE=DATE:{}", entry.date.format("%Y%m%d")),
+            format!(
+                "DTEND;VALUE=DATE:{}",
+                (entry.date + chrono::Duration::days(1)).format("%Y%m%d")
+            ),
+            format!("SUMMARY:{}", escape_text(&entry.summary)),
+            format!("DESCRIPTION:{}", escape_text(&entry.description)),
+            "TRANSP:TRANSPARENT".to_string(),
+            "END:VEVENT".to_string(),
+        ];
+        for line in lines {
+            ics.push_str(&fold_line(&line));
+        }
+    }
+    ics.push_str("END:VCALENDAR\r\n");
+    ics
+}
+
+/// Keep UIDs usable as file names in the calendar collection.
+fn uid(parts: &[&str]) -> String {
+    let raw = format!("zaakchat-{}", parts.join("-"));
+    raw.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn parse_date(date: &str) -> Option<chrono::NaiveDate> {
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+/// The open deadlines and planning moments of one issue. With `user`, only theirs: tasks
+/// assigned to them (or unassigned, when the issue is), and the planning of issues assigned
+/// to them.
+async fn issue_entries(
+    state: &AppState,
+    issue_id: &str,
+    issue: &Issue,
+    user: Option<&str>,
+) -> Result<Vec<CalendarEntry>, Box<dyn std::error::Error + Send + Sync>> {
+    let base_url = std::env::var("BASE_URL").unwrap_or_else(|_| "https://zaakchat.nl".to_string());
+    let assigned = user.is_none_or(|user| issue.assignee.as_deref() == Some(user));
+    let link = format!("{}/zaak/{}", base_url, issue_id);
+    let mut entries = Vec::new();
+
+    for item in crate::portal::thread_items(state, issue_id).await? {
+        if item.value.get("cta").is_some() {
+            let Ok(task) = serde_json::from_value::<Task>(item.value) else {
+                continue;
+            };
+            let for_user = match (&task.assignee, user) {
+                (Some(assignee), Some(user)) => assignee == user,
+                (Some(_), None) => true,
+                (None, _) => assigned,
+            };
+            let date = task.deadline.as_deref().and_then(parse_date);
+            if let (true, false, Some(date)) = (for_user, task.completed, date) {
+                entries.push(CalendarEntry {
+                    uid: uid(&["task", &item.id]),
+                    date,
+                    summary: format!("{} ({})", task.cta, issue.title),
+                    description: format!("{}\n\n{}", task.description, link),
+                });
+            }
+        } else if assigned && item.value.get("moments").is_some() {
+            let Ok(planning) = serde_json::from_value::<Planning>(item.value) else {
+                continue;
+            };
+            for (index, moment) in planning.moments.iter().enumerate() {
+                if matches!(moment.status, PlanningStatus::Completed) {
+                    continue;
+                }
+                if let Some(date) = moment.date.as_deref().and_then(parse_date) {
+                    entries.push(CalendarEntry {
+                        uid: uid(&["planning", &item.id, &index.to_string()]),
+                        date,
+                        summary: format!("{} ({})", moment.title, issue.title),
+                        description: link.clone(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn sort_entries(entries: &mut [CalendarEntry]) {
+    entries.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.uid.cmp(&b.uid)));
+}
+
+/// The deadlines and planning moments that belong in `user`'s calendar, in date order.
+pub async fn user_entries(
+    state: &AppState,
+    user: &str,
+) -> Result<Vec<CalendarEntry>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut entries = Vec::new();
+    for (issue_id, value) in state.storage.list_resources(0, usize::MAX).await? {
+        let Ok(issue) = serde_json::from_value::<Issue>(value) else {
+            continue;
+        };
+        if matches!(issue.status, IssueStatus::Closed) {
+            continue;
+        }
+        entries.extend(issue_entries(state, &issue_id, &issue, Some(user)).await?);
+    }
+    sort_entries(&mut entries);
+    Ok(entries)
+}
+
+/// The open deadlines and planning moments of `issue_id`, in date order.
+pub async fn all_issue_entries(
+    state: &AppState,
+    issue_id: &str,
+) -> Result<Vec<CalendarEntry>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(issue) = state.storage.get_resource(issue_id).await? else {
+        return Ok(Vec::new());
+    };
+    let issue: Issue = serde_json::from_value(issue)?;
+    let mut entries = issue_entries(state, issue_id, &issue, None).await?;
+    sort_entries(&mut entries);
+    Ok(entries)
+}
+
+/// Writes events to a calendar collection.
+#[async_trait]
+pub trait CalendarClient: Send + Sync {
+    async fn put(
+        &self,
+        settings: &CalendarSettings,
+        uid: &str,
+        ics: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    async fn delete(
+        &self,
+        settings: &CalendarSettings,
+        uid: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Is `ip` reachable from anywhere? Loopback, private, link-local (cloud metadata), shared
+/// (carrier-grade NAT) and reserved addresses are the server's own network.
+fn is_public(ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    };
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_multicast()
+                || a == 0
+                || (a == 100 && (b & 0xc0) == 64)
+                || a >= 240)
+        }
+        IpAddr::V6(v6) => {
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_unique_local()
+                || v6.is_unicast_link_local()
+                || v6.is_multicast())
+        }
+    }
+}
+
+/// Check `url` against [`check_caldav_url`]'s rules, with `allowed` for `CALDAV_HOSTS`.
+async fn check_url(url: &str, allowed: Option<&[String]>) -> Result<(), String> {
+    let url = reqwest::Url::parse(url).map_err(|e| format!("invalid URL: {}", e))?;
+    if url.scheme() != "https" {
+        return Err("not an https URL".to_string());
+    }
+    let host = url.host_str().ok_or("URL without host")?;
+    if let Some(allowed) = allowed {
+        return match allowed
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+        {
+            true => Ok(()),
+            false => Err(format!("{} is not in CALDAV_HOSTS", host)),
+        };
+    }
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses: Vec<IpAddr> = match literal.parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => {
+            let port = url.port_or_known_default().unwrap_or(443);
+            tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| format!("can't resolve {}: {}", host, e))?
+                .map(|address| address.ip())
+                .collect()
+        }
+    };
+    match addresses.iter().find(|ip| !is_public(**ip)) {
+        Some(ip) => Err(format!("{} is not a public address", ip)),
+        None if addresses.is_empty() => Err("host has no addresses".to_string()),
+        None => Ok(()),
+    }
+}
+
+/// May the server contact the calendar at `url`? It must be https, and on a host listed in
+/// `CALDAV_HOSTS` or, without that setting, on a host that only has public addresses. Checked
+/// when the calendar is configured and before every request, as DNS may change.
+pub async fn check_caldav_url(url: &str) -> Result<(), String> {
+    let allowed = std::env::var("CALDAV_HOSTS").ok().map(|hosts| {
+        hosts
+            .split(',')
+            .map(|host| host.trim().to_string())
+            .filter(|host| !host.is_empty())
+            .collect::<Vec<_>>()
+    });
+    check_url(url, allowed.as_deref()).await
+}
+
+/// CalDAV over HTTP: one `{uid}.ics` resource per event, with basic auth. Redirects are not
+/// followed, as they could lead to hosts [`check_caldav_url`] refuses.
+pub struct CalDavClient {
+    http: reqwest::Client,
+}
+
+impl Default for CalDavClient {
+    fn default() -> Self {
+        let http = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("valid HTTP client");
+        Self { http }
+    }
+}
+
+fn event_url(settings: &CalendarSettings, uid: &str) -> String {
+    format!("{}/{}.ics", settings.url.trim_end_matches('/'), uid)
+}
+
+#[async_trait]
+impl CalendarClient for CalDavClient {
+    async fn put(
+        &self,
+        settings: &CalendarSettings,
+        uid: &str,
+        ics: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        check_caldav_url(&settings.url).await?;
+        self.http
+            .put(event_url(settings, uid))
+            .basic_auth(&settings.username, Some(&settings.password))
+            .header("Content-Type", "text/calendar; charset=utf-8")
+            .body(ics.to_string())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn delete(
+        &self,
+        settings: &CalendarSettings,
+        uid: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        check_caldav_url(&settings.url).await?;
+        let response = self
+            .http
+            .delete(event_url(settings, uid))
+            .basic_auth(&settings.username, Some(&settings.password))
+            .send()
+            .await?;
+        // Already removed in the calendar app is fine
+        if response.status() != reqwest::StatusCode::NOT_FOUND {
+            response.error_for_status()?;
+        }
+        Ok(())
+    }
+}
+
+/// Bring `user`'s calendar in line with `entries`: push new and changed events, remove
+/// events that are no longer there.
+async fn sync_entries(
+    state: &AppState,
+    client: &dyn CalendarClient,
+    user: &str,
+    settings: &CalendarSettings,
+    entries: Vec<CalendarEntry>,
+) -> Result<SyncReport, Box<dyn std::error::Error + Send + Sync>> {
+    let mut pushed: HashMap<String, String> = state
+        .storage
+        .list_calendar_entries(user)
+        .await?
+        .into_iter()
+        .collect();
+    let mut report = SyncReport::default();
+    let now = chrono::Utc::now();
+
+    for entry in entries {
+        let fingerprint = entry.fingerprint();
+        if pushed.remove(&entry.uid).as_ref() == Some(&fingerprint) {
+            continue;
+        }
+        let ics = to_ics(std::slice::from_ref(&entry), now);
+        client.put(settings, &entry.uid, &ics).await?;
+        state
+            .storage
+            .set_calendar_entry(user, &entry.uid, Some(&fingerprint))
+            .await?;
+        report.pushed += 1;
+    }
+    // What is left was pushed before but is no longer wanted
+    for uid in pushed.into_keys() {
+        client.delete(settings, &uid).await?;
+        state.storage.set_calendar_entry(user, &uid, None).await?;
+        report.removed += 1;
+    }
+    Ok(report)
+}
+
+/// Synchronize the calendar of one user.
+pub async fn sync_user(
+    state: &AppState,
+    client: &dyn CalendarClient,
+    user: &str,
+    settings: &CalendarSettings,
+) -> Result<SyncReport, Box<dyn std::error::Error + Send + Sync>> {
+    let entries = user_entries(state, user).await?;
+    sync_entries(state, client, user, settings, entries).await
+}
+
+async fn user_settings(
+    state: &AppState,
+    user: &str,
+) -> Result<Option<CalendarSettings>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(state
+        .storage
+        .list_calendars()
+        .await?
+        .into_iter()
+        .find(|(u, _)| u == user)
+        .and_then(|(_, settings)| serde_json::from_str(&settings).ok()))
+}
+
+/// Synchronize all configured calendars every [`SYNC_INTERVAL`].
+pub fn spawn_calendar_sync(state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = CalDavClient::default();
+        let mut ticker = tokio::time::interval(SYNC_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let calendars = match state.storage.list_calendars().await {
+                Ok(calendars) => calendars,
+                Err(e) => {
+                    eprintln!("[calendar] failed to list calendars: {}", e);
+                    continue;
+                }
+            };
+            for (user, settings) in calendars {
+                let Ok(settings) = serde_json::from_str::<CalendarSettings>(&settings) else {
+                    eprintln!("[calendar] skipping malformed settings of {}", user);
+                    continue;
+                };
+                if let Err(e) = sync_user(&state, &client, &user, &settings).await {
+                    eprintln!("[calendar] failed to sync calendar of {}: {}", user, e);
+                }
+            }
+        }
+    })
+}
+
+/// GET /users/me/calendar - The caller's CalDAV calendar (without password)
+#[utoipa::path(
+    get,
+    path = "/users/me/calendar",
+    tag = "resources",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The configured calendar", body = CalendarStatus),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "No calendar configured"),
+    )
+)]
+pub async fn get_calendar_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<CalendarStatus>, StatusCode> {
+    let user = auth_user.user_id;
+    let settings = user_settings(&state, &user)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let entries = state
+        .storage
+        .list_calendar_entries(&user)
+        .await
+        .map_err(internal_error)?
+        .len();
+    Ok(Json(CalendarStatus {
+        url: settings.url,
+        username: settings.username,
+        entries,
+    }))
+}
+
+/// PUT /users/me/calendar - Configure the CalDAV calendar to push deadlines to
+#[utoipa::path(
+    put,
+    path = "/users/me/calendar",
+    tag = "resources",
+    request_body = CalendarSettings,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Calendar configured; it is filled on the next sync", body = CalendarStatus),
+        (status = 400, description = "The URL is not https, or its host is not allowed"),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn update_calendar_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(settings): Json<CalendarSettings>,
+) -> Result<Json<CalendarStatus>, StatusCode> {
+    let user = auth_user.user_id;
+    if let Err(e) = check_caldav_url(&settings.url).await {
+        eprintln!("[calendar] refusing calendar of {}: {}", user, e);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // Events pushed to a previous calendar are not ours to track anymore
+    let previous = user_settings(&state, &user).await.map_err(internal_error)?;
+    if previous.is_some_and(|p| p.url != settings.url) {
+        for (uid, _) in state
+            .storage
+            .list_calendar_entries(&user)
+            .await
+            .map_err(internal_error)?
+        {
+            state
+                .storage
+                .set_calendar_entry(&user, &uid, None)
+                .await
+                .map_err(internal_error)?;
+        }
+    }
+    let json = serde_json::to_string(&settings).map_err(internal_error)?;
+    state
+        .storage
+        .set_calendar(&user, Some(&json))
+        .await
+        .map_err(internal_error)?;
+    let entries = state
+        .storage
+        .list_calendar_entries(&user)
+        .await
+        .map_err(internal_error)?
+        .len();
+    Ok(Json(CalendarStatus {
+        url: settings.url,
+        username: settings.username,
+        entries,
+    }))
+}
+
+/// DELETE /users/me/calendar - Remove the pushed events and stop synchronizing
+#[utoipa::path(
+    delete,
+    path = "/users/me/calendar",
+    tag = "resources",
+    security(("bearer" = [])),
+    responses(
+        (status = 204, description = "Events removed from the calendar and settings deleted"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "No calendar configured"),
+        (status = 502, description = "The calendar server refused to remove the events"),
+    )
+)]
+pub async fn delete_calendar_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<StatusCode, StatusCode> {
+    let user = auth_user.user_id;
+    let settings = user_settings(&state, &user)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    sync_entries(
+        &state,
+        &CalDavClient::default(),
+        &user,
+        &settings,
+        Vec::new(),
+    )
+    .await
+    .map_err(|e| {
+        eprintln!("[calendar] failed to clear calendar of {}: {}", user, e);
+        StatusCode::BAD_GATEWAY
+    })?;
+    state
+        .storage
+        .set_calendar(&user, None)
+        .await
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /users/me/calendar/sync - Synchronize the caller's calendar now
+#[utoipa::path(
+    post,
+    path = "/users/me/calendar/sync",
+    tag = "resources",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Calendar synchronized", body = SyncReport),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "No calendar configured"),
+        (status = 502, description = "The calendar server refused the changes"),
+    )
+)]
+pub async fn sync_calendar_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<SyncReport>, StatusCode> {
+    let user = auth_user.user_id;
+    let settings = user_settings(&state, &user)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    sync_user(&state, &CalDavClient::default(), &user, &settings)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            eprintln!("[calendar] failed to sync calendar of {}: {}", user, e);
+            StatusCode::BAD_GATEWAY
+        })
+}
+
+/// POST /users/me/calendar/feed-token - Issue a feed token, revoking the previous one
+#[utoipa::path(
+    post,
+    path = "/users/me/calendar/feed-token",
+    tag = "resources",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "A new feed token; earlier ones no longer work", body = FeedToken),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn create_feed_token_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<FeedToken>, StatusCode> {
+    let user = auth_user.user_id;
+    let token_id = format!("{}{}", FEED_TOKEN_PREFIX, uuid::Uuid::now_v7());
+    let lifetime = chrono::Duration::days(FEED_TOKEN_DAYS);
+    let token = crate::auth::create_jwt_with_id(&user, lifetime, Some(token_id.clone()))
+        .map_err(internal_error)?;
+    state
+        .storage
+        .set_calendar_feed(&user, Some(&token_id))
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(FeedToken {
+        token,
+        expires_at: (chrono::Utc::now() + lifetime).to_rfc3339(),
+    }))
+}
+
+/// DELETE /users/me/calendar/feed-token - Revoke the caller's feed token
+#[utoipa::path(
+    delete,
+    path = "/users/me/calendar/feed-token",
+    tag = "resources",
+    security(("bearer" = [])),
+    responses(
+        (status = 204, description = "Feed token revoked"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "No feed token issued"),
+    )
+)]
+pub async fn delete_feed_token_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<StatusCode, StatusCode> {
+    let user = auth_user.user_id;
+    state
+        .storage
+        .get_calendar_feed(&user)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    state
+        .storage
+        .set_calendar_feed(&user, None)
+        .await
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FeedParams {
+    /// Feed token, for calendar apps that cannot send an Authorization header
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// The user a feed request is authenticated as: a Bearer session token, or the user's current
+/// feed token as `?token=`.
+async fn feed_user(
+    state: &AppState,
+    headers: &HeaderMap,
+    params: &FeedParams,
+) -> Result<String, StatusCode> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if let Some(token) = bearer {
+        return crate::auth::verify_session_jwt(token)
+            .map(|claims| claims.sub)
+            .map_err(|_| StatusCode::UNAUTHORIZED);
+    }
+    let token = params.token.as_deref().ok_or(StatusCode::UNAUTHORIZED)?;
+    let claims = crate::auth::verify_jwt(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let Some(token_id) = claims.jti.filter(|id| id.starts_with(FEED_TOKEN_PREFIX)) else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let current = state
+        .storage
+        .get_calendar_feed(&claims.sub)
+        .await
+        .map_err(internal_error)?;
+    if current.as_deref() != Some(token_id.as_str()) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(claims.sub)
+}
+
+fn ics_response(entries: &[CalendarEntry]) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        to_ics(entries, chrono::Utc::now()),
+    )
+}
+
+/// GET /me/calendar.ics - The caller's deadlines and planning as an iCalendar feed
+#[utoipa::path(
+    get,
+    path = "/me/calendar.ics",
+    tag = "resources",
+    params(FeedParams),
+    security(("bearer" = []), ("feed_token" = [])),
+    responses(
+        (status = 200, description = "One all-day event per open deadline or planning moment", content_type = "text/calendar"),
+        (status = 401, description = "Missing, invalid or revoked token"),
+    )
+)]
+pub async fn my_calendar_feed_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<FeedParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let user = feed_user(&state, &headers, &params).await?;
+    let entries = user_entries(&state, &user).await.map_err(internal_error)?;
+    Ok(ics_response(&entries))
+}
+
+/// GET /issues/{id}/calendar.ics - An issue's deadlines and planning as an iCalendar feed
+#[utoipa::path(
+    get,
+    path = "/issues/{id}/calendar.ics",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID"), FeedParams),
+    security(("bearer" = []), ("feed_token" = [])),
+    responses(
+        (status = 200, description = "One all-day event per open deadline or planning moment", content_type = "text/calendar"),
+        (status = 401, description = "Missing, invalid or revoked token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn issue_calendar_feed_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(issue_id): Path<String>,
+    Query(params): Query<FeedParams>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let user = feed_user(&state, &headers, &params).await?;
+    crate::handlers::authorize_issue(&state, &user, &issue_id).await?;
+    let entries = all_issue_entries(&state, &issue_id)
+        .await
+        .map_err(internal_error)?;
+    Ok(ics_response(&entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{
+        auth_user as user, create_issue, issue, submit_commit_event, test_state,
+    };
+    use crate::schemas::CommitBuilder;
+    use std::sync::Mutex;
+
+    /// Keeps the calendar in memory
+    #[derive(Default)]
+    struct MemoryCalendar {
+        events: Mutex<HashMap<String, String>>,
+    }
+
+    #[async_trait]
+    impl CalendarClient for MemoryCalendar {
+        async fn put(
+            &self,
+            _settings: &CalendarSettings,
+            uid: &str,
+            ics: &str,
+        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            self.events
+                .lock()
+                .unwrap()
+                .insert(uid.to_string(), ics.to_string());
+            Ok(())
+        }
+
+        async fn delete(
+            &self,
+            _settings: &CalendarSettings,
+            uid: &str,
+        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            self.events.lock().unwrap().remove(uid);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_ics() {
+        let entry = CalendarEntry {
+            uid: "zaakchat-task-1".to_string(),
+            date: chrono::NaiveDate::from_ymd_opt(2024, 1, 25).unwrap(),
+            summary: "Bellen, mailen; klaar".to_string(),
+            description: "x".repeat(100),
+        };
+        let ics = to_ics(&[entry], chrono::Utc::now());
+        assert!(ics.contains("DTSTART;VALUE=DATE:20240125\r\nDTEND;VALUE=DATE:20240126\r\n"));
+        assert!(ics.contains("SUMMARY:Bellen\\, mailen\\; klaar\r\n"));
+        assert!(ics.lines().all(|line| line.len() <= 75));
+    }
+
+    #[tokio::test]
+    async fn test_caldav_url() {
+        for url in [
+            "http://93.184.216.34/dav/",
+            "https://127.0.0.1/dav/",
+            "https://10.1.2.3/dav/",
+            "https://169.254.169.254/latest/meta-data/",
+            "https://100.64.0.1/",
+            "https://[::1]/dav/",
+            "https://[fd00::1]/dav/",
+            "https://[::ffff:192.168.1.1]/dav/",
+            "https://localhost/dav/",
+            "not a url",
+        ] {
+            assert!(check_url(url, None).await.is_err(), "{}", url);
+        }
+        assert!(check_url("https://93.184.216.34/dav/", None).await.is_ok());
+        assert!(check_url("https://[2606:2800:220:1::1]/", None)
+            .await
+            .is_ok());
+
+        // Configured hosts may be internal, others are refused
+        let allowed = ["cal.gemeente.local".to_string()];
+        assert!(check_url("https://cal.gemeente.local/dav/", Some(&allowed))
+            .await
+            .is_ok());
+        assert!(check_url("https://93.184.216.34/dav/", Some(&allowed))
+            .await
+            .is_err());
+        assert!(check_url("http://cal.gemeente.local/dav/", Some(&allowed))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sync_follows_commits() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let settings = CalendarSettings {
+            url: "https://cal.example/alice/".to_string(),
+            username: alice.to_string(),
+            password: "geheim".to_string(),
+        };
+        let calendar = MemoryCalendar::default();
+
+        let issue = Issue {
+            assignee: Some(alice.to_string()),
+            ..issue("Kapvergunning", &[alice])
+        };
+        create_issue(&state, "issue-1", &issue, alice).await;
+        let task = Task {
+            cta: "Boom inspecteren".to_string(),
+            description: String::new(),
+            url: String::new(),
+            completed: false,
+            deadline: Some("2024-01-25".to_string()),
+            assignee: None,
+            recurrence: None,
+        };
+        let commit = CommitBuilder::create("task-1", &task).actor(alice).build();
+        submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+
+        let sync = || sync_user(&state, &calendar, alice, &settings);
+        assert_eq!(
+            sync().await.unwrap(),
+            SyncReport {
+                pushed: 1,
+                removed: 0
+            }
+        );
+        assert_eq!(sync().await.unwrap(), SyncReport::default());
+
+        // Moving the deadline updates the event
+        let patch =
+            CommitBuilder::patch::<Task>("task-1", serde_json::json!({ "deadline": "2024-02-01" }))
+                .build();
+        submit_commit_event(&state, "issue-1", &patch)
+            .await
+            .unwrap();
+        assert_eq!(sync().await.unwrap().pushed, 1);
+        let events = calendar.events.lock().unwrap().clone();
+        assert!(events["zaakchat-task-task-1"].contains("DTSTART;VALUE=DATE:20240201"));
+
+        // Completing the task removes it
+        let patch =
+            CommitBuilder::patch::<Task>("task-1", serde_json::json!({ "completed": true }))
+                .build();
+        submit_commit_event(&state, "issue-1", &patch)
+            .await
+            .unwrap();
+        assert_eq!(sync().await.unwrap().removed, 1);
+        assert!(calendar.events.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ics_feeds() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let (alice, bob) = ("alice@gemeente.nl", "bob@gemeente.nl");
+        let kapvergunning = Issue {
+            assignee: Some(alice.to_string()),
+            ..issue("Kapvergunning", &[alice, bob])
+        };
+        create_issue(&state, "issue-1", &kapvergunning, alice).await;
+        for (id, assignee) in [("task-1", alice), ("task-2", bob)] {
+            let task = Task {
+                cta: "Boom inspecteren".to_string(),
+                description: String::new(),
+                url: String::new(),
+                completed: false,
+                deadline: Some("2024-01-25".to_string()),
+                assignee: Some(assignee.to_string()),
+                recurrence: None,
+            };
+            let commit = CommitBuilder::create(id, &task).build();
+            submit_commit_event(&state, "issue-1", &commit)
+                .await
+                .unwrap();
+        }
+        let issue_token = |who: &str| create_feed_token_handler(State(state.clone()), user(who));
+        let params = |token: &str| {
+            Query(FeedParams {
+                token: Some(token.to_string()),
+            })
+        };
+        let my_feed = |token: &str| {
+            my_calendar_feed_handler(State(state.clone()), HeaderMap::new(), params(token))
+        };
+        let issue_feed = |token: &str| {
+            issue_calendar_feed_handler(
+                State(state.clone()),
+                HeaderMap::new(),
+                Path("issue-1".to_string()),
+                params(token),
+            )
+        };
+        let body = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        let bob_token = issue_token(bob).await.unwrap().0.token;
+        let response = my_feed(&bob_token).await.unwrap().into_response();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/calendar; charset=utf-8"
+        );
+        let ics = body(response).await;
+        assert!(ics.contains("UID:zaakchat-task-task-2\r\n"));
+        assert!(!ics.contains("task-1"));
+
+        // The issue feed has everyone's deadlines
+        let response = issue_feed(&bob_token).await.unwrap().into_response();
+        let ics = body(response).await;
+        assert!(ics.contains("task-1") && ics.contains("task-2"));
+        let eve_token = issue_token("eve@example.com").await.unwrap().0.token;
+        assert_eq!(
+            issue_feed(&eve_token).await.err(),
+            Some(StatusCode::FORBIDDEN)
+        );
+
+        // A feed token is no session token, and session tokens don't go in URLs
+        let mut parts = axum::http::Request::builder()
+            .header(header::AUTHORIZATION, format!("Bearer {}", bob_token))
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0;
+        let session =
+            <AuthUser as axum::extract::FromRequestParts<()>>::from_request_parts(&mut parts, &());
+        assert_eq!(session.await.err(), Some(StatusCode::UNAUTHORIZED));
+        let session_token = crate::auth::create_jwt(bob).unwrap();
+        assert_eq!(
+            my_feed(&session_token).await.err(),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+
+        // A new token revokes the old one, and so does deleting it
+        let renewed = issue_token(bob).await.unwrap().0.token;
+        assert_eq!(
+            my_feed(&bob_token).await.err(),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert!(my_feed(&renewed).await.is_ok());
+        assert_eq!(
+            delete_feed_token_handler(State(state.clone()), user(bob)).await,
+            Ok(StatusCode::NO_CONTENT)
+        );
+        assert_eq!(
+            my_feed(&renewed).await.err(),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            my_calendar_feed_handler(
+                State(state.clone()),
+                HeaderMap::new(),
+                Query(FeedParams::default()),
+            )
+            .await
+            .err(),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+    }
+}
@@ -0,0 +1,365 @@
+//! Checklists: routine verification steps within an issue, lighter than Tasks.
+//!
+//! A `Checklist` is a resource in the issue's thread, created with a commit like any other.
+//! Zaaktypes can define checklist templates; `POST /issues/{id}/checklists` creates a
+//! checklist from one (or from a list of items). `PUT /checklists/{id}/items/{index}` ticks
+//! an item off or on with a JSON Patch commit, so toggling two items at once does not
+//! overwrite either. Issue reads report the progress over all checklists of the issue.
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{authorize_issue, internal_error, submit_commit, AppState};
+use crate::schemas::{Checklist, ChecklistItem, CommitBuilder, Zaaktype};
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChecklistRequest {
+    /// Zaaktype whose template to use
+    #[serde(default)]
+    pub zaaktype: Option<String>,
+    /// Title of the template, or of a checklist without template
+    pub title: String,
+    /// The items of a checklist without template
+    #[serde(default)]
+    pub items: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ToggleRequest {
+    pub done: bool,
+}
+
+/// Items done of all checklists of an issue
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct ChecklistProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+/// The checklists of `issue_id`, with their IDs, in order of creation.
+pub async fn issue_checklists(
+    state: &AppState,
+    issue_id: &str,
+) -> Result<Vec<(String, Checklist)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut checklists = Vec::new();
+    for item in crate::portal::thread_items(state, issue_id).await? {
+        if item.value.get("items").is_none() {
+            continue;
+        }
+        if state.storage.get_resource_type(&item.id).await?.as_deref() != Some("Checklist") {
+            continue;
+        }
+        if let Ok(checklist) = serde_json::from_value(item.value) {
+            checklists.push((item.id, checklist));
+        }
+    }
+    Ok(checklists)
+}
+
+/// The progress over the checklists of `issue_id`; `None` when it has none.
+pub async fn progress(
+    state: &AppState,
+    issue_id: &str,
+) -> Result<Option<ChecklistProgress>, Box<dyn std::error::Error + Send + Sync>> {
+    let checklists = issue_checklists(state, issue_id).await?;
+    if checklists.is_empty() {
+        return Ok(None);
+    }
+    let items = || checklists.iter().flat_map(|(_, c)| &c.items);
+    Ok(Some(ChecklistProgress {
+        done: items().filter(|item| item.done).count(),
+        total: items().count(),
+    }))
+}
+
+/// The items of `zaaktype_id`'s template titled `title`.
+async fn template_items(
+    state: &AppState,
+    zaaktype_id: &str,
+    title: &str,
+) -> Result<Vec<String>, StatusCode> {
+    if state
+        .storage
+        .get_resource_type(zaaktype_id)
+        .await
+        .map_err(internal_error)?
+        .as_deref()
+        != Some("Zaaktype")
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let zaaktype: Zaaktype = state
+        .storage
+        .get_resource(zaaktype_id)
+        .await
+        .map_err(internal_error)?
+        .and_then(|value| serde_json::from_value(value).ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    zaaktype
+        .checklists
+        .into_iter()
+        .find(|template| template.title == title)
+        .map(|template| template.items)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// POST /issues/{id}/checklists - Add a checklist to an issue
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/checklists",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    request_body = ChecklistRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Create commit stored, processed and broadcast", body = crate::schemas::CloudEvent),
+        (status = 400, description = "No items, and no template to take them from"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue, zaaktype or template"),
+    )
+)]
+pub async fn create_checklist_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(issue_id): Path<String>,
+    Json(request): Json<ChecklistRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    authorize_issue(&state, &user, &issue_id).await?;
+    let items = match &request.zaaktype {
+        Some(zaaktype) => template_items(&state, zaaktype, &request.title).await?,
+        None => request.items,
+    };
+    if items.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let checklist = Checklist {
+        issue_id: issue_id.clone(),
+        title: request.title,
+        items: items
+            .into_iter()
+            .map(|text| ChecklistItem {
+                text,
+                done: false,
+                done_by: None,
+            })
+            .collect(),
+    };
+    let id = format!("checklist-{}", uuid::Uuid::now_v7());
+    let commit = CommitBuilder::create(id, &checklist).actor(user).build();
+    submit_commit(&state, &headers, &issue_id, commit).await
+}
+
+/// PUT /checklists/{id}/items/{index} - Tick a checklist item off, or on again
+#[utoipa::path(
+    put,
+    path = "/checklists/{id}/items/{index}",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Checklist ID"),
+        ("index" = usize, Path, description = "Position of the item, from 0"),
+    ),
+    request_body = ToggleRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Patch commit stored, processed and broadcast", body = crate::schemas::CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the checklist's issue"),
+        (status = 404, description = "Unknown checklist or item"),
+    )
+)]
+pub async fn toggle_item_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path((id, index)): Path<(String, usize)>,
+    Json(request): Json<ToggleRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    if state
+        .storage
+        .get_resource_type(&id)
+        .await
+        .map_err(internal_error)?
+        .as_deref()
+        != Some("Checklist")
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let checklist: Checklist = state
+        .storage
+        .get_resource(&id)
+        .await
+        .map_err(internal_error)?
+        .and_then(|value| serde_json::from_value(value).ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    authorize_issue(&state, &user, &checklist.issue_id).await?;
+    let item = checklist.items.get(index).ok_or(StatusCode::NOT_FOUND)?;
+
+    let path = format!("/items/{}", index);
+    let mut operations = vec![
+        // Fails when the items were reordered meanwhile, instead of ticking the wrong one
+        json!({"op": "test", "path": format!("{}/text", path), "value": item.text}),
+        json!({"op": "replace", "path": format!("{}/done", path), "value": request.done}),
+    ];
+    if request.done {
+        operations.push(json!({"op": "add", "path": format!("{}/done_by", path), "value": user}));
+    } else if item.done_by.is_some() {
+        operations.push(json!({"op": "remove", "path": format!("{}/done_by", path)}));
+    }
+    let commit = CommitBuilder::json_patch::<Checklist>(id, json!(operations))
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &checklist.issue_id, commit).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{
+        auth_user as user, create_issue, issue, submit_commit_event, test_state,
+    };
+
+    #[tokio::test]
+    async fn test_checklist_from_template() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        create_issue(&state, "issue-1", &issue("Paspoort", &[alice]), alice).await;
+        let zaaktype: Zaaktype = serde_json::from_value(json!({
+            "title": "Paspoort aanvragen",
+            "states": [{"key": "ontvangen", "title": "Ontvangen"}],
+            "checklists": [{"title": "Documentcheck", "items": ["Pasfoto", "Oud paspoort"]}],
+        }))
+        .unwrap();
+        let commit = CommitBuilder::create("zaaktype-paspoort", &zaaktype).build();
+        submit_commit_event(&state, "zaaktype-paspoort", &commit)
+            .await
+            .unwrap();
+
+        let request = |zaaktype: Option<&str>, title: &str| {
+            Json(ChecklistRequest {
+                zaaktype: zaaktype.map(str::to_string),
+                title: title.to_string(),
+                items: vec![],
+            })
+        };
+        let response = create_checklist_handler(
+            State(state.clone()),
+            user(alice),
+            HeaderMap::new(),
+            Path("issue-1".to_string()),
+            request(Some("zaaktype-paspoort"), "Documentcheck"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(
+            create_checklist_handler(
+                State(state.clone()),
+                user(alice),
+                HeaderMap::new(),
+                Path("issue-1".to_string()),
+                request(Some("zaaktype-paspoort"), "Onbekend"),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            create_checklist_handler(
+                State(state.clone()),
+                user(alice),
+                HeaderMap::new(),
+                Path("issue-1".to_string()),
+                request(None, "Leeg"),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let outsider = create_checklist_handler(
+            State(state.clone()),
+            user("mallory@example.com"),
+            HeaderMap::new(),
+            Path("issue-1".to_string()),
+            request(Some("zaaktype-paspoort"), "Documentcheck"),
+        );
+        assert_eq!(outsider.await.unwrap_err(), StatusCode::FORBIDDEN);
+
+        let checklists = issue_checklists(&state, "issue-1").await.unwrap();
+        assert_eq!(checklists.len(), 1);
+        let (id, checklist) = &checklists[0];
+        assert_eq!(checklist.items.len(), 2);
+        assert_eq!(
+            progress(&state, "issue-1").await.unwrap(),
+            Some(ChecklistProgress { done: 0, total: 2 })
+        );
+
+        let outsider = toggle_item_handler(
+            State(state.clone()),
+            user("mallory@example.com"),
+            HeaderMap::new(),
+            Path((id.clone(), 1)),
+            Json(ToggleRequest { done: true }),
+        );
+        assert_eq!(outsider.await.unwrap_err(), StatusCode::FORBIDDEN);
+        toggle_item_handler(
+            State(state.clone()),
+            user(alice),
+            HeaderMap::new(),
+            Path((id.clone(), 1)),
+            Json(ToggleRequest { done: true }),
+        )
+        .await
+        .unwrap();
+        let (_, checklist) = &issue_checklists(&state, "issue-1").await.unwrap()[0];
+        assert!(checklist.items[1].done);
+        assert_eq!(checklist.items[1].done_by.as_deref(), Some(alice));
+
+        // Issue reads show the progress
+        let Json(issue) = crate::handlers::get_resource(
+            State(state.clone()),
+            Some(user(alice)),
+            Path("issue-1".to_string()),
+            axum::extract::Query(Default::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(issue["checklists"], json!({"done": 1, "total": 2}));
+
+        toggle_item_handler(
+            State(state.clone()),
+            user(alice),
+            HeaderMap::new(),
+            Path((id.clone(), 1)),
+            Json(ToggleRequest { done: false }),
+        )
+        .await
+        .unwrap();
+        let (_, checklist) = &issue_checklists(&state, "issue-1").await.unwrap()[0];
+        assert!(!checklist.items[1].done && checklist.items[1].done_by.is_none());
+        assert_eq!(
+            toggle_item_handler(
+                State(state.clone()),
+                user(alice),
+                HeaderMap::new(),
+                Path((id.clone(), 2)),
+                Json(ToggleRequest { done: true }),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,330 @@
+//! Typed HTTP client for the ZaakChat API, for Rust services that talk to a ZaakChat server.
+//!
+//! Enabled with the `client` feature:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//! use futures_util::StreamExt;
+//! use zaakchat::client::ZaakchatClient;
+//!
+//! let client = ZaakchatClient::new("http://localhost:8000").with_token("<jwt>");
+//! let issues = client.search("is:Issue status:open", 50).await?;
+//!
+//! let mut events = Box::pin(client.subscribe(None));
+//! while let Some(event) = events.next().await {
+//!     println!("{} {}", event.event_type, event.subject);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::time::Duration;
+
+use futures_util::Stream;
+use reqwest::{header::ACCEPT, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+
+use crate::handlers::ResourceResponse;
+use crate::schemas::{CloudEvent, CloudEventBuilder, JSONCommit};
+use crate::storage::SearchResult;
+
+type ClientResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Delay before the first reconnect attempt of `subscribe`
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Upper bound for the reconnect delay of `subscribe`
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+pub struct ZaakchatClient {
+    base_url: String,
+    token: Option<String>,
+    http: reqwest::Client,
+}
+
+impl ZaakchatClient {
+    /// Create a client for the server at `base_url` (e.g. "https://zaakchat.nl").
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: None,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Authenticate requests with a JWT (see `POST /login`).
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Use a preconfigured reqwest client (timeouts, proxies, ...).
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// POST a CloudEvent. Returns the stored event, including its server-assigned sequence.
+    pub async fn submit_event(&self, event: &CloudEvent) -> ClientResult<CloudEvent> {
+        let request = self.http.post(self.url("/events")).json(event);
+        self.send_json(request).await
+    }
+
+    /// Wrap a JSONCommit (see `schemas::CommitBuilder`) in a `json.commit` CloudEvent about
+    /// `subject` and submit it.
+    pub async fn submit_commit(
+        &self,
+        subject: &str,
+        commit: &JSONCommit,
+    ) -> ClientResult<CloudEvent> {
+        let event = CloudEventBuilder::commit(subject, commit)
+            .source("zaakchat-client")
+            .dataschema(self.url("/schemas/JSONCommit"))
+            .build();
+        self.submit_event(&event).await
+    }
+
+    /// GET /resources
+    pub async fn list_resources(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> ClientResult<Vec<ResourceResponse>> {
+        let request = self
+            .http
+            .get(self.url("/resources"))
+            .query(&[("offset", offset), ("limit", limit)]);
+        self.send_json(request).await
+    }
+
+    /// GET /resources/{id}. Returns `None` when the resource does not exist.
+    pub async fn get_resource(&self, id: &str) -> ClientResult<Option<ResourceResponse>> {
+        let request = self.http.get(self.url(&format!("/resources/{}", id)));
+        match self.send_json(request).await {
+            Ok(resource) => Ok(Some(resource)),
+            Err(e) if is_not_found(e.as_ref()) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// GET /query - full-text search, scoped to the authenticated user.
+    pub async fn search(&self, query: &str, limit: usize) -> ClientResult<Vec<SearchResult>> {
+        let request = self
+            .http
+            .get(self.url("/query"))
+            .query(&[("q", query.to_string()), ("limit", limit.to_string())]);
+        self.send_json(request).await
+    }
+
+    /// GET /events?format=json - stored events after `after_seq`, in sequence order.
+    pub async fn list_events(
+        &self,
+        after_seq: Option<&str>,
+        limit: usize,
+    ) -> ClientResult<Vec<CloudEvent>> {
+        let mut request = self
+            .events_request()
+            .query(&[("format", "json"), ("limit", &limit.to_string())]);
+        if let Some(after_seq) = after_seq {
+            request = request.query(&[("after_seq", after_seq)]);
+        }
+        self.send_json(request).await
+    }
+
+    /// Follow the SSE stream on `/events`, starting after `after_seq`.
+    ///
+    /// The stream never ends: when the connection drops it reconnects with exponential
+    /// backoff and resumes after the last sequence it delivered, so no events are skipped
+    /// or repeated.
+    pub fn subscribe(&self, after_seq: Option<String>) -> impl Stream<Item = CloudEvent> + Send {
+        let client = self.clone();
+
+        async_stream::stream! {
+            let mut last_seq = after_seq;
+            let mut backoff = INITIAL_BACKOFF;
+
+            loop {
+                let mut request = client
+                    .events_request()
+                    .header(ACCEPT, "text/event-stream");
+                if let Some(seq) = &last_seq {
+                    request = request.query(&[("after_seq", seq)]);
+                }
+
+                match request.send().await.and_then(|r| r.error_for_status()) {
+                    Ok(mut response) => {
+                        backoff = INITIAL_BACKOFF;
+                        let mut parser = SseParser::default();
+
+                        loop {
+                            let chunk = match response.chunk().await {
+                                Ok(Some(chunk)) => chunk,
+                                Ok(None) => break,
+                                Err(e) => {
+                                    eprintln!("[client] event stream interrupted: {}", e);
+                                    break;
+                                }
+                            };
+
+                            for frame in parser.push(&chunk) {
+                                for event in frame.cloud_events() {
+                                    if event.sequence.is_some() {
+                                        last_seq = event.sequence.clone();
+                                    }
+                                    yield event;
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => eprintln!("[client] failed to connect to event stream: {}", e),
+                }
+
+                tokio::time::sleep(backoff).await;
+                backoff = next_backoff(backoff);
+            }
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// `GET /events` authenticates with `?token=`, because browsers' EventSource cannot
+    /// send an Authorization header.
+    fn events_request(&self) -> RequestBuilder {
+        let request = self.http.get(self.url("/events"));
+        match &self.token {
+            Some(token) => request.query(&[("token", token)]),
+            None => request,
+        }
+    }
+
+    async fn send_json<T: DeserializeOwned>(&self, request: RequestBuilder) -> ClientResult<T> {
+        let request = match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request.send().await?.error_for_status()?;
+        Ok(response.json().await?)
+    }
+}
+
+fn is_not_found(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        == Some(StatusCode::NOT_FOUND)
+}
+
+fn next_backoff(current: Duration) -> Duration {
+    (current * 2).min(MAX_BACKOFF)
+}
+
+/// A dispatched Server-Sent Event
+#[derive(Debug, PartialEq)]
+struct SseFrame {
+    event: String,
+    data: String,
+}
+
+impl SseFrame {
+    /// CloudEvents carried by a `snapshot` (array) or `delta` (single event) frame.
+    fn cloud_events(&self) -> Vec<CloudEvent> {
+        let parsed = match self.event.as_str() {
+            "snapshot" => serde_json::from_str::<Vec<CloudEvent>>(&self.data),
+            "delta" => serde_json::from_str::<CloudEvent>(&self.data).map(|e| vec![e]),
+            _ => return Vec::new(),
+        };
+        parsed.unwrap_or_else(|e| {
+            eprintln!("[client] ignoring malformed {} frame: {}", self.event, e);
+            Vec::new()
+        })
+    }
+}
+
+/// Incremental parser for the `text/event-stream` format. Chunks may split lines (and
+/// UTF-8 sequences) anywhere, so bytes are buffered until a full line is available.
+#[derive(Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+    event: String,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    fn push(&mut self, chunk: &[u8]) -> Vec<SseFrame> {
+        self.buffer.extend_from_slice(chunk);
+        let mut frames = Vec::new();
+
+        while let Some(newline) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    frames.push(SseFrame {
+                        event: std::mem::take(&mut self.event),
+                        data: self.data.join("\n"),
+                    });
+                }
+                self.event.clear();
+                self.data.clear();
+                continue;
+            }
+            if line.starts_with(':') {
+                // Comment, used for keep-alives
+                continue;
+            }
+
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => self.event = value.to_string(),
+                "data" => self.data.push(value.to_string()),
+                _ => {}
+            }
+        }
+
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser_handles_split_chunks() {
+        let mut parser = SseParser::default();
+        let delta = r#"{"specversion":"1.0","id":"e1","source":"s","subject":"issue-1","type":"json.commit","sequence":"00000000000000000007"}"#;
+        let stream = format!(
+            ": keep-alive\n\nevent: snapshot\ndata: []\n\nevent: delta\r\ndata: {}\n\n",
+            delta
+        );
+
+        // Feed the stream in small pieces, splitting lines mid-way
+        let mut frames = Vec::new();
+        for chunk in stream.as_bytes().chunks(7) {
+            frames.extend(parser.push(chunk));
+        }
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].event, "snapshot");
+        assert!(frames[0].cloud_events().is_empty());
+
+        let events = frames[1].cloud_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, "e1");
+        assert_eq!(events[0].sequence.as_deref(), Some("00000000000000000007"));
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let mut backoff = INITIAL_BACKOFF;
+        for _ in 0..20 {
+            backoff = next_backoff(backoff);
+        }
+        assert_eq!(backoff, MAX_BACKOFF);
+        assert_eq!(next_backoff(INITIAL_BACKOFF), Duration::from_secs(1));
+    }
+}
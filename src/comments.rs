@@ -0,0 +1,792 @@
+//! Interactions with comments: editing, emoji reactions and reply threads.
+//!
+//! Authors can edit or delete their own comments for `COMMENT_EDIT_WINDOW_MINUTES` after
+//! posting. Edits are patch commits that set `edited_at` (rendered as "bewerkt"); earlier
+//! versions stay in the event log and are served by `GET /comments/{id}/history`.
+//!
+//! Reactions are ordinary resources (`Reaction`), created and deleted with JSONCommits on
+//! the comment's issue, so they show up in the event stream like any other change. The
+//! issue timeline (`GET /issues/{id}/timeline`) folds them into per-comment counts.
+//!
+//! A comment replies to the comment in its `quote_comment`. `process_event` keeps an index
+//! of replies per comment in storage, which `GET /comments/{id}/thread` and the threaded
+//! timeline order walk.
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{
+    apply_commit, check_access, commit_of, submit_commit, AppState, CATCHUP_PAGE_SIZE,
+};
+use crate::schemas::{CloudEvent, Comment, CommitBuilder, JSONCommit, Reaction};
+use crate::storage::Storage;
+
+/// Replies nested deeper than this are not followed (guards against `quote_comment` cycles)
+const MAX_THREAD_DEPTH: usize = 100;
+
+/// Default for `COMMENT_EDIT_WINDOW_MINUTES`
+const DEFAULT_EDIT_WINDOW_MINUTES: i64 = 15;
+
+/// Longest accepted emoji, in chars (flags and skin-tone/ZWJ sequences are several chars)
+const MAX_EMOJI_CHARS: usize = 16;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ReactionRequest {
+    /// The emoji to react with, e.g. "👍"
+    pub emoji: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CommentEditRequest {
+    /// The new text of the comment
+    pub content: String,
+}
+
+/// One version of a comment, as left behind by a commit
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CommentVersion {
+    /// Sequence of the event that produced this version
+    pub sequence: Option<String>,
+    pub actor: String,
+    pub timestamp: Option<String>,
+    /// The comment after the commit; `None` once deleted
+    pub comment: Option<Value>,
+}
+
+/// A comment in a thread
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ThreadComment {
+    pub id: String,
+    /// The comment this one replies to; `None` for the thread root
+    pub parent_id: Option<String>,
+    /// 0 for the root, 1 for its direct replies, ...
+    pub depth: usize,
+    pub comment: Value,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CommentThread {
+    pub root_id: String,
+    /// The root followed by all replies, depth-first in creation order
+    pub comments: Vec<ThreadComment>,
+}
+
+/// Resource ID of `actor`'s `emoji` reaction on a comment. Deterministic, so adding the
+/// same reaction twice is a no-op and removing it needs no lookup.
+pub fn reaction_id(comment_id: &str, actor: &str, emoji: &str) -> String {
+    let codepoints: Vec<String> = emoji.chars().map(|c| format!("{:x}", c as u32)).collect();
+    format!("reaction-{}-{}-{}", comment_id, actor, codepoints.join("-"))
+}
+
+/// Is this commit about a `Reaction` resource?
+pub fn is_reaction_commit(commit: &JSONCommit) -> bool {
+    commit
+        .schema
+        .trim_end_matches(".json")
+        .ends_with("/Reaction")
+}
+
+fn valid_emoji(emoji: &str) -> bool {
+    let chars = emoji.chars().count();
+    chars > 0 && chars <= MAX_EMOJI_CHARS && !emoji.chars().any(char::is_whitespace)
+}
+
+/// Where a comment comes from, according to the commit that created it
+#[derive(Debug, Clone)]
+pub(crate) struct CommentOrigin {
+    /// The issue the comment belongs to: the subject of its latest commit, which differs
+    /// from the one it was posted on once that issue is merged into another
+    pub issue_id: String,
+    /// Actor of the creating commit
+    pub author: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Find the commit that created a comment. Comments don't reference their issue or
+/// author, so this scans the event log.
+pub(crate) async fn comment_origin(
+    storage: &Storage,
+    comment_id: &str,
+) -> Result<Option<CommentOrigin>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut origin: Option<CommentOrigin> = None;
+    let mut after = None;
+    loop {
+        let page = storage.list_events_after(after, CATCHUP_PAGE_SIZE).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = last.sequence.clone();
+
+        for event in &page {
+            let Some(commit) = commit_of(event).filter(|c| c.resource_id == comment_id) else {
+                continue;
+            };
+            // A merged issue's comments are re-committed on the issue they were merged into
+            if let Some(origin) = origin.as_mut() {
+                origin.issue_id = event.subject.clone();
+                continue;
+            }
+            let created_at = commit
+                .timestamp
+                .as_deref()
+                .or(event.time.as_deref())
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.with_timezone(&Utc));
+            origin = Some(CommentOrigin {
+                issue_id: event.subject.clone(),
+                author: commit.actor,
+                created_at,
+            });
+        }
+    }
+    Ok(origin)
+}
+
+/// How long after posting authors may still edit or delete a comment
+/// (`COMMENT_EDIT_WINDOW_MINUTES`, default 15).
+pub fn comment_edit_window() -> chrono::Duration {
+    let minutes = std::env::var("COMMENT_EDIT_WINDOW_MINUTES")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|minutes| *minutes > 0)
+        .unwrap_or(DEFAULT_EDIT_WINDOW_MINUTES);
+    chrono::Duration::minutes(minutes)
+}
+
+/// May `user` still edit or delete the comment at `now`? Only its author may, and only
+/// within the edit window.
+fn may_modify(origin: &CommentOrigin, user: &str, now: DateTime<Utc>) -> bool {
+    origin.author == user
+        && origin
+            .created_at
+            .is_some_and(|created| now - created <= comment_edit_window())
+}
+
+fn quoted_comment(comment: &Value) -> Option<&str> {
+    comment.get("quote_comment").and_then(|v| v.as_str())
+}
+
+/// The replies below `root_id`, depth-first, each comment's replies in creation order.
+///
+/// Deleted replies are left out, but their own replies are kept. Index entries of comments
+/// whose `quote_comment` has since changed are skipped: they are listed under their new
+/// parent.
+pub(crate) async fn reply_tree(
+    storage: &Storage,
+    root_id: &str,
+) -> Result<Vec<ThreadComment>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut tree = Vec::new();
+    let mut visited = HashSet::from([root_id.to_string()]);
+    // (comment, its parent, depth); replies are pushed in reverse so they pop in order
+    let mut stack: Vec<(String, String, usize)> = storage
+        .list_comment_replies(root_id)
+        .await?
+        .into_iter()
+        .rev()
+        .map(|child| (child, root_id.to_string(), 1))
+        .collect();
+
+    while let Some((id, parent_id, depth)) = stack.pop() {
+        if !visited.insert(id.clone()) {
+            continue;
+        }
+        match storage.get_resource(&id).await? {
+            Some(comment) if quoted_comment(&comment) != Some(parent_id.as_str()) => continue,
+            Some(comment) => tree.push(ThreadComment {
+                id: id.clone(),
+                parent_id: Some(parent_id),
+                depth,
+                comment,
+            }),
+            None => {}
+        }
+        if depth < MAX_THREAD_DEPTH {
+            for child in storage.list_comment_replies(&id).await?.into_iter().rev() {
+                stack.push((child, id.clone(), depth + 1));
+            }
+        }
+    }
+    Ok(tree)
+}
+
+/// Resolve the comment's origin and check that the user may act on its issue.
+async fn authorize_comment(
+    state: &AppState,
+    user: &str,
+    comment_id: &str,
+) -> Result<CommentOrigin, StatusCode> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("[comments] failed to look up comment {}: {}", comment_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    state
+        .storage
+        .get_resource(comment_id)
+        .await
+        .map_err(internal)?
+        .filter(|comment| comment.get("content").is_some())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let origin = comment_origin(&state.storage, comment_id)
+        .await
+        .map_err(internal)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if !check_access(&state.storage, user, &origin.issue_id).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(origin)
+}
+
+/// PATCH /comments/{id} - Edit one's own comment within the edit window
+#[utoipa::path(
+    patch,
+    path = "/comments/{id}",
+    tag = "resources",
+    params(("id" = String, Path, description = "Comment ID")),
+    request_body = CommentEditRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Patch commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Empty content"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not the author, or the edit window has passed"),
+        (status = 404, description = "Unknown comment"),
+    )
+)]
+pub async fn edit_comment_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(comment_id): Path<String>,
+    Json(request): Json<CommentEditRequest>,
+) -> Result<Response, StatusCode> {
+    if request.content.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let user = auth_user.user_id;
+    let origin = authorize_comment(&state, &user, &comment_id).await?;
+    let now = Utc::now();
+    if !may_modify(&origin, &user, now) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let patch = serde_json::json!({
+        "content": request.content,
+        "edited_at": now.to_rfc3339(),
+    });
+    let commit = CommitBuilder::patch::<Comment>(comment_id, patch)
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &origin.issue_id, commit).await
+}
+
+/// DELETE /comments/{id} - Delete one's own comment within the edit window
+#[utoipa::path(
+    delete,
+    path = "/comments/{id}",
+    tag = "resources",
+    params(("id" = String, Path, description = "Comment ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Delete commit stored, processed and broadcast", body = CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not the author, or the edit window has passed"),
+        (status = 404, description = "Unknown comment"),
+    )
+)]
+pub async fn delete_comment_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(comment_id): Path<String>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    let origin = authorize_comment(&state, &user, &comment_id).await?;
+    if !may_modify(&origin, &user, Utc::now()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let commit = CommitBuilder::delete::<Comment>(comment_id)
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &origin.issue_id, commit).await
+}
+
+/// GET /comments/{id}/history - Every version of a comment, including deleted ones
+#[utoipa::path(
+    get,
+    path = "/comments/{id}/history",
+    tag = "resources",
+    params(("id" = String, Path, description = "Comment ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Versions of the comment, oldest first", body = [CommentVersion]),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the comment's issue"),
+        (status = 404, description = "Unknown comment"),
+    )
+)]
+pub async fn comment_history_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(comment_id): Path<String>,
+) -> Result<Json<Vec<CommentVersion>>, StatusCode> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("[comments] failed to load history of {}: {}", comment_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    // Not `authorize_comment`: a deleted comment still has a history
+    let origin = comment_origin(&state.storage, &comment_id)
+        .await
+        .map_err(internal)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if !check_access(&state.storage, &auth_user.user_id, &origin.issue_id).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut versions = Vec::new();
+    let mut current = None;
+    let mut after = None;
+    loop {
+        let page = state
+            .storage
+            .list_events_after(after, CATCHUP_PAGE_SIZE)
+            .await
+            .map_err(internal)?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = last.sequence.clone();
+
+        for event in page {
+            let Some(commit) = commit_of(&event).filter(|c| c.resource_id == comment_id) else {
+                continue;
+            };
+            current = apply_commit(current, &commit);
+            versions.push(CommentVersion {
+                sequence: event.sequence,
+                actor: commit.actor,
+                timestamp: commit.timestamp.or(event.time),
+                comment: current.clone(),
+            });
+        }
+    }
+
+    Ok(Json(versions))
+}
+
+/// POST /comments/{id}/reactions - React to a comment with an emoji
+#[utoipa::path(
+    post,
+    path = "/comments/{id}/reactions",
+    tag = "resources",
+    params(("id" = String, Path, description = "Comment ID")),
+    request_body = ReactionRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Reaction commit stored, processed and broadcast", body = CloudEvent),
+        (status = 204, description = "The caller already reacted with this emoji"),
+        (status = 400, description = "Empty or overly long emoji"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the comment's issue"),
+        (status = 404, description = "Unknown comment"),
+    )
+)]
+pub async fn add_reaction_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(comment_id): Path<String>,
+    Json(request): Json<ReactionRequest>,
+) -> Result<Response, StatusCode> {
+    if !valid_emoji(&request.emoji) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let user = auth_user.user_id;
+    let origin = authorize_comment(&state, &user, &comment_id).await?;
+
+    let id = reaction_id(&comment_id, &user, &request.emoji);
+    let existing = state.storage.get_resource(&id).await.map_err(|e| {
+        eprintln!("[reactions] failed to get reaction {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if existing.is_some() {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    let reaction = Reaction {
+        emoji: request.emoji,
+        actor: user.clone(),
+        comment_id,
+    };
+    let commit = CommitBuilder::create(id, &reaction).actor(user).build();
+    submit_commit(&state, &headers, &origin.issue_id, commit).await
+}
+
+/// DELETE /comments/{id}/reactions/{emoji} - Remove one's own reaction from a comment
+#[utoipa::path(
+    delete,
+    path = "/comments/{id}/reactions/{emoji}",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Comment ID"),
+        ("emoji" = String, Path, description = "The emoji to remove (URL-encoded)"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Delete commit stored, processed and broadcast", body = CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the comment's issue"),
+        (status = 404, description = "Unknown comment, or the caller did not react with this emoji"),
+    )
+)]
+pub async fn remove_reaction_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path((comment_id, emoji)): Path<(String, String)>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    let origin = authorize_comment(&state, &user, &comment_id).await?;
+
+    let id = reaction_id(&comment_id, &user, &emoji);
+    state
+        .storage
+        .get_resource(&id)
+        .await
+        .map_err(|e| {
+            eprintln!("[reactions] failed to get reaction {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let commit = CommitBuilder::delete::<Reaction>(id).actor(user).build();
+    submit_commit(&state, &headers, &origin.issue_id, commit).await
+}
+
+/// GET /comments/{id}/thread - The whole discussion a comment is part of
+#[utoipa::path(
+    get,
+    path = "/comments/{id}/thread",
+    tag = "resources",
+    params(("id" = String, Path, description = "ID of any comment in the thread")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The thread, starting at its root comment", body = CommentThread),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the comment's issue"),
+        (status = 404, description = "Unknown comment"),
+    )
+)]
+pub async fn comment_thread_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(comment_id): Path<String>,
+) -> Result<Json<CommentThread>, StatusCode> {
+    authorize_comment(&state, &auth_user.user_id, &comment_id).await?;
+
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("[threads] failed to load thread of {}: {}", comment_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    // Walk up to the root: the first ancestor that quotes nothing (or a deleted comment)
+    let mut root_id = comment_id.clone();
+    let mut root = state
+        .storage
+        .get_resource(&root_id)
+        .await
+        .map_err(internal)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let mut seen = HashSet::from([root_id.clone()]);
+    while let Some(parent_id) = quoted_comment(&root).map(str::to_string) {
+        if seen.len() > MAX_THREAD_DEPTH || !seen.insert(parent_id.clone()) {
+            break;
+        }
+        match state
+            .storage
+            .get_resource(&parent_id)
+            .await
+            .map_err(internal)?
+        {
+            Some(parent) => {
+                root_id = parent_id;
+                root = parent;
+            }
+            None => break,
+        }
+    }
+
+    let mut comments = vec![ThreadComment {
+        id: root_id.clone(),
+        parent_id: None,
+        depth: 0,
+        comment: root,
+    }];
+    comments.extend(
+        reply_tree(&state.storage, &root_id)
+            .await
+            .map_err(internal)?,
+    );
+
+    Ok(Json(CommentThread { root_id, comments }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{
+        auth_user as user, create_issue, issue, submit_commit_event, test_state,
+    };
+    use crate::timeline::{issue_timeline_handler, TimelineOrder, TimelineParams};
+    use axum::extract::Query;
+
+    fn comment(content: &str, quote: Option<&str>) -> Comment {
+        Comment {
+            content: content.to_string(),
+            quote_comment: quote.map(str::to_string),
+            mentions: None,
+            edited_at: None,
+            internal: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_comment_thread_and_threaded_timeline() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+
+        create_issue(
+            &state,
+            "issue-1",
+            &issue("Kapvergunning Dorpsstraat 12", &[alice]),
+            alice,
+        )
+        .await;
+        // c1 <- c2 <- c4, c1 <- c5, c3 standalone
+        let commits = [
+            CommitBuilder::create("c1", &comment("Boom is ziek", None)).build(),
+            CommitBuilder::create("c2", &comment("Foto's?", Some("c1"))).build(),
+            CommitBuilder::create("c3", &comment("Inspectie gepland", None)).build(),
+            CommitBuilder::create("c4", &comment("Bijgevoegd", Some("c2"))).build(),
+            CommitBuilder::create("c5", &comment("Dank", Some("c1"))).build(),
+        ];
+        for commit in commits {
+            submit_commit_event(&state, "issue-1", &commit)
+                .await
+                .unwrap();
+        }
+
+        let Json(thread) =
+            comment_thread_handler(State(state.clone()), user(alice), Path("c4".to_string()))
+                .await
+                .unwrap();
+        assert_eq!(thread.root_id, "c1");
+        let ids: Vec<(&str, usize)> = thread
+            .comments
+            .iter()
+            .map(|c| (c.id.as_str(), c.depth))
+            .collect();
+        assert_eq!(ids, vec![("c1", 0), ("c2", 1), ("c4", 2), ("c5", 1)]);
+        assert_eq!(thread.comments[2].parent_id.as_deref(), Some("c2"));
+
+        let Json(timeline) = issue_timeline_handler(
+            State(state.clone()),
+            user(alice),
+            Path("issue-1".to_string()),
+            Query(TimelineParams {
+                order: TimelineOrder::Thread,
+            }),
+        )
+        .await
+        .unwrap();
+        let order: Vec<String> = timeline
+            .events
+            .iter()
+            .filter_map(|e| commit_of(e).map(|c| c.resource_id))
+            .collect();
+        assert_eq!(order, vec!["issue-1", "c1", "c2", "c4", "c5", "c3"]);
+
+        let thread = |who: &str, id: &str| {
+            comment_thread_handler(State(state.clone()), user(who), Path(id.to_string()))
+        };
+        assert_eq!(
+            thread("mallory@example.com", "c2").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            thread(alice, "issue-1").await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_edit_and_delete_own_comment_within_window() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let (alice, bob) = ("alice@gemeente.nl", "bob@gemeente.nl");
+        let issue = issue("Paspoort aanvragen", &[alice, bob]);
+        create_issue(&state, "issue-1", &issue, alice).await;
+        let comment = comment("Afspraak op dinsdag", None);
+        let commits = [
+            CommitBuilder::create("c1", &comment).actor(alice).build(),
+            CommitBuilder::create("c-old", &comment)
+                .actor(alice)
+                .timestamp("2020-01-01T00:00:00Z")
+                .build(),
+        ];
+        for commit in commits {
+            submit_commit_event(&state, "issue-1", &commit)
+                .await
+                .unwrap();
+        }
+
+        let edit = |who: &str, id: &str| {
+            edit_comment_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Path(id.to_string()),
+                Json(CommentEditRequest {
+                    content: "Afspraak op woensdag".to_string(),
+                }),
+            )
+        };
+        // Only the author, and only within the window
+        assert_eq!(edit(bob, "c1").await.unwrap_err(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            edit(alice, "c-old").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            edit(alice, "c1").await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+
+        let edited = state.storage.get_resource("c1").await.unwrap().unwrap();
+        assert_eq!(edited["content"], "Afspraak op woensdag");
+        assert!(edited.get("edited_at").is_some());
+
+        let delete = |who: &str| {
+            delete_comment_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Path("c1".to_string()),
+            )
+        };
+        assert_eq!(delete(bob).await.unwrap_err(), StatusCode::FORBIDDEN);
+        assert_eq!(delete(alice).await.unwrap().status(), StatusCode::ACCEPTED);
+        assert!(state.storage.get_resource("c1").await.unwrap().is_none());
+
+        // Every version stays available
+        let Json(history) =
+            comment_history_handler(State(state.clone()), user(bob), Path("c1".to_string()))
+                .await
+                .unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(
+            history[0].comment.as_ref().unwrap()["content"],
+            "Afspraak op dinsdag"
+        );
+        assert_eq!(
+            history[1].comment.as_ref().unwrap()["content"],
+            "Afspraak op woensdag"
+        );
+        assert!(history[2].comment.is_none());
+        let outsider = comment_history_handler(
+            State(state),
+            user("mallory@example.com"),
+            Path("c1".to_string()),
+        );
+        assert_eq!(outsider.await.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_reactions_only_by_the_involved() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        create_issue(&state, "issue-1", &issue("Paspoort", &[alice]), alice).await;
+        let commit = CommitBuilder::create("c1", &comment("Afspraak gemaakt", None))
+            .actor(alice)
+            .build();
+        submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+
+        let react = |who: &str, id: &str, emoji: &str| {
+            add_reaction_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Path(id.to_string()),
+                Json(ReactionRequest {
+                    emoji: emoji.to_string(),
+                }),
+            )
+        };
+        let unreact = |who: &str| {
+            remove_reaction_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Path(("c1".to_string(), "👍".to_string())),
+            )
+        };
+        let mallory = "mallory@example.com";
+        assert_eq!(
+            react(mallory, "c1", "👍").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            react(alice, "c9", "👍").await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            react(alice, "c1", "").await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            react(alice, "c1", "👍").await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            react(alice, "c1", "👍").await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(unreact(mallory).await.unwrap_err(), StatusCode::FORBIDDEN);
+        assert_eq!(unreact(alice).await.unwrap().status(), StatusCode::ACCEPTED);
+        assert_eq!(unreact(alice).await.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_reaction_id_and_emoji_validation() {
+        assert_eq!(
+            reaction_id("comment-1", "alice@gemeente.nl", "👍"),
+            "reaction-comment-1-alice@gemeente.nl-1f44d"
+        );
+        assert_ne!(
+            reaction_id("comment-1", "alice@gemeente.nl", "👍🏽"),
+            reaction_id("comment-1", "alice@gemeente.nl", "👍")
+        );
+        assert!(valid_emoji("👍🏽"));
+        assert!(!valid_emoji(""));
+        assert!(!valid_emoji("👍 👍"));
+        assert!(!valid_emoji(&"x".repeat(MAX_EMOJI_CHARS + 1)));
+    }
+}
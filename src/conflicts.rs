@@ -0,0 +1,593 @@
+//! Field-level merge conflicts for commits made on a stale version of a resource.
+//!
+//! A commit may name the version it was made on (`JSONCommit::base_sequence`). When other
+//! commits to the same resource landed after that version, the `conflicts` step of the event
+//! pipeline compares the fields the commit changes with the fields those commits changed.
+//! Changes to different fields are merged as usual; when both changed the same field (to
+//! different values) the commit is rejected with 409 and a [`MergeConflict`], which lists per
+//! field the value at the base, the current value and the proposed one. The client can then
+//! merge field by field and retry with `current_sequence` as its new base.
+//!
+//! Commits made offline (`JSONCommit::offline`) often carry the whole resource as the client
+//! had it. Applied as is, that would undo every change made in the meantime, so the step
+//! rewrites them into a merge patch of only the fields they change relative to their base:
+//! a three-way merge. True conflicts are resolved with `POST /resources/{id}/resolve`, which
+//! records the chosen values as a commit on the current version.
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::encoding;
+use crate::handlers::{
+    apply_commit, commit_of, internal_error, latest_commit_schema, merge_patch_diff, AppState,
+    ErrorResponse,
+};
+use crate::pipeline::{EventContext, EventProcessor, ProcessError};
+use crate::schemas::{CloudEventBuilder, JSONCommit, PatchType};
+use crate::storage::Storage;
+
+/// Why a commit on a stale version was rejected
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct MergeConflict {
+    pub resource_id: String,
+    /// The version the commit was made on
+    pub base_sequence: u64,
+    /// The latest commit to the resource; the base for a retry
+    pub current_sequence: u64,
+    pub conflicts: Vec<FieldConflict>,
+}
+
+/// A field changed both by the rejected commit and by a commit after its base
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct FieldConflict {
+    /// JSON Pointer to the field, e.g. "/status" ("" for the whole resource)
+    pub path: String,
+    /// Value at the base version (null when absent)
+    #[schema(value_type = Object)]
+    pub base: Value,
+    /// Value now
+    #[schema(value_type = Object)]
+    pub current: Value,
+    /// Value the rejected commit would have set
+    #[schema(value_type = Object)]
+    pub proposed: Value,
+    /// Actors of the commits that changed the field since the base
+    pub changed_by: Vec<String>,
+}
+
+/// The leaf paths a JSON Merge Patch sets or removes, as JSON Pointers.
+fn patch_paths(patch: &Value, prefix: &str, paths: &mut Vec<String>) {
+    match patch.as_object() {
+        Some(fields) => {
+            for (key, value) in fields {
+                let path = format!("{}/{}", prefix, key.replace('~', "~0").replace('/', "~1"));
+                match value.as_object() {
+                    Some(nested) if !nested.is_empty() => patch_paths(value, &path, paths),
+                    _ => paths.push(path),
+                }
+            }
+        }
+        None => paths.push(prefix.to_string()),
+    }
+}
+
+/// The paths that differ between two states of a resource.
+fn changed_paths(before: Option<&Value>, after: Option<&Value>) -> Vec<String> {
+    match (before, after) {
+        (Some(before), Some(after)) => {
+            let mut paths = Vec::new();
+            patch_paths(&merge_patch_diff(before, after), "", &mut paths);
+            paths
+        }
+        (None, None) => Vec::new(),
+        _ => vec![String::new()],
+    }
+}
+
+/// Does one path contain the other?
+fn overlaps(a: &str, b: &str) -> bool {
+    let within = |inner: &str, outer: &str| {
+        inner == outer || inner.starts_with(&format!("{}/", outer)) || outer.is_empty()
+    };
+    within(a, b) || within(b, a)
+}
+
+fn value_at(state: Option<&Value>, path: &str) -> Value {
+    state
+        .and_then(|s| s.pointer(path))
+        .cloned()
+        .unwrap_or(Value::Null)
+}
+
+/// A resource's history since the version a commit was made on
+struct History {
+    /// The resource at the base version
+    base: Option<Value>,
+    /// The resource now
+    current: Option<Value>,
+    /// The latest commit to the resource
+    current_sequence: u64,
+    /// Paths changed since the base, with who changed them
+    changes: Vec<(String, String)>,
+}
+
+impl History {
+    async fn load(
+        storage: &Storage,
+        resource_id: &str,
+        base_sequence: u64,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut history = History {
+            base: None,
+            current: None,
+            current_sequence: base_sequence,
+            changes: Vec::new(),
+        };
+        // Whatever subject they were sent with
+        for event in storage.list_resource_events(resource_id).await? {
+            let Some(other) = commit_of(&event) else {
+                continue;
+            };
+            let next = apply_commit(history.current.clone(), &other);
+            match event.sequencenumber {
+                Some(seq) if seq > base_sequence => {
+                    for path in changed_paths(history.current.as_ref(), next.as_ref()) {
+                        history.changes.push((path, other.actor.clone()));
+                    }
+                    history.current_sequence = seq;
+                }
+                _ => history.base = next.clone(),
+            }
+            history.current = next;
+        }
+        Ok(history)
+    }
+
+    /// The conflicts of `commit` with the changes since the base
+    fn conflicts(&self, commit: &JSONCommit, base_sequence: u64) -> Option<MergeConflict> {
+        if self.changes.is_empty() {
+            return None;
+        }
+        let (base, current) = (self.base.as_ref(), self.current.as_ref());
+        let proposed = apply_commit(self.base.clone(), commit);
+        let mut paths = Vec::new();
+        match (&commit.patch, commit.deleted) {
+            (Some(patch), None | Some(false)) if commit.resource_data.is_none() => {
+                match commit.is_json_patch() {
+                    true => paths = crate::json_patch::changed_paths(patch),
+                    false => patch_paths(patch, "", &mut paths),
+                }
+            }
+            _ => paths = changed_paths(base, proposed.as_ref()),
+        }
+
+        let mut conflicts = Vec::new();
+        for path in paths {
+            let mut changed_by: Vec<String> = Vec::new();
+            for (changed, actor) in &self.changes {
+                if overlaps(&path, changed) && !changed_by.contains(actor) {
+                    changed_by.push(actor.clone());
+                }
+            }
+            let (current, proposed) =
+                (value_at(current, &path), value_at(proposed.as_ref(), &path));
+            // Both made the same change: nothing to merge
+            if changed_by.is_empty() || current == proposed {
+                continue;
+            }
+            conflicts.push(FieldConflict {
+                base: value_at(base, &path),
+                path,
+                current,
+                proposed,
+                changed_by,
+            });
+        }
+        (!conflicts.is_empty()).then(|| MergeConflict {
+            resource_id: commit.resource_id.clone(),
+            base_sequence,
+            current_sequence: self.current_sequence,
+            conflicts,
+        })
+    }
+
+    /// `commit` rewritten to carry only what it changes relative to the base, when it
+    /// replaces the whole resource. Applied to the current version, that merges it with the
+    /// changes made since, where applying it as is would undo them.
+    fn rebase(&self, commit: &JSONCommit) -> Option<JSONCommit> {
+        let (Some(base), Some(_)) = (&self.base, &self.current) else {
+            return None;
+        };
+        if commit.resource_data.is_none() || commit.deleted.unwrap_or(false) {
+            return None;
+        }
+        let proposed = apply_commit(Some(base.clone()), commit)?;
+        Some(JSONCommit {
+            resource_data: None,
+            patch: Some(merge_patch_diff(base, &proposed)),
+            patch_type: None,
+            ..commit.clone()
+        })
+    }
+}
+
+/// The conflicts of `commit` (made on `base_sequence`) with the commits to its resource
+/// stored after that version. `None` when it can be applied as is.
+pub async fn detect(
+    storage: &Storage,
+    commit: &JSONCommit,
+    base_sequence: u64,
+) -> Result<Option<MergeConflict>, Box<dyn std::error::Error + Send + Sync>> {
+    let history = History::load(storage, &commit.resource_id, base_sequence).await?;
+    Ok(history.conflicts(commit, base_sequence))
+}
+
+/// Pipeline step that rejects commits conflicting with changes made after their base
+pub struct ConflictProcessor;
+
+#[async_trait]
+impl EventProcessor for ConflictProcessor {
+    fn name(&self) -> &'static str {
+        "conflicts"
+    }
+
+    async fn prepare(&self, state: &AppState, ctx: &mut EventContext) -> Result<(), ProcessError> {
+        let Some(commit) = ctx.commit() else {
+            return Ok(());
+        };
+        let Some(base_sequence) = commit.base_sequence else {
+            return Ok(());
+        };
+        let history = History::load(&state.storage, &commit.resource_id, base_sequence).await?;
+        if let Some(conflict) = history.conflicts(&commit, base_sequence) {
+            return Err(ProcessError::Conflict(Box::new(conflict)));
+        }
+        // An offline edit of the whole resource only applies the fields it changed
+        if commit.offline.unwrap_or(false) && !history.changes.is_empty() {
+            if let Some(rebased) = history.rebase(&commit) {
+                ctx.event.data = Some(
+                    serde_json::to_value(&rebased)
+                        .map_err(|e| ProcessError::Internal(Box::new(e)))?,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The value chosen for one conflicting field
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ResolvedField {
+    /// JSON Pointer to the field, as in the conflict
+    pub path: String,
+    /// The value to keep; null removes the field
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub value: Value,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ConflictResolution {
+    /// The subject the resource's events are about; defaults to the resource itself (issues)
+    #[serde(default)]
+    pub subject: Option<String>,
+    /// The conflict's `current_sequence`: the version the fields were resolved on
+    pub base_sequence: u64,
+    pub fields: Vec<ResolvedField>,
+}
+
+/// The JSON Patch that sets the resolved fields on `current`.
+fn resolution_patch(current: &Value, fields: &[ResolvedField]) -> Value {
+    let operations: Vec<Value> = fields
+        .iter()
+        .filter_map(|field| match &field.value {
+            Value::Null => current
+                .pointer(&field.path)
+                .map(|_| json!({"op": "remove", "path": field.path})),
+            value => Some(json!({"op": "add", "path": field.path, "value": value})),
+        })
+        .collect();
+    Value::Array(operations)
+}
+
+/// POST /resources/{id}/resolve - Record how a merge conflict was resolved
+#[utoipa::path(
+    post,
+    path = "/resources/{id}/resolve",
+    tag = "resources",
+    params(("id" = String, Path, description = "Resource ID")),
+    request_body = ConflictResolution,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Resolution commit stored, processed and broadcast", body = crate::schemas::CloudEvent),
+        (status = 400, description = "No fields, or a field that is not a JSON Pointer into the resource"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller may not change the resource"),
+        (status = 404, description = "Unknown resource"),
+        (status = 409, description = "The fields changed again since `base_sequence`", body = MergeConflict),
+    )
+)]
+pub async fn resolve_conflict_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(resolution): Json<ConflictResolution>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    if resolution.fields.is_empty() || resolution.fields.iter().any(|f| !f.path.starts_with('/')) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let subject = resolution.subject.as_deref().unwrap_or(&id);
+    let schema = latest_commit_schema(&state.storage, subject, &id)
+        .await
+        .map_err(internal_error)?;
+    let current = state
+        .storage
+        .get_resource(&id)
+        .await
+        .map_err(internal_error)?;
+    let (Some(schema), Some(current)) = (schema, current) else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let commit = JSONCommit {
+        schema,
+        resource_id: id.clone(),
+        actor: user.clone(),
+        timestamp: Some(chrono::Utc::now().to_rfc3339()),
+        resource_data: None,
+        patch: Some(resolution_patch(&current, &resolution.fields)),
+        patch_type: Some(PatchType::JsonPatch),
+        deleted: None,
+        base_sequence: Some(resolution.base_sequence),
+        offline: None,
+    };
+    let event = CloudEventBuilder::commit(subject, &commit).build();
+    let ctx = EventContext::new(event).inbound(Some(user));
+    match state.pipeline.submit(&state, ctx).await {
+        Ok(event) => Ok(encoding::negotiated(
+            &headers,
+            StatusCode::ACCEPTED,
+            &event,
+            encoding::CLOUDEVENTS_CBOR,
+        )),
+        Err(ProcessError::Conflict(conflict)) => Ok(encoding::negotiated(
+            &headers,
+            StatusCode::CONFLICT,
+            &conflict,
+            encoding::CBOR,
+        )),
+        Err(ProcessError::Invalid(reason)) => Ok(encoding::negotiated(
+            &headers,
+            StatusCode::BAD_REQUEST,
+            &ErrorResponse { error: reason },
+            encoding::CBOR,
+        )),
+        Err(e) => {
+            eprintln!("[conflicts] resolution of {} not accepted: {}", id, e);
+            Err(e.status())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, issue, submit_commit_event, test_state};
+    use crate::pipeline::EventContext;
+    use crate::schemas::{CloudEventBuilder, CommitBuilder, Issue, IssueStatus};
+    use serde_json::json;
+
+    #[test]
+    fn test_patch_paths_and_overlap() {
+        let mut paths = Vec::new();
+        patch_paths(
+            &json!({"status": "closed", "address": {"city": "Utrecht"}, "a/b": null}),
+            "",
+            &mut paths,
+        );
+        paths.sort();
+        assert_eq!(paths, vec!["/address/city", "/a~1b", "/status"]);
+        assert!(overlaps("/address", "/address/city"));
+        assert!(overlaps("", "/status"));
+        assert!(!overlaps("/status", "/statusx"));
+    }
+
+    #[tokio::test]
+    async fn test_stale_patch_conflicts_per_field() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let submit = |commit: JSONCommit| {
+            let state = state.clone();
+            async move {
+                let event = CloudEventBuilder::commit("issue-1", &commit).build();
+                state
+                    .pipeline
+                    .submit(&state, EventContext::new(event))
+                    .await
+            }
+        };
+        let issue = issue("Paspoort", &[]);
+        let base = submit(CommitBuilder::create("issue-1", &issue).build())
+            .await
+            .unwrap()
+            .sequencenumber
+            .unwrap();
+        let patch = |patch: Value| CommitBuilder::patch::<Issue>("issue-1", patch);
+        let closed = submit(patch(json!({"status": "closed"})).actor("bob").build())
+            .await
+            .unwrap();
+
+        // Other fields merge
+        let retitle = patch(json!({"title": "Paspoort aanvragen"})).base_sequence(base);
+        submit(retitle.build()).await.unwrap();
+        // The same change doesn't conflict either
+        let close = patch(json!({"status": "closed"})).base_sequence(base);
+        submit(close.build()).await.unwrap();
+
+        let reopen = patch(json!({"status": "in_progress"})).base_sequence(base);
+        let Err(ProcessError::Conflict(conflict)) = submit(reopen.build()).await else {
+            panic!("expected a conflict");
+        };
+        assert_eq!(conflict.base_sequence, base);
+        assert!(conflict.current_sequence > closed.sequencenumber.unwrap());
+        assert_eq!(
+            conflict.conflicts,
+            vec![FieldConflict {
+                path: "/status".to_string(),
+                base: json!("open"),
+                current: json!("closed"),
+                proposed: json!("in_progress"),
+                changed_by: vec!["bob".to_string()],
+            }]
+        );
+
+        // Retrying on the current version succeeds
+        let retry =
+            patch(json!({"status": "in_progress"})).base_sequence(conflict.current_sequence);
+        let retried = submit_commit_event(&state, "issue-1", &retry.build())
+            .await
+            .unwrap();
+        let issue = state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(issue["status"], "in_progress");
+        assert_eq!(issue["title"], "Paspoort aanvragen");
+
+        // Commits to the resource sent with another subject are part of its history too
+        let moved = patch(json!({"title": "Paspoort verlengen"}))
+            .actor("carol")
+            .build();
+        submit_commit_event(&state, "issue-2", &moved)
+            .await
+            .unwrap();
+        let retitle = patch(json!({"title": "Paspoort kwijt"}))
+            .base_sequence(retried.sequencenumber.unwrap());
+        let Err(ProcessError::Conflict(conflict)) = submit(retitle.build()).await else {
+            panic!("expected a conflict");
+        };
+        assert_eq!(conflict.conflicts[0].changed_by, vec!["carol".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_offline_edit_merges_and_resolves() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let submit = |commit: JSONCommit| {
+            let state = state.clone();
+            async move {
+                let event = CloudEventBuilder::commit("issue-1", &commit).build();
+                state
+                    .pipeline
+                    .submit(&state, EventContext::new(event))
+                    .await
+            }
+        };
+        let issue = |title: &str, status: IssueStatus| Issue {
+            status,
+            ..issue(title, &[alice])
+        };
+        let base =
+            submit(CommitBuilder::create("issue-1", &issue("Paspoort", IssueStatus::Open)).build())
+                .await
+                .unwrap()
+                .sequencenumber
+                .unwrap();
+        let close = CommitBuilder::patch::<Issue>("issue-1", json!({"status": "closed"}));
+        submit(close.actor("bob").build()).await.unwrap();
+
+        // Offline, alice renamed the issue as she had it: bob's close is kept
+        let offline = |issue: &Issue| {
+            CommitBuilder::create("issue-1", issue)
+                .actor(alice)
+                .base_sequence(base)
+                .offline()
+                .build()
+        };
+        submit(offline(&issue("Paspoort aanvragen", IssueStatus::Open)))
+            .await
+            .unwrap();
+        let current = state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (&current["title"], &current["status"]),
+            (&json!("Paspoort aanvragen"), &json!("closed"))
+        );
+
+        // Both changed the status: a true conflict
+        let Err(ProcessError::Conflict(conflict)) =
+            submit(offline(&issue("Paspoort", IssueStatus::InProgress))).await
+        else {
+            panic!("expected a conflict");
+        };
+        assert_eq!(conflict.conflicts.len(), 1);
+        assert_eq!(conflict.conflicts[0].path, "/status");
+
+        let resolve_as = |who: &str, id: &str, base_sequence: u64, status: Option<&str>| {
+            resolve_conflict_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Path(id.to_string()),
+                Json(ConflictResolution {
+                    subject: None,
+                    base_sequence,
+                    fields: status
+                        .map(|status| ResolvedField {
+                            path: "/status".to_string(),
+                            value: json!(status),
+                        })
+                        .into_iter()
+                        .collect(),
+                }),
+            )
+        };
+        let resolve =
+            |base_sequence, status| resolve_as(alice, "issue-1", base_sequence, Some(status));
+        let sequence = conflict.current_sequence;
+        let outsider = resolve_as("mallory@example.com", "issue-1", sequence, Some("closed"));
+        assert_eq!(outsider.await.unwrap_err(), StatusCode::FORBIDDEN);
+        let unknown = resolve_as(alice, "issue-9", sequence, Some("closed"));
+        assert_eq!(unknown.await.unwrap_err(), StatusCode::NOT_FOUND);
+        let nothing = resolve_as(alice, "issue-1", sequence, None);
+        assert_eq!(nothing.await.unwrap_err(), StatusCode::BAD_REQUEST);
+        let response = resolve(conflict.current_sequence, "in_progress")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let current = state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(current["status"], "in_progress");
+        assert_eq!(current["title"], "Paspoort aanvragen");
+
+        // The resolution is a commit like any other
+        let last = state.storage.list_subject_events("issue-1").await.unwrap();
+        let commit = commit_of(last.last().unwrap()).unwrap();
+        assert_eq!(commit.actor, alice);
+        assert_eq!(commit.base_sequence, Some(conflict.current_sequence));
+
+        // Resolving on an outdated version conflicts again
+        let response = resolve(conflict.current_sequence, "open").await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+}
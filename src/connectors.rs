@@ -0,0 +1,596 @@
+//! Outgoing Slack / Microsoft Teams connectors.
+//!
+//! A connector (`connector-{tenant}-{uuid}`) posts a card to a channel's incoming webhook
+//! when one of its triggers happens in a zaak of its tenant (and of its team, if it has one):
+//! - `urgent_issue`: an issue is created with the [`URGENT_TAG`] label;
+//! - `sla_breach`: an escalation step is recorded (see `escalation`);
+//! - `team_mention`: a comment mentions the connector's team ("@Handhaving").
+//!
+//! The outbox (see `outbox`) calls [`deliver`] for every stored event, and retries until the
+//! cards are posted.
+//!
+//! Admins (see `auth::is_admin`) manage the connectors of their tenant. A webhook URL lets
+//! anyone post to the channel, so it is shown to admins only: connectors are left out of
+//! `GET /resources`, and listed without their URL to the tenant's other users.
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::auth::{tenant_of, AuthUser};
+use crate::availability::URGENT_TAG;
+use crate::handlers::{commit_of, internal_error, submit_commit, AppState, ResourceResponse};
+use crate::schemas::{
+    CloudEvent, Comment, CommitBuilder, Connector, ConnectorTriggerType, ConnectorType, Escalation,
+    EscalationType, Issue,
+};
+use crate::storage::Storage;
+use crate::teams::get_team;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ConnectorRequest {
+    pub name: String,
+    #[schema(value_type = String)]
+    pub kind: ConnectorType,
+    /// Incoming webhook URL of the channel (https)
+    pub webhook_url: String,
+    #[schema(value_type = Vec<String>)]
+    pub triggers: Vec<ConnectorTriggerType>,
+    /// Only zaken of (and mentions of) this team
+    pub team: Option<String>,
+}
+
+/// The content of a card, independent of the platform
+#[derive(Debug, Clone, PartialEq)]
+pub struct Card {
+    pub title: String,
+    pub text: String,
+    pub link: String,
+}
+
+/// Slack message with the card as Block Kit blocks.
+pub fn slack_payload(card: &Card) -> Value {
+    json!({
+        "text": card.title,
+        "blocks": [
+            {
+                "type": "section",
+                "text": { "type": "mrkdwn", "text": format!("*{}*\n{}", card.title, card.text) }
+            },
+            {
+                "type": "actions",
+                "elements": [{
+                    "type": "button",
+                    "text": { "type": "plain_text", "text": "Bekijk zaak" },
+                    "url": card.link
+                }]
+            }
+        ]
+    })
+}
+
+/// Teams message with the card as an Adaptive Card.
+pub fn teams_payload(card: &Card) -> Value {
+    json!({
+        "type": "message",
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "body": [
+                    { "type": "TextBlock", "text": card.title, "weight": "Bolder", "size": "Medium", "wrap": true },
+                    { "type": "TextBlock", "text": card.text, "wrap": true }
+                ],
+                "actions": [{ "type": "Action.OpenUrl", "title": "Bekijk zaak", "url": card.link }]
+            }
+        }]
+    })
+}
+
+/// Does `content` mention the team called `name` ("@Handhaving", any case)?
+pub fn mentions_team(content: &str, name: &str) -> bool {
+    content
+        .to_lowercase()
+        .contains(&format!("@{}", name.to_lowercase()))
+}
+
+/// Is `resource` a connector (see [`Connector`])?
+pub(crate) fn is_connector(resource: &Value) -> bool {
+    resource.get("tenant").is_some() && resource.get("webhook_url").is_some()
+}
+
+/// All connectors, by ID.
+async fn all_connectors(
+    storage: &Storage,
+) -> Result<Vec<(String, Connector)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut connectors = Vec::new();
+    for (id, value) in storage.list_resources_with_prefix("connector-").await? {
+        match serde_json::from_value::<Connector>(value) {
+            Ok(connector) => connectors.push((id, connector)),
+            Err(e) => eprintln!("[connectors] skipping malformed connector {}: {}", id, e),
+        }
+    }
+    Ok(connectors)
+}
+
+/// The tenants an issue belongs to: those of its assignee, its team and the people involved.
+async fn issue_tenants(
+    storage: &Storage,
+    issue: &Issue,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut tenants: Vec<String> = Vec::new();
+    let mut add = |tenant: &str| {
+        if !tenants.iter().any(|t| t == tenant) {
+            tenants.push(tenant.to_string());
+        }
+    };
+    if let Some(assignee) = &issue.assignee {
+        add(tenant_of(assignee));
+    }
+    if let Some(team) = &issue.team {
+        if let Some(team) = get_team(storage, team).await? {
+            add(&team.tenant);
+        }
+    }
+    for person in issue.involved.iter().flatten() {
+        add(tenant_of(person));
+    }
+    Ok(tenants)
+}
+
+/// The cards to post for an event, with the connector to post each one to.
+pub async fn cards_for(
+    storage: &Storage,
+    event: &CloudEvent,
+) -> Result<Vec<(Connector, Card)>, Box<dyn std::error::Error + Send + Sync>> {
+    // Only newly created resources trigger cards
+    let Some(commit) = commit_of(event) else {
+        return Ok(Vec::new());
+    };
+    let Some(data) = commit.resource_data else {
+        return Ok(Vec::new());
+    };
+    let Some(issue) = storage
+        .get_resource(&event.subject)
+        .await?
+        .and_then(|value| serde_json::from_value::<Issue>(value).ok())
+    else {
+        return Ok(Vec::new());
+    };
+
+    let base_url = std::env::var("BASE_URL").unwrap_or_else(|_| "https://zaakchat.nl".to_string());
+    let link = format!("{}/zaak/{}", base_url, event.subject);
+    let connectors = all_connectors(storage).await?;
+    let tenants = issue_tenants(storage, &issue).await?;
+    let mut cards = Vec::new();
+
+    if let Ok(comment) = serde_json::from_value::<Comment>(data.clone()) {
+        for (_, connector) in connectors {
+            if !connector
+                .triggers
+                .contains(&ConnectorTriggerType::TeamMention)
+                || !tenants.contains(&connector.tenant)
+            {
+                continue;
+            }
+            let Some(team_id) = &connector.team else {
+                continue;
+            };
+            let Some(team) = get_team(storage, team_id).await? else {
+                continue;
+            };
+            if team.tenant == connector.tenant && mentions_team(&comment.content, &team.name) {
+                let card = Card {
+                    title: format!(
+                        "{} noemde {} in \"{}\"",
+                        commit.actor, team.name, issue.title
+                    ),
+                    text: comment.content.clone(),
+                    link: link.clone(),
+                };
+                cards.push((connector, card));
+            }
+        }
+        return Ok(cards);
+    }
+
+    let (trigger, card) = if commit.resource_id == event.subject {
+        if !crate::labels::has_tag(&data, URGENT_TAG) {
+            return Ok(cards);
+        }
+        let card = Card {
+            title: format!("Nieuwe spoedmelding: {}", issue.title),
+            text: issue.description.clone().unwrap_or_default(),
+            link,
+        };
+        (ConnectorTriggerType::UrgentIssue, card)
+    } else if let Ok(escalation) = serde_json::from_value::<Escalation>(data) {
+        let action = match (escalation.action, &escalation.target) {
+            (EscalationType::Notify, Some(target)) => format!("{} is ingelicht.", target),
+            (EscalationType::Reassign, Some(target)) => {
+                format!("De zaak is overgedragen aan {}.", target)
+            }
+            _ => String::new(),
+        };
+        let card = Card {
+            title: format!("Escalatie: {}", issue.title),
+            text: format!(
+                "Al {} uur geen activiteit. {}",
+                escalation.idle_hours, action
+            )
+            .trim_end()
+            .to_string(),
+            link,
+        };
+        (ConnectorTriggerType::SlaBreach, card)
+    } else {
+        return Ok(cards);
+    };
+
+    for (_, connector) in connectors {
+        let team_matches = connector.team.is_none() || connector.team == issue.team;
+        if connector.triggers.contains(&trigger)
+            && tenants.contains(&connector.tenant)
+            && team_matches
+        {
+            cards.push((connector, card.clone()));
+        }
+    }
+    Ok(cards)
+}
+
+/// Post the cards for `event` to their channels. Fails when a channel could not be reached.
+pub async fn deliver(
+    storage: &Storage,
+    http: &reqwest::Client,
+    event: &CloudEvent,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for (connector, card) in cards_for(storage, event).await? {
+        let payload = match connector.kind {
+            ConnectorType::Slack => slack_payload(&card),
+            ConnectorType::Teams => teams_payload(&card),
+        };
+        http.post(&connector.webhook_url)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                format!(
+                    "failed to post to {} ({}): {}",
+                    connector.name, connector.tenant, e
+                )
+            })?;
+    }
+    Ok(())
+}
+
+/// GET /connectors - The connectors of the caller's tenant
+#[utoipa::path(
+    get,
+    path = "/connectors",
+    tag = "resources",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The tenant's connectors; their webhook URLs for admins only", body = [ResourceResponse]),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn list_connectors_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<ResourceResponse>>, StatusCode> {
+    let tenant = tenant_of(&auth_user.user_id);
+    let admin = auth_user.is_admin();
+    let response = all_connectors(&state.storage)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .filter(|(_, connector)| connector.tenant == tenant)
+        .map(|(id, connector)| {
+            let mut data = serde_json::to_value(connector).unwrap_or_default();
+            if let Some(obj) = data.as_object_mut().filter(|_| !admin) {
+                obj.remove("webhook_url");
+            }
+            ResourceResponse {
+                id,
+                resource_type: "connector".to_string(),
+                data,
+                unread: None,
+            }
+        })
+        .collect();
+    Ok(Json(response))
+}
+
+/// POST /connectors - Add a Slack or Teams connector for the caller's tenant
+#[utoipa::path(
+    post,
+    path = "/connectors",
+    tag = "resources",
+    request_body = ConnectorRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Connector commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Empty name, no triggers, a webhook URL that is not https, a team mention trigger without team, or a team of another tenant"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+    )
+)]
+pub async fn create_connector_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Json(request): Json<ConnectorRequest>,
+) -> Result<Response, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let user = auth_user.user_id;
+    let tenant = tenant_of(&user).to_string();
+    let name = request.name.trim().to_string();
+    let mention_without_team = request
+        .triggers
+        .contains(&ConnectorTriggerType::TeamMention)
+        && request.team.is_none();
+    if name.is_empty()
+        || request.triggers.is_empty()
+        || !request.webhook_url.starts_with("https://")
+        || mention_without_team
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if let Some(team) = &request.team {
+        let own_team = get_team(&state.storage, team)
+            .await
+            .map_err(internal_error)?
+            .is_some_and(|t| t.tenant == tenant);
+        if !own_team {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    let id = format!("connector-{}-{}", tenant, uuid::Uuid::now_v7());
+    let connector = Connector {
+        tenant,
+        name,
+        kind: request.kind,
+        webhook_url: request.webhook_url,
+        triggers: request.triggers,
+        team: request.team,
+    };
+    let commit = CommitBuilder::create(id.clone(), &connector)
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &id, commit).await
+}
+
+/// DELETE /connectors/{id} - Remove a connector
+#[utoipa::path(
+    delete,
+    path = "/connectors/{id}",
+    tag = "resources",
+    params(("id" = String, Path, description = "Connector ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Delete commit stored, processed and broadcast", body = CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin, or the connector belongs to another tenant"),
+        (status = 404, description = "Unknown connector"),
+    )
+)]
+pub async fn delete_connector_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let user = auth_user.user_id;
+    let connector: Connector = state
+        .storage
+        .get_resource(&id)
+        .await
+        .map_err(internal_error)?
+        .and_then(|value| serde_json::from_value(value).ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if connector.tenant != tenant_of(&user) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let commit = CommitBuilder::delete::<Connector>(id.clone())
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &id, commit).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user, submit_commit_event, test_state, ADMIN};
+    use crate::handlers::{list_resources, ListParams};
+    use crate::schemas::{JSONCommit, Team};
+
+    #[test]
+    fn test_payloads() {
+        let card = Card {
+            title: "Nieuwe spoedmelding: Gaslucht".to_string(),
+            text: "Sterke gaslucht in portiek".to_string(),
+            link: "https://zaakchat.nl/zaak/issue-1".to_string(),
+        };
+        let slack = slack_payload(&card);
+        assert_eq!(slack["blocks"][1]["elements"][0]["url"], card.link);
+        let teams = teams_payload(&card);
+        let content = &teams["attachments"][0]["content"];
+        assert_eq!(content["type"], "AdaptiveCard");
+        assert_eq!(content["body"][0]["text"], card.title);
+
+        assert!(mentions_team(
+            "Kan @handhaving hiernaar kijken?",
+            "Handhaving"
+        ));
+        assert!(!mentions_team("Handhaving weet ervan", "Handhaving"));
+    }
+
+    #[tokio::test]
+    async fn test_triggers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+
+        let team = Team {
+            name: "Handhaving".to_string(),
+            tenant: "gemeente.nl".to_string(),
+            department: None,
+            members: vec![alice.to_string()],
+        };
+        let team_id = crate::teams::team_id("gemeente.nl", "Handhaving");
+        let commit = CommitBuilder::create(team_id.clone(), &team)
+            .actor(alice)
+            .build();
+        submit_commit_event(&state, &team_id, &commit)
+            .await
+            .unwrap();
+        let create_connector = |user: &str, team: &str| {
+            create_connector_handler(
+                State(state.clone()),
+                auth_user(user),
+                HeaderMap::new(),
+                Json(ConnectorRequest {
+                    name: "Teams Handhaving".to_string(),
+                    kind: ConnectorType::Teams,
+                    webhook_url: "https://127.0.0.1:9/webhook".to_string(),
+                    triggers: vec![
+                        ConnectorTriggerType::UrgentIssue,
+                        ConnectorTriggerType::TeamMention,
+                    ],
+                    team: Some(team.to_string()),
+                }),
+            )
+        };
+        assert_eq!(
+            create_connector(alice, &team_id).await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        // Not for a team of another tenant
+        let elsewhere = crate::teams::team_id("example.com", "Handhaving");
+        let eve = "eve@example.com";
+        let other_team = Team {
+            tenant: "example.com".to_string(),
+            members: vec![eve.to_string()],
+            ..team.clone()
+        };
+        let commit = CommitBuilder::create(elsewhere.clone(), &other_team)
+            .actor(eve)
+            .build();
+        submit_commit_event(&state, &elsewhere, &commit)
+            .await
+            .unwrap();
+        assert_eq!(
+            create_connector(ADMIN, &elsewhere).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        let created = create_connector(ADMIN, &team_id).await.unwrap();
+        assert_eq!(created.status(), StatusCode::ACCEPTED);
+
+        // The webhook URL is for admins only
+        let listed = list_connectors_handler(State(state.clone()), auth_user(alice))
+            .await
+            .unwrap();
+        assert_eq!(listed.0.len(), 1);
+        assert!(listed.0[0].data.get("webhook_url").is_none());
+        let listed = list_connectors_handler(State(state.clone()), auth_user(ADMIN))
+            .await
+            .unwrap();
+        assert_eq!(
+            listed.0[0].data["webhook_url"],
+            "https://127.0.0.1:9/webhook"
+        );
+        let connector_id = listed.0[0].id.clone();
+        let resources = list_resources(
+            State(state.clone()),
+            None,
+            HeaderMap::new(),
+            axum::extract::Query(ListParams {
+                offset: 0,
+                limit: 100,
+                tag: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(resources.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("webhook"));
+        let fetched = crate::handlers::get_resource(
+            State(state.clone()),
+            None,
+            Path(connector_id.clone()),
+            axum::extract::Query(Default::default()),
+        )
+        .await;
+        assert_eq!(fetched.unwrap_err(), StatusCode::NOT_FOUND);
+        let deleted = delete_connector_handler(
+            State(state.clone()),
+            auth_user(alice),
+            HeaderMap::new(),
+            Path(connector_id),
+        )
+        .await;
+        assert_eq!(deleted.unwrap_err(), StatusCode::FORBIDDEN);
+
+        let issue = |tags: Vec<&str>| Issue {
+            assignee: Some(alice.to_string()),
+            team: Some(team_id.clone()),
+            tags: Some(tags.into_iter().map(str::to_string).collect()),
+            ..crate::handlers::tests::issue("Gaslucht", &[alice])
+        };
+        let create = |subject: &str, commit: JSONCommit| {
+            let subject = subject.to_string();
+            let state = state.clone();
+            async move {
+                let event = submit_commit_event(&state, &subject, &commit)
+                    .await
+                    .unwrap();
+                cards_for(&state.storage, &event).await.unwrap()
+            }
+        };
+        let create_issue = |id: &str, tags: Vec<&str>| {
+            let commit = CommitBuilder::create(id, &issue(tags)).actor(alice).build();
+            create(id, commit)
+        };
+
+        let cards = create_issue("issue-1", vec![URGENT_TAG]).await;
+        assert_eq!(cards.len(), 1);
+        assert_eq!(cards[0].1.title, "Nieuwe spoedmelding: Gaslucht");
+        assert!(create_issue("issue-2", vec![]).await.is_empty());
+
+        let comment = |id: &str, content: &str| {
+            let comment = Comment {
+                content: content.to_string(),
+                quote_comment: None,
+                mentions: None,
+                edited_at: None,
+                internal: None,
+            };
+            create(
+                "issue-2",
+                CommitBuilder::create(id, &comment).actor(alice).build(),
+            )
+        };
+        let cards = comment("comment-1", "@Handhaving graag actie").await;
+        assert_eq!(cards.len(), 1);
+        assert_eq!(cards[0].1.text, "@Handhaving graag actie");
+        assert!(comment("comment-2", "Graag actie").await.is_empty());
+    }
+}
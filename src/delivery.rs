@@ -0,0 +1,303 @@
+//! Delivery status of notifications.
+//!
+//! Every attempt to inform someone of an event is recorded as a `notification.delivery`
+//! event about the issue: `queued` when the notification is handed to the sender, then `sent`
+//! or `failed`. Bounces arrive later through a `delivery_report` integration (see `hooks`),
+//! the webhook the mail provider calls. The issue timeline groups the deliveries by the
+//! event that triggered them, so behandelaars can see whether the citizen was informed.
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::handlers::AppState;
+use crate::pipeline::EventContext;
+use crate::schemas::{CloudEvent, CloudEventBuilder};
+
+pub const DELIVERY_EVENT_TYPE: &str = "notification.delivery";
+
+/// Source of the delivery events the server records itself
+const SOURCE: &str = "notifications";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryChannel {
+    Email,
+    Push,
+    Sms,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryOutcome {
+    /// Handed to the sender
+    Queued,
+    /// Accepted by the provider (or reported delivered)
+    Sent,
+    /// Reported undeliverable by the provider
+    Bounced,
+    /// Sending failed
+    Failed,
+}
+
+/// The data of a `notification.delivery` event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DeliveryStatus {
+    pub channel: DeliveryChannel,
+    pub recipient: String,
+    pub outcome: DeliveryOutcome,
+    /// The event the notification was about; unknown for some provider reports
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger_event: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The delivery event about `issue_id`.
+pub fn delivery_event(issue_id: &str, source: &str, status: &DeliveryStatus) -> CloudEvent {
+    CloudEventBuilder::new(DELIVERY_EVENT_TYPE, issue_id)
+        .source(source)
+        .data(serde_json::to_value(status).unwrap_or_default())
+        .build()
+}
+
+/// The delivery status recorded by `event`, if it is a delivery event.
+pub fn delivery_of(event: &CloudEvent) -> Option<DeliveryStatus> {
+    if event.event_type != DELIVERY_EVENT_TYPE {
+        return None;
+    }
+    serde_json::from_value(event.data.clone()?).ok()
+}
+
+/// Record a delivery attempt or outcome. Failing to record is logged, not returned: it must
+/// not stop the notification itself.
+pub async fn record(state: &AppState, issue_id: &str, status: DeliveryStatus) {
+    let event = delivery_event(issue_id, SOURCE, &status);
+    if let Err(e) = state.pipeline.submit(state, EventContext::new(event)).await {
+        eprintln!(
+            "[delivery] failed to record {:?} to {}: {}",
+            status.outcome, status.recipient, e
+        );
+    }
+}
+
+/// The state of the notification of one recipient over one channel
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Delivery {
+    pub channel: DeliveryChannel,
+    pub recipient: String,
+    /// The latest outcome
+    pub outcome: DeliveryOutcome,
+    /// When the latest outcome was recorded
+    pub time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Group delivery events (in sequence order) by the event that triggered them, keeping the
+/// latest outcome per recipient and channel. A report without trigger event belongs to the
+/// latest earlier delivery to the same recipient over the same channel.
+pub fn aggregate_deliveries<'a>(
+    events: impl IntoIterator<Item = &'a CloudEvent>,
+) -> BTreeMap<String, Vec<Delivery>> {
+    let mut by_trigger: BTreeMap<String, Vec<Delivery>> = BTreeMap::new();
+    let mut last_trigger: BTreeMap<(DeliveryChannel, String), String> = BTreeMap::new();
+    for event in events {
+        let Some(status) = delivery_of(event) else {
+            continue;
+        };
+        let key = (status.channel, status.recipient.clone());
+        let Some(trigger) = status
+            .trigger_event
+            .clone()
+            .or_else(|| last_trigger.get(&key).cloned())
+        else {
+            continue;
+        };
+        last_trigger.insert(key, trigger.clone());
+        let delivery = Delivery {
+            channel: status.channel,
+            recipient: status.recipient,
+            outcome: status.outcome,
+            time: event.time.clone(),
+            error: status.error,
+        };
+        let deliveries = by_trigger.entry(trigger).or_default();
+        match deliveries
+            .iter_mut()
+            .find(|d| d.channel == delivery.channel && d.recipient == delivery.recipient)
+        {
+            Some(existing) => *existing = delivery,
+            None => deliveries.push(delivery),
+        }
+    }
+    by_trigger
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{
+        submit_event,
+        tests::{issue, submit_commit_event, test_state},
+    };
+    use crate::schemas::CommitBuilder;
+    use serde_json::json;
+
+    fn status(recipient: &str, outcome: DeliveryOutcome, trigger: Option<&str>) -> CloudEvent {
+        delivery_event(
+            "issue-1",
+            SOURCE,
+            &DeliveryStatus {
+                channel: DeliveryChannel::Email,
+                recipient: recipient.to_string(),
+                outcome,
+                trigger_event: trigger.map(str::to_string),
+                error: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_aggregate_deliveries() {
+        let events = [
+            status(
+                "alice@example.com",
+                DeliveryOutcome::Queued,
+                Some("event-1"),
+            ),
+            status("bob@example.com", DeliveryOutcome::Queued, Some("event-1")),
+            status("alice@example.com", DeliveryOutcome::Sent, Some("event-1")),
+            status("bob@example.com", DeliveryOutcome::Sent, Some("event-1")),
+            status("bob@example.com", DeliveryOutcome::Queued, Some("event-2")),
+            status("bob@example.com", DeliveryOutcome::Sent, Some("event-2")),
+            // The provider's report names no event: it is about the latest mail to bob
+            status("bob@example.com", DeliveryOutcome::Bounced, None),
+            status("carol@example.com", DeliveryOutcome::Bounced, None),
+            CloudEventBuilder::new("json.commit", "issue-1").build(),
+        ];
+        let deliveries = aggregate_deliveries(&events);
+        assert_eq!(deliveries.len(), 2);
+        let outcomes = |trigger: &str| -> Vec<(String, DeliveryOutcome)> {
+            deliveries[trigger]
+                .iter()
+                .map(|d| (d.recipient.clone(), d.outcome))
+                .collect()
+        };
+        assert_eq!(
+            outcomes("event-1"),
+            vec![
+                ("alice@example.com".to_string(), DeliveryOutcome::Sent),
+                ("bob@example.com".to_string(), DeliveryOutcome::Sent),
+            ]
+        );
+        assert_eq!(
+            outcomes("event-2"),
+            vec![("bob@example.com".to_string(), DeliveryOutcome::Bounced)]
+        );
+    }
+
+    /// Accepts every notification, except those to `carol`
+    struct PickyTransport;
+
+    #[async_trait::async_trait]
+    impl crate::email::EmailTransport for PickyTransport {
+        async fn send_magic_link(
+            &self,
+            _email: &str,
+            _token: &str,
+        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            Ok(())
+        }
+
+        async fn send_notification(
+            &self,
+            to: &str,
+            _subject: &str,
+            _html_body: &str,
+            _text_body: &str,
+            _reply_to: Option<&str>,
+            _thread_id: Option<&str>,
+        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            if to.starts_with("carol") {
+                return Err("inactive recipient".into());
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_notifications_are_tracked() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut state = test_state(dir.path()).await;
+        state.email_service = std::sync::Arc::new(crate::email::EmailService::new(
+            std::sync::Arc::new(PickyTransport),
+        ));
+        let (alice, carol) = ("alice@example.com", "carol@example.com");
+        let mut commit =
+            CommitBuilder::create("issue-1", &issue("Paspoort", &[alice, carol])).build();
+        // As clients send it
+        commit.resource_data.as_mut().unwrap()["id"] = json!("issue-1");
+        let created = submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+
+        // Sending happens in the background
+        let deliveries = || async {
+            let events = state.storage.list_events_after(None, 100).await.unwrap();
+            aggregate_deliveries(&events)
+                .remove(&created.id)
+                .unwrap_or_default()
+        };
+        let outcome = |deliveries: &[Delivery], recipient: &str| {
+            deliveries
+                .iter()
+                .find(|d| d.recipient == recipient)
+                .map(|d| (d.outcome, d.error.clone()))
+        };
+        for _ in 0..100 {
+            let current = deliveries().await;
+            if current.len() == 2 && current.iter().all(|d| d.outcome != DeliveryOutcome::Queued) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let current = deliveries().await;
+        assert_eq!(
+            outcome(&current, alice),
+            Some((DeliveryOutcome::Sent, None))
+        );
+        assert_eq!(
+            outcome(&current, carol),
+            Some((
+                DeliveryOutcome::Failed,
+                Some("inactive recipient".to_string())
+            ))
+        );
+
+        // Later, the mail provider reports a bounce
+        let settings: crate::hooks::IntegrationSettings = serde_json::from_value(json!({
+            "kind": "delivery_report",
+            "signature": "hmac",
+            "secret": "geheim",
+        }))
+        .unwrap();
+        let report = json!({
+            "RecordType": "Bounce",
+            "Email": alice,
+            "Description": "Mailbox does not exist",
+            "Metadata": {"issue": "issue-1"},
+        });
+        let events = crate::hooks::translate("postmark", &settings, &report).unwrap();
+        submit_event(&state, events.into_iter().next().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome(&deliveries().await, alice),
+            Some((
+                DeliveryOutcome::Bounced,
+                Some("Mailbox does not exist".to_string())
+            ))
+        );
+    }
+}
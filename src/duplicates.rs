@@ -0,0 +1,564 @@
+//! Duplicate meldingen: detection when an issue is created, and merging a duplicate into
+//! the issue it duplicates.
+//!
+//! Detection looks up open issues with overlapping title words in the search index, then
+//! scores them on word overlap (Jaccard) of title and description. Issues scoring at least
+//! [`DUPLICATE_THRESHOLD`] are flagged on the new issue as `possible_duplicates`, with a
+//! patch commit by "system".
+//!
+//! `POST /issues/{id}/merge/{other}` merges `other` into `id`, entirely through commits:
+//! every resource on `other` (comments, tasks, ...) is re-committed on `id`, the `involved`
+//! lists are merged, and `other` is closed and linked to `id` as `duplicate_of`.
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{
+    authorize_issue, check_access, commit_of, submit_event, AppState, CATCHUP_PAGE_SIZE,
+};
+use crate::relations::relation_id;
+use crate::schemas::{
+    CloudEvent, CloudEventBuilder, CommitBuilder, Issue, JSONCommit, Relation, RelationType,
+};
+
+/// Minimum similarity score for an issue to be flagged as a possible duplicate
+pub const DUPLICATE_THRESHOLD: f64 = 0.5;
+
+/// At most this many possible duplicates are flagged or listed
+const MAX_CANDIDATES: usize = 5;
+
+/// Search hits considered before scoring
+const SEARCH_LIMIT: usize = 50;
+
+/// Common Dutch words that say nothing about what an issue is about
+const STOPWORDS: &[&str] = &[
+    "aan", "bij", "dat", "de", "den", "der", "die", "een", "en", "het", "in", "is", "met", "naar",
+    "niet", "of", "om", "op", "te", "van", "voor", "wordt", "zijn",
+];
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DuplicateCandidate {
+    pub issue_id: String,
+    pub title: String,
+    /// Similarity between 0 and 1
+    pub score: f64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MergeSummary {
+    /// The issue that was kept
+    pub issue_id: String,
+    /// The issue that was merged and closed
+    pub duplicate_id: String,
+    /// Resources moved from the duplicate, in creation order
+    pub moved: Vec<String>,
+    /// The kept issue's `involved` list after the merge
+    pub involved: Vec<String>,
+}
+
+/// Lowercase words of at least two characters, without stopwords.
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(str::to_lowercase)
+        .filter(|w| w.chars().count() >= 2 && !STOPWORDS.contains(&w.as_str()))
+        .collect()
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+fn text_field<'a>(issue: &'a Value, field: &str) -> &'a str {
+    issue
+        .get(field)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+}
+
+/// Similarity of two issues: mostly title overlap, some overlap of title and description.
+pub fn similarity(a: &Value, b: &Value) -> f64 {
+    let title = |issue| words(text_field(issue, "title"));
+    let text = |issue| {
+        words(&format!(
+            "{} {}",
+            text_field(issue, "title"),
+            text_field(issue, "description")
+        ))
+    };
+    0.7 * jaccard(&title(a), &title(b)) + 0.3 * jaccard(&text(a), &text(b))
+}
+
+/// Open issues that are probably duplicates of `issue`, most similar first.
+pub async fn find_duplicates(
+    state: &AppState,
+    issue_id: &str,
+    issue: &Value,
+) -> Result<Vec<DuplicateCandidate>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut terms: Vec<String> = words(text_field(issue, "title")).into_iter().collect();
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+    terms.sort();
+    let query = terms
+        .iter()
+        .map(|t| format!("title:{}", t))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+    for hit in state
+        .search
+        .search(&state.storage, &query, SEARCH_LIMIT)
+        .await?
+    {
+        let Some(other) = hit.resource else {
+            continue;
+        };
+        if hit.id == issue_id || !seen.insert(hit.id.clone()) {
+            continue;
+        }
+        if other.get("involved").is_none() || text_field(&other, "status") == "closed" {
+            continue;
+        }
+        let score = similarity(issue, &other);
+        if score >= DUPLICATE_THRESHOLD {
+            candidates.push(DuplicateCandidate {
+                title: text_field(&other, "title").to_string(),
+                issue_id: hit.id,
+                score,
+            });
+        }
+    }
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    candidates.truncate(MAX_CANDIDATES);
+    Ok(candidates)
+}
+
+/// If `event` created an issue, flag its probable duplicates on it.
+pub async fn flag_possible_duplicates(
+    state: &AppState,
+    event: &CloudEvent,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(commit) = commit_of(event) else {
+        return Ok(());
+    };
+    let is_issue_creation = commit.resource_data.is_some()
+        && commit.deleted != Some(true)
+        && commit.schema.trim_end_matches(".json").ends_with("/Issue");
+    if !is_issue_creation {
+        return Ok(());
+    }
+    let Some(issue) = state.storage.get_resource(&commit.resource_id).await? else {
+        return Ok(());
+    };
+
+    let candidates = find_duplicates(state, &commit.resource_id, &issue).await?;
+    if candidates.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<String> = candidates.into_iter().map(|c| c.issue_id).collect();
+    let flag = CommitBuilder::patch::<Issue>(
+        commit.resource_id.clone(),
+        json!({ "possible_duplicates": ids }),
+    )
+    .build();
+    submit_event(
+        state,
+        CloudEventBuilder::commit(commit.resource_id, &flag).build(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// GET /issues/{id}/duplicates - Open issues that look like duplicates of an issue
+#[utoipa::path(
+    get,
+    path = "/issues/{id}/duplicates",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Possible duplicates the caller has access to, most similar first", body = [DuplicateCandidate]),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn list_duplicates_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+) -> Result<Json<Vec<DuplicateCandidate>>, StatusCode> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!(
+            "[duplicates] failed to find duplicates of {}: {}",
+            issue_id, e
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    authorize_issue(&state, &auth_user.user_id, &issue_id).await?;
+    let issue = state
+        .storage
+        .get_resource(&issue_id)
+        .await
+        .map_err(internal)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut visible = Vec::new();
+    for candidate in find_duplicates(&state, &issue_id, &issue)
+        .await
+        .map_err(internal)?
+    {
+        if check_access(&state.storage, &auth_user.user_id, &candidate.issue_id).await {
+            visible.push(candidate);
+        }
+    }
+    Ok(Json(visible))
+}
+
+/// The resources that were committed on `issue_id` (other than the issue itself and its
+/// relations), in order of first commit, each with the schema of its latest commit.
+async fn resources_on_issue(
+    state: &AppState,
+    issue_id: &str,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut resources: Vec<(String, String)> = Vec::new();
+    let mut after = None;
+    loop {
+        let page = state
+            .storage
+            .list_events_after(after, CATCHUP_PAGE_SIZE)
+            .await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = last.sequence.clone();
+
+        for event in page.iter().filter(|e| e.subject == issue_id) {
+            let Some(commit) = commit_of(event) else {
+                continue;
+            };
+            if commit.resource_id == issue_id
+                || commit
+                    .schema
+                    .trim_end_matches(".json")
+                    .ends_with("/Relation")
+            {
+                continue;
+            }
+            match resources
+                .iter_mut()
+                .find(|(id, _)| *id == commit.resource_id)
+            {
+                Some(entry) => entry.1 = commit.schema,
+                None => resources.push((commit.resource_id, commit.schema)),
+            }
+        }
+    }
+    Ok(resources)
+}
+
+fn involved_of(issue: &Value) -> Vec<String> {
+    issue
+        .get("involved")
+        .and_then(|v| v.as_array())
+        .map(|people| {
+            people
+                .iter()
+                .filter_map(|p| p.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// POST /issues/{id}/merge/{other} - Merge a duplicate issue into this one
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/merge/{other}",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Issue to keep"),
+        ("other" = String, Path, description = "Duplicate issue to merge and close"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Merge commits stored, processed and broadcast", body = MergeSummary),
+        (status = 400, description = "An issue cannot be merged into itself"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in both issues"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn merge_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path((issue_id, other_id)): Path<(String, String)>,
+) -> Result<Json<MergeSummary>, StatusCode> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!(
+            "[duplicates] failed to merge {} into {}: {}",
+            other_id, issue_id, e
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    if issue_id == other_id {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let user = auth_user.user_id;
+    authorize_issue(&state, &user, &issue_id).await?;
+    authorize_issue(&state, &user, &other_id).await?;
+
+    let issue = state
+        .storage
+        .get_resource(&issue_id)
+        .await
+        .map_err(internal)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let other = state
+        .storage
+        .get_resource(&other_id)
+        .await
+        .map_err(internal)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let submit = |subject: &str, commit: JSONCommit| {
+        submit_event(&state, CloudEventBuilder::commit(subject, &commit).build())
+    };
+
+    // Re-commit each resource of the duplicate, unchanged, on the kept issue
+    let mut moved = Vec::new();
+    for (id, schema) in resources_on_issue(&state, &other_id)
+        .await
+        .map_err(internal)?
+    {
+        let Some(current) = state.storage.get_resource(&id).await.map_err(internal)? else {
+            continue;
+        };
+        let commit = JSONCommit {
+            schema,
+            resource_id: id.clone(),
+            actor: user.clone(),
+            timestamp: Some(chrono::Utc::now().to_rfc3339()),
+            resource_data: Some(current),
+            patch: None,
+            deleted: None,
+            base_sequence: None,
+            offline: None,
+            patch_type: None,
+        };
+        submit(&issue_id, commit).await.map_err(internal)?;
+        moved.push(id);
+    }
+
+    let mut involved = involved_of(&issue);
+    let before = involved.len();
+    for person in involved_of(&other) {
+        if !involved.contains(&person) {
+            involved.push(person);
+        }
+    }
+    let mut patch = serde_json::Map::new();
+    if involved.len() != before {
+        patch.insert("involved".to_string(), json!(involved));
+    }
+    let flagged: Vec<Value> = issue
+        .get("possible_duplicates")
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default();
+    if flagged
+        .iter()
+        .any(|id| id.as_str() == Some(other_id.as_str()))
+    {
+        let remaining: Vec<Value> = flagged
+            .into_iter()
+            .filter(|id| id.as_str() != Some(other_id.as_str()))
+            .collect();
+        let remaining = if remaining.is_empty() {
+            Value::Null
+        } else {
+            Value::Array(remaining)
+        };
+        patch.insert("possible_duplicates".to_string(), remaining);
+    }
+    if !patch.is_empty() {
+        let commit = CommitBuilder::patch::<Issue>(issue_id.clone(), Value::Object(patch))
+            .actor(user.clone())
+            .build();
+        submit(&issue_id, commit).await.map_err(internal)?;
+    }
+
+    let close = CommitBuilder::patch::<Issue>(
+        other_id.clone(),
+        json!({
+            "status": "closed",
+            "resolution": format!("Samengevoegd met {}", text_field(&issue, "title")),
+            "possible_duplicates": null,
+        }),
+    )
+    .actor(user.clone())
+    .build();
+    submit(&other_id, close).await.map_err(internal)?;
+
+    let relation = Relation {
+        relation_type: RelationType::DuplicateOf,
+        source_id: other_id.clone(),
+        target_id: issue_id.clone(),
+    };
+    let link = CommitBuilder::create(
+        relation_id(&other_id, RelationType::DuplicateOf, &issue_id),
+        &relation,
+    )
+    .actor(user)
+    .build();
+    submit(&other_id, link).await.map_err(internal)?;
+
+    Ok(Json(MergeSummary {
+        issue_id,
+        duplicate_id: other_id,
+        moved,
+        involved,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user, create_issue, submit_commit_event, test_state};
+    use crate::relations::{issue_links, LinkDirection};
+    use crate::schemas::Comment;
+
+    fn issue(title: &str, description: &str, involved: &str) -> Issue {
+        Issue {
+            description: Some(description.to_string()),
+            ..crate::handlers::tests::issue(title, &[involved])
+        }
+    }
+
+    #[test]
+    fn test_similarity() {
+        let a = json!({"title": "Lantaarnpaal kapot in de Dorpsstraat"});
+        let b = json!({"title": "Kapotte lantaarnpaal Dorpsstraat"});
+        let c = json!({"title": "Paspoort aanvragen"});
+        assert!(similarity(&a, &a) > 0.99);
+        assert!(similarity(&a, &b) > similarity(&a, &c));
+        assert_eq!(similarity(&a, &c), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_flag_and_merge_duplicate() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let (alice, bob) = ("alice@gemeente.nl", "bob@gemeente.nl");
+
+        let first = issue(
+            "Lantaarnpaal kapot Dorpsstraat",
+            "De lantaarnpaal bij nummer 12 brandt niet",
+            alice,
+        );
+        create_issue(&state, "melding-1", &first, alice).await;
+        let second = issue(
+            "Lantaarnpaal Dorpsstraat kapot",
+            "Lantaarnpaal bij nummer 12 is stuk",
+            alice,
+        );
+        let event = create_issue(&state, "melding-2", &second, alice).await;
+        flag_possible_duplicates(&state, &event).await.unwrap();
+        let flagged = state.storage.get_resource("melding-2").await.unwrap();
+        assert_eq!(
+            flagged.unwrap()["possible_duplicates"],
+            json!(["melding-1"])
+        );
+
+        let comment = Comment {
+            content: "Al twee weken donker".to_string(),
+            quote_comment: None,
+            mentions: None,
+            edited_at: None,
+            internal: None,
+        };
+        let commit = CommitBuilder::create("comment-1", &comment).build();
+        submit_commit_event(&state, "melding-2", &commit)
+            .await
+            .unwrap();
+
+        // Unrelated issues are not flagged
+        let other = issue("Paspoort aanvragen", "Verlopen paspoort", bob);
+        let event = create_issue(&state, "melding-3", &other, bob).await;
+        flag_possible_duplicates(&state, &event).await.unwrap();
+        let unflagged = state.storage.get_resource("melding-3").await.unwrap();
+        assert!(unflagged.unwrap().get("possible_duplicates").is_none());
+
+        let list = |who: &str| {
+            list_duplicates_handler(
+                State(state.clone()),
+                auth_user(who),
+                Path("melding-2".to_string()),
+            )
+        };
+        assert_eq!(list(bob).await.unwrap_err(), StatusCode::FORBIDDEN);
+        let Json(candidates) = list(alice).await.unwrap();
+        let ids: Vec<&str> = candidates.iter().map(|c| c.issue_id.as_str()).collect();
+        assert_eq!(ids, vec!["melding-1"]);
+
+        let merge = |id: &str, other: &str, who: &str| {
+            merge_handler(
+                State(state.clone()),
+                auth_user(who),
+                Path((id.to_string(), other.to_string())),
+            )
+        };
+        assert_eq!(
+            merge("melding-1", "melding-3", alice).await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            merge("melding-1", "melding-2", bob).await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            merge("melding-1", "melding-1", alice).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let Json(summary) = merge("melding-1", "melding-2", alice).await.unwrap();
+        assert_eq!(summary.moved, vec!["comment-1"]);
+        assert_eq!(summary.involved, vec![alice]);
+
+        let closed = state
+            .storage
+            .get_resource("melding-2")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(closed["status"], "closed");
+        assert!(closed.get("possible_duplicates").is_none());
+
+        // The comment now belongs to the kept issue
+        let origin = crate::comments::comment_origin(&state.storage, "comment-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(origin.issue_id, "melding-1");
+
+        let links = issue_links(&state.storage, alice, "melding-1")
+            .await
+            .unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].relation_type, RelationType::DuplicateOf);
+        assert_eq!(links[0].direction, LinkDirection::Incoming);
+        assert_eq!(links[0].issue_id, "melding-2");
+    }
+}
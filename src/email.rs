@@ -30,6 +30,9 @@ struct PostmarkEmail {
     reply_to: Option<String>,
     #[serde(rename = "Headers", skip_serializing_if = "Vec::is_empty")]
     headers: Vec<PostmarkHeader>,
+    /// Returned in bounce webhooks, to tie the bounce to the issue (see `delivery`)
+    #[serde(rename = "Metadata", skip_serializing_if = "Option::is_none")]
+    metadata: Option<serde_json::Value>,
 }
 
 #[async_trait]
@@ -135,6 +138,7 @@ impl EmailTransport for PostmarkTransport {
             message_stream: "outbound".to_string(),
             reply_to: None,
             headers: vec![],
+            metadata: None,
         };
 
         let res = self
@@ -187,6 +191,7 @@ impl EmailTransport for PostmarkTransport {
             message_stream: "outbound".to_string(),
             reply_to: reply_to.map(|s| s.to_string()),
             headers,
+            metadata: thread_id.map(|tid| json!({ "issue": tid })),
         };
 
         let res = self
@@ -0,0 +1,248 @@
+//! Content negotiation between JSON and CBOR.
+//!
+//! High-volume machine consumers can send `Accept: application/cbor` to receive compact
+//! binary responses (when they prefer it to JSON by quality, or by order at equal quality),
+//! and POST CloudEvents with `Content-Type: application/cbor`.
+//! CloudEvents are encoded per the CloudEvents CBOR event format: the envelope attributes
+//! form a CBOR map and structured `data` is embedded as native CBOR values.
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Generic CBOR media type (used for resources)
+pub const CBOR: &str = "application/cbor";
+/// CloudEvents structured-mode CBOR media type (single event)
+pub const CLOUDEVENTS_CBOR: &str = "application/cloudevents+cbor";
+/// CloudEvents batch CBOR media type (list of events)
+pub const CLOUDEVENTS_BATCH_CBOR: &str = "application/cloudevents-batch+cbor";
+
+/// True when the media type (parameters stripped) is one of the CBOR types we understand.
+fn is_cbor_media_type(media_type: &str) -> bool {
+    let essence = media_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    essence == CBOR || essence == CLOUDEVENTS_CBOR || essence == CLOUDEVENTS_BATCH_CBOR
+}
+
+/// The media types we answer with JSON
+const JSON_TYPES: [&str; 3] = [
+    "application/json",
+    "application/cloudevents+json",
+    "application/cloudevents-batch+json",
+];
+
+/// The media ranges of the `Accept` header, as (essence in lower case, quality), in order.
+fn media_ranges(headers: &HeaderMap) -> Vec<(String, f32)> {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let essence = parts.next()?.trim().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (!essence.is_empty()).then_some((essence, quality))
+        })
+        .collect()
+}
+
+/// The quality `ranges` give `media_type`, from the most specific range that matches it
+/// (`type/subtype` over `type/*` over `*/*`), with that range's position.
+fn quality(ranges: &[(String, f32)], media_type: &str) -> Option<(f32, usize)> {
+    let main_type = media_type.split('/').next().unwrap_or("");
+    ranges
+        .iter()
+        .enumerate()
+        .filter_map(|(position, (range, quality))| {
+            let specificity = if range == media_type {
+                2
+            } else if range.strip_suffix("/*") == Some(main_type) {
+                1
+            } else if range == "*/*" {
+                0
+            } else {
+                return None;
+            };
+            Some((specificity, position, *quality))
+        })
+        .min_by_key(|(specificity, position, _)| (-specificity, *position))
+        .map(|(_, position, quality)| (quality, position))
+}
+
+/// The best quality of any of `media_types`, earliest position first at equal quality.
+fn best_quality(ranges: &[(String, f32)], media_types: &[&str]) -> Option<(f32, usize)> {
+    media_types
+        .iter()
+        .filter_map(|media_type| quality(ranges, media_type))
+        .reduce(
+            |best, q| match q.0 > best.0 || (q.0 == best.0 && q.1 < best.1) {
+                true => q,
+                false => best,
+            },
+        )
+}
+
+/// Does the `Accept` header prefer CBOR to JSON? CBOR must be acceptable (not `q=0`) and
+/// have a higher quality than JSON, or the same quality and come first.
+pub fn accepts_cbor(headers: &HeaderMap) -> bool {
+    let ranges = media_ranges(headers);
+    let cbor = best_quality(&ranges, &[CBOR, CLOUDEVENTS_CBOR, CLOUDEVENTS_BATCH_CBOR]);
+    match (cbor, best_quality(&ranges, &JSON_TYPES)) {
+        (Some((cbor, _)), _) if cbor <= 0.0 => false,
+        (Some(_), None) => true,
+        (Some((cbor, cbor_position)), Some((json, json_position))) => {
+            cbor > json || (cbor == json && cbor_position < json_position)
+        }
+        (None, _) => false,
+    }
+}
+
+/// Is the request body CBOR-encoded?
+pub fn is_cbor_body(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(is_cbor_media_type)
+        .unwrap_or(false)
+}
+
+/// Encode a value as CBOR bytes.
+pub fn to_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>, ciborium::ser::Error<std::io::Error>> {
+    let mut buf = Vec::new();
+    ciborium::into_writer(value, &mut buf)?;
+    Ok(buf)
+}
+
+/// Build a response in the representation the client asked for: CBOR (with the given
+/// CBOR media type) when `Accept` requests it, JSON otherwise.
+pub fn negotiated<T: Serialize>(
+    headers: &HeaderMap,
+    status: StatusCode,
+    value: &T,
+    cbor_media_type: &'static str,
+) -> Response {
+    if !accepts_cbor(headers) {
+        return (status, Json(value)).into_response();
+    }
+
+    match to_cbor(value) {
+        Ok(bytes) => {
+            let mut response = (status, bytes).into_response();
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static(cbor_media_type));
+            response
+        }
+        Err(e) => {
+            eprintln!("[encoding] failed to encode CBOR response: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Request body extractor that accepts either JSON or CBOR, based on `Content-Type`.
+pub struct JsonOrCbor<T>(pub T);
+
+impl<T, S> FromRequest<S> for JsonOrCbor<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if is_cbor_body(req.headers()) {
+            let bytes = Bytes::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            ciborium::from_reader(bytes.as_ref())
+                .map(JsonOrCbor)
+                .map_err(|e| {
+                    (StatusCode::BAD_REQUEST, format!("Invalid CBOR body: {}", e)).into_response()
+                })
+        } else {
+            Json::<T>::from_request(req, state)
+                .await
+                .map(|Json(value)| JsonOrCbor(value))
+                .map_err(IntoResponse::into_response)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::event;
+    use crate::schemas::CloudEvent;
+    use axum::body::Body;
+
+    fn sample_event() -> CloudEvent {
+        CloudEvent {
+            data: Some(serde_json::json!({"resource_id": "issue-1", "patch": {"status": "open"}})),
+            ..event("evt-cbor", "issue-1")
+        }
+    }
+
+    #[test]
+    fn test_accept_negotiation() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_cbor(&headers));
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/json;q=0.5, application/cbor"),
+        );
+        assert!(accepts_cbor(&headers));
+
+        let response = negotiated(&headers, StatusCode::OK, &sample_event(), CLOUDEVENTS_CBOR);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            CLOUDEVENTS_CBOR
+        );
+
+        let accepts = |accept: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, HeaderValue::from_static(accept));
+            accepts_cbor(&headers)
+        };
+        assert!(accepts("application/cbor, application/json"));
+        assert!(accepts("application/cloudevents+cbor, */*;q=0.1"));
+        assert!(accepts("application/json;q=0.5, application/cbor;q=0.8"));
+        // Refused, or less preferred than JSON
+        assert!(!accepts("application/cbor;q=0, application/json;q=0.1"));
+        assert!(!accepts("application/cbor; q=0"));
+        assert!(!accepts("application/json, application/cbor"));
+        assert!(!accepts("application/cbor;q=0.5, application/json"));
+        assert!(!accepts("*/*"));
+        assert!(!accepts("application/*, application/cbor;q=0"));
+    }
+
+    #[tokio::test]
+    async fn test_cbor_body_round_trip() {
+        let bytes = to_cbor(&sample_event()).unwrap();
+        let req = Request::builder()
+            .header(CONTENT_TYPE, CLOUDEVENTS_CBOR)
+            .body(Body::from(bytes))
+            .unwrap();
+
+        let JsonOrCbor(event) = JsonOrCbor::<CloudEvent>::from_request(req, &())
+            .await
+            .unwrap_or_else(|_| panic!("CBOR body should decode"));
+        assert_eq!(event.id, "evt-cbor");
+        assert_eq!(event.data.unwrap()["patch"]["status"], "open");
+    }
+}
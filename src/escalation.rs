@@ -0,0 +1,419 @@
+//! Automatic escalation of issues that lie idle for too long.
+//!
+//! Each tenant can have an `EscalationPolicy` (`escalation-policy-{tenant}`) with steps like
+//! "48 hours without activity: notify the teamleider; 7 days: reassign". A scheduler runs
+//! the policies every [`ESCALATION_INTERVAL`]: for each open issue it takes the time since
+//! the last event not made by "system", and executes every step whose threshold has passed
+//! and that did not run yet for this idle period. Each executed step is recorded as an
+//! `Escalation` resource, created with a commit on the issue, so it shows in the timeline.
+//! A policy reassigns issues, so only admins (see `auth::is_admin`) may set it.
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::{extract::State, http::HeaderMap, http::StatusCode, response::Response, Json};
+use serde::Deserialize;
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::auth::{tenant_of, AuthUser};
+use crate::availability::active_delegate;
+use crate::handlers::{commit_of, submit_commit, submit_event, AppState};
+use crate::schemas::{
+    CloudEvent, CloudEventBuilder, CommitBuilder, Escalation, EscalationPolicy, EscalationStep,
+    EscalationType, Issue,
+};
+use crate::storage::Storage;
+use crate::teams::get_team;
+
+/// How often the scheduler checks for idle issues
+pub const ESCALATION_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Commits by this actor (automatic flags, reassignments, escalations) are not activity
+const SYSTEM_ACTOR: &str = "system";
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PolicyRequest {
+    #[schema(value_type = Vec<Object>)]
+    pub steps: Vec<EscalationStep>,
+}
+
+/// Resource ID of a tenant's escalation policy
+pub fn policy_id(tenant: &str) -> String {
+    format!("escalation-policy-{}", tenant)
+}
+
+/// Resource ID of an executed step. `since` is the sequence of the issue's last activity,
+/// so every step runs once per idle period.
+pub fn escalation_id(issue_id: &str, since: &str, step: usize) -> String {
+    format!("escalation-{}-{}-{}", issue_id, since, step)
+}
+
+/// Sequence and time of the last event about `issue_id` not made by "system".
+async fn last_activity(
+    storage: &Storage,
+    issue_id: &str,
+) -> Result<Option<(String, chrono::DateTime<chrono::Utc>)>, Box<dyn std::error::Error + Send + Sync>>
+{
+    let events = storage.list_subject_events(issue_id).await?;
+    let last = events
+        .into_iter()
+        .rev()
+        .find(|event| commit_of(event).is_none_or(|commit| commit.actor != SYSTEM_ACTOR));
+    Ok(last.and_then(|event| {
+        let time = chrono::DateTime::parse_from_rfc3339(event.time.as_deref()?).ok()?;
+        Some((event.sequence?, time.with_timezone(&chrono::Utc)))
+    }))
+}
+
+/// The policy that applies to an issue: that of the assignee's tenant, else the team's,
+/// else that of the first involved person whose tenant has one.
+async fn policy_for(
+    storage: &Storage,
+    issue: &Issue,
+    cache: &mut HashMap<String, Option<EscalationPolicy>>,
+) -> Result<Option<(String, EscalationPolicy)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut tenants = Vec::new();
+    tenants.extend(issue.assignee.as_deref().map(|a| tenant_of(a).to_string()));
+    if let Some(team) = &issue.team {
+        tenants.extend(get_team(storage, team).await?.map(|t| t.tenant));
+    }
+    for person in issue.involved.iter().flatten() {
+        tenants.push(tenant_of(person).to_string());
+    }
+    for tenant in tenants {
+        if !cache.contains_key(&tenant) {
+            let policy = storage
+                .get_resource(&policy_id(&tenant))
+                .await?
+                .and_then(|value| serde_json::from_value(value).ok());
+            cache.insert(tenant.clone(), policy);
+        }
+        if let Some(Some(policy)) = cache.get(&tenant) {
+            return Ok(Some((tenant, policy.clone())));
+        }
+    }
+    Ok(None)
+}
+
+/// Execute one step; returns who was notified or got the issue.
+async fn apply_step(
+    state: &AppState,
+    issue_id: &str,
+    issue: &Issue,
+    tenant: &str,
+    step: &EscalationStep,
+    idle_hours: u64,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    match step.action {
+        EscalationType::Notify => {
+            let Some(target) = step.target.clone() else {
+                return Ok(None);
+            };
+            let base_url =
+                std::env::var("BASE_URL").unwrap_or_else(|_| "https://zaakchat.nl".to_string());
+            let subject = format!("Escalatie: {}", issue.title);
+            let text_body = format!(
+                "De zaak \"{}\" heeft al {} uur geen activiteit.\n\nBekijk in ZaakChat: {}/zaak/{}",
+                issue.title, idle_hours, base_url, issue_id
+            );
+            let html_body = format!(
+                "<html><body><p>De zaak \"{}\" heeft al {} uur geen activiteit.</p><p><a href=\"{}/zaak/{}\">Bekijk in ZaakChat</a></p></body></html>",
+                issue.title, idle_hours, base_url, issue_id
+            );
+            tokio::spawn({
+                let email_service = state.email_service.clone();
+                let target = target.clone();
+                let issue_id = issue_id.to_string();
+                async move {
+                    if let Err(e) = email_service
+                        .send_notification(
+                            &target,
+                            &subject,
+                            &html_body,
+                            &text_body,
+                            None,
+                            Some(&issue_id),
+                        )
+                        .await
+                    {
+                        eprintln!("[escalation] failed to notify {}: {}", target, e);
+                    }
+                }
+            });
+            Ok(Some(target))
+        }
+        EscalationType::Reassign => {
+            let target = match &step.target {
+                Some(target) => Some(target.clone()),
+                None => crate::assignment::suggest(&state.storage, issue_id, tenant)
+                    .await?
+                    .into_iter()
+                    .map(|s| s.user)
+                    .find(|user| Some(user) != issue.assignee.as_ref()),
+            };
+            let Some(mut target) = target else {
+                return Ok(None);
+            };
+            if let Some(delegate) = active_delegate(&state.storage, &target).await? {
+                target = delegate;
+            }
+            let mut involved = issue.involved.clone().unwrap_or_default();
+            if !involved.contains(&target) {
+                involved.push(target.clone());
+            }
+            let reassign = CommitBuilder::patch::<Issue>(
+                issue_id,
+                json!({ "assignee": target, "involved": involved }),
+            )
+            .build();
+            submit_event(
+                state,
+                CloudEventBuilder::commit(issue_id, &reassign).build(),
+            )
+            .await?;
+            Ok(Some(target))
+        }
+    }
+}
+
+/// Execute all due escalation steps; returns the escalations recorded.
+pub async fn run_escalations(
+    state: &AppState,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Vec<Escalation>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut policies = HashMap::new();
+    let mut escalations = Vec::new();
+    let mut open = crate::views::issues_with_status(&state.storage, "open").await?;
+    open.extend(crate::views::issues_with_status(&state.storage, "in_progress").await?);
+    for issue_id in open {
+        let Some(value) = state.storage.get_resource(&issue_id).await? else {
+            continue;
+        };
+        let Ok(issue) = serde_json::from_value::<Issue>(value) else {
+            continue;
+        };
+        let Some((tenant, policy)) = policy_for(&state.storage, &issue, &mut policies).await?
+        else {
+            continue;
+        };
+        let Some((since, last)) = last_activity(&state.storage, &issue_id).await? else {
+            continue;
+        };
+        let idle_hours = (now - last).num_hours().max(0) as u64;
+
+        for (index, step) in policy.steps.iter().enumerate() {
+            if idle_hours < step.after_hours {
+                break;
+            }
+            let id = escalation_id(&issue_id, &since, index);
+            if state.storage.get_resource(&id).await?.is_some() {
+                continue;
+            }
+            let target = apply_step(state, &issue_id, &issue, &tenant, step, idle_hours).await?;
+            let escalation = Escalation {
+                issue_id: issue_id.clone(),
+                step: index,
+                action: step.action,
+                target,
+                idle_hours,
+            };
+            let commit = CommitBuilder::create(id, &escalation).build();
+            submit_event(state, CloudEventBuilder::commit(&issue_id, &commit).build()).await?;
+            escalations.push(escalation);
+        }
+    }
+    Ok(escalations)
+}
+
+/// Run the escalation policies every [`ESCALATION_INTERVAL`].
+pub fn spawn_escalation_scheduler(state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(ESCALATION_INTERVAL);
+        loop {
+            ticker.tick().await;
+            match run_escalations(&state, chrono::Utc::now()).await {
+                Ok(escalations) if !escalations.is_empty() => {
+                    println!("[escalation] escalated {} step(s)", escalations.len())
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("[escalation] run failed: {}", e),
+            }
+        }
+    })
+}
+
+/// GET /escalation-policy - The escalation policy of the caller's tenant
+#[utoipa::path(
+    get,
+    path = "/escalation-policy",
+    tag = "resources",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The tenant's escalation policy", body = Value),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "The tenant has no escalation policy"),
+    )
+)]
+pub async fn get_policy_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<EscalationPolicy>, StatusCode> {
+    let id = policy_id(tenant_of(&auth_user.user_id));
+    state
+        .storage
+        .get_resource(&id)
+        .await
+        .map_err(|e| {
+            eprintln!("[escalation] failed to load {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .and_then(|value| serde_json::from_value(value).ok())
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// PUT /escalation-policy - Replace the escalation policy of the caller's tenant
+#[utoipa::path(
+    put,
+    path = "/escalation-policy",
+    tag = "resources",
+    request_body = PolicyRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Policy commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "A step without waiting time, a notification without recipient, or a recipient from another tenant"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+    )
+)]
+pub async fn update_policy_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Json(request): Json<PolicyRequest>,
+) -> Result<Response, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let user = auth_user.user_id;
+    let tenant = tenant_of(&user).to_string();
+    let valid = request.steps.iter().all(|step| {
+        step.after_hours > 0
+            && (step.action != EscalationType::Notify || step.target.is_some())
+            && step
+                .target
+                .as_deref()
+                .is_none_or(|t| tenant_of(t) == tenant)
+    });
+    if !valid {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut steps = request.steps;
+    steps.sort_by_key(|step| step.after_hours);
+    let id = policy_id(&tenant);
+    let policy = EscalationPolicy { tenant, steps };
+    let commit = CommitBuilder::create(id.clone(), &policy)
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &id, commit).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user, create_issue, issue, test_state, ADMIN};
+
+    #[tokio::test]
+    async fn test_idle_issue_is_escalated_once() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let bob = "bob@gemeente.nl";
+        let teamleider = "teamleider@gemeente.nl";
+
+        let step =
+            |after_hours: u64, action: EscalationType, target: Option<&str>| EscalationStep {
+                after_hours,
+                action,
+                target: target.map(str::to_string),
+            };
+        let update_as = |user: &str, steps: Vec<EscalationStep>| {
+            update_policy_handler(
+                State(state.clone()),
+                auth_user(user),
+                HeaderMap::new(),
+                Json(PolicyRequest { steps }),
+            )
+        };
+        let update = |steps| update_as(ADMIN, steps);
+        assert_eq!(
+            update_as(
+                alice,
+                vec![step(48, EscalationType::Notify, Some(teamleider))]
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            update(vec![step(48, EscalationType::Notify, None)])
+                .await
+                .unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        let updated = update(vec![
+            step(168, EscalationType::Reassign, Some(bob)),
+            step(48, EscalationType::Notify, Some(teamleider)),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(updated.status(), StatusCode::ACCEPTED);
+
+        let issue = Issue {
+            assignee: Some(alice.to_string()),
+            ..issue("Bezwaar WOZ", &[alice, "melder@example.com"])
+        };
+        let created = create_issue(&state, "issue-1", &issue, alice).await;
+        let created_at =
+            chrono::DateTime::parse_from_rfc3339(created.time.as_deref().unwrap()).unwrap();
+
+        // Two days later only the notification is due
+        let escalations =
+            run_escalations(&state, (created_at + chrono::Duration::hours(50)).into())
+                .await
+                .unwrap();
+        assert_eq!(escalations.len(), 1);
+        assert_eq!(escalations[0].action, EscalationType::Notify);
+        assert_eq!(escalations[0].target.as_deref(), Some(teamleider));
+
+        // After a week the issue is reassigned; the notification is not repeated
+        let week = (created_at + chrono::Duration::hours(170)).into();
+        let escalations = run_escalations(&state, week).await.unwrap();
+        assert_eq!(escalations.len(), 1);
+        assert_eq!(escalations[0].action, EscalationType::Reassign);
+        let reassigned: Issue = serde_json::from_value(
+            state
+                .storage
+                .get_resource("issue-1")
+                .await
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(reassigned.assignee.as_deref(), Some(bob));
+        assert!(run_escalations(&state, week).await.unwrap().is_empty());
+
+        // Both steps are on the issue's timeline
+        let recorded = state
+            .storage
+            .list_subject_events("issue-1")
+            .await
+            .unwrap()
+            .iter()
+            .filter_map(commit_of)
+            .filter(|commit| commit.resource_id.starts_with("escalation-"))
+            .count();
+        assert_eq!(recorded, 2);
+    }
+}
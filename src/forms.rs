@@ -0,0 +1,405 @@
+//! Validating form submissions: `POST /forms/{id}/validate`.
+//!
+//! A `Form` resource defines the fields of an aanvraag or melding. The citizen portal and the
+//! internal intake both check drafts here instead of each keeping their own rules, and get
+//! the errors per field, in Dutch, to show next to the inputs. Besides the field type and
+//! its constraints, a field can carry a JSON Schema (`schema`, for custom fields); the check
+//! covers `type`, `enum`, `minimum`/`maximum`, `minLength`/`maxLength`, `pattern`,
+//! `required`, `properties` and `items`.
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::AppState;
+use crate::schemas::{Form, FormField, FormFieldType};
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct FormSubmission {
+    /// The values per field key
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub fields: Map<String, Value>,
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct FormValidation {
+    pub valid: bool,
+    /// Per field key; values without field are reported under their own key
+    pub errors: BTreeMap<String, Vec<String>>,
+}
+
+fn is_empty(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) => true,
+        Some(Value::String(s)) => s.trim().is_empty(),
+        _ => false,
+    }
+}
+
+fn is_email(s: &str) -> bool {
+    let Some((local, domain)) = s.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !s.contains(char::is_whitespace)
+        && domain
+            .split_once('.')
+            .is_some_and(|(name, tld)| !name.is_empty() && !tld.is_empty())
+}
+
+fn matches(pattern: &str, s: &str) -> Result<bool, String> {
+    regex::Regex::new(pattern)
+        .map(|re| re.is_match(s))
+        .map_err(|_| "Het formulier bevat een ongeldig patroon".to_string())
+}
+
+/// The errors of `value` against the JSON Schema `schema`, prefixed with `path` for nested
+/// values.
+pub fn schema_errors(schema: &Value, value: &Value, path: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    let at = |message: String| match path {
+        "" => message,
+        path => format!("{}: {}", path, message),
+    };
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        let is = |t: &str| match t {
+            "string" => value.is_string(),
+            "number" => value.is_number(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "boolean" => value.is_boolean(),
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "null" => value.is_null(),
+            _ => true,
+        };
+        if !types.is_empty() && !types.iter().any(|t| is(t)) {
+            errors.push(at(format!("Verwacht: {}", types.join(" of "))));
+            return errors;
+        }
+    }
+    if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+        if !options.contains(value) {
+            errors.push(at("Geen van de toegestane waarden".to_string()));
+        }
+    }
+    if let Some(n) = value.as_f64() {
+        if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+            if n < min {
+                errors.push(at(format!("Minimaal {}", min)));
+            }
+        }
+        if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+            if n > max {
+                errors.push(at(format!("Maximaal {}", max)));
+            }
+        }
+    }
+    if let Some(s) = value.as_str() {
+        let length = s.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+            if length < min {
+                errors.push(at(format!("Minimaal {} tekens", min)));
+            }
+        }
+        if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+            if length > max {
+                errors.push(at(format!("Maximaal {} tekens", max)));
+            }
+        }
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            match matches(pattern, s) {
+                Ok(true) => {}
+                Ok(false) => errors.push(at("Ongeldige invoer".to_string())),
+                Err(e) => errors.push(at(e)),
+            }
+        }
+    }
+    if let Some(object) = value.as_object() {
+        for key in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !object.contains_key(key) {
+                errors.push(at(format!("{} is verplicht", key)));
+            }
+        }
+        if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+            for (key, property) in properties {
+                if let Some(value) = object.get(key) {
+                    let path = match path {
+                        "" => key.clone(),
+                        path => format!("{}.{}", path, key),
+                    };
+                    errors.extend(schema_errors(property, value, &path));
+                }
+            }
+        }
+    }
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            let path = match path {
+                "" => i.to_string(),
+                path => format!("{}.{}", path, i),
+            };
+            errors.extend(schema_errors(items, item, &path));
+        }
+    }
+    errors
+}
+
+/// The errors of one field's `value` (`None`: not filled in).
+pub fn field_errors(field: &FormField, value: Option<&Value>) -> Vec<String> {
+    let Some(value) = value.filter(|_| !is_empty(value)) else {
+        return match field.required {
+            true => vec!["Dit veld is verplicht".to_string()],
+            false => vec![],
+        };
+    };
+    let mut errors = Vec::new();
+    match field.field_type {
+        FormFieldType::Text => match value.as_str() {
+            Some(s) => {
+                if let Some(max) = field.max_length {
+                    if s.chars().count() > max {
+                        errors.push(format!("Maximaal {} tekens", max));
+                    }
+                }
+                if let Some(pattern) = &field.pattern {
+                    match matches(&format!("^(?:{})$", pattern), s) {
+                        Ok(true) => {}
+                        Ok(false) => errors.push("Ongeldige invoer".to_string()),
+                        Err(e) => errors.push(e),
+                    }
+                }
+            }
+            None => errors.push("Verwacht: tekst".to_string()),
+        },
+        FormFieldType::Number => match value.as_f64() {
+            Some(n) => {
+                if let Some(min) = field.min.filter(|min| n < *min) {
+                    errors.push(format!("Minimaal {}", min));
+                }
+                if let Some(max) = field.max.filter(|max| n > *max) {
+                    errors.push(format!("Maximaal {}", max));
+                }
+            }
+            None => errors.push("Verwacht: een getal".to_string()),
+        },
+        FormFieldType::Date => {
+            if value
+                .as_str()
+                .and_then(|s| chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").ok())
+                .is_none()
+            {
+                errors.push("Verwacht: een datum (JJJJ-MM-DD)".to_string());
+            }
+        }
+        FormFieldType::Email => {
+            if !value.as_str().is_some_and(is_email) {
+                errors.push("Verwacht: een e-mailadres".to_string());
+            }
+        }
+        FormFieldType::Boolean => {
+            if !value.is_boolean() {
+                errors.push("Verwacht: ja of nee".to_string());
+            }
+        }
+        FormFieldType::Choice => {
+            let options = field.options.as_deref().unwrap_or_default();
+            if !value
+                .as_str()
+                .is_some_and(|s| options.iter().any(|o| o == s))
+            {
+                errors.push(format!("Kies uit: {}", options.join(", ")));
+            }
+        }
+        FormFieldType::Custom => {}
+    }
+    if let Some(schema) = &field.schema {
+        errors.extend(schema_errors(schema, value, ""));
+    }
+    errors
+}
+
+/// Check `submission` against `form`.
+pub fn validate(form: &Form, submission: &FormSubmission) -> FormValidation {
+    let mut errors = BTreeMap::new();
+    for field in &form.fields {
+        let field_errors = field_errors(field, submission.fields.get(&field.key));
+        if !field_errors.is_empty() {
+            errors.insert(field.key.clone(), field_errors);
+        }
+    }
+    for key in submission.fields.keys() {
+        if !form.fields.iter().any(|field| &field.key == key) {
+            errors.insert(key.clone(), vec!["Onbekend veld".to_string()]);
+        }
+    }
+    FormValidation {
+        valid: errors.is_empty(),
+        errors,
+    }
+}
+
+/// POST /forms/{id}/validate - Check a draft submission against a form
+#[utoipa::path(
+    post,
+    path = "/forms/{id}/validate",
+    tag = "resources",
+    params(("id" = String, Path, description = "Form ID")),
+    request_body = FormSubmission,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Whether the draft is valid, with the errors per field", body = FormValidation),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "Unknown form"),
+        (status = 422, description = "The stored form is invalid"),
+    )
+)]
+pub async fn validate_form_handler(
+    State(state): State<AppState>,
+    _auth_user: AuthUser,
+    Path(id): Path<String>,
+    Json(submission): Json<FormSubmission>,
+) -> Result<Json<FormValidation>, StatusCode> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("[forms] failed to read form {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    if state
+        .storage
+        .get_resource_type(&id)
+        .await
+        .map_err(internal)?
+        .as_deref()
+        != Some("Form")
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let data = state
+        .storage
+        .get_resource(&id)
+        .await
+        .map_err(internal)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let form: Form = serde_json::from_value(data).map_err(|e| {
+        eprintln!("[forms] form {} is invalid: {}", id, e);
+        StatusCode::UNPROCESSABLE_ENTITY
+    })?;
+    Ok(Json(validate(&form, &submission)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, submit_commit_event, test_state};
+    use crate::schemas::CommitBuilder;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_validate_form() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let form: Form = serde_json::from_value(json!({
+            "title": "Melding evenement",
+            "fields": [
+                {"key": "naam", "label": "Naam evenement", "type": "text", "required": true, "max_length": 20},
+                {"key": "postcode", "label": "Postcode", "type": "text", "pattern": "[0-9]{4} ?[A-Z]{2}"},
+                {"key": "datum", "label": "Datum", "type": "date", "required": true},
+                {"key": "bezoekers", "label": "Bezoekers", "type": "number", "min": 1, "max": 5000},
+                {"key": "email", "label": "E-mail", "type": "email"},
+                {"key": "soort", "label": "Soort", "type": "choice", "options": ["markt", "concert"]},
+                {"key": "locatie", "label": "Locatie", "type": "custom", "schema": {
+                    "type": "object",
+                    "required": ["straat"],
+                    "properties": {"huisnummer": {"type": "integer", "minimum": 1}},
+                }},
+            ],
+        }))
+        .unwrap();
+        let commit = CommitBuilder::create("form-evenement", &form).build();
+        submit_commit_event(&state, "form-evenement", &commit)
+            .await
+            .unwrap();
+        let check = |fields: Value| {
+            let state = state.clone();
+            async move {
+                validate_form_handler(
+                    State(state),
+                    user("jan@example.com"),
+                    Path("form-evenement".to_string()),
+                    Json(serde_json::from_value(json!({ "fields": fields })).unwrap()),
+                )
+                .await
+            }
+        };
+
+        let Json(result) = check(json!({
+            "naam": "Koningsmarkt",
+            "postcode": "1234 AB",
+            "datum": "2024-04-27",
+            "bezoekers": 300,
+            "email": "jan@example.com",
+            "soort": "markt",
+            "locatie": {"straat": "Markt", "huisnummer": 1},
+        }))
+        .await
+        .unwrap();
+        assert_eq!(result.errors, BTreeMap::new());
+        assert!(result.valid);
+
+        let Json(result) = check(json!({
+            "naam": "",
+            "postcode": "1234",
+            "datum": "27-04-2024",
+            "bezoekers": 0,
+            "email": "jan",
+            "soort": "circus",
+            "locatie": {"huisnummer": 0},
+            "kleur": "rood",
+        }))
+        .await
+        .unwrap();
+        assert!(!result.valid);
+        let errors = |key: &str| result.errors[key].clone();
+        assert_eq!(errors("naam"), vec!["Dit veld is verplicht"]);
+        assert_eq!(errors("postcode"), vec!["Ongeldige invoer"]);
+        assert_eq!(errors("datum"), vec!["Verwacht: een datum (JJJJ-MM-DD)"]);
+        assert_eq!(errors("bezoekers"), vec!["Minimaal 1"]);
+        assert_eq!(errors("email"), vec!["Verwacht: een e-mailadres"]);
+        assert_eq!(errors("soort"), vec!["Kies uit: markt, concert"]);
+        assert_eq!(
+            errors("locatie"),
+            vec!["straat is verplicht", "huisnummer: Minimaal 1"]
+        );
+        assert_eq!(errors("kleur"), vec!["Onbekend veld"]);
+
+        assert_eq!(
+            validate_form_handler(
+                State(state.clone()),
+                user("jan@example.com"),
+                Path("form-onbekend".to_string()),
+                Json(FormSubmission::default()),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
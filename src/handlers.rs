@@ -1,6 +1,8 @@
 //! HTTP handlers for /events, /resources, and /query endpoints
 
+use crate::delivery::{DeliveryChannel, DeliveryOutcome, DeliveryStatus};
 use crate::email::EmailService;
+use crate::encoding::{self, JsonOrCbor};
 use axum::{
     extract::{Path, Query, State},
     http::{HeaderMap, StatusCode},
@@ -9,16 +11,18 @@ use axum::{
     Json,
 };
 use dashmap::DashMap;
-use futures_util::stream;
+use futures_util::stream::{self, Stream};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::convert::Infallible;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
 use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::StreamExt;
+use utoipa::{IntoParams, ToSchema};
 
-use crate::schemas::{CloudEvent, JSONCommit};
+use crate::schemas::{CloudEvent, CloudEventBuilder, Comment, CommitBuilder, JSONCommit};
 use crate::storage::{SearchResult, Storage};
 use crate::types::PushSubscription;
 
@@ -40,6 +44,20 @@ pub struct AppState {
     pub email_service: Arc<EmailService>,
     /// Track active users for smart notification suppression
     pub active_users: Arc<DashMap<String, Instant>>,
+    /// Who has which issue open (see `live::PresenceTracker`)
+    pub presence: Arc<crate::live::PresenceTracker>,
+    /// Rate limits of anonymous status lookups (see `status`)
+    pub status_lookups: Arc<crate::status::StatusLookupLimiter>,
+    /// The processors every submitted event goes through
+    pub pipeline: Arc<crate::pipeline::Pipeline>,
+    /// Named projections, kept up to date by the pipeline
+    pub projections: Arc<crate::projections::Projections>,
+    /// State of the search index (see `integrity`)
+    pub index_health: Arc<crate::integrity::IndexHealth>,
+    /// Uploads being appended to (see `uploads`)
+    pub uploads: Arc<crate::uploads::UploadLocks>,
+    /// Rendered AsyncAPI specs (see `asyncapi`)
+    pub asyncapi: Arc<crate::asyncapi::SpecCache>,
 }
 
 /// Convenience constructor for handlers to create an AppState when needed.
@@ -57,25 +75,39 @@ impl AppState {
             push_subscriptions: Arc::new(tokio::sync::RwLock::new(Vec::new())),
             email_service,
             active_users: Arc::new(DashMap::new()),
+            presence: Arc::new(Default::default()),
+            status_lookups: Arc::new(Default::default()),
+            pipeline: Arc::new(Default::default()),
+            projections: Arc::new(Default::default()),
+            index_health: Arc::new(Default::default()),
+            uploads: Arc::new(Default::default()),
+            asyncapi: Arc::new(Default::default()),
         }
     }
 }
 
 /// Response for resource retrieval
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct ResourceResponse {
     pub id: String,
     pub resource_type: String,
     pub data: Value,
+    /// For issues the caller is involved in: number of events since their last visit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unread: Option<usize>,
 }
 
 /// Query parameters for listing resources
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ListParams {
     #[serde(default = "default_offset")]
     pub offset: usize,
     #[serde(default = "default_limit")]
     pub limit: usize,
+    /// Only resources tagged with this label name
+    #[serde(default)]
+    pub tag: Option<String>,
 }
 
 fn default_offset() -> usize {
@@ -87,19 +119,24 @@ fn default_limit() -> usize {
 }
 
 /// Query parameters for search
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct QueryParams {
+    /// Tantivy query string (e.g. "title:paspoort")
     pub q: String,
 
     #[serde(default = "default_limit")]
     pub limit: usize,
     /// Optional user identifier to scope the search (e.g. "alice@gemeente.nl")
     pub user: Option<String>,
+    /// Only results tagged with this label name
+    #[serde(default)]
+    pub tag: Option<String>,
 }
 
 /// Query parameters for listing events (used for JSON listing or snapshot pagination)
-/// Query parameters for listing events (used for JSON listing or snapshot pagination)
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct EventsListParams {
     #[serde(default = "default_offset")]
     pub offset: usize,
@@ -118,6 +155,44 @@ pub struct EventsListParams {
     /// Optional JWT token for authentication (passed in query for SSE)
     #[serde(default)]
     pub token: Option<String>,
+    /// Optional comma-separated list of event types to deliver
+    /// (e.g. "json.commit,nl.vng.zaken.status-change.v1"). Other types are skipped.
+    #[serde(default)]
+    pub types: Option<String>,
+}
+
+/// Query parameters for the legacy `/events/stream` endpoint
+#[derive(Debug, Default, Deserialize)]
+pub struct StreamParams {
+    /// Optional comma-separated list of event types to deliver
+    #[serde(default)]
+    pub types: Option<String>,
+}
+
+/// Parse a comma-separated `types` filter. `None` means every event type is accepted.
+pub fn parse_event_types(types: Option<&str>) -> Option<std::collections::HashSet<String>> {
+    let set: std::collections::HashSet<String> = types?
+        .split(',')
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .map(String::from)
+        .collect();
+    if set.is_empty() {
+        None
+    } else {
+        Some(set)
+    }
+}
+
+/// Check an event against an optional type filter from `parse_event_types`.
+pub fn event_type_allowed(
+    filter: &Option<std::collections::HashSet<String>>,
+    event: &CloudEvent,
+) -> bool {
+    filter
+        .as_ref()
+        .map(|types| types.contains(&event.event_type))
+        .unwrap_or(true)
 }
 
 /// Helper to get all topics (issue IDs) a user has access to using Tantivy search.
@@ -171,8 +246,35 @@ async fn get_authorized_topics(
     Ok(topic_set)
 }
 
+/// 404 for unknown issues, 403 if `user_id` has no access to the issue.
+pub(crate) async fn authorize_issue(
+    state: &AppState,
+    user_id: &str,
+    issue_id: &str,
+) -> Result<(), StatusCode> {
+    state
+        .storage
+        .get_resource(issue_id)
+        .await
+        .map_err(|e| {
+            eprintln!("[auth] failed to load {}: {}", issue_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if !check_access(&state.storage, user_id, issue_id).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+/// Whether `user_id` gets `event` in the change feeds: events about issues they can access,
+/// and resets.
+pub(crate) async fn may_see_event(storage: &Storage, user_id: &str, event: &CloudEvent) -> bool {
+    event.event_type == "system.reset" || check_access(storage, user_id, &event.subject).await
+}
+
 /// Helper to check if a user has access to a resource (and thus its events)
-async fn check_access(storage: &Storage, user_id: &str, resource_id: &str) -> bool {
+pub(crate) async fn check_access(storage: &Storage, user_id: &str, resource_id: &str) -> bool {
     // 1. Try to fetch the resource
     let resource = match storage.get_resource(resource_id).await {
         Ok(Some(r)) => r,
@@ -192,6 +294,12 @@ async fn check_access(storage: &Storage, user_id: &str, resource_id: &str) -> bo
                 return true;
             }
         }
+        // Members of the team the issue is assigned to have access too
+        if let Some(team) = resource.get("team").and_then(|v| v.as_str()) {
+            if crate::teams::is_member(storage, team, user_id).await {
+                return true;
+            }
+        }
         eprintln!(
             "[auth] Access denied for user {} to resource {}. Involved: {:?}",
             user_id, resource_id, involved
@@ -205,6 +313,55 @@ async fn check_access(storage: &Storage, user_id: &str, resource_id: &str) -> bo
         return Box::pin(check_access(storage, user_id, quote_id)).await;
     }
 
+    // A kanban board is shared by its tenant or team
+    if let (Some(team), Some(_)) = (
+        resource.get("team").and_then(|v| v.as_str()),
+        resource.get("columns"),
+    ) {
+        return crate::auth::tenant_of(user_id) == team
+            || crate::teams::is_member(storage, team, user_id).await;
+    }
+
+    // Profiles are visible within the organisation
+    if let (Some(email), Some(_)) = (
+        resource.get("email").and_then(|v| v.as_str()),
+        resource.get("display_name"),
+    ) {
+        return crate::auth::tenant_of(user_id) == crate::auth::tenant_of(email);
+    }
+
+    // So is whether someone is away
+    if let (Some(user), Some(_)) = (
+        resource.get("user").and_then(|v| v.as_str()),
+        resource.get("away"),
+    ) {
+        return crate::auth::tenant_of(user_id) == crate::auth::tenant_of(user);
+    }
+
+    // Connectors are shared within their tenant
+    if let (Some(tenant), Some(_)) = (
+        resource.get("tenant").and_then(|v| v.as_str()),
+        resource.get("webhook_url"),
+    ) {
+        return crate::auth::tenant_of(user_id) == tenant;
+    }
+
+    // Invites too
+    if let (Some(issue_id), Some(_)) = (
+        resource.get("issue_id").and_then(|v| v.as_str()),
+        resource.get("invited_by"),
+    ) {
+        return Box::pin(check_access(storage, user_id, issue_id)).await;
+    }
+
+    // Escalations are visible to whoever can see the issue
+    if let (Some(issue_id), Some(_)) = (
+        resource.get("issue_id").and_then(|v| v.as_str()),
+        resource.get("idle_hours"),
+    ) {
+        return Box::pin(check_access(storage, user_id, issue_id)).await;
+    }
+
     // For other types (Task, Planning, Document), we need to know their parent.
     // If they don't have a parent link in the JSON, we can't authorize them based on Issue.
     // Current schema for Task/Planning/Document doesn't show a parent_id.
@@ -216,21 +373,154 @@ async fn check_access(storage: &Storage, user_id: &str, resource_id: &str) -> bo
     false
 }
 
+/// Page size used when catching a lagging subscriber up from storage.
+pub(crate) const CATCHUP_PAGE_SIZE: usize = 500;
+
+/// Live event stream that survives broadcast lag.
+///
+/// Wraps the broadcast receiver and tracks the sequence of the last event delivered.
+/// When the receiver reports `Lagged`, the missed range is read back from storage
+/// (everything after the last delivered sequence) before resuming the live feed.
+/// Broadcast events at or before the last delivered sequence are dropped, which also
+/// removes duplicates between the initial snapshot and the first live events.
+pub fn live_events_with_catchup(
+    storage: Arc<Storage>,
+    rx: tokio::sync::broadcast::Receiver<CloudEvent>,
+    mut last_seq: Option<String>,
+) -> impl Stream<Item = CloudEvent> {
+    async_stream::stream! {
+        let mut live = BroadcastStream::new(rx);
+        while let Some(msg) = live.next().await {
+            match msg {
+                Ok(event) => {
+                    if let (Some(seq), Some(last)) = (event.sequence.as_deref(), last_seq.as_deref()) {
+                        if seq <= last {
+                            continue;
+                        }
+                    }
+                    if event.sequence.is_some() {
+                        last_seq = event.sequence.clone();
+                    }
+                    yield event;
+                }
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                    eprintln!(
+                        "[sse] subscriber lagged by {} events, catching up from storage after {:?}",
+                        skipped, last_seq
+                    );
+                    loop {
+                        let page = match storage
+                            .list_events_after(last_seq.clone(), CATCHUP_PAGE_SIZE)
+                            .await
+                        {
+                            Ok(page) => page,
+                            Err(e) => {
+                                eprintln!("[sse] catch-up from storage failed: {}", e);
+                                break;
+                            }
+                        };
+                        let done = page.len() < CATCHUP_PAGE_SIZE;
+                        for event in page {
+                            if event.sequence.is_some() {
+                                last_seq = event.sequence.clone();
+                            }
+                            yield event;
+                        }
+                        if done {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Default interval between `checkpoint` events on SSE streams.
+const DEFAULT_CHECKPOINT_INTERVAL_SECS: u64 = 30;
+
+/// Interval between `checkpoint` events, configurable via `SSE_CHECKPOINT_INTERVAL_SECS`.
+pub fn checkpoint_interval() -> Duration {
+    let secs = std::env::var("SSE_CHECKPOINT_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_CHECKPOINT_INTERVAL_SECS);
+    Duration::from_secs(secs)
+}
+
+/// The last sequence an SSE stream has considered: delivered, or skipped by its filters.
+#[derive(Debug, Clone, Default)]
+pub struct StreamCursor(Arc<std::sync::Mutex<Option<String>>>);
+
+impl StreamCursor {
+    /// A cursor at `sequence`, where the stream's snapshot ended (or the client resumed).
+    pub fn new(sequence: Option<String>) -> Self {
+        StreamCursor(Arc::new(std::sync::Mutex::new(sequence)))
+    }
+
+    /// Move the cursor to `event`, if it is further along.
+    pub fn advance(&self, event: &CloudEvent) {
+        let Some(sequence) = &event.sequence else {
+            return;
+        };
+        let mut cursor = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if cursor.as_ref().is_none_or(|current| sequence > current) {
+            *cursor = Some(sequence.clone());
+        }
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Periodic `checkpoint` SSE events carrying the stream's `cursor`.
+///
+/// Everything up to that sequence that the client may see has been sent to it, so it can
+/// resume from there with `?after_seq=...`. Clients whose last received sequence is further
+/// behind than they expect can catch up via `?format=json&after_seq=...`.
+pub fn checkpoint_events(cursor: StreamCursor, every: Duration) -> impl Stream<Item = Event> {
+    async_stream::stream! {
+        let mut ticker = tokio::time::interval(every);
+        // The first tick completes immediately; the snapshot already covers that moment.
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let data = serde_json::json!({ "sequence": cursor.get() }).to_string();
+            yield Event::default().event("checkpoint").data(data);
+        }
+    }
+}
+
 /// GET /events - Returns an SSE stream by default. If the query `?format=json` is present,
 /// the handler will return a JSON list instead (keeps frontend compatibility: SSE is default).
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    params(EventsListParams),
+    security(("query_token" = [])),
+    responses(
+        (status = 200, description = "SSE stream (`snapshot`, `delta`, `ephemeral` and `checkpoint` events), or a JSON list of events with `?format=json`", body = [CloudEvent]),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
 pub async fn get_or_stream_events(
     State(state): State<AppState>,
-    _headers: HeaderMap,
+    headers: HeaderMap,
     Query(params): Query<EventsListParams>,
 ) -> Result<Response, StatusCode> {
     // 1. Authenticate
     let token = params.token.as_deref().ok_or(StatusCode::UNAUTHORIZED)?;
-    let claims = crate::auth::verify_jwt(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let claims = crate::auth::verify_session_jwt(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
     let user_id = claims.sub;
 
     // Update active status
     state.active_users.insert(user_id.clone(), Instant::now());
 
+    let type_filter = parse_event_types(params.types.as_deref());
+
     // Only return JSON when explicitly requested via query param `?format=json`.
     let want_json = params
         .format
@@ -252,6 +542,10 @@ pub async fn get_or_stream_events(
         // Filter events by topic AND authorization
         let mut filtered = Vec::new();
         for event in events {
+            if !event_type_allowed(&type_filter, &event) {
+                continue;
+            }
+
             // Topic filter
             if let Some(topic) = params.topic.as_deref() {
                 let matches = event.subject.contains(topic) || event.event_type.contains(topic);
@@ -267,7 +561,12 @@ pub async fn get_or_stream_events(
             }
         }
 
-        return Ok(Json(filtered).into_response());
+        return Ok(encoding::negotiated(
+            &headers,
+            StatusCode::OK,
+            &filtered,
+            encoding::CLOUDEVENTS_BATCH_CBOR,
+        ));
     }
 
     // Default: return SSE stream (snapshot followed by deltas)
@@ -290,9 +589,12 @@ pub async fn get_or_stream_events(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
+    let snapshot_last_seq = snapshot_events.last().and_then(|e| e.sequence.clone());
+
     // Filter snapshot events using in-memory HashSet lookup (very fast!)
     let authorized_snapshot: Vec<_> = snapshot_events
         .into_iter()
+        .filter(|event| event_type_allowed(&type_filter, event))
         .filter(|event| {
             authorized_topics.contains(&event.subject) || event.event_type == "system.reset"
         })
@@ -300,12 +602,21 @@ pub async fn get_or_stream_events(
 
     let snapshot = serde_json::to_string(&authorized_snapshot).unwrap_or_else(|_| "[]".to_string());
 
+    // Resume point for lag recovery: the last sequence covered by the snapshot
+    let last_seq = snapshot_last_seq.or(params.after_seq.clone());
+    let cursor = StreamCursor::new(last_seq.clone());
+    let checkpoints = checkpoint_events(cursor.clone(), checkpoint_interval());
+    let live = live_events_with_catchup(state.storage.clone(), rx, last_seq).map(move |event| {
+        cursor.advance(&event);
+        event
+    });
+
     let stream = stream::once(async move {
         Ok::<Event, Infallible>(Event::default().event("snapshot").data(snapshot))
     })
     .chain(
-        BroadcastStream::new(rx)
-            .then(move |msg| {
+        live.filter(move |event| event_type_allowed(&type_filter, event))
+            .then(move |event| {
                 let state_clone = state.clone();
                 let user_id_clone = user_id.clone();
                 let authorized_topics = authorized_topics.clone();
@@ -315,35 +626,34 @@ pub async fn get_or_stream_events(
                         .active_users
                         .insert(user_id_clone.clone(), Instant::now());
 
-                    match msg {
-                        Ok(event) => {
-                            // Check authorization
-                            // Optimization: use the static set first
-                            if authorized_topics.contains(&event.subject)
-                                || event.event_type == "system.reset"
-                            {
-                                return Some(event);
-                            }
+                    // Check authorization
+                    // Optimization: use the static set first
+                    if authorized_topics.contains(&event.subject)
+                        || event.event_type == "system.reset"
+                    {
+                        return Some(event);
+                    }
 
-                            // Dynamic check for new issues or updated access
-                            if check_access(&state_clone.storage, &user_id_clone, &event.subject)
-                                .await
-                            {
-                                // Note: We can't easily update authorized_topics here as it's a cloned HashSet
-                                // in a stream. But check_access is fast enough for the delta stream.
-                                return Some(event);
-                            }
-                            None
-                        }
-                        Err(_) => None,
+                    // Dynamic check for new issues or updated access
+                    if check_access(&state_clone.storage, &user_id_clone, &event.subject).await {
+                        // Note: We can't easily update authorized_topics here as it's a cloned HashSet
+                        // in a stream. But check_access is fast enough for the delta stream.
+                        return Some(event);
                     }
+                    None
                 }
             })
             .filter_map(|opt| opt)
             .map(|event| {
                 let json = serde_json::to_string(&event).unwrap_or_else(|_| "{}".to_string());
-                Ok(Event::default().event("delta").data(json))
-            }),
+                let name = if crate::live::is_ephemeral(&event) {
+                    "ephemeral"
+                } else {
+                    "delta"
+                };
+                Ok(Event::default().event(name).data(json))
+            })
+            .merge(checkpoints.map(Ok)),
     );
 
     let sse = Sse::new(stream).keep_alive(KeepAlive::default());
@@ -351,7 +661,7 @@ pub async fn get_or_stream_events(
 }
 
 /// Response for query endpoint
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct QueryResponse {
     pub query: String,
     pub results: Vec<SearchResult>,
@@ -359,78 +669,291 @@ pub struct QueryResponse {
 }
 
 /// Error response type
-#[derive(Debug, Serialize)]
-#[allow(dead_code)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ErrorResponse {
     pub error: String,
 }
 
+/// Log an unexpected (storage) error and answer 500: `.map_err(internal_error)`
+pub(crate) fn internal_error(e: impl std::fmt::Display) -> StatusCode {
+    eprintln!("[server] internal error: {}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
 /// POST /events - Handle incoming CloudEvents (Command + Sync)
-/// This is where resources are created, updated, and deleted
+/// This is where resources are created, updated, and deleted.
+/// Accepts JSON or CBOR (`Content-Type: application/cbor`) bodies.
+#[utoipa::path(
+    post,
+    path = "/events",
+    tag = "events",
+    request_body(content = CloudEvent, description = "CloudEvent, usually carrying a JSONCommit in `data`"),
+    responses(
+        (status = 202, description = "Event stored, processed and broadcast (with its assigned sequence)", body = CloudEvent),
+        (status = 400, description = "The event carries an invalid commit, or is refused by strict ingestion", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The caller has no access to the event's issue or the resource it changes, or the commit names someone else as actor"),
+        (status = 409, description = "The commit's `base_sequence` is stale and it conflicts with newer changes, its JSON Patch no longer applies after a concurrent change, or an event with the same source and id is already stored", body = crate::conflicts::MergeConflict),
+        (status = 413, description = "The document would exceed a storage quota", body = crate::quotas::QuotaExceeded),
+        (status = 429, description = "The submitter or their tenant is at an event or resource quota", body = crate::quotas::QuotaExceeded),
+        (status = 500, description = "Event could not be stored or processed"),
+    )
+)]
 pub async fn handle_event(
     State(state): State<AppState>,
-    Json(mut event): Json<CloudEvent>,
+    auth_user: Option<AuthUser>,
+    headers: HeaderMap,
+    JsonOrCbor(event): JsonOrCbor<CloudEvent>,
 ) -> Result<Response, StatusCode> {
-    // Store the event and get the assigned server sequence key
-    let seq_key = state.storage.store_event(&event).await.map_err(|e| {
-        eprintln!("Failed to store event: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+    let ctx = crate::pipeline::EventContext::new(event).inbound(auth_user.map(|u| u.user_id));
+    let event = match state.pipeline.submit(&state, ctx).await {
+        Ok(event) => event,
+        Err(crate::pipeline::ProcessError::Conflict(conflict)) => {
+            eprintln!(
+                "[events] event not accepted: stale commit to {}",
+                conflict.resource_id
+            );
+            return Ok(encoding::negotiated(
+                &headers,
+                StatusCode::CONFLICT,
+                &conflict,
+                encoding::CBOR,
+            ));
+        }
+        Err(crate::pipeline::ProcessError::Stale(reason)) => {
+            eprintln!("[events] event not accepted: stale commit: {}", reason);
+            return Ok(encoding::negotiated(
+                &headers,
+                StatusCode::CONFLICT,
+                &ErrorResponse { error: reason },
+                encoding::CBOR,
+            ));
+        }
+        Err(crate::pipeline::ProcessError::QuotaExceeded(exceeded)) => {
+            eprintln!("[events] event not accepted: {}", exceeded);
+            return Ok(encoding::negotiated(
+                &headers,
+                exceeded.status(),
+                &exceeded,
+                encoding::CBOR,
+            ));
+        }
+        Err(crate::pipeline::ProcessError::Invalid(reason)) => {
+            eprintln!("[events] event not accepted: {}", reason);
+            return Ok(encoding::negotiated(
+                &headers,
+                StatusCode::BAD_REQUEST,
+                &ErrorResponse { error: reason },
+                encoding::CBOR,
+            ));
+        }
+        Err(e) => {
+            eprintln!("[events] event not accepted: {}", e);
+            return Err(e.status());
+        }
+    };
+
+    Ok(encoding::negotiated(
+        &headers,
+        StatusCode::ACCEPTED,
+        &event,
+        encoding::CLOUDEVENTS_CBOR,
+    ))
+}
+
+/// Routing of an inbound event: flag possible duplicates of new issues, auto-assign them
+/// and reroute assignments to away behandelaars. Failures are logged, as the event itself
+/// is already stored.
+pub(crate) async fn apply_routing_rules(state: &AppState, event: &CloudEvent) {
+    if let Err(e) = crate::duplicates::flag_possible_duplicates(state, event).await {
+        eprintln!(
+            "[duplicates] failed to check {} for duplicates: {}",
+            event.id, e
+        );
+    }
+    if let Err(e) = crate::assignment::auto_assign(state, event).await {
+        eprintln!("[assignment] failed to auto-assign {}: {}", event.id, e);
+    }
+    if let Err(e) = crate::availability::reroute_assignment(state, event).await {
+        eprintln!(
+            "[availability] failed to reroute assignment of {}: {}",
+            event.id, e
+        );
+    }
+}
+
+/// Submit `commit` as a `json.commit` event on `subject` and answer with the stored event
+/// (202), for endpoints that make commits on the caller's behalf. The commit's actor is the
+/// caller: it is authorized and charged as if it came in at `POST /events`.
+pub(crate) async fn submit_commit(
+    state: &AppState,
+    headers: &HeaderMap,
+    subject: &str,
+    commit: JSONCommit,
+) -> Result<Response, StatusCode> {
+    let actor = commit.actor.clone();
+    let event = CloudEventBuilder::commit(subject, &commit).build();
+    let ctx = crate::pipeline::EventContext::new(event).inbound(Some(actor));
+    let event = state.pipeline.submit(state, ctx).await.map_err(|e| {
+        eprintln!("[commits] commit to {} not accepted: {}", subject, e);
+        e.status()
     })?;
 
-    // Attach the assigned sequence to the CloudEvent so clients can use it for ordering/pagination
-    event.sequence = Some(seq_key.clone());
+    Ok(encoding::negotiated(
+        headers,
+        StatusCode::ACCEPTED,
+        &event,
+        encoding::CLOUDEVENTS_CBOR,
+    ))
+}
 
-    // Schedule background indexing of the event (search subsystem).
-    // Serialize once and pass the payload string to avoid cloning the entire CloudEvent.
-    // Index the event synchronously
-    // Schedule background indexing of the event (search subsystem).
-    // Serialize once and pass the payload string to avoid cloning the entire CloudEvent.
-    // Index the event synchronously
-    {
-        let search = state.search.clone();
-        // Serialize CloudEvent once (no snippet content to avoid extra allocations)
-        let payload = serde_json::to_string(&event).unwrap_or_default();
-        let id = event.id.clone();
+/// Submit an event through the processing pipeline (see `pipeline`): validate, store,
+/// process, index and broadcast it. Used by endpoints that submit commits on a user's
+/// behalf (e.g. revert) and by background tasks; workflow rules are not applied.
+/// Returns the event with its assigned sequence.
+pub async fn submit_event(
+    state: &AppState,
+    event: CloudEvent,
+) -> Result<CloudEvent, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(state
+        .pipeline
+        .submit(state, crate::pipeline::EventContext::new(event))
+        .await?)
+}
 
-        // Architecture Decision: All CloudEvents are indexed with doc_type="Event".
-        // This allows searching the audit history via is:Event.
-        // Specific event types (e.g. json.commit) are properties of the event payload.
-        let doc_type = "Event".to_string();
+/// POST /events/{id}/revert - Undo a commit by submitting its inverse as a new commit.
+///
+/// Fields changed by the commit are patched back to their previous values (later edits to
+/// other fields are kept); a created resource is deleted and a deleted one is recreated.
+/// The new commit is attributed to the reverting user.
+#[utoipa::path(
+    post,
+    path = "/events/{id}/revert",
+    tag = "events",
+    params(("id" = String, Path, description = "Id of the CloudEvent to revert")),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Inverse commit stored, processed and broadcast", body = CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the event's issue"),
+        (status = 404, description = "Unknown event"),
+        (status = 422, description = "Event is not a commit, or there is nothing to revert"),
+    )
+)]
+pub async fn revert_event_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, StatusCode> {
+    let event = state
+        .storage
+        .get_event(&id)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to get event: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
 
-        // Do not parse timestamp here; pass None to the search indexer (it can set now)
+    let commit = commit_of(&event).ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    let seq = event
+        .sequence
+        .clone()
+        .ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
 
-        if let Err(e) = search
-            .add_event_payload(&id, &doc_type, "", &payload, None)
-            .await
-        {
-            eprintln!(
-                "[handlers] failed adding event payload to search index id={} err={}",
-                id, e
-            );
-        }
-    }
+    let (before, after) = resource_state_around(&state.storage, &commit.resource_id, &seq)
+        .await
+        .map_err(|e| {
+            eprintln!("[revert] failed to replay {}: {}", commit.resource_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
-    // Process the event to update resources
-    if let Err(e) = process_event(&state, &event).await {
-        eprintln!("Failed to process event: {}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    // The issue may itself be the deleted resource being restored, so fall back to the
+    // involved list of its previous version.
+    let user = &auth_user.user_id;
+    let involved_before = before
+        .as_ref()
+        .and_then(|b| b.get("involved"))
+        .and_then(|v| v.as_array())
+        .is_some_and(|involved| involved.iter().any(|p| p.as_str() == Some(user)));
+    if !check_access(&state.storage, user, &event.subject).await && !involved_before {
+        return Err(StatusCode::FORBIDDEN);
     }
 
-    // Force a commit to ensure the event is searchable immediately
-    // This is critical for the "create then view" flow where the user expects
-    // the new item to be available in the snapshot immediately.
-    if let Err(e) = state.search.commit().await {
-        eprintln!("[handlers] failed to commit search index: {}", e);
-    }
+    let mut inverse = invert_commit(&commit, before.as_ref(), after.as_ref())
+        .ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    inverse.actor = user.clone();
+
+    let revert = CloudEventBuilder::commit(&event.subject, &inverse)
+        .source(event.source.clone())
+        .build();
+    let revert = submit_event(&state, revert)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    println!("[revert] {} reverted event {} as {}", user, id, revert.id);
 
-    // Broadcast the event (with attached sequence) to SSE subscribers
-    let _ = state.tx.send(event.clone());
+    Ok(encoding::negotiated(
+        &headers,
+        StatusCode::ACCEPTED,
+        &revert,
+        encoding::CLOUDEVENTS_CBOR,
+    ))
+}
 
-    Ok((StatusCode::ACCEPTED, Json(event)).into_response())
+/// Completeness of the event log, from `GET /events/gaps`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EventGapsReport {
+    /// Latest assigned sequence number, if any event was stored
+    pub latest_sequence: Option<u64>,
+    /// True when every sequence up to `latest_sequence` has a stored event
+    pub complete: bool,
+    /// Number of missing sequences
+    pub missing: u64,
+    pub gaps: Vec<crate::storage::SequenceGap>,
+}
+
+/// GET /events/gaps - Sequence numbers without a stored event
+///
+/// A partial import or a restore that lost part of the log leaves a gap. Consumers replaying
+/// `/events` can use this to tell a gap in the log from an event they missed.
+#[utoipa::path(
+    get,
+    path = "/events/gaps",
+    tag = "events",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Missing sequence ranges (inclusive)", body = EventGapsReport),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn event_gaps_handler(
+    State(state): State<AppState>,
+    _auth_user: AuthUser,
+) -> Result<Json<EventGapsReport>, StatusCode> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("[events] failed to detect sequence gaps: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let latest_sequence = state
+        .storage
+        .latest_sequence()
+        .await
+        .map_err(internal)?
+        .and_then(|key| key.parse().ok());
+    let gaps = state.storage.sequence_gaps().await.map_err(internal)?;
+    let missing = gaps.iter().map(|gap| gap.to - gap.from + 1).sum();
+    Ok(Json(EventGapsReport {
+        latest_sequence,
+        complete: gaps.is_empty(),
+        missing,
+        gaps,
+    }))
 }
 
 /// Helper to send notifications for new comments/issues
-async fn send_notifications_for_event(
+pub(crate) async fn send_notifications_for_event(
     state: &AppState,
     event: &CloudEvent,
     resource: &Value,
@@ -495,12 +1018,22 @@ async fn send_notifications_for_event(
                 }
             }
 
-            if recipients.is_empty() {
-                return; // No new users added, no notification needed for issue update
+            if !recipients.is_empty() {
+                subject = format!("Je bent toegevoegd aan Zaak: {}", issue_title);
+                content_prefix = "Je bent toegevoegd aan deze zaak.".to_string();
+            } else {
+                // Other updates only go to the issue's watchers
+                recipients = state
+                    .storage
+                    .list_watchers(resource_id)
+                    .await
+                    .unwrap_or_default();
+                if recipients.is_empty() {
+                    return;
+                }
+                subject = format!("Zaak bijgewerkt: {}", issue_title);
+                content_prefix = "Deze zaak is bijgewerkt.".to_string();
             }
-
-            subject = format!("Je bent toegevoegd aan Zaak: {}", issue_title);
-            content_prefix = "Je bent toegevoegd aan deze zaak.".to_string();
         } else {
             // New Issue: Notify all involved
             recipients = new_involved;
@@ -534,6 +1067,48 @@ async fn send_notifications_for_event(
         } else {
             subject = format!("Nieuwe Reactie op {}", thread_id);
         }
+
+        for watcher in state
+            .storage
+            .list_watchers(&thread_id)
+            .await
+            .unwrap_or_default()
+        {
+            if !recipients.contains(&watcher) {
+                recipients.push(watcher);
+            }
+        }
+
+        // Internal notes stay within the author's organisation
+        if resource.get("internal").and_then(|v| v.as_bool()) == Some(true) {
+            let actor = commit_of(event).map(|c| c.actor).unwrap_or_default();
+            let tenant = crate::auth::tenant_of(&actor);
+            recipients.retain(|r| crate::auth::tenant_of(r) == tenant);
+        }
+    }
+
+    // Urgent issues go to the delegates of recipients who are away
+    let issue = if is_issue {
+        Some(resource.clone())
+    } else {
+        state.storage.get_resource(&thread_id).await.ok().flatten()
+    };
+    if issue
+        .as_ref()
+        .is_some_and(|issue| crate::labels::has_tag(issue, crate::availability::URGENT_TAG))
+    {
+        let mut rerouted = Vec::new();
+        for recipient in recipients {
+            let target = crate::availability::active_delegate(&state.storage, &recipient)
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or(recipient);
+            if !rerouted.contains(&target) {
+                rerouted.push(target);
+            }
+        }
+        recipients = rerouted;
     }
 
     // 3. Determine author (to exclude from notifications)
@@ -549,6 +1124,15 @@ async fn send_notifications_for_event(
             continue;
         }
 
+        // Muted by the recipient
+        if crate::mute::is_muted(&state.storage, &thread_id, &recipient, chrono::Utc::now())
+            .await
+            .unwrap_or(false)
+        {
+            println!("[notify] Skipping {} (muted {})", recipient, thread_id);
+            continue;
+        }
+
         // Smart Suppression: Check if user is active (seen in last 2 mins)
         if let Some(last_seen) = state.active_users.get(&recipient) {
             if last_seen.elapsed() < Duration::from_secs(120) {
@@ -583,7 +1167,24 @@ async fn send_notifications_for_event(
             format!("{} opende een nieuwe zaak:", author_name)
         };
 
-        let full_content = format!("{}\n\n{}", header, content);
+        // "Nieuw sinds laatste bezoek": everything in this zaak the recipient hasn't seen
+        let unread = if is_comment {
+            state
+                .storage
+                .unread_count(&recipient, &thread_id)
+                .await
+                .unwrap_or(0)
+        } else {
+            0
+        };
+        let full_content = if unread > 1 {
+            format!(
+                "{}\n\n{}\n\n({} nieuwe updates in deze zaak sinds je laatste bezoek)",
+                header, content, unread
+            )
+        } else {
+            format!("{}\n\n{}", header, content)
+        };
 
         // Generate magic link token
         let magic_link = match crate::auth::create_jwt(&recipient) {
@@ -618,8 +1219,16 @@ async fn send_notifications_for_event(
             "[notify] Sending email to {} for thread {}",
             recipient, thread_id
         );
+        let delivery = DeliveryStatus {
+            channel: DeliveryChannel::Email,
+            recipient: recipient.clone(),
+            outcome: DeliveryOutcome::Queued,
+            trigger_event: Some(event.id.clone()),
+            error: None,
+        };
+        crate::delivery::record(state, &thread_id, delivery.clone()).await;
         tokio::spawn({
-            let email_service = state.email_service.clone();
+            let state = state.clone();
             let recipient = recipient.clone();
             let subject = subject.clone();
             let html_body = html_body.clone();
@@ -627,7 +1236,8 @@ async fn send_notifications_for_event(
             let reply_to = reply_to.clone();
             let thread_id = thread_id.clone();
             async move {
-                if let Err(e) = email_service
+                let result = state
+                    .email_service
                     .send_notification(
                         &recipient,
                         &subject,
@@ -636,309 +1246,610 @@ async fn send_notifications_for_event(
                         Some(&reply_to),
                         Some(&thread_id),
                     )
-                    .await
-                {
-                    eprintln!("[notify] Failed to send email to {}: {}", recipient, e);
-                }
+                    .await;
+                let delivery = match result {
+                    Ok(()) => DeliveryStatus {
+                        outcome: DeliveryOutcome::Sent,
+                        ..delivery
+                    },
+                    Err(e) => {
+                        eprintln!("[notify] Failed to send email to {}: {}", recipient, e);
+                        DeliveryStatus {
+                            outcome: DeliveryOutcome::Failed,
+                            error: Some(e.to_string()),
+                            ..delivery
+                        }
+                    }
+                };
+                crate::delivery::record(&state, &thread_id, delivery).await;
             }
         });
     }
 }
 
-/// Process an event and update resources accordingly
-pub async fn process_event(
-    state: &AppState,
-    event: &CloudEvent,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Extract data from the event
-    let data = match &event.data {
-        Some(d) => d,
-        None => return Ok(()), // No data to process
-    };
-
-    // Check if this is a JSONCommit event (accept both legacy and NL-VNG names)
-    if event.event_type == "nl.vng.zaken.json-commit.v1" || event.event_type == "json.commit" {
-        let commit: JSONCommit = serde_json::from_value(data.clone())?;
+/// Apply JSON Merge Patch (RFC 7396)
+fn apply_json_merge_patch(target: &mut Value, patch: &Value) {
+    if !patch.is_object() {
+        *target = patch.clone();
+        return;
+    }
 
-        // Handle deletion
-        if commit.deleted.unwrap_or(false) {
-            state.storage.delete_resource(&commit.resource_id).await?;
-            return Ok(());
-        }
+    if !target.is_object() {
+        *target = serde_json::json!({});
+    }
 
-        // Determine resource type more robustly:
-        let mut resource_type = extract_resource_type_from_schema(&commit.schema).to_string();
+    let target_obj = target.as_object_mut().unwrap();
+    let patch_obj = patch.as_object().unwrap();
 
-        if resource_type == "unknown" {
-            let subj_type = extract_resource_type_from_subject(&event.subject);
-            if subj_type != "unknown" {
-                resource_type = subj_type.to_string();
-            }
+    for (key, value) in patch_obj {
+        if value.is_null() {
+            target_obj.remove(key);
+        } else if value.is_object() && target_obj.contains_key(key) {
+            let mut target_value = target_obj.get(key).unwrap().clone();
+            apply_json_merge_patch(&mut target_value, value);
+            target_obj.insert(key.clone(), target_value);
+        } else {
+            target_obj.insert(key.clone(), value.clone());
         }
+    }
+}
 
-        if resource_type == "unknown" {
-            if let Some(resource_data) = &commit.resource_data {
-                if resource_data.is_object() {
-                    let obj = resource_data.as_object().unwrap();
-                    if obj.contains_key("title") {
-                        resource_type = "Issue".to_string();
-                    } else if obj.contains_key("content") {
-                        resource_type = "Comment".to_string();
-                    } else if obj.contains_key("cta") {
-                        resource_type = "Task".to_string();
-                    } else if obj.contains_key("moments") {
-                        resource_type = "Planning".to_string();
-                    } else if obj.get("url").is_some() || obj.get("size").is_some() {
-                        resource_type = "Document".to_string();
-                    }
-                }
-            }
-        }
+/// The state of a resource after applying `commit` to `existing` (`None` = deleted / absent).
+pub(crate) fn apply_commit(existing: Option<Value>, commit: &JSONCommit) -> Option<Value> {
+    try_apply_commit(existing.clone(), commit).unwrap_or_else(|e| {
+        // Only stored commits are replayed, and a failing JSON Patch is never stored
+        eprintln!(
+            "[commit] JSON Patch on {} not applied: {}",
+            commit.resource_id, e
+        );
+        existing
+    })
+}
 
-        // Get existing resource if it exists
-        let existing_resource = state.storage.get_resource(&commit.resource_id).await?;
-        let old_resource = existing_resource.clone(); // Capture old state
+/// The resource after `commit`, or why its JSON Patch doesn't apply (see `apply_commit`)
+pub(crate) fn try_apply_commit(
+    existing: Option<Value>,
+    commit: &JSONCommit,
+) -> Result<Option<Value>, String> {
+    if commit.deleted.unwrap_or(false) {
+        return Ok(None);
+    }
 
-        // Apply changes (merge patch or replace with resource_data)
-        let new_resource = if let Some(mut existing) = existing_resource {
+    Ok(match existing {
+        Some(mut existing) => {
             // Apply patch if provided
-            if let Some(patch) = &commit.patch {
-                apply_json_merge_patch(&mut existing, patch);
+            match &commit.patch {
+                Some(patch) if commit.is_json_patch() => {
+                    crate::json_patch::apply(&mut existing, patch)?
+                }
+                Some(patch) => apply_json_merge_patch(&mut existing, patch),
+                None => {}
             }
             // Override with full resource_data if provided
             if let Some(resource_data) = &commit.resource_data {
                 existing = resource_data.clone();
             }
-            existing
-        } else {
-            // New resource - use resource_data if available, else empty object
+            Some(existing)
+        }
+        // New resource - use resource_data if available, else empty object
+        None => Some(
             commit
                 .resource_data
                 .clone()
-                .unwrap_or_else(|| serde_json::json!({}))
-        };
+                .unwrap_or_else(|| serde_json::json!({})),
+        ),
+    })
+}
 
-        // Store the updated resource
-        state
-            .storage
-            .store_resource(&commit.resource_id, &resource_type, &new_resource)
-            .await?;
+/// The JSON Merge Patch that turns `from` into `to` (the inverse of `apply_json_merge_patch`).
+pub(crate) fn merge_patch_diff(from: &Value, to: &Value) -> Value {
+    let (Some(from_obj), Some(to_obj)) = (from.as_object(), to.as_object()) else {
+        return to.clone();
+    };
 
-        // Schedule background indexing of the resource via the search subsystem.
-        let resource_id = commit.resource_id.clone();
-        let resource_type_clone = resource_type.clone();
-        let mut data_clone = new_resource.clone();
-        let search = state.search.clone();
-        // AUTH FIX: Denormalize 'involved' for Comments (and other child resources)
-        // Comments don't have 'involved' field, so they fail the default auth filter.
-        // We look up the parent issue and copy its 'involved' list into the indexing payload.
-        if (resource_type_clone == "Comment" || resource_type_clone == "comment")
-            && data_clone.get("involved").is_none()
-        {
-            // Use event.subject as the parent Issue ID
-            // The frontend sends zaakId as subject for Comments
-            let parent_id = event.subject.clone();
-
-            if let Ok(Some(parent)) = state.storage.get_resource(&parent_id).await {
-                if let Some(involved) = parent.get("involved") {
-                    if let Some(obj) = data_clone.as_object_mut() {
-                        obj.insert("involved".to_string(), involved.clone());
-                    }
-                }
+    let mut patch = serde_json::Map::new();
+    for (key, old_value) in from_obj {
+        match to_obj.get(key) {
+            None => {
+                patch.insert(key.clone(), Value::Null);
             }
+            Some(new_value) if new_value != old_value => {
+                let value = if new_value.is_object() && old_value.is_object() {
+                    merge_patch_diff(old_value, new_value)
+                } else {
+                    new_value.clone()
+                };
+                patch.insert(key.clone(), value);
+            }
+            Some(_) => {}
         }
+    }
+    for (key, new_value) in to_obj {
+        if !from_obj.contains_key(key) {
+            patch.insert(key.clone(), new_value.clone());
+        }
+    }
+    Value::Object(patch)
+}
 
-        let timestamp_opt = commit
-            .timestamp
-            .as_ref()
-            .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
-            .map(|dt| dt.with_timezone(&chrono::Utc));
-
-        let payload = serde_json::to_string(&data_clone).unwrap_or_default();
-
-        if let Err(err) = search
-            .add_resource_payload(
-                &resource_id,
-                &resource_type_clone,
-                "",
-                &payload,
-                timestamp_opt,
-            )
-            .await
-        {
-            eprintln!(
-                "[handlers] failed adding resource payload to search index id={} err={}",
-                resource_id, err
-            );
+/// The commit that undoes `commit`, given the resource state right before and right after it.
+/// Returns `None` when the commit changed nothing that can be restored.
+fn invert_commit(
+    commit: &JSONCommit,
+    before: Option<&Value>,
+    after: Option<&Value>,
+) -> Option<JSONCommit> {
+    let mut inverse = JSONCommit {
+        schema: commit.schema.clone(),
+        resource_id: commit.resource_id.clone(),
+        actor: commit.actor.clone(),
+        timestamp: Some(chrono::Utc::now().to_rfc3339()),
+        resource_data: None,
+        patch: None,
+        deleted: None,
+        base_sequence: None,
+        offline: None,
+        patch_type: None,
+    };
+
+    match (before, after) {
+        // Deleted: recreate the previous version
+        (Some(before), None) => inverse.resource_data = Some(before.clone()),
+        // Created: delete again
+        (None, Some(_)) => inverse.deleted = Some(true),
+        // Updated: patch back only the fields this commit changed
+        (Some(before), Some(after)) => {
+            let patch = merge_patch_diff(after, before);
+            if patch.as_object().is_some_and(|p| p.is_empty()) {
+                return None;
+            }
+            inverse.patch = Some(patch);
         }
+        (None, None) => return None,
+    }
 
-        // Trigger Notifications
-        send_notifications_for_event(state, event, &new_resource, old_resource.as_ref()).await;
-    } else {
-        // For other event types, we'll just store them as-is
-        let resource_type = extract_resource_type_from_subject(&event.subject);
-        state
-            .storage
-            .store_resource(&event.id, resource_type, data)
+    Some(inverse)
+}
+
+/// Replay the event log to find the state of `resource_id` right before and right after the
+/// event with sequence `seq`.
+async fn resource_state_around(
+    storage: &Storage,
+    resource_id: &str,
+    seq: &str,
+) -> Result<(Option<Value>, Option<Value>), Box<dyn std::error::Error + Send + Sync>> {
+    let mut state: Option<Value> = None;
+    let mut after: Option<String> = None;
+
+    loop {
+        let page = storage
+            .list_events_after(after.clone(), CATCHUP_PAGE_SIZE)
             .await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = last.sequence.clone();
 
-        // schedule resource indexing via search subsystem (serialize once)
-        let id_clone = event.id.clone();
-        let rt_clone = resource_type.to_string();
-        let data_clone = data.clone();
-        let payload = serde_json::to_string(&data_clone).unwrap_or_default();
-        let search = state.search.clone();
-        // Index resource synchronously
-        if let Err(err) = search
-            .add_resource_payload(&id_clone, &rt_clone, "", &payload, None)
-            .await
-        {
-            eprintln!(
-                "[handlers] failed adding non-json-commit resource payload id={} err={}",
-                id_clone, err
-            );
+        for event in page {
+            let event_seq = event.sequence.clone().unwrap_or_default();
+            if event_seq.as_str() > seq {
+                break;
+            }
+            let Some(commit) = commit_of(&event) else {
+                continue;
+            };
+            if commit.resource_id != resource_id {
+                continue;
+            }
+            let next = apply_commit(state.clone(), &commit);
+            if event_seq == seq {
+                return Ok((state, next));
+            }
+            state = next;
+        }
+
+        if after.as_deref().is_some_and(|a| a >= seq) {
+            break;
         }
     }
 
-    Ok(())
+    Err(format!("event with sequence {} does not touch {}", seq, resource_id).into())
 }
 
-/// Extract resource type from schema URL
-fn extract_resource_type_from_schema(schema: &str) -> &str {
-    if schema.contains("Issue") {
-        "Issue"
-    } else if schema.contains("Comment") {
-        "Comment"
-    } else if schema.contains("Task") {
-        "Task"
-    } else if schema.contains("Planning") {
-        "Planning"
-    } else if schema.contains("Document") {
-        "Document"
-    } else {
-        "unknown"
-    }
+/// A point in the event log: a sequence number, or a moment in time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsOf {
+    Sequence(u64),
+    Time(chrono::DateTime<chrono::FixedOffset>),
 }
 
-/// Extract resource type from subject
-fn extract_resource_type_from_subject(subject: &str) -> &str {
-    if subject.contains("issue") {
-        "Issue"
-    } else if subject.contains("comment") {
-        "Comment"
-    } else if subject.contains("task") {
-        "Task"
-    } else if subject.contains("planning") {
-        "Planning"
-    } else if subject.contains("document") {
-        "Document"
-    } else {
-        "unknown"
+impl std::str::FromStr for AsOf {
+    type Err = String;
+
+    /// `"42"` is a sequence number, anything else must be an RFC 3339 timestamp
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(seq) = s.parse::<u64>() {
+            return Ok(AsOf::Sequence(seq));
+        }
+        chrono::DateTime::parse_from_rfc3339(s)
+            .map(AsOf::Time)
+            .map_err(|e| {
+                format!(
+                    "as_of must be a sequence number or an RFC 3339 timestamp: {}",
+                    e
+                )
+            })
     }
 }
 
-/// Apply JSON Merge Patch (RFC 7396)
-fn apply_json_merge_patch(target: &mut Value, patch: &Value) {
-    if !patch.is_object() {
-        *target = patch.clone();
-        return;
+impl AsOf {
+    /// Was `event` stored at or before this point?
+    fn includes(&self, event: &CloudEvent) -> bool {
+        match self {
+            AsOf::Sequence(seq) => event.sequencenumber.is_none_or(|n| n <= *seq),
+            AsOf::Time(time) => event
+                .time
+                .as_deref()
+                .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+                .is_none_or(|t| t <= *time),
+        }
     }
+}
 
-    if !target.is_object() {
-        *target = serde_json::json!({});
+/// The state of `resource_id` at `as_of`, folded from the commits in the events about
+/// `subject` (`None` when it did not exist then).
+pub async fn resource_as_of(
+    storage: &Storage,
+    subject: &str,
+    resource_id: &str,
+    as_of: &AsOf,
+) -> Result<Option<Value>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut state = None;
+    for event in storage.list_subject_events(subject).await? {
+        // Events are in sequence order, so times only increase (up to clock differences)
+        if !as_of.includes(&event) {
+            break;
+        }
+        if let Some(commit) = commit_of(&event).filter(|c| c.resource_id == resource_id) {
+            state = apply_commit(state, &commit);
+        }
     }
+    Ok(state)
+}
 
-    let target_obj = target.as_object_mut().unwrap();
-    let patch_obj = patch.as_object().unwrap();
-
-    for (key, value) in patch_obj {
-        if value.is_null() {
-            target_obj.remove(key);
-        } else if value.is_object() && target_obj.contains_key(key) {
-            let mut target_value = target_obj.get(key).unwrap().clone();
-            apply_json_merge_patch(&mut target_value, value);
-            target_obj.insert(key.clone(), target_value);
-        } else {
-            target_obj.insert(key.clone(), value.clone());
-        }
+/// The JSONCommit carried by a commit event
+pub(crate) fn commit_of(event: &CloudEvent) -> Option<JSONCommit> {
+    if event.event_type != "json.commit" && event.event_type != "nl.vng.zaken.json-commit.v1" {
+        return None;
     }
+    serde_json::from_value(event.data.clone()?).ok()
 }
 
 /// GET /resources - List all resources (paginated)
+#[utoipa::path(
+    get,
+    path = "/resources",
+    tag = "resources",
+    params(ListParams),
+    security((), ("bearer" = [])),
+    responses((status = 200, description = "Resources (JSON, or CBOR with `Accept: application/cbor`), without connectors. Authenticated callers get unread counts on their issues.", body = [ResourceResponse]))
+)]
 pub async fn list_resources(
     State(state): State<AppState>,
+    auth_user: Option<AuthUser>,
+    headers: HeaderMap,
     Query(params): Query<ListParams>,
-) -> Result<Json<Vec<ResourceResponse>>, StatusCode> {
+) -> Result<Response, StatusCode> {
     let resources = state
         .storage
-        .list_resources(params.offset, params.limit)
+        .list_typed_resources(params.offset, params.limit)
         .await
         .map_err(|e| {
             eprintln!("Failed to list resources: {}", e);
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-    let response: Vec<ResourceResponse> = resources
+    let mut response: Vec<ResourceResponse> = resources
         .into_iter()
-        .map(|(id, data)| {
-            // Try to determine resource type from the data
-            let resource_type = if let Some(_title) = data.get("title") {
-                // Likely an issue
-                "issue".to_string()
-            } else if let Some(_content) = data.get("content") {
-                "comment".to_string()
-            } else if let Some(_cta) = data.get("cta") {
-                "task".to_string()
-            } else if let Some(_moments) = data.get("moments") {
-                "planning".to_string()
-            } else {
-                "unknown".to_string()
-            };
-
-            ResourceResponse {
-                id,
-                resource_type,
-                data,
-            }
+        .filter(|(_, _, data)| {
+            params
+                .tag
+                .as_deref()
+                .is_none_or(|tag| crate::labels::has_tag(data, tag))
+                && !crate::connectors::is_connector(data)
+        })
+        .map(|(id, resource_type, data)| ResourceResponse {
+            id,
+            resource_type: crate::resource_types::api_name(&resource_type),
+            data,
+            unread: None,
         })
         .collect();
 
-    Ok(Json(response))
+    if let Some(auth_user) = auth_user {
+        for resource in &mut response {
+            resource.unread = crate::read_receipts::unread_for(
+                &state.storage,
+                &auth_user.user_id,
+                &resource.id,
+                &resource.data,
+            )
+            .await;
+        }
+    }
+
+    Ok(encoding::negotiated(
+        &headers,
+        StatusCode::OK,
+        &response,
+        encoding::CBOR,
+    ))
+}
+
+/// Query parameters for reading a resource
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ResourceParams {
+    /// Read the resource as it was at this sequence number or RFC 3339 timestamp
+    #[serde(default)]
+    pub as_of: Option<String>,
+    /// With `as_of`: the subject the resource's events are about, for resources in an
+    /// issue's thread (comments, tasks, ...). Defaults to the resource itself.
+    #[serde(default)]
+    pub subject: Option<String>,
 }
 
 /// GET /resources/:id - Get a specific resource
+///
+/// Connectors are not served here: their webhook URL is a secret (see `connectors`). For issues the caller is involved in, the issues linked to it are included as
+/// `relations` (see `relations::IssueLink`). With `as_of`, the resource is reconstructed as
+/// it was at that point from its commits, without relations.
+#[utoipa::path(
+    get,
+    path = "/resources/{id}",
+    tag = "resources",
+    params(("id" = String, Path, description = "Resource id"), ResourceParams),
+    responses(
+        (status = 200, description = "The resource JSON", body = Value),
+        (status = 400, description = "Invalid as_of"),
+        (status = 404, description = "Resource not found (at as_of)"),
+    )
+)]
 pub async fn get_resource(
     State(state): State<AppState>,
+    auth_user: Option<AuthUser>,
     Path(id): Path<String>,
+    Query(params): Query<ResourceParams>,
 ) -> Result<Json<Value>, StatusCode> {
+    if let Some(as_of) = &params.as_of {
+        let as_of: AsOf = as_of.parse().map_err(|e| {
+            eprintln!("[resources] {}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+        let subject = params.subject.as_deref().unwrap_or(&id);
+        return resource_as_of(&state.storage, subject, &id, &as_of)
+            .await
+            .map_err(|e| {
+                eprintln!("Failed to reconstruct resource {}: {}", id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .filter(|data| !crate::connectors::is_connector(data))
+            .map(Json)
+            .ok_or(StatusCode::NOT_FOUND);
+    }
+
     let resource = state.storage.get_resource(&id).await.map_err(|e| {
         eprintln!("Failed to get resource: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    match resource {
-        Some(data) => Ok(Json(data)),
-        None => Err(StatusCode::NOT_FOUND),
+    let Some(mut data) = resource.filter(|data| !crate::connectors::is_connector(data)) else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if let Some(user) = auth_user {
+        let is_issue = data.get("title").is_some() && data.get("involved").is_some();
+        if is_issue && check_access(&state.storage, &user.user_id, &id).await {
+            let links = crate::relations::issue_links(&state.storage, &user.user_id, &id)
+                .await
+                .map_err(|e| {
+                    eprintln!("Failed to get relations of {}: {}", id, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            if let Some(obj) = data.as_object_mut().filter(|_| !links.is_empty()) {
+                obj.insert(
+                    "relations".to_string(),
+                    serde_json::to_value(links).unwrap(),
+                );
+            }
+            let watching = state
+                .storage
+                .is_watching(&id, &user.user_id)
+                .await
+                .map_err(|e| {
+                    eprintln!("Failed to get watch state of {}: {}", id, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            if let Some(obj) = data.as_object_mut() {
+                obj.insert("watching".to_string(), Value::Bool(watching));
+            }
+            let checklists = crate::checklists::progress(&state, &id)
+                .await
+                .map_err(|e| {
+                    eprintln!("Failed to get checklists of {}: {}", id, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            if let (Some(obj), Some(progress)) = (data.as_object_mut(), checklists) {
+                obj.insert(
+                    "checklists".to_string(),
+                    serde_json::to_value(progress).unwrap(),
+                );
+            }
+        }
     }
+    Ok(Json(data))
+}
+
+/// Schema URL of the latest commit to `resource_id` among the events about `subject`.
+pub(crate) async fn latest_commit_schema(
+    storage: &Storage,
+    subject: &str,
+    resource_id: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(storage
+        .list_subject_events(subject)
+        .await?
+        .iter()
+        .rev()
+        .filter_map(commit_of)
+        .find(|c| c.resource_id == resource_id)
+        .map(|c| c.schema))
+}
+
+/// Query parameters for changing a resource over REST
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WriteParams {
+    /// The subject the resource's events are about, for resources in an issue's thread
+    /// (comments, tasks, ...). Defaults to the resource itself.
+    #[serde(default)]
+    pub subject: Option<String>,
 }
 
 /// DELETE /resources/:id - Delete a specific resource
+///
+/// Submits a `deleted: true` commit like `POST /events` does, so the deletion is stored,
+/// indexed and broadcast to the other clients.
+#[utoipa::path(
+    delete,
+    path = "/resources/{id}",
+    tag = "resources",
+    params(("id" = String, Path, description = "Resource id"), WriteParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Deletion commit stored, processed and broadcast", body = CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The caller has no access to the resource's issue"),
+        (status = 404, description = "Resource not found"),
+    )
+)]
 pub async fn delete_resource(
     State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
     Path(id): Path<String>,
-) -> Result<StatusCode, StatusCode> {
-    state.storage.delete_resource(&id).await.map_err(|e| {
-        eprintln!("Failed to delete resource: {}", e);
+    Query(params): Query<WriteParams>,
+) -> Result<Response, StatusCode> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("Failed to delete resource {}: {}", id, e);
         StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    };
+    if state
+        .storage
+        .get_resource(&id)
+        .await
+        .map_err(internal)?
+        .is_none()
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let subject = params.subject.unwrap_or_else(|| id.clone());
+    // Resources not made by a commit (e.g. stored integration events) have no schema
+    let schema = latest_commit_schema(&state.storage, &subject, &id)
+        .await
+        .map_err(internal)?
+        .unwrap_or_else(|| crate::schemas::schema_url("unknown"));
+    let commit = JSONCommit {
+        schema,
+        resource_id: id.clone(),
+        actor: auth_user.user_id.clone(),
+        timestamp: Some(chrono::Utc::now().to_rfc3339()),
+        resource_data: None,
+        patch: None,
+        patch_type: None,
+        deleted: Some(true),
+        base_sequence: None,
+        offline: None,
+    };
+    let event = CloudEventBuilder::commit(&subject, &commit).build();
+    handle_event(State(state), Some(auth_user), headers, JsonOrCbor(event)).await
+}
 
-    Ok(StatusCode::NO_CONTENT)
+/// PATCH /resources/:id - Update a resource without building a CloudEvent
+///
+/// The body is a JSON Merge Patch, or with `Content-Type: application/json-patch+json` a
+/// JSON Patch. It is wrapped in a commit by the caller, with the schema of the stored
+/// resource's type, and submitted like `POST /events`.
+#[utoipa::path(
+    patch,
+    path = "/resources/{id}",
+    tag = "resources",
+    params(("id" = String, Path, description = "Resource id"), WriteParams),
+    request_body(content = Object, description = "JSON Merge Patch (RFC 7396), or JSON Patch (RFC 6902) operations"),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Invalid patch"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "No access to the resource's issue"),
+        (status = 404, description = "Resource not found"),
+    )
+)]
+pub async fn patch_resource(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Query(params): Query<WriteParams>,
+    Json(patch): Json<Value>,
+) -> Result<Response, StatusCode> {
+    let resource_type = state.storage.get_resource_type(&id).await.map_err(|e| {
+        eprintln!("Failed to get resource {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let Some(resource_type) = resource_type else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let json_patch = headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json-patch+json"));
+    let patch_type = match json_patch {
+        true => Some(crate::schemas::PatchType::JsonPatch),
+        false if patch.is_object() => None,
+        false => return Err(StatusCode::BAD_REQUEST),
+    };
+    let commit = JSONCommit {
+        schema: crate::schemas::schema_url(&resource_type),
+        resource_id: id.clone(),
+        actor: auth_user.user_id.clone(),
+        timestamp: Some(chrono::Utc::now().to_rfc3339()),
+        resource_data: None,
+        patch: Some(patch),
+        patch_type,
+        deleted: None,
+        base_sequence: None,
+        offline: None,
+    };
+    let subject = params.subject.unwrap_or(id);
+    let event = CloudEventBuilder::commit(&subject, &commit).build();
+    handle_event(State(state), Some(auth_user), headers, JsonOrCbor(event)).await
 }
 
 use crate::auth::AuthUser;
 
 /// GET /query - Search resources using full-text search
 /// Returns structured search results produced by the storage layer.
+#[utoipa::path(
+    get,
+    path = "/query",
+    tag = "search",
+    params(QueryParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Search results the caller is involved in", body = [SearchResult]),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
 pub async fn query_resources(
     State(state): State<AppState>,
     auth_user: AuthUser,
@@ -946,9 +1857,23 @@ pub async fn query_resources(
 ) -> Result<Json<Vec<SearchResult>>, StatusCode> {
     // Always use the authenticated user for filtering
     let user = &auth_user.user_id;
-    let final_query = crate::search::SearchIndex::apply_authorization_filter(&params.q, user);
+    let query = match params.tag.as_deref() {
+        Some(tag) if params.q.trim().is_empty() || params.q.trim() == "*" => {
+            crate::labels::tag_clause(tag)
+        }
+        Some(tag) => format!("({}) AND {}", params.q, crate::labels::tag_clause(tag)),
+        None => params.q.clone(),
+    };
+    let teams = crate::teams::user_teams(&state.storage, user)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to load teams of {}: {}", user, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let final_query =
+        crate::search::SearchIndex::apply_scoped_authorization_filter(&query, user, &teams);
 
-    let results = state
+    let mut results = state
         .search
         .search(&state.storage, &final_query, params.limit)
         .await
@@ -957,10 +1882,27 @@ pub async fn query_resources(
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
+    for result in &mut results {
+        if let Some(resource) = &result.resource {
+            result.unread =
+                crate::read_receipts::unread_for(&state.storage, user, &result.id, resource).await;
+        }
+    }
+
     Ok(Json(results))
 }
 
 /// POST /api/email/inbound - Handle incoming Postmark webhooks
+#[utoipa::path(
+    post,
+    path = "/api/email/inbound",
+    tag = "events",
+    request_body(content = Value, description = "Postmark inbound webhook payload"),
+    responses(
+        (status = 200, description = "Reply converted into a Comment"),
+        (status = 400, description = "Sender or recipient missing or malformed"),
+    )
+)]
 pub async fn inbound_email_handler(
     State(state): State<AppState>,
     Json(payload): Json<Value>,
@@ -1019,82 +1961,38 @@ pub async fn inbound_email_handler(
     );
 
     // 4. Create Comment
-    let comment_id = uuid::Uuid::new_v4().to_string();
-    let timestamp = chrono::Utc::now().to_rfc3339();
-
-    // The Comment resource itself only needs content according to the schema
-    let comment_data = serde_json::json!({
-        "content": content,
-    });
-
-    let event = CloudEvent {
-        specversion: "1.0".to_string(),
-        id: uuid::Uuid::new_v4().to_string(),
-        // Use sender email as source so they are identified as author
-        source: sender_email.to_string(),
-        // Subject should be the Issue ID (thread ID)
-        subject: issue_id.to_string(),
-        event_type: "json.commit".to_string(),
-        time: Some(timestamp.clone()),
-        datacontenttype: Some("application/json".to_string()),
-        dataschema: None,
-        dataref: None,
-        sequence: None,
-        sequencetype: None,
-        data: Some(serde_json::json!({
-            "resource_id": comment_id,
-            "schema": "https://zaakchat.nl/schemas/Comment.json",
-            "actor": sender_email,
-            "timestamp": timestamp,
-            "resource_data": comment_data
-        })),
+    let comment = Comment {
+        content: content.to_string(),
+        quote_comment: None,
+        mentions: None,
+        edited_at: None,
+        internal: None,
     };
+    let commit = CommitBuilder::create(uuid::Uuid::now_v7().to_string(), &comment)
+        .actor(sender_email)
+        .build();
 
-    // Use handle_event logic (store, index, broadcast)
-    // We can't call handle_event directly because of Axum types, so we replicate the logic or extract a shared function.
-    // For simplicity, let's call the internal logic.
+    // Subject is the Issue ID (thread ID); the sender is the source so they are identified as author
+    let event = CloudEventBuilder::commit(issue_id, &commit)
+        .source(sender_email)
+        .build();
 
-    let seq_key = state.storage.store_event(&event).await.map_err(|e| {
-        eprintln!("Failed to store inbound event: {}", e);
+    submit_event(&state, event).await.map_err(|e| {
+        eprintln!("[inbound] failed to submit reply: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    // We need to mutate event to add sequence, but we can't easily here without cloning.
-    // Let's just create a new event with sequence for broadcasting.
-    let mut broadcast_event = event.clone();
-    broadcast_event.sequence = Some(seq_key);
-
-    // Indexing
-    {
-        let search = state.search.clone();
-        let payload = serde_json::to_string(&broadcast_event).unwrap_or_default();
-        let id = broadcast_event.id.clone();
-        let doc_type = broadcast_event.event_type.clone();
-        if let Err(e) = search
-            .add_event_payload(&id, &doc_type, "", &payload, None)
-            .await
-        {
-            eprintln!("[inbound] failed indexing: {}", e);
-        }
-    }
-
-    // Process (store resource)
-    if let Err(e) = process_event(&state, &broadcast_event).await {
-        eprintln!("[inbound] failed processing: {}", e);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    }
-
-    // Commit search
-    let _ = state.search.commit().await;
-
-    // Broadcast
-    let _ = state.tx.send(broadcast_event);
-
     Ok(StatusCode::OK)
 }
 
 /// GET /debug/db - Return counts and sample ids of events and resources for diagnostics.
 /// Use this to verify what is persisted on disk.
+#[utoipa::path(
+    get,
+    path = "/debug/db",
+    tag = "admin",
+    responses((status = 200, description = "Event and resource counts with sample ids", body = Value))
+)]
 pub async fn debug_db(
     State(state): State<AppState>,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
@@ -1138,7 +2036,7 @@ pub async fn debug_db(
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     #[test]
@@ -1171,33 +2069,416 @@ mod tests {
     }
 
     #[test]
-    fn test_extract_resource_type_from_schema() {
+    fn test_parse_event_types_filter() {
+        assert!(parse_event_types(None).is_none());
+        assert!(parse_event_types(Some(" , ")).is_none());
+
+        let filter = parse_event_types(Some("json.commit, nl.vng.zaken.status-change.v1"));
+        let types = filter.as_ref().unwrap();
+        assert_eq!(types.len(), 2);
+        assert!(types.contains("nl.vng.zaken.status-change.v1"));
+
+        let mut event = event("evt", "issue-1");
+        assert!(event_type_allowed(&filter, &event));
+        event.event_type = "system.reset".to_string();
+        assert!(!event_type_allowed(&filter, &event));
+        assert!(event_type_allowed(&None, &event));
+    }
+
+    #[tokio::test]
+    async fn test_live_events_catch_up_after_lag() {
+        use tempfile::TempDir;
+        use tokio::sync::broadcast;
+
+        let dir = TempDir::new().unwrap();
+        let storage = Arc::new(Storage::new(dir.path()).await.unwrap());
+        // Tiny channel so the subscriber is guaranteed to lag
+        let (tx, rx) = broadcast::channel(2);
+
+        for i in 1..=6 {
+            let mut event = event(&format!("evt-{}", i), "issue-1");
+            event.sequence = Some(storage.store_event(&event).await.unwrap());
+            tx.send(event).unwrap();
+        }
+        drop(tx);
+
+        let ids: Vec<String> = live_events_with_catchup(storage, rx, None)
+            .map(|e| e.id)
+            .collect()
+            .await;
+
         assert_eq!(
-            extract_resource_type_from_schema("https://zaakchat.nl/schemas/Issue.json"),
-            "Issue"
+            ids,
+            vec!["evt-1", "evt-2", "evt-3", "evt-4", "evt-5", "evt-6"],
+            "lagged subscriber should receive every event once, in order"
         );
-        assert_eq!(
-            extract_resource_type_from_schema("https://zaakchat.nl/schemas/Comment.json"),
-            "Comment"
+    }
+
+    #[tokio::test]
+    async fn test_checkpoints_carry_the_stream_cursor() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let storage = Storage::new(&dir.path().join("data")).await.unwrap();
+        let mut sequences = Vec::new();
+        for i in 1..=3 {
+            let event = CloudEventBuilder::new("json.commit", "issue-1")
+                .id(format!("evt-{}", i))
+                .build();
+            sequences.push(storage.store_event(&event).await.unwrap());
+        }
+        let cursor = StreamCursor::new(Some(sequences[0].clone()));
+        let at = |sequence: &str| CloudEvent {
+            sequence: Some(sequence.to_string()),
+            ..CloudEventBuilder::new("json.commit", "issue-1").build()
+        };
+        cursor.advance(&at(&sequences[1]));
+        // Older events don't move it back
+        cursor.advance(&at(&sequences[0]));
+
+        let mut checkpoints = Box::pin(checkpoint_events(cursor, Duration::from_millis(10)));
+        let checkpoint = format!("{:?}", checkpoints.next().await.unwrap());
+        // Not the latest stored sequence: the stream hasn't considered that event yet
+        assert!(checkpoint.contains(&sequences[1]), "{}", checkpoint);
+        assert!(!checkpoint.contains(&sequences[2]), "{}", checkpoint);
+    }
+
+    /// The admin in tests (see `auth::is_admin`). Tests share the environment, so they all
+    /// use this one.
+    pub(crate) const ADMIN: &str = "admin@gemeente.nl";
+
+    /// `user`, authenticated; [`ADMIN`] is an admin
+    pub(crate) fn auth_user(user: &str) -> AuthUser {
+        std::env::set_var("ADMINS", ADMIN);
+        AuthUser {
+            user_id: user.to_string(),
+        }
+    }
+
+    /// An open issue titled `title`, with `involved` involved
+    pub(crate) fn issue(title: &str, involved: &[&str]) -> crate::schemas::Issue {
+        crate::schemas::Issue {
+            title: title.to_string(),
+            involved: Some(involved.iter().map(|user| user.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    /// A bare `json.commit` event `id` about `subject`, without data
+    pub(crate) fn event(id: &str, subject: &str) -> CloudEvent {
+        CloudEventBuilder::new("json.commit", subject)
+            .id(id)
+            .source("test")
+            .build()
+    }
+
+    /// Submit `commit` about `subject` (the issue it belongs to) through the pipeline,
+    /// like POST /events does
+    pub(crate) async fn submit_commit_event(
+        state: &AppState,
+        subject: &str,
+        commit: &JSONCommit,
+    ) -> Result<CloudEvent, Box<dyn std::error::Error + Send + Sync>> {
+        submit_event(state, CloudEventBuilder::commit(subject, commit).build()).await
+    }
+
+    /// Create `issue` as `id` on behalf of `actor`
+    pub(crate) async fn create_issue(
+        state: &AppState,
+        id: &str,
+        issue: &crate::schemas::Issue,
+        actor: &str,
+    ) -> CloudEvent {
+        let commit = CommitBuilder::create(id, issue).actor(actor).build();
+        submit_commit_event(state, id, &commit).await.unwrap()
+    }
+
+    /// AppState backed by a fresh storage and search index in `dir`
+    pub(crate) async fn test_state(dir: &std::path::Path) -> AppState {
+        let storage = Arc::new(Storage::new(&dir.join("data")).await.unwrap());
+        let search = Arc::new(
+            crate::search::SearchIndex::open(
+                dir.join("index"),
+                false,
+                std::time::Duration::from_secs(5),
+            )
+            .unwrap(),
         );
+        let (tx, _rx) = tokio::sync::broadcast::channel(100);
+        let transport = Arc::new(crate::email::MockTransport::new(
+            "http://test.local".to_string(),
+        ));
+        AppState::new(storage, search, tx, Arc::new(EmailService::new(transport)))
+    }
+
+    #[test]
+    fn test_invert_commit() {
+        let before = serde_json::json!({"title": "Paspoort", "status": "open"});
+        let after =
+            serde_json::json!({"title": "Paspoort", "status": "closed", "resolution": "done"});
+        let commit = CommitBuilder::patch::<crate::schemas::Issue>(
+            "issue-1",
+            serde_json::json!({"status": "closed", "resolution": "done"}),
+        )
+        .build();
+
+        let inverse = invert_commit(&commit, Some(&before), Some(&after)).unwrap();
         assert_eq!(
-            extract_resource_type_from_schema("https://other.com/schemas/Task"),
-            "Task"
+            inverse.patch.unwrap(),
+            serde_json::json!({"status": "open", "resolution": null})
         );
-        assert_eq!(extract_resource_type_from_schema("unknown"), "unknown");
+
+        let created = invert_commit(&commit, None, Some(&after)).unwrap();
+        assert_eq!(created.deleted, Some(true));
+
+        let deleted = invert_commit(&commit, Some(&before), None).unwrap();
+        assert_eq!(deleted.resource_data.unwrap(), before);
+
+        assert!(invert_commit(&commit, Some(&before), Some(&before)).is_none());
     }
 
-    #[test]
-    fn test_extract_resource_type_from_subject() {
+    #[tokio::test]
+    async fn test_revert_restores_only_reverted_fields() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let user = "alice@gemeente.nl";
+        create_issue(&state, "issue-1", &issue("Paspoort", &[user]), user).await;
+
+        let accidental = CommitBuilder::patch::<crate::schemas::Issue>(
+            "issue-1",
+            serde_json::json!({"status": "closed"}),
+        )
+        .build();
+        let accidental = submit_commit_event(&state, "issue-1", &accidental)
+            .await
+            .unwrap();
+
+        let later = CommitBuilder::patch::<crate::schemas::Issue>(
+            "issue-1",
+            serde_json::json!({"title": "Paspoort aanvragen"}),
+        )
+        .build();
+        submit_commit_event(&state, "issue-1", &later)
+            .await
+            .unwrap();
+
+        let response = revert_event_handler(
+            State(state.clone()),
+            auth_user(user),
+            HeaderMap::new(),
+            Path(accidental.id.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let resource = state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(resource["status"], "open");
+        assert_eq!(resource["title"], "Paspoort aanvragen");
+
+        let outsider = revert_event_handler(
+            State(state.clone()),
+            auth_user("mallory@example.com"),
+            HeaderMap::new(),
+            Path(accidental.id),
+        )
+        .await;
+        assert_eq!(outsider.unwrap_err(), StatusCode::FORBIDDEN);
+
+        let missing = revert_event_handler(
+            State(state),
+            auth_user(user),
+            HeaderMap::new(),
+            Path("nope".into()),
+        )
+        .await;
+        assert_eq!(missing.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_delete_resource_submits_commit() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@example.com";
+        create_issue(&state, "issue-1", &issue("Paspoort", &[alice]), alice).await;
+        let comment = Comment {
+            content: "Per ongeluk geplaatst".to_string(),
+            quote_comment: None,
+            mentions: None,
+            edited_at: None,
+            internal: None,
+        };
+        let create = CommitBuilder::create("comment-1", &comment)
+            .actor(alice)
+            .build();
+        submit_commit_event(&state, "issue-1", &create)
+            .await
+            .unwrap();
+        let mut events = state.tx.subscribe();
+
+        let delete = |user: &str, id: &str| {
+            delete_resource(
+                State(state.clone()),
+                auth_user(user),
+                HeaderMap::new(),
+                Path(id.to_string()),
+                Query(WriteParams {
+                    subject: Some("issue-1".to_string()),
+                }),
+            )
+        };
+        let outsider = delete("mallory@example.com", "comment-1").await;
+        assert_eq!(outsider.unwrap_err(), StatusCode::FORBIDDEN);
+        let response = delete(alice, "comment-1").await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(state
+            .storage
+            .get_resource("comment-1")
+            .await
+            .unwrap()
+            .is_none());
+
+        let broadcast = events.recv().await.unwrap();
+        let commit = commit_of(&broadcast).unwrap();
+        assert_eq!(commit.deleted, Some(true));
+        assert!(commit.schema.ends_with("/Comment"));
         assert_eq!(
-            extract_resource_type_from_subject("new issue created"),
-            "Issue"
+            state
+                .storage
+                .list_subject_events("issue-1")
+                .await
+                .unwrap()
+                .len(),
+            3
         );
+
         assert_eq!(
-            extract_resource_type_from_subject("comment added"),
-            "Comment"
+            delete(alice, "comment-1").await.unwrap_err(),
+            StatusCode::NOT_FOUND
         );
-        assert_eq!(extract_resource_type_from_subject("unknown"), "unknown");
+    }
+
+    #[tokio::test]
+    async fn test_patch_resource_over_rest() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@example.com";
+        let paspoort = issue("Paspoort", &[alice, "bob@example.com"]);
+        create_issue(&state, "issue-1", &paspoort, alice).await;
+
+        let patch = |user: &str, content_type: &str, id: &str, body: Value| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                axum::http::header::CONTENT_TYPE,
+                content_type.parse().unwrap(),
+            );
+            patch_resource(
+                State(state.clone()),
+                auth_user(user),
+                headers,
+                Path(id.to_string()),
+                Query(WriteParams::default()),
+                Json(body),
+            )
+        };
+        let merge = "application/merge-patch+json";
+        let response = patch(
+            alice,
+            merge,
+            "issue-1",
+            serde_json::json!({"status": "closed"}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let remove_bob = serde_json::json!([{"op": "remove", "path": "/involved/1"}]);
+        patch(alice, "application/json-patch+json", "issue-1", remove_bob)
+            .await
+            .unwrap();
+        let stored = state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored["status"], "closed");
+        assert_eq!(stored["involved"], serde_json::json!([alice]));
+
+        let events = state.storage.list_subject_events("issue-1").await.unwrap();
+        let commit = commit_of(events.last().unwrap()).unwrap();
+        assert_eq!(commit.actor, alice);
+        assert!(commit.schema.ends_with("/Issue"));
+
+        let outsider = patch(
+            "mallory@example.com",
+            merge,
+            "issue-1",
+            serde_json::json!({}),
+        );
+        assert_eq!(outsider.await.unwrap_err(), StatusCode::FORBIDDEN);
+        let missing = patch(alice, merge, "issue-9", serde_json::json!({}));
+        assert_eq!(missing.await.unwrap_err(), StatusCode::NOT_FOUND);
+        let not_an_object = patch(alice, merge, "issue-1", serde_json::json!([1]));
+        assert_eq!(not_an_object.await.unwrap_err(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_resource_as_of() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let created = create_issue(&state, "issue-1", &issue("Bezwaar", &[alice]), alice).await;
+        let comment = Comment {
+            content: "Besluit genomen".to_string(),
+            quote_comment: None,
+            mentions: None,
+            edited_at: None,
+            internal: None,
+        };
+        let comment = CommitBuilder::create("comment-1", &comment).build();
+        let commented = submit_commit_event(&state, "issue-1", &comment)
+            .await
+            .unwrap();
+        let close = CommitBuilder::patch::<crate::schemas::Issue>(
+            "issue-1",
+            serde_json::json!({"status": "closed"}),
+        )
+        .build();
+        submit_commit_event(&state, "issue-1", &close)
+            .await
+            .unwrap();
+
+        let read = |id: &str, as_of: Option<String>, subject: Option<&str>| {
+            get_resource(
+                State(state.clone()),
+                None,
+                Path(id.to_string()),
+                Query(ResourceParams {
+                    as_of,
+                    subject: subject.map(str::to_string),
+                }),
+            )
+        };
+        let at_creation = created.sequencenumber.unwrap().to_string();
+        let Json(issue) = read("issue-1", Some(at_creation.clone()), None)
+            .await
+            .unwrap();
+        assert_eq!(issue["status"], "open");
+        let Json(issue) = read("issue-1", None, None).await.unwrap();
+        assert_eq!(issue["status"], "closed");
+
+        // Thread items are found through their issue
+        let Json(comment) = read("comment-1", commented.time.clone(), Some("issue-1"))
+            .await
+            .unwrap();
+        assert_eq!(comment["content"], "Besluit genomen");
+        let before = read("comment-1", Some(at_creation), Some("issue-1")).await;
+        assert_eq!(before.unwrap_err(), StatusCode::NOT_FOUND);
+        let invalid = read("issue-1", Some("gisteren".to_string()), None).await;
+        assert_eq!(invalid.unwrap_err(), StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
@@ -1235,7 +2516,6 @@ mod tests {
         let state = AppState::new(storage, search, tx, email_service);
 
         use axum::extract::State;
-        use axum::Json;
 
         // Define test user
         let user = "integration@example.com";
@@ -1249,9 +2529,11 @@ mod tests {
             event_type: "json.commit".to_string(),
             subject: issue_id.to_string(),
             time: Some(Utc::now().to_rfc3339()),
+            time_received: None,
             datacontenttype: Some("application/json".to_string()),
             dataschema: None,
             dataref: None,
+            sequencenumber: None,
             sequencetype: None,
             data: Some(serde_json::json!({
                 "resource_id": issue_id,
@@ -1269,9 +2551,14 @@ mod tests {
             sequence: None,
         };
 
-        handle_event(State(state.clone()), Json(issue_event))
-            .await
-            .unwrap();
+        handle_event(
+            State(state.clone()),
+            Some(auth_user(user)),
+            HeaderMap::new(),
+            JsonOrCbor(issue_event),
+        )
+        .await
+        .unwrap();
 
         // 2. Create Comment Event (referencing Issue)
         let comment_id = "comment-int-1";
@@ -1282,9 +2569,11 @@ mod tests {
             event_type: "json.commit".to_string(),
             subject: issue_id.to_string(),
             time: Some(Utc::now().to_rfc3339()),
+            time_received: None,
             datacontenttype: Some("application/json".to_string()),
             dataschema: None,
             dataref: None,
+            sequencenumber: None,
             sequencetype: None,
             data: Some(serde_json::json!({
                 "resource_id": comment_id,
@@ -1305,9 +2594,14 @@ mod tests {
         let mut comment_event = comment_event;
         comment_event.subject = issue_id.to_string();
 
-        handle_event(State(state.clone()), Json(comment_event))
-            .await
-            .unwrap();
+        handle_event(
+            State(state.clone()),
+            Some(auth_user(user)),
+            HeaderMap::new(),
+            JsonOrCbor(comment_event),
+        )
+        .await
+        .unwrap();
 
         // Allow indexing (handle_event calls commit, but let's be safe or wait if needed)
         // handle_event calls search.commit() at the end, so it should be visible.
@@ -1341,18 +2635,25 @@ mod tests {
 }
 
 /// Login Request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub email: String,
 }
 
 /// Login Response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct LoginResponse {
     pub token: String,
 }
 
 /// POST /login - Initiate passwordless login
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses((status = 200, description = "Magic link sent", body = Value))
+)]
 pub async fn login_handler(
     State(state): State<AppState>,
     Json(payload): Json<LoginRequest>,
@@ -1383,18 +2684,41 @@ pub async fn login_handler(
 }
 
 /// GET /auth/verify - Verify magic link token
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct VerifyParams {
+    /// Short-lived token from the magic link
     token: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/auth/verify",
+    tag = "auth",
+    params(VerifyParams),
+    responses(
+        (status = 200, description = "Session token (24h)", body = LoginResponse),
+        (status = 401, description = "Invalid or expired magic link, or a revoked or re-sent invite"),
+    )
+)]
 pub async fn verify_login_handler(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
     Query(params): Query<VerifyParams>,
 ) -> Result<Json<LoginResponse>, StatusCode> {
     // Verify the token directly as a JWT
     match crate::auth::verify_jwt(&params.token) {
         Ok(claims) => {
+            // Invite links only work while the invite is outstanding
+            if let Some(token_id) = &claims.jti {
+                match crate::invites::accept(&state, &claims.sub, token_id).await {
+                    Ok(true) => {}
+                    Ok(false) => return Err(StatusCode::UNAUTHORIZED),
+                    Err(e) => {
+                        eprintln!("[auth] failed to accept invite of {}: {}", claims.sub, e);
+                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                    }
+                }
+            }
             // Token is valid. Issue a new long-lived session JWT (24h).
             match crate::auth::create_jwt(&claims.sub) {
                 Ok(token) => Ok(Json(LoginResponse { token })),
@@ -1441,6 +2765,12 @@ mod tests_access {
 }
 
 /// Reset handler for E2E tests
+#[utoipa::path(
+    post,
+    path = "/reset/",
+    tag = "admin",
+    responses((status = 200, description = "Storage, search index and active users wiped"))
+)]
 pub async fn reset_handler(
     State(state): State<AppState>,
 ) -> Result<impl axum::response::IntoResponse, (axum::http::StatusCode, String)> {
@@ -1460,10 +2790,11 @@ pub async fn reset_handler(
         ));
     }
 
-    // 3. Clear active users
+    // 3. Clear active users and presence
     state.active_users.clear();
+    state.presence.clear();
 
-    println!("[reset] Server state wiped (storage + search + active_users)");
+    println!("[reset] Server state wiped (storage + search + active_users + presence)");
 
     Ok(axum::http::StatusCode::OK)
 }
@@ -0,0 +1,594 @@
+//! Signed inbound webhooks: `POST /hooks/{integration}`.
+//!
+//! External systems (a payment provider, a document scanner, an e-forms vendor) post their
+//! payloads to the hook of their integration. Each integration has a shared secret, kept in
+//! storage rather than as a resource, and a signature scheme:
+//! - `hmac`: the `X-Signature` header holds the HMAC-SHA256 of the raw body, hex or base64
+//!   encoded, optionally prefixed with `sha256=`;
+//! - `jwt`: the `Authorization: Bearer` token is an HS256 JWT with an `exp` claim.
+//!
+//! Requests that fail verification are rejected before the payload is read. Verified
+//! payloads are translated into `json.commit` CloudEvents according to the integration's
+//! kind (see [`IntegrationKind`]) and go through the commit pipeline with source
+//! `hooks/{integration}`. Integrations are managed by admins (see `auth::is_admin`).
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::delivery::{delivery_event, DeliveryChannel, DeliveryOutcome, DeliveryStatus};
+use crate::handlers::{commit_of, internal_error, AppState};
+use crate::mapping::{map_payload, CommitMapping};
+use crate::pipeline::{EventContext, ProcessError};
+use crate::schemas::{
+    CloudEvent, CloudEventBuilder, Comment, CommitBuilder, Document, Issue, IssueStatus,
+};
+
+/// How payloads of an integration become commits
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrationKind {
+    /// `{"zaaknummer", "status", "amount"?, "reference"?}`: a comment on the zaak
+    Payment,
+    /// `{"zaaknummer", "title", "url", "size"?}`: a document on the zaak
+    DocumentScanner,
+    /// `{"form", "email"?, "fields": {...}}`: a new zaak, routed like other new zaken
+    EForms,
+    /// Any payload, translated by the integration's `mapping` (see `mapping`)
+    Mapped,
+    /// A mail provider's bounce or delivery webhook (Postmark format: `{"RecordType",
+    /// "Email" | "Recipient", "Description"?, "Metadata": {"issue", "trigger_event"?}}`):
+    /// the delivery status of a notification (see `delivery`)
+    DeliveryReport,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureScheme {
+    Hmac,
+    Jwt,
+}
+
+/// Settings of an integration, as stored
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct IntegrationSettings {
+    pub kind: IntegrationKind,
+    pub signature: SignatureScheme,
+    /// Shared secret for the HMAC or JWT signature
+    pub secret: String,
+    /// The commits to make from a payload, for `mapped` integrations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mapping: Option<Vec<CommitMapping>>,
+}
+
+/// An integration as listed (without its secret)
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IntegrationInfo {
+    pub name: String,
+    pub kind: IntegrationKind,
+    pub signature: SignatureScheme,
+}
+
+/// Secrets shorter than this are refused
+const MIN_SECRET_LENGTH: usize = 16;
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Decode a signature given as hex, base64 or base64url
+fn decode_signature(signature: &str) -> Option<Vec<u8>> {
+    let signature = signature.trim();
+    let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+    decode_hex(signature)
+        .or_else(|| {
+            base64::engine::general_purpose::STANDARD
+                .decode(signature)
+                .ok()
+        })
+        .or_else(|| {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .decode(signature.trim_end_matches('='))
+                .ok()
+        })
+}
+
+/// The HMAC-SHA256 of `body` with `secret`
+pub(crate) fn hmac_sha256(secret: &str, body: &[u8]) -> Vec<u8> {
+    let key = jsonwebtoken::EncodingKey::from_secret(secret.as_bytes());
+    jsonwebtoken::crypto::sign(body, &key, jsonwebtoken::Algorithm::HS256)
+        .ok()
+        .and_then(|s| {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .decode(s)
+                .ok()
+        })
+        .expect("HMAC signing does not fail")
+}
+
+/// Does the request carry a valid signature for `settings`?
+pub fn verify_signature(settings: &IntegrationSettings, headers: &HeaderMap, body: &[u8]) -> bool {
+    match settings.signature {
+        SignatureScheme::Hmac => {
+            let Some(given) = headers
+                .get("x-signature")
+                .and_then(|v| v.to_str().ok())
+                .and_then(decode_signature)
+            else {
+                return false;
+            };
+            let expected = hmac_sha256(&settings.secret, body);
+            // Compare every byte, so the time taken doesn't reveal the matching prefix
+            given.len() == expected.len()
+                && given.iter().zip(&expected).fold(0, |d, (a, b)| d | (a ^ b)) == 0
+        }
+        SignatureScheme::Jwt => {
+            let Some(token) = headers
+                .get("authorization")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+            else {
+                return false;
+            };
+            jsonwebtoken::decode::<serde_json::Value>(
+                token,
+                &jsonwebtoken::DecodingKey::from_secret(settings.secret.as_bytes()),
+                &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256),
+            )
+            .is_ok()
+        }
+    }
+}
+
+fn text(payload: &serde_json::Value, field: &str) -> Option<String> {
+    payload
+        .get(field)
+        .and_then(|v| v.as_str())
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+/// Translate a payload into the commit events it stands for. The zaken the events are
+/// about must exist, unless an event creates its zaak; that is checked by the caller.
+pub fn translate(
+    integration: &str,
+    settings: &IntegrationSettings,
+    payload: &serde_json::Value,
+) -> Result<Vec<CloudEvent>, String> {
+    let actor = format!("hooks/{}", integration);
+    let event = |subject: &str, commit| {
+        CloudEventBuilder::commit(subject, &commit)
+            .source(actor.clone())
+            .build()
+    };
+    let zaaknummer = || text(payload, "zaaknummer").ok_or("missing zaaknummer");
+
+    match settings.kind {
+        IntegrationKind::Payment => {
+            let zaaknummer = zaaknummer()?;
+            let status = text(payload, "status").ok_or("missing status")?;
+            let mut content = format!("Betaling {}", status);
+            if let Some(amount) = payload.get("amount").and_then(|v| v.as_f64()) {
+                content.push_str(&format!(": € {:.2}", amount).replace('.', ","));
+            }
+            if let Some(reference) = text(payload, "reference") {
+                content.push_str(&format!(" (referentie {})", reference));
+            }
+            let comment = Comment {
+                content,
+                quote_comment: None,
+                mentions: None,
+                edited_at: None,
+                internal: None,
+            };
+            let commit = CommitBuilder::create(uuid::Uuid::now_v7().to_string(), &comment)
+                .actor(actor.clone())
+                .build();
+            Ok(vec![event(&zaaknummer, commit)])
+        }
+        IntegrationKind::DocumentScanner => {
+            let zaaknummer = zaaknummer()?;
+            let document = Document {
+                title: text(payload, "title").ok_or("missing title")?,
+                url: text(payload, "url").ok_or("missing url")?,
+                size: payload.get("size").and_then(|v| v.as_u64()).unwrap_or(0),
+            };
+            let commit = CommitBuilder::create(uuid::Uuid::now_v7().to_string(), &document)
+                .actor(actor.clone())
+                .build();
+            Ok(vec![event(&zaaknummer, commit)])
+        }
+        IntegrationKind::EForms => {
+            let form = text(payload, "form").ok_or("missing form")?;
+            let fields = payload
+                .get("fields")
+                .and_then(|v| v.as_object())
+                .ok_or("missing fields")?;
+            let description = fields
+                .iter()
+                .map(|(name, value)| match value {
+                    serde_json::Value::String(s) => format!("{}: {}", name, s),
+                    other => format!("{}: {}", name, other),
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            let issue = Issue {
+                title: form,
+                description: Some(description),
+                status: IssueStatus::Open,
+                involved: text(payload, "email").map(|email| vec![email]),
+                ..Default::default()
+            };
+            let id = uuid::Uuid::now_v7().to_string();
+            let commit = CommitBuilder::create(id.clone(), &issue)
+                .actor(actor.clone())
+                .build();
+            Ok(vec![event(&id, commit)])
+        }
+        IntegrationKind::Mapped => {
+            let mapping = settings.mapping.as_deref().unwrap_or_default();
+            map_payload(mapping, payload, &actor)
+        }
+        IntegrationKind::DeliveryReport => {
+            let outcome = match text(payload, "RecordType").as_deref() {
+                Some("Bounce") => DeliveryOutcome::Bounced,
+                Some("Delivery") => DeliveryOutcome::Sent,
+                other => return Err(format!("unsupported RecordType {:?}", other)),
+            };
+            let metadata = payload.get("Metadata").unwrap_or(&serde_json::Value::Null);
+            let issue = text(metadata, "issue").ok_or("missing Metadata.issue")?;
+            let status = DeliveryStatus {
+                channel: DeliveryChannel::Email,
+                recipient: text(payload, "Email")
+                    .or_else(|| text(payload, "Recipient"))
+                    .ok_or("missing Email")?,
+                outcome,
+                trigger_event: text(metadata, "trigger_event"),
+                error: (outcome == DeliveryOutcome::Bounced)
+                    .then(|| text(payload, "Description"))
+                    .flatten(),
+            };
+            Ok(vec![delivery_event(&issue, &actor, &status)])
+        }
+    }
+}
+
+async fn load_settings(
+    state: &AppState,
+    integration: &str,
+) -> Result<Option<IntegrationSettings>, StatusCode> {
+    Ok(state
+        .storage
+        .get_integration(integration)
+        .await
+        .map_err(internal_error)?
+        .and_then(|settings| serde_json::from_str(&settings).ok()))
+}
+
+/// POST /hooks/{integration} - Receive a signed payload from an external system
+#[utoipa::path(
+    post,
+    path = "/hooks/{integration}",
+    tag = "events",
+    params(("integration" = String, Path, description = "Integration name")),
+    request_body(content = Object, description = "Payload in the format of the integration's kind"),
+    responses(
+        (status = 202, description = "Payload translated; the resulting events were stored, processed and broadcast", body = Vec<CloudEvent>),
+        (status = 401, description = "Missing or invalid signature"),
+        (status = 404, description = "Unknown integration"),
+        (status = 422, description = "Payload could not be translated, or refers to an unknown zaak"),
+    )
+)]
+pub async fn receive_hook_handler(
+    State(state): State<AppState>,
+    Path(integration): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<Vec<CloudEvent>>), StatusCode> {
+    let settings = load_settings(&state, &integration)
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if !verify_signature(&settings, &headers, &body) {
+        eprintln!("[hooks] rejected {}: invalid signature", integration);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let unprocessable = |reason: String| {
+        eprintln!(
+            "[hooks] could not translate {} payload: {}",
+            integration, reason
+        );
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+    let payload: serde_json::Value =
+        serde_json::from_slice(&body).map_err(|e| unprocessable(e.to_string()))?;
+    let events = translate(&integration, &settings, &payload).map_err(unprocessable)?;
+
+    let mut submitted = Vec::new();
+    for event in events {
+        let subject = event.subject.clone();
+        let creates_issue = commit_of(&event)
+            .is_some_and(|c| c.resource_id == subject && c.resource_data.is_some());
+        if !creates_issue
+            && state
+                .storage
+                .get_resource(&subject)
+                .await
+                .map_err(internal_error)?
+                .is_none()
+        {
+            return Err(unprocessable(format!("unknown zaak {}", subject)));
+        }
+        // Integrations feed events in from outside, so workflow rules apply
+        let event = state
+            .pipeline
+            .submit(&state, EventContext::new(event).from_integration())
+            .await
+            .map_err(|e| match e {
+                ProcessError::Invalid(reason) => unprocessable(reason),
+                ProcessError::Conflict(conflict) => {
+                    eprintln!("[hooks] stale commit to {}", conflict.resource_id);
+                    StatusCode::CONFLICT
+                }
+                ProcessError::Stale(reason) => {
+                    eprintln!("[hooks] stale commit: {}", reason);
+                    StatusCode::CONFLICT
+                }
+                e => internal_error(e),
+            })?;
+        submitted.push(event);
+    }
+    Ok((StatusCode::ACCEPTED, Json(submitted)))
+}
+
+/// GET /integrations - List the inbound webhook integrations
+#[utoipa::path(
+    get,
+    path = "/integrations",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Integrations, without their secrets", body = Vec<IntegrationInfo>),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+    )
+)]
+pub async fn list_integrations_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<IntegrationInfo>>, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let integrations = state
+        .storage
+        .list_integrations()
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .filter_map(|(name, settings)| {
+            let settings: IntegrationSettings = serde_json::from_str(&settings).ok()?;
+            Some(IntegrationInfo {
+                name,
+                kind: settings.kind,
+                signature: settings.signature,
+            })
+        })
+        .collect();
+    Ok(Json(integrations))
+}
+
+/// PUT /integrations/{name} - Create or update an inbound webhook integration
+#[utoipa::path(
+    put,
+    path = "/integrations/{name}",
+    tag = "admin",
+    params(("name" = String, Path, description = "Integration name, used in `/hooks/{integration}`")),
+    request_body = IntegrationSettings,
+    security(("bearer" = [])),
+    responses(
+        (status = 204, description = "Integration stored"),
+        (status = 400, description = "Name is not lowercase letters, digits and dashes, the secret is too short, or a mapped integration has no valid mapping"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+    )
+)]
+pub async fn update_integration_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(name): Path<String>,
+    Json(settings): Json<IntegrationSettings>,
+) -> Result<StatusCode, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid_name || settings.secret.len() < MIN_SECRET_LENGTH {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if settings.kind == IntegrationKind::Mapped {
+        let mapping = settings.mapping.as_deref().unwrap_or_default();
+        if mapping.is_empty() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        if let Err(e) = mapping.iter().try_for_each(CommitMapping::validate) {
+            eprintln!("[hooks] refused mapping of {}: {}", name, e);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    let json = serde_json::to_string(&settings).map_err(internal_error)?;
+    state
+        .storage
+        .set_integration(&name, Some(&json))
+        .await
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// DELETE /integrations/{name} - Remove an inbound webhook integration
+#[utoipa::path(
+    delete,
+    path = "/integrations/{name}",
+    tag = "admin",
+    params(("name" = String, Path, description = "Integration name")),
+    security(("bearer" = [])),
+    responses(
+        (status = 204, description = "Integration removed; its hook no longer accepts payloads"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+        (status = 404, description = "Unknown integration"),
+    )
+)]
+pub async fn delete_integration_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(name): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if load_settings(&state, &name).await?.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    state
+        .storage
+        .set_integration(&name, None)
+        .await
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{create_issue, issue, test_state};
+
+    const SECRET: &str = "een-lang-gedeeld-geheim";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    async fn configure(state: &AppState, name: &str, settings: IntegrationSettings) {
+        let json = serde_json::to_string(&settings).unwrap();
+        state
+            .storage
+            .set_integration(name, Some(&json))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hmac_signed_document() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        configure(
+            &state,
+            "scanner",
+            IntegrationSettings {
+                kind: IntegrationKind::DocumentScanner,
+                signature: SignatureScheme::Hmac,
+                secret: SECRET.to_string(),
+                mapping: None,
+            },
+        )
+        .await;
+        let alice = "alice@gemeente.nl";
+        create_issue(&state, "issue-1", &issue("Bouwvergunning", &[alice]), alice).await;
+
+        let body = Bytes::from(
+            r#"{"zaaknummer": "issue-1", "title": "Tekening.pdf", "url": "https://scan.example/1"}"#,
+        );
+        let post = |signature: String, body: Bytes| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-signature", signature.parse().unwrap());
+            receive_hook_handler(
+                State(state.clone()),
+                Path("scanner".to_string()),
+                headers,
+                body,
+            )
+        };
+
+        let forged = hex(&hmac_sha256("ander-geheim-van-16+", &body));
+        assert_eq!(
+            post(forged, body.clone()).await.unwrap_err(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let signature = format!("sha256={}", hex(&hmac_sha256(SECRET, &body)));
+        let (status, Json(events)) = post(signature, body).await.unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(events[0].subject, "issue-1");
+        assert_eq!(events[0].source, "hooks/scanner");
+
+        let unknown = Bytes::from(r#"{"zaaknummer": "issue-9", "title": "x", "url": "y"}"#);
+        let signature = hex(&hmac_sha256(SECRET, &unknown));
+        assert_eq!(
+            post(signature, unknown).await.unwrap_err(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jwt_signed_form_creates_issue() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        configure(
+            &state,
+            "eformulieren",
+            IntegrationSettings {
+                kind: IntegrationKind::EForms,
+                signature: SignatureScheme::Jwt,
+                secret: SECRET.to_string(),
+                mapping: None,
+            },
+        )
+        .await;
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &serde_json::json!({ "exp": chrono::Utc::now().timestamp() + 60 }),
+            &jsonwebtoken::EncodingKey::from_secret(SECRET.as_bytes()),
+        )
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        let body = Bytes::from(
+            r#"{"form": "Melding evenement", "email": "jan@example.com", "fields": {"datum": "2024-06-01", "bezoekers": 300}}"#,
+        );
+
+        let (_, Json(events)) = receive_hook_handler(
+            State(state.clone()),
+            Path("eformulieren".to_string()),
+            headers,
+            body,
+        )
+        .await
+        .unwrap();
+        let id = events[0].subject.clone();
+        let issue: Issue =
+            serde_json::from_value(state.storage.get_resource(&id).await.unwrap().unwrap())
+                .unwrap();
+        assert_eq!(issue.title, "Melding evenement");
+        assert_eq!(issue.involved, Some(vec!["jan@example.com".to_string()]));
+        assert!(issue.description.unwrap().contains("bezoekers: 300"));
+    }
+}
@@ -0,0 +1,597 @@
+//! Startup integrity check of the search index.
+//!
+//! The search index lives next to the database but is not part of it: after restoring a
+//! backup of `data.redb`, or losing `search_index`, searches would quietly return nothing (or
+//! results of another point in time). On start the server compares the number of stored
+//! events with the number of indexed ones; when the index is missing or clearly stale, it is
+//! rebuilt in the background from the event log and the stored resources. `GET /health`
+//! reports the check and the progress of the rebuild.
+//!
+//! A rebuild reads the log and the resources page by page and hands the pages to worker
+//! tasks, which feed the Tantivy writer side by side (`REINDEX_WORKERS`, by default one per
+//! CPU). Admins start a rebuild with `POST /admin/search/reindex` and cancel it with
+//! `DELETE /admin/search/reindex`; what was indexed until then is kept.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::task::JoinSet;
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{commit_of, AppState};
+
+/// Events and resources indexed per step of a rebuild
+pub const PAGE_SIZE: usize = 500;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Stored and indexed record counts
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct IndexCheck {
+    pub events_stored: u64,
+    pub resources_stored: u64,
+    pub events_indexed: u64,
+    pub documents_indexed: u64,
+}
+
+impl IndexCheck {
+    /// Is the index empty while there are events?
+    pub fn is_missing(&self) -> bool {
+        self.events_stored > 0 && self.documents_indexed == 0
+    }
+
+    /// Do the indexed events differ from the stored ones by more than 1%? Every event is
+    /// indexed once, so after a clean start the counts are equal.
+    pub fn is_stale(&self) -> bool {
+        let tolerance = self.events_stored / 100;
+        self.events_stored.abs_diff(self.events_indexed) > tolerance
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexState {
+    /// The startup check has not run yet
+    #[default]
+    Unchecked,
+    Ok,
+    Reindexing,
+    Failed,
+    /// The rebuild was cancelled; the index is incomplete
+    Cancelled,
+}
+
+/// The state of the search index, for `GET /health`
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct IndexStatus {
+    pub state: IndexState,
+    /// The counts found by the startup check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check: Option<IndexCheck>,
+    /// Progress of the rebuild: records indexed of `total`
+    pub indexed: u64,
+    pub total: u64,
+    /// Worker tasks of the rebuild
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workers: Option<usize>,
+    /// Records indexed per second so far
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_second: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Shared [`IndexStatus`], updated by the check and the rebuild
+#[derive(Debug, Default)]
+pub struct IndexHealth {
+    status: RwLock<IndexStatus>,
+    cancel: AtomicBool,
+}
+
+impl IndexHealth {
+    pub fn status(&self) -> IndexStatus {
+        self.status
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut IndexStatus)) {
+        f(&mut self.status.write().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    /// Mark a rebuild of `total` records as started; `false` when one is running already.
+    pub fn begin(&self, total: u64, workers: usize) -> bool {
+        let mut status = self.status.write().unwrap_or_else(|e| e.into_inner());
+        if status.state == IndexState::Reindexing {
+            return false;
+        }
+        self.cancel.store(false, Ordering::SeqCst);
+        status.state = IndexState::Reindexing;
+        status.indexed = 0;
+        status.total = total;
+        status.workers = Some(workers);
+        status.per_second = None;
+        status.started_at = Some(Utc::now());
+        status.finished_at = None;
+        status.error = None;
+        true
+    }
+
+    /// Ask the running rebuild to stop; `false` when none is running.
+    pub fn cancel(&self) -> bool {
+        let reindexing = self.status().state == IndexState::Reindexing;
+        if reindexing {
+            self.cancel.store(true, Ordering::SeqCst);
+        }
+        reindexing
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+}
+
+/// Worker tasks for a rebuild: `REINDEX_WORKERS`, or one per CPU.
+pub fn reindex_workers() -> usize {
+    std::env::var("REINDEX_WORKERS")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(4)
+        })
+}
+
+/// Compare the stored records with the indexed documents.
+pub async fn check(
+    state: &AppState,
+) -> Result<IndexCheck, Box<dyn std::error::Error + Send + Sync>> {
+    let (events_stored, resources_stored) = state.storage.record_counts().await?;
+    let (events_indexed, documents_indexed) = state.search.doc_counts()?;
+    Ok(IndexCheck {
+        events_stored,
+        resources_stored,
+        events_indexed,
+        documents_indexed,
+    })
+}
+
+/// Index every stored event and resource again with `reindex_workers()` workers, reporting
+/// progress to `state.index_health`. Documents are replaced, so searching keeps working while
+/// it runs. Returns the number of records indexed.
+pub async fn reindex(state: &AppState) -> Result<u64, BoxError> {
+    reindex_with(state, reindex_workers(), PAGE_SIZE).await
+}
+
+/// `reindex` with `workers` tasks, each indexing `page_size` records at a time.
+pub async fn reindex_with(
+    state: &AppState,
+    workers: usize,
+    page_size: usize,
+) -> Result<u64, BoxError> {
+    let (events, resources) = state.storage.record_counts().await?;
+    if !state.index_health.begin(events + resources, workers) {
+        return Err("the search index is being rebuilt already".into());
+    }
+    run_reindex(state, workers, page_size).await
+}
+
+/// Run the rebuild `IndexHealth::begin` started, and record how it ended.
+async fn run_reindex(state: &AppState, workers: usize, page_size: usize) -> Result<u64, BoxError> {
+    let mut rebuild = Rebuild {
+        state: state.clone(),
+        tasks: JoinSet::new(),
+        workers: workers.max(1),
+        indexed: 0,
+        started: Instant::now(),
+    };
+    let result = rebuild.run(page_size.max(1)).await;
+    // Keep what was indexed, also when cancelled
+    rebuild.tasks.abort_all();
+    let committed = state.search.commit().await;
+    let result = result.and_then(|indexed| committed.map(|_| indexed));
+    let cancelled = state.index_health.cancelled();
+    state.index_health.update(|status| {
+        status.finished_at = Some(Utc::now());
+        match &result {
+            Ok(_) => status.state = IndexState::Ok,
+            Err(_) if cancelled => status.state = IndexState::Cancelled,
+            Err(e) => {
+                status.state = IndexState::Failed;
+                status.error = Some(e.to_string());
+            }
+        }
+    });
+    result
+}
+
+/// A running rebuild: pages of records being indexed by at most `workers` tasks
+struct Rebuild {
+    state: AppState,
+    tasks: JoinSet<Result<u64, BoxError>>,
+    workers: usize,
+    indexed: u64,
+    started: Instant,
+}
+
+impl Rebuild {
+    async fn run(&mut self, page_size: usize) -> Result<u64, BoxError> {
+        // The subject and time of the last commit of every resource, as the indexer had them
+        let mut last_commits: HashMap<String, (String, Option<DateTime<Utc>>)> = HashMap::new();
+        let mut after = None;
+        loop {
+            self.check_cancelled()?;
+            let page = self
+                .state
+                .storage
+                .list_events_after(after.clone(), page_size)
+                .await?;
+            for event in &page {
+                if let Some(commit) = commit_of(event) {
+                    let timestamp = commit
+                        .timestamp
+                        .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+                        .map(|dt| dt.with_timezone(&Utc));
+                    last_commits.insert(commit.resource_id, (event.subject.clone(), timestamp));
+                }
+            }
+            let last = page.last().and_then(|event| event.sequence.clone());
+            let full = page.len() == page_size;
+            if !page.is_empty() {
+                let state = self.state.clone();
+                self.spawn(async move {
+                    for event in &page {
+                        let payload = serde_json::to_string(event)?;
+                        state
+                            .search
+                            .add_event_payload(&event.id, "Event", "", &payload, None)
+                            .await?;
+                    }
+                    Ok(page.len() as u64)
+                })
+                .await?;
+            }
+            match last {
+                Some(last) if full => after = Some(last),
+                last => {
+                    self.finish().await?;
+                    self.state.search.commit().await?;
+                    self.state.search.stats().record_indexed(last.as_deref());
+                    break;
+                }
+            }
+        }
+
+        let last_commits = Arc::new(last_commits);
+        let mut after: Option<String> = None;
+        loop {
+            self.check_cancelled()?;
+            let page = self
+                .state
+                .storage
+                .list_typed_resources_after(after.as_deref(), page_size)
+                .await?;
+            let full = page.len() == page_size;
+            after = page.last().map(|(id, _, _)| id.clone());
+            if !page.is_empty() {
+                let (state, last_commits) = (self.state.clone(), last_commits.clone());
+                self.spawn(async move {
+                    for (id, resource_type, data) in &page {
+                        let last_commit = last_commits.get(id);
+                        crate::pipeline::index_resource(
+                            &state,
+                            id,
+                            resource_type,
+                            data,
+                            last_commit.map(|(subject, _)| subject.as_str()),
+                            last_commit.and_then(|(_, timestamp)| *timestamp),
+                        )
+                        .await?;
+                    }
+                    Ok(page.len() as u64)
+                })
+                .await?;
+            }
+            if !full {
+                break;
+            }
+        }
+        self.finish().await?;
+        Ok(self.indexed)
+    }
+
+    fn check_cancelled(&self) -> Result<(), BoxError> {
+        match self.state.index_health.cancelled() {
+            true => Err("reindexing was cancelled".into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Index a page in a new task, once fewer than `workers` are busy.
+    async fn spawn(
+        &mut self,
+        task: impl std::future::Future<Output = Result<u64, BoxError>> + Send + 'static,
+    ) -> Result<(), BoxError> {
+        while self.tasks.len() >= self.workers {
+            self.join_next().await?;
+        }
+        self.tasks.spawn(task);
+        Ok(())
+    }
+
+    /// Wait for every busy task.
+    async fn finish(&mut self) -> Result<(), BoxError> {
+        while !self.tasks.is_empty() {
+            self.join_next().await?;
+        }
+        Ok(())
+    }
+
+    async fn join_next(&mut self) -> Result<(), BoxError> {
+        let Some(result) = self.tasks.join_next().await else {
+            return Ok(());
+        };
+        self.indexed += result??;
+        let (indexed, seconds) = (self.indexed, self.started.elapsed().as_secs_f64());
+        self.state.index_health.update(|status| {
+            status.indexed = indexed;
+            status.per_second = (seconds > 0.0).then(|| indexed as f64 / seconds);
+        });
+        Ok(())
+    }
+}
+
+/// Check the index, and rebuild it in the background when it is missing or stale.
+pub fn spawn_startup_check(state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let counts = match check(&state).await {
+            Ok(counts) => counts,
+            Err(e) => {
+                eprintln!("[integrity] failed to check the search index: {}", e);
+                state.index_health.update(|status| {
+                    status.state = IndexState::Failed;
+                    status.error = Some(e.to_string());
+                });
+                return;
+            }
+        };
+        state
+            .index_health
+            .update(|status| status.check = Some(counts));
+        if !counts.is_missing() && !counts.is_stale() {
+            // Indexed up to the log, as far as the counts tell
+            if let Ok(latest) = state.storage.latest_sequence().await {
+                state.search.stats().record_indexed(latest.as_deref());
+            }
+            state
+                .index_health
+                .update(|status| status.state = IndexState::Ok);
+            return;
+        }
+        println!(
+            "[integrity] search index {} ({} of {} events indexed), reindexing",
+            if counts.is_missing() {
+                "missing"
+            } else {
+                "stale"
+            },
+            counts.events_indexed,
+            counts.events_stored
+        );
+        match reindex(&state).await {
+            Ok(n) => println!("[integrity] reindexed {} events and resources", n),
+            Err(e) => eprintln!("[integrity] reindexing failed: {}", e),
+        }
+    })
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Health {
+    /// "ok", or "degraded" while the search index is incomplete
+    pub status: String,
+    pub search_index: IndexStatus,
+}
+
+/// GET /health - Whether the server is up, and the state of the search index
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Up; `status` is degraded while search results may be incomplete", body = Health),
+    )
+)]
+pub async fn health_handler(State(state): State<AppState>) -> Json<Health> {
+    let search_index = state.index_health.status();
+    let status = match search_index.state {
+        IndexState::Reindexing | IndexState::Failed | IndexState::Cancelled => "degraded",
+        IndexState::Unchecked | IndexState::Ok => "ok",
+    };
+    Json(Health {
+        status: status.to_string(),
+        search_index,
+    })
+}
+
+/// POST /admin/search/reindex - Rebuild the search index in the background
+#[utoipa::path(
+    post,
+    path = "/admin/search/reindex",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Rebuild started; follow it at /health", body = IndexStatus),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+        (status = 409, description = "A rebuild is running already"),
+    )
+)]
+pub async fn start_reindex_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<(StatusCode, Json<IndexStatus>), StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let (events, resources) = state.storage.record_counts().await.map_err(|e| {
+        eprintln!("[integrity] failed to count records: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let workers = reindex_workers();
+    if !state.index_health.begin(events + resources, workers) {
+        return Err(StatusCode::CONFLICT);
+    }
+    println!(
+        "[integrity] {} started reindexing with {} workers",
+        auth_user.user_id, workers
+    );
+    let status = state.index_health.status();
+    tokio::spawn(async move {
+        match run_reindex(&state, workers, PAGE_SIZE).await {
+            Ok(n) => println!("[integrity] reindexed {} events and resources", n),
+            Err(e) => eprintln!("[integrity] reindexing stopped: {}", e),
+        }
+    });
+    Ok((StatusCode::ACCEPTED, Json(status)))
+}
+
+/// DELETE /admin/search/reindex - Cancel the running rebuild
+#[utoipa::path(
+    delete,
+    path = "/admin/search/reindex",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "The rebuild stops after the pages being indexed"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+        (status = 404, description = "No rebuild is running"),
+    )
+)]
+pub async fn cancel_reindex_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> StatusCode {
+    if !auth_user.is_admin() {
+        return StatusCode::FORBIDDEN;
+    }
+    if !state.index_health.cancel() {
+        return StatusCode::NOT_FOUND;
+    }
+    println!("[integrity] {} cancelled reindexing", auth_user.user_id);
+    StatusCode::ACCEPTED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{
+        auth_user as user, create_issue, issue, submit_commit_event, test_state, ADMIN,
+    };
+    use crate::schemas::{Comment, CommitBuilder};
+
+    #[tokio::test]
+    async fn test_missing_index_is_rebuilt() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@example.com";
+        let paspoort = issue("Paspoort verlopen", &[alice]);
+        create_issue(&state, "issue-1", &paspoort, alice).await;
+        let comment = Comment {
+            content: "Afspraak ingepland".to_string(),
+            quote_comment: None,
+            mentions: None,
+            edited_at: None,
+            internal: None,
+        };
+        let commit = CommitBuilder::create("comment-1", &comment).build();
+        submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+
+        let counts = check(&state).await.unwrap();
+        assert_eq!((counts.events_stored, counts.events_indexed), (2, 2));
+        assert!(!counts.is_missing() && !counts.is_stale());
+
+        // As after restoring a backup without the index
+        state.search.clear().await.unwrap();
+        let counts = check(&state).await.unwrap();
+        assert!(counts.is_missing() && counts.is_stale());
+
+        assert_eq!(reindex(&state).await.unwrap(), 4);
+        let counts = check(&state).await.unwrap();
+        assert_eq!(counts.events_indexed, 2);
+        assert_eq!(counts.documents_indexed, 4);
+        let status = state.index_health.status();
+        assert_eq!(status.state, IndexState::Ok);
+        assert_eq!((status.indexed, status.total), (4, 4));
+
+        // The comment is found by those involved in its issue again
+        let query = crate::search::SearchIndex::apply_authorization_filter("*", alice);
+        let results = state
+            .search
+            .search_best_effort(&state.storage, &query, 10)
+            .await;
+        assert!(results.iter().any(|r| r.id == "comment-1"));
+    }
+
+    #[tokio::test]
+    async fn test_parallel_reindex_and_cancel() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        for i in 0..25 {
+            let id = format!("issue-{}", i);
+            create_issue(&state, &id, &issue(&format!("Melding {}", i), &[]), ADMIN).await;
+        }
+        state.search.clear().await.unwrap();
+
+        // Pages of 3 over 4 workers
+        assert_eq!(reindex_with(&state, 4, 3).await.unwrap(), 50);
+        let counts = check(&state).await.unwrap();
+        assert_eq!((counts.events_indexed, counts.documents_indexed), (25, 50));
+        let status = state.index_health.status();
+        assert_eq!(status.state, IndexState::Ok);
+        assert_eq!((status.indexed, status.workers), (50, Some(4)));
+        assert!(status.per_second.is_some());
+
+        // One rebuild at a time
+        assert!(state.index_health.begin(50, 2));
+        assert!(reindex_with(&state, 2, 3).await.is_err());
+        assert!(state.index_health.cancel());
+        assert!(run_reindex(&state, 2, 3).await.is_err());
+        assert_eq!(state.index_health.status().state, IndexState::Cancelled);
+        assert!(!state.index_health.cancel());
+        assert_eq!(
+            cancel_reindex_handler(State(state.clone()), user(ADMIN)).await,
+            StatusCode::NOT_FOUND
+        );
+
+        // Only admins start or cancel a rebuild
+        let alice = "alice@gemeente.nl";
+        let forbidden = start_reindex_handler(State(state.clone()), user(alice)).await;
+        assert_eq!(forbidden.unwrap_err(), StatusCode::FORBIDDEN);
+        assert!(state.index_health.begin(50, 2));
+        assert_eq!(
+            cancel_reindex_handler(State(state.clone()), user(alice)).await,
+            StatusCode::FORBIDDEN
+        );
+        let running = start_reindex_handler(State(state.clone()), user(ADMIN)).await;
+        assert_eq!(running.unwrap_err(), StatusCode::CONFLICT);
+        assert_eq!(
+            cancel_reindex_handler(State(state), user(ADMIN)).await,
+            StatusCode::ACCEPTED
+        );
+    }
+}
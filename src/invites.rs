@@ -0,0 +1,550 @@
+//! Invites: giving someone outside the organisation access to an issue by email.
+//!
+//! An invite is a resource `invite-{issue}-{email}` with a token id. The invite email
+//! contains a magic link whose JWT carries that id (`jti`); only when that link is used does
+//! the invitee become involved in the issue. Behandelaars can list outstanding invites, change
+//! their expiry, re-send them (which replaces the token id, so older links stop working) and
+//! revoke them (which also removes the invitee from `involved`). Every change is a commit on
+//! the issue.
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{authorize_issue, internal_error, submit_commit, submit_event, AppState};
+use crate::schemas::{CloudEvent, CloudEventBuilder, CommitBuilder, Invite, InviteStatus, Issue};
+
+/// How long an invite can be accepted when no expiry is given
+const DEFAULT_VALIDITY_DAYS: i64 = 14;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct InviteRequest {
+    pub email: String,
+    /// Until when the invite can be accepted (RFC 3339); two weeks if omitted
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ExpiryRequest {
+    /// Until when the invite can be accepted (RFC 3339)
+    pub expires_at: String,
+}
+
+/// Resource ID of the invite of `email` to `issue_id`
+pub fn invite_id(issue_id: &str, email: &str) -> String {
+    format!("invite-{}-{}", issue_id, email)
+}
+
+fn new_token_id() -> String {
+    uuid::Uuid::now_v7().to_string()
+}
+
+fn parse_time(time: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|t| t.with_timezone(&chrono::Utc))
+}
+
+/// Can the invite still be accepted at `now`?
+pub fn is_outstanding(invite: &Invite, now: chrono::DateTime<chrono::Utc>) -> bool {
+    invite.status == InviteStatus::Pending
+        && parse_time(&invite.expires_at).is_some_and(|expires| now < expires)
+}
+
+async fn load_issue(state: &AppState, issue_id: &str) -> Result<Issue, StatusCode> {
+    state
+        .storage
+        .get_resource(issue_id)
+        .await
+        .map_err(internal_error)?
+        .and_then(|value| serde_json::from_value(value).ok())
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn load_invite(state: &AppState, issue_id: &str, email: &str) -> Result<Invite, StatusCode> {
+    state
+        .storage
+        .get_resource(&invite_id(issue_id, email))
+        .await
+        .map_err(internal_error)?
+        .and_then(|value| serde_json::from_value(value).ok())
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Email the invite's magic link. The JWT lives as long as the invite.
+fn send_invite(state: &AppState, invite: &Invite, title: &str) -> Result<(), StatusCode> {
+    let valid_for = parse_time(&invite.expires_at)
+        .map(|expires| expires - chrono::Utc::now())
+        .filter(|d| *d > chrono::Duration::zero())
+        .ok_or(StatusCode::CONFLICT)?;
+    let token =
+        crate::auth::create_jwt_with_id(&invite.email, valid_for, Some(invite.token_id.clone()))
+            .map_err(|e| {
+                eprintln!("[invites] failed to create JWT for {}: {}", invite.email, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+    let base_url = std::env::var("BASE_URL").unwrap_or_else(|_| "https://zaakchat.nl".to_string());
+    let link = format!(
+        "{}/verify-login?token={}&redirect=/zaak/{}",
+        base_url, token, invite.issue_id
+    );
+    let subject = format!("Uitnodiging voor Zaak: {}", title);
+    let text_body = format!(
+        "{} nodigt je uit om mee te praten over de zaak \"{}\".\n\nBekijk in ZaakChat: {}",
+        invite.invited_by, title, link
+    );
+    let html_body = format!(
+        "<html><body><p>{} nodigt je uit om mee te praten over de zaak \"{}\".</p><p><a href=\"{}\">Bekijk in ZaakChat</a></p></body></html>",
+        invite.invited_by, title, link
+    );
+
+    tokio::spawn({
+        let email_service = state.email_service.clone();
+        let recipient = invite.email.clone();
+        let issue_id = invite.issue_id.clone();
+        async move {
+            if let Err(e) = email_service
+                .send_notification(
+                    &recipient,
+                    &subject,
+                    &html_body,
+                    &text_body,
+                    None,
+                    Some(&issue_id),
+                )
+                .await
+            {
+                eprintln!("[invites] failed to send invite to {}: {}", recipient, e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Set `involved` of an issue with a commit by `actor`. The event's source is the actor too,
+/// so they are not notified about their own change.
+async fn update_involved(
+    state: &AppState,
+    issue_id: &str,
+    involved: Vec<String>,
+    actor: &str,
+) -> Result<(), StatusCode> {
+    let commit = CommitBuilder::patch::<Issue>(issue_id, json!({ "involved": involved }))
+        .actor(actor)
+        .build();
+    let event = CloudEventBuilder::commit(issue_id, &commit)
+        .source(actor)
+        .build();
+    submit_event(state, event).await.map_err(internal_error)?;
+    Ok(())
+}
+
+/// Accept the invite whose magic link carried `token_id`: mark it accepted and make `email`
+/// involved in the issue. Returns false if no outstanding invite has this token (revoked,
+/// re-sent, expired, or for another address).
+pub async fn accept(
+    state: &AppState,
+    email: &str,
+    token_id: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let invite = state
+        .storage
+        .list_resources_with_prefix("invite-")
+        .await?
+        .into_iter()
+        .filter_map(|(_, value)| serde_json::from_value::<Invite>(value).ok())
+        .find(|invite| invite.token_id == token_id && invite.email == email);
+    let Some(invite) = invite else {
+        return Ok(false);
+    };
+    // Using the link a second time just logs in again
+    if invite.status == InviteStatus::Accepted {
+        return Ok(true);
+    }
+    if !is_outstanding(&invite, chrono::Utc::now()) {
+        return Ok(false);
+    }
+
+    let id = invite_id(&invite.issue_id, email);
+    let commit = CommitBuilder::patch::<Invite>(id, json!({ "status": InviteStatus::Accepted }))
+        .actor(email)
+        .build();
+    submit_event(
+        state,
+        CloudEventBuilder::commit(&invite.issue_id, &commit)
+            .source(email)
+            .build(),
+    )
+    .await?;
+
+    if let Some(issue) = state
+        .storage
+        .get_resource(&invite.issue_id)
+        .await?
+        .and_then(|value| serde_json::from_value::<Issue>(value).ok())
+    {
+        let mut involved = issue.involved.unwrap_or_default();
+        if !involved.iter().any(|i| i == email) {
+            involved.push(email.to_string());
+            update_involved(state, &invite.issue_id, involved, email)
+                .await
+                .map_err(|status| format!("failed to add {}: {}", email, status))?;
+        }
+    }
+    Ok(true)
+}
+
+/// GET /issues/{id}/invites - Invites to the issue that can still be accepted
+#[utoipa::path(
+    get,
+    path = "/issues/{id}/invites",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Outstanding invites, oldest first", body = [Value]),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn list_invites_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+) -> Result<Json<Vec<Invite>>, StatusCode> {
+    authorize_issue(&state, &auth_user.user_id, &issue_id).await?;
+    let now = chrono::Utc::now();
+    let invites = state
+        .storage
+        .list_resources_with_prefix(&format!("invite-{}-", issue_id))
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .filter_map(|(_, value)| serde_json::from_value::<Invite>(value).ok())
+        .filter(|invite| invite.issue_id == issue_id && is_outstanding(invite, now))
+        .collect();
+    Ok(Json(invites))
+}
+
+/// POST /issues/{id}/invites - Invite someone to the issue by email
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/invites",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    request_body = InviteRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Invite commit stored, processed and broadcast; the invite is emailed", body = CloudEvent),
+        (status = 400, description = "Invalid email address, or an expiry that is malformed or in the past"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue"),
+        (status = 409, description = "Already involved, or an outstanding invite exists"),
+    )
+)]
+pub async fn create_invite_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(issue_id): Path<String>,
+    Json(request): Json<InviteRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    authorize_issue(&state, &user, &issue_id).await?;
+    let email = request.email.trim().to_lowercase();
+    if !email.contains('@') || email.contains(char::is_whitespace) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let now = chrono::Utc::now();
+    let expires_at = match request.expires_at.as_deref() {
+        Some(time) => parse_time(time)
+            .filter(|t| *t > now)
+            .ok_or(StatusCode::BAD_REQUEST)?,
+        None => now + chrono::Duration::days(DEFAULT_VALIDITY_DAYS),
+    };
+
+    let issue = load_issue(&state, &issue_id).await?;
+    if issue.involved.iter().flatten().any(|i| *i == email) {
+        return Err(StatusCode::CONFLICT);
+    }
+    match load_invite(&state, &issue_id, &email).await {
+        Ok(existing) if is_outstanding(&existing, now) => return Err(StatusCode::CONFLICT),
+        Ok(_) | Err(StatusCode::NOT_FOUND) => {}
+        Err(status) => return Err(status),
+    }
+
+    let invite = Invite {
+        issue_id: issue_id.clone(),
+        email: email.clone(),
+        invited_by: user.clone(),
+        status: InviteStatus::Pending,
+        expires_at: expires_at.to_rfc3339(),
+        token_id: new_token_id(),
+    };
+    let commit = CommitBuilder::create(invite_id(&issue_id, &email), &invite)
+        .actor(user)
+        .build();
+    // Only email the link once the invite it belongs to is stored
+    let response = submit_commit(&state, &headers, &issue_id, commit).await?;
+    send_invite(&state, &invite, &issue.title)?;
+    Ok(response)
+}
+
+/// DELETE /issues/{id}/invites/{email} - Revoke an invite
+#[utoipa::path(
+    delete,
+    path = "/issues/{id}/invites/{email}",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Issue ID"),
+        ("email" = String, Path, description = "Email the invite was sent to"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Invite revoked; its link no longer works and an accepted invitee is no longer involved", body = CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue or invite"),
+        (status = 409, description = "Invite was already revoked"),
+    )
+)]
+pub async fn revoke_invite_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path((issue_id, email)): Path<(String, String)>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    authorize_issue(&state, &user, &issue_id).await?;
+    let invite = load_invite(&state, &issue_id, &email).await?;
+    if invite.status == InviteStatus::Revoked {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    // No access must be left behind for the invitee
+    let issue = load_issue(&state, &issue_id).await?;
+    let involved = issue.involved.unwrap_or_default();
+    if involved.contains(&email) {
+        let remaining = involved.into_iter().filter(|i| *i != email).collect();
+        update_involved(&state, &issue_id, remaining, &user).await?;
+    }
+
+    let commit = CommitBuilder::patch::<Invite>(
+        invite_id(&issue_id, &email),
+        json!({ "status": InviteStatus::Revoked, "token_id": new_token_id() }),
+    )
+    .actor(user)
+    .build();
+    submit_commit(&state, &headers, &issue_id, commit).await
+}
+
+/// PUT /issues/{id}/invites/{email}/expiry - Change until when an invite can be accepted
+#[utoipa::path(
+    put,
+    path = "/issues/{id}/invites/{email}/expiry",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Issue ID"),
+        ("email" = String, Path, description = "Email the invite was sent to"),
+    ),
+    request_body = ExpiryRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Expiry commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Malformed expiry, or one in the past"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue or invite"),
+        (status = 409, description = "Invite was already accepted or revoked"),
+    )
+)]
+pub async fn set_expiry_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path((issue_id, email)): Path<(String, String)>,
+    Json(request): Json<ExpiryRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    authorize_issue(&state, &user, &issue_id).await?;
+    let expires_at = parse_time(&request.expires_at)
+        .filter(|t| *t > chrono::Utc::now())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let invite = load_invite(&state, &issue_id, &email).await?;
+    if invite.status != InviteStatus::Pending {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let commit = CommitBuilder::patch::<Invite>(
+        invite_id(&issue_id, &email),
+        json!({ "expires_at": expires_at.to_rfc3339() }),
+    )
+    .actor(user)
+    .build();
+    submit_commit(&state, &headers, &issue_id, commit).await
+}
+
+/// POST /issues/{id}/invites/{email}/resend - Send a pending invite again, with a new link
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/invites/{email}/resend",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Issue ID"),
+        ("email" = String, Path, description = "Email the invite was sent to"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Invite emailed again; links in earlier emails no longer work", body = CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue or invite"),
+        (status = 409, description = "Invite was accepted, revoked or has expired"),
+    )
+)]
+pub async fn resend_invite_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path((issue_id, email)): Path<(String, String)>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    authorize_issue(&state, &user, &issue_id).await?;
+    let mut invite = load_invite(&state, &issue_id, &email).await?;
+    if !is_outstanding(&invite, chrono::Utc::now()) {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let issue = load_issue(&state, &issue_id).await?;
+    invite.token_id = new_token_id();
+    let commit = CommitBuilder::patch::<Invite>(
+        invite_id(&issue_id, &email),
+        json!({ "token_id": invite.token_id }),
+    )
+    .actor(user)
+    .build();
+    let response = submit_commit(&state, &headers, &issue_id, commit).await?;
+    send_invite(&state, &invite, &issue.title)?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user, create_issue, issue, test_state};
+
+    #[tokio::test]
+    async fn test_invite_lifecycle() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let typo = "piet@exmaple.com";
+        let piet = "piet@example.com";
+        let user = || auth_user(alice);
+        let issue = issue("Geluidsoverlast", &[alice]);
+        create_issue(&state, "issue-1", &issue, alice).await;
+
+        let invite_as = |who: &str, email: &str| {
+            create_invite_handler(
+                State(state.clone()),
+                auth_user(who),
+                HeaderMap::new(),
+                Path("issue-1".to_string()),
+                Json(InviteRequest {
+                    email: email.to_string(),
+                    expires_at: None,
+                }),
+            )
+        };
+        let invite = |email: &str| invite_as(alice, email);
+        let outstanding = || async {
+            let Json(invites) =
+                list_invites_handler(State(state.clone()), user(), Path("issue-1".to_string()))
+                    .await
+                    .unwrap();
+            invites
+        };
+        let involved = || async {
+            let issue: Issue = serde_json::from_value(
+                state
+                    .storage
+                    .get_resource("issue-1")
+                    .await
+                    .unwrap()
+                    .unwrap(),
+            )
+            .unwrap();
+            issue.involved.unwrap()
+        };
+
+        // A mistyped address is revoked before anyone uses the link
+        assert_eq!(invite(typo).await.unwrap().status(), StatusCode::ACCEPTED);
+        assert_eq!(invite(typo).await.unwrap_err(), StatusCode::CONFLICT);
+        let typo_token = outstanding().await[0].token_id.clone();
+        assert_eq!(involved().await, vec![alice]);
+        let revoked = revoke_invite_handler(
+            State(state.clone()),
+            user(),
+            HeaderMap::new(),
+            Path(("issue-1".to_string(), typo.to_string())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(revoked.status(), StatusCode::ACCEPTED);
+        assert!(outstanding().await.is_empty());
+        assert!(!accept(&state, typo, &typo_token).await.unwrap());
+
+        // Re-sending replaces the link
+        invite(piet).await.unwrap();
+        let first_token = outstanding().await[0].token_id.clone();
+        resend_invite_handler(
+            State(state.clone()),
+            user(),
+            HeaderMap::new(),
+            Path(("issue-1".to_string(), piet.to_string())),
+        )
+        .await
+        .unwrap();
+        let second_token = outstanding().await[0].token_id.clone();
+        assert_ne!(first_token, second_token);
+        assert!(!accept(&state, piet, &first_token).await.unwrap());
+        assert!(accept(&state, piet, &second_token).await.unwrap());
+        assert_eq!(involved().await, vec![alice, piet]);
+        assert!(outstanding().await.is_empty());
+
+        // Only those involved manage the invites
+        let mallory = "mallory@example.com";
+        assert_eq!(
+            invite_as(mallory, mallory).await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        let list = list_invites_handler(
+            State(state.clone()),
+            auth_user(mallory),
+            Path("issue-1".to_string()),
+        );
+        assert_eq!(list.await.unwrap_err(), StatusCode::FORBIDDEN);
+        let revoke = |who: &str| {
+            revoke_invite_handler(
+                State(state.clone()),
+                auth_user(who),
+                HeaderMap::new(),
+                Path(("issue-1".to_string(), piet.to_string())),
+            )
+        };
+        assert_eq!(revoke(mallory).await.unwrap_err(), StatusCode::FORBIDDEN);
+
+        // Revoking an accepted invite takes the access away again
+        revoke(alice).await.unwrap();
+        assert_eq!(involved().await, vec![alice]);
+        assert!(!accept(&state, piet, &second_token).await.unwrap());
+    }
+}
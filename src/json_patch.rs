@@ -0,0 +1,308 @@
+//! JSON Patch (RFC 6902), for commits with `patch_type: "json-patch"`.
+//!
+//! Unlike a merge patch, a JSON Patch can change arrays element by element ("remove one
+//! person from `involved`"). Operations are applied in order and all or nothing: when one
+//! fails (a missing path, a failing `test`), the document is left unchanged.
+use serde_json::Value;
+
+/// Apply the operations in `patch` (a JSON array) to `target`.
+pub fn apply(target: &mut Value, patch: &Value) -> Result<(), String> {
+    let ops = patch
+        .as_array()
+        .ok_or("a JSON Patch must be an array of operations")?;
+    let mut doc = target.clone();
+    for (index, op) in ops.iter().enumerate() {
+        apply_op(&mut doc, op).map_err(|e| format!("operation {}: {}", index, e))?;
+    }
+    *target = doc;
+    Ok(())
+}
+
+fn apply_op(doc: &mut Value, op: &Value) -> Result<(), String> {
+    let name = op
+        .get("op")
+        .and_then(Value::as_str)
+        .ok_or("missing \"op\"")?;
+    let path = op
+        .get("path")
+        .and_then(Value::as_str)
+        .ok_or("missing \"path\"")?;
+    let value = || op.get("value").cloned().ok_or("missing \"value\"");
+    let from = || {
+        op.get("from")
+            .and_then(Value::as_str)
+            .ok_or("missing \"from\"")
+    };
+    match name {
+        "add" => add(doc, path, value()?),
+        "remove" => remove(doc, path).map(|_| ()),
+        "replace" => {
+            remove(doc, path)?;
+            add(doc, path, value()?)
+        }
+        "move" => {
+            let from = from()?;
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(format!("cannot move {} into itself", from));
+            }
+            let moved = remove(doc, from)?;
+            add(doc, path, moved)
+        }
+        "copy" => {
+            let from = from()?;
+            let copied = doc
+                .pointer(from)
+                .cloned()
+                .ok_or_else(|| format!("no value at {}", from))?;
+            add(doc, path, copied)
+        }
+        "test" => match doc.pointer(path) {
+            Some(actual) if *actual == value()? => Ok(()),
+            _ => Err(format!("test failed at {}", path)),
+        },
+        other => Err(format!("unknown operation {:?}", other)),
+    }
+}
+
+/// Split a JSON Pointer into its parent pointer and unescaped last token.
+fn split(path: &str) -> Result<(&str, String), String> {
+    let at = path
+        .rfind('/')
+        .ok_or_else(|| format!("invalid path {:?}", path))?;
+    let token = path[at + 1..].replace("~1", "/").replace("~0", "~");
+    Ok((&path[..at], token))
+}
+
+fn array_index(token: &str, len: usize) -> Result<usize, String> {
+    match token.parse::<usize>() {
+        Ok(index) if index <= len && (token == "0" || !token.starts_with('0')) => Ok(index),
+        _ => Err(format!("invalid array index {:?}", token)),
+    }
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    let (parent, token) = split(path)?;
+    match doc.pointer_mut(parent) {
+        Some(Value::Object(fields)) => {
+            fields.insert(token, value);
+            Ok(())
+        }
+        Some(Value::Array(items)) => {
+            let index = match token.as_str() {
+                "-" => items.len(),
+                token => array_index(token, items.len())?,
+            };
+            items.insert(index, value);
+            Ok(())
+        }
+        _ => Err(format!("no object or array at {:?}", parent)),
+    }
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value, String> {
+    if path.is_empty() {
+        return Ok(std::mem::take(doc));
+    }
+    let (parent, token) = split(path)?;
+    let removed = match doc.pointer_mut(parent) {
+        Some(Value::Object(fields)) => fields.remove(&token),
+        Some(Value::Array(items)) => match array_index(&token, items.len()) {
+            Ok(index) if index < items.len() => Some(items.remove(index)),
+            _ => None,
+        },
+        _ => None,
+    };
+    removed.ok_or_else(|| format!("no value at {}", path))
+}
+
+/// What `patch` does to top-level fields, as a merge patch: `add` / `replace` of a field
+/// sets it, `remove` of a field removes it. Operations inside fields (array elements,
+/// nested objects) can't be expressed without the document and are left out.
+pub fn top_level_changes(patch: &Value) -> Value {
+    let mut changes = serde_json::Map::new();
+    for op in patch.as_array().into_iter().flatten() {
+        let path = op.get("path").and_then(Value::as_str).unwrap_or_default();
+        let Some(field) = path.strip_prefix('/').filter(|f| !f.contains('/')) else {
+            continue;
+        };
+        let field = field.replace("~1", "/").replace("~0", "~");
+        match op.get("op").and_then(Value::as_str) {
+            Some("add" | "replace") => {
+                changes.insert(field, op.get("value").cloned().unwrap_or(Value::Null));
+            }
+            Some("remove") => {
+                changes.insert(field, Value::Null);
+            }
+            _ => {}
+        }
+    }
+    Value::Object(changes)
+}
+
+/// The paths `patch` changes (targets, and the sources of moves), as JSON Pointers.
+pub fn changed_paths(patch: &Value) -> Vec<String> {
+    let mut paths = Vec::new();
+    for op in patch.as_array().into_iter().flatten() {
+        let kind = op.get("op").and_then(Value::as_str);
+        if kind == Some("test") {
+            continue;
+        }
+        if let (Some("move"), Some(from)) = (kind, op.get("from").and_then(Value::as_str)) {
+            paths.push(from.to_string());
+        }
+        if let Some(path) = op.get("path").and_then(Value::as_str) {
+            paths.push(path.to_string());
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{issue, submit_commit_event, test_state};
+    use crate::pipeline::ProcessError;
+    use crate::schemas::{CloudEventBuilder, CommitBuilder, Issue};
+    use serde_json::json;
+
+    #[test]
+    fn test_apply_json_patch() {
+        let mut issue = json!({
+            "title": "Paspoort",
+            "involved": ["alice@example.com", "bob@example.com", "carol@example.com"],
+        });
+        let patch = json!([
+            {"op": "test", "path": "/involved/1", "value": "bob@example.com"},
+            {"op": "remove", "path": "/involved/1"},
+            {"op": "add", "path": "/involved/-", "value": "dave@example.com"},
+            {"op": "move", "from": "/involved/0", "path": "/involved/2"},
+            {"op": "replace", "path": "/title", "value": "Paspoort aanvragen"},
+            {"op": "copy", "from": "/title", "path": "/a~1b"},
+        ]);
+        apply(&mut issue, &patch).unwrap();
+        assert_eq!(
+            issue,
+            json!({
+                "title": "Paspoort aanvragen",
+                "a/b": "Paspoort aanvragen",
+                "involved": ["carol@example.com", "dave@example.com", "alice@example.com"],
+            })
+        );
+
+        // All or nothing
+        let failing = json!([
+            {"op": "remove", "path": "/title"},
+            {"op": "remove", "path": "/involved/7"},
+        ]);
+        let before = issue.clone();
+        assert!(apply(&mut issue, &failing).is_err());
+        assert_eq!(issue, before);
+        assert!(apply(&mut issue, &json!({"title": "x"})).is_err());
+
+        assert_eq!(
+            top_level_changes(&json!([
+                {"op": "replace", "path": "/status", "value": "closed"},
+                {"op": "remove", "path": "/assignee"},
+                {"op": "remove", "path": "/involved/0"},
+            ])),
+            json!({"status": "closed", "assignee": null})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_patch_commits() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let issue = issue("Paspoort", &["alice@example.com", "bob@example.com"]);
+        submit_commit_event(
+            &state,
+            "issue-1",
+            &CommitBuilder::create("issue-1", &issue).build(),
+        )
+        .await
+        .unwrap();
+
+        let remove_bob = json!([
+            {"op": "test", "path": "/involved/1", "value": "bob@example.com"},
+            {"op": "remove", "path": "/involved/1"},
+            {"op": "replace", "path": "/status", "value": "closed"},
+        ]);
+        let commit = CommitBuilder::json_patch::<Issue>("issue-1", remove_bob.clone()).build();
+        submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+        let issue = state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(issue["involved"], json!(["alice@example.com"]));
+        assert_eq!(
+            crate::views::issues_with_status(&state.storage, "closed")
+                .await
+                .unwrap(),
+            vec!["issue-1"]
+        );
+
+        // Bob is gone, so the test fails and nothing is stored
+        let commit = CommitBuilder::json_patch::<Issue>("issue-1", remove_bob).build();
+        let again = submit_commit_event(&state, "issue-1", &commit).await;
+        let error = again.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ProcessError>(),
+            Some(ProcessError::Invalid(_))
+        ));
+        assert_eq!(
+            state
+                .storage
+                .list_subject_events("issue-1")
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // A patch that validated, but whose resource changed before it was stored: neither
+        // the event nor the change is stored
+        let remove_alice = CloudEventBuilder::commit(
+            "issue-1",
+            &CommitBuilder::json_patch::<Issue>(
+                "issue-1",
+                json!([{"op": "remove", "path": "/involved/0"}]),
+            )
+            .build(),
+        )
+        .build();
+        let projection = crate::pipeline::projection(&state.storage, &remove_alice)
+            .await
+            .unwrap();
+        let commit = CommitBuilder::patch::<Issue>("issue-1", json!({"involved": []})).build();
+        submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+        let error = state
+            .storage
+            .store_projected_event(&remove_alice, projection)
+            .await
+            .unwrap_err();
+        assert!(error.is::<crate::storage::ProjectionError>());
+        assert!(state
+            .storage
+            .get_event(&remove_alice.id)
+            .await
+            .unwrap()
+            .is_none());
+        let issue = state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(issue["involved"], json!([]));
+    }
+}
@@ -0,0 +1,634 @@
+//! Labels: named, colored tags that group issues (e.g. "spoed", "wonen").
+//!
+//! A `Label` belongs to a tenant, the email domain of the people who use it, and is
+//! identified by `label-{tenant}-{slug}`. Issues carry the names of their labels in
+//! `tags`; attaching and detaching patches that list with a commit on the issue. Both
+//! `/resources` and `/query` accept `?tag=` to filter on a label, and
+//! `GET /labels/facets` counts issues per tag.
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::{tenant_of, AuthUser};
+use crate::handlers::{authorize_issue, internal_error, submit_commit, AppState, ResourceResponse};
+use crate::schemas::{CloudEvent, CommitBuilder, Issue, Label};
+use crate::search::SearchIndex;
+use crate::storage::Storage;
+
+/// Longest accepted label name, in chars
+const MAX_NAME_CHARS: usize = 40;
+
+/// Search hits counted for facets
+const FACET_SEARCH_LIMIT: usize = 10_000;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LabelRequest {
+    pub name: String,
+    /// Hex color, e.g. "#e5484d"
+    pub color: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LabelUpdate {
+    /// Hex color, e.g. "#e5484d"
+    pub color: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AttachLabelRequest {
+    /// Name of one of the tenant's labels
+    pub name: String,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FacetParams {
+    /// Tantivy query string restricting the counted issues (default: all)
+    pub q: Option<String>,
+}
+
+/// Number of issues with a tag
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+    /// Color of the tenant's label with this name, if there is one
+    pub color: Option<String>,
+}
+
+/// Lowercase words of `name` joined by `-`, for resource IDs that ignore case.
+pub(crate) fn slug(name: &str) -> String {
+    let words: Vec<String> = name
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect();
+    words.join("-")
+}
+
+/// Resource ID of a tenant's label. Names are unique per tenant, ignoring case.
+pub fn label_id(tenant: &str, name: &str) -> String {
+    format!("label-{}-{}", tenant, slug(name))
+}
+
+pub(crate) fn valid_name(name: &str) -> bool {
+    let chars = name.chars().count();
+    chars > 0
+        && chars <= MAX_NAME_CHARS
+        && name.trim() == name
+        && !name
+            .chars()
+            .any(|c| c == '"' || c == '\\' || c.is_control())
+}
+
+fn valid_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Search clause matching resources tagged with `tag`.
+pub fn tag_clause(tag: &str) -> String {
+    format!(
+        "json_payload.tags:\"{}\"",
+        tag.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+/// Does this resource carry `tag`?
+pub fn has_tag(resource: &Value, tag: &str) -> bool {
+    resource
+        .get("tags")
+        .and_then(|tags| tags.as_array())
+        .is_some_and(|tags| tags.iter().any(|t| t.as_str() == Some(tag)))
+}
+
+/// The labels of a tenant, by ID.
+pub async fn tenant_labels(
+    storage: &Storage,
+    tenant: &str,
+) -> Result<Vec<(String, Label)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut labels = Vec::new();
+    for (id, value) in storage
+        .list_resources_with_prefix(&format!("label-{}-", tenant))
+        .await?
+    {
+        match serde_json::from_value::<Label>(value) {
+            // The prefix also matches tenants that extend this one ("gemeente.nl-x")
+            Ok(label) if label.tenant == tenant => labels.push((id, label)),
+            Ok(_) => {}
+            Err(e) => eprintln!("[labels] skipping malformed label {}: {}", id, e),
+        }
+    }
+    Ok(labels)
+}
+
+/// Load a label of the caller's tenant: 404 if unknown, 403 if another tenant's.
+async fn own_label(state: &AppState, user: &str, id: &str) -> Result<Label, StatusCode> {
+    let label: Label = state
+        .storage
+        .get_resource(id)
+        .await
+        .map_err(internal_error)?
+        .and_then(|value| serde_json::from_value(value).ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if label.tenant != tenant_of(user) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(label)
+}
+
+/// GET /labels - The labels of the caller's tenant
+#[utoipa::path(
+    get,
+    path = "/labels",
+    tag = "resources",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The tenant's labels", body = [ResourceResponse]),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn list_labels_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<ResourceResponse>>, StatusCode> {
+    let labels = tenant_labels(&state.storage, tenant_of(&auth_user.user_id))
+        .await
+        .map_err(internal_error)?;
+    let response = labels
+        .into_iter()
+        .map(|(id, label)| ResourceResponse {
+            id,
+            resource_type: "label".to_string(),
+            data: serde_json::to_value(label).unwrap_or_default(),
+            unread: None,
+        })
+        .collect();
+    Ok(Json(response))
+}
+
+/// POST /labels - Create a label for the caller's tenant
+#[utoipa::path(
+    post,
+    path = "/labels",
+    tag = "resources",
+    request_body = LabelRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Label commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Invalid name or color"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 409, description = "The tenant already has a label with this name"),
+    )
+)]
+pub async fn create_label_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Json(request): Json<LabelRequest>,
+) -> Result<Response, StatusCode> {
+    if !valid_name(&request.name) || !valid_color(&request.color) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let user = auth_user.user_id;
+    let tenant = tenant_of(&user).to_string();
+
+    let id = label_id(&tenant, &request.name);
+    if state
+        .storage
+        .get_resource(&id)
+        .await
+        .map_err(internal_error)?
+        .is_some()
+    {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let label = Label {
+        name: request.name,
+        color: request.color.to_lowercase(),
+        tenant,
+    };
+    let commit = CommitBuilder::create(id.clone(), &label)
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &id, commit).await
+}
+
+/// PATCH /labels/{id} - Change the color of a label
+#[utoipa::path(
+    patch,
+    path = "/labels/{id}",
+    tag = "resources",
+    params(("id" = String, Path, description = "Label ID")),
+    request_body = LabelUpdate,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Patch commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Invalid color"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The label belongs to another tenant"),
+        (status = 404, description = "Unknown label"),
+    )
+)]
+pub async fn update_label_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(request): Json<LabelUpdate>,
+) -> Result<Response, StatusCode> {
+    if !valid_color(&request.color) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let user = auth_user.user_id;
+    own_label(&state, &user, &id).await?;
+
+    let commit =
+        CommitBuilder::patch::<Label>(id.clone(), json!({ "color": request.color.to_lowercase() }))
+            .actor(user)
+            .build();
+    submit_commit(&state, &headers, &id, commit).await
+}
+
+/// DELETE /labels/{id} - Delete a label. Issues keep the name in their `tags`.
+#[utoipa::path(
+    delete,
+    path = "/labels/{id}",
+    tag = "resources",
+    params(("id" = String, Path, description = "Label ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Delete commit stored, processed and broadcast", body = CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The label belongs to another tenant"),
+        (status = 404, description = "Unknown label"),
+    )
+)]
+pub async fn delete_label_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    own_label(&state, &user, &id).await?;
+
+    let commit = CommitBuilder::delete::<Label>(id.clone())
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &id, commit).await
+}
+
+/// The `tags` of an issue the caller has access to.
+async fn issue_tags(
+    state: &AppState,
+    user: &str,
+    issue_id: &str,
+) -> Result<Vec<String>, StatusCode> {
+    authorize_issue(state, user, issue_id).await?;
+    let issue = state
+        .storage
+        .get_resource(issue_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(issue
+        .get("tags")
+        .and_then(|tags| serde_json::from_value(tags.clone()).ok())
+        .unwrap_or_default())
+}
+
+/// POST /issues/{id}/labels - Attach a label to an issue
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/labels",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    request_body = AttachLabelRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Patch commit stored, processed and broadcast", body = CloudEvent),
+        (status = 204, description = "The issue already has this label"),
+        (status = 400, description = "The caller's tenant has no label with this name"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn attach_label_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(issue_id): Path<String>,
+    Json(request): Json<AttachLabelRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    let mut tags = issue_tags(&state, &user, &issue_id).await?;
+
+    let label: Label = state
+        .storage
+        .get_resource(&label_id(tenant_of(&user), &request.name))
+        .await
+        .map_err(internal_error)?
+        .and_then(|value| serde_json::from_value(value).ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    if tags.contains(&label.name) {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    tags.push(label.name);
+    let commit = CommitBuilder::patch::<Issue>(issue_id.clone(), json!({ "tags": tags }))
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &issue_id, commit).await
+}
+
+/// DELETE /issues/{id}/labels/{name} - Detach a label from an issue
+#[utoipa::path(
+    delete,
+    path = "/issues/{id}/labels/{name}",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Issue ID"),
+        ("name" = String, Path, description = "Label name"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Patch commit stored, processed and broadcast", body = CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue, or the issue does not have this label"),
+    )
+)]
+pub async fn detach_label_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path((issue_id, name)): Path<(String, String)>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    let mut tags = issue_tags(&state, &user, &issue_id).await?;
+    let before = tags.len();
+    tags.retain(|tag| *tag != name);
+    if tags.len() == before {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let tags = if tags.is_empty() {
+        Value::Null
+    } else {
+        json!(tags)
+    };
+    let commit = CommitBuilder::patch::<Issue>(issue_id.clone(), json!({ "tags": tags }))
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &issue_id, commit).await
+}
+
+/// GET /labels/facets - Number of issues per tag, among the issues the caller can see
+#[utoipa::path(
+    get,
+    path = "/labels/facets",
+    tag = "search",
+    params(FacetParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Tags by descending issue count", body = [TagCount]),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 500, description = "Invalid query"),
+    )
+)]
+pub async fn label_facets_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<FacetParams>,
+) -> Result<Json<Vec<TagCount>>, StatusCode> {
+    let user = &auth_user.user_id;
+    let teams = crate::teams::user_teams(&state.storage, user)
+        .await
+        .map_err(internal_error)?;
+    let query = SearchIndex::apply_scoped_authorization_filter(
+        params.q.as_deref().unwrap_or("*"),
+        user,
+        &teams,
+    );
+    let results = state
+        .search
+        .search(&state.storage, &query, FACET_SEARCH_LIMIT)
+        .await
+        .map_err(|e| {
+            eprintln!("[labels] facet search failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut counted = std::collections::HashSet::new();
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for result in results {
+        let Some(resource) = result.resource else {
+            continue;
+        };
+        if !counted.insert(result.id) {
+            continue;
+        }
+        let tags = resource.get("tags").and_then(|tags| tags.as_array());
+        for tag in tags.into_iter().flatten().filter_map(|t| t.as_str()) {
+            *counts.entry(tag.to_string()).or_default() += 1;
+        }
+    }
+
+    let colors: BTreeMap<String, String> = tenant_labels(&state.storage, tenant_of(user))
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|(_, label)| (label.name, label.color))
+        .collect();
+    let mut facets: Vec<TagCount> = counts
+        .into_iter()
+        .map(|(tag, count)| TagCount {
+            color: colors.get(&tag).cloned(),
+            tag,
+            count,
+        })
+        .collect();
+    facets.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    Ok(Json(facets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state};
+    use crate::handlers::{list_resources, query_resources, ListParams, QueryParams};
+
+    #[test]
+    fn test_label_ids_and_validation() {
+        assert_eq!(tenant_of("alice@gemeente.nl"), "gemeente.nl");
+        assert_eq!(label_id("gemeente.nl", "Spoed"), "label-gemeente.nl-spoed");
+        assert_eq!(
+            label_id("gemeente.nl", "Openbare  ruimte"),
+            "label-gemeente.nl-openbare-ruimte"
+        );
+        assert!(valid_color("#E5484D"));
+        assert!(!valid_color("red"));
+        assert!(!valid_name(" spoed"));
+        assert!(!valid_name("a\"b"));
+        assert_eq!(tag_clause("a\"b"), "json_payload.tags:\"a\\\"b\"");
+    }
+
+    #[tokio::test]
+    async fn test_labels_attach_filter_and_facets() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+
+        for (id, title) in [("issue-1", "Kapvergunning"), ("issue-2", "Bouwvergunning")] {
+            create_issue(&state, id, &issue(title, &[alice]), alice).await;
+        }
+
+        let create = |name: &str, color: &str| {
+            create_label_handler(
+                State(state.clone()),
+                user(alice),
+                HeaderMap::new(),
+                Json(LabelRequest {
+                    name: name.to_string(),
+                    color: color.to_string(),
+                }),
+            )
+        };
+        assert_eq!(
+            create("spoed", "#E5484D").await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            create("Spoed", "#000000").await.unwrap_err(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            create("groen", "green").await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+
+        // Labels are per tenant
+        let Json(labels) = list_labels_handler(State(state.clone()), user("eve@example.com"))
+            .await
+            .unwrap();
+        assert!(labels.is_empty());
+        assert_eq!(
+            delete_label_handler(
+                State(state.clone()),
+                user("eve@example.com"),
+                HeaderMap::new(),
+                Path("label-gemeente.nl-spoed".to_string()),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+
+        let attach_as = |who: &str, issue: &str, name: &str| {
+            attach_label_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Path(issue.to_string()),
+                Json(AttachLabelRequest {
+                    name: name.to_string(),
+                }),
+            )
+        };
+        let attach = |issue: &str, name: &str| attach_as(alice, issue, name);
+        assert_eq!(
+            attach_as("eve@example.com", "issue-1", "spoed")
+                .await
+                .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            attach("issue-1", "spoed").await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            attach("issue-1", "spoed").await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            attach("issue-1", "onbekend").await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let response = list_resources(
+            State(state.clone()),
+            Some(user(alice)),
+            HeaderMap::new(),
+            Query(ListParams {
+                offset: 0,
+                limit: 100,
+                tag: Some("spoed".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let listing: Vec<Value> = serde_json::from_slice(&body).unwrap();
+        let ids: Vec<&str> = listing.iter().filter_map(|r| r["id"].as_str()).collect();
+        assert_eq!(ids, vec!["issue-1"]);
+
+        let Json(results) = query_resources(
+            State(state.clone()),
+            user(alice),
+            Query(QueryParams {
+                q: "*".to_string(),
+                limit: 100,
+                user: None,
+                tag: Some("spoed".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(results.iter().any(|r| r.id == "issue-1"));
+        assert!(!results.iter().any(|r| r.id == "issue-2"));
+
+        let Json(facets) = label_facets_handler(
+            State(state.clone()),
+            user(alice),
+            Query(FacetParams::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(facets.len(), 1);
+        assert_eq!(facets[0].tag, "spoed");
+        assert_eq!(facets[0].count, 1);
+        assert_eq!(facets[0].color.as_deref(), Some("#e5484d"));
+
+        let detach = |who: &str| {
+            detach_label_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Path(("issue-1".to_string(), "spoed".to_string())),
+            )
+        };
+        assert_eq!(
+            detach("eve@example.com").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(detach(alice).await.unwrap().status(), StatusCode::ACCEPTED);
+        let issue = state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(issue.get("tags").is_none());
+    }
+}
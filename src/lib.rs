@@ -1,11 +1,65 @@
+pub mod allowlist;
+pub mod assignment;
+pub mod asyncapi;
+pub mod audit;
 pub mod auth;
+pub mod availability;
+pub mod boards;
+pub mod bulk;
+pub mod calendar;
+pub mod checklists;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod comments;
+pub mod conflicts;
+pub mod connectors;
+pub mod delivery;
+pub mod duplicates;
 pub mod email;
+pub mod encoding;
+pub mod escalation;
+pub mod forms;
 pub mod types;
 pub use types::{PushKeys, PushSubscription};
 
 pub mod handlers;
+pub mod hooks;
+pub mod integrity;
+pub mod invites;
+pub mod json_patch;
+pub mod labels;
+pub mod live;
+pub mod maintenance;
+pub mod mapping;
+pub mod meldingen;
+pub mod mqtt;
+pub mod mute;
+pub mod openapi;
+pub mod outbox;
+pub mod pipeline;
+pub mod poll;
+pub mod portal;
+pub mod projections;
+pub mod quotas;
 
 pub mod push;
+pub mod read_receipts;
+pub mod recurrence;
+pub mod registry;
+pub mod relations;
+pub mod resource_types;
 pub mod schemas;
 pub mod search;
+pub mod search_status;
+pub mod signing;
+pub mod status;
 pub mod storage;
+pub mod sync;
+pub mod teams;
+pub mod timeline;
+pub mod uploads;
+pub mod users;
+pub mod validation;
+pub mod views;
+pub mod watch;
+pub mod workflow;
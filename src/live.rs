@@ -0,0 +1,337 @@
+//! Ephemeral live events: short-lived signals such as typing indicators. They are fanned
+//! out over the same broadcast channel as stored events, but never stored, indexed or
+//! replayed, and carry no `sequence`.
+//!
+//! `GET /events` delivers them as `ephemeral` SSE events rather than `delta`s, so clients
+//! that only apply deltas to their event list are not affected.
+//!
+//! Presence is heartbeat based: clients with an issue open call `POST /issues/{id}/presence`
+//! periodically. The first heartbeat broadcasts `presence.joined`; an explicit `DELETE` or
+//! missing heartbeats for [`PRESENCE_TTL`] broadcast `presence.left`.
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{authorize_issue, AppState};
+use crate::schemas::{schema_url, CloudEvent, CloudEventBuilder, Presence, TypingIndicator};
+
+/// Event type of typing indicators
+pub const TYPING_EVENT_TYPE: &str = "chat.typing";
+/// Event type broadcast when someone opens an issue
+pub const PRESENCE_JOINED_EVENT_TYPE: &str = "presence.joined";
+/// Event type broadcast when someone closes an issue or stops sending heartbeats
+pub const PRESENCE_LEFT_EVENT_TYPE: &str = "presence.left";
+
+/// Event types that are only broadcast, never persisted
+pub const EPHEMERAL_EVENT_TYPES: &[&str] = &[
+    TYPING_EVENT_TYPE,
+    PRESENCE_JOINED_EVENT_TYPE,
+    PRESENCE_LEFT_EVENT_TYPE,
+];
+
+/// How long a typing indicator stays visible without a new one
+const TYPING_TTL_SECS: i64 = 5;
+
+/// How long someone counts as present without a heartbeat
+pub const PRESENCE_TTL: Duration = Duration::from_secs(30);
+
+/// Who has which issue open, by time of the last heartbeat.
+#[derive(Debug, Default)]
+pub struct PresenceTracker {
+    seen: DashMap<(String, String), Instant>,
+}
+
+impl PresenceTracker {
+    /// Record a heartbeat. Returns true if `user` was not present on `subject` yet.
+    pub fn heartbeat(&self, subject: &str, user: &str) -> bool {
+        self.seen
+            .insert((subject.to_string(), user.to_string()), Instant::now())
+            .is_none()
+    }
+
+    /// Returns true if `user` was present on `subject`.
+    pub fn leave(&self, subject: &str, user: &str) -> bool {
+        self.seen
+            .remove(&(subject.to_string(), user.to_string()))
+            .is_some()
+    }
+
+    /// Users present on `subject`, sorted.
+    pub fn viewers(&self, subject: &str) -> Vec<String> {
+        let mut viewers: Vec<String> = self
+            .seen
+            .iter()
+            .filter(|entry| entry.key().0 == subject)
+            .map(|entry| entry.key().1.clone())
+            .collect();
+        viewers.sort();
+        viewers
+    }
+
+    /// Drop everyone without a heartbeat for `ttl`, returning the `(subject, user)` pairs.
+    pub fn expire(&self, ttl: Duration) -> Vec<(String, String)> {
+        let stale: Vec<(String, String)> = self
+            .seen
+            .iter()
+            .filter(|entry| entry.value().elapsed() >= ttl)
+            .map(|entry| entry.key().clone())
+            .collect();
+        // Re-check on removal: a heartbeat may have arrived in the meantime
+        stale
+            .into_iter()
+            .filter(|key| {
+                self.seen
+                    .remove_if(key, |_, seen| seen.elapsed() >= ttl)
+                    .is_some()
+            })
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.seen.clear();
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IssueViewers {
+    pub issue_id: String,
+    /// Emails of the people who have the issue open, sorted
+    pub viewers: Vec<String>,
+}
+
+/// Whether `event` is an ephemeral live event (and not a stored event of the same type).
+pub fn is_ephemeral(event: &CloudEvent) -> bool {
+    event.sequence.is_none() && EPHEMERAL_EVENT_TYPES.contains(&event.event_type.as_str())
+}
+
+/// Send an ephemeral event to the current subscribers. Having none is not an error.
+pub fn broadcast(state: &AppState, event: CloudEvent) {
+    let _ = state.tx.send(event);
+}
+
+/// Broadcast a `presence.joined` or `presence.left` event for `actor` on `subject`.
+fn broadcast_presence(state: &AppState, event_type: &str, subject: &str, actor: &str) {
+    let presence = Presence {
+        actor: actor.to_string(),
+        viewers: state.presence.viewers(subject),
+    };
+    let event = CloudEventBuilder::new(event_type, subject)
+        .dataschema(schema_url("Presence"))
+        .data(serde_json::to_value(&presence).expect("Presence serializes to JSON"))
+        .build();
+    broadcast(state, event);
+}
+
+/// Periodically expire presence without heartbeats, broadcasting `presence.left`.
+pub fn spawn_presence_sweeper(state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(PRESENCE_TTL / 3);
+        loop {
+            ticker.tick().await;
+            for (subject, user) in state.presence.expire(PRESENCE_TTL) {
+                broadcast_presence(&state, PRESENCE_LEFT_EVENT_TYPE, &subject, &user);
+            }
+        }
+    })
+}
+
+/// POST /issues/{id}/typing - Tell the other people on an issue that the caller is typing
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/typing",
+    tag = "events",
+    params(("id" = String, Path, description = "Issue ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 204, description = "Typing indicator sent to the issue's subscribers"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn typing_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    authorize_issue(&state, &auth_user.user_id, &issue_id).await?;
+
+    let indicator = TypingIndicator {
+        actor: auth_user.user_id,
+        expires_at: (chrono::Utc::now() + chrono::Duration::seconds(TYPING_TTL_SECS)).to_rfc3339(),
+    };
+    let event = CloudEventBuilder::new(TYPING_EVENT_TYPE, issue_id)
+        .dataschema(schema_url("TypingIndicator"))
+        .data(serde_json::to_value(&indicator).expect("TypingIndicator serializes to JSON"))
+        .build();
+    broadcast(&state, event);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /issues/{id}/presence - Heartbeat: the caller has the issue open
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/presence",
+    tag = "events",
+    params(("id" = String, Path, description = "Issue ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Everyone who has the issue open, including the caller", body = IssueViewers),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn presence_heartbeat_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+) -> Result<Json<IssueViewers>, StatusCode> {
+    let user = &auth_user.user_id;
+    authorize_issue(&state, user, &issue_id).await?;
+
+    if state.presence.heartbeat(&issue_id, user) {
+        broadcast_presence(&state, PRESENCE_JOINED_EVENT_TYPE, &issue_id, user);
+    }
+    Ok(Json(IssueViewers {
+        viewers: state.presence.viewers(&issue_id),
+        issue_id,
+    }))
+}
+
+/// DELETE /issues/{id}/presence - The caller closed the issue
+#[utoipa::path(
+    delete,
+    path = "/issues/{id}/presence",
+    tag = "events",
+    params(("id" = String, Path, description = "Issue ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 204, description = "Caller no longer counts as present"),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn presence_leave_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+) -> StatusCode {
+    // Only present users can leave, and they passed the access check when they joined
+    if state.presence.leave(&issue_id, &auth_user.user_id) {
+        broadcast_presence(
+            &state,
+            PRESENCE_LEFT_EVENT_TYPE,
+            &issue_id,
+            &auth_user.user_id,
+        );
+    }
+    StatusCode::NO_CONTENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state};
+
+    #[tokio::test]
+    async fn test_typing_is_broadcast_but_not_stored() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        create_issue(
+            &state,
+            "issue-1",
+            &issue("Parkeervergunning", &[alice]),
+            alice,
+        )
+        .await;
+        let latest = state.storage.latest_sequence().await.unwrap();
+
+        let mut rx = state.tx.subscribe();
+        let typing = |who: &str, id: &str| {
+            typing_handler(State(state.clone()), user(who), Path(id.to_string()))
+        };
+        assert_eq!(
+            typing(alice, "issue-1").await.unwrap(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            typing("mallory@example.com", "issue-1").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            typing(alice, "issue-2").await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+
+        let event = rx.try_recv().unwrap();
+        assert!(is_ephemeral(&event));
+        assert_eq!(event.subject, "issue-1");
+        let indicator: TypingIndicator = serde_json::from_value(event.data.unwrap()).unwrap();
+        assert_eq!(indicator.actor, alice);
+        assert!(rx.try_recv().is_err());
+
+        assert_eq!(state.storage.latest_sequence().await.unwrap(), latest);
+    }
+
+    #[tokio::test]
+    async fn test_presence_join_heartbeat_and_leave() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let (alice, bob) = ("alice@gemeente.nl", "bob@gemeente.nl");
+        let issue = issue("Bezwaar WOZ-waarde", &[alice, bob]);
+        create_issue(&state, "issue-1", &issue, alice).await;
+
+        let mut rx = state.tx.subscribe();
+        let heartbeat = |who: &str| {
+            presence_heartbeat_handler(State(state.clone()), user(who), Path("issue-1".to_string()))
+        };
+        let _ = heartbeat(alice).await.unwrap();
+        let Json(viewers) = heartbeat(bob).await.unwrap();
+        assert_eq!(viewers.viewers, vec![alice, bob]);
+        // Repeated heartbeats do not announce again
+        let _ = heartbeat(alice).await.unwrap();
+        assert_eq!(
+            heartbeat("mallory@example.com").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+
+        let joined = rx.try_recv().unwrap();
+        assert_eq!(joined.event_type, PRESENCE_JOINED_EVENT_TYPE);
+        assert!(is_ephemeral(&joined));
+        let joined = rx.try_recv().unwrap();
+        let presence: Presence = serde_json::from_value(joined.data.unwrap()).unwrap();
+        assert_eq!(presence.actor, bob);
+        assert_eq!(presence.viewers, vec![alice, bob]);
+        assert!(rx.try_recv().is_err());
+
+        let leave = |who: &str| {
+            presence_leave_handler(State(state.clone()), user(who), Path("issue-1".to_string()))
+        };
+        // Leaving without having joined announces nothing
+        assert_eq!(leave("mallory@example.com").await, StatusCode::NO_CONTENT);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(leave(bob).await, StatusCode::NO_CONTENT);
+        let left = rx.try_recv().unwrap();
+        assert_eq!(left.event_type, PRESENCE_LEFT_EVENT_TYPE);
+        let presence: Presence = serde_json::from_value(left.data.unwrap()).unwrap();
+        assert_eq!(presence.viewers, vec![alice]);
+
+        // Without heartbeats, presence expires
+        assert!(state.presence.expire(Duration::from_secs(60)).is_empty());
+        assert_eq!(
+            state.presence.expire(Duration::ZERO),
+            vec![("issue-1".to_string(), alice.to_string())]
+        );
+        assert!(state.presence.viewers("issue-1").is_empty());
+    }
+}
@@ -4,24 +4,21 @@ use zaakchat::{handlers, schemas};
 pub mod auth;
 
 use futures_util::stream::{self, Stream};
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
 use std::path::PathBuf;
 
 use axum::{
-    extract::State,
+    extract::{Query, State},
     http::StatusCode,
     response::sse::{Event, KeepAlive, Sse},
-    response::{Html, Response},
-    routing::{delete, get, post},
+    response::Html,
+    routing::{delete, get, patch, post, put},
     serve, Router,
 };
 use std::{convert::Infallible, sync::Arc};
 use tokio::sync::{broadcast, RwLock};
-use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::StreamExt;
 use tower_http::services::ServeDir;
-use tower_http::{cors::CorsLayer, services::ServeFile};
+use tower_http::{compression::CompressionLayer, cors::CorsLayer, services::ServeFile};
 
 use zaakchat::storage::Storage;
 
@@ -45,20 +42,6 @@ pub struct AppState {
 /// CloudEvent following the CloudEvents specification v1.0
 pub use schemas::CloudEvent;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct IncomingCloudEvent {
-    specversion: String,
-    id: String,
-    source: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    subject: Option<String>,
-    #[serde(rename = "type")]
-    event_type: String,
-    time: Option<String>,
-    datacontenttype: Option<String>,
-    data: Option<Value>,
-}
-
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
@@ -66,7 +49,12 @@ async fn main() {
     let addr = "0.0.0.0:8000";
     println!("→ http://{addr}");
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    serve(listener, app).await.unwrap();
+    serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
 async fn create_app() -> Router {
@@ -94,7 +82,7 @@ async fn create_app() -> Router {
 
     // Initialize search index (separate module)
     let search_index = match zaakchat::search::SearchIndex::open(
-        &data_dir.join("search_index"),
+        data_dir.join("search_index"),
         true,
         std::time::Duration::from_secs(10),
     ) {
@@ -132,7 +120,39 @@ async fn create_app() -> Router {
         push_subscriptions: state.push_subscriptions.clone(),
         email_service: state.email_service.clone(),
         active_users: std::sync::Arc::new(dashmap::DashMap::new()),
+        presence: Arc::new(Default::default()),
+        status_lookups: Arc::new(Default::default()),
+        pipeline: Arc::new(zaakchat::pipeline::Pipeline::from_env()),
+        projections: Arc::new(Default::default()),
+        index_health: Arc::new(Default::default()),
+        uploads: Arc::new(Default::default()),
+        asyncapi: Arc::new(Default::default()),
     };
+    // Finish indexing of events committed before a crash
+    match handler_state.pipeline.replay(&handler_state).await {
+        Ok(report) if report == Default::default() => {}
+        Ok(report) => println!(
+            "[startup] replayed {} unindexed events; {} failed, {} given up on",
+            report.replayed, report.failed, report.dead
+        ),
+        Err(e) => eprintln!("[startup] failed to replay the changelog: {}", e),
+    }
+    if let Err(e) = zaakchat::registry::register_builtin(&handler_state).await {
+        eprintln!("[registry] failed to register built-in schemas: {}", e);
+    }
+    zaakchat::integrity::spawn_startup_check(handler_state.clone());
+    zaakchat::live::spawn_presence_sweeper(handler_state.clone());
+    zaakchat::escalation::spawn_escalation_scheduler(handler_state.clone());
+    zaakchat::calendar::spawn_calendar_sync(handler_state.clone());
+    zaakchat::outbox::spawn_outbox_dispatcher(handler_state.clone());
+    if let Ok(path) = std::env::var("MQTT_CONFIG") {
+        match zaakchat::mqtt::load_config(&path) {
+            Ok(config) => {
+                zaakchat::mqtt::spawn_mqtt_bridge(handler_state.clone(), config);
+            }
+            Err(e) => eprintln!("[mqtt] bridge disabled, failed to load {}: {}", path, e),
+        }
+    }
 
     // API routes with new storage-backed endpoints
     let api_routes = Router::new()
@@ -144,10 +164,318 @@ async fn create_app() -> Router {
             "/events",
             get(handlers::get_or_stream_events).post(handlers::handle_event),
         )
+        .route("/events/gaps", get(handlers::event_gaps_handler))
+        .route("/events/poll", get(zaakchat::poll::poll_events_handler))
+        .route("/sync", post(zaakchat::sync::sync_handler))
+        .route("/events/{id}/revert", post(handlers::revert_event_handler))
         // Resource endpoints
         .route("/resources", get(handlers::list_resources))
+        .route("/resources/bulk", post(zaakchat::bulk::bulk_handler))
         .route("/resources/{id}", get(handlers::get_resource))
         .route("/resources/{id}", delete(handlers::delete_resource))
+        .route("/resources/{id}", patch(handlers::patch_resource))
+        .route(
+            "/resources/{id}/resolve",
+            post(zaakchat::conflicts::resolve_conflict_handler),
+        )
+        .route(
+            "/issues/{id}/timeline",
+            get(zaakchat::timeline::issue_timeline_handler),
+        )
+        .route("/issues/{id}/typing", post(zaakchat::live::typing_handler))
+        .route(
+            "/issues/{id}/presence",
+            post(zaakchat::live::presence_heartbeat_handler)
+                .delete(zaakchat::live::presence_leave_handler),
+        )
+        .route(
+            "/issues/{id}/read",
+            post(zaakchat::read_receipts::mark_read_handler),
+        )
+        .route(
+            "/issues/{id}/team",
+            put(zaakchat::teams::assign_team_handler),
+        )
+        .route(
+            "/issues/{id}/watch",
+            post(zaakchat::watch::watch_handler).delete(zaakchat::watch::unwatch_handler),
+        )
+        .route(
+            "/issues/{id}/mute",
+            post(zaakchat::mute::mute_handler).delete(zaakchat::mute::unmute_handler),
+        )
+        .route(
+            "/zaaktypes/{id}/workflow",
+            get(zaakchat::workflow::workflow_handler),
+        )
+        .route(
+            "/forms/{id}/validate",
+            post(zaakchat::forms::validate_form_handler),
+        )
+        .route(
+            "/issues/{id}/checklists",
+            post(zaakchat::checklists::create_checklist_handler),
+        )
+        .route(
+            "/checklists/{id}/items/{index}",
+            put(zaakchat::checklists::toggle_item_handler),
+        )
+        .route(
+            "/issues/{id}/signing",
+            post(zaakchat::signing::start_signing_handler),
+        )
+        .route(
+            "/signing/callback",
+            post(zaakchat::signing::signing_callback_handler),
+        )
+        .route(
+            "/signing/{id}/document",
+            get(zaakchat::signing::signed_document_handler),
+        )
+        .route(
+            "/uploads",
+            post(zaakchat::uploads::create_upload_handler)
+                .options(zaakchat::uploads::options_handler),
+        )
+        .route(
+            "/uploads/{id}",
+            get(zaakchat::uploads::download_upload_handler)
+                .head(zaakchat::uploads::upload_offset_handler)
+                .patch(zaakchat::uploads::append_upload_handler)
+                .delete(zaakchat::uploads::delete_upload_handler),
+        )
+        .route(
+            "/comments/{id}",
+            patch(zaakchat::comments::edit_comment_handler)
+                .delete(zaakchat::comments::delete_comment_handler),
+        )
+        .route(
+            "/comments/{id}/history",
+            get(zaakchat::comments::comment_history_handler),
+        )
+        .route(
+            "/comments/{id}/thread",
+            get(zaakchat::comments::comment_thread_handler),
+        )
+        .route(
+            "/comments/{id}/reactions",
+            post(zaakchat::comments::add_reaction_handler),
+        )
+        .route(
+            "/comments/{id}/reactions/{emoji}",
+            delete(zaakchat::comments::remove_reaction_handler),
+        )
+        .route(
+            "/labels",
+            get(zaakchat::labels::list_labels_handler).post(zaakchat::labels::create_label_handler),
+        )
+        .route(
+            "/labels/facets",
+            get(zaakchat::labels::label_facets_handler),
+        )
+        .route(
+            "/labels/{id}",
+            patch(zaakchat::labels::update_label_handler)
+                .delete(zaakchat::labels::delete_label_handler),
+        )
+        .route(
+            "/teams",
+            get(zaakchat::teams::list_teams_handler).post(zaakchat::teams::create_team_handler),
+        )
+        .route(
+            "/teams/{id}/members",
+            post(zaakchat::teams::add_member_handler),
+        )
+        .route(
+            "/teams/{id}/members/{user}",
+            delete(zaakchat::teams::remove_member_handler),
+        )
+        .route(
+            "/teams/{id}/stats",
+            get(zaakchat::teams::team_stats_handler),
+        )
+        .route("/boards/{team}", get(zaakchat::boards::get_board_handler))
+        .route(
+            "/boards/{team}/columns",
+            put(zaakchat::boards::reorder_columns_handler),
+        )
+        .route(
+            "/boards/{team}/cards",
+            post(zaakchat::boards::move_card_handler),
+        )
+        .route("/users", get(zaakchat::users::search_users_handler))
+        .route("/users/me", put(zaakchat::users::update_profile_handler))
+        .route(
+            "/users/me/availability",
+            put(zaakchat::availability::update_availability_handler),
+        )
+        .route(
+            "/users/{user}/availability",
+            get(zaakchat::availability::get_availability_handler),
+        )
+        .route(
+            "/issues/{id}/invites",
+            get(zaakchat::invites::list_invites_handler)
+                .post(zaakchat::invites::create_invite_handler),
+        )
+        .route(
+            "/issues/{id}/invites/{email}",
+            delete(zaakchat::invites::revoke_invite_handler),
+        )
+        .route(
+            "/issues/{id}/invites/{email}/expiry",
+            put(zaakchat::invites::set_expiry_handler),
+        )
+        .route(
+            "/issues/{id}/invites/{email}/resend",
+            post(zaakchat::invites::resend_invite_handler),
+        )
+        .route("/portal/zaken", get(zaakchat::portal::list_zaken_handler))
+        .route(
+            "/portal/zaken/{id}",
+            get(zaakchat::portal::get_zaak_handler),
+        )
+        .route(
+            "/portal/zaken/{id}/comments",
+            post(zaakchat::portal::add_comment_handler),
+        )
+        .route(
+            "/meldingen",
+            post(zaakchat::meldingen::create_melding_handler),
+        )
+        .route(
+            "/status/{zaaknummer}/{code}",
+            get(zaakchat::status::public_status_handler),
+        )
+        .route(
+            "/issues/{id}/status-code",
+            get(zaakchat::status::status_code_handler),
+        )
+        .route(
+            "/connectors",
+            get(zaakchat::connectors::list_connectors_handler)
+                .post(zaakchat::connectors::create_connector_handler),
+        )
+        .route(
+            "/connectors/{id}",
+            delete(zaakchat::connectors::delete_connector_handler),
+        )
+        .route(
+            "/users/me/calendar",
+            get(zaakchat::calendar::get_calendar_handler)
+                .put(zaakchat::calendar::update_calendar_handler)
+                .delete(zaakchat::calendar::delete_calendar_handler),
+        )
+        .route(
+            "/users/me/calendar/sync",
+            post(zaakchat::calendar::sync_calendar_handler),
+        )
+        .route(
+            "/users/me/calendar/feed-token",
+            post(zaakchat::calendar::create_feed_token_handler)
+                .delete(zaakchat::calendar::delete_feed_token_handler),
+        )
+        .route(
+            "/me/calendar.ics",
+            get(zaakchat::calendar::my_calendar_feed_handler),
+        )
+        .route(
+            "/issues/{id}/calendar.ics",
+            get(zaakchat::calendar::issue_calendar_feed_handler),
+        )
+        .route(
+            "/hooks/{integration}",
+            post(zaakchat::hooks::receive_hook_handler),
+        )
+        .route(
+            "/integrations",
+            get(zaakchat::hooks::list_integrations_handler),
+        )
+        .route(
+            "/integrations/{name}",
+            put(zaakchat::hooks::update_integration_handler)
+                .delete(zaakchat::hooks::delete_integration_handler),
+        )
+        .route(
+            "/registry",
+            get(zaakchat::registry::list_event_types_handler),
+        )
+        .route(
+            "/registry/{event_type}",
+            get(zaakchat::registry::list_versions_handler)
+                .post(zaakchat::registry::register_schema_handler),
+        )
+        .route(
+            "/registry/{event_type}/check",
+            post(zaakchat::registry::check_schema_handler),
+        )
+        .route(
+            "/registry/{event_type}/{version}",
+            get(zaakchat::registry::get_version_handler),
+        )
+        .route(
+            "/resource-types",
+            get(zaakchat::resource_types::list_resource_types_handler)
+                .put(zaakchat::resource_types::register_resource_type_handler),
+        )
+        .route(
+            "/projections",
+            get(zaakchat::projections::list_projections_handler),
+        )
+        .route(
+            "/projections/{name}",
+            get(zaakchat::projections::get_projection_handler),
+        )
+        .route(
+            "/projections/{name}/rebuild",
+            post(zaakchat::projections::rebuild_projection_handler),
+        )
+        .route(
+            "/projections/{name}/{key}",
+            get(zaakchat::projections::get_projection_value_handler),
+        )
+        .route(
+            "/views/issues-by-status/{status}",
+            get(zaakchat::views::issues_by_status_handler),
+        )
+        .route(
+            "/views/tasks-by-assignee/{assignee}",
+            get(zaakchat::views::tasks_by_assignee_handler),
+        )
+        .route("/me/tasks", get(zaakchat::views::my_tasks_handler))
+        .route("/usage/{tenant}", get(zaakchat::quotas::usage_handler))
+        .route(
+            "/admin/storage",
+            get(zaakchat::maintenance::storage_report_handler),
+        )
+        .route(
+            "/admin/storage/vacuum",
+            post(zaakchat::maintenance::vacuum_handler),
+        )
+        .route(
+            "/admin/storage/compact",
+            post(zaakchat::maintenance::compact_handler),
+        )
+        .route("/health", get(zaakchat::integrity::health_handler))
+        .route(
+            "/admin/search/status",
+            get(zaakchat::search_status::search_status_handler),
+        )
+        .route(
+            "/admin/search/reindex",
+            post(zaakchat::integrity::start_reindex_handler)
+                .delete(zaakchat::integrity::cancel_reindex_handler),
+        )
+        .route("/metrics", get(zaakchat::search_status::metrics_handler))
+        .route(
+            "/escalation-policy",
+            get(zaakchat::escalation::get_policy_handler)
+                .put(zaakchat::escalation::update_policy_handler),
+        )
+        .route(
+            "/assignment/suggestions",
+            get(zaakchat::assignment::suggestions_handler),
+        )
         // Query endpoint with Tantivy search
         .route("/query", get(handlers::query_resources))
         // Debug endpoint to inspect persisted DB counts and samples
@@ -155,66 +483,90 @@ async fn create_app() -> Router {
         .route("/api/email/inbound", post(handlers::inbound_email_handler))
         .route("/reset/", post(handlers::reset_handler))
         // Legacy endpoints (can be removed later)
+        .route("/openapi.json", get(zaakchat::openapi::handle_get_openapi))
+        .route("/swagger-ui", get(zaakchat::openapi::handle_swagger_ui))
         .route("/schemas", get(crate::schemas::handle_get_schemas_index))
         .route("/schemas/{*name}", get(crate::schemas::handle_get_schema))
+        .route(
+            "/asyncapi-docs/asyncapi.yaml",
+            get(zaakchat::asyncapi::asyncapi_yaml_handler),
+        )
+        .route(
+            "/asyncapi-docs/asyncapi.json",
+            get(zaakchat::asyncapi::asyncapi_json_handler),
+        )
         .route("/login", post(handlers::login_handler))
         .route("/auth/verify", get(handlers::verify_login_handler))
+        .route(
+            "/audit/actors/{email}",
+            get(zaakchat::audit::actor_audit_handler),
+        )
+        // Record authenticated requests for the per-actor audit trail
+        .layer(axum::middleware::from_fn_with_state(
+            handler_state.clone(),
+            zaakchat::audit::access_log_middleware,
+        ))
+        // Admin and integration routes only from `ADMIN_ALLOWED_CIDRS`
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::new(zaakchat::allowlist::IpAllowlist::from_env()),
+            zaakchat::allowlist::ip_allowlist_middleware,
+        ))
         .with_state(handler_state);
 
     // Combine API routes with static file serving
-    let app = Router::new()
+    Router::new()
         .merge(api_routes)
-        .route("/asyncapi-docs/asyncapi.yaml", get(serve_asyncapi_yaml))
-        .route("/asyncapi-docs/asyncapi.json", get(serve_asyncapi_json))
         .route("/asyncapi-docs", get(serve_asyncapi_docs))
         .nest_service("/asyncapi-docs/css", ServeDir::new("asyncapi-docs/css"))
         .nest_service("/asyncapi-docs/js", ServeDir::new("asyncapi-docs/js"))
         .fallback_service(ServeDir::new("dist").fallback(ServeFile::new("dist/index.html")))
-        .layer(CorsLayer::permissive());
-
-    app
+        // gzip/br for JSON listings, search results and schemas (negotiated via Accept-Encoding).
+        // The default predicate skips SSE streams, images and tiny bodies.
+        .layer(CompressionLayer::new())
+        .layer(CorsLayer::permissive())
 }
 
-/* The helper `extract_resource_type` was removed from `main.rs` because resource-type
-detection is handled centrally in the handlers module. Keeping duplicate helpers
-here caused unused-function warnings. If a shared helper is desired in future,
-move it to a single common module (e.g., `handlers` or `types`) and import it where needed. */
-
 /// SSE handler for streaming events
 async fn sse_handler(
     State(state): State<handlers::AppState>,
+    Query(params): Query<handlers::StreamParams>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     let rx = state.tx.subscribe();
+    let type_filter = handlers::parse_event_types(params.types.as_deref());
 
     // Get snapshot from storage
     let snapshot_events = state.storage.list_events(0, 1000).await.unwrap_or_default();
 
+    let last_seq = snapshot_events.last().and_then(|e| e.sequence.clone());
+    let snapshot_events: Vec<_> = snapshot_events
+        .into_iter()
+        .filter(|e| handlers::event_type_allowed(&type_filter, e))
+        .collect();
     let snapshot = serde_json::to_string(&snapshot_events).unwrap();
 
+    let cursor = handlers::StreamCursor::new(last_seq.clone());
+    let checkpoints = handlers::checkpoint_events(cursor.clone(), handlers::checkpoint_interval());
     let stream = stream::once(async move { Ok(Event::default().event("snapshot").data(snapshot)) })
         .chain(
-            BroadcastStream::new(rx)
-                .map(|msg| {
-                    let delta = msg.unwrap();
-                    let json = serde_json::to_string(&delta).unwrap();
+            handlers::live_events_with_catchup(state.storage.clone(), rx, last_seq)
+                .map(move |delta| {
+                    cursor.advance(&delta);
+                    delta
+                })
+                // This stream is unauthenticated: keep live-only signals (typing) off it
+                .filter(|delta| !zaakchat::live::is_ephemeral(delta))
+                .filter(move |delta| handlers::event_type_allowed(&type_filter, delta))
+                .map(|delta| {
+                    let json = serde_json::to_string(&delta).unwrap_or_else(|_| "{}".to_string());
                     Event::default().event("delta").data(json)
                 })
+                .merge(checkpoints)
                 .map(Ok),
         );
 
     Sse::new(stream).keep_alive(KeepAlive::default())
 }
 
-/// Reset state handler
-
-/// Push subscription handler
-#[allow(dead_code)]
-// `subscribe_push` local stub removed: routes now use `crate::push::subscribe_push`
-
-/// Push unsubscribe handler
-#[allow(dead_code)]
-// `unsubscribe_push` local stub removed: routes now use `crate::push::unsubscribe_push`
-
 /// Serve the AsyncAPI HTML documentation
 async fn serve_asyncapi_docs() -> Result<Html<String>, StatusCode> {
     let docs_path = std::path::Path::new("asyncapi-docs/index.html");
@@ -227,43 +579,3 @@ async fn serve_asyncapi_docs() -> Result<Html<String>, StatusCode> {
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
-
-/// Serve the AsyncAPI YAML file
-async fn serve_asyncapi_yaml() -> Result<Response, StatusCode> {
-    let yaml_path = std::path::Path::new("asyncapi.yaml");
-    if !yaml_path.exists() {
-        return Err(StatusCode::NOT_FOUND);
-    }
-
-    match tokio::fs::read_to_string(yaml_path).await {
-        Ok(content) => {
-            let mut response = Response::new(content.into());
-            response.headers_mut().insert(
-                axum::http::header::CONTENT_TYPE,
-                axum::http::HeaderValue::from_static("application/x-yaml"),
-            );
-            Ok(response)
-        }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
-}
-
-/// Serve the AsyncAPI JSON file
-async fn serve_asyncapi_json() -> Result<Response, StatusCode> {
-    let json_path = std::path::Path::new("asyncapi.json");
-    if !json_path.exists() {
-        return Err(StatusCode::NOT_FOUND);
-    }
-
-    match tokio::fs::read_to_string(json_path).await {
-        Ok(content) => {
-            let mut response = Response::new(content.into());
-            response.headers_mut().insert(
-                axum::http::header::CONTENT_TYPE,
-                axum::http::HeaderValue::from_static("application/json"),
-            );
-            Ok(response)
-        }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
-}
@@ -0,0 +1,255 @@
+//! Storage maintenance: reporting on, vacuuming and compacting the redb file.
+//!
+//! redb reuses freed pages but never shrinks its file, so without maintenance the database
+//! only grows. Three operations, for admins (see `auth::is_admin`):
+//! - `GET /admin/storage`: file size, fragmentation and the size of every table;
+//! - `POST /admin/storage/vacuum`: remove what deleted resources left in the indexes, and the
+//!   read models of projections that are no longer registered;
+//! - `POST /admin/storage/compact`: give free pages back to the file system. Storage is
+//!   blocked while it runs.
+//!
+//! The `storage_maintenance` binary does the same on a stopped server's data directory.
+use std::collections::HashSet;
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{commit_of, internal_error, AppState};
+use crate::storage::{Storage, StorageReport, VacuumReport};
+
+/// Events read per step while looking for deletions
+const PAGE_SIZE: usize = 500;
+
+/// The resources the event log deleted (and did not create again).
+pub async fn deleted_resources(
+    storage: &Storage,
+) -> Result<HashSet<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut deleted = HashSet::new();
+    let mut after = None;
+    loop {
+        let events = storage.list_events_after(after.clone(), PAGE_SIZE).await?;
+        for event in &events {
+            let Some(commit) = commit_of(event) else {
+                continue;
+            };
+            match commit.deleted {
+                Some(true) => deleted.insert(commit.resource_id),
+                _ => deleted.remove(&commit.resource_id),
+            };
+        }
+        match events.last() {
+            Some(last) if events.len() == PAGE_SIZE => after = last.sequence.clone(),
+            _ => break,
+        }
+    }
+    // Resources stored without a commit may have come back
+    let mut gone = HashSet::new();
+    for id in deleted {
+        if storage.get_resource(&id).await?.is_none() {
+            gone.insert(id);
+        }
+    }
+    Ok(gone)
+}
+
+/// Vacuum `storage`, keeping the read models of `projections`.
+pub async fn vacuum(
+    storage: &Storage,
+    projections: &[&str],
+) -> Result<VacuumReport, Box<dyn std::error::Error + Send + Sync>> {
+    let deleted = deleted_resources(storage).await?;
+    storage.vacuum(&deleted, projections).await
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CompactionReport {
+    /// Whether there was anything to compact
+    pub compacted: bool,
+    pub file_bytes_before: u64,
+    pub file_bytes_after: u64,
+}
+
+/// Compact `storage`, reporting the file size before and after.
+pub fn compact(
+    storage: &Storage,
+) -> Result<CompactionReport, Box<dyn std::error::Error + Send + Sync>> {
+    let before = storage.storage_report()?.file_bytes;
+    let compacted = storage.compact()?;
+    Ok(CompactionReport {
+        compacted,
+        file_bytes_before: before,
+        file_bytes_after: storage.storage_report()?.file_bytes,
+    })
+}
+
+/// GET /admin/storage - Size and fragmentation of the database
+#[utoipa::path(
+    get,
+    path = "/admin/storage",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "File, table sizes and fragmentation", body = StorageReport),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+    )
+)]
+pub async fn storage_report_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<StorageReport>, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    state
+        .storage
+        .storage_report()
+        .map(Json)
+        .map_err(internal_error)
+}
+
+/// POST /admin/storage/vacuum - Remove leftovers of deleted resources and old projections
+#[utoipa::path(
+    post,
+    path = "/admin/storage/vacuum",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Removed entries per table", body = VacuumReport),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+    )
+)]
+pub async fn vacuum_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<VacuumReport>, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let report = vacuum(&state.storage, &state.projections.names())
+        .await
+        .map_err(internal_error)?;
+    println!(
+        "[maintenance] {} vacuumed storage: {:?}",
+        auth_user.user_id, report.removed
+    );
+    Ok(Json(report))
+}
+
+/// POST /admin/storage/compact - Shrink the database file
+#[utoipa::path(
+    post,
+    path = "/admin/storage/compact",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "File size before and after", body = CompactionReport),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+        (status = 409, description = "A transaction was still open; try again"),
+    )
+)]
+pub async fn compact_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<CompactionReport>, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let storage = state.storage.clone();
+    let report = tokio::task::spawn_blocking(move || compact(&storage))
+        .await
+        .map_err(internal_error)?
+        .map_err(|e| match e.downcast_ref::<redb::CompactionError>() {
+            Some(redb::CompactionError::TransactionInProgress) => {
+                eprintln!("[maintenance] compaction postponed: {}", e);
+                StatusCode::CONFLICT
+            }
+            _ => internal_error(e),
+        })?;
+    println!(
+        "[maintenance] {} compacted storage: {} -> {} bytes",
+        auth_user.user_id, report.file_bytes_before, report.file_bytes_after
+    );
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{
+        auth_user as user, create_issue, issue, submit_commit_event, test_state, ADMIN,
+    };
+    use crate::schemas::{CommitBuilder, Issue};
+
+    #[tokio::test]
+    async fn test_vacuum_and_compact() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@example.com";
+        for id in ["issue-1", "issue-2"] {
+            create_issue(&state, id, &issue(id, &[alice]), alice).await;
+            state.storage.set_watching(id, alice, true).await.unwrap();
+            state.storage.mark_read(alice, id, "1").await.unwrap();
+        }
+        let delete = CommitBuilder::delete::<Issue>("issue-2").build();
+        submit_commit_event(&state, "issue-2", &delete)
+            .await
+            .unwrap();
+        state
+            .storage
+            .apply_projection_changes("retired", "00000000000000000001", &[])
+            .await
+            .unwrap();
+
+        let deleted = deleted_resources(&state.storage).await.unwrap();
+        assert_eq!(deleted, HashSet::from(["issue-2".to_string()]));
+        let report = vacuum(&state.storage, &state.projections.names())
+            .await
+            .unwrap();
+        assert_eq!(report.removed["watchers"], 1);
+        assert_eq!(report.removed["read_markers"], 1);
+        assert_eq!(report.removed["projection_cursors"], 1);
+        assert!(state.storage.is_watching("issue-1", alice).await.unwrap());
+        assert!(!state.storage.is_watching("issue-2", alice).await.unwrap());
+        assert!(state
+            .storage
+            .get_read_marker(alice, "issue-1")
+            .await
+            .unwrap()
+            .is_some());
+        assert!(state
+            .storage
+            .projection_cursor("retired")
+            .await
+            .unwrap()
+            .is_none());
+
+        let Json(report) = storage_report_handler(State(state.clone()), user(ADMIN))
+            .await
+            .unwrap();
+        assert!(report.file_bytes > 0);
+        assert!(report.tables.iter().any(|t| t.name == "events_by_seq"));
+        let Json(compaction) = compact_handler(State(state.clone()), user(ADMIN))
+            .await
+            .unwrap();
+        assert!(compaction.file_bytes_after <= compaction.file_bytes_before);
+        assert!(state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .is_some());
+
+        // Only admins maintain storage
+        let forbidden = storage_report_handler(State(state.clone()), user(alice)).await;
+        assert_eq!(forbidden.unwrap_err(), StatusCode::FORBIDDEN);
+        let forbidden = vacuum_handler(State(state.clone()), user(alice)).await;
+        assert_eq!(forbidden.unwrap_err(), StatusCode::FORBIDDEN);
+        let forbidden = compact_handler(State(state), user(alice)).await;
+        assert_eq!(forbidden.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,606 @@
+//! Payload mappings: configurable translation of external payloads into commits.
+//!
+//! An integration of kind `mapped` (see `hooks`) carries a list of [`CommitMapping`]s
+//! instead of a translation written in Rust. Each mapping describes one commit; its
+//! `subject`, `resource_id` and `when` fields and every string in its `resource` template are
+//! expressions in a small JSONata-like language, evaluated against the payload:
+//!
+//! - `$` is the payload, `$.betaling.bedrag` and `$.regels[0].omschrijving` select parts of it
+//!   (missing parts are `null`);
+//! - `'text'` or `"text"`, numbers, `true`, `false` and `null` are literals;
+//! - `a & b` concatenates as text (`null` counts as empty);
+//! - `a = b` and `a != b` compare;
+//! - functions: `uuid()`, `now()`, `lower(s)`, `upper(s)`, `string(v)`, `number(v)`,
+//!   `join(array, separator)`, `coalesce(a, b, ...)` (the first that is not `null`) and
+//!   `if(condition, then, else)`.
+//!
+//! Objects and arrays in the template are walked; numbers and booleans are kept as they
+//! are. The rendered resource must be valid for its resource type, so a mapping can only
+//! produce commits that the pipeline accepts.
+//!
+//! ```json
+//! {
+//!   "subject": "$.metadata.zaak",
+//!   "resource_type": "comment",
+//!   "resource": { "content": "'Betaling ' & lower($.status) & ' (' & $.id & ')'" },
+//!   "when": "$.type = 'payment.updated'"
+//! }
+//! ```
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::schemas::{
+    CloudEvent, CloudEventBuilder, Comment, CommitBuilder, Document, Issue, JSONCommit, Planning,
+    Task,
+};
+
+/// What a mapped commit does with its resource
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingAction {
+    #[default]
+    Create,
+    Patch,
+    Delete,
+}
+
+/// How one commit is made from a payload
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CommitMapping {
+    /// Expression for the event subject: the zaak the commit is about
+    pub subject: String,
+    /// `issue`, `comment`, `task`, `planning` or `document`
+    pub resource_type: String,
+    /// Expression for the resource ID; a new UUID when missing. For an issue created with
+    /// the subject as ID, use the same expression as `subject`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource_id: Option<String>,
+    #[serde(default)]
+    pub action: MappingAction,
+    /// Template of the resource (create) or the merge patch (patch)
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub resource: Value,
+    /// Expression; the commit is only made when it is true
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Literal(Value),
+    Path(Vec<Segment>),
+    Concat(Vec<Expr>),
+    Compare(Box<Expr>, Box<Expr>, bool),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Str(String),
+    Num(f64),
+    Ident(String),
+    Dollar,
+    Dot,
+    LBracket,
+    RBracket,
+    LParen,
+    RParen,
+    Comma,
+    Amp,
+    Eq,
+    NotEq,
+}
+
+const FUNCTIONS: &[&str] = &[
+    "uuid", "now", "lower", "upper", "string", "number", "join", "coalesce", "if",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ' ' | '\t' | '\n' | '\r' => i += 1,
+            '$' => {
+                tokens.push(Token::Dollar);
+                i += 1;
+            }
+            '.' => {
+                tokens.push(Token::Dot);
+                i += 1;
+            }
+            '[' => {
+                tokens.push(Token::LBracket);
+                i += 1;
+            }
+            ']' => {
+                tokens.push(Token::RBracket);
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                i += 1;
+            }
+            '&' => {
+                tokens.push(Token::Amp);
+                i += 1;
+            }
+            '=' => {
+                tokens.push(Token::Eq);
+                i += 1;
+            }
+            '!' if chars.get(i + 1) == Some(&'=') => {
+                tokens.push(Token::NotEq);
+                i += 2;
+            }
+            '\'' | '"' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&q| q == c)
+                    .ok_or_else(|| format!("unterminated string at {}", i))?;
+                tokens.push(Token::Str(chars[i + 1..i + 1 + end].iter().collect()));
+                i += end + 2;
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let number = text
+                    .parse()
+                    .map_err(|_| format!("invalid number '{}' at {}", text, start))?;
+                tokens.push(Token::Num(number));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '-')
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            }
+            c => return Err(format!("unexpected '{}' at {}", c, i)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            other => Err(format!("expected {:?}, found {:?}", expected, other)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let left = self.concat()?;
+        let negate = match self.peek() {
+            Some(Token::Eq) => false,
+            Some(Token::NotEq) => true,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.concat()?;
+        Ok(Expr::Compare(Box::new(left), Box::new(right), negate))
+    }
+
+    fn concat(&mut self) -> Result<Expr, String> {
+        let mut parts = vec![self.primary()?];
+        while self.peek() == Some(&Token::Amp) {
+            self.pos += 1;
+            parts.push(self.primary()?);
+        }
+        Ok(if parts.len() == 1 {
+            parts.remove(0)
+        } else {
+            Expr::Concat(parts)
+        })
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::Num(n)) => Ok(Expr::Literal(
+                serde_json::Number::from_f64(n).map_or(Value::Null, Value::Number),
+            )),
+            Some(Token::Dollar) => {
+                let mut path = Vec::new();
+                loop {
+                    match self.peek() {
+                        Some(Token::Dot) => {
+                            self.pos += 1;
+                            match self.next() {
+                                Some(Token::Ident(field)) => path.push(Segment::Field(field)),
+                                other => return Err(format!("expected field, found {:?}", other)),
+                            }
+                        }
+                        Some(Token::LBracket) => {
+                            self.pos += 1;
+                            match self.next() {
+                                Some(Token::Num(n)) if n >= 0.0 && n.fract() == 0.0 => {
+                                    path.push(Segment::Index(n as usize))
+                                }
+                                other => return Err(format!("expected index, found {:?}", other)),
+                            }
+                            self.expect(Token::RBracket)?;
+                        }
+                        _ => return Ok(Expr::Path(path)),
+                    }
+                }
+            }
+            Some(Token::LParen) => {
+                let expr = self.expr()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) if self.peek() == Some(&Token::LParen) => {
+                if !FUNCTIONS.contains(&name.as_str()) {
+                    return Err(format!("unknown function '{}'", name));
+                }
+                self.pos += 1;
+                let mut args = Vec::new();
+                if self.peek() != Some(&Token::RParen) {
+                    args.push(self.expr()?);
+                    while self.peek() == Some(&Token::Comma) {
+                        self.pos += 1;
+                        args.push(self.expr()?);
+                    }
+                }
+                self.expect(Token::RParen)?;
+                Ok(Expr::Call(name, args))
+            }
+            Some(Token::Ident(word)) => match word.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ => Err(format!("unexpected '{}'", word)),
+            },
+            other => Err(format!("unexpected {:?}", other)),
+        }
+    }
+}
+
+fn parse(source: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+    };
+    let expr = parser.expr()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected {:?} after expression", token)),
+    }
+}
+
+/// Text form of a value for concatenation
+fn as_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::String(s) => !s.is_empty(),
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        _ => true,
+    }
+}
+
+/// Numbers compare by value, so `1` equals `1.0`
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(x), Some(y)) => x == y,
+        _ => a == b,
+    }
+}
+
+fn eval(expr: &Expr, payload: &Value) -> Value {
+    match expr {
+        Expr::Literal(value) => value.clone(),
+        Expr::Path(path) => path
+            .iter()
+            .try_fold(payload, |value, segment| match segment {
+                Segment::Field(field) => value.get(field),
+                Segment::Index(index) => value.get(index),
+            })
+            .cloned()
+            .unwrap_or(Value::Null),
+        Expr::Concat(parts) => Value::String(
+            parts
+                .iter()
+                .map(|part| as_text(&eval(part, payload)))
+                .collect(),
+        ),
+        Expr::Compare(left, right, negate) => {
+            Value::Bool(equal(&eval(left, payload), &eval(right, payload)) != *negate)
+        }
+        Expr::Call(name, args) => {
+            let arg = |i: usize| args.get(i).map_or(Value::Null, |a| eval(a, payload));
+            match name.as_str() {
+                "uuid" => Value::String(uuid::Uuid::now_v7().to_string()),
+                "now" => Value::String(chrono::Utc::now().to_rfc3339()),
+                "lower" => Value::String(as_text(&arg(0)).to_lowercase()),
+                "upper" => Value::String(as_text(&arg(0)).to_uppercase()),
+                "string" => Value::String(as_text(&arg(0))),
+                "number" => match arg(0) {
+                    Value::Number(n) => Value::Number(n),
+                    other => as_text(&other)
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(serde_json::Number::from_f64)
+                        .map_or(Value::Null, Value::Number),
+                },
+                "join" => {
+                    let separator = as_text(&arg(1));
+                    match arg(0) {
+                        Value::Array(items) => Value::String(
+                            items
+                                .iter()
+                                .map(as_text)
+                                .collect::<Vec<_>>()
+                                .join(&separator),
+                        ),
+                        other => Value::String(as_text(&other)),
+                    }
+                }
+                "coalesce" => args
+                    .iter()
+                    .map(|a| eval(a, payload))
+                    .find(|v| !v.is_null())
+                    .unwrap_or(Value::Null),
+                "if" => {
+                    if truthy(&arg(0)) {
+                        arg(1)
+                    } else {
+                        arg(2)
+                    }
+                }
+                _ => Value::Null,
+            }
+        }
+    }
+}
+
+/// Evaluate an expression against a payload.
+pub fn evaluate(source: &str, payload: &Value) -> Result<Value, String> {
+    Ok(eval(&parse(source)?, payload))
+}
+
+/// Render a template: every string in it is an expression.
+fn render(template: &Value, payload: &Value) -> Result<Value, String> {
+    Ok(match template {
+        Value::String(source) => evaluate(source, payload)?,
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| render(item, payload))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), render(value, payload)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+/// Check every expression of a mapping, in all its string positions
+fn check_template(template: &Value) -> Result<(), String> {
+    match template {
+        Value::String(source) => parse(source).map(|_| ()),
+        Value::Array(items) => items.iter().try_for_each(check_template),
+        Value::Object(fields) => fields.values().try_for_each(check_template),
+        _ => Ok(()),
+    }
+}
+
+/// Build the commit for a resource type, checking created resources against their type.
+fn build_commit(
+    resource_type: &str,
+    action: MappingAction,
+    id: String,
+    resource: Value,
+) -> Result<CommitBuilder, String> {
+    fn typed<T: schemars::JsonSchema + Serialize + serde::de::DeserializeOwned>(
+        action: MappingAction,
+        id: String,
+        resource: Value,
+    ) -> Result<CommitBuilder, String> {
+        match action {
+            MappingAction::Create => {
+                let resource: T = serde_json::from_value(resource)
+                    .map_err(|e| format!("invalid {}: {}", T::schema_name(), e))?;
+                Ok(CommitBuilder::create(id, &resource))
+            }
+            MappingAction::Patch if resource.is_object() => {
+                Ok(CommitBuilder::patch::<T>(id, resource))
+            }
+            MappingAction::Patch => Err("a patch must be an object".to_string()),
+            MappingAction::Delete => Ok(CommitBuilder::delete::<T>(id)),
+        }
+    }
+    match resource_type {
+        "issue" => typed::<Issue>(action, id, resource),
+        "comment" => typed::<Comment>(action, id, resource),
+        "task" => typed::<Task>(action, id, resource),
+        "planning" => typed::<Planning>(action, id, resource),
+        "document" => typed::<Document>(action, id, resource),
+        other => Err(format!("unsupported resource type '{}'", other)),
+    }
+}
+
+impl CommitMapping {
+    /// Check the mapping's expressions and resource type, e.g. before storing it.
+    pub fn validate(&self) -> Result<(), String> {
+        parse(&self.subject)?;
+        if let Some(id) = &self.resource_id {
+            parse(id)?;
+        }
+        if let Some(when) = &self.when {
+            parse(when)?;
+        }
+        check_template(&self.resource)?;
+        match self.resource_type.as_str() {
+            "issue" | "comment" | "task" | "planning" | "document" => Ok(()),
+            other => Err(format!("unsupported resource type '{}'", other)),
+        }
+    }
+
+    /// The commit this mapping makes from `payload` and its subject, or `None` when its
+    /// `when` condition is not met.
+    pub fn commit(
+        &self,
+        payload: &Value,
+        actor: &str,
+    ) -> Result<Option<(String, JSONCommit)>, String> {
+        if let Some(when) = &self.when {
+            if !truthy(&evaluate(when, payload)?) {
+                return Ok(None);
+            }
+        }
+        let subject = as_text(&evaluate(&self.subject, payload)?);
+        if subject.is_empty() {
+            return Err("subject evaluates to nothing".to_string());
+        }
+        let id = match &self.resource_id {
+            Some(expr) => as_text(&evaluate(expr, payload)?),
+            None => uuid::Uuid::now_v7().to_string(),
+        };
+        if id.is_empty() {
+            return Err("resource_id evaluates to nothing".to_string());
+        }
+        let resource = render(&self.resource, payload)?;
+        let commit = build_commit(&self.resource_type, self.action, id, resource)?
+            .actor(actor)
+            .build();
+        Ok(Some((subject, commit)))
+    }
+}
+
+/// Apply all mappings to a payload: the commit events, with `source` as event source and
+/// commit actor.
+pub fn map_payload(
+    mappings: &[CommitMapping],
+    payload: &Value,
+    source: &str,
+) -> Result<Vec<CloudEvent>, String> {
+    let mut events = Vec::new();
+    for mapping in mappings {
+        if let Some((subject, commit)) = mapping.commit(payload, source)? {
+            events.push(
+                CloudEventBuilder::commit(subject, &commit)
+                    .source(source)
+                    .build(),
+            );
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_expressions() {
+        let payload = json!({
+            "type": "payment.updated",
+            "amount": { "value": "12.50" },
+            "lines": [{ "name": "Leges" }, { "name": "Porto" }],
+            "tags": ["a", "b"],
+        });
+        let eval = |source: &str| evaluate(source, &payload).unwrap();
+        assert_eq!(eval("$.lines[1].name"), json!("Porto"));
+        assert_eq!(eval("$.missing.deeper"), Value::Null);
+        assert_eq!(eval("'€ ' & $.amount.value & $.missing"), json!("€ 12.50"));
+        assert_eq!(eval("number($.amount.value)"), json!(12.5));
+        assert_eq!(eval("$.type = 'payment.updated'"), json!(true));
+        assert_eq!(eval("if($.type != 'x', upper('ja'), 'nee')"), json!("JA"));
+        assert_eq!(eval("coalesce($.missing, 3)"), json!(3.0));
+        assert_eq!(eval("join($.tags, ', ')"), json!("a, b"));
+        assert!(evaluate("shout($.type)", &payload).is_err());
+        assert!(evaluate("$.type &", &payload).is_err());
+    }
+
+    #[test]
+    fn test_mapping_makes_valid_commits() {
+        let mappings: Vec<CommitMapping> = serde_json::from_value(json!([
+            {
+                "subject": "$.metadata.zaak",
+                "resource_type": "comment",
+                "resource": { "content": "'Betaling ' & lower($.status)" },
+                "when": "$.type = 'payment.updated'"
+            },
+            {
+                "subject": "$.metadata.zaak",
+                "resource_type": "task",
+                "resource": { "cta": "'Controleer betaling'", "description": "$.id" }
+            }
+        ]))
+        .unwrap();
+        mappings.iter().for_each(|m| m.validate().unwrap());
+        let payload = json!({ "type": "payment.updated", "id": "tr_1", "status": "PAID", "metadata": { "zaak": "issue-1" } });
+
+        // The task template lacks required fields, so nothing is made
+        let err = map_payload(&mappings, &payload, "hooks/psp").unwrap_err();
+        assert!(err.contains("invalid Task"), "{}", err);
+
+        let events = map_payload(&mappings[..1], &payload, "hooks/psp").unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].subject, "issue-1");
+        let commit: JSONCommit = serde_json::from_value(events[0].data.clone().unwrap()).unwrap();
+        assert_eq!(commit.resource_data.unwrap()["content"], "Betaling paid");
+        assert_eq!(commit.actor, "hooks/psp");
+
+        let other = json!({ "type": "payment.created", "metadata": { "zaak": "issue-1" } });
+        assert!(map_payload(&mappings[..1], &other, "hooks/psp")
+            .unwrap()
+            .is_empty());
+    }
+}
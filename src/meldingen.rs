@@ -0,0 +1,296 @@
+//! Intake of meldingen openbare ruimte: `POST /meldingen`.
+//!
+//! A citizen reports something at a place (a loose paving stone, a full container) with a
+//! category, a description, a location and references to uploaded photos. The melding
+//! becomes an `Issue` with a structured `location`, tagged with its category so labels,
+//! filters and assignment suggestions pick it up, and with the photos as `Document`s on it.
+//! The issue is submitted as an inbound event, so it goes through the same routing as
+//! `POST /events` (duplicate flagging, auto-assignment, rerouting). The answer holds the zaaknummer and its verification code
+//! for the anonymous status lookup. The issue and its photos are checked against the quotas
+//! together before the issue is submitted, so a melding is refused as a whole; photos that
+//! still fail to attach are listed in the answer.
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{internal_error, AppState};
+use crate::pipeline::EventContext;
+use crate::quotas::Quotas;
+use crate::schemas::{CloudEventBuilder, CommitBuilder, Document, Issue, IssueStatus, Location};
+
+/// Reference to a photo that was uploaded beforehand
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PhotoReference {
+    pub url: String,
+    /// File name; taken from the URL when missing
+    pub title: Option<String>,
+    /// Size in bytes, if known
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MeldingRequest {
+    /// Category, e.g. "afval", "straatverlichting"; becomes a label of the zaak
+    pub category: String,
+    pub description: String,
+    #[serde(default)]
+    pub photos: Vec<PhotoReference>,
+    pub lat: f64,
+    pub lon: f64,
+    /// Address or description of the place, if the app resolved one
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MeldingReceipt {
+    pub zaaknummer: String,
+    /// Code for `GET /status/{zaaknummer}/{code}`
+    pub verification_code: String,
+    /// URLs of photos that could not be attached; the melding is registered without them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing_photos: Vec<String>,
+}
+
+/// Category labels are lower case without surrounding spaces
+fn category_label(category: &str) -> String {
+    category.trim().to_lowercase()
+}
+
+fn valid_coordinates(lat: f64, lon: f64) -> bool {
+    (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)
+}
+
+fn photo_document(photo: PhotoReference) -> Document {
+    let title = photo.title.unwrap_or_else(|| {
+        photo
+            .url
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .unwrap_or("foto")
+            .to_string()
+    });
+    Document {
+        title,
+        url: photo.url,
+        size: photo.size.unwrap_or(0),
+    }
+}
+
+/// POST /meldingen - Report a melding openbare ruimte
+#[utoipa::path(
+    post,
+    path = "/meldingen",
+    tag = "portal",
+    request_body = MeldingRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "Melding registered as a zaak", body = MeldingReceipt),
+        (status = 400, description = "Empty category or description, coordinates out of range, or a photo URL that is not http(s)"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 413, description = "The photos would exceed a storage quota"),
+        (status = 429, description = "The melding would exceed an event or resource quota"),
+    )
+)]
+pub async fn create_melding_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<MeldingRequest>,
+) -> Result<(StatusCode, Json<MeldingReceipt>), StatusCode> {
+    register_melding(&state, &Quotas::from_env(), auth_user.user_id, request).await
+}
+
+/// Register the melding of `user` as a zaak with its photos, if all of it fits in `quotas`.
+async fn register_melding(
+    state: &AppState,
+    quotas: &Quotas,
+    user: String,
+    request: MeldingRequest,
+) -> Result<(StatusCode, Json<MeldingReceipt>), StatusCode> {
+    let category = category_label(&request.category);
+    let description = request.description.trim().to_string();
+    let photo_urls_valid = request
+        .photos
+        .iter()
+        .all(|p| p.url.starts_with("https://") || p.url.starts_with("http://"));
+    if category.is_empty()
+        || description.is_empty()
+        || !valid_coordinates(request.lat, request.lon)
+        || !photo_urls_valid
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let address = request
+        .address
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty());
+    let title = match &address {
+        Some(address) => format!("Melding {}: {}", category, address),
+        None => format!("Melding {}", category),
+    };
+    let issue = Issue {
+        title,
+        description: Some(description),
+        status: IssueStatus::Open,
+        involved: Some(vec![user.clone()]),
+        tags: Some(vec![category]),
+        location: Some(Location {
+            lat: request.lat,
+            lon: request.lon,
+            address,
+        }),
+        ..Default::default()
+    };
+
+    let zaaknummer = uuid::Uuid::now_v7().to_string();
+    let mut commits = vec![CommitBuilder::create(zaaknummer.clone(), &issue)
+        .actor(user.clone())
+        .build()];
+    let mut photo_urls = Vec::new();
+    for photo in request.photos {
+        photo_urls.push(photo.url.clone());
+        let document = photo_document(photo);
+        let commit = CommitBuilder::create(uuid::Uuid::now_v7().to_string(), &document)
+            .actor(user.clone())
+            .build();
+        commits.push(commit);
+    }
+    let exceeded = crate::quotas::check_commits(&state.storage, quotas, &user, &commits)
+        .await
+        .map_err(internal_error)?;
+    if let Some(exceeded) = exceeded {
+        eprintln!("[meldingen] melding refused: {}", exceeded);
+        return Err(exceeded.status());
+    }
+
+    // The melding comes in from outside, so routing rules apply to it, and its parts are
+    // charged to the melder
+    let submit = |commit| {
+        let event = CloudEventBuilder::commit(zaaknummer.clone(), &commit)
+            .source(user.clone())
+            .build();
+        let ctx = EventContext::new(event).inbound(Some(user.clone()));
+        state.pipeline.submit(state, ctx)
+    };
+    let mut commits = commits.into_iter();
+    let issue_commit = commits.next().expect("the issue commit");
+    submit(issue_commit).await.map_err(|e| {
+        eprintln!("[meldingen] melding not accepted: {}", e);
+        e.status()
+    })?;
+    let mut missing_photos = Vec::new();
+    for (url, commit) in photo_urls.into_iter().zip(commits) {
+        if let Err(e) = submit(commit).await {
+            eprintln!(
+                "[meldingen] photo {} of {} not attached: {}",
+                url, zaaknummer, e
+            );
+            missing_photos.push(url);
+        }
+    }
+    Ok((
+        StatusCode::CREATED,
+        Json(MeldingReceipt {
+            verification_code: crate::status::verification_code(&zaaknummer),
+            zaaknummer,
+            missing_photos,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user, test_state};
+
+    #[tokio::test]
+    async fn test_melding_intake() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let melding = |lat: f64| MeldingRequest {
+            category: " Afval ".to_string(),
+            description: "Container puilt uit".to_string(),
+            photos: vec![PhotoReference {
+                url: "https://uploads.example/abc/container.jpg".to_string(),
+                title: None,
+                size: Some(2048),
+            }],
+            lat,
+            lon: 4.8952,
+            address: Some("Dam 1, Amsterdam".to_string()),
+        };
+        let jan = "jan@example.com";
+        let user = || auth_user(jan);
+
+        let err = create_melding_handler(State(state.clone()), user(), Json(melding(123.0)))
+            .await
+            .unwrap_err();
+        assert_eq!(err, StatusCode::BAD_REQUEST);
+
+        let (status, Json(receipt)) =
+            create_melding_handler(State(state.clone()), user(), Json(melding(52.3702)))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(crate::status::verify_code(
+            &receipt.zaaknummer,
+            &receipt.verification_code
+        ));
+
+        let issue: Issue = serde_json::from_value(
+            state
+                .storage
+                .get_resource(&receipt.zaaknummer)
+                .await
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(issue.title, "Melding afval: Dam 1, Amsterdam");
+        assert_eq!(issue.tags, Some(vec!["afval".to_string()]));
+        assert_eq!(issue.location.unwrap().lat, 52.3702);
+        assert_eq!(issue.involved, Some(vec!["jan@example.com".to_string()]));
+
+        let photos: Vec<Document> = crate::portal::thread_items(&state, &receipt.zaaknummer)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|item| serde_json::from_value(item.value).ok())
+            .collect();
+        assert_eq!(photos.len(), 1);
+        assert_eq!(photos[0].title, "container.jpg");
+        assert!(receipt.missing_photos.is_empty());
+
+        // A melding whose photos don't fit is refused before anything is registered
+        let quotas = Quotas {
+            user: crate::quotas::QuotaLimits {
+                resources: Some(3),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let resources = || async { state.storage.list_resources(0, 1000).await.unwrap().len() };
+        let before = resources().await;
+        let mut with_photos = melding(52.3702);
+        with_photos.photos.push(PhotoReference {
+            url: "https://uploads.example/abc/tegel.jpg".to_string(),
+            title: None,
+            size: None,
+        });
+        let err = register_melding(&state, &quotas, jan.to_string(), with_photos)
+            .await
+            .unwrap_err();
+        assert_eq!(err, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resources().await, before);
+        let without_photos = MeldingRequest {
+            photos: vec![],
+            ..melding(52.3702)
+        };
+        let (status, _) = register_melding(&state, &quotas, jan.to_string(), without_photos)
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+    }
+}
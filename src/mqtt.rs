@@ -0,0 +1,534 @@
+//! MQTT bridge that turns smart-city sensor messages into meldingen.
+//!
+//! Sensors (afvalcontainers, geluidsmeters, ...) publish JSON readings to an MQTT broker. The
+//! bridge subscribes to the topics in its configuration (a YAML file, see [`BridgeConfig`])
+//! and for each message takes the first sensor mapping whose topic filter matches and whose
+//! threshold is passed. It then creates an `Issue` through the commit pipeline, with title
+//! and description rendered from the reading. A sensor that keeps reporting creates at most
+//! one melding per deduplication window of its mapping.
+//!
+//! The client speaks plain MQTT 3.1.1 over TCP (no TLS; run a local broker or bridge for
+//! remote `mqtts://` brokers). Messages are subscribed with QoS 1 and acknowledged once
+//! they are handled, and retained messages are skipped, as they describe old readings.
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::handlers::{submit_event, AppState};
+use crate::schemas::{CloudEventBuilder, CommitBuilder, Issue, IssueStatus};
+
+/// Seconds between pings; the broker drops us after 1.5 times this without packets
+const KEEP_ALIVE: u16 = 60;
+/// Wait before reconnecting after the connection is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+const SUBSCRIBE_PACKET_ID: u16 = 1;
+
+/// Bridge configuration, read from the file in `MQTT_CONFIG`:
+///
+/// ```yaml
+/// broker: mqtt://broker.gemeente.nl:1883
+/// username: zaakchat
+/// password: geheim
+/// sensors:
+///   - topic: sensors/afval/+/vulgraad
+///     sensor_field: container_id
+///     when: { field: vulgraad, above: 90 }
+///     title: "Afvalcontainer {container_id} is vol ({vulgraad}%)"
+///     team: team-afval
+///     tags: [afval]
+///     dedup_minutes: 720
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct BridgeConfig {
+    /// `mqtt://host[:port]`
+    pub broker: String,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    pub sensors: Vec<SensorMapping>,
+}
+
+fn default_client_id() -> String {
+    "zaakchat".to_string()
+}
+
+/// How messages on a topic become meldingen
+#[derive(Debug, Clone, Deserialize)]
+pub struct SensorMapping {
+    /// Topic filter, with the MQTT wildcards `+` (one level) and `#` (the rest)
+    pub topic: String,
+    /// Field of the reading that identifies the sensor; the topic is used when missing
+    #[serde(default)]
+    pub sensor_field: Option<String>,
+    /// Only readings passing this threshold create a melding
+    #[serde(default)]
+    pub when: Option<Threshold>,
+    /// Title template; `{field}` is replaced by a field of the reading (`a.b` for nested
+    /// fields), `{sensor}` by the sensor and `{topic}` by the topic
+    pub title: String,
+    /// Description template; defaults to the sensor, topic and reading
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub team: Option<String>,
+    #[serde(default)]
+    pub assignee: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Readings of a sensor within this many minutes after a melding don't create another
+    #[serde(default = "default_dedup_minutes")]
+    pub dedup_minutes: u64,
+}
+
+fn default_dedup_minutes() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Threshold {
+    pub field: String,
+    #[serde(default)]
+    pub above: Option<f64>,
+    #[serde(default)]
+    pub below: Option<f64>,
+}
+
+impl Threshold {
+    fn passed(&self, reading: &serde_json::Value) -> bool {
+        let Some(value) = field(reading, &self.field).and_then(|v| v.as_f64()) else {
+            return false;
+        };
+        self.above.is_none_or(|above| value > above) && self.below.is_none_or(|below| value < below)
+    }
+}
+
+/// Read a bridge configuration from a YAML file.
+pub fn load_config(
+    path: impl AsRef<std::path::Path>,
+) -> Result<BridgeConfig, Box<dyn std::error::Error + Send + Sync>> {
+    let config: BridgeConfig = serde_yaml::from_str(&std::fs::read_to_string(path)?)?;
+    broker_address(&config.broker)?;
+    Ok(config)
+}
+
+/// `host:port` of an `mqtt://` broker URL
+fn broker_address(broker: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let host = broker
+        .strip_prefix("mqtt://")
+        .ok_or("broker must be an mqtt:// URL")?
+        .trim_end_matches('/');
+    Ok(if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:1883", host)
+    })
+}
+
+/// Does `topic` match the subscription `filter`?
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (part, Some(level)) if part == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+/// A field of the reading by dotted path
+fn field<'a>(reading: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.')
+        .try_fold(reading, |value, key| value.get(key))
+}
+
+fn field_text(reading: &serde_json::Value, path: &str) -> Option<String> {
+    field(reading, path).map(|value| match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    })
+}
+
+/// Fill in a title or description template. Unknown fields are left as they are.
+fn render(template: &str, reading: &serde_json::Value, sensor: &str, topic: &str) -> String {
+    let placeholder = regex::Regex::new(r"\{([A-Za-z0-9_.]+)\}").expect("valid regex");
+    placeholder
+        .replace_all(template, |caps: &regex::Captures| match &caps[1] {
+            "sensor" => sensor.to_string(),
+            "topic" => topic.to_string(),
+            path => field_text(reading, path).unwrap_or_else(|| caps[0].to_string()),
+        })
+        .into_owned()
+}
+
+/// Turns messages into meldingen, remembering when each sensor last created one.
+pub struct Bridge {
+    config: BridgeConfig,
+    last_melding: DashMap<(usize, String), Instant>,
+}
+
+impl Bridge {
+    pub fn new(config: BridgeConfig) -> Self {
+        Self {
+            config,
+            last_melding: DashMap::new(),
+        }
+    }
+
+    /// Handle one message. Returns the ID of the melding it created, if any.
+    pub async fn handle_message(
+        &self,
+        state: &AppState,
+        topic: &str,
+        payload: &[u8],
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let Ok(reading) = serde_json::from_slice::<serde_json::Value>(payload) else {
+            eprintln!("[mqtt] ignoring non-JSON message on {}", topic);
+            return Ok(None);
+        };
+        let Some((index, mapping)) = self.config.sensors.iter().enumerate().find(|(_, m)| {
+            topic_matches(&m.topic, topic) && m.when.as_ref().is_none_or(|t| t.passed(&reading))
+        }) else {
+            return Ok(None);
+        };
+
+        let sensor = mapping
+            .sensor_field
+            .as_deref()
+            .and_then(|path| field_text(&reading, path))
+            .unwrap_or_else(|| topic.to_string());
+        let window = Duration::from_secs(mapping.dedup_minutes * 60);
+        let key = (index, sensor.clone());
+        if self
+            .last_melding
+            .get(&key)
+            .is_some_and(|last| last.elapsed() < window)
+        {
+            return Ok(None);
+        }
+
+        let description = match &mapping.description {
+            Some(template) => render(template, &reading, &sensor, topic),
+            None => format!("Sensor {} meldt op {}:\n{}", sensor, topic, reading),
+        };
+        let issue = Issue {
+            title: render(&mapping.title, &reading, &sensor, topic),
+            description: Some(description),
+            status: IssueStatus::Open,
+            assignee: mapping.assignee.clone(),
+            team: mapping.team.clone(),
+            involved: mapping.assignee.clone().map(|a| vec![a]),
+            tags: (!mapping.tags.is_empty()).then(|| mapping.tags.clone()),
+            ..Default::default()
+        };
+        let id = uuid::Uuid::now_v7().to_string();
+        let commit = CommitBuilder::create(id.clone(), &issue)
+            .actor(format!("sensor:{}", sensor))
+            .build();
+        let event = CloudEventBuilder::commit(id.clone(), &commit)
+            .source("mqtt")
+            .build();
+        submit_event(state, event).await?;
+        self.last_melding.insert(key, Instant::now());
+        Ok(Some(id))
+    }
+}
+
+fn encode_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// A packet with its fixed header
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![header];
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        buf.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    buf.extend_from_slice(body);
+    buf
+}
+
+fn connect_packet(config: &BridgeConfig) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_string(&mut body, "MQTT");
+    body.push(4); // protocol level 3.1.1
+    let mut flags = 0x02; // clean session
+    if config.username.is_some() {
+        flags |= 0x80;
+    }
+    if config.password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    body.extend_from_slice(&KEEP_ALIVE.to_be_bytes());
+    encode_string(&mut body, &config.client_id);
+    for credential in [&config.username, &config.password].into_iter().flatten() {
+        encode_string(&mut body, credential);
+    }
+    packet(0x10, &body)
+}
+
+fn subscribe_packet(filters: &[&str]) -> Vec<u8> {
+    let mut body = SUBSCRIBE_PACKET_ID.to_be_bytes().to_vec();
+    for filter in filters {
+        encode_string(&mut body, filter);
+        body.push(1); // QoS 1
+    }
+    packet(0x82, &body)
+}
+
+/// Read a packet: its fixed header byte and its body.
+async fn read_packet(stream: &mut (impl AsyncRead + Unpin)) -> std::io::Result<(u8, Vec<u8>)> {
+    let header = stream.read_u8().await?;
+    let mut len = 0usize;
+    for shift in [0, 7, 14, 21] {
+        let byte = stream.read_u8().await?;
+        len |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            let mut body = vec![0; len];
+            stream.read_exact(&mut body).await?;
+            return Ok((header, body));
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "malformed remaining length",
+    ))
+}
+
+/// A received PUBLISH: topic, packet ID (QoS > 0), payload and whether it was retained
+struct Publish {
+    topic: String,
+    packet_id: Option<u16>,
+    payload: Vec<u8>,
+    retained: bool,
+}
+
+fn parse_publish(header: u8, body: &[u8]) -> Option<Publish> {
+    let qos = (header >> 1) & 0x03;
+    let topic_len = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let topic = String::from_utf8(body.get(2..2 + topic_len)?.to_vec()).ok()?;
+    let mut rest = 2 + topic_len;
+    let packet_id = if qos > 0 {
+        rest += 2;
+        Some(u16::from_be_bytes([
+            *body.get(rest - 2)?,
+            *body.get(rest - 1)?,
+        ]))
+    } else {
+        None
+    };
+    Some(Publish {
+        topic,
+        packet_id,
+        payload: body.get(rest..)?.to_vec(),
+        retained: header & 0x01 != 0,
+    })
+}
+
+impl Bridge {
+    /// Connect, subscribe and handle messages until the connection fails.
+    async fn run(&self, state: &AppState) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let stream = TcpStream::connect(broker_address(&self.config.broker)?).await?;
+        let (mut reader, writer) = stream.into_split();
+        let writer = Arc::new(Mutex::new(writer));
+
+        writer
+            .lock()
+            .await
+            .write_all(&connect_packet(&self.config))
+            .await?;
+        let (header, body) = read_packet(&mut reader).await?;
+        if header != 0x20 || body.get(1) != Some(&0) {
+            return Err(
+                format!("broker refused connection (return code {:?})", body.get(1)).into(),
+            );
+        }
+        let filters: Vec<&str> = self
+            .config
+            .sensors
+            .iter()
+            .map(|m| m.topic.as_str())
+            .collect();
+        writer
+            .lock()
+            .await
+            .write_all(&subscribe_packet(&filters))
+            .await?;
+        println!("[mqtt] subscribed to {} topic(s)", filters.len());
+
+        let pinger = {
+            let writer = writer.clone();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(Duration::from_secs(KEEP_ALIVE as u64));
+                loop {
+                    ticker.tick().await;
+                    if writer.lock().await.write_all(&[0xc0, 0x00]).await.is_err() {
+                        break;
+                    }
+                }
+            })
+        };
+
+        let result = async {
+            loop {
+                let (header, body) = read_packet(&mut reader).await?;
+                // Only PUBLISH needs handling; SUBACK and PINGRESP are ignored
+                if header >> 4 != 3 {
+                    continue;
+                }
+                let Some(publish) = parse_publish(header, &body) else {
+                    eprintln!("[mqtt] ignoring malformed PUBLISH");
+                    continue;
+                };
+                if !publish.retained {
+                    if let Err(e) = self
+                        .handle_message(state, &publish.topic, &publish.payload)
+                        .await
+                    {
+                        eprintln!(
+                            "[mqtt] failed to handle message on {}: {}",
+                            publish.topic, e
+                        );
+                    }
+                }
+                if let Some(id) = publish.packet_id {
+                    let mut puback = vec![0x40, 0x02];
+                    puback.extend_from_slice(&id.to_be_bytes());
+                    writer.lock().await.write_all(&puback).await?;
+                }
+            }
+        }
+        .await;
+        pinger.abort();
+        result
+    }
+}
+
+/// Run the bridge, reconnecting whenever the connection is lost.
+pub fn spawn_mqtt_bridge(state: AppState, config: BridgeConfig) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let bridge = Bridge::new(config);
+        loop {
+            if let Err(e) = bridge.run(&state).await {
+                eprintln!("[mqtt] connection to {} lost: {}", bridge.config.broker, e);
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::test_state;
+
+    #[test]
+    fn test_topic_matches() {
+        assert!(topic_matches(
+            "sensors/afval/+/vulgraad",
+            "sensors/afval/c12/vulgraad"
+        ));
+        assert!(topic_matches("sensors/#", "sensors/geluid/dam"));
+        assert!(!topic_matches(
+            "sensors/afval/+/vulgraad",
+            "sensors/afval/c12"
+        ));
+        assert!(!topic_matches(
+            "sensors/afval/+",
+            "sensors/afval/c12/vulgraad"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_parse_packets() {
+        let mut body = Vec::new();
+        encode_string(&mut body, "sensors/afval/c12");
+        body.extend_from_slice(&7u16.to_be_bytes());
+        body.extend_from_slice(&[b'x'; 200]);
+        let bytes = packet(0x32, &body);
+
+        let (header, body) = read_packet(&mut bytes.as_slice()).await.unwrap();
+        let publish = parse_publish(header, &body).unwrap();
+        assert_eq!(publish.topic, "sensors/afval/c12");
+        assert_eq!(publish.packet_id, Some(7));
+        assert_eq!(publish.payload.len(), 200);
+        assert!(!publish.retained);
+    }
+
+    #[tokio::test]
+    async fn test_messages_become_meldingen() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let config: BridgeConfig = serde_yaml::from_str(
+            r#"
+broker: mqtt://localhost
+sensors:
+  - topic: sensors/afval/+/vulgraad
+    sensor_field: container.id
+    when: { field: vulgraad, above: 90 }
+    title: "Afvalcontainer {container.id} is vol ({vulgraad}%)"
+    team: team-afval
+    tags: [afval]
+"#,
+        )
+        .unwrap();
+        let bridge = Bridge::new(config);
+        let reading = |id: &str, level: u32| {
+            format!(
+                r#"{{"container": {{"id": "{}"}}, "vulgraad": {}}}"#,
+                id, level
+            )
+        };
+        let topic = "sensors/afval/dam/vulgraad";
+
+        let none = bridge
+            .handle_message(&state, topic, reading("c12", 40).as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(none, None);
+
+        let id = bridge
+            .handle_message(&state, topic, reading("c12", 95).as_bytes())
+            .await
+            .unwrap()
+            .unwrap();
+        let issue: Issue =
+            serde_json::from_value(state.storage.get_resource(&id).await.unwrap().unwrap())
+                .unwrap();
+        assert_eq!(issue.title, "Afvalcontainer c12 is vol (95%)");
+        assert_eq!(issue.team.as_deref(), Some("team-afval"));
+
+        // The same sensor within the window is deduplicated, another sensor is not
+        let repeat = bridge
+            .handle_message(&state, topic, reading("c12", 97).as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(repeat, None);
+        let other = bridge
+            .handle_message(&state, topic, reading("c13", 92).as_bytes())
+            .await
+            .unwrap();
+        assert!(other.is_some());
+    }
+}
@@ -0,0 +1,225 @@
+//! Muting issues: silence the notifications of a noisy issue for a while.
+//!
+//! Like watching, a mute is a user preference stored per user and issue, so muting does not
+//! change `involved` or the watchers: once the mute ends (or is removed) notifications resume
+//! as before. A mute applies to every channel `send_notifications_for_event` dispatches to.
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{authorize_issue, AppState};
+use crate::storage::Storage;
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct MuteRequest {
+    /// How long to mute the issue; without it, the issue stays muted until unmuted
+    #[serde(default)]
+    pub minutes: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MuteStatus {
+    pub issue_id: String,
+    pub muted: bool,
+    /// When the mute ends, if it ends by itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Whether `user` has muted `issue_id` at `now`. Expired mutes don't count.
+pub async fn is_muted(
+    storage: &Storage,
+    issue_id: &str,
+    user: &str,
+    now: DateTime<Utc>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(match storage.get_mute(issue_id, user).await?.as_deref() {
+        None => false,
+        Some("") => true,
+        Some(until) => DateTime::parse_from_rfc3339(until)? > now,
+    })
+}
+
+fn internal(issue_id: &str, e: Box<dyn std::error::Error + Send + Sync>) -> StatusCode {
+    eprintln!("[mute] failed to update mute on {}: {}", issue_id, e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// POST /issues/{id}/mute - Stop notifications about an issue, optionally for a while
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/mute",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    request_body(content = MuteRequest, description = "Optional duration of the mute"),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The issue is muted for the caller", body = MuteStatus),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn mute_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+    body: Option<Json<MuteRequest>>,
+) -> Result<Json<MuteStatus>, StatusCode> {
+    let user = &auth_user.user_id;
+    authorize_issue(&state, user, &issue_id).await?;
+    let Json(request) = body.unwrap_or_default();
+    let until = match request.minutes {
+        Some(minutes) => {
+            let minutes = i64::try_from(minutes).map_err(|_| StatusCode::BAD_REQUEST)?;
+            let until = Duration::try_minutes(minutes)
+                .and_then(|duration| Utc::now().checked_add_signed(duration))
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            Some(until)
+        }
+        None => None,
+    };
+    state
+        .storage
+        .set_mute(
+            &issue_id,
+            user,
+            until.map(|until| until.to_rfc3339()).as_deref(),
+        )
+        .await
+        .map_err(|e| internal(&issue_id, e))?;
+    Ok(Json(MuteStatus {
+        issue_id,
+        muted: true,
+        until,
+    }))
+}
+
+/// DELETE /issues/{id}/mute - Get notifications about an issue again
+#[utoipa::path(
+    delete,
+    path = "/issues/{id}/mute",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The issue is no longer muted for the caller", body = MuteStatus),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn unmute_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+) -> Result<Json<MuteStatus>, StatusCode> {
+    let user = &auth_user.user_id;
+    authorize_issue(&state, user, &issue_id).await?;
+    state
+        .storage
+        .remove_mute(&issue_id, user)
+        .await
+        .map_err(|e| internal(&issue_id, e))?;
+    Ok(Json(MuteStatus {
+        issue_id,
+        muted: false,
+        until: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state};
+
+    #[tokio::test]
+    async fn test_mute_and_unmute() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let lantaarnpaal = issue("Lantaarnpaal kapot", &[alice]);
+        create_issue(&state, "issue-1", &lantaarnpaal, alice).await;
+        let muted = |at: DateTime<Utc>| {
+            let storage = state.storage.clone();
+            async move { is_muted(&storage, "issue-1", alice, at).await.unwrap() }
+        };
+        let now = Utc::now();
+        assert!(!muted(now).await);
+
+        let Json(status) = mute_handler(
+            State(state.clone()),
+            user(alice),
+            Path("issue-1".to_string()),
+            Some(Json(MuteRequest { minutes: Some(60) })),
+        )
+        .await
+        .unwrap();
+        assert!(status.muted);
+        assert!(muted(now).await);
+        // The mute ends by itself
+        assert!(!muted(now + Duration::hours(2)).await);
+
+        // Without a duration, until unmuted
+        let Json(status) = mute_handler(
+            State(state.clone()),
+            user(alice),
+            Path("issue-1".to_string()),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(status.until, None);
+        assert!(muted(now + Duration::days(365)).await);
+
+        assert_eq!(
+            mute_handler(
+                State(state.clone()),
+                user("bob@gemeente.nl"),
+                Path("issue-1".to_string()),
+                None,
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        let unknown = mute_handler(
+            State(state.clone()),
+            user(alice),
+            Path("issue-9".to_string()),
+            None,
+        );
+        assert_eq!(unknown.await.unwrap_err(), StatusCode::NOT_FOUND);
+        let forever = mute_handler(
+            State(state.clone()),
+            user(alice),
+            Path("issue-1".to_string()),
+            Some(Json(MuteRequest {
+                minutes: Some(u64::MAX),
+            })),
+        );
+        assert_eq!(forever.await.unwrap_err(), StatusCode::BAD_REQUEST);
+        let outsider = unmute_handler(
+            State(state.clone()),
+            user("bob@gemeente.nl"),
+            Path("issue-1".to_string()),
+        );
+        assert_eq!(outsider.await.unwrap_err(), StatusCode::FORBIDDEN);
+
+        let Json(status) = unmute_handler(
+            State(state.clone()),
+            user(alice),
+            Path("issue-1".to_string()),
+        )
+        .await
+        .unwrap();
+        assert!(!status.muted);
+        assert!(!muted(now).await);
+    }
+}
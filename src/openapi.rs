@@ -0,0 +1,316 @@
+//! OpenAPI specification for the REST surface (`/resources`, `/query`, `/login`, ...).
+//!
+//! Paths come from the `#[utoipa::path]` annotations on the handlers. The CloudEvent and
+//! resource schemas are NOT derived a second time for utoipa: they are taken from
+//! `schemas::get_all_schemas()` (schemars), the same source that feeds `/schemas` and the
+//! AsyncAPI spec, so REST and event documentation cannot drift apart.
+use std::borrow::Cow;
+
+use axum::response::Html;
+use axum::Json;
+use serde_json::Value;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{Ref, RefOr, Schema};
+use utoipa::{Modify, OpenApi, PartialSchema, ToSchema};
+
+use crate::schemas::{self, CloudEvent, JSONCommit, RelationType};
+use crate::{
+    assignment, audit, availability, boards, bulk, calendar, checklists, comments, conflicts,
+    connectors, duplicates, escalation, forms, handlers, hooks, integrity, invites, labels, live,
+    maintenance, meldingen, mute, poll, portal, projections, quotas, read_receipts, registry,
+    relations, resource_types, search_status, signing, status, sync, teams, timeline, uploads,
+    users, views, watch, workflow,
+};
+
+/// Let schemars-described types be used in `#[utoipa::path]` bodies. They resolve to a
+/// `$ref` into `components/schemas`, which `openapi_spec` fills from `get_all_schemas()`.
+macro_rules! schemars_component {
+    ($($type_name:ident),+ $(,)?) => {
+        $(
+            impl PartialSchema for $type_name {
+                fn schema() -> RefOr<Schema> {
+                    Ref::from_schema_name(stringify!($type_name)).into()
+                }
+            }
+
+            impl ToSchema for $type_name {
+                fn name() -> Cow<'static, str> {
+                    Cow::Borrowed(stringify!($type_name))
+                }
+            }
+        )+
+    };
+}
+
+schemars_component!(CloudEvent, JSONCommit, RelationType);
+
+/// Registers the authentication schemes used by the API.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        // EventSource cannot set headers, so the event stream takes the JWT as `?token=`
+        components.add_security_scheme(
+            "query_token",
+            SecurityScheme::ApiKey(ApiKey::Query(ApiKeyValue::new("token"))),
+        );
+        // Calendar apps subscribe to the iCalendar feeds with a feed token as `?token=`
+        components.add_security_scheme(
+            "feed_token",
+            SecurityScheme::ApiKey(ApiKey::Query(ApiKeyValue::new("token"))),
+        );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "ZaakChat REST API",
+        description = "REST endpoints for resources, search and authentication. The real-time event stream is documented in the AsyncAPI specification at /asyncapi-docs."
+    ),
+    paths(
+        handlers::get_or_stream_events,
+        handlers::handle_event,
+        handlers::revert_event_handler,
+        handlers::event_gaps_handler,
+        poll::poll_events_handler,
+        sync::sync_handler,
+        handlers::list_resources,
+        handlers::get_resource,
+        handlers::delete_resource,
+        handlers::patch_resource,
+        conflicts::resolve_conflict_handler,
+        resource_types::list_resource_types_handler,
+        resource_types::register_resource_type_handler,
+        bulk::bulk_handler,
+        handlers::query_resources,
+        timeline::issue_timeline_handler,
+        read_receipts::mark_read_handler,
+        watch::watch_handler,
+        watch::unwatch_handler,
+        mute::mute_handler,
+        mute::unmute_handler,
+        workflow::workflow_handler,
+        forms::validate_form_handler,
+        checklists::create_checklist_handler,
+        checklists::toggle_item_handler,
+        signing::start_signing_handler,
+        signing::signing_callback_handler,
+        signing::signed_document_handler,
+        uploads::options_handler,
+        uploads::create_upload_handler,
+        uploads::upload_offset_handler,
+        uploads::append_upload_handler,
+        uploads::delete_upload_handler,
+        uploads::download_upload_handler,
+        relations::list_relations_handler,
+        relations::link_handler,
+        relations::unlink_handler,
+        duplicates::list_duplicates_handler,
+        duplicates::merge_handler,
+        labels::list_labels_handler,
+        labels::create_label_handler,
+        labels::update_label_handler,
+        labels::delete_label_handler,
+        labels::attach_label_handler,
+        labels::detach_label_handler,
+        labels::label_facets_handler,
+        teams::list_teams_handler,
+        teams::create_team_handler,
+        teams::add_member_handler,
+        teams::remove_member_handler,
+        teams::assign_team_handler,
+        teams::team_stats_handler,
+        boards::get_board_handler,
+        boards::reorder_columns_handler,
+        boards::move_card_handler,
+        assignment::suggestions_handler,
+        users::search_users_handler,
+        users::update_profile_handler,
+        availability::update_availability_handler,
+        availability::get_availability_handler,
+        escalation::get_policy_handler,
+        escalation::update_policy_handler,
+        calendar::get_calendar_handler,
+        calendar::update_calendar_handler,
+        calendar::delete_calendar_handler,
+        calendar::sync_calendar_handler,
+        calendar::create_feed_token_handler,
+        calendar::delete_feed_token_handler,
+        calendar::my_calendar_feed_handler,
+        calendar::issue_calendar_feed_handler,
+        invites::list_invites_handler,
+        invites::create_invite_handler,
+        invites::revoke_invite_handler,
+        invites::set_expiry_handler,
+        invites::resend_invite_handler,
+        portal::list_zaken_handler,
+        portal::get_zaak_handler,
+        portal::add_comment_handler,
+        meldingen::create_melding_handler,
+        hooks::receive_hook_handler,
+        hooks::list_integrations_handler,
+        hooks::update_integration_handler,
+        hooks::delete_integration_handler,
+        projections::list_projections_handler,
+        projections::get_projection_handler,
+        projections::get_projection_value_handler,
+        projections::rebuild_projection_handler,
+        views::issues_by_status_handler,
+        views::tasks_by_assignee_handler,
+        views::my_tasks_handler,
+        quotas::usage_handler,
+        maintenance::storage_report_handler,
+        maintenance::vacuum_handler,
+        maintenance::compact_handler,
+        integrity::health_handler,
+        search_status::search_status_handler,
+        integrity::start_reindex_handler,
+        integrity::cancel_reindex_handler,
+        search_status::metrics_handler,
+        registry::list_event_types_handler,
+        registry::list_versions_handler,
+        registry::get_version_handler,
+        registry::check_schema_handler,
+        registry::register_schema_handler,
+        status::public_status_handler,
+        status::status_code_handler,
+        connectors::list_connectors_handler,
+        connectors::create_connector_handler,
+        connectors::delete_connector_handler,
+        live::typing_handler,
+        live::presence_heartbeat_handler,
+        live::presence_leave_handler,
+        comments::add_reaction_handler,
+        comments::remove_reaction_handler,
+        comments::comment_thread_handler,
+        comments::edit_comment_handler,
+        comments::delete_comment_handler,
+        comments::comment_history_handler,
+        handlers::inbound_email_handler,
+        handlers::debug_db,
+        handlers::login_handler,
+        handlers::verify_login_handler,
+        handlers::reset_handler,
+        audit::actor_audit_handler,
+        schemas::handle_get_schemas_index,
+        schemas::handle_get_schema,
+    ),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "events", description = "CloudEvents command and sync endpoints"),
+        (name = "resources", description = "Materialized resources (issues, comments, tasks, ...)"),
+        (name = "search", description = "Full-text search"),
+        (name = "auth", description = "Passwordless login"),
+        (name = "portal", description = "Citizen portal: the citizen's view of their own zaken"),
+        (name = "schemas", description = "JSON Schemas for events and resources"),
+        (name = "admin", description = "Diagnostics and maintenance"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Build the OpenAPI document, with `components/schemas` completed from schemars.
+pub fn openapi_spec() -> Value {
+    let mut spec = serde_json::to_value(ApiDoc::openapi()).unwrap_or(Value::Null);
+
+    let schemas_obj = spec
+        .as_object_mut()
+        .map(|root| {
+            root.entry("components")
+                .or_insert_with(|| serde_json::json!({}))
+        })
+        .and_then(|components| components.as_object_mut())
+        .map(|components| {
+            components
+                .entry("schemas")
+                .or_insert_with(|| serde_json::json!({}))
+        })
+        .and_then(|schemas| schemas.as_object_mut());
+
+    if let Some(component_schemas) = schemas_obj {
+        for (name, mut schema) in schemas::get_all_schemas() {
+            // Root-level JSON Schema keywords that are not valid inside an OpenAPI component
+            if let Some(obj) = schema.as_object_mut() {
+                obj.remove("$schema");
+                obj.remove("definitions");
+            }
+            component_schemas.insert(name, schema);
+        }
+    }
+
+    spec
+}
+
+/// GET /openapi.json - OpenAPI specification for the REST API
+pub async fn handle_get_openapi() -> Json<Value> {
+    Json(openapi_spec())
+}
+
+/// GET /swagger-ui - Interactive API documentation (Swagger UI loaded from a CDN)
+pub async fn handle_swagger_ui() -> Html<&'static str> {
+    Html(
+        r##"<!DOCTYPE html>
+<html lang="nl">
+  <head>
+    <meta charset="utf-8" />
+    <title>ZaakChat REST API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script>
+      window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+    </script>
+  </body>
+</html>"##,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_spec_covers_rest_paths() {
+        let spec = openapi_spec();
+        let paths = spec["paths"].as_object().expect("paths object");
+
+        for path in [
+            "/events",
+            "/resources",
+            "/resources/{id}",
+            "/query",
+            "/login",
+        ] {
+            assert!(paths.contains_key(path), "missing path {}", path);
+        }
+        assert!(paths["/events"].get("post").is_some());
+    }
+
+    #[test]
+    fn test_openapi_components_come_from_schemars() {
+        let spec = openapi_spec();
+        let components = spec["components"]["schemas"].as_object().unwrap();
+
+        // Event schemas are the schemars output, not a self-referencing placeholder
+        let cloud_event = &components["CloudEvent"];
+        assert!(cloud_event["properties"].get("specversion").is_some());
+        assert!(cloud_event.get("$schema").is_none());
+        assert!(components.contains_key("Issue"));
+        assert!(components.contains_key("ResourceResponse"));
+
+        let security = spec["components"]["securitySchemes"].as_object().unwrap();
+        assert!(security.contains_key("bearer"));
+    }
+}
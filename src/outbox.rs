@@ -0,0 +1,348 @@
+//! Transactional outbox for outgoing integrations.
+//!
+//! `Storage::store_event` writes an outbox entry in the same transaction as the event, so
+//! every stored event is handed to the outgoing integrations (sinks), even when the server
+//! stops right after storing it. A dispatcher drains the outbox every [`DRAIN_INTERVAL`]: it
+//! delivers each entry's event to every sink that has not received it yet, and removes the
+//! entry once all sinks have. Failed sinks are retried with exponential backoff (up to
+//! [`MAX_BACKOFF`]), so delivery is at least once: a sink may see an event twice, never zero
+//! times.
+//!
+//! Sinks:
+//! - `connectors`: Slack / Teams cards (see `connectors`), always on;
+//! - `notificaties`: a Notificaties API (VNG) at `NOTIFICATIES_URL`, with
+//!   `NOTIFICATIES_TOKEN` as bearer token, on kanaal `NOTIFICATIES_KANAAL` ("zaken");
+//! - `kafka`: a Kafka REST Proxy at `KAFKA_REST_URL`, topic `KAFKA_TOPIC`
+//!   ("zaakchat.events"), keyed by subject.
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::json;
+
+use crate::handlers::{commit_of, AppState};
+use crate::schemas::CloudEvent;
+
+/// How often the dispatcher looks for undelivered events
+pub const DRAIN_INTERVAL: Duration = Duration::from_secs(2);
+/// Longest wait between retries of a failing sink
+pub const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+/// Due entries handled per drain
+const BATCH_SIZE: usize = 100;
+const BASE_BACKOFF_SECS: u64 = 5;
+
+/// An outgoing integration that receives every stored event.
+#[async_trait]
+pub trait OutboxSink: Send + Sync {
+    /// Stable name, recorded for the events the sink received
+    fn name(&self) -> &str;
+    async fn deliver(
+        &self,
+        state: &AppState,
+        event: &CloudEvent,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Slack / Teams cards
+pub struct ConnectorSink {
+    http: reqwest::Client,
+}
+
+#[async_trait]
+impl OutboxSink for ConnectorSink {
+    fn name(&self) -> &str {
+        "connectors"
+    }
+
+    async fn deliver(
+        &self,
+        state: &AppState,
+        event: &CloudEvent,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        crate::connectors::deliver(&state.storage, &self.http, event).await
+    }
+}
+
+/// Notifications on one kanaal of a Notificaties API
+pub struct NotificatiesSink {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    kanaal: String,
+    base_url: String,
+}
+
+#[async_trait]
+impl OutboxSink for NotificatiesSink {
+    fn name(&self) -> &str {
+        "notificaties"
+    }
+
+    async fn deliver(
+        &self,
+        _state: &AppState,
+        event: &CloudEvent,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Only commits describe changes to zaken
+        let Some(commit) = commit_of(event) else {
+            return Ok(());
+        };
+        let actie = if commit.deleted == Some(true) {
+            "destroy"
+        } else if commit.resource_data.is_some() {
+            "create"
+        } else {
+            "update"
+        };
+        let resource = commit
+            .schema
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let message = json!({
+            "kanaal": self.kanaal,
+            "hoofdObject": format!("{}/zaak/{}", self.base_url, event.subject),
+            "resource": resource,
+            "resourceUrl": format!("{}/resources/{}", self.base_url, commit.resource_id),
+            "actie": actie,
+            "aanmaakdatum": event.time.clone().unwrap_or_else(|| chrono::Utc::now().to_rfc3339()),
+            "kenmerken": {},
+        });
+        let mut request = self.http.post(&self.url).json(&message);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Events as JSON records on a Kafka topic, through a Kafka REST Proxy (v2 API)
+pub struct KafkaRestSink {
+    http: reqwest::Client,
+    url: String,
+    topic: String,
+}
+
+#[async_trait]
+impl OutboxSink for KafkaRestSink {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    async fn deliver(
+        &self,
+        _state: &AppState,
+        event: &CloudEvent,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.http
+            .post(format!(
+                "{}/topics/{}",
+                self.url.trim_end_matches('/'),
+                self.topic
+            ))
+            .header("Content-Type", "application/vnd.kafka.json.v2+json")
+            .json(&json!({ "records": [{ "key": event.subject, "value": event }] }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// The sinks configured in the environment.
+pub fn sinks_from_env() -> Vec<Arc<dyn OutboxSink>> {
+    let http = reqwest::Client::new();
+    let mut sinks: Vec<Arc<dyn OutboxSink>> = vec![Arc::new(ConnectorSink { http: http.clone() })];
+    if let Ok(url) = std::env::var("NOTIFICATIES_URL") {
+        sinks.push(Arc::new(NotificatiesSink {
+            http: http.clone(),
+            url,
+            token: std::env::var("NOTIFICATIES_TOKEN").ok(),
+            kanaal: std::env::var("NOTIFICATIES_KANAAL").unwrap_or_else(|_| "zaken".to_string()),
+            base_url: std::env::var("BASE_URL")
+                .unwrap_or_else(|_| "https://zaakchat.nl".to_string()),
+        }));
+    }
+    if let Ok(url) = std::env::var("KAFKA_REST_URL") {
+        sinks.push(Arc::new(KafkaRestSink {
+            http,
+            url,
+            topic: std::env::var("KAFKA_TOPIC").unwrap_or_else(|_| "zaakchat.events".to_string()),
+        }));
+    }
+    sinks
+}
+
+/// Wait before retry after `attempts` failed rounds: 5s, 10s, 20s, ... up to [`MAX_BACKOFF`]
+fn backoff(attempts: u32) -> Duration {
+    let secs = BASE_BACKOFF_SECS.saturating_mul(1u64 << attempts.saturating_sub(1).min(20));
+    Duration::from_secs(secs).min(MAX_BACKOFF)
+}
+
+/// Deliver the due outbox entries to `sinks`. Returns how many entries were completed.
+pub async fn drain(
+    state: &AppState,
+    sinks: &[Arc<dyn OutboxSink>],
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let mut completed = 0;
+    // Entries waiting for a retry must not hold up the ones behind them
+    let due = state
+        .storage
+        .list_outbox(usize::MAX)
+        .await?
+        .into_iter()
+        .filter(|(_, entry)| {
+            entry
+                .retry_at
+                .as_deref()
+                .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+                .is_none_or(|t| t <= now)
+        })
+        .take(BATCH_SIZE);
+    for (seq_key, mut entry) in due {
+        let event = state
+            .storage
+            .get_event(&entry.event_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let Some(event) = event else {
+            // The event was removed (e.g. with its resource); nothing left to deliver
+            state.storage.set_outbox_entry(&seq_key, None).await?;
+            continue;
+        };
+
+        let mut failed = false;
+        for sink in sinks {
+            if entry.delivered.iter().any(|name| name == sink.name()) {
+                continue;
+            }
+            match sink.deliver(state, &event).await {
+                Ok(()) => entry.delivered.push(sink.name().to_string()),
+                Err(e) => {
+                    eprintln!(
+                        "[outbox] failed to deliver {} to {}: {}",
+                        event.id,
+                        sink.name(),
+                        e
+                    );
+                    failed = true;
+                }
+            }
+        }
+
+        if failed {
+            entry.attempts += 1;
+            let wait = chrono::Duration::from_std(backoff(entry.attempts))?;
+            entry.retry_at = Some((now + wait).to_rfc3339());
+            state
+                .storage
+                .set_outbox_entry(&seq_key, Some(&entry))
+                .await?;
+        } else {
+            state.storage.set_outbox_entry(&seq_key, None).await?;
+            completed += 1;
+        }
+    }
+    Ok(completed)
+}
+
+/// Drain the outbox every [`DRAIN_INTERVAL`].
+pub fn spawn_outbox_dispatcher(state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let sinks = sinks_from_env();
+        let mut ticker = tokio::time::interval(DRAIN_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = drain(&state, &sinks, chrono::Utc::now()).await {
+                eprintln!("[outbox] failed to drain the outbox: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{submit_commit_event, test_state};
+    use crate::schemas::{Comment, CommitBuilder};
+    use std::sync::Mutex;
+
+    /// Records deliveries; fails the first `failures` times
+    struct RecordingSink {
+        name: &'static str,
+        failures: Mutex<u32>,
+        received: Mutex<Vec<String>>,
+    }
+
+    impl RecordingSink {
+        fn new(name: &'static str, failures: u32) -> Arc<Self> {
+            Arc::new(Self {
+                name,
+                failures: Mutex::new(failures),
+                received: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl OutboxSink for RecordingSink {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn deliver(
+            &self,
+            _state: &AppState,
+            event: &CloudEvent,
+        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err("unreachable".into());
+            }
+            self.received.lock().unwrap().push(event.id.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outbox_retries_failed_sinks_only() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let comment = Comment {
+            content: "Hallo".to_string(),
+            quote_comment: None,
+            mentions: None,
+            edited_at: None,
+            internal: None,
+        };
+        let commit = CommitBuilder::create("comment-1", &comment).build();
+        let event = submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+        assert_eq!(state.storage.list_outbox(10).await.unwrap().len(), 1);
+
+        let steady = RecordingSink::new("steady", 0);
+        let flaky = RecordingSink::new("flaky", 1);
+        let sinks: Vec<Arc<dyn OutboxSink>> = vec![steady.clone(), flaky.clone()];
+
+        let now = chrono::Utc::now();
+        assert_eq!(drain(&state, &sinks, now).await.unwrap(), 0);
+        let (_, entry) = state.storage.list_outbox(10).await.unwrap().remove(0);
+        assert_eq!(entry.delivered, vec!["steady".to_string()]);
+        assert_eq!(entry.attempts, 1);
+
+        // Not due yet
+        assert_eq!(drain(&state, &sinks, now).await.unwrap(), 0);
+        assert!(flaky.received.lock().unwrap().is_empty());
+
+        let later = now + chrono::Duration::seconds(60);
+        assert_eq!(drain(&state, &sinks, later).await.unwrap(), 1);
+        assert_eq!(*steady.received.lock().unwrap(), vec![event.id.clone()]);
+        assert_eq!(*flaky.received.lock().unwrap(), vec![event.id]);
+        assert!(state.storage.list_outbox(10).await.unwrap().is_empty());
+    }
+}
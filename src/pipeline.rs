@@ -0,0 +1,1365 @@
+//! The event processing pipeline: an ordered chain of [`EventProcessor`]s.
+//!
+//! Every submitted event passes each processor twice. Before the event is stored,
+//! [`EventProcessor::prepare`] may reject it or add the resource change it makes; the event
+//! and that change are then committed in one transaction (see
+//! `Storage::store_projected_event`). After the commit, [`EventProcessor::committed`] applies
+//! the event's effects. Those are recorded in the changelog until every processor has run, so
+//! after a crash [`Pipeline::replay`] runs them again (without repeating workflow rules and
+//! notifications). An entry that fails to replay is marked as such and skipped; after
+//! [`MAX_REPLAYS`] failures it stays in the changelog as a dead letter, to be looked into.
+//!
+//! Events with a malformed CloudEvents envelope are rejected before any processor runs (see
+//! `validation`).
+//!
+//! The standard chain, in order:
+//! - `validation`: commits must be valid `JSONCommit`s, and JSON Patches must apply;
+//! - `authorization`: a known submitter must have access to the issue the event is about;
+//! - `conflicts`: commits made on a stale version must not overwrite newer changes (see
+//!   `conflicts`);
+//! - `projection`: the resource change, and the derived indexes (replies, relations, users);
+//! - `indexing`: the search index;
+//! - `projections`: the named projections (see `projections`);
+//! - `workflow`: routing rules (duplicates, auto-assignment, rerouting) for inbound events;
+//! - `recurrence`: the next occurrence of completed recurring tasks (see `recurrence`);
+//! - `notifications`: email and push notifications.
+//!
+//! With storage quotas configured (see `quotas`), the `quotas` step runs after authorization.
+//!
+//! `DISABLED_EVENT_PROCESSORS` (comma-separated names) leaves processors out at startup, e.g.
+//! `workflow,notifications` for an instance that only imports history. `STRICT_INGESTION=true`
+//! adds the `strict` step after validation, for instances that accept events from external
+//! parties: inbound events then need a known type and a `dataschema`, and commits a known
+//! resource type.
+//!
+//! Every submitted event gets the server's `time_received`. With `MAX_CLOCK_SKEW_SECONDS`,
+//! a client-supplied `time` further off than that is replaced by `time_received`, so a client
+//! with a wrong clock can't move its events around in the timeline.
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use serde_json::Value;
+
+use crate::auth::tenant_of;
+use crate::handlers::{
+    apply_routing_rules, check_access, commit_of, send_notifications_for_event, try_apply_commit,
+    AppState,
+};
+use crate::schemas::{CloudEvent, JSONCommit};
+use crate::storage::{
+    ChangelogEntry, DuplicateEvent, Projection, ProjectionError, ResourceChange, Storage,
+};
+
+/// Why an event was not accepted
+#[derive(Debug)]
+pub enum ProcessError {
+    /// The event is malformed
+    Invalid(String),
+    /// The event comes from outside, but not from an authenticated submitter
+    Unauthorized(String),
+    /// The submitter may not make this change
+    Forbidden(String),
+    /// The commit was made on a stale version and conflicts with newer changes
+    Conflict(Box<crate::conflicts::MergeConflict>),
+    /// The commit passed validation, but no longer applies to its resource as stored (a
+    /// concurrent change got there first)
+    Stale(String),
+    /// The submitter or their tenant is at a storage quota
+    QuotaExceeded(Box<crate::quotas::QuotaExceeded>),
+    /// An event with the same source and id is already stored (it is given)
+    Duplicate(Box<CloudEvent>),
+    /// Storing or processing failed
+    Internal(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl ProcessError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ProcessError::Invalid(_) => StatusCode::BAD_REQUEST,
+            ProcessError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ProcessError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ProcessError::Conflict(_) | ProcessError::Stale(_) | ProcessError::Duplicate(_) => {
+                StatusCode::CONFLICT
+            }
+            ProcessError::QuotaExceeded(exceeded) => exceeded.status(),
+            ProcessError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl std::fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessError::Invalid(reason) => write!(f, "invalid event: {}", reason),
+            ProcessError::Unauthorized(reason) => write!(f, "unauthorized: {}", reason),
+            ProcessError::Forbidden(reason) => write!(f, "forbidden: {}", reason),
+            ProcessError::Conflict(conflict) => write!(
+                f,
+                "{} conflicting field(s) in {} since sequence {}",
+                conflict.conflicts.len(),
+                conflict.resource_id,
+                conflict.base_sequence
+            ),
+            ProcessError::Stale(reason) => write!(f, "stale commit: {}", reason),
+            ProcessError::QuotaExceeded(exceeded) => write!(f, "{}", exceeded),
+            ProcessError::Duplicate(stored) => write!(
+                f,
+                "event {} from {} is already stored",
+                stored.id, stored.source
+            ),
+            ProcessError::Internal(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for ProcessError {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        ProcessError::Internal(e)
+    }
+}
+
+/// An event on its way through the pipeline
+pub struct EventContext {
+    pub event: CloudEvent,
+    /// The authenticated submitter, if any; events the server makes itself have none
+    pub actor: Option<String>,
+    /// The event comes from outside (a client or an integration), so workflow rules apply.
+    /// Events the server makes itself (e.g. an auto-assignment) don't trigger them again.
+    pub inbound: bool,
+    /// The event comes from an integration that proved who it is (a signed webhook, see
+    /// `hooks`), so it has no user as submitter
+    pub integration: bool,
+    /// Run from the changelog after a crash; side effects were (possibly) done already
+    pub replay: bool,
+    /// The resource change to commit with the event, set while preparing
+    pub projection: Option<Projection<'static>>,
+    /// What the commit changed, available after the commit
+    pub change: Option<ChangelogEntry>,
+}
+
+impl EventContext {
+    pub fn new(event: CloudEvent) -> Self {
+        Self {
+            event,
+            actor: None,
+            inbound: false,
+            integration: false,
+            replay: false,
+            projection: None,
+            change: None,
+        }
+    }
+
+    /// An event from outside, submitted by `actor` when authenticated.
+    pub fn inbound(mut self, actor: Option<String>) -> Self {
+        self.actor = actor;
+        self.inbound = true;
+        self
+    }
+
+    /// An event from a verified integration, which has no authenticated submitter.
+    pub fn from_integration(mut self) -> Self {
+        self.inbound = true;
+        self.integration = true;
+        self
+    }
+
+    /// The commit the event carries, if it is a `json.commit` event
+    pub fn commit(&self) -> Option<JSONCommit> {
+        commit_of(&self.event)
+    }
+}
+
+/// One step of the pipeline.
+#[async_trait]
+pub trait EventProcessor: Send + Sync {
+    /// Stable name, used in logs and in `DISABLED_EVENT_PROCESSORS`
+    fn name(&self) -> &'static str;
+
+    /// Before the event is stored. An error rejects the event; nothing is stored.
+    async fn prepare(
+        &self,
+        _state: &AppState,
+        _ctx: &mut EventContext,
+    ) -> Result<(), ProcessError> {
+        Ok(())
+    }
+
+    /// After the event is committed. On an error the remaining processors still run, and
+    /// the whole chain is run again from the changelog on the next start.
+    async fn committed(
+        &self,
+        _state: &AppState,
+        _ctx: &EventContext,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Ok(())
+    }
+}
+
+/// Commits must deserialize as `JSONCommit`.
+pub struct ValidationProcessor;
+
+#[async_trait]
+impl EventProcessor for ValidationProcessor {
+    fn name(&self) -> &'static str {
+        "validation"
+    }
+
+    async fn prepare(&self, state: &AppState, ctx: &mut EventContext) -> Result<(), ProcessError> {
+        if let (true, Some(data)) = (is_commit_type(&ctx.event.event_type), &ctx.event.data) {
+            let commit = serde_json::from_value::<JSONCommit>(data.clone())
+                .map_err(|e| ProcessError::Invalid(format!("data is not a JSONCommit: {}", e)))?;
+            // A JSON Patch must apply to the resource as it is now
+            if let (true, Some(patch)) = (commit.is_json_patch(), &commit.patch) {
+                let mut resource = state
+                    .storage
+                    .get_resource(&commit.resource_id)
+                    .await?
+                    .unwrap_or_else(|| serde_json::json!({}));
+                crate::json_patch::apply(&mut resource, patch)
+                    .map_err(|e| ProcessError::Invalid(format!("JSON Patch: {}", e)))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Strict ingestion of inbound events: unknown event types, events without `dataschema`,
+/// commits without data and commits of an unknown resource type are rejected instead of
+/// stored as they are. Events the server makes itself are not checked.
+pub struct StrictIngestionProcessor;
+
+/// Is `event_type` a commit, or registered in the event schema registry?
+async fn is_known_event_type(storage: &Storage, event_type: &str) -> Result<bool, ProcessError> {
+    if is_commit_type(event_type) {
+        return Ok(true);
+    }
+    Ok(storage
+        .list_schema_event_types()
+        .await?
+        .iter()
+        .any(|t| t == event_type))
+}
+
+fn is_commit_type(event_type: &str) -> bool {
+    event_type == crate::registry::COMMIT_EVENT_TYPE || event_type == "nl.vng.zaken.json-commit.v1"
+}
+
+#[async_trait]
+impl EventProcessor for StrictIngestionProcessor {
+    fn name(&self) -> &'static str {
+        "strict"
+    }
+
+    async fn prepare(&self, state: &AppState, ctx: &mut EventContext) -> Result<(), ProcessError> {
+        if !ctx.inbound {
+            return Ok(());
+        }
+        let event = &ctx.event;
+        if !is_known_event_type(&state.storage, &event.event_type).await? {
+            return Err(ProcessError::Invalid(format!(
+                "unknown event type {:?}; register its schema first",
+                event.event_type
+            )));
+        }
+        if event.dataschema.as_deref().unwrap_or_default().is_empty() {
+            return Err(ProcessError::Invalid("dataschema is missing".to_string()));
+        }
+        if !is_commit_type(&event.event_type) {
+            return Ok(());
+        }
+        // Validation already rejected data that is not a commit
+        let Some(commit) = ctx.commit() else {
+            return Err(ProcessError::Invalid("a commit needs data".to_string()));
+        };
+        let resource_type = crate::resource_types::resolve(&state.storage, &commit.schema).await?;
+        if resource_type == crate::resource_types::UNKNOWN {
+            return Err(ProcessError::Invalid(format!(
+                "unknown resource type for schema {:?}; register it at /resource-types",
+                commit.schema
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Events from outside must come from an authenticated submitter, and the commits they
+/// carry must name that submitter as `actor`. The submitter must have access to the existing
+/// issue the event is about, and to the resource a commit changes and the issue that resource
+/// belongs to. Resources that are not part of an issue's thread are held to the rules of
+/// their REST endpoints (see [`may_write`]), both as they are and as the commit leaves them.
+/// New threads, events the server makes itself and those of verified integrations are not
+/// checked.
+pub struct AuthorizationProcessor;
+
+/// May `actor` have `resource`, of type `resource_type`, as it is or as they make it? Teams
+/// and labels belong to a tenant, connectors and escalation policies to the admins of a
+/// tenant, boards to a tenant or team, and availabilities and profiles to their user. Other
+/// resources are checked through their issue.
+pub(crate) async fn may_write(
+    storage: &Storage,
+    actor: &str,
+    resource_type: &str,
+    resource: &Value,
+) -> bool {
+    let field = |name: &str| resource.get(name).and_then(Value::as_str);
+    let own_tenant = || field("tenant") == Some(tenant_of(actor));
+    match resource_type {
+        "Team" | "Label" => own_tenant(),
+        "Connector" | "EscalationPolicy" => own_tenant() && crate::auth::is_admin(actor),
+        "Board" => match field("team") {
+            Some(team) => {
+                team == tenant_of(actor) || crate::teams::is_member(storage, team, actor).await
+            }
+            None => false,
+        },
+        "Availability" => field("user") == Some(actor),
+        "Profile" => field("email") == Some(actor),
+        _ => true,
+    }
+}
+
+impl AuthorizationProcessor {
+    /// `actor` must have access to `id` if it is an existing issue.
+    async fn authorize(state: &AppState, actor: &str, id: &str) -> Result<(), ProcessError> {
+        let resource = state.storage.get_resource(id).await?;
+        let is_issue = resource
+            .as_ref()
+            .is_some_and(|r| r.get("involved").is_some() && r.get("title").is_some());
+        if is_issue && !check_access(&state.storage, actor, id).await {
+            return Err(ProcessError::Forbidden(format!(
+                "{} has no access to {}",
+                actor, id
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventProcessor for AuthorizationProcessor {
+    fn name(&self) -> &'static str {
+        "authorization"
+    }
+
+    async fn prepare(&self, state: &AppState, ctx: &mut EventContext) -> Result<(), ProcessError> {
+        if !ctx.inbound || ctx.integration {
+            return Ok(());
+        }
+        let Some(actor) = &ctx.actor else {
+            return Err(ProcessError::Unauthorized(
+                "events can only be submitted with a valid token".to_string(),
+            ));
+        };
+        Self::authorize(state, actor, &ctx.event.subject).await?;
+        let Some(commit) = ctx.commit() else {
+            return Ok(());
+        };
+        if commit.actor != *actor {
+            return Err(ProcessError::Forbidden(format!(
+                "commit by {} submitted by {}",
+                commit.actor, actor
+            )));
+        }
+        let before = state.storage.get_resource(&commit.resource_id).await?;
+        let stored_type = state.storage.get_resource_type(&commit.resource_id).await?;
+        let after = try_apply_commit(before.clone(), &commit).ok().flatten();
+        let commit_type = crate::resource_types::resolve(&state.storage, &commit.schema).await?;
+        let states = [(stored_type, before), (Some(commit_type), after)];
+        for (resource_type, resource) in states {
+            if let (Some(resource_type), Some(resource)) = (resource_type, resource) {
+                if !may_write(&state.storage, actor, &resource_type, &resource).await {
+                    return Err(ProcessError::Forbidden(format!(
+                        "{} may not change {} {}",
+                        actor, resource_type, commit.resource_id
+                    )));
+                }
+            }
+        }
+        if commit.resource_id != ctx.event.subject {
+            Self::authorize(state, actor, &commit.resource_id).await?;
+        }
+        // The issue the resource was created under, whatever subject this commit names
+        if let Some(parent) = state.storage.resource_subject(&commit.resource_id).await? {
+            if parent != ctx.event.subject && parent != commit.resource_id {
+                Self::authorize(state, actor, &parent).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Commits patch, replace or delete their resource; other events with data are stored as
+/// a resource under their own ID. After the commit, the indexes derived from resources are
+/// brought up to date.
+pub struct ProjectionProcessor;
+
+#[async_trait]
+impl EventProcessor for ProjectionProcessor {
+    fn name(&self) -> &'static str {
+        "projection"
+    }
+
+    async fn prepare(&self, state: &AppState, ctx: &mut EventContext) -> Result<(), ProcessError> {
+        ctx.projection = projection(&state.storage, &ctx.event).await?;
+        Ok(())
+    }
+
+    async fn committed(
+        &self,
+        state: &AppState,
+        ctx: &EventContext,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (Some(commit), Some((change, new_resource))) = (ctx.commit(), changed_resource(ctx))
+        else {
+            return Ok(());
+        };
+
+        // Keep the comment reply index up to date (`quote_comment` is the parent)
+        let quoted = |r: Option<&Value>| {
+            r.and_then(|r| r.get("quote_comment"))
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        if let Some(parent) = quoted(Some(new_resource)) {
+            if quoted(change.old.as_ref()).as_ref() != Some(&parent) {
+                let seq = ctx.event.sequence.as_deref().unwrap_or_default();
+                state
+                    .storage
+                    .add_comment_reply(&parent, seq, &change.id)
+                    .await?;
+            }
+        }
+
+        // Index relations under both issues they link
+        if change.resource_type == "Relation" {
+            let end = |key: &str| new_resource.get(key).and_then(|v| v.as_str());
+            if let (Some(source), Some(target)) = (end("source_id"), end("target_id")) {
+                state
+                    .storage
+                    .add_issue_relation(&change.id, source, target)
+                    .await?;
+            }
+        }
+
+        // Keep the user directory up to date with everyone the commit mentions
+        let mut users = vec![commit.actor.as_str()];
+        if let Some(involved) = new_resource.get("involved").and_then(|v| v.as_array()) {
+            users.extend(involved.iter().filter_map(|v| v.as_str()));
+        }
+        users.extend(new_resource.get("assignee").and_then(|v| v.as_str()));
+        users.retain(|u| u.contains('@'));
+        if !users.is_empty() {
+            let seen = commit
+                .timestamp
+                .clone()
+                .unwrap_or_else(|| chrono::Utc::now().to_rfc3339());
+            state.storage.record_users(&users, &seen).await?;
+        }
+        Ok(())
+    }
+}
+
+/// The search index: every event, and the resource it changed.
+pub struct IndexingProcessor;
+
+#[async_trait]
+impl EventProcessor for IndexingProcessor {
+    fn name(&self) -> &'static str {
+        "indexing"
+    }
+
+    async fn committed(
+        &self,
+        state: &AppState,
+        ctx: &EventContext,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let stats = state.search.stats();
+        match index_event(state, ctx).await {
+            Ok(()) => {
+                stats.record_indexed(ctx.event.sequence.as_deref());
+                Ok(())
+            }
+            Err(e) => {
+                stats.record_failure(format!("event {}: {}", ctx.event.id, e));
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Add the event, and the resource it changed, to the search index.
+async fn index_event(
+    state: &AppState,
+    ctx: &EventContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let event = &ctx.event;
+    // Architecture Decision: All CloudEvents are indexed with doc_type="Event".
+    // This allows searching the audit history via is:Event.
+    // Specific event types (e.g. json.commit) are properties of the event payload.
+    let payload = serde_json::to_string(event)?;
+    state
+        .search
+        .add_event_payload(&event.id, "Event", "", &payload, None)
+        .await?;
+
+    if let Some((change, new_resource)) = changed_resource(ctx) {
+        let commit = ctx.commit();
+        let parent = commit.is_some().then_some(event.subject.as_str());
+        let timestamp = commit
+            .and_then(|c| c.timestamp)
+            .and_then(|s| chrono::DateTime::parse_from_rfc3339(&s).ok())
+            .map(|dt| dt.with_timezone(&chrono::Utc));
+        index_resource(
+            state,
+            &change.id,
+            &change.resource_type,
+            new_resource,
+            parent,
+            timestamp,
+        )
+        .await?;
+    }
+
+    // Force a commit to ensure the event is searchable immediately
+    // This is critical for the "create then view" flow where the user expects
+    // the new item to be available in the snapshot immediately.
+    state.search.commit().await
+}
+
+/// Routing rules for inbound events (see `handlers::apply_routing_rules`).
+pub struct WorkflowProcessor;
+
+#[async_trait]
+impl EventProcessor for WorkflowProcessor {
+    fn name(&self) -> &'static str {
+        "workflow"
+    }
+
+    async fn committed(
+        &self,
+        state: &AppState,
+        ctx: &EventContext,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if ctx.inbound && !ctx.replay {
+            apply_routing_rules(state, &ctx.event).await;
+        }
+        Ok(())
+    }
+}
+
+/// Email and push notifications about changed resources.
+pub struct NotificationProcessor;
+
+#[async_trait]
+impl EventProcessor for NotificationProcessor {
+    fn name(&self) -> &'static str {
+        "notifications"
+    }
+
+    async fn committed(
+        &self,
+        state: &AppState,
+        ctx: &EventContext,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if ctx.replay || ctx.commit().is_none() {
+            return Ok(());
+        }
+        if let Some((change, new_resource)) = changed_resource(ctx) {
+            send_notifications_for_event(state, &ctx.event, new_resource, change.old.as_ref())
+                .await;
+        }
+        Ok(())
+    }
+}
+
+/// Add the state of a resource to the search index. `parent` is the subject of the commit
+/// that changed it, whose `involved` and `team` child resources like comments inherit.
+pub(crate) async fn index_resource(
+    state: &AppState,
+    id: &str,
+    resource_type: &str,
+    resource: &Value,
+    parent: Option<&str>,
+    timestamp: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut data = resource.clone();
+    // AUTH FIX: Denormalize 'involved' for Comments (and other child resources)
+    // Comments don't have 'involved' field, so they fail the default auth filter.
+    // We look up the parent issue and copy its 'involved' list into the indexing payload.
+    if let Some(parent) = parent.filter(|_| {
+        (resource_type == "Comment" || resource_type == "comment") && data.get("involved").is_none()
+    }) {
+        // The frontend sends zaakId as subject for Comments
+        if let Ok(Some(parent)) = state.storage.get_resource(parent).await {
+            for key in ["involved", "team"] {
+                if let Some(value) = parent.get(key) {
+                    if let Some(obj) = data.as_object_mut() {
+                        obj.insert(key.to_string(), value.clone());
+                    }
+                }
+            }
+        }
+    }
+    let payload = serde_json::to_string(&data)?;
+    state
+        .search
+        .add_resource_payload(id, resource_type, "", &payload, timestamp)
+        .await
+}
+
+/// The resource the committed event created or updated (not deleted), with its new state
+fn changed_resource(ctx: &EventContext) -> Option<(&ResourceChange, &Value)> {
+    let change = ctx.change.as_ref()?.resource.as_ref()?;
+    Some((change, change.new.as_ref()?))
+}
+
+/// The resource change an event makes. Commits patch, replace or delete their resource,
+/// typed by their schema (see `resource_types`); other events with data are stored as a
+/// resource of their event type.
+pub(crate) async fn projection(
+    storage: &Storage,
+    event: &CloudEvent,
+) -> Result<Option<Projection<'static>>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(data) = &event.data else {
+        return Ok(None);
+    };
+
+    // Accept both legacy and NL-VNG names
+    if event.event_type == "nl.vng.zaken.json-commit.v1" || event.event_type == "json.commit" {
+        let commit: JSONCommit = serde_json::from_value(data.clone())?;
+        let resource_type = crate::resource_types::resolve(storage, &commit.schema).await?;
+
+        Ok(Some(Projection {
+            resource_id: commit.resource_id.clone(),
+            resource_type,
+            apply: Box::new(move |existing| {
+                try_apply_commit(existing, &commit).map_err(|e| {
+                    ProjectionError(format!("JSON Patch on {}: {}", commit.resource_id, e))
+                })
+            }),
+        }))
+    } else {
+        let data = data.clone();
+        Ok(Some(Projection {
+            resource_id: event.id.clone(),
+            resource_type: event.event_type.clone(),
+            apply: Box::new(move |_| Ok(Some(data))),
+        }))
+    }
+}
+
+/// How often a changelog entry is replayed before it is left as a dead letter
+pub const MAX_REPLAYS: u32 = 3;
+
+/// What [`Pipeline::replay`] did
+#[derive(Debug, Default, PartialEq)]
+pub struct ReplayReport {
+    /// Entries whose processors all ran
+    pub replayed: usize,
+    /// Entries that failed (again) and were marked
+    pub failed: usize,
+    /// Entries skipped after failing [`MAX_REPLAYS`] times
+    pub dead: usize,
+}
+
+/// The ordered chain of processors every event goes through
+pub struct Pipeline {
+    processors: Vec<Arc<dyn EventProcessor>>,
+    /// How far a client's `time` may be off before it is replaced; `None`: never replaced
+    max_clock_skew: Option<chrono::Duration>,
+}
+
+/// Record when the server received `event`, and replace its `time` by that when it is
+/// missing the mark by more than `max_skew` (or can't be parsed).
+pub fn stamp_received(
+    event: &mut CloudEvent,
+    received: chrono::DateTime<chrono::Utc>,
+    max_skew: Option<chrono::Duration>,
+) {
+    let received_at = received.to_rfc3339();
+    if let (Some(max_skew), Some(time)) = (max_skew, &event.time) {
+        let skew = chrono::DateTime::parse_from_rfc3339(time)
+            .map(|time| (time.with_timezone(&chrono::Utc) - received).abs());
+        if skew.map_or(true, |skew| skew > max_skew) {
+            eprintln!(
+                "[pipeline] time {} of event {} is off, using {}",
+                time, event.id, received_at
+            );
+            event.time = Some(received_at.clone());
+        }
+    }
+    event.time_received = Some(received_at);
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+impl Pipeline {
+    pub fn new(processors: Vec<Arc<dyn EventProcessor>>) -> Self {
+        Self {
+            processors,
+            max_clock_skew: None,
+        }
+    }
+
+    /// Replace client-supplied times that are off by more than `skew`.
+    pub fn max_clock_skew(mut self, skew: chrono::Duration) -> Self {
+        self.max_clock_skew = Some(skew);
+        self
+    }
+
+    /// Validation, authorization, projection, indexing, projections, workflow, recurrence and
+    /// notifications
+    pub fn standard() -> Self {
+        Self::new(vec![
+            Arc::new(ValidationProcessor),
+            Arc::new(AuthorizationProcessor),
+            Arc::new(crate::conflicts::ConflictProcessor),
+            Arc::new(ProjectionProcessor),
+            Arc::new(IndexingProcessor),
+            Arc::new(crate::projections::ProjectionsProcessor),
+            Arc::new(WorkflowProcessor),
+            Arc::new(crate::recurrence::RecurrenceProcessor),
+            Arc::new(NotificationProcessor),
+        ])
+    }
+
+    /// The standard chain without the processors in `DISABLED_EVENT_PROCESSORS`, with the
+    /// `strict` step when `STRICT_INGESTION` is set, the `quotas` step when quotas are
+    /// configured and the `MAX_CLOCK_SKEW_SECONDS` skew.
+    pub fn from_env() -> Self {
+        let disabled = std::env::var("DISABLED_EVENT_PROCESSORS").unwrap_or_default();
+        let disabled: Vec<&str> = disabled.split(',').map(str::trim).collect();
+        let strict = std::env::var("STRICT_INGESTION")
+            .is_ok_and(|v| matches!(v.trim(), "1" | "true" | "yes"));
+        let mut pipeline = Self::standard();
+        if strict {
+            pipeline.insert_after("validation", Arc::new(StrictIngestionProcessor));
+        }
+        let quotas = crate::quotas::Quotas::from_env();
+        if !quotas.is_unlimited() {
+            let quotas = crate::quotas::QuotaProcessor { quotas };
+            pipeline.insert_after("authorization", Arc::new(quotas));
+        }
+        if let Some(seconds) = std::env::var("MAX_CLOCK_SKEW_SECONDS")
+            .ok()
+            .and_then(|s| s.trim().parse::<i64>().ok())
+        {
+            pipeline = pipeline.max_clock_skew(chrono::Duration::seconds(seconds));
+        }
+        pipeline
+            .processors
+            .retain(|p| !disabled.contains(&p.name()));
+        pipeline
+    }
+
+    /// Add `processor` at the end of the chain.
+    pub fn push(&mut self, processor: Arc<dyn EventProcessor>) {
+        self.processors.push(processor);
+    }
+
+    /// Add `processor` right after the one named `name`, or first when there is none.
+    pub fn insert_after(&mut self, name: &str, processor: Arc<dyn EventProcessor>) {
+        let index = self
+            .processors
+            .iter()
+            .position(|p| p.name() == name)
+            .map_or(0, |i| i + 1);
+        self.processors.insert(index, processor);
+    }
+
+    /// Names of the processors, in order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.processors.iter().map(|p| p.name()).collect()
+    }
+
+    /// Check the envelope (see `validation`), then prepare, commit and process an event and
+    /// broadcast it. Returns the event with its assigned sequence.
+    pub async fn submit(
+        &self,
+        state: &AppState,
+        mut ctx: EventContext,
+    ) -> Result<CloudEvent, ProcessError> {
+        crate::validation::validate_envelope(&ctx.event).map_err(|errors| {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            ProcessError::Invalid(format!("invalid envelope: {}", errors.join("; ")))
+        })?;
+        stamp_received(&mut ctx.event, chrono::Utc::now(), self.max_clock_skew);
+        for processor in &self.processors {
+            processor.prepare(state, &mut ctx).await?;
+        }
+
+        let (seq_key, entry) = state
+            .storage
+            .store_projected_event(&ctx.event, ctx.projection.take())
+            .await
+            .map_err(|e| match e.downcast::<ProjectionError>() {
+                Ok(rejected) => ProcessError::Stale(rejected.0),
+                Err(e) => match e.downcast::<DuplicateEvent>() {
+                    Ok(duplicate) => ProcessError::Duplicate(duplicate.0),
+                    Err(e) => {
+                        eprintln!("Failed to store event: {}", e);
+                        ProcessError::Internal(e)
+                    }
+                },
+            })?;
+        // Attach the assigned sequence to the CloudEvent so clients can use it for ordering/pagination
+        ctx.event.set_sequence(seq_key.clone());
+        ctx.change = Some(entry);
+
+        // The event is committed from here on; failures are retried from the changelog
+        if self.run_committed(state, &ctx).await.is_ok() {
+            if let Err(e) = state.storage.remove_changelog_entry(&seq_key).await {
+                eprintln!(
+                    "[pipeline] failed to clear changelog entry {}: {}",
+                    seq_key, e
+                );
+            }
+        }
+
+        // Broadcast the event (with attached sequence) to SSE subscribers
+        let _ = state.tx.send(ctx.event.clone());
+        Ok(ctx.event)
+    }
+
+    /// Run the processors for the changelog entries left by a crash or a failed processor,
+    /// oldest first. An entry that fails again is marked and the replay goes on with the next;
+    /// entries that failed [`MAX_REPLAYS`] times are left alone.
+    pub async fn replay(
+        &self,
+        state: &AppState,
+    ) -> Result<ReplayReport, Box<dyn std::error::Error + Send + Sync>> {
+        let mut report = ReplayReport::default();
+        for (seq_key, mut entry) in state.storage.list_changelog().await? {
+            if entry.failed_replays >= MAX_REPLAYS {
+                report.dead += 1;
+                continue;
+            }
+            let result = match state.storage.get_event(&entry.event_id).await {
+                Ok(Some(event)) => {
+                    let mut ctx = EventContext::new(event);
+                    ctx.replay = true;
+                    ctx.change = Some(entry.clone());
+                    self.run_committed(state, &ctx).await.map(|()| true)
+                }
+                Ok(None) => Ok(false),
+                Err(e) => Err(format!("failed to read the event: {}", e)),
+            };
+            match result {
+                Ok(found) => {
+                    state.storage.remove_changelog_entry(&seq_key).await?;
+                    report.replayed += usize::from(found);
+                }
+                Err(error) => {
+                    entry.failed_replays += 1;
+                    eprintln!(
+                        "[pipeline] replay {} of event {} failed: {}",
+                        entry.failed_replays, entry.event_id, error
+                    );
+                    entry.error = Some(error);
+                    state.storage.set_changelog_entry(&seq_key, &entry).await?;
+                    report.failed += 1;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Run every processor's `committed` step; the failures, if any.
+    async fn run_committed(&self, state: &AppState, ctx: &EventContext) -> Result<(), String> {
+        let mut failures = Vec::new();
+        for processor in &self.processors {
+            if let Err(e) = processor.committed(state, ctx).await {
+                eprintln!(
+                    "[pipeline] {} failed for event {}: {}",
+                    processor.name(),
+                    ctx.event.id,
+                    e
+                );
+                failures.push(format!("{}: {}", processor.name(), e));
+            }
+        }
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(failures.join("; ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user, issue, test_state, ADMIN};
+    use crate::schemas::{
+        Board, CloudEventBuilder, Comment, CommitBuilder, Connector, ConnectorType, Issue, Team,
+    };
+    use std::sync::Mutex;
+
+    /// Records the events it sees; rejects events about `blocked`, and fails on those
+    /// that were committed anyway
+    struct Recorder {
+        blocked: &'static str,
+        seen: Mutex<Vec<(String, bool)>>,
+    }
+
+    #[async_trait]
+    impl EventProcessor for Recorder {
+        fn name(&self) -> &'static str {
+            "recorder"
+        }
+
+        async fn prepare(
+            &self,
+            _state: &AppState,
+            ctx: &mut EventContext,
+        ) -> Result<(), ProcessError> {
+            if ctx.event.subject == self.blocked {
+                return Err(ProcessError::Invalid("blocked".to_string()));
+            }
+            Ok(())
+        }
+
+        async fn committed(
+            &self,
+            _state: &AppState,
+            ctx: &EventContext,
+        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            self.seen
+                .lock()
+                .unwrap()
+                .push((ctx.event.subject.clone(), ctx.replay));
+            if ctx.event.subject == self.blocked {
+                return Err("blocked".into());
+            }
+            Ok(())
+        }
+    }
+
+    fn issue_event(id: &str, involved: &str) -> CloudEvent {
+        let commit = CommitBuilder::create(id, &issue("Paspoort", &[involved]))
+            .actor(involved)
+            .build();
+        CloudEventBuilder::commit(id, &commit).build()
+    }
+
+    #[tokio::test]
+    async fn test_custom_processor_and_rejections() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let recorder = Arc::new(Recorder {
+            blocked: "issue-2",
+            seen: Mutex::new(Vec::new()),
+        });
+        let mut pipeline = Pipeline::standard();
+        pipeline.push(recorder.clone());
+        assert_eq!(pipeline.names().last(), Some(&"recorder"));
+
+        let event = pipeline
+            .submit(
+                &state,
+                EventContext::new(issue_event("issue-1", "alice@gemeente.nl")),
+            )
+            .await
+            .unwrap();
+        assert!(event.sequence.is_some());
+        let rejected = pipeline
+            .submit(
+                &state,
+                EventContext::new(issue_event("issue-2", "alice@gemeente.nl")),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+        assert!(state
+            .storage
+            .get_resource("issue-2")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            *recorder.seen.lock().unwrap(),
+            vec![("issue-1".to_string(), false)]
+        );
+
+        // Someone without access to issue-1 may not change it, nor pass a change off as
+        // someone else's, and anonymous changes from outside are refused
+        let close = |actor: &str| {
+            let patch =
+                CommitBuilder::patch::<Issue>("issue-1", serde_json::json!({"status": "closed"}))
+                    .actor(actor)
+                    .build();
+            CloudEventBuilder::commit("issue-1", &patch).build()
+        };
+        let submit = |event: CloudEvent, actor: Option<&str>| {
+            pipeline.submit(
+                &state,
+                EventContext::new(event).inbound(actor.map(str::to_string)),
+            )
+        };
+        let mallory = Some("mallory@example.com");
+        let status = |result: Result<CloudEvent, ProcessError>| result.unwrap_err().status();
+        assert_eq!(
+            status(submit(close("mallory@example.com"), mallory).await),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(submit(close("alice@gemeente.nl"), mallory).await),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(submit(close("alice@gemeente.nl"), None).await),
+            StatusCode::UNAUTHORIZED
+        );
+        // ... nor reach into issue-1 from a thread they can see
+        let comment = Comment {
+            content: "Hoi".to_string(),
+            quote_comment: None,
+            mentions: None,
+            edited_at: None,
+            internal: None,
+        };
+        let comment = CommitBuilder::create("comment-1", &comment)
+            .actor("alice@gemeente.nl")
+            .build();
+        submit(
+            CloudEventBuilder::commit("issue-1", &comment).build(),
+            Some("alice@gemeente.nl"),
+        )
+        .await
+        .unwrap();
+        let edit =
+            CommitBuilder::patch::<Comment>("comment-1", serde_json::json!({"content": "Doei"}))
+                .actor("mallory@example.com")
+                .build();
+        assert_eq!(
+            status(
+                submit(
+                    CloudEventBuilder::commit("issue-mallory", &edit).build(),
+                    mallory
+                )
+                .await
+            ),
+            StatusCode::FORBIDDEN
+        );
+        submit(close("alice@gemeente.nl"), Some("alice@gemeente.nl"))
+            .await
+            .unwrap();
+
+        // Invalid commits are rejected before anything is stored
+        let mut invalid = close("alice@gemeente.nl");
+        invalid.subject = "issue-3".to_string();
+        invalid.data = Some(serde_json::json!({"resource_id": 3}));
+        let err = pipeline
+            .submit(&state, EventContext::new(invalid))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(state.storage.list_events(0, 10).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_authorization_of_tenant_resources() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let carol = "carol@gemeente.nl";
+        let mallory = "mallory@evil.com";
+        auth_user(ADMIN);
+        let pipeline = Pipeline::standard();
+        let submit = |actor: &str, commit: JSONCommit| {
+            let event = CloudEventBuilder::commit(commit.resource_id.clone(), &commit).build();
+            pipeline.submit(
+                &state,
+                EventContext::new(event).inbound(Some(actor.to_string())),
+            )
+        };
+        let status = |result: Result<CloudEvent, ProcessError>| match result {
+            Ok(_) => StatusCode::ACCEPTED,
+            Err(e) => e.status(),
+        };
+
+        let team_id = crate::teams::team_id("gemeente.nl", "Handhaving");
+        let team = Team {
+            name: "Handhaving".to_string(),
+            tenant: "gemeente.nl".to_string(),
+            department: None,
+            members: vec![alice.to_string()],
+        };
+        let create_team = CommitBuilder::create(team_id.clone(), &team).actor(alice);
+        submit(alice, create_team.build()).await.unwrap();
+        let issue = Issue {
+            title: "Overlast".to_string(),
+            team: Some(team_id.clone()),
+            involved: Some(vec!["melder@example.com".to_string()]),
+            ..Default::default()
+        };
+        let create_issue = CommitBuilder::create("issue-1", &issue).actor("melder@example.com");
+        submit("melder@example.com", create_issue.build())
+            .await
+            .unwrap();
+
+        // An outsider can't join a team to see its issues, nor claim a team for another
+        // tenant, nor pass the team off as a resource of another type
+        let join = |actor: &str| {
+            CommitBuilder::patch::<Team>(
+                team_id.clone(),
+                serde_json::json!({"members": [alice, actor]}),
+            )
+            .actor(actor)
+            .build()
+        };
+        assert_eq!(
+            status(submit(mallory, join(mallory)).await),
+            StatusCode::FORBIDDEN
+        );
+        let moved = CommitBuilder::patch::<Team>(
+            team_id.clone(),
+            serde_json::json!({"tenant": "evil.com"}),
+        )
+        .actor(mallory)
+        .build();
+        assert_eq!(status(submit(mallory, moved).await), StatusCode::FORBIDDEN);
+        let disguised = CommitBuilder::patch::<Issue>(
+            team_id.clone(),
+            serde_json::json!({"members": [mallory]}),
+        )
+        .actor(mallory)
+        .build();
+        assert_eq!(
+            status(submit(mallory, disguised).await),
+            StatusCode::FORBIDDEN
+        );
+        assert!(!check_access(&state.storage, mallory, "issue-1").await);
+        // A colleague can
+        assert_eq!(
+            status(submit(carol, join(carol)).await),
+            StatusCode::ACCEPTED
+        );
+        assert!(check_access(&state.storage, carol, "issue-1").await);
+
+        // Connectors are for the tenant's admins
+        let connector = Connector {
+            tenant: "gemeente.nl".to_string(),
+            name: "Teams".to_string(),
+            kind: ConnectorType::Teams,
+            webhook_url: "https://example.com/hook".to_string(),
+            triggers: vec![],
+            team: None,
+        };
+        let create_connector = |actor: &str| {
+            CommitBuilder::create("connector-1", &connector)
+                .actor(actor)
+                .build()
+        };
+        assert_eq!(
+            status(submit(alice, create_connector(alice)).await),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(submit(ADMIN, create_connector(ADMIN)).await),
+            StatusCode::ACCEPTED
+        );
+
+        // A board is for its team, an availability for its user
+        let board = Board {
+            team: team_id.clone(),
+            columns: vec![],
+        };
+        let create_board = |actor: &str| {
+            CommitBuilder::create("board-1", &board)
+                .actor(actor)
+                .build()
+        };
+        assert_eq!(
+            status(submit(mallory, create_board(mallory)).await),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(submit(carol, create_board(carol)).await),
+            StatusCode::ACCEPTED
+        );
+        let away = crate::schemas::Availability {
+            user: alice.to_string(),
+            away: true,
+            until: None,
+            delegate: Some(carol.to_string()),
+        };
+        let availability_id = crate::availability::availability_id(alice);
+        let set_away = CommitBuilder::create(availability_id, &away).actor(carol);
+        assert_eq!(
+            status(submit(carol, set_away.build()).await),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[test]
+    fn test_stamp_received_and_clock_skew() {
+        let received = chrono::DateTime::parse_from_rfc3339("2026-03-02T10:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let stamped = |time: Option<&str>, max_skew: Option<i64>| {
+            let mut event = CloudEventBuilder::new("json.commit", "issue-1").build();
+            event.time = time.map(str::to_string);
+            event.time_received = Some("2000-01-01T00:00:00Z".to_string());
+            stamp_received(
+                &mut event,
+                received,
+                max_skew.map(chrono::Duration::seconds),
+            );
+            assert_eq!(event.time_received, Some(received.to_rfc3339()));
+            event.time
+        };
+        let late = Some("2026-03-02T09:00:00Z");
+        assert_eq!(stamped(late, None).as_deref(), late);
+        assert_eq!(stamped(late, Some(60)), Some(received.to_rfc3339()));
+        let close = Some("2026-03-02T11:00:30+01:00");
+        assert_eq!(stamped(close, Some(60)).as_deref(), close);
+        assert_eq!(
+            stamped(Some("yesterday"), Some(60)),
+            Some(received.to_rfc3339())
+        );
+        assert_eq!(stamped(None, Some(60)), None);
+    }
+
+    #[tokio::test]
+    async fn test_strict_ingestion() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let mut pipeline = Pipeline::standard();
+        pipeline.insert_after("validation", Arc::new(StrictIngestionProcessor));
+        assert_eq!(&pipeline.names()[..2], ["validation", "strict"]);
+        let alice = Some("alice@gemeente.nl".to_string());
+        let submit = |event: CloudEvent| {
+            pipeline.submit(&state, EventContext::new(event).inbound(alice.clone()))
+        };
+        let reason = |result: Result<CloudEvent, ProcessError>| match result {
+            Err(ProcessError::Invalid(reason)) => reason,
+            other => panic!("expected a 400, got {:?}", other.map(|e| e.id)),
+        };
+
+        let unknown = CloudEventBuilder::new("com.example.melding", "m-1")
+            .dataschema("https://example.com/melding.json")
+            .data(serde_json::json!({"text": "Lamp kapot"}))
+            .build();
+        assert!(reason(submit(unknown).await).contains("unknown event type"));
+
+        let mut undescribed = issue_event("issue-1", "alice@gemeente.nl");
+        undescribed.dataschema = None;
+        assert_eq!(reason(submit(undescribed).await), "dataschema is missing");
+
+        let mut untyped =
+            CommitBuilder::patch::<Issue>("melding-1", serde_json::json!({"title": "Lamp"}))
+                .build();
+        untyped.schema = "https://example.com/schemas/Melding.json".to_string();
+        let untyped = CloudEventBuilder::commit("melding-1", &untyped).build();
+        assert!(reason(submit(untyped.clone()).await).contains("unknown resource type"));
+
+        let mut unparseable = issue_event("issue-1", "alice@gemeente.nl");
+        unparseable.data = Some(serde_json::json!({"resource_id": 3}));
+        assert!(reason(submit(unparseable).await).contains("not a JSONCommit"));
+        assert!(state.storage.list_events(0, 10).await.unwrap().is_empty());
+
+        // Known types are accepted, and so are events the server makes itself
+        submit(issue_event("issue-1", "alice@gemeente.nl"))
+            .await
+            .unwrap();
+        pipeline
+            .submit(&state, EventContext::new(untyped))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_changelog_replay_after_crash() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let user = "alice@gemeente.nl";
+        let event = issue_event("issue-1", user);
+
+        // The server stops right after committing the event and its resource
+        let (_, entry) = state
+            .storage
+            .store_projected_event(&event, projection(&state.storage, &event).await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(entry.resource.unwrap().new.unwrap()["title"], "Paspoort");
+        assert!(state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .is_some());
+        assert!(state.storage.list_users().await.unwrap().is_empty());
+
+        let pipeline = Pipeline::standard();
+        assert_eq!(pipeline.replay(&state).await.unwrap().replayed, 1);
+        assert!(state.storage.list_changelog().await.unwrap().is_empty());
+        let users = state.storage.list_users().await.unwrap();
+        assert_eq!(users[0].0, user);
+        let found = state
+            .search
+            .search(&state.storage, "type:Issue", 10)
+            .await
+            .unwrap();
+        assert!(found.iter().any(|r| r.id == "issue-1"));
+
+        // A submitted event leaves nothing to replay
+        let patch =
+            CommitBuilder::patch::<Issue>("issue-1", serde_json::json!({"status": "closed"}))
+                .build();
+        pipeline
+            .submit(
+                &state,
+                EventContext::new(CloudEventBuilder::commit("issue-1", &patch).build()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            pipeline.replay(&state).await.unwrap(),
+            ReplayReport::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_continues_past_failing_entries() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let mut pipeline = Pipeline::standard();
+        pipeline.push(Arc::new(Recorder {
+            blocked: "issue-1",
+            seen: Mutex::new(Vec::new()),
+        }));
+        for id in ["issue-1", "issue-2"] {
+            let event = issue_event(id, "alice@gemeente.nl");
+            let projection = projection(&state.storage, &event).await.unwrap();
+            state
+                .storage
+                .store_projected_event(&event, projection)
+                .await
+                .unwrap();
+        }
+
+        // The entry after the failing one is replayed, the failing one is marked
+        let report = pipeline.replay(&state).await.unwrap();
+        assert_eq!((report.replayed, report.failed), (1, 1));
+        let changelog = state.storage.list_changelog().await.unwrap();
+        assert_eq!(changelog.len(), 1);
+        let (_, entry) = &changelog[0];
+        assert_eq!(entry.failed_replays, 1);
+        assert_eq!(entry.error.as_deref(), Some("recorder: blocked"));
+
+        // ... until it is given up on
+        for _ in 1..MAX_REPLAYS {
+            assert_eq!(pipeline.replay(&state).await.unwrap().failed, 1);
+        }
+        let report = pipeline.replay(&state).await.unwrap();
+        assert_eq!((report.failed, report.dead), (0, 1));
+    }
+}
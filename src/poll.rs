@@ -0,0 +1,200 @@
+//! Long-polling change feed, for integration partners whose middleware can't hold an SSE
+//! connection open.
+//!
+//! `GET /events/poll?after_seq=...&timeout=30s` answers right away when there are stored
+//! events after `after_seq` that the caller may see; otherwise it waits until one arrives or
+//! the timeout elapses, and then answers with an empty list. Every answer carries the
+//! sequence to pass as the next `after_seq`, which also moves past events the caller can't
+//! see, so those are not scanned again.
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::AuthUser;
+use crate::handlers::{
+    event_type_allowed, internal_error, may_see_event, parse_event_types, AppState,
+};
+use crate::schemas::CloudEvent;
+
+/// How long a poll waits without `timeout`
+pub const DEFAULT_POLL_TIMEOUT: Duration = Duration::from_secs(30);
+/// The longest a poll may wait, below the idle timeout of most proxies
+pub const MAX_POLL_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PollParams {
+    /// Sequence of the last event received; all events when absent
+    #[serde(default)]
+    pub after_seq: Option<String>,
+    /// How long to wait for new events: seconds, optionally with an `s` or `m` unit
+    /// (e.g. `30s`). At most 120 seconds.
+    #[serde(default)]
+    pub timeout: Option<String>,
+    /// Optional comma-separated list of event types to deliver
+    #[serde(default)]
+    pub types: Option<String>,
+    /// Most events in one answer
+    #[serde(default = "default_poll_limit")]
+    pub limit: usize,
+}
+
+fn default_poll_limit() -> usize {
+    100
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PollResponse {
+    /// New events, in sequence order; empty when the timeout elapsed
+    pub events: Vec<CloudEvent>,
+    /// Pass this as `after_seq` in the next poll
+    pub after_seq: Option<String>,
+}
+
+/// Parse `30`, `30s` or `2m`.
+pub fn parse_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.strip_suffix('m') {
+        Some(minutes) => (minutes, 60),
+        None => (value.strip_suffix('s').unwrap_or(value), 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|n| Duration::from_secs(n * unit))
+}
+
+/// GET /events/poll - Wait for events after a sequence
+#[utoipa::path(
+    get,
+    path = "/events/poll",
+    tag = "events",
+    params(PollParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "New events, or none when the timeout elapsed", body = PollResponse),
+        (status = 400, description = "Invalid timeout or limit"),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn poll_events_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<PollParams>,
+) -> Result<Json<PollResponse>, StatusCode> {
+    let user = auth_user.user_id;
+    let timeout = match params.timeout.as_deref() {
+        Some(value) => parse_timeout(value).ok_or(StatusCode::BAD_REQUEST)?,
+        None => DEFAULT_POLL_TIMEOUT,
+    };
+    if timeout > MAX_POLL_TIMEOUT || params.limit == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let type_filter = parse_event_types(params.types.as_deref());
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    // Subscribe before reading storage, so no event falls between the two
+    let mut rx = state.tx.subscribe();
+    let mut cursor = params.after_seq;
+    let mut events = Vec::new();
+    loop {
+        let page = state
+            .storage
+            .list_events_after(cursor.clone(), params.limit)
+            .await
+            .map_err(internal_error)?;
+        let full = page.len() == params.limit;
+        for event in page {
+            if event.sequence.is_some() {
+                cursor = event.sequence.clone();
+            }
+            if !event_type_allowed(&type_filter, &event) {
+                continue;
+            }
+            if may_see_event(&state.storage, &user, &event).await {
+                events.push(event);
+            }
+        }
+        if !events.is_empty() {
+            break;
+        }
+        if full {
+            // Only events the caller can't see so far; read on
+            continue;
+        }
+        // Wait for the next stored event; ephemeral events don't have a sequence
+        let arrived = loop {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Ok(event)) if event.sequence.is_none() => continue,
+                Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => break true,
+                Ok(Err(RecvError::Closed)) | Err(_) => break false,
+            }
+        };
+        if !arrived {
+            break;
+        }
+    }
+    Ok(Json(PollResponse {
+        events,
+        after_seq: cursor,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state};
+
+    #[tokio::test]
+    async fn test_long_poll() {
+        assert_eq!(parse_timeout("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_timeout("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_timeout("5"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_timeout("soon"), None);
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let (alice, bob) = ("alice@gemeente.nl", "bob@gemeente.nl");
+        let poll = |after_seq: Option<String>, timeout: &str| {
+            poll_events_handler(
+                State(state.clone()),
+                user(alice),
+                Query(PollParams {
+                    after_seq,
+                    timeout: Some(timeout.to_string()),
+                    types: None,
+                    limit: 100,
+                }),
+            )
+        };
+
+        // Events the caller can't see are skipped, and the timeout ends the wait
+        create_issue(&state, "issue-bob", &issue("Melding", &[bob]), bob).await;
+        let Json(response) = poll(None, "0s").await.unwrap();
+        assert!(response.events.is_empty());
+        let cursor = response.after_seq;
+        assert!(cursor.is_some());
+
+        // A poll that is waiting answers as soon as a visible event is stored
+        let waiting = tokio::spawn(poll(cursor.clone(), "10s"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        create_issue(&state, "issue-alice", &issue("Melding", &[alice]), alice).await;
+        let Json(response) = waiting.await.unwrap().unwrap();
+        assert_eq!(response.events.len(), 1);
+        assert_eq!(response.events[0].subject, "issue-alice");
+        assert_ne!(response.after_seq, cursor);
+
+        assert_eq!(
+            poll(None, "10m").await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
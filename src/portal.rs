@@ -0,0 +1,419 @@
+//! Citizen portal: the `/portal` API, a restricted view of the caller's own zaken.
+//!
+//! Only issues the caller is explicitly involved in are visible (team membership does not
+//! count), and every response is built from portal types here instead of the stored
+//! resources, so internal fields (assignee, team, tags, other participants, internal notes,
+//! tasks for staff) never reach the citizen, whatever the frontend shows. Unknown zaken and
+//! zaken of others both answer 404.
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{commit_of, internal_error, submit_commit, AppState};
+use crate::schemas::{CloudEvent, Comment, CommitBuilder, Issue, IssueStatus, Planning, Task};
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PortalZaak {
+    pub id: String,
+    pub title: String,
+    pub description: Option<String>,
+    #[schema(value_type = String)]
+    pub status: IssueStatus,
+    pub resolution: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PortalComment {
+    pub id: String,
+    /// Email of the author
+    pub author: String,
+    pub content: String,
+    /// When the comment was placed (RFC 3339)
+    pub created_at: Option<String>,
+    pub quote_comment: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PortalTask {
+    pub id: String,
+    pub cta: String,
+    pub description: String,
+    pub url: String,
+    pub completed: bool,
+    pub deadline: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PortalZaakDetail {
+    pub zaak: PortalZaak,
+    #[schema(value_type = Vec<Object>)]
+    pub planning: Vec<Planning>,
+    /// Comments in the order they were placed, internal notes excluded
+    pub comments: Vec<PortalComment>,
+    /// Tasks assigned to the caller
+    pub tasks: Vec<PortalTask>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PortalCommentRequest {
+    pub content: String,
+    /// ID of a (public) comment this replies to
+    pub quote_comment: Option<String>,
+}
+
+fn portal_zaak(id: String, issue: Issue) -> PortalZaak {
+    PortalZaak {
+        id,
+        title: issue.title,
+        description: issue.description,
+        status: issue.status,
+        resolution: issue.resolution,
+    }
+}
+
+/// The issue, if `user` is involved in it; 404 otherwise.
+async fn own_issue(state: &AppState, user: &str, issue_id: &str) -> Result<Issue, StatusCode> {
+    state
+        .storage
+        .get_resource(issue_id)
+        .await
+        .map_err(internal_error)?
+        .and_then(|value| serde_json::from_value::<Issue>(value).ok())
+        .filter(|issue| issue.involved.iter().flatten().any(|i| i == user))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// A resource of the issue's thread with who created it and when
+pub(crate) struct ThreadItem {
+    pub(crate) id: String,
+    pub(crate) actor: String,
+    pub(crate) created_at: Option<String>,
+    pub(crate) value: serde_json::Value,
+}
+
+/// The current resources created in the issue's thread, in order of creation.
+pub(crate) async fn thread_items(
+    state: &AppState,
+    issue_id: &str,
+) -> Result<Vec<ThreadItem>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut order = Vec::new();
+    let mut created: HashMap<String, (String, Option<String>)> = HashMap::new();
+    for event in state.storage.list_subject_events(issue_id).await? {
+        let Some(commit) = commit_of(&event) else {
+            continue;
+        };
+        if commit.resource_id == issue_id || created.contains_key(&commit.resource_id) {
+            continue;
+        }
+        order.push(commit.resource_id.clone());
+        created.insert(
+            commit.resource_id,
+            (commit.actor, commit.timestamp.or(event.time)),
+        );
+    }
+
+    let mut items = Vec::new();
+    for id in order {
+        // Deleted resources are gone from storage
+        if let Some(value) = state.storage.get_resource(&id).await? {
+            let (actor, created_at) = created.remove(&id).unwrap_or_default();
+            items.push(ThreadItem {
+                id,
+                actor,
+                created_at,
+                value,
+            });
+        }
+    }
+    Ok(items)
+}
+
+/// GET /portal/zaken - The caller's own zaken
+#[utoipa::path(
+    get,
+    path = "/portal/zaken",
+    tag = "portal",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Zaken the caller is involved in", body = [PortalZaak]),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn list_zaken_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<PortalZaak>>, StatusCode> {
+    let user = auth_user.user_id;
+    let zaken = state
+        .storage
+        .list_resources(0, usize::MAX)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .filter_map(|(id, value)| Some((id, serde_json::from_value::<Issue>(value).ok()?)))
+        .filter(|(_, issue)| issue.involved.iter().flatten().any(|i| *i == user))
+        .map(|(id, issue)| portal_zaak(id, issue))
+        .collect();
+    Ok(Json(zaken))
+}
+
+/// GET /portal/zaken/{id} - Status, planning, comments and the caller's tasks of a zaak
+#[utoipa::path(
+    get,
+    path = "/portal/zaken/{id}",
+    tag = "portal",
+    params(("id" = String, Path, description = "Issue ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The citizen's view of the zaak", body = PortalZaakDetail),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "Unknown zaak, or the caller is not involved in it"),
+    )
+)]
+pub async fn get_zaak_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+) -> Result<Json<PortalZaakDetail>, StatusCode> {
+    let user = auth_user.user_id;
+    let issue = own_issue(&state, &user, &issue_id).await?;
+
+    let mut planning = Vec::new();
+    let mut comments = Vec::new();
+    let mut tasks = Vec::new();
+    for item in thread_items(&state, &issue_id)
+        .await
+        .map_err(internal_error)?
+    {
+        if item.value.get("moments").is_some() {
+            if let Ok(p) = serde_json::from_value::<Planning>(item.value) {
+                planning.push(p);
+            }
+        } else if item.value.get("content").is_some() {
+            match serde_json::from_value::<Comment>(item.value) {
+                Ok(comment) if comment.internal != Some(true) => comments.push(PortalComment {
+                    id: item.id,
+                    author: item.actor,
+                    content: comment.content,
+                    created_at: item.created_at,
+                    quote_comment: comment.quote_comment,
+                }),
+                _ => {}
+            }
+        } else if item.value.get("cta").is_some() {
+            match serde_json::from_value::<Task>(item.value) {
+                Ok(task) if task.assignee.as_deref() == Some(user.as_str()) => {
+                    tasks.push(PortalTask {
+                        id: item.id,
+                        cta: task.cta,
+                        description: task.description,
+                        url: task.url,
+                        completed: task.completed,
+                        deadline: task.deadline,
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(Json(PortalZaakDetail {
+        zaak: portal_zaak(issue_id, issue),
+        planning,
+        comments,
+        tasks,
+    }))
+}
+
+/// POST /portal/zaken/{id}/comments - Place a comment on one of the caller's zaken
+#[utoipa::path(
+    post,
+    path = "/portal/zaken/{id}/comments",
+    tag = "portal",
+    params(("id" = String, Path, description = "Issue ID")),
+    request_body = PortalCommentRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Comment commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Empty comment"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "Unknown zaak, the caller is not involved in it, or the quoted comment is not part of its public conversation"),
+    )
+)]
+pub async fn add_comment_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(issue_id): Path<String>,
+    Json(request): Json<PortalCommentRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    own_issue(&state, &user, &issue_id).await?;
+    let content = request.content.trim().to_string();
+    if content.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if let Some(quoted) = &request.quote_comment {
+        let public = thread_items(&state, &issue_id)
+            .await
+            .map_err(internal_error)?
+            .into_iter()
+            .find(|item| item.id == *quoted)
+            .and_then(|item| serde_json::from_value::<Comment>(item.value).ok())
+            .is_some_and(|comment| comment.internal != Some(true));
+        if !public {
+            return Err(StatusCode::NOT_FOUND);
+        }
+    }
+
+    let comment = Comment {
+        content,
+        quote_comment: request.quote_comment,
+        mentions: None,
+        edited_at: None,
+        internal: None,
+    };
+    let commit = CommitBuilder::create(uuid::Uuid::now_v7().to_string(), &comment)
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &issue_id, commit).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{
+        auth_user as user, create_issue, issue, submit_commit_event, test_state,
+    };
+    use crate::schemas::{CommitBuilder, PlanningMoment, PlanningStatus};
+
+    #[tokio::test]
+    async fn test_portal_shows_only_the_citizen_view() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let burger = "jan@example.com";
+        let issue = Issue {
+            description: Some("Eik in de achtertuin".to_string()),
+            status: IssueStatus::InProgress,
+            assignee: Some(alice.to_string()),
+            tags: Some(vec!["spoed".to_string()]),
+            ..issue("Kapvergunning", &[alice, burger])
+        };
+        create_issue(&state, "issue-1", &issue, alice).await;
+        let comment = |content: &str, internal: Option<bool>| Comment {
+            content: content.to_string(),
+            quote_comment: None,
+            mentions: None,
+            edited_at: None,
+            internal,
+        };
+        let task = |cta: &str, assignee: &str| Task {
+            cta: cta.to_string(),
+            description: String::new(),
+            url: "/formulier".to_string(),
+            completed: false,
+            deadline: None,
+            assignee: Some(assignee.to_string()),
+            recurrence: None,
+        };
+        let planning = Planning {
+            title: None,
+            description: None,
+            moments: vec![PlanningMoment {
+                date: None,
+                title: "Besluit".to_string(),
+                status: PlanningStatus::Planned,
+            }],
+        };
+        let commits = [
+            CommitBuilder::create("comment-1", &comment("We gaan kijken", None)),
+            CommitBuilder::create("comment-2", &comment("Buurman klaagt ook", Some(true))),
+            CommitBuilder::create("task-1", &task("Foto's aanleveren", burger)),
+            CommitBuilder::create("task-2", &task("Boomdeskundige bellen", alice)),
+            CommitBuilder::create("planning-1", &planning),
+        ];
+        for commit in commits {
+            let commit = commit.actor(alice).build();
+            submit_commit_event(&state, "issue-1", &commit)
+                .await
+                .unwrap();
+        }
+
+        let Json(zaken) = list_zaken_handler(State(state.clone()), user(burger))
+            .await
+            .unwrap();
+        assert_eq!(zaken.len(), 1);
+        let listed = serde_json::to_value(&zaken[0]).unwrap();
+        assert!(listed.get("assignee").is_none() && listed.get("tags").is_none());
+
+        let Json(detail) = get_zaak_handler(
+            State(state.clone()),
+            user(burger),
+            Path("issue-1".to_string()),
+        )
+        .await
+        .unwrap();
+        let comments: Vec<&str> = detail.comments.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(comments, vec!["comment-1"]);
+        let tasks: Vec<&str> = detail.tasks.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(tasks, vec!["task-1"]);
+        assert_eq!(detail.planning.len(), 1);
+
+        // Internal notes can't be replied to, and others' zaken don't exist
+        let reply = |quote: Option<&str>| {
+            add_comment_handler(
+                State(state.clone()),
+                user(burger),
+                HeaderMap::new(),
+                Path("issue-1".to_string()),
+                Json(PortalCommentRequest {
+                    content: "Dank!".to_string(),
+                    quote_comment: quote.map(str::to_string),
+                }),
+            )
+        };
+        assert_eq!(
+            reply(Some("comment-2")).await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            reply(Some("comment-1")).await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        let piet = "piet@example.com";
+        assert_eq!(
+            get_zaak_handler(
+                State(state.clone()),
+                user(piet),
+                Path("issue-1".to_string()),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        let Json(zaken) = list_zaken_handler(State(state.clone()), user(piet))
+            .await
+            .unwrap();
+        assert!(zaken.is_empty());
+        let outsider_reply = add_comment_handler(
+            State(state.clone()),
+            user(piet),
+            HeaderMap::new(),
+            Path("issue-1".to_string()),
+            Json(PortalCommentRequest {
+                content: "Ik ook".to_string(),
+                quote_comment: None,
+            }),
+        );
+        assert_eq!(outsider_reply.await.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+}
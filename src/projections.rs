@@ -0,0 +1,599 @@
+//! Named projections: read models derived from the event log.
+//!
+//! A projection is a reducer over events ([`NamedProjection::reduce`]) that keeps its read
+//! model as JSON values under its own keys (`Storage::get_projection_value`). Each projection
+//! tracks the sequence of the last event it processed; the `projections` step of the event
+//! pipeline catches every projection up after each commit, and writes an event's changes
+//! together with the new cursor, so a projection never sees an event twice. A projection
+//! registered later starts at the beginning of the log, and `POST /projections/{name}/rebuild`
+//! clears one and replays it, without touching the others.
+//!
+//! New projections are registered on [`Projections`] at startup, without changes to the
+//! pipeline. Built in are `open-issues-by-team`: the open issues per team ("unassigned" for
+//! issues without one), the materialized views in `views` and the usage counts of `quotas`.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{commit_of, internal_error, AppState};
+use crate::pipeline::{EventContext, EventProcessor};
+use crate::resource_types::resolve;
+use crate::schemas::CloudEvent;
+use crate::storage::Storage;
+
+/// Events read per step while catching up
+const CATCH_UP_PAGE_SIZE: usize = 200;
+
+/// A projection's read model while it processes one event: reads see the changes made so
+/// far, which are written when the event is done.
+pub struct ModelView<'a> {
+    storage: &'a Storage,
+    projection: &'static str,
+    changes: BTreeMap<String, Option<Value>>,
+}
+
+impl<'a> ModelView<'a> {
+    fn new(storage: &'a Storage, projection: &'static str) -> Self {
+        Self {
+            storage,
+            projection,
+            changes: BTreeMap::new(),
+        }
+    }
+
+    pub async fn get(
+        &self,
+        key: &str,
+    ) -> Result<Option<Value>, Box<dyn std::error::Error + Send + Sync>> {
+        match self.changes.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => {
+                self.storage
+                    .get_projection_value(self.projection, key)
+                    .await
+            }
+        }
+    }
+
+    /// The store, e.g. to resolve resource types
+    pub fn storage(&self) -> &'a Storage {
+        self.storage
+    }
+
+    pub fn put(&mut self, key: impl Into<String>, value: Value) {
+        self.changes.insert(key.into(), Some(value));
+    }
+
+    pub fn remove(&mut self, key: impl Into<String>) {
+        self.changes.insert(key.into(), None);
+    }
+}
+
+/// A reducer over events that maintains a read model.
+#[async_trait]
+pub trait NamedProjection: Send + Sync {
+    /// Unique name; also the key prefix of the read model
+    fn name(&self) -> &'static str;
+
+    /// Apply `event` to the read model. Events are seen once each, in sequence order.
+    async fn reduce(
+        &self,
+        model: &mut ModelView<'_>,
+        event: &CloudEvent,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Open issues per team: `team/{team}` holds the sorted IDs, `issue/{id}` what the
+/// projection knows of each issue (`team`, `open`)
+pub struct OpenIssuesByTeam;
+
+const NO_TEAM: &str = "unassigned";
+
+#[async_trait]
+impl NamedProjection for OpenIssuesByTeam {
+    fn name(&self) -> &'static str {
+        "open-issues-by-team"
+    }
+
+    async fn reduce(
+        &self,
+        model: &mut ModelView<'_>,
+        event: &CloudEvent,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(commit) = commit_of(event) else {
+            return Ok(());
+        };
+        if resolve(model.storage(), &commit.schema).await? != "Issue" {
+            return Ok(());
+        }
+        let issue_key = format!("issue/{}", commit.resource_id);
+        let previous = model.get(&issue_key).await?;
+        let team = |v: &Value| {
+            v.get("team")
+                .and_then(Value::as_str)
+                .unwrap_or(NO_TEAM)
+                .to_string()
+        };
+        let open = |v: &Value| v.get("status").and_then(Value::as_str) != Some("closed");
+
+        let next = if commit.deleted == Some(true) {
+            None
+        } else if let Some(data) = &commit.resource_data {
+            Some((team(data), open(data)))
+        } else if let (Some(patch), Some(previous)) = (&commit.merge_patch(), &previous) {
+            let team = match patch.get("team") {
+                Some(_) => team(patch),
+                None => team(previous),
+            };
+            let open = match patch.get("status") {
+                Some(_) => open(patch),
+                None => previous["open"].as_bool().unwrap_or(true),
+            };
+            Some((team, open))
+        } else {
+            return Ok(());
+        };
+
+        if let Some(previous) = &previous {
+            if previous["open"].as_bool().unwrap_or(false) {
+                let key = format!("team/{}", team(previous));
+                let mut ids = ids(model.get(&key).await?);
+                ids.retain(|id| id != &commit.resource_id);
+                match ids.is_empty() {
+                    true => model.remove(key),
+                    false => model.put(key, json!(ids)),
+                }
+            }
+        }
+        match next {
+            Some((team, open)) => {
+                if open {
+                    let key = format!("team/{}", team);
+                    let mut ids = ids(model.get(&key).await?);
+                    if let Err(at) = ids.binary_search(&commit.resource_id) {
+                        ids.insert(at, commit.resource_id.clone());
+                    }
+                    model.put(key, json!(ids));
+                }
+                model.put(issue_key, json!({ "team": team, "open": open }));
+            }
+            None => model.remove(issue_key),
+        }
+        Ok(())
+    }
+}
+
+fn ids(value: Option<Value>) -> Vec<String> {
+    value
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default()
+}
+
+struct Registered {
+    projection: Arc<dyn NamedProjection>,
+    /// Held while catching up, so no event is applied twice
+    lock: tokio::sync::Mutex<()>,
+}
+
+/// The registered projections
+pub struct Projections {
+    registered: Vec<Registered>,
+}
+
+impl Default for Projections {
+    /// The built-in projections
+    fn default() -> Self {
+        let mut projections = Self::new();
+        projections.register(Arc::new(OpenIssuesByTeam));
+        projections.register(Arc::new(crate::views::IssuesByStatus));
+        projections.register(Arc::new(crate::views::TasksByAssignee));
+        projections.register(Arc::new(crate::quotas::UsageProjection));
+        projections
+    }
+}
+
+impl Projections {
+    pub fn new() -> Self {
+        Self {
+            registered: Vec::new(),
+        }
+    }
+
+    pub fn register(&mut self, projection: Arc<dyn NamedProjection>) {
+        self.registered.push(Registered {
+            projection,
+            lock: tokio::sync::Mutex::new(()),
+        });
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.registered
+            .iter()
+            .map(|r| r.projection.name())
+            .collect()
+    }
+
+    fn find(&self, name: &str) -> Option<&Registered> {
+        self.registered.iter().find(|r| r.projection.name() == name)
+    }
+
+    /// Apply the events after its cursor to projection `name`. Returns how many were applied.
+    pub async fn catch_up(
+        &self,
+        storage: &Storage,
+        name: &str,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let registered = self
+            .find(name)
+            .ok_or_else(|| format!("unknown projection {}", name))?;
+        let _guard = registered.lock.lock().await;
+        let projection = registered.projection.as_ref();
+        let mut after = storage.projection_cursor(name).await?;
+        let mut applied = 0;
+        loop {
+            let events = storage
+                .list_events_after(after.clone(), CATCH_UP_PAGE_SIZE)
+                .await?;
+            if events.is_empty() {
+                return Ok(applied);
+            }
+            for event in events {
+                let seq_key = event.sequence.clone().unwrap_or_default();
+                let mut model = ModelView::new(storage, projection.name());
+                projection
+                    .reduce(&mut model, &event)
+                    .await
+                    .map_err(|e| format!("event {} ({}): {}", event.id, seq_key, e))?;
+                let changes: Vec<_> = model.changes.into_iter().collect();
+                storage
+                    .apply_projection_changes(name, &seq_key, &changes)
+                    .await?;
+                after = Some(seq_key);
+                applied += 1;
+            }
+        }
+    }
+
+    /// Catch up every projection; a failing one doesn't hold up the others.
+    pub async fn catch_up_all(
+        &self,
+        storage: &Storage,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut failed = Vec::new();
+        for name in self.names() {
+            if let Err(e) = self.catch_up(storage, name).await {
+                failed.push(format!("{}: {}", name, e));
+            }
+        }
+        match failed.is_empty() {
+            true => Ok(()),
+            false => Err(failed.join("; ").into()),
+        }
+    }
+
+    /// Clear projection `name` and replay the whole log into it.
+    pub async fn rebuild(
+        &self,
+        storage: &Storage,
+        name: &str,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let registered = self
+            .find(name)
+            .ok_or_else(|| format!("unknown projection {}", name))?;
+        {
+            let _guard = registered.lock.lock().await;
+            storage.clear_projection(name).await?;
+        }
+        self.catch_up(storage, name).await
+    }
+}
+
+/// Pipeline step that catches the projections up with each committed event
+pub struct ProjectionsProcessor;
+
+#[async_trait]
+impl EventProcessor for ProjectionsProcessor {
+    fn name(&self) -> &'static str {
+        "projections"
+    }
+
+    async fn committed(
+        &self,
+        state: &AppState,
+        _ctx: &EventContext,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        state.projections.catch_up_all(&state.storage).await
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProjectionStatus {
+    pub name: String,
+    /// Sequence key of the last event the projection processed
+    pub sequence: Option<String>,
+    /// Stored events it has not processed yet
+    pub lag: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProjectionValue {
+    pub key: String,
+    #[schema(value_type = Object)]
+    pub value: Value,
+}
+
+/// GET /projections - The registered projections and how far they are
+#[utoipa::path(
+    get,
+    path = "/projections",
+    tag = "resources",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Projections with their cursor and lag", body = Vec<ProjectionStatus>),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn list_projections_handler(
+    State(state): State<AppState>,
+    _auth_user: AuthUser,
+) -> Result<Json<Vec<ProjectionStatus>>, StatusCode> {
+    let latest = state
+        .storage
+        .latest_sequence()
+        .await
+        .map_err(internal_error)?
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(0);
+    let mut statuses = Vec::new();
+    for name in state.projections.names() {
+        let sequence = state
+            .storage
+            .projection_cursor(name)
+            .await
+            .map_err(internal_error)?;
+        let processed = sequence
+            .as_deref()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(0);
+        statuses.push(ProjectionStatus {
+            name: name.to_string(),
+            sequence,
+            lag: latest.saturating_sub(processed),
+        });
+    }
+    Ok(Json(statuses))
+}
+
+/// GET /projections/{name} - The read model of a projection
+#[utoipa::path(
+    get,
+    path = "/projections/{name}",
+    tag = "resources",
+    params(("name" = String, Path, description = "Projection name, e.g. \"open-issues-by-team\"")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Keys and values, ordered by key", body = Vec<ProjectionValue>),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "Unknown projection"),
+    )
+)]
+pub async fn get_projection_handler(
+    State(state): State<AppState>,
+    _auth_user: AuthUser,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<ProjectionValue>>, StatusCode> {
+    if !state.projections.names().contains(&name.as_str()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let values = state
+        .storage
+        .list_projection_values(&name)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(
+        values
+            .into_iter()
+            .map(|(key, value)| ProjectionValue { key, value })
+            .collect(),
+    ))
+}
+
+/// GET /projections/{name}/{key} - One value of a projection's read model
+#[utoipa::path(
+    get,
+    path = "/projections/{name}/{key}",
+    tag = "resources",
+    params(
+        ("name" = String, Path, description = "Projection name"),
+        ("key" = String, Path, description = "Key in the read model, e.g. \"team/vergunningen\""),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The value", body = Object),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "Unknown projection or key"),
+    )
+)]
+pub async fn get_projection_value_handler(
+    State(state): State<AppState>,
+    _auth_user: AuthUser,
+    Path((name, key)): Path<(String, String)>,
+) -> Result<Json<Value>, StatusCode> {
+    if !state.projections.names().contains(&name.as_str()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    state
+        .storage
+        .get_projection_value(&name, &key)
+        .await
+        .map_err(internal_error)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// POST /projections/{name}/rebuild - Clear a projection and replay the log into it
+#[utoipa::path(
+    post,
+    path = "/projections/{name}/rebuild",
+    tag = "admin",
+    params(("name" = String, Path, description = "Projection name")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Rebuilt; the projection's status afterwards", body = ProjectionStatus),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+        (status = 404, description = "Unknown projection"),
+    )
+)]
+pub async fn rebuild_projection_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(name): Path<String>,
+) -> Result<Json<ProjectionStatus>, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if !state.projections.names().contains(&name.as_str()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let applied = state
+        .projections
+        .rebuild(&state.storage, &name)
+        .await
+        .map_err(internal_error)?;
+    println!("[projections] rebuilt {} from {} events", name, applied);
+    let sequence = state
+        .storage
+        .projection_cursor(&name)
+        .await
+        .map_err(internal_error)?;
+    let latest = state
+        .storage
+        .latest_sequence()
+        .await
+        .map_err(internal_error)?;
+    let number = |s: Option<&str>| s.and_then(|s| s.parse::<u64>().ok()).unwrap_or(0);
+    Ok(Json(ProjectionStatus {
+        lag: number(latest.as_deref()).saturating_sub(number(sequence.as_deref())),
+        name,
+        sequence,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{issue, submit_commit_event, test_state};
+    use crate::schemas::{CommitBuilder, Issue, JSONCommit};
+
+    async fn submit_issue_commit(state: &AppState, commit: JSONCommit) {
+        submit_commit_event(state, &commit.resource_id, &commit)
+            .await
+            .unwrap();
+    }
+
+    async fn team(state: &AppState, team: &str) -> Vec<String> {
+        ids(state
+            .storage
+            .get_projection_value("open-issues-by-team", &format!("team/{}", team))
+            .await
+            .unwrap())
+    }
+
+    /// Counts events per type
+    struct EventTypes;
+
+    #[async_trait]
+    impl NamedProjection for EventTypes {
+        fn name(&self) -> &'static str {
+            "event-types"
+        }
+
+        async fn reduce(
+            &self,
+            model: &mut ModelView<'_>,
+            event: &CloudEvent,
+        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            let count = model
+                .get(&event.event_type)
+                .await?
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0);
+            model.put(event.event_type.clone(), json!(count + 1));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_issues_by_team() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        for (id, team) in [("issue-1", Some("vergunningen")), ("issue-2", None)] {
+            let issue = Issue {
+                team: team.map(str::to_string),
+                ..issue(id, &[])
+            };
+            submit_issue_commit(&state, CommitBuilder::create(id, &issue).build()).await;
+        }
+        assert_eq!(team(&state, "vergunningen").await, vec!["issue-1"]);
+        assert_eq!(team(&state, NO_TEAM).await, vec!["issue-2"]);
+
+        let move_to_team = json!({"team": "vergunningen"});
+        submit_issue_commit(
+            &state,
+            CommitBuilder::patch::<Issue>("issue-2", move_to_team).build(),
+        )
+        .await;
+        let close = json!({"status": "closed"});
+        submit_issue_commit(
+            &state,
+            CommitBuilder::patch::<Issue>("issue-1", close).build(),
+        )
+        .await;
+        assert_eq!(team(&state, "vergunningen").await, vec!["issue-2"]);
+        assert!(team(&state, NO_TEAM).await.is_empty());
+
+        // A projection registered later starts at the beginning, and rebuilds on its own
+        let mut projections = Projections::default();
+        projections.register(Arc::new(EventTypes));
+        assert_eq!(
+            projections
+                .catch_up(&state.storage, "event-types")
+                .await
+                .unwrap(),
+            4
+        );
+        assert_eq!(
+            projections
+                .catch_up(&state.storage, "event-types")
+                .await
+                .unwrap(),
+            0
+        );
+        let count = || {
+            state
+                .storage
+                .get_projection_value("event-types", "json.commit")
+        };
+        assert_eq!(count().await.unwrap(), Some(json!(4)));
+        assert_eq!(
+            projections
+                .rebuild(&state.storage, "event-types")
+                .await
+                .unwrap(),
+            4
+        );
+        assert_eq!(count().await.unwrap(), Some(json!(4)));
+        assert_eq!(team(&state, "vergunningen").await, vec!["issue-2"]);
+    }
+}
@@ -0,0 +1,725 @@
+//! Storage quotas per user and per tenant (the domain of their email).
+//!
+//! The `usage` projection counts, per user and per tenant, the commits they submitted, the
+//! resources they created that still exist and the bytes of those resources that are
+//! documents. A resource counts against its creator until it is deleted. A document's bytes
+//! are the length of the finished upload its `url` points to (see `uploads`), not the `size`
+//! the client states; documents elsewhere store no bytes here. Commits count against their
+//! `actor`, which for inbound commits the `authorization` step holds to the authenticated
+//! submitter.
+//!
+//! Limits are set with `USER_QUOTA_EVENTS`, `USER_QUOTA_RESOURCES` and
+//! `USER_QUOTA_DOCUMENT_BYTES`, and the same `TENANT_QUOTA_*` variables for tenants; unset
+//! means unlimited. With any limit set, the `quotas` step of the event pipeline (after
+//! authorization) rejects inbound commits that would go over one: 413 for document bytes,
+//! 429 for the event and resource counts, with the exceeded quota in the body. Uploads are
+//! held to the document byte quotas when they start (`POST /uploads`), by their announced
+//! length. Admins (see `auth::is_admin`) see a tenant's usage at `GET /usage/{tenant}`.
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::auth::{tenant_of, AuthUser};
+use crate::handlers::{commit_of, internal_error, AppState};
+use crate::pipeline::{EventContext, EventProcessor, ProcessError};
+use crate::projections::{ModelView, NamedProjection};
+use crate::resource_types::resolve;
+use crate::schemas::{CloudEvent, JSONCommit};
+use crate::storage::Storage;
+
+pub const USAGE: &str = "usage";
+
+/// What a user or tenant uses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Usage {
+    /// Commits submitted
+    pub events: u64,
+    /// Resources created that still exist
+    pub resources: u64,
+    /// Total size of those resources that are documents
+    pub document_bytes: u64,
+}
+
+/// The limits for one user or tenant; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct QuotaLimits {
+    pub events: Option<u64>,
+    pub resources: Option<u64>,
+    pub document_bytes: Option<u64>,
+}
+
+impl QuotaLimits {
+    /// The limits in `{prefix}EVENTS`, `{prefix}RESOURCES` and `{prefix}DOCUMENT_BYTES`.
+    pub fn from_env(prefix: &str) -> Self {
+        let limit = |name: &str| {
+            std::env::var(format!("{}{}", prefix, name))
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+        };
+        QuotaLimits {
+            events: limit("EVENTS"),
+            resources: limit("RESOURCES"),
+            document_bytes: limit("DOCUMENT_BYTES"),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        *self == QuotaLimits::default()
+    }
+}
+
+/// The configured limits
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quotas {
+    pub user: QuotaLimits,
+    pub tenant: QuotaLimits,
+}
+
+impl Quotas {
+    pub fn from_env() -> Self {
+        Quotas {
+            user: QuotaLimits::from_env("USER_QUOTA_"),
+            tenant: QuotaLimits::from_env("TENANT_QUOTA_"),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.user.is_unlimited() && self.tenant.is_unlimited()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Quota {
+    Events,
+    Resources,
+    DocumentBytes,
+}
+
+/// Why a commit was refused
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct QuotaExceeded {
+    pub quota: Quota,
+    /// "user" or "tenant"
+    pub scope: String,
+    /// The user's email or the tenant's domain
+    pub account: String,
+    pub limit: u64,
+    pub used: u64,
+    /// What the commit would add
+    pub requested: u64,
+}
+
+impl QuotaExceeded {
+    pub fn status(&self) -> StatusCode {
+        match self.quota {
+            Quota::DocumentBytes => StatusCode::PAYLOAD_TOO_LARGE,
+            Quota::Events | Quota::Resources => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} quota of {} {} exceeded ({} + {} > {})",
+            self.quota, self.scope, self.account, self.used, self.requested, self.limit
+        )
+    }
+}
+
+/// What the usage projection knows of a resource
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Owned {
+    owner: String,
+    #[serde(default)]
+    document_bytes: u64,
+}
+
+fn user_key(user: &str) -> String {
+    format!("user/{}", user)
+}
+
+fn tenant_key(tenant: &str) -> String {
+    format!("tenant/{}", tenant)
+}
+
+/// The bytes a document commit stores, when it sets the document's `url`: the length of the
+/// finished upload it points to, or 0 for anything else.
+async fn document_bytes(
+    storage: &Storage,
+    commit: &JSONCommit,
+) -> Result<Option<u64>, Box<dyn std::error::Error + Send + Sync>> {
+    let data = commit
+        .resource_data
+        .clone()
+        .or_else(|| commit.merge_patch());
+    let Some(url) = data
+        .as_ref()
+        .and_then(|d| d.get("url"))
+        .and_then(Value::as_str)
+    else {
+        return Ok(None);
+    };
+    Ok(Some(
+        crate::uploads::finished_length(storage, url)
+            .await?
+            .unwrap_or(0),
+    ))
+}
+
+async fn owned_resource(
+    storage: &Storage,
+    resource_id: &str,
+) -> Result<Option<Owned>, Box<dyn std::error::Error + Send + Sync>> {
+    let key = format!("resource/{}", resource_id);
+    match storage.get_projection_value(USAGE, &key).await? {
+        Some(value) => Ok(Some(serde_json::from_value(value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Usage per user and tenant: `user/{email}` and `tenant/{domain}` hold a [`Usage`],
+/// `resource/{id}` the creator of each resource and its document bytes
+pub struct UsageProjection;
+
+impl UsageProjection {
+    async fn update(
+        model: &mut ModelView<'_>,
+        user: &str,
+        change: impl Fn(&mut Usage),
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for key in [user_key(user), tenant_key(tenant_of(user))] {
+            let mut usage: Usage = match model.get(&key).await? {
+                Some(value) => serde_json::from_value(value)?,
+                None => Usage::default(),
+            };
+            change(&mut usage);
+            model.put(key, serde_json::to_value(usage)?);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl NamedProjection for UsageProjection {
+    fn name(&self) -> &'static str {
+        USAGE
+    }
+
+    async fn reduce(
+        &self,
+        model: &mut ModelView<'_>,
+        event: &CloudEvent,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(commit) = commit_of(event) else {
+            return Ok(());
+        };
+        Self::update(model, &commit.actor, |u| u.events += 1).await?;
+
+        let key = format!("resource/{}", commit.resource_id);
+        let owned: Option<Owned> = match model.get(&key).await? {
+            Some(value) => Some(serde_json::from_value(value)?),
+            None => None,
+        };
+        match (owned, commit.deleted == Some(true)) {
+            (Some(owned), true) => {
+                Self::update(model, &owned.owner, |u| {
+                    u.resources = u.resources.saturating_sub(1);
+                    u.document_bytes = u.document_bytes.saturating_sub(owned.document_bytes);
+                })
+                .await?;
+                model.remove(key);
+            }
+            (None, true) => {}
+            (owned, false) => {
+                let is_new = owned.is_none();
+                let mut owned = owned.unwrap_or_else(|| Owned {
+                    owner: commit.actor.clone(),
+                    document_bytes: 0,
+                });
+                let before = owned.document_bytes;
+                if resolve(model.storage(), &commit.schema).await? == "Document" {
+                    owned.document_bytes = document_bytes(model.storage(), &commit)
+                        .await?
+                        .unwrap_or(before);
+                }
+                let after = owned.document_bytes;
+                Self::update(model, &owned.owner, |u| {
+                    u.resources += u64::from(is_new);
+                    u.document_bytes = (u.document_bytes + after).saturating_sub(before);
+                })
+                .await?;
+                model.put(key, serde_json::to_value(owned)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The usage of a user (`user/{email}`) or tenant (`tenant/{domain}`).
+pub async fn usage_of(
+    storage: &Storage,
+    key: &str,
+) -> Result<Usage, Box<dyn std::error::Error + Send + Sync>> {
+    match storage.get_projection_value(USAGE, key).await? {
+        Some(value) => Ok(serde_json::from_value(value)?),
+        None => Ok(Usage::default()),
+    }
+}
+
+/// Pipeline step that rejects inbound commits going over a quota of the account they count
+/// against
+pub struct QuotaProcessor {
+    pub quotas: Quotas,
+}
+
+impl QuotaProcessor {
+    /// What `commit` adds, as (events, resources, document bytes).
+    async fn requested(
+        storage: &Storage,
+        commit: &JSONCommit,
+    ) -> Result<(u64, u64, u64), Box<dyn std::error::Error + Send + Sync>> {
+        if commit.deleted == Some(true) {
+            return Ok((1, 0, 0));
+        }
+        let current = storage.get_resource(&commit.resource_id).await?;
+        let new = u64::from(current.is_none());
+        let bytes = match resolve(storage, &commit.schema).await? == "Document" {
+            true => match document_bytes(storage, commit).await? {
+                Some(after) => {
+                    let before = owned_resource(storage, &commit.resource_id)
+                        .await?
+                        .map_or(0, |owned| owned.document_bytes);
+                    after.saturating_sub(before)
+                }
+                None => 0,
+            },
+            false => 0,
+        };
+        Ok((1, new, bytes))
+    }
+}
+
+#[async_trait]
+impl EventProcessor for QuotaProcessor {
+    fn name(&self) -> &'static str {
+        "quotas"
+    }
+
+    async fn prepare(&self, state: &AppState, ctx: &mut EventContext) -> Result<(), ProcessError> {
+        let Some(commit) = ctx.commit().filter(|_| ctx.inbound) else {
+            return Ok(());
+        };
+        // The account the usage projection charges
+        let requested = Self::requested(&state.storage, &commit).await?;
+        match exceeded(&state.storage, &self.quotas, &commit.actor, requested).await? {
+            Some(exceeded) => Err(ProcessError::QuotaExceeded(Box::new(exceeded))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The quota of `actor` or their tenant that adding `requested` (events, resources, document
+/// bytes) would exceed, if any.
+async fn exceeded(
+    storage: &Storage,
+    quotas: &Quotas,
+    actor: &str,
+    (events, resources, bytes): (u64, u64, u64),
+) -> Result<Option<QuotaExceeded>, Box<dyn std::error::Error + Send + Sync>> {
+    let accounts = [
+        ("user", actor, user_key(actor), quotas.user),
+        (
+            "tenant",
+            tenant_of(actor),
+            tenant_key(tenant_of(actor)),
+            quotas.tenant,
+        ),
+    ];
+    for (scope, account, key, limits) in accounts {
+        if limits.is_unlimited() {
+            continue;
+        }
+        let usage = usage_of(storage, &key).await?;
+        let checks = [
+            (Quota::Events, limits.events, usage.events, events),
+            (
+                Quota::Resources,
+                limits.resources,
+                usage.resources,
+                resources,
+            ),
+            (
+                Quota::DocumentBytes,
+                limits.document_bytes,
+                usage.document_bytes,
+                bytes,
+            ),
+        ];
+        for (quota, limit, used, requested) in checks {
+            match limit {
+                Some(limit) if requested > 0 && used + requested > limit => {
+                    return Ok(Some(QuotaExceeded {
+                        quota,
+                        scope: scope.to_string(),
+                        account: account.to_string(),
+                        limit,
+                        used,
+                        requested,
+                    }));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The quota that `commits`, all by `actor`, would exceed together, if any. For requests
+/// that submit several commits, so they can be refused before the first is in.
+pub async fn check_commits(
+    storage: &Storage,
+    quotas: &Quotas,
+    actor: &str,
+    commits: &[JSONCommit],
+) -> Result<Option<QuotaExceeded>, Box<dyn std::error::Error + Send + Sync>> {
+    if quotas.is_unlimited() {
+        return Ok(None);
+    }
+    let mut total = (0, 0, 0);
+    for commit in commits {
+        let (events, resources, bytes) = QuotaProcessor::requested(storage, commit).await?;
+        total = (total.0 + events, total.1 + resources, total.2 + bytes);
+    }
+    exceeded(storage, quotas, actor, total).await
+}
+
+/// The document byte quota an upload of `length` bytes by `user` would exceed, if any.
+/// Uploads count before a document points to them: an account uses what its documents
+/// count or what its stored uploads take, whichever is more.
+pub async fn check_upload(
+    storage: &Storage,
+    quotas: &Quotas,
+    user: &str,
+    length: u64,
+) -> Result<Option<QuotaExceeded>, Box<dyn std::error::Error + Send + Sync>> {
+    if quotas.user.document_bytes.is_none() && quotas.tenant.document_bytes.is_none() {
+        return Ok(None);
+    }
+    let tenant = tenant_of(user);
+    let (mut user_uploads, mut tenant_uploads) = (0, 0);
+    for (_, session) in storage.list_uploads().await? {
+        let Ok(session) = serde_json::from_str::<crate::uploads::UploadSession>(&session) else {
+            continue;
+        };
+        if tenant_of(&session.owner) == tenant {
+            tenant_uploads += session.length;
+            if session.owner == user {
+                user_uploads += session.length;
+            }
+        }
+    }
+    let accounts = [
+        ("user", user, user_key(user), quotas.user, user_uploads),
+        (
+            "tenant",
+            tenant,
+            tenant_key(tenant),
+            quotas.tenant,
+            tenant_uploads,
+        ),
+    ];
+    for (scope, account, key, limits, uploads) in accounts {
+        let Some(limit) = limits.document_bytes else {
+            continue;
+        };
+        let used = usage_of(storage, &key).await?.document_bytes.max(uploads);
+        if used + length > limit {
+            return Ok(Some(QuotaExceeded {
+                quota: Quota::DocumentBytes,
+                scope: scope.to_string(),
+                account: account.to_string(),
+                limit,
+                used,
+                requested: length,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// A user's usage, in a tenant report
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserUsage {
+    pub user: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TenantUsage {
+    pub tenant: String,
+    pub usage: Usage,
+    pub tenant_limits: QuotaLimits,
+    /// The limits of each user
+    pub user_limits: QuotaLimits,
+    /// The tenant's users, by email
+    pub users: Vec<UserUsage>,
+}
+
+/// GET /usage/{tenant} - A tenant's usage and quotas, per user
+#[utoipa::path(
+    get,
+    path = "/usage/{tenant}",
+    tag = "admin",
+    params(("tenant" = String, Path, description = "Email domain of the tenant")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Usage of the tenant and its users", body = TenantUsage),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+    )
+)]
+pub async fn usage_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(tenant): Path<String>,
+) -> Result<Json<TenantUsage>, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let mut usage = Usage::default();
+    let mut users = Vec::new();
+    let values = state
+        .storage
+        .list_projection_values(USAGE)
+        .await
+        .map_err(internal_error)?;
+    for (key, value) in values {
+        let parsed = || serde_json::from_value::<Usage>(value.clone()).map_err(internal_error);
+        if key == tenant_key(&tenant) {
+            usage = parsed()?;
+        } else if let Some(user) = key.strip_prefix("user/") {
+            if tenant_of(user) == tenant {
+                users.push(UserUsage {
+                    user: user.to_string(),
+                    usage: parsed()?,
+                });
+            }
+        }
+    }
+    let quotas = Quotas::from_env();
+    Ok(Json(TenantUsage {
+        tenant,
+        usage,
+        tenant_limits: quotas.tenant,
+        user_limits: quotas.user,
+        users,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, issue, test_state, ADMIN};
+    use crate::pipeline::Pipeline;
+    use crate::schemas::{CloudEventBuilder, CommitBuilder, Document, Issue};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_usage_and_quotas() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let mut pipeline = Pipeline::standard();
+        let quotas = Quotas {
+            user: QuotaLimits {
+                resources: Some(3),
+                ..Default::default()
+            },
+            tenant: QuotaLimits {
+                document_bytes: Some(1000),
+                ..Default::default()
+            },
+        };
+        pipeline.insert_after("authorization", Arc::new(QuotaProcessor { quotas }));
+        let alice = "alice@gemeente.nl";
+        let submit = |commit: JSONCommit| {
+            let event = CloudEventBuilder::commit("issue-1", &commit).build();
+            pipeline.submit(
+                &state,
+                EventContext::new(event).inbound(Some(alice.to_string())),
+            )
+        };
+        let exceeded = |result: Result<CloudEvent, ProcessError>| match result {
+            Err(ProcessError::QuotaExceeded(exceeded)) => *exceeded,
+            other => panic!("expected a quota error, got {:?}", other.map(|e| e.id)),
+        };
+        // Finished uploads of 600, 500, 100 and 1 bytes
+        for length in [600, 500, 100, 1] {
+            let session = crate::uploads::UploadSession {
+                owner: alice.to_string(),
+                length,
+                offset: length,
+                filename: None,
+                filetype: None,
+                created_at: chrono::Utc::now(),
+            };
+            let session = serde_json::to_string(&session).unwrap();
+            state
+                .storage
+                .set_upload(&format!("upload-{}", length), Some(&session))
+                .await
+                .unwrap();
+        }
+        // The stated size doesn't count, the upload's length does
+        let document = |length: u64| Document {
+            title: "Paspoortfoto.jpg".to_string(),
+            url: format!("https://zaakchat.nl/uploads/upload-{}", length),
+            size: 0,
+        };
+
+        let issue = issue("Paspoort", &[alice]);
+        let create = |id: &str, size| CommitBuilder::create(id, &document(size)).actor(alice);
+        submit(
+            CommitBuilder::create("issue-1", &issue)
+                .actor(alice)
+                .build(),
+        )
+        .await
+        .unwrap();
+        submit(create("doc-1", 600).build()).await.unwrap();
+
+        let too_large = exceeded(submit(create("doc-2", 500).build()).await);
+        assert_eq!(too_large.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            (
+                too_large.scope.as_str(),
+                too_large.used,
+                too_large.requested
+            ),
+            ("tenant", 600, 500)
+        );
+
+        // Shrinking a document frees its bytes
+        let shrink =
+            CommitBuilder::patch::<Document>("doc-1", json!({"url": "/uploads/upload-100"}))
+                .actor(alice);
+        submit(shrink.build()).await.unwrap();
+        submit(create("doc-2", 500).build()).await.unwrap();
+        let usage = usage_of(&state.storage, "user/alice@gemeente.nl")
+            .await
+            .unwrap();
+        assert_eq!(
+            usage,
+            Usage {
+                events: 4,
+                resources: 3,
+                document_bytes: 600
+            }
+        );
+
+        let too_many = exceeded(submit(create("doc-3", 1).build()).await);
+        assert_eq!(too_many.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(too_many.quota, Quota::Resources);
+
+        // Deleting one makes room again
+        submit(
+            CommitBuilder::delete::<Document>("doc-2")
+                .actor(alice)
+                .build(),
+        )
+        .await
+        .unwrap();
+        submit(create("doc-3", 1).build()).await.unwrap();
+
+        // Uploads count as soon as they are stored: 1201 bytes of them, over the tenant's 1000
+        let too_large = check_upload(&state.storage, &quotas, alice, 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((too_large.scope.as_str(), too_large.used), ("tenant", 1201));
+        let quotas = Quotas {
+            tenant: QuotaLimits {
+                document_bytes: Some(2000),
+                ..Default::default()
+            },
+            ..quotas
+        };
+        assert!(check_upload(&state.storage, &quotas, alice, 799)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(check_upload(&state.storage, &quotas, alice, 800)
+            .await
+            .unwrap()
+            .is_some());
+
+        // A commit can't be charged to someone else
+        let spoofed = submit(create("doc-4", 1).actor("bob@gemeente.nl").build()).await;
+        assert_eq!(spoofed.unwrap_err().status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            usage_of(&state.storage, "user/bob@gemeente.nl")
+                .await
+                .unwrap(),
+            Usage::default()
+        );
+
+        // Commits made over REST are checked the same way
+        let mut rest_pipeline = Pipeline::standard();
+        rest_pipeline.insert_after("authorization", Arc::new(QuotaProcessor { quotas }));
+        let rest = AppState {
+            pipeline: Arc::new(rest_pipeline),
+            ..state.clone()
+        };
+        let submit_commit = |commit: JSONCommit| {
+            let rest = rest.clone();
+            async move {
+                crate::handlers::submit_commit(&rest, &Default::default(), "issue-1", commit).await
+            }
+        };
+        assert_eq!(
+            submit_commit(create("doc-4", 1).build()).await.unwrap_err(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        let outsider = CommitBuilder::patch::<Issue>("issue-1", json!({"title": "Van mij"}))
+            .actor("mallory@evil.com")
+            .build();
+        assert_eq!(
+            submit_commit(outsider).await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+
+        let Json(report) = usage_handler(
+            State(state.clone()),
+            user(ADMIN),
+            Path("gemeente.nl".to_string()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.usage.resources, 3);
+        assert_eq!(report.usage.document_bytes, 101);
+        assert_eq!(report.users.len(), 1);
+        assert_eq!(report.users[0].user, alice);
+        // Another tenant sees none of it
+        let Json(other) = usage_handler(
+            State(state.clone()),
+            user(ADMIN),
+            Path("example.com".to_string()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(other.usage, Usage::default());
+        assert!(other.users.is_empty());
+        let forbidden = usage_handler(State(state), user(alice), Path("gemeente.nl".to_string()));
+        assert_eq!(forbidden.await.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+}
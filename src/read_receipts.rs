@@ -0,0 +1,217 @@
+//! Read receipts: per user and issue, the sequence of the last event the user has seen.
+//!
+//! `POST /issues/{id}/read` moves the marker forward. Listings (`/resources`, `/query`)
+//! report the number of unread events per issue, and notification emails mention how much
+//! is new since the recipient's last visit.
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::AuthUser;
+use crate::handlers::{check_access, AppState};
+use crate::storage::Storage;
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MarkReadParams {
+    /// Sequence of the last event seen; defaults to the issue's latest event
+    pub sequence: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReadReceipt {
+    pub issue_id: String,
+    /// The user's read marker after the update (`None` if the issue has no events)
+    pub sequence: Option<String>,
+    /// Events after the marker
+    pub unread: usize,
+}
+
+/// Normalize a sequence to the zero-padded storage key, so string comparison is numeric.
+fn sequence_key(sequence: &str) -> Option<String> {
+    sequence.parse::<u128>().ok().map(|n| format!("{:020}", n))
+}
+
+/// Number of unread events for `user` on `resource`, if it is an issue they are involved in.
+pub(crate) async fn unread_for(
+    storage: &Storage,
+    user: &str,
+    id: &str,
+    resource: &Value,
+) -> Option<usize> {
+    let involved = resource.get("involved")?.as_array()?;
+    if !involved.iter().any(|p| p.as_str() == Some(user)) {
+        return None;
+    }
+    match storage.unread_count(user, id).await {
+        Ok(count) => Some(count),
+        Err(e) => {
+            eprintln!("[read] failed to count unread events of {}: {}", id, e);
+            None
+        }
+    }
+}
+
+/// POST /issues/{id}/read - Mark an issue as read up to a sequence
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/read",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID"), MarkReadParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The updated read marker", body = ReadReceipt),
+        (status = 400, description = "Malformed sequence"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn mark_read_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+    Query(params): Query<MarkReadParams>,
+) -> Result<Json<ReadReceipt>, StatusCode> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("[read] failed to mark {} as read: {}", issue_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let user = &auth_user.user_id;
+
+    state
+        .storage
+        .get_resource(&issue_id)
+        .await
+        .map_err(internal)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if !check_access(&state.storage, user, &issue_id).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let sequence = match params.sequence.as_deref() {
+        Some(sequence) => Some(sequence_key(sequence).ok_or(StatusCode::BAD_REQUEST)?),
+        None => state
+            .storage
+            .latest_subject_sequence(&issue_id)
+            .await
+            .map_err(internal)?,
+    };
+    let Some(sequence) = sequence else {
+        return Ok(Json(ReadReceipt {
+            issue_id,
+            sequence: None,
+            unread: 0,
+        }));
+    };
+
+    let marker = state
+        .storage
+        .mark_read(user, &issue_id, &sequence)
+        .await
+        .map_err(internal)?;
+    let unread = state
+        .storage
+        .count_subject_events_after(&issue_id, Some(&marker))
+        .await
+        .map_err(internal)?;
+
+    Ok(Json(ReadReceipt {
+        issue_id,
+        sequence: Some(marker),
+        unread,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user, create_issue, issue, submit_commit_event, test_state};
+    use crate::handlers::{list_resources, ListParams};
+    use crate::schemas::{Comment, CommitBuilder};
+    use axum::http::HeaderMap;
+
+    #[tokio::test]
+    async fn test_read_markers_and_unread_counts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+
+        let issue = issue("Verhuizing doorgeven", &[alice]);
+        let first_seq = create_issue(&state, "issue-1", &issue, alice)
+            .await
+            .sequence;
+        let comment = Comment {
+            content: "Nieuw adres ontvangen".to_string(),
+            quote_comment: None,
+            mentions: None,
+            edited_at: None,
+            internal: None,
+        };
+        let post = |id: &str| CommitBuilder::create(id, &comment).build();
+        submit_commit_event(&state, "issue-1", &post("c1"))
+            .await
+            .unwrap();
+        assert_eq!(
+            state.storage.unread_count(alice, "issue-1").await.unwrap(),
+            2
+        );
+
+        let mark_as = |user: &str, sequence: Option<String>| {
+            mark_read_handler(
+                State(state.clone()),
+                auth_user(user),
+                Path("issue-1".to_string()),
+                Query(MarkReadParams { sequence }),
+            )
+        };
+        let mark = |sequence: Option<String>| mark_as(alice, sequence);
+        assert_eq!(
+            mark_as("mallory@example.com", None).await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        let Json(receipt) = mark(None).await.unwrap();
+        assert_eq!(receipt.unread, 0);
+        let marker = receipt.sequence.unwrap();
+
+        // Markers never move back
+        let Json(receipt) = mark(first_seq.map(|s| s.trim_start_matches('0').to_string()))
+            .await
+            .unwrap();
+        assert_eq!(receipt.sequence.as_deref(), Some(marker.as_str()));
+        assert_eq!(
+            mark(Some("not-a-sequence".to_string())).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+
+        submit_commit_event(&state, "issue-1", &post("c2"))
+            .await
+            .unwrap();
+
+        let response = list_resources(
+            State(state.clone()),
+            Some(auth_user(alice)),
+            HeaderMap::new(),
+            Query(ListParams {
+                offset: 0,
+                limit: 100,
+                tag: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let listing: Vec<Value> = serde_json::from_slice(&body).unwrap();
+        let issue = listing.iter().find(|r| r["id"] == "issue-1").unwrap();
+        assert_eq!(issue["unread"], 1);
+        let comment = listing.iter().find(|r| r["id"] == "c1").unwrap();
+        assert!(comment.get("unread").is_none());
+    }
+}
@@ -0,0 +1,299 @@
+//! Recurring tasks: periodic obligations like the jaarlijkse controle of a permit.
+//!
+//! A Task with a `recurrence` rule (a subset of RFC 5545 RRULE: `FREQ`, `INTERVAL`, `COUNT`
+//! and `UNTIL`) is followed up when it is completed: the `recurrence` step of the event
+//! pipeline creates the next Task in the same issue with a "system" commit, with the deadline
+//! moved one period on from the completed one (or from the day of completion, without
+//! deadline). `COUNT` is the number of occurrences left, so the next task gets one less.
+//! The next task's ID is derived from the series and its deadline, so running the step again
+//! (e.g. replaying the changelog after a crash) does not create it twice.
+use async_trait::async_trait;
+use chrono::{Months, NaiveDate};
+
+use crate::handlers::{submit_event, AppState};
+use crate::pipeline::{EventContext, EventProcessor};
+use crate::schemas::{CloudEventBuilder, CommitBuilder, Task};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A parsed recurrence rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub interval: u32,
+    /// Occurrences left, including the current one
+    pub count: Option<u32>,
+    /// No occurrences after this date
+    pub until: Option<NaiveDate>,
+}
+
+impl Recurrence {
+    /// Parse `FREQ=MONTHLY;INTERVAL=3;COUNT=4`, optionally prefixed with `RRULE:`.
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        let rule = rule.trim();
+        let rule = rule.strip_prefix("RRULE:").unwrap_or(rule);
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut count = None;
+        let mut until = None;
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid rule part {:?}", part))?;
+            let number = || {
+                value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("invalid {} {:?}", name, value))
+            };
+            match name.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(format!("unsupported FREQ {:?}", value)),
+                    })
+                }
+                "INTERVAL" => interval = number()?,
+                "COUNT" => count = Some(number()?),
+                "UNTIL" => {
+                    let date = value.get(..8).unwrap_or(value);
+                    until = Some(
+                        NaiveDate::parse_from_str(date, "%Y%m%d")
+                            .map_err(|_| format!("invalid UNTIL {:?}", value))?,
+                    );
+                }
+                _ => return Err(format!("unsupported rule part {:?}", name)),
+            }
+        }
+        Ok(Self {
+            frequency: frequency.ok_or("FREQ is missing")?,
+            interval,
+            count,
+            until,
+        })
+    }
+
+    /// The rule as text, in the order `parse` reads it.
+    pub fn to_rule(&self) -> String {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        let mut rule = format!("FREQ={}", frequency);
+        if self.interval != 1 {
+            rule.push_str(&format!(";INTERVAL={}", self.interval));
+        }
+        if let Some(count) = self.count {
+            rule.push_str(&format!(";COUNT={}", count));
+        }
+        if let Some(until) = self.until {
+            rule.push_str(&format!(";UNTIL={}", until.format("%Y%m%d")));
+        }
+        rule
+    }
+
+    /// The date one period after `date`. Months that are too short end on their last day.
+    pub fn step(&self, date: NaiveDate) -> Option<NaiveDate> {
+        match self.frequency {
+            Frequency::Daily => date.checked_add_days(chrono::Days::new(self.interval.into())),
+            Frequency::Weekly => {
+                date.checked_add_days(chrono::Days::new(7 * u64::from(self.interval)))
+            }
+            Frequency::Monthly => date.checked_add_months(Months::new(self.interval)),
+            Frequency::Yearly => date.checked_add_months(Months::new(12 * self.interval)),
+        }
+    }
+
+    /// The deadline and rule of the occurrence after one due at `date`, if there is one.
+    pub fn next(&self, date: NaiveDate) -> Option<(NaiveDate, Recurrence)> {
+        if self.count.is_some_and(|count| count <= 1) {
+            return None;
+        }
+        let next = self.step(date)?;
+        if self.until.is_some_and(|until| next > until) {
+            return None;
+        }
+        let rule = Recurrence {
+            count: self.count.map(|count| count - 1),
+            ..self.clone()
+        };
+        Some((next, rule))
+    }
+}
+
+/// ID of the occurrence of the series of `task_id` due at `date`.
+pub fn occurrence_id(task_id: &str, date: NaiveDate) -> String {
+    // Tasks of a series share the part before the date suffix
+    let series = match task_id.rsplit_once('-') {
+        Some((series, suffix))
+            if suffix.len() == 8 && suffix.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            series
+        }
+        _ => task_id,
+    };
+    format!("{}-{}", series, date.format("%Y%m%d"))
+}
+
+/// The task following `task` once it is completed on `today`, with its ID.
+pub fn next_task(task_id: &str, task: &Task, today: NaiveDate) -> Option<(String, Task)> {
+    let rule = match Recurrence::parse(task.recurrence.as_deref()?) {
+        Ok(rule) => rule,
+        Err(e) => {
+            eprintln!("[recurrence] task {} has an invalid rule: {}", task_id, e);
+            return None;
+        }
+    };
+    let due = task
+        .deadline
+        .as_deref()
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        .unwrap_or(today);
+    let (deadline, rule) = rule.next(due)?;
+    let next = Task {
+        completed: false,
+        deadline: Some(deadline.format("%Y-%m-%d").to_string()),
+        recurrence: Some(rule.to_rule()),
+        ..task.clone()
+    };
+    Some((occurrence_id(task_id, deadline), next))
+}
+
+/// Creates the next occurrence of recurring tasks that are completed.
+pub struct RecurrenceProcessor;
+
+#[async_trait]
+impl EventProcessor for RecurrenceProcessor {
+    fn name(&self) -> &'static str {
+        "recurrence"
+    }
+
+    async fn committed(
+        &self,
+        state: &AppState,
+        ctx: &EventContext,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(change) = ctx.change.as_ref().and_then(|c| c.resource.as_ref()) else {
+            return Ok(());
+        };
+        if change.resource_type != "Task" {
+            return Ok(());
+        }
+        let completed = |v: &Option<serde_json::Value>| {
+            v.as_ref()
+                .and_then(|v| v.get("completed")?.as_bool())
+                .unwrap_or(false)
+        };
+        if completed(&change.old) || !completed(&change.new) {
+            return Ok(());
+        }
+        let Some(Ok(task)) = change.new.clone().map(serde_json::from_value::<Task>) else {
+            return Ok(());
+        };
+        let Some((id, next)) = next_task(&change.id, &task, chrono::Utc::now().date_naive()) else {
+            return Ok(());
+        };
+        if state.storage.get_resource(&id).await?.is_some() {
+            return Ok(());
+        }
+        let commit = CommitBuilder::create(id, &next).build();
+        submit_event(
+            state,
+            CloudEventBuilder::commit(ctx.event.subject.clone(), &commit).build(),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{create_issue, issue, submit_commit_event, test_state};
+    use serde_json::json;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_step() {
+        let rule = Recurrence::parse("RRULE:FREQ=MONTHLY;INTERVAL=3;COUNT=2").unwrap();
+        assert_eq!(rule.to_rule(), "FREQ=MONTHLY;INTERVAL=3;COUNT=2");
+        let (next, rule) = rule.next(date("2024-11-30")).unwrap();
+        assert_eq!(next, date("2025-02-28"));
+        assert_eq!(rule.count, Some(1));
+        assert_eq!(rule.next(next), None);
+
+        let rule = Recurrence::parse("FREQ=WEEKLY;UNTIL=20240110T000000Z").unwrap();
+        assert_eq!(rule.next(date("2024-01-01")).unwrap().0, date("2024-01-08"));
+        assert_eq!(rule.next(date("2024-01-08")), None);
+
+        assert!(Recurrence::parse("FREQ=HOURLY").is_err());
+        assert!(Recurrence::parse("FREQ=DAILY;BYDAY=MO").is_err());
+        assert!(Recurrence::parse("INTERVAL=2").is_err());
+        assert_eq!(
+            occurrence_id("task-controle-20240101", date("2025-01-01")),
+            "task-controle-20250101"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_completing_spawns_next() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        create_issue(&state, "issue-1", &issue("Kapvergunning", &[alice]), alice).await;
+        let task: Task = serde_json::from_value(json!({
+            "cta": "Jaarlijkse controle",
+            "description": "",
+            "url": "",
+            "completed": false,
+            "deadline": "2024-03-01",
+            "assignee": alice,
+            "recurrence": "FREQ=YEARLY;COUNT=2",
+        }))
+        .unwrap();
+        let commit = CommitBuilder::create("task-controle", &task).build();
+        submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+
+        let complete = |id: &str| {
+            let commit = CommitBuilder::patch::<Task>(id, json!({"completed": true})).build();
+            let state = state.clone();
+            async move { submit_commit_event(&state, "issue-1", &commit).await }
+        };
+        complete("task-controle").await.unwrap();
+        let next = state
+            .storage
+            .get_resource("task-controle-20250301")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(next["completed"], false);
+        assert_eq!(next["assignee"], alice);
+        assert_eq!(next["recurrence"], "FREQ=YEARLY;COUNT=1");
+
+        // The last occurrence has no successor
+        complete("task-controle-20250301").await.unwrap();
+        assert!(state
+            .storage
+            .get_resource("task-controle-20260301")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
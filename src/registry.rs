@@ -0,0 +1,524 @@
+//! Event schema registry: versioned JSON Schemas per CloudEvent type.
+//!
+//! Downstream consumers of `/events` look up the schema of an event type here. Registering
+//! a new version runs a compatibility check against the latest version:
+//! - `backward`: consumers using the new schema can read events written with the old one;
+//! - `forward`: consumers still using the old schema can read events written with the new one;
+//! - `full`: both; `none`: no check.
+//!
+//! Breaking versions are refused (409, with the incompatibilities) unless `force` is set.
+//! The check covers the JSON Schema parts the generated schemas use: `type`, `enum`,
+//! `properties`, `required`, `additionalProperties`, `items` and `anyOf`/`oneOf`. The built-in
+//! `json.commit` type is registered as version 1 on startup. New versions are registered
+//! by admins (see `auth::is_admin`).
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{internal_error, AppState};
+
+/// Event type of commits, registered with the `JSONCommit` schema
+pub const COMMIT_EVENT_TYPE: &str = "json.commit";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CompatibilityMode {
+    #[default]
+    Backward,
+    Forward,
+    Full,
+    None,
+}
+
+/// A registered schema version
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SchemaVersion {
+    pub event_type: String,
+    pub version: u32,
+    #[schema(value_type = Object)]
+    pub schema: Value,
+    pub compatibility: CompatibilityMode,
+    /// Registered despite incompatibilities
+    #[serde(default)]
+    pub forced: bool,
+    pub registered_by: String,
+    pub registered_at: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RegistryEntry {
+    pub event_type: String,
+    pub latest_version: u32,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RegisterRequest {
+    #[schema(value_type = Object)]
+    pub schema: Value,
+    /// Defaults to `backward`
+    #[serde(default)]
+    pub compatibility: CompatibilityMode,
+    /// Register even when the check finds incompatibilities
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CompatibilityReport {
+    pub compatible: bool,
+    /// Version checked against, if any was registered
+    pub against_version: Option<u32>,
+    pub incompatibilities: Vec<String>,
+}
+
+/// The types a schema allows; `None` allows anything
+fn types(schema: &Value) -> Option<Vec<&str>> {
+    match schema.get("type")? {
+        Value::String(t) => Some(vec![t.as_str()]),
+        Value::Array(ts) => Some(ts.iter().filter_map(|t| t.as_str()).collect()),
+        _ => None,
+    }
+}
+
+fn accepts_type(reader: &[&str], written: &str) -> bool {
+    reader.contains(&written) || (written == "integer" && reader.contains(&"number"))
+}
+
+/// The alternatives of a schema: its `anyOf` / `oneOf`, or the schema itself
+fn variants(schema: &Value) -> Vec<&Value> {
+    match schema.get("anyOf").or_else(|| schema.get("oneOf")) {
+        Some(Value::Array(alternatives)) => alternatives.iter().collect(),
+        _ => vec![schema],
+    }
+}
+
+fn is_alternation(schema: &Value) -> bool {
+    schema.get("anyOf").is_some() || schema.get("oneOf").is_some()
+}
+
+/// Collect why data valid against `writer` might not be valid against `reader`.
+fn check_readable(writer: &Value, reader: &Value, path: &str, problems: &mut Vec<String>) {
+    if is_alternation(writer) || is_alternation(reader) {
+        for (i, written) in variants(writer).into_iter().enumerate() {
+            let readable = variants(reader).into_iter().any(|candidate| {
+                let mut found = Vec::new();
+                check_readable(written, candidate, path, &mut found);
+                found.is_empty()
+            });
+            if !readable {
+                problems.push(format!(
+                    "{}: alternative {} is no longer accepted",
+                    path,
+                    i + 1
+                ));
+            }
+        }
+        return;
+    }
+    if let (Some(w), Some(r)) = (writer.get("$ref"), reader.get("$ref")) {
+        if w != r {
+            problems.push(format!("{}: reference changed from {} to {}", path, w, r));
+        }
+        return;
+    }
+
+    if let Some(reader_types) = types(reader) {
+        match types(writer) {
+            Some(writer_types) => {
+                for t in writer_types {
+                    if !accepts_type(&reader_types, t) {
+                        problems.push(format!("{}: type {} is not accepted", path, t));
+                    }
+                }
+            }
+            None => problems.push(format!(
+                "{}: any type was allowed, now {:?}",
+                path, reader_types
+            )),
+        }
+    }
+    if let Some(Value::Array(allowed)) = reader.get("enum") {
+        match writer.get("enum") {
+            Some(Value::Array(written)) => {
+                for value in written.iter().filter(|v| !allowed.contains(v)) {
+                    problems.push(format!("{}: value {} is not accepted", path, value));
+                }
+            }
+            _ => problems.push(format!(
+                "{}: values are now restricted to {:?}",
+                path, allowed
+            )),
+        }
+    }
+
+    let required = |schema: &Value| -> Vec<String> {
+        schema
+            .get("required")
+            .and_then(|r| r.as_array())
+            .map(|r| {
+                r.iter()
+                    .filter_map(|v| v.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let writer_required = required(writer);
+    for field in required(reader) {
+        if !writer_required.contains(&field) {
+            problems.push(format!("{}.{}: required, but may be missing", path, field));
+        }
+    }
+    let reader_properties = reader.get("properties").and_then(|p| p.as_object());
+    let closed = reader.get("additionalProperties") == Some(&Value::Bool(false));
+    if let Some(writer_properties) = writer.get("properties").and_then(|p| p.as_object()) {
+        for (name, written) in writer_properties {
+            match reader_properties.and_then(|p| p.get(name)) {
+                Some(read) => {
+                    check_readable(written, read, &format!("{}.{}", path, name), problems)
+                }
+                None if closed => {
+                    problems.push(format!("{}.{}: property is no longer allowed", path, name))
+                }
+                None => {}
+            }
+        }
+    }
+    if let (Some(written), Some(read)) = (writer.get("items"), reader.get("items")) {
+        check_readable(written, read, &format!("{}[]", path), problems);
+    }
+}
+
+/// The incompatibilities of `new` with `old` under `mode`.
+pub fn incompatibilities(old: &Value, new: &Value, mode: CompatibilityMode) -> Vec<String> {
+    let mut problems = Vec::new();
+    if matches!(mode, CompatibilityMode::Backward | CompatibilityMode::Full) {
+        check_readable(old, new, "backward: $", &mut problems);
+    }
+    if matches!(mode, CompatibilityMode::Forward | CompatibilityMode::Full) {
+        check_readable(new, old, "forward: $", &mut problems);
+    }
+    problems
+}
+
+async fn versions(
+    state: &AppState,
+    event_type: &str,
+) -> Result<Vec<SchemaVersion>, Box<dyn std::error::Error + Send + Sync>> {
+    state
+        .storage
+        .list_schema_versions(event_type)
+        .await?
+        .iter()
+        .map(|record| serde_json::from_str(record).map_err(Into::into))
+        .collect()
+}
+
+/// Register the built-in `json.commit` schema as version 1, if nothing is registered yet.
+pub async fn register_builtin(
+    state: &AppState,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !versions(state, COMMIT_EVENT_TYPE).await?.is_empty() {
+        return Ok(());
+    }
+    let record = SchemaVersion {
+        event_type: COMMIT_EVENT_TYPE.to_string(),
+        version: 1,
+        schema: crate::schemas::get_schema("JSONCommit").unwrap_or_default(),
+        compatibility: CompatibilityMode::Backward,
+        forced: false,
+        registered_by: "system".to_string(),
+        registered_at: chrono::Utc::now().to_rfc3339(),
+    };
+    state
+        .storage
+        .put_schema_version(COMMIT_EVENT_TYPE, 1, &serde_json::to_string(&record)?)
+        .await
+}
+
+fn check(latest: Option<&SchemaVersion>, request: &RegisterRequest) -> CompatibilityReport {
+    let incompatibilities = latest
+        .map(|latest| incompatibilities(&latest.schema, &request.schema, request.compatibility))
+        .unwrap_or_default();
+    CompatibilityReport {
+        compatible: incompatibilities.is_empty(),
+        against_version: latest.map(|l| l.version),
+        incompatibilities,
+    }
+}
+
+/// GET /registry - Event types with registered schemas
+#[utoipa::path(
+    get,
+    path = "/registry",
+    tag = "schemas",
+    responses((status = 200, description = "Event types and their latest schema version", body = Vec<RegistryEntry>))
+)]
+pub async fn list_event_types_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<RegistryEntry>>, StatusCode> {
+    let mut entries = Vec::new();
+    for event_type in state
+        .storage
+        .list_schema_event_types()
+        .await
+        .map_err(internal_error)?
+    {
+        if let Some(latest) = versions(&state, &event_type)
+            .await
+            .map_err(internal_error)?
+            .last()
+        {
+            entries.push(RegistryEntry {
+                event_type,
+                latest_version: latest.version,
+            });
+        }
+    }
+    Ok(Json(entries))
+}
+
+/// GET /registry/{event_type} - All schema versions of an event type
+#[utoipa::path(
+    get,
+    path = "/registry/{event_type}",
+    tag = "schemas",
+    params(("event_type" = String, Path, description = "CloudEvent type, e.g. \"json.commit\"")),
+    responses(
+        (status = 200, description = "Schema versions, oldest first", body = Vec<SchemaVersion>),
+        (status = 404, description = "No schema registered for this event type"),
+    )
+)]
+pub async fn list_versions_handler(
+    State(state): State<AppState>,
+    Path(event_type): Path<String>,
+) -> Result<Json<Vec<SchemaVersion>>, StatusCode> {
+    let versions = versions(&state, &event_type)
+        .await
+        .map_err(internal_error)?;
+    if versions.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(versions))
+}
+
+/// GET /registry/{event_type}/{version} - One schema version of an event type
+#[utoipa::path(
+    get,
+    path = "/registry/{event_type}/{version}",
+    tag = "schemas",
+    params(
+        ("event_type" = String, Path, description = "CloudEvent type"),
+        ("version" = u32, Path, description = "Schema version"),
+    ),
+    responses(
+        (status = 200, description = "The schema version", body = SchemaVersion),
+        (status = 404, description = "Unknown event type or version"),
+    )
+)]
+pub async fn get_version_handler(
+    State(state): State<AppState>,
+    Path((event_type, version)): Path<(String, u32)>,
+) -> Result<Json<SchemaVersion>, StatusCode> {
+    versions(&state, &event_type)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .find(|v| v.version == version)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// POST /registry/{event_type}/check - Check a schema against the latest version
+#[utoipa::path(
+    post,
+    path = "/registry/{event_type}/check",
+    tag = "schemas",
+    params(("event_type" = String, Path, description = "CloudEvent type")),
+    request_body = RegisterRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Result of the compatibility check", body = CompatibilityReport),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn check_schema_handler(
+    State(state): State<AppState>,
+    _auth_user: AuthUser,
+    Path(event_type): Path<String>,
+    Json(request): Json<RegisterRequest>,
+) -> Result<Json<CompatibilityReport>, StatusCode> {
+    let versions = versions(&state, &event_type)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(check(versions.last(), &request)))
+}
+
+/// POST /registry/{event_type} - Register a new schema version
+#[utoipa::path(
+    post,
+    path = "/registry/{event_type}",
+    tag = "schemas",
+    params(("event_type" = String, Path, description = "CloudEvent type")),
+    request_body = RegisterRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "Schema version registered", body = SchemaVersion),
+        (status = 400, description = "The schema is not a JSON object"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+        (status = 409, description = "Breaking change; register with `force` to accept it", body = CompatibilityReport),
+    )
+)]
+pub async fn register_schema_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(event_type): Path<String>,
+    Json(request): Json<RegisterRequest>,
+) -> Result<Response, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if !request.schema.is_object() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let versions = versions(&state, &event_type)
+        .await
+        .map_err(internal_error)?;
+    let report = check(versions.last(), &request);
+    if !report.compatible && !request.force {
+        return Ok((StatusCode::CONFLICT, Json(report)).into_response());
+    }
+
+    let record = SchemaVersion {
+        version: versions.last().map_or(1, |v| v.version + 1),
+        event_type,
+        schema: request.schema,
+        compatibility: request.compatibility,
+        forced: !report.compatible,
+        registered_by: auth_user.user_id,
+        registered_at: chrono::Utc::now().to_rfc3339(),
+    };
+    let json = serde_json::to_string(&record).map_err(internal_error)?;
+    state
+        .storage
+        .put_schema_version(&record.event_type, record.version, &json)
+        .await
+        .map_err(internal_error)?;
+    Ok((StatusCode::CREATED, Json(record)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user, test_state, ADMIN};
+    use serde_json::json;
+
+    fn melding_v1() -> Value {
+        json!({
+            "type": "object",
+            "required": ["zaak", "status"],
+            "properties": {
+                "zaak": { "type": "string" },
+                "status": { "type": "string", "enum": ["open", "closed"] },
+                "bedrag": { "type": "integer" },
+                "opmerking": { "type": ["string", "null"] }
+            }
+        })
+    }
+
+    #[test]
+    fn test_compatible_changes() {
+        let mut v2 = melding_v1();
+        // A new optional field, a wider number type and an extra enum value
+        v2["properties"]["bijlage"] = json!({ "type": "string" });
+        v2["properties"]["bedrag"] = json!({ "type": "number" });
+        v2["properties"]["status"]["enum"] = json!(["open", "closed", "archived"]);
+        assert!(incompatibilities(&melding_v1(), &v2, CompatibilityMode::Backward).is_empty());
+
+        // Old consumers don't know "archived", so this is not forward compatible
+        let forward = incompatibilities(&melding_v1(), &v2, CompatibilityMode::Forward);
+        assert!(
+            forward.iter().any(|p| p.contains("archived")),
+            "{:?}",
+            forward
+        );
+    }
+
+    #[test]
+    fn test_breaking_changes() {
+        let mut v2 = melding_v1();
+        v2["required"] = json!(["zaak", "status", "bijlage"]);
+        v2["properties"]["opmerking"] = json!({ "type": "string" });
+        let problems = incompatibilities(&melding_v1(), &v2, CompatibilityMode::Backward);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems.iter().any(|p| p.contains("bijlage: required")));
+        assert!(problems.iter().any(|p| p.contains("opmerking: type null")));
+        assert!(incompatibilities(&melding_v1(), &v2, CompatibilityMode::None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_register_refuses_breaking_versions() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        register_builtin(&state).await.unwrap();
+        let Json(builtin) = list_versions_handler(State(state.clone()), Path("json.commit".into()))
+            .await
+            .unwrap();
+        assert_eq!(builtin.len(), 1);
+
+        // Dropping a required field of JSONCommit breaks forward compatibility
+        let mut schema = builtin[0].schema.clone();
+        schema["required"] = json!(["schema", "resource_id"]);
+        let request = |force: bool| RegisterRequest {
+            schema: schema.clone(),
+            compatibility: CompatibilityMode::Full,
+            force,
+        };
+        let developer = "dev@gemeente.nl";
+        let Json(report) = check_schema_handler(
+            State(state.clone()),
+            auth_user(developer),
+            Path("json.commit".into()),
+            Json(request(false)),
+        )
+        .await
+        .unwrap();
+        assert!(!report.compatible);
+        assert_eq!(report.against_version, Some(1));
+        assert!(report.incompatibilities.iter().any(|p| p.contains("actor")));
+
+        // Only admins register, and breaking versions only when forced
+        let register = |user: &str, force: bool| {
+            register_schema_handler(
+                State(state.clone()),
+                auth_user(user),
+                Path("json.commit".into()),
+                Json(request(force)),
+            )
+        };
+        assert_eq!(
+            register(developer, true).await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            register(ADMIN, false).await.unwrap().status(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            register(ADMIN, true).await.unwrap().status(),
+            StatusCode::CREATED
+        );
+        let Json(version) = get_version_handler(State(state), Path(("json.commit".into(), 2)))
+            .await
+            .unwrap();
+        assert!(version.forced);
+        assert_eq!(version.registered_by, ADMIN);
+    }
+}
@@ -0,0 +1,353 @@
+//! Links between issues (zaken): parent/child, duplicate-of, related-to and blocks.
+//!
+//! Relations are ordinary resources (`Relation`), created and deleted with JSONCommits on
+//! the source issue, like comment reactions. `process_event` indexes every relation under
+//! both issues; `GET /issues/{id}/relations` and `GET /resources/{id}` resolve that index
+//! to the linked issues the caller has access to.
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{authorize_issue, check_access, submit_commit, AppState};
+use crate::schemas::{CloudEvent, CommitBuilder, Relation, RelationType};
+use crate::storage::Storage;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RelationRequest {
+    pub relation_type: RelationType,
+    /// The issue to link to
+    pub target_id: String,
+}
+
+/// Which end of a relation an issue is
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkDirection {
+    /// The issue is the relation's source ("this issue blocks the other")
+    Outgoing,
+    /// The issue is the relation's target ("this issue is blocked by the other")
+    Incoming,
+}
+
+/// A relation as seen from one of the issues it links
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IssueLink {
+    pub relation_id: String,
+    pub relation_type: RelationType,
+    pub direction: LinkDirection,
+    /// The other issue
+    pub issue_id: String,
+    /// Title of the other issue, for navigation
+    pub title: Option<String>,
+}
+
+fn relation_type_name(relation_type: RelationType) -> String {
+    serde_json::to_value(relation_type)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Resource ID of a relation. Deterministic, so linking twice is a no-op. `related_to` is
+/// symmetric: both directions map to the same ID.
+pub fn relation_id(source_id: &str, relation_type: RelationType, target_id: &str) -> String {
+    let (a, b) = if relation_type == RelationType::RelatedTo && target_id < source_id {
+        (target_id, source_id)
+    } else {
+        (source_id, target_id)
+    };
+    format!("relation-{}-{}-{}", a, relation_type_name(relation_type), b)
+}
+
+/// The relations of `issue_id`, limited to linked issues `user` has access to.
+pub async fn issue_links(
+    storage: &Storage,
+    user: &str,
+    issue_id: &str,
+) -> Result<Vec<IssueLink>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut links = Vec::new();
+    for id in storage.list_issue_relations(issue_id).await? {
+        // Unlinked relations are deleted resources
+        let Some(value) = storage.get_resource(&id).await? else {
+            continue;
+        };
+        let relation: Relation = match serde_json::from_value(value) {
+            Ok(relation) => relation,
+            Err(e) => {
+                eprintln!("[relations] skipping malformed relation {}: {}", id, e);
+                continue;
+            }
+        };
+        let (direction, other) = if relation.source_id == issue_id {
+            (LinkDirection::Outgoing, relation.target_id)
+        } else {
+            (LinkDirection::Incoming, relation.source_id)
+        };
+        if !check_access(storage, user, &other).await {
+            continue;
+        }
+        let title = storage
+            .get_resource(&other)
+            .await?
+            .and_then(|issue| issue.get("title")?.as_str().map(str::to_string));
+        links.push(IssueLink {
+            relation_id: id,
+            relation_type: relation.relation_type,
+            direction,
+            issue_id: other,
+            title,
+        });
+    }
+    Ok(links)
+}
+
+/// GET /issues/{id}/relations - Issues linked to an issue
+#[utoipa::path(
+    get,
+    path = "/issues/{id}/relations",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Linked issues the caller has access to", body = [IssueLink]),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn list_relations_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+) -> Result<Json<Vec<IssueLink>>, StatusCode> {
+    authorize_issue(&state, &auth_user.user_id, &issue_id).await?;
+    let links = issue_links(&state.storage, &auth_user.user_id, &issue_id)
+        .await
+        .map_err(|e| {
+            eprintln!(
+                "[relations] failed to list relations of {}: {}",
+                issue_id, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(links))
+}
+
+/// POST /issues/{id}/relations - Link an issue to another issue
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/relations",
+    tag = "resources",
+    params(("id" = String, Path, description = "Source issue ID")),
+    request_body = RelationRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Relation commit stored, processed and broadcast", body = CloudEvent),
+        (status = 204, description = "The issues are already linked this way"),
+        (status = 400, description = "An issue cannot be linked to itself"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in both issues"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn link_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(issue_id): Path<String>,
+    Json(request): Json<RelationRequest>,
+) -> Result<Response, StatusCode> {
+    if request.target_id == issue_id {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let user = auth_user.user_id;
+    authorize_issue(&state, &user, &issue_id).await?;
+    authorize_issue(&state, &user, &request.target_id).await?;
+
+    let id = relation_id(&issue_id, request.relation_type, &request.target_id);
+    let existing = state.storage.get_resource(&id).await.map_err(|e| {
+        eprintln!("[relations] failed to get relation {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if existing.is_some() {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    let relation = Relation {
+        relation_type: request.relation_type,
+        source_id: issue_id.clone(),
+        target_id: request.target_id,
+    };
+    let commit = CommitBuilder::create(id, &relation).actor(user).build();
+    submit_commit(&state, &headers, &issue_id, commit).await
+}
+
+/// DELETE /issues/{id}/relations/{relation_id} - Remove a link from either end
+#[utoipa::path(
+    delete,
+    path = "/issues/{id}/relations/{relation_id}",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Issue ID (source or target of the relation)"),
+        ("relation_id" = String, Path, description = "Relation ID, as listed in the issue's relations"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Delete commit stored, processed and broadcast", body = CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue, or no such relation on it"),
+    )
+)]
+pub async fn unlink_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path((issue_id, relation_id)): Path<(String, String)>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    authorize_issue(&state, &user, &issue_id).await?;
+
+    let relation: Relation = state
+        .storage
+        .get_resource(&relation_id)
+        .await
+        .map_err(|e| {
+            eprintln!("[relations] failed to get relation {}: {}", relation_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .and_then(|value| serde_json::from_value(value).ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if relation.source_id != issue_id && relation.target_id != issue_id {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let commit = CommitBuilder::delete::<Relation>(relation_id)
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &relation.source_id, commit).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state};
+
+    #[test]
+    fn test_related_to_is_symmetric() {
+        assert_eq!(
+            relation_id("issue-a", RelationType::RelatedTo, "issue-b"),
+            relation_id("issue-b", RelationType::RelatedTo, "issue-a")
+        );
+        assert_ne!(
+            relation_id("issue-a", RelationType::Blocks, "issue-b"),
+            relation_id("issue-b", RelationType::Blocks, "issue-a")
+        );
+        assert_eq!(
+            relation_id("issue-a", RelationType::ParentOf, "issue-b"),
+            "relation-issue-a-parent_of-issue-b"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_link_list_and_unlink() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+
+        let bob = "bob@gemeente.nl";
+        for (id, title, involved) in [
+            ("verhuizing", "Verhuizing doorgeven", alice),
+            ("parkeren", "Parkeervergunning aanvragen", alice),
+            ("geheim", "Andere zaak", bob),
+        ] {
+            create_issue(&state, id, &issue(title, &[involved]), involved).await;
+        }
+
+        let link = |target: &str| {
+            link_handler(
+                State(state.clone()),
+                user(alice),
+                HeaderMap::new(),
+                Path("verhuizing".to_string()),
+                Json(RelationRequest {
+                    relation_type: RelationType::ParentOf,
+                    target_id: target.to_string(),
+                }),
+            )
+        };
+        assert_eq!(
+            link("parkeren").await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            link("parkeren").await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            link("verhuizing").await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(link("geheim").await.unwrap_err(), StatusCode::FORBIDDEN);
+        assert_eq!(link("onbekend").await.unwrap_err(), StatusCode::NOT_FOUND);
+
+        // The follow-up sees the link from the other end
+        let list = |who: &str| {
+            list_relations_handler(
+                State(state.clone()),
+                user(who),
+                Path("parkeren".to_string()),
+            )
+        };
+        assert_eq!(list(bob).await.unwrap_err(), StatusCode::FORBIDDEN);
+        let Json(links) = list(alice).await.unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].direction, LinkDirection::Incoming);
+        assert_eq!(links[0].issue_id, "verhuizing");
+        assert_eq!(links[0].title.as_deref(), Some("Verhuizing doorgeven"));
+
+        // Issue reads include the relations
+        let Json(issue) = crate::handlers::get_resource(
+            State(state.clone()),
+            Some(user(alice)),
+            Path("verhuizing".to_string()),
+            axum::extract::Query(Default::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(issue["relations"][0]["issue_id"], "parkeren");
+        assert_eq!(issue["relations"][0]["direction"], "outgoing");
+
+        let unlink = |who: &str, relation_id: &str| {
+            unlink_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Path(("parkeren".to_string(), relation_id.to_string())),
+            )
+        };
+        let relation_id = links[0].relation_id.as_str();
+        assert_eq!(
+            unlink(bob, relation_id).await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            unlink(alice, "relation-onbekend").await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            unlink(alice, relation_id).await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        assert!(issue_links(&state.storage, alice, "verhuizing")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+//! Resource types: which type of resource a commit's schema URL describes.
+//!
+//! The built-in types are those of the `schemas` module; a schema URL resolves to one when
+//! its last path segment is the type's name (`https://zaakchat.nl/schemas/Issue`, also with
+//! another host or a `.json` suffix). Other schemas (e.g. of an integration) are registered at
+//! runtime with `PUT /resource-types`; an exact registration wins over the built-in names.
+//!
+//! The event pipeline stores the resolved type on the resource record, so readers use the
+//! stored type (`Storage::get_resource_type`) instead of guessing from the data.
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{internal_error, AppState};
+use crate::schemas::schema_url;
+use crate::storage::Storage;
+
+/// The type of resources whose schema is not known
+pub const UNKNOWN: &str = "unknown";
+
+/// The resource types defined in `schemas`
+pub const BUILTIN_TYPES: &[&str] = &[
+    "Issue",
+    "Comment",
+    "Reaction",
+    "Relation",
+    "Label",
+    "Board",
+    "Profile",
+    "Team",
+    "Availability",
+    "Connector",
+    "Invite",
+    "EscalationPolicy",
+    "Escalation",
+    "Zaaktype",
+    "Form",
+    "Checklist",
+    "SigningRequest",
+    "Task",
+    "Planning",
+    "Document",
+];
+
+/// The built-in type named by the last path segment of `schema`.
+pub fn builtin(schema: &str) -> Option<&'static str> {
+    let name = schema
+        .split(['#', '?'])
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    let name = name.strip_suffix(".json").unwrap_or(name);
+    BUILTIN_TYPES.iter().copied().find(|t| *t == name)
+}
+
+/// The resource type of `schema`: a runtime registration, else a built-in type, else
+/// [`UNKNOWN`].
+pub async fn resolve(
+    storage: &Storage,
+    schema: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(resource_type) = storage.get_resource_type_for_schema(schema).await? {
+        return Ok(resource_type);
+    }
+    Ok(builtin(schema).unwrap_or(UNKNOWN).to_string())
+}
+
+/// The name of a resource type in listings: "EscalationPolicy" is "escalation_policy".
+pub fn api_name(resource_type: &str) -> String {
+    let mut name = String::new();
+    for (i, c) in resource_type.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            name.push('_');
+        }
+        name.extend(c.to_lowercase());
+    }
+    name
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ResourceTypeEntry {
+    pub schema_url: String,
+    pub resource_type: String,
+    /// Defined in `schemas` (not registered at runtime)
+    #[serde(default)]
+    pub builtin: bool,
+}
+
+/// GET /resource-types - The known schema URLs and their resource types
+#[utoipa::path(
+    get,
+    path = "/resource-types",
+    tag = "schemas",
+    responses(
+        (status = 200, description = "Built-in types, then the runtime registrations", body = Vec<ResourceTypeEntry>),
+    )
+)]
+pub async fn list_resource_types_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ResourceTypeEntry>>, StatusCode> {
+    let mut entries: Vec<ResourceTypeEntry> = BUILTIN_TYPES
+        .iter()
+        .map(|name| ResourceTypeEntry {
+            schema_url: schema_url(name),
+            resource_type: name.to_string(),
+            builtin: true,
+        })
+        .collect();
+    let registered = state
+        .storage
+        .list_resource_types()
+        .await
+        .map_err(internal_error)?;
+    entries.extend(
+        registered
+            .into_iter()
+            .map(|(schema_url, resource_type)| ResourceTypeEntry {
+                schema_url,
+                resource_type,
+                builtin: false,
+            }),
+    );
+    Ok(Json(entries))
+}
+
+/// PUT /resource-types - Register the resource type of a schema URL
+#[utoipa::path(
+    put,
+    path = "/resource-types",
+    tag = "schemas",
+    request_body = ResourceTypeEntry,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Registered", body = ResourceTypeEntry),
+        (status = 400, description = "Empty schema URL or type"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+    )
+)]
+pub async fn register_resource_type_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(entry): Json<ResourceTypeEntry>,
+) -> Result<Json<ResourceTypeEntry>, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if entry.schema_url.trim().is_empty() || entry.resource_type.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    state
+        .storage
+        .put_resource_type(&entry.schema_url, &entry.resource_type)
+        .await
+        .map_err(internal_error)?;
+    println!(
+        "[resource-types] {} registered {} as {}",
+        auth_user.user_id, entry.schema_url, entry.resource_type
+    );
+    Ok(Json(ResourceTypeEntry {
+        builtin: false,
+        ..entry
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_types() {
+        assert_eq!(
+            builtin("https://zaakchat.nl/schemas/Issue.json"),
+            Some("Issue")
+        );
+        assert_eq!(builtin("https://other.com/schemas/Task"), Some("Task"));
+        assert_eq!(
+            builtin("http://localhost:8000/schemas/EscalationPolicy"),
+            Some("EscalationPolicy")
+        );
+        // Names, not substrings
+        assert_eq!(builtin("https://example.com/schemas/IssueTemplate"), None);
+        assert_eq!(builtin("new issue created"), None);
+        assert_eq!(api_name("EscalationPolicy"), "escalation_policy");
+        assert_eq!(api_name("Issue"), "issue");
+    }
+
+    #[tokio::test]
+    async fn test_runtime_registration() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let storage = Storage::new(dir.path()).await.unwrap();
+        let zaak = "https://catalogi.example.com/zaaktypen/melding.json";
+        assert_eq!(resolve(&storage, zaak).await.unwrap(), UNKNOWN);
+        storage.put_resource_type(zaak, "Issue").await.unwrap();
+        assert_eq!(resolve(&storage, zaak).await.unwrap(), "Issue");
+        assert_eq!(
+            resolve(&storage, &schema_url("Comment")).await.unwrap(),
+            "Comment"
+        );
+    }
+}
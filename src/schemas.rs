@@ -1,4 +1,9 @@
-use axum::{extract::Path, http::StatusCode, Json};
+use axum::{
+    extract::Path,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
 use schemars::{schema_for, JsonSchema};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -21,6 +26,10 @@ pub struct CloudEvent {
     /// Tijdstip waarop de gebeurtenis plaatsvond (ISO 8601 formaat)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub time: Option<String>,
+    /// Tijdstip waarop de server de gebeurtenis ontving (ISO 8601 formaat). Wordt altijd door
+    /// de server gezet; een waarde van de client wordt overschreven
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_received: Option<String>,
     /// Formaat van de data (meestal "application/json")
     #[serde(skip_serializing_if = "Option::is_none")]
     pub datacontenttype: Option<String>,
@@ -33,7 +42,10 @@ pub struct CloudEvent {
     /// Volgnummer voor het ordenen van gebeurtenissen
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sequence: Option<String>,
-    /// Type van de volgnummering die gebruikt wordt
+    /// Volgnummer als getal, gelijk aan `sequence` zonder voorloopnullen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequencenumber: Option<u64>,
+    /// Type van de volgnummering die gebruikt wordt (altijd "Integer" bij opgeslagen gebeurtenissen)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sequencetype: Option<String>,
     /// De inhoud van de eigenlijke gebeurtenis.
@@ -42,6 +54,17 @@ pub struct CloudEvent {
     pub data: Option<Value>,
 }
 
+impl CloudEvent {
+    /// Attach the server-assigned sequence: the zero-padded key (`sequence`), the same
+    /// number as `sequencenumber`, and `sequencetype: "Integer"`.
+    pub fn set_sequence(&mut self, seq_key: impl Into<String>) {
+        let seq_key = seq_key.into();
+        self.sequencenumber = seq_key.parse().ok();
+        self.sequence = Some(seq_key);
+        self.sequencetype = Some("Integer".to_string());
+    }
+}
+
 /// JSONCommit - Een commit van wijzigingen aan een JSON resource
 ///
 /// Dit event type vertegenwoordigt elke wijziging aan een JSON resource, of het nu gaat om:
@@ -66,12 +89,55 @@ pub struct JSONCommit {
     /// JSON Merge Patch (RFC 7396) met wijzigingen (bij updates).
     /// Velden met een null waarde worden verwijderd.
     /// Alle andere velden worden bijgewerkt / overgeschreven.
+    /// Met `patch_type: "json-patch"` is dit een lijst JSON Patch (RFC 6902) operaties.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub patch: Option<Value>,
+    /// Soort patch: "merge-patch" (standaard) of "json-patch"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patch_type: Option<PatchType>,
     /// Markeert de resource als verwijderd (bij verwijderingen).
     /// De resource (en de gerelateerde events) moeten dan uit de store verwijderd worden.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub deleted: Option<bool>,
+    /// Sequencenummer van de versie van de resource waarop deze wijziging gebaseerd is.
+    /// Zijn de gewijzigde velden sindsdien door een andere commit aangepast, dan wordt de
+    /// commit geweigerd (409) met een overzicht van de conflicterende velden.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_sequence: Option<u64>,
+    /// Gemaakt zonder verbinding (bijv. in het veld). Samen met `base_sequence` worden alleen
+    /// de velden die deze commit ten opzichte van die versie wijzigt toegepast, zodat
+    /// wijzigingen van anderen in de tussentijd behouden blijven.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offline: Option<bool>,
+}
+
+/// Soort patch in een commit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum PatchType {
+    /// JSON Merge Patch (RFC 7396): een object met de gewijzigde velden
+    #[default]
+    MergePatch,
+    /// JSON Patch (RFC 6902): een lijst operaties (add, remove, replace, move, copy, test),
+    /// waarmee ook losse elementen van een lijst gewijzigd kunnen worden
+    JsonPatch,
+}
+
+impl JSONCommit {
+    /// Is `patch` a JSON Patch?
+    pub fn is_json_patch(&self) -> bool {
+        self.patch_type == Some(PatchType::JsonPatch)
+    }
+
+    /// The patch as a merge patch. For a JSON Patch these are only its changes to top-level
+    /// fields (see `json_patch::top_level_changes`).
+    pub fn merge_patch(&self) -> Option<Value> {
+        let patch = self.patch.as_ref()?;
+        Some(match self.is_json_patch() {
+            true => crate::json_patch::top_level_changes(patch),
+            false => patch.clone(),
+        })
+    }
 }
 
 /// Soorten items in het zaaksysteem
@@ -102,7 +168,7 @@ pub struct Document {
 }
 
 /// Zaak - een burgerzaak of aanvraag die door de gemeente behandeld wordt
-#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct Issue {
     /// Korte, duidelijke titel van de zaak (bijv. "Paspoort aanvragen", "Kapvergunning Dorpsstraat 12")
     pub title: String,
@@ -114,11 +180,25 @@ pub struct Issue {
     /// Email van de ambtenaar die de zaak behandelt (bijv. "alice@gemeente.nl")
     #[serde(skip_serializing_if = "Option::is_none")]
     pub assignee: Option<String>,
+    /// ID van het team dat de zaak behandelt, als de zaak aan een team in plaats van
+    /// een persoon is toegewezen (bijv. "team-gemeente.nl-handhaving")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub resolution: Option<String>,
     /// Lijst van betrokken personen (emails) bij deze zaak
     #[serde(skip_serializing_if = "Option::is_none")]
     pub involved: Option<Vec<String>>,
+    /// Namen van de labels waarmee de zaak is getagd (bijv. ["spoed", "wonen"])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// IDs van open zaken die sterk op deze lijken, bij het aanmaken automatisch gesignaleerd.
+    /// Een behandelaar kan de dubbele melding samenvoegen of de signalering negeren
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub possible_duplicates: Option<Vec<String>>,
+    /// Plaats waar de melding over gaat, bij meldingen openbare ruimte
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
 }
 
 /// Taak - een actie die uitgevoerd moet worden om een zaak te behandelen
@@ -135,13 +215,22 @@ pub struct Task {
     /// Uiterste datum voor voltooiing (YYYY-MM-DD, bijv. "2024-01-25")
     #[serde(skip_serializing_if = "Option::is_none")]
     pub deadline: Option<String>,
+    /// Email van degene die de taak moet uitvoeren (bijv. de burger die documenten aanlevert)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assignee: Option<String>,
+    /// Herhaling als RRULE (bijv. "FREQ=YEARLY" voor een jaarlijkse controle). Als de taak
+    /// voltooid is, wordt de volgende aangemaakt. Ondersteund: FREQ (DAILY, WEEKLY, MONTHLY,
+    /// YEARLY), INTERVAL, COUNT en UNTIL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<String>,
 }
 
 /// Status van een zaak in behandeling
-#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum IssueStatus {
     /// Nieuw binnengekomen, nog niet in behandeling genomen
+    #[default]
     Open,
     /// Wordt momenteel behandeld door een ambtenaar
     #[serde(rename = "in_progress")]
@@ -161,6 +250,444 @@ pub struct Comment {
     /// Email adressen van collega's die specifiek genoemd worden (bijv. "@alice@gemeente.nl")
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mentions: Option<Vec<String>>,
+    /// Tijdstip van de laatste bewerking (ISO 8601). Indien aanwezig wordt de reactie als "bewerkt" getoond;
+    /// eerdere versies blijven in de event log bewaard
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<String>,
+    /// Interne notitie tussen medewerkers: niet zichtbaar in het burgerportaal en niet gemaild
+    /// naar betrokkenen buiten de organisatie
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub internal: Option<bool>,
+}
+
+/// Emoji-reactie van een gebruiker op een reactie (comment), als lichte bevestiging
+/// zonder formeel antwoord (bijv. "👍" op "Documenten zijn ingestuurd")
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Reaction {
+    /// De emoji (bijv. "👍", "✅")
+    pub emoji: String,
+    /// Email van de persoon die reageert
+    pub actor: String,
+    /// ID van de reactie (comment) waarop gereageerd wordt
+    pub comment_id: String,
+}
+
+/// Label - een etiket waarmee zaken gegroepeerd en gefilterd worden (bijv. "spoed", "wonen").
+/// Zaken verwijzen in hun `tags` naar de naam van het label
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Label {
+    /// Naam van het label, uniek binnen de organisatie
+    pub name: String,
+    /// Kleur in hexadecimale notatie (bijv. "#e5484d")
+    pub color: String,
+    /// Organisatie waartoe het label behoort: het e-maildomein van de medewerkers (bijv. "gemeente.nl")
+    pub tenant: String,
+}
+
+/// Kanbanbord - de gedeelde indeling van zaken in kolommen voor een team
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Board {
+    /// Team waarvoor het bord is (het e-maildomein van de medewerkers, bijv. "gemeente.nl")
+    pub team: String,
+    /// Kolommen in weergavevolgorde, van links naar rechts
+    pub columns: Vec<BoardColumn>,
+}
+
+/// Kolom op een kanbanbord
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BoardColumn {
+    /// Vaste sleutel van de kolom (bijv. "open", "in_progress")
+    pub id: String,
+    /// Kop van de kolom zoals getoond (bijv. "In behandeling")
+    pub title: String,
+    /// IDs van de zaken in deze kolom, van boven naar beneden. Zaken die nergens staan
+    /// worden onderaan de kolom van hun status getoond
+    #[serde(default)]
+    pub cards: Vec<String>,
+}
+
+/// Beschikbaarheid - of een medewerker afwezig is en wie het werk dan overneemt
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Availability {
+    /// Email van de medewerker
+    pub user: String,
+    /// Is de medewerker afwezig (bijv. met vakantie of ziek)?
+    pub away: bool,
+    /// Tot wanneer de afwezigheid duurt (RFC 3339); zonder einddatum tot het weer wordt uitgezet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<String>,
+    /// Email van de collega die nieuwe toewijzingen en spoedmeldingen overneemt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delegate: Option<String>,
+}
+
+/// Team - een groep medewerkers die samen zaken behandelt, binnen een afdeling
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Team {
+    /// Naam van het team, uniek binnen de organisatie (bijv. "Handhaving")
+    pub name: String,
+    /// Organisatie waartoe het team behoort: het e-maildomein van de medewerkers (bijv. "gemeente.nl")
+    pub tenant: String,
+    /// Afdeling waar het team onder valt (bijv. "Publiekszaken")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub department: Option<String>,
+    /// Emails van de teamleden
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+/// Gebruikersprofiel - hoe een medewerker of inwoner getoond wordt, in plaats van het kale e-mailadres
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Profile {
+    /// E-mailadres van de gebruiker
+    pub email: String,
+    /// Naam zoals getoond in gesprekken en bij @-vermeldingen (bijv. "Alice de Vries")
+    pub display_name: String,
+    /// Afdeling van de medewerker (bijv. "Vergunningen")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub department: Option<String>,
+}
+
+/// Escalatiebeleid - wat er gebeurt als een zaak van een organisatie te lang stil ligt
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EscalationPolicy {
+    /// Organisatie waarvoor het beleid geldt: het e-maildomein van de medewerkers (bijv. "gemeente.nl")
+    pub tenant: String,
+    /// Stappen, oplopend in wachttijd
+    pub steps: Vec<EscalationStep>,
+}
+
+/// Eén stap in een escalatiebeleid, bijv. "na 48 uur zonder reactie de teamleider waarschuwen"
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EscalationStep {
+    /// Aantal uren zonder activiteit op de zaak waarna de stap wordt uitgevoerd
+    pub after_hours: u64,
+    pub action: EscalationType,
+    /// Email van wie gewaarschuwd wordt of aan wie de zaak wordt toegewezen. Bij herverdelen
+    /// zonder ontvanger krijgt de best passende beschikbare behandelaar de zaak
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+/// Wat een escalatiestap doet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EscalationType {
+    /// Stuur een e-mail aan de ontvanger (bijv. de teamleider)
+    Notify,
+    /// Wijs de zaak toe aan een andere behandelaar
+    Reassign,
+}
+
+/// Escalatie - een uitgevoerde escalatiestap, zichtbaar in de tijdlijn van de zaak
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Escalation {
+    /// ID van de zaak die te lang stil lag
+    pub issue_id: String,
+    /// Positie van de stap in het escalatiebeleid (0 = eerste stap)
+    pub step: usize,
+    pub action: EscalationType,
+    /// Wie gewaarschuwd is of de zaak heeft gekregen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Aantal uren dat de zaak zonder activiteit was
+    pub idle_hours: u64,
+}
+
+/// Sjabloon voor een afvinklijst (bijv. "Documentcheck" met de stukken die aanwezig moeten zijn)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ChecklistTemplate {
+    /// Naam van de afvinklijst
+    pub title: String,
+    /// De punten, in volgorde
+    pub items: Vec<String>,
+}
+
+/// Afvinklijst - routinematige controlestappen bij een zaak, lichter dan taken
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Checklist {
+    /// ID van de zaak waar de lijst bij hoort
+    pub issue_id: String,
+    /// Naam van de afvinklijst (bijv. "Documentcheck")
+    pub title: String,
+    /// De punten, in volgorde
+    pub items: Vec<ChecklistItem>,
+}
+
+/// Eén punt van een afvinklijst
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ChecklistItem {
+    /// Wat er gecontroleerd moet worden (bijv. "Pasfoto voldoet aan de eisen")
+    pub text: String,
+    /// Of het punt is afgevinkt
+    #[serde(default)]
+    pub done: bool,
+    /// Email van wie het punt het laatst heeft afgevinkt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub done_by: Option<String>,
+}
+
+/// Ondertekenverzoek - een document dat via een ondertekendienst getekend moet worden
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SigningRequest {
+    /// ID van de zaak waar het document bij hoort
+    pub issue_id: String,
+    /// ID van het Document dat getekend wordt
+    pub document_id: String,
+    /// Emails van de ondertekenaars, in de volgorde waarin ze tekenen
+    pub signers: Vec<String>,
+    /// De ondertekendienst (bijv. "docusign")
+    pub provider: String,
+    /// Kenmerk van het verzoek bij de ondertekendienst
+    pub reference: String,
+    /// Stand van het ondertekenen
+    pub status: SigningStatus,
+    /// Moment waarop het document getekend, geweigerd of ingetrokken is (RFC 3339)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<String>,
+}
+
+/// Stand van een ondertekenverzoek
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SigningStatus {
+    /// Verstuurd naar de ondertekenaars
+    Sent,
+    /// Door iedereen getekend; het Document is vervangen door de getekende versie
+    Signed,
+    /// Een ondertekenaar heeft geweigerd te tekenen
+    Declined,
+    /// Ingetrokken of verlopen bij de ondertekendienst
+    Voided,
+}
+
+/// Formulier - de velden die een inwoner of medewerker invult bij een aanvraag of melding
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Form {
+    /// Naam van het formulier (bijv. "Melding evenement")
+    pub title: String,
+    /// Velden, in de volgorde waarin ze getoond worden
+    pub fields: Vec<FormField>,
+}
+
+/// Eén veld van een formulier
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FormField {
+    /// Sleutel van het veld in de ingevulde gegevens (bijv. "bezoekers")
+    pub key: String,
+    /// Label zoals getoond bij het veld (bijv. "Verwacht aantal bezoekers")
+    pub label: String,
+    #[serde(rename = "type")]
+    pub field_type: FormFieldType,
+    /// Of het veld ingevuld moet zijn
+    #[serde(default)]
+    pub required: bool,
+    /// Toegestane waarden bij een keuzeveld
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<Vec<String>>,
+    /// Kleinste toegestane waarde bij een getal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// Grootste toegestane waarde bij een getal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// Maximaal aantal tekens bij tekst
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    /// Reguliere expressie waar tekst aan moet voldoen (bijv. een postcode)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// JSON Schema van een eigen veld, waar de waarde ook aan moet voldoen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Value>,
+}
+
+/// Soort invoer van een formulierveld
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FormFieldType {
+    Text,
+    Number,
+    /// Datum als "JJJJ-MM-DD"
+    Date,
+    Email,
+    Boolean,
+    /// Eén van de `options`
+    Choice,
+    /// Alleen gecontroleerd met het `schema` van het veld
+    Custom,
+}
+
+/// Zaaktype - het proces dat zaken van een soort doorlopen (bijv. "Paspoort aanvragen")
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Zaaktype {
+    /// Naam van het zaaktype (bijv. "Paspoort aanvragen")
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Fasen van het proces; de eerste is de beginfase
+    pub states: Vec<ZaaktypeState>,
+    /// Toegestane overgangen tussen fasen
+    #[serde(default)]
+    pub transitions: Vec<ZaaktypeTransition>,
+    /// Sjablonen voor afvinklijsten bij zaken van dit type
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checklists: Vec<ChecklistTemplate>,
+}
+
+/// Eén fase in het proces van een zaaktype
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ZaaktypeState {
+    /// Sleutel van de fase, waar overgangen naar verwijzen (bijv. "beoordeling")
+    pub key: String,
+    /// Naam van de fase zoals de inwoner die ziet (bijv. "Aanvraag wordt beoordeeld")
+    pub title: String,
+    /// Uitleg voor de inwoner: wat er in deze fase gebeurt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Rol die verantwoordelijk is voor de fase (bijv. "behandelaar", "teamleider")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    /// Maximaal aantal uren dat een zaak in deze fase mag zijn
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sla_hours: Option<u64>,
+}
+
+/// Overgang van de ene fase naar de andere (bijv. "goedkeuren": beoordeling -> afgerond)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ZaaktypeTransition {
+    /// Sleutel van de fase waaruit de overgang vertrekt
+    pub from: String,
+    /// Sleutel van de fase waar de overgang naartoe leidt
+    pub to: String,
+    /// Naam van de overgang (bijv. "Goedkeuren")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Rol die de overgang mag uitvoeren
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+}
+
+/// Uitnodiging - een externe betrokkene die per email toegang tot een zaak krijgt
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Invite {
+    /// ID van de zaak waarvoor is uitgenodigd
+    pub issue_id: String,
+    /// Email van de uitgenodigde
+    pub email: String,
+    /// Email van de behandelaar die de uitnodiging verstuurde
+    pub invited_by: String,
+    pub status: InviteStatus,
+    /// Tot wanneer de uitnodiging geaccepteerd kan worden (RFC 3339)
+    pub expires_at: String,
+    /// Kenmerk van de link in de laatst verstuurde uitnodiging; eerdere links werken niet meer
+    pub token_id: String,
+}
+
+/// Status van een uitnodiging
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InviteStatus {
+    /// Verstuurd, nog niet geaccepteerd
+    Pending,
+    /// De uitgenodigde heeft de link gebruikt en is betrokken bij de zaak
+    Accepted,
+    /// Ingetrokken; de link werkt niet meer en de toegang is weggenomen
+    Revoked,
+}
+
+/// Connector - een uitgaande koppeling die meldingen als kaart in een Slack- of Teams-kanaal plaatst
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Connector {
+    /// Organisatie waartoe de connector behoort (bijv. "gemeente.nl")
+    pub tenant: String,
+    /// Naam om de connector te herkennen (bijv. "Teams-kanaal Handhaving")
+    pub name: String,
+    pub kind: ConnectorType,
+    /// Incoming webhook URL van het kanaal
+    pub webhook_url: String,
+    /// Gebeurtenissen waarvoor een kaart geplaatst wordt
+    pub triggers: Vec<ConnectorTriggerType>,
+    /// ID van het team: alleen zaken van dit team, en vermeldingen van dit team
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+}
+
+/// Chatplatform van een connector
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectorType {
+    Slack,
+    Teams,
+}
+
+/// Gebeurtenis waarvoor een connector een kaart plaatst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectorTriggerType {
+    /// Een nieuwe zaak met het label "spoed"
+    UrgentIssue,
+    /// Een escalatiestap omdat een zaak te lang zonder activiteit is
+    SlaBreach,
+    /// Een reactie waarin het team van de connector genoemd wordt (bijv. "@Handhaving")
+    TeamMention,
+}
+
+/// Locatie in de openbare ruimte (WGS84)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Location {
+    /// Breedtegraad (bijv. 52.3702)
+    pub lat: f64,
+    /// Lengtegraad (bijv. 4.8952)
+    pub lon: f64,
+    /// Adres of omschrijving van de plek (bijv. "Dam 1, Amsterdam")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+/// Soort koppeling tussen twee zaken, gelezen als "bronzaak <soort> doelzaak"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationType {
+    /// De bronzaak is de hoofdzaak, de doelzaak een deelzaak ervan
+    ParentOf,
+    /// De bronzaak is een dubbele melding van de doelzaak
+    DuplicateOf,
+    /// De zaken horen bij elkaar (bijv. een verhuizing en de parkeervergunning die daarop volgt)
+    RelatedTo,
+    /// De doelzaak kan pas verder als de bronzaak is afgerond
+    Blocks,
+}
+
+/// Relatie - een koppeling tussen twee zaken, zodat je van de ene naar de andere kunt navigeren
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Relation {
+    /// Soort koppeling
+    pub relation_type: RelationType,
+    /// ID van de bronzaak
+    pub source_id: String,
+    /// ID van de doelzaak
+    pub target_id: String,
+}
+
+/// Typ-indicator - iemand is een reactie aan het schrijven in een zaak. Vluchtig: wordt alleen
+/// live verstuurd (event type "chat.typing") en nooit opgeslagen
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TypingIndicator {
+    /// Email van de persoon die aan het typen is
+    pub actor: String,
+    /// Tijdstip (ISO 8601) waarop de indicator vervalt als er geen nieuwe binnenkomt
+    pub expires_at: String,
+}
+
+/// Aanwezigheid - wie heeft een zaak op dit moment open. Vluchtig: wordt alleen live verstuurd
+/// (event types "presence.joined" en "presence.left") en nooit opgeslagen
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Presence {
+    /// Email van de persoon die de zaak opent of verlaat
+    pub actor: String,
+    /// Emails van iedereen die de zaak na deze wijziging open heeft
+    pub viewers: Vec<String>,
 }
 
 /// Planning - een tijdlijn met verschillende stappen of fasen voor zaakbehandeling
@@ -200,6 +727,167 @@ pub enum PlanningStatus {
     Planned,
 }
 
+/// Base URL under which the schemas are served (`BASE_URL`, defaults to the dev server)
+pub fn schema_base_url() -> String {
+    std::env::var("BASE_URL").unwrap_or_else(|_| "http://localhost:8000".to_string())
+}
+
+/// Public URL of a schema, e.g. `http://localhost:8000/schemas/Issue`
+pub fn schema_url(name: &str) -> String {
+    format!("{}/schemas/{}", schema_base_url(), name)
+}
+
+/// Builder for CloudEvents with the required attributes filled in: specversion "1.0",
+/// a UUIDv7 id, the current time and source "zaakchat".
+pub struct CloudEventBuilder {
+    event: CloudEvent,
+}
+
+impl CloudEventBuilder {
+    pub fn new(event_type: impl Into<String>, subject: impl Into<String>) -> Self {
+        Self {
+            event: CloudEvent {
+                specversion: "1.0".to_string(),
+                id: uuid::Uuid::now_v7().to_string(),
+                source: "zaakchat".to_string(),
+                subject: subject.into(),
+                event_type: event_type.into(),
+                time: Some(chrono::Utc::now().to_rfc3339()),
+                time_received: None,
+                datacontenttype: None,
+                dataschema: None,
+                dataref: None,
+                sequence: None,
+                sequencenumber: None,
+                sequencetype: None,
+                data: None,
+            },
+        }
+    }
+
+    /// A `json.commit` event carrying `commit`. The subject is the thread the resource
+    /// belongs to (the issue ID), which for comments and tasks differs from `resource_id`.
+    pub fn commit(subject: impl Into<String>, commit: &JSONCommit) -> Self {
+        Self::new("json.commit", subject)
+            .dataschema(schema_url("JSONCommit"))
+            .data(serde_json::to_value(commit).expect("JSONCommit serializes to JSON"))
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.event.id = id.into();
+        self
+    }
+
+    pub fn source(mut self, source: impl Into<String>) -> Self {
+        self.event.source = source.into();
+        self
+    }
+
+    pub fn time(mut self, time: impl Into<String>) -> Self {
+        self.event.time = Some(time.into());
+        self
+    }
+
+    pub fn dataschema(mut self, dataschema: impl Into<String>) -> Self {
+        self.event.dataschema = Some(dataschema.into());
+        self
+    }
+
+    /// Set JSON `data` (and `datacontenttype: application/json`).
+    pub fn data(mut self, data: Value) -> Self {
+        self.event.datacontenttype = Some("application/json".to_string());
+        self.event.data = Some(data);
+        self
+    }
+
+    pub fn build(self) -> CloudEvent {
+        self.event
+    }
+}
+
+/// Builder for JSONCommits. The constructors pick the schema URL from the resource type,
+/// so `CommitBuilder::patch::<Issue>(..)` always points at `/schemas/Issue`.
+pub struct CommitBuilder {
+    commit: JSONCommit,
+}
+
+impl CommitBuilder {
+    fn new<T: JsonSchema>(resource_id: impl Into<String>) -> Self {
+        Self {
+            commit: JSONCommit {
+                schema: schema_url(&T::schema_name()),
+                resource_id: resource_id.into(),
+                actor: "system".to_string(),
+                timestamp: Some(chrono::Utc::now().to_rfc3339()),
+                resource_data: None,
+                patch: None,
+                patch_type: None,
+                deleted: None,
+                base_sequence: None,
+                offline: None,
+            },
+        }
+    }
+
+    /// Create a resource with its complete data.
+    pub fn create<T: JsonSchema + Serialize>(resource_id: impl Into<String>, resource: &T) -> Self {
+        let mut builder = Self::new::<T>(resource_id);
+        builder.commit.resource_data =
+            Some(serde_json::to_value(resource).expect("resource serializes to JSON"));
+        builder
+    }
+
+    /// Update a resource with a JSON Merge Patch (`null` removes a field).
+    pub fn patch<T: JsonSchema>(resource_id: impl Into<String>, patch: Value) -> Self {
+        let mut builder = Self::new::<T>(resource_id);
+        builder.commit.patch = Some(patch);
+        builder
+    }
+
+    /// Update a resource with JSON Patch operations (RFC 6902), e.g. to remove one element
+    /// from an array.
+    pub fn json_patch<T: JsonSchema>(resource_id: impl Into<String>, operations: Value) -> Self {
+        let mut builder = Self::new::<T>(resource_id);
+        builder.commit.patch = Some(operations);
+        builder.commit.patch_type = Some(PatchType::JsonPatch);
+        builder
+    }
+
+    /// Delete a resource.
+    pub fn delete<T: JsonSchema>(resource_id: impl Into<String>) -> Self {
+        let mut builder = Self::new::<T>(resource_id);
+        builder.commit.deleted = Some(true);
+        builder
+    }
+
+    /// Email of the person performing the change (defaults to "system").
+    pub fn actor(mut self, actor: impl Into<String>) -> Self {
+        self.commit.actor = actor.into();
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: impl Into<String>) -> Self {
+        self.commit.timestamp = Some(timestamp.into());
+        self
+    }
+
+    /// The sequence number of the version this change was made on (see `conflicts`).
+    pub fn base_sequence(mut self, sequence: u64) -> Self {
+        self.commit.base_sequence = Some(sequence);
+        self
+    }
+
+    /// Mark the change as made offline, on `base_sequence` (see `conflicts`).
+    pub fn offline(mut self) -> Self {
+        self.commit.offline = Some(true);
+        self
+    }
+
+    pub fn build(self) -> JSONCommit {
+        self.commit
+    }
+}
+
 /// Resolve schema references recursively
 fn resolve_schema_refs(mut schema: Value, all_schemas: &HashMap<String, Value>) -> Value {
     fn resolve_refs_recursive(value: &mut Value, schemas: &HashMap<String, Value>) {
@@ -221,7 +909,7 @@ fn resolve_schema_refs(mut schema: Value, all_schemas: &HashMap<String, Value>)
                 // Handle allOf with $ref patterns
                 if let Some(Value::Array(all_of_array)) = map.get_mut("allOf") {
                     if all_of_array.len() == 1 {
-                        if let Some(Value::Object(ref_obj)) = all_of_array.get(0) {
+                        if let Some(Value::Object(ref_obj)) = all_of_array.first() {
                             if let Some(ref_value) = ref_obj.get("$ref") {
                                 if let Some(ref_str) = ref_value.as_str() {
                                     if let Some(definition_name) =
@@ -317,8 +1005,39 @@ pub fn get_all_schemas() -> HashMap<String, Value> {
         Document,
         Issue,
         IssueStatus,
+        Location,
         Task,
         Comment,
+        Reaction,
+        Label,
+        Board,
+        BoardColumn,
+        Team,
+        Profile,
+        Availability,
+        EscalationPolicy,
+        EscalationStep,
+        Escalation,
+        Zaaktype,
+        ZaaktypeState,
+        ZaaktypeTransition,
+        ChecklistTemplate,
+        Checklist,
+        ChecklistItem,
+        SigningRequest,
+        SigningStatus,
+        Form,
+        FormField,
+        FormFieldType,
+        Invite,
+        InviteStatus,
+        Connector,
+        ConnectorType,
+        ConnectorTriggerType,
+        Relation,
+        RelationType,
+        TypingIndicator,
+        Presence,
         Planning,
         PlanningMoment,
         PlanningStatus
@@ -326,16 +1045,98 @@ pub fn get_all_schemas() -> HashMap<String, Value> {
 }
 
 /// Get all available schemas as an index
+#[utoipa::path(
+    get,
+    path = "/schemas",
+    tag = "schemas",
+    responses((status = 200, description = "Names of all available schemas", body = Value))
+)]
 pub async fn handle_get_schemas_index() -> Json<Value> {
     Json(get_schema_index())
 }
 
-/// Get a specific schema by name
-pub async fn handle_get_schema(Path(name): Path<String>) -> Result<Json<Value>, StatusCode> {
-    match get_schema(&name) {
-        Some(schema) => Ok(Json(schema)),
-        None => Err(StatusCode::NOT_FOUND),
+/// How long clients and caches may use a schema without revalidating; schemas only change
+/// with a deploy, and the ETag makes revalidation cheap
+const SCHEMA_MAX_AGE_SECS: u64 = 3600;
+
+/// Get a specific schema by name, as JSON or (with `Accept: application/yaml`) YAML
+#[utoipa::path(
+    get,
+    path = "/schemas/{name}",
+    tag = "schemas",
+    params(("name" = String, Path, description = "Schema name, e.g. \"Issue\"")),
+    responses(
+        (status = 200, description = "JSON Schema with its absolute `$id`; YAML with `Accept: application/yaml`", body = Value, content_type = "application/schema+json"),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match"),
+        (status = 404, description = "Unknown schema"),
+    )
+)]
+pub async fn handle_get_schema(
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let schema = get_schema(&name).ok_or(StatusCode::NOT_FOUND)?;
+    let schema = with_schema_id(&name, schema, &schema_base_url());
+    let yaml = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("yaml"));
+    let (body, content_type) = if yaml {
+        (
+            serde_yaml::to_string(&schema).map_err(|e| {
+                eprintln!("[schemas] failed to render {} as YAML: {}", name, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?,
+            "application/yaml",
+        )
+    } else {
+        (schema.to_string(), "application/schema+json")
+    };
+
+    let etag = {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::hash::DefaultHasher::new();
+        body.hash(&mut hasher);
+        format!("\"{:016x}\"", hasher.finish())
+    };
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (
+            header::CACHE_CONTROL,
+            format!("public, max-age={}", SCHEMA_MAX_AGE_SECS),
+        ),
+        (header::VARY, "Accept".to_string()),
+    ];
+    let unchanged = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+        });
+    if unchanged {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
     }
+    Ok((cache_headers, [(header::CONTENT_TYPE, content_type)], body).into_response())
+}
+
+/// `schema` as served at `{base_url}/schemas/{name}`: with that URL as `$id`, so it can be
+/// referenced and dereferenced, and the JSON Schema dialect in `$schema`.
+pub fn with_schema_id(name: &str, mut schema: Value, base_url: &str) -> Value {
+    if let Some(object) = schema.as_object_mut() {
+        object.insert(
+            "$id".to_string(),
+            json!(format!(
+                "{}/schemas/{}",
+                base_url.trim_end_matches('/'),
+                name
+            )),
+        );
+        object
+            .entry("$schema")
+            .or_insert_with(|| json!("http://json-schema.org/draft-07/schema#"));
+    }
+    schema
 }
 
 /// Get a specific schema by name
@@ -370,7 +1171,7 @@ mod tests {
         assert!(index.get("description").is_some());
 
         let schemas = index.get("schemas").unwrap().as_array().unwrap();
-        assert!(schemas.len() > 0);
+        assert!(!schemas.is_empty());
 
         // Check that key schemas are present
         let schema_names: Vec<String> = schemas
@@ -409,6 +1210,41 @@ mod tests {
         assert!(properties.contains_key("sequencetype"));
     }
 
+    #[test]
+    fn test_commit_builders() {
+        let issue = Issue {
+            title: "Paspoort aanvragen".to_string(),
+            status: IssueStatus::Open,
+            ..Default::default()
+        };
+        let create = CommitBuilder::create("issue-1", &issue)
+            .actor("alice@gemeente.nl")
+            .build();
+        assert!(create.schema.ends_with("/schemas/Issue"));
+        assert_eq!(create.resource_data.unwrap()["status"], "open");
+        assert!(create.patch.is_none() && create.deleted.is_none());
+
+        let patch = CommitBuilder::patch::<Issue>("issue-1", json!({"status": "closed"})).build();
+        assert_eq!(patch.patch.unwrap()["status"], "closed");
+        assert_eq!(patch.actor, "system");
+
+        let delete = CommitBuilder::delete::<Comment>("comment-1").build();
+        assert!(delete.schema.ends_with("/schemas/Comment"));
+        assert_eq!(delete.deleted, Some(true));
+
+        let event = CloudEventBuilder::commit("issue-1", &delete).build();
+        assert_eq!(event.specversion, "1.0");
+        assert_eq!(event.event_type, "json.commit");
+        assert_eq!(event.subject, "issue-1");
+        assert_eq!(event.datacontenttype.as_deref(), Some("application/json"));
+        assert!(event.dataschema.unwrap().ends_with("/schemas/JSONCommit"));
+        assert_eq!(event.data.unwrap()["resource_id"], "comment-1");
+        assert_eq!(
+            uuid::Uuid::parse_str(&event.id).unwrap().get_version_num(),
+            7
+        );
+    }
+
     #[test]
     fn test_get_nonexistent_schema() {
         let result = get_schema("NonExistentSchema");
@@ -610,15 +1446,24 @@ mod tests {
 #[tokio::test]
 async fn test_get_specific_schema_endpoint() {
     use axum::extract::Path;
-    // Call handler and unwrap Json wrapper
+    // Call handler and parse the body
     let path = Path("CloudEvent".to_string());
-    let json = handle_get_schema(path)
+    let response = handle_get_schema(path, HeaderMap::new())
         .await
         .expect("CloudEvent schema should exist");
-    let schema = json.0;
+    let etag = response.headers()[header::ETAG].clone();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let schema: Value = serde_json::from_slice(&body).unwrap();
 
     assert!(schema.is_object());
     assert!(schema.get("properties").is_some());
+    assert_eq!(
+        schema["$id"],
+        format!("{}/schemas/CloudEvent", schema_base_url())
+    );
+    assert!(schema.get("$schema").is_some());
 
     let properties = schema.get("properties").unwrap().as_object().unwrap();
     assert!(properties.contains_key("specversion"));
@@ -626,6 +1471,27 @@ async fn test_get_specific_schema_endpoint() {
     assert!(properties.contains_key("source"));
     assert!(properties.contains_key("dataschema"));
     assert!(properties.contains_key("dataref"));
+
+    // Revalidation with the ETag
+    let mut headers = HeaderMap::new();
+    headers.insert(header::IF_NONE_MATCH, etag);
+    let response = handle_get_schema(Path("CloudEvent".to_string()), headers)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // YAML on request
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ACCEPT, "application/yaml".parse().unwrap());
+    let response = handle_get_schema(Path("CloudEvent".to_string()), headers)
+        .await
+        .unwrap();
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/yaml");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let yaml: Value = serde_yaml::from_slice(&body).unwrap();
+    assert_eq!(yaml, schema);
 }
 
 #[tokio::test]
@@ -635,7 +1501,7 @@ async fn test_get_nonexistent_schema_endpoint() {
 
     // Test getting non-existent schema
     let path = Path("NonExistentSchema".to_string());
-    let result = handle_get_schema(path).await;
+    let result = handle_get_schema(path, HeaderMap::new()).await;
 
     assert!(result.is_err());
     let status = result.unwrap_err();
@@ -2,14 +2,15 @@ use std::{
     collections::BTreeMap,
     error::Error,
     path::{Path, PathBuf},
-    sync::Arc,
-    time::Duration,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use chrono::{DateTime, Utc};
 use serde_json::Value as JsonValue;
-use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::query::{QueryParser, TermQuery};
 use tantivy::schema::OwnedValue;
 use tantivy::schema::*;
 use tantivy::{doc, Index, IndexWriter, ReloadPolicy, TantivyDocument};
@@ -19,6 +20,103 @@ use tokio::task::JoinHandle;
 use crate::schemas::CloudEvent;
 use crate::storage::{SearchResult, Storage};
 
+/// Counters of the indexing work, to tell how far search is behind the event log
+#[derive(Debug, Default)]
+pub struct IndexStats {
+    /// Adds and deletes not committed yet
+    pending_ops: AtomicU64,
+    commits: AtomicU64,
+    commit_micros_total: AtomicU64,
+    commit_micros_last: AtomicU64,
+    commit_micros_max: AtomicU64,
+    failures: AtomicU64,
+    last_indexed_sequence: Mutex<Option<String>>,
+    last_failure: Mutex<Option<String>>,
+}
+
+/// A copy of the [`IndexStats`]
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, utoipa::ToSchema)]
+pub struct IndexStatsSnapshot {
+    pub pending_ops: u64,
+    pub commits: u64,
+    pub commit_ms_last: f64,
+    pub commit_ms_avg: f64,
+    pub commit_ms_max: f64,
+    /// Events whose indexing failed
+    pub failures: u64,
+    /// Highest sequence of the events indexed since the server started
+    pub last_indexed_sequence: Option<String>,
+    pub last_failure: Option<String>,
+}
+
+impl IndexStats {
+    fn record_op(&self) {
+        self.pending_ops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_commit(&self, took: Duration) {
+        let micros = took.as_micros() as u64;
+        self.pending_ops.store(0, Ordering::Relaxed);
+        self.commits.fetch_add(1, Ordering::Relaxed);
+        self.commit_micros_total
+            .fetch_add(micros, Ordering::Relaxed);
+        self.commit_micros_last.store(micros, Ordering::Relaxed);
+        self.commit_micros_max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// The event with `sequence` is indexed and committed
+    pub fn record_indexed(&self, sequence: Option<&str>) {
+        let Some(sequence) = sequence else {
+            return;
+        };
+        let mut last = self
+            .last_indexed_sequence
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        // Zero-padded, so the highest sorts last
+        if last.as_deref().is_none_or(|last| last < sequence) {
+            *last = Some(sequence.to_string());
+        }
+    }
+
+    pub fn record_failure(&self, error: String) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_failure.lock().unwrap_or_else(|e| e.into_inner()) = Some(error);
+    }
+
+    pub fn snapshot(&self) -> IndexStatsSnapshot {
+        let ms = |micros: u64| micros as f64 / 1000.0;
+        let commits = self.commits.load(Ordering::Relaxed);
+        let total = self.commit_micros_total.load(Ordering::Relaxed);
+        IndexStatsSnapshot {
+            pending_ops: self.pending_ops.load(Ordering::Relaxed),
+            commits,
+            commit_ms_last: ms(self.commit_micros_last.load(Ordering::Relaxed)),
+            commit_ms_avg: total.checked_div(commits).map_or(0.0, ms),
+            commit_ms_max: ms(self.commit_micros_max.load(Ordering::Relaxed)),
+            failures: self.failures.load(Ordering::Relaxed),
+            last_indexed_sequence: self
+                .last_indexed_sequence
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+            last_failure: self
+                .last_failure
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        }
+    }
+}
+
+/// Commit `writer`, timing it in `stats`.
+fn commit_writer(writer: &mut IndexWriter, stats: &IndexStats) -> tantivy::Result<u64> {
+    let started = Instant::now();
+    let opstamp = writer.commit()?;
+    stats.record_commit(started.elapsed());
+    Ok(opstamp)
+}
+
 /// SearchIndex manages the Tantivy index: initialization, background commits,
 /// and add/delete/search operations.
 ///
@@ -32,6 +130,8 @@ use crate::storage::{SearchResult, Storage};
 /// Note: this module intentionally depends on Tantivy; storage.rs does not.
 pub struct SearchIndex {
     index: Arc<Index>,
+    /// Shared for adds and deletes, which Tantivy's writer takes from many threads at once;
+    /// exclusive for commits
     writer: Arc<RwLock<IndexWriter>>,
     id_field: Field,
     type_field: Field,
@@ -39,6 +139,7 @@ pub struct SearchIndex {
     timestamp_field: Field,
     // Background commit task handle (optional)
     commit_task: Option<JoinHandle<()>>,
+    stats: Arc<IndexStats>,
 }
 
 impl SearchIndex {
@@ -88,17 +189,19 @@ impl SearchIndex {
             json_field,
             timestamp_field,
             commit_task: None,
+            stats: Arc::new(IndexStats::default()),
         };
 
         // Optionally spawn a periodic committer
         let commit_task = if spawn_committer {
             let writer_clone = si.writer.clone();
+            let stats = si.stats.clone();
             let interval = commit_interval;
             Some(tokio::spawn(async move {
                 loop {
                     tokio::time::sleep(interval).await;
                     let mut w = writer_clone.write().await;
-                    if let Err(e) = w.commit() {
+                    if let Err(e) = commit_writer(&mut w, &stats) {
                         eprintln!("[search][committer] commit failed: {}", e);
                     }
                 }
@@ -159,7 +262,7 @@ impl SearchIndex {
         payload_json: &str,
         timestamp: Option<DateTime<Utc>>,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let writer = self.writer.write().await;
+        let writer = self.writer.read().await;
         // Ensure we delete any existing document with this ID to prevent duplicates
         writer.delete_term(Term::from_field_text(self.id_field, id));
 
@@ -199,6 +302,7 @@ impl SearchIndex {
         }
 
         writer.add_document(doc)?;
+        self.stats.record_op();
         // commit deferred to periodic committer
         Ok(())
     }
@@ -230,7 +334,7 @@ impl SearchIndex {
         payload_json: &str,
         timestamp: Option<DateTime<Utc>>,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let writer = self.writer.write().await;
+        let writer = self.writer.read().await;
         // Ensure we delete any existing document with this ID to prevent duplicates
         writer.delete_term(Term::from_field_text(self.id_field, id));
 
@@ -263,19 +367,42 @@ impl SearchIndex {
             );
         }
 
-        println!("[search] DEBUG: adding doc {:?}", doc);
         writer.add_document(doc)?;
+        self.stats.record_op();
         Ok(())
     }
 
     /// Delete any indexed document that has the provided id (by term).
     /// This schedules a delete; the periodic committer will flush it.
     pub async fn delete_by_id(&self, id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let writer = self.writer.write().await;
+        let writer = self.writer.read().await;
         writer.delete_term(Term::from_field_text(self.id_field, id));
+        self.stats.record_op();
         Ok(())
     }
 
+    /// Counters of the indexing work
+    pub fn stats(&self) -> &IndexStats {
+        &self.stats
+    }
+
+    /// Number of indexed documents: events, and all documents (events and resources).
+    /// Counts what was committed.
+    pub fn doc_counts(&self) -> Result<(u64, u64), Box<dyn Error + Send + Sync>> {
+        let reader = self
+            .index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        let searcher = reader.searcher();
+        let events = TermQuery::new(
+            Term::from_field_text(self.type_field, "Event"),
+            IndexRecordOption::Basic,
+        );
+        let event_docs = searcher.search(&events, &Count)?;
+        Ok((event_docs as u64, searcher.num_docs()))
+    }
+
     /// Perform a search and return structured SearchResult rows.
     /// This hydrates the result by fetching event/resource data from the provided Storage.
     pub async fn search(
@@ -382,6 +509,7 @@ impl SearchIndex {
                 content,
                 event,
                 resource,
+                unread: None,
             });
         }
 
@@ -413,8 +541,7 @@ impl SearchIndex {
         // Acquire writer lock, call commit which returns the number of operations flushed (u64).
         // Map successful u64 result to () and map errors into a boxed error type.
         let mut writer = self.writer.write().await;
-        writer
-            .commit()
+        commit_writer(&mut writer, &self.stats)
             .map(|_n| ())
             .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)
     }
@@ -423,7 +550,7 @@ impl SearchIndex {
     pub async fn clear(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
         let mut writer = self.writer.write().await;
         writer.delete_all_documents()?;
-        writer.commit()?;
+        commit_writer(&mut writer, &self.stats)?;
         println!("[search] cleared all documents");
         Ok(())
     }
@@ -437,13 +564,24 @@ impl SearchIndex {
     /// Apply authorization filter to a query string.
     /// This injects a clause to restrict results to items where the user is involved.
     pub fn apply_authorization_filter(query: &str, user: &str) -> String {
+        Self::apply_scoped_authorization_filter(query, user, &[])
+    }
+
+    /// Like `apply_authorization_filter`, but also allowing items assigned to one of
+    /// `teams` (team IDs the user is a member of).
+    pub fn apply_scoped_authorization_filter(query: &str, user: &str, teams: &[String]) -> String {
         // We check two paths:
         // 1. json_payload.involved: For resources (Issues) that have the field directly.
         // 2. json_payload.data.resource_data.involved: For events (CloudEvents) where the involved field is inside the resource_data.
-        let user_filter = format!(
-            "(json_payload.involved:\"{}\" OR json_payload.data.resource_data.involved:\"{}\")",
-            user, user
-        );
+        let mut clauses = vec![
+            format!("json_payload.involved:\"{}\"", user),
+            format!("json_payload.data.resource_data.involved:\"{}\"", user),
+        ];
+        for team in teams {
+            clauses.push(format!("json_payload.team:\"{}\"", team));
+            clauses.push(format!("json_payload.data.resource_data.team:\"{}\"", team));
+        }
+        let user_filter = format!("({})", clauses.join(" OR "));
         if query.trim().is_empty() || query.trim() == "*" {
             user_filter
         } else {
@@ -452,6 +590,33 @@ impl SearchIndex {
     }
 }
 
+fn json_to_owned_value(v: &JsonValue) -> OwnedValue {
+    match v {
+        JsonValue::Null => OwnedValue::Null,
+        JsonValue::Bool(b) => OwnedValue::Bool(*b),
+        JsonValue::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                OwnedValue::I64(i)
+            } else if let Some(u) = n.as_u64() {
+                OwnedValue::U64(u)
+            } else if let Some(f) = n.as_f64() {
+                OwnedValue::F64(f)
+            } else {
+                OwnedValue::Null
+            }
+        }
+        JsonValue::String(s) => OwnedValue::Str(s.clone()),
+        JsonValue::Array(arr) => OwnedValue::Array(arr.iter().map(json_to_owned_value).collect()),
+        JsonValue::Object(obj) => {
+            let map: BTreeMap<String, OwnedValue> = obj
+                .iter()
+                .map(|(k, v)| (k.clone(), json_to_owned_value(v)))
+                .collect();
+            OwnedValue::Object(map)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -598,6 +763,23 @@ mod tests {
             "Should find issue with specific query and auth"
         );
 
+        // 6. Team scope: Bob sees issues assigned to his team
+        let team_issue = serde_json::json!({
+            "title": "Team issue",
+            "involved": ["alice@example.com"],
+            "team": "team-example.com-support"
+        });
+        index
+            .add_resource_doc("issue-2", "issue", &team_issue, None)
+            .await?;
+        index.commit().await?;
+        let teams = vec!["team-example.com-support".to_string()];
+        let query_team =
+            SearchIndex::apply_scoped_authorization_filter("*", "bob@example.com", &teams);
+        let results_team = index.search_best_effort(&storage, &query_team, 10).await;
+        assert!(results_team.iter().any(|r| r.id == "issue-2"));
+        assert!(!results_team.iter().any(|r| r.id == "issue-1"));
+
         Ok(())
     }
     #[tokio::test]
@@ -689,30 +871,3 @@ mod tests {
         Ok(())
     }
 }
-
-fn json_to_owned_value(v: &JsonValue) -> OwnedValue {
-    match v {
-        JsonValue::Null => OwnedValue::Null,
-        JsonValue::Bool(b) => OwnedValue::Bool(*b),
-        JsonValue::Number(n) => {
-            if let Some(i) = n.as_i64() {
-                OwnedValue::I64(i)
-            } else if let Some(u) = n.as_u64() {
-                OwnedValue::U64(u)
-            } else if let Some(f) = n.as_f64() {
-                OwnedValue::F64(f)
-            } else {
-                OwnedValue::Null
-            }
-        }
-        JsonValue::String(s) => OwnedValue::Str(s.clone()),
-        JsonValue::Array(arr) => OwnedValue::Array(arr.iter().map(json_to_owned_value).collect()),
-        JsonValue::Object(obj) => {
-            let map: BTreeMap<String, OwnedValue> = obj
-                .iter()
-                .map(|(k, v)| (k.clone(), json_to_owned_value(v)))
-                .collect();
-            OwnedValue::Object(map)
-        }
-    }
-}
@@ -0,0 +1,221 @@
+//! How far search is behind the event log.
+//!
+//! Events are stored first and indexed after (see `pipeline::IndexingProcessor`), so search
+//! results can lag behind: while the writer is busy, after failed indexing, or during a
+//! rebuild (see `integrity`). `GET /admin/search/status` reports the last stored and indexed
+//! sequence, the events still waiting for their indexing step (and those given up on), the
+//! writer's uncommitted operations and commit durations, and failed indexing. `GET /metrics`
+//! exposes the same in the Prometheus text format, for scraping from the addresses in
+//! `ADMIN_ALLOWED_CIDRS`.
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{internal_error, AppState};
+use crate::integrity::{IndexState, IndexStatus};
+use crate::search::IndexStatsSnapshot;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SearchStatus {
+    pub last_stored_sequence: Option<String>,
+    pub last_indexed_sequence: Option<String>,
+    /// Stored events after the last indexed one
+    pub lag_events: u64,
+    /// Committed events whose indexing (or another after-commit step) has not finished
+    pub unprocessed_events: usize,
+    /// Of those, the ones no longer replayed (see `pipeline::MAX_REPLAYS`)
+    pub dead_letters: usize,
+    pub writer: IndexStatsSnapshot,
+    /// The startup check and rebuild
+    pub index: IndexStatus,
+}
+
+/// The indexing backlog of `state`.
+pub async fn search_status(
+    state: &AppState,
+) -> Result<SearchStatus, Box<dyn std::error::Error + Send + Sync>> {
+    let last_stored_sequence = state.storage.latest_sequence().await?;
+    let writer = state.search.stats().snapshot();
+    let sequence = |s: &Option<String>| s.as_deref().and_then(|s| s.parse::<u64>().ok());
+    let lag_events = sequence(&last_stored_sequence)
+        .unwrap_or(0)
+        .saturating_sub(sequence(&writer.last_indexed_sequence).unwrap_or(0));
+    let changelog = state.storage.list_changelog().await?;
+    let dead_letters = changelog
+        .iter()
+        .filter(|(_, entry)| entry.failed_replays >= crate::pipeline::MAX_REPLAYS)
+        .count();
+    Ok(SearchStatus {
+        last_stored_sequence,
+        last_indexed_sequence: writer.last_indexed_sequence.clone(),
+        lag_events,
+        unprocessed_events: changelog.len(),
+        dead_letters,
+        writer,
+        index: state.index_health.status(),
+    })
+}
+
+/// `status` in the Prometheus text format.
+pub fn render_metrics(status: &SearchStatus) -> String {
+    let reindexing = status.index.state == IndexState::Reindexing;
+    let metrics: &[(&str, &str, &str, f64)] = &[
+        (
+            "zaakchat_search_lag_events",
+            "gauge",
+            "Stored events after the last indexed one",
+            status.lag_events as f64,
+        ),
+        (
+            "zaakchat_search_unprocessed_events",
+            "gauge",
+            "Committed events whose after-commit steps have not finished",
+            status.unprocessed_events as f64,
+        ),
+        (
+            "zaakchat_changelog_dead_letters",
+            "gauge",
+            "Committed events whose after-commit steps are no longer retried",
+            status.dead_letters as f64,
+        ),
+        (
+            "zaakchat_search_pending_writer_ops",
+            "gauge",
+            "Index adds and deletes not committed yet",
+            status.writer.pending_ops as f64,
+        ),
+        (
+            "zaakchat_search_commits_total",
+            "counter",
+            "Commits of the index writer",
+            status.writer.commits as f64,
+        ),
+        (
+            "zaakchat_search_commit_seconds_last",
+            "gauge",
+            "Duration of the last index commit",
+            status.writer.commit_ms_last / 1000.0,
+        ),
+        (
+            "zaakchat_search_commit_seconds_max",
+            "gauge",
+            "Longest index commit",
+            status.writer.commit_ms_max / 1000.0,
+        ),
+        (
+            "zaakchat_search_indexing_failures_total",
+            "counter",
+            "Events whose indexing failed",
+            status.writer.failures as f64,
+        ),
+        (
+            "zaakchat_search_reindexing",
+            "gauge",
+            "1 while the index is rebuilt",
+            if reindexing { 1.0 } else { 0.0 },
+        ),
+    ];
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        out.push_str(&format!(
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+        ));
+    }
+    out
+}
+
+/// GET /admin/search/status - The indexing backlog
+#[utoipa::path(
+    get,
+    path = "/admin/search/status",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Stored vs. indexed sequence, writer and rebuild state", body = SearchStatus),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not an admin"),
+    )
+)]
+pub async fn search_status_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<SearchStatus>, StatusCode> {
+    if !auth_user.is_admin() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    search_status(&state)
+        .await
+        .map(Json)
+        .map_err(internal_error)
+}
+
+/// GET /metrics - Indexing metrics for Prometheus
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", content_type = "text/plain"),
+    )
+)]
+pub async fn metrics_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let status = search_status(&state).await.map_err(internal_error)?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render_metrics(&status),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state, ADMIN};
+    use crate::schemas::CloudEventBuilder;
+
+    #[tokio::test]
+    async fn test_search_status() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        create_issue(&state, "issue-1", &issue("Paspoort", &[]), ADMIN).await;
+
+        let Json(status) = search_status_handler(State(state.clone()), user(ADMIN))
+            .await
+            .unwrap();
+        assert_eq!(
+            status.last_indexed_sequence,
+            Some("00000000000000000001".to_string())
+        );
+        assert_eq!(status.lag_events, 0);
+        assert_eq!((status.unprocessed_events, status.dead_letters), (0, 0));
+        assert_eq!(status.writer.pending_ops, 0);
+        assert!(status.writer.commits > 0);
+
+        // Stored, but not (yet) indexed
+        let event = CloudEventBuilder::new("test.event", "issue-1").build();
+        state.storage.store_event(&event).await.unwrap();
+        state.search.delete_by_id("issue-1").await.unwrap();
+        let status = search_status(&state).await.unwrap();
+        assert_eq!(status.lag_events, 1);
+        assert_eq!(status.writer.pending_ops, 1);
+
+        state
+            .search
+            .stats()
+            .record_failure("event x: disk full".to_string());
+        let metrics = render_metrics(&search_status(&state).await.unwrap());
+        assert!(metrics.contains("zaakchat_search_lag_events 1\n"));
+        assert!(metrics.contains("zaakchat_search_indexing_failures_total 1\n"));
+        assert!(metrics.contains("# TYPE zaakchat_search_commits_total counter\n"));
+
+        let forbidden = search_status_handler(State(state), user("alice@gemeente.nl")).await;
+        assert_eq!(forbidden.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+}
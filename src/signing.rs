@@ -0,0 +1,687 @@
+//! Electronic signatures through an external signing service.
+//!
+//! `POST /issues/{id}/signing` sends a Document of the issue to a [`SigningProvider`] and
+//! records a `SigningRequest` (`signing-{reference}`) in the issue's thread. The provider
+//! reports back on `POST /signing/callback`; the callback is verified by the provider, and
+//! moves the request to `signed`, `declined` or `voided` with a commit, so every step shows
+//! on the issue's timeline. Once signed, the Document is patched to point at the signed
+//! version, served by `GET /signing/{id}/document`; earlier versions stay in the event log.
+//!
+//! The provider is configured in the environment; only DocuSign is implemented so far (see
+//! [`DocuSignProvider`]). Without one, the endpoints answer 503.
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{authorize_issue, submit_commit, submit_event, AppState};
+use crate::schemas::{CloudEventBuilder, CommitBuilder, Document, SigningRequest, SigningStatus};
+
+/// What a provider reports about a signing request
+#[derive(Debug, Clone, PartialEq)]
+pub struct SigningCallback {
+    pub reference: String,
+    pub status: SigningStatus,
+}
+
+/// A signing service.
+#[async_trait]
+pub trait SigningProvider: Send + Sync {
+    /// Short name, stored on the `SigningRequest`
+    fn name(&self) -> &'static str;
+
+    /// Send `document` to `signers`, in order. Returns the provider's reference.
+    async fn start(
+        &self,
+        document: &Document,
+        signers: &[String],
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Whether a callback request really comes from the provider
+    fn verify(&self, headers: &HeaderMap, body: &[u8]) -> bool;
+
+    /// The status change in a verified callback body; `None` for notifications that don't
+    /// end the signing (e.g. "delivered").
+    fn parse_callback(&self, body: &[u8]) -> Result<Option<SigningCallback>, String>;
+
+    /// The signed document
+    async fn signed_document(
+        &self,
+        reference: &str,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// DocuSign eSignature: an envelope per request, and a Connect configuration (JSON, with HMAC
+/// key) that posts envelope events to the callback.
+pub struct DocuSignProvider {
+    http: reqwest::Client,
+    /// REST base, e.g. `https://demo.docusign.net/restapi`
+    base_url: String,
+    account_id: String,
+    access_token: String,
+    connect_secret: String,
+}
+
+impl DocuSignProvider {
+    pub fn new(
+        base_url: String,
+        account_id: String,
+        access_token: String,
+        connect_secret: String,
+    ) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url,
+            account_id,
+            access_token,
+            connect_secret,
+        }
+    }
+
+    fn envelope_url(&self, path: &str) -> String {
+        format!(
+            "{}/v2.1/accounts/{}/envelopes{}",
+            self.base_url.trim_end_matches('/'),
+            self.account_id,
+            path
+        )
+    }
+}
+
+#[async_trait]
+impl SigningProvider for DocuSignProvider {
+    fn name(&self) -> &'static str {
+        "docusign"
+    }
+
+    async fn start(
+        &self,
+        document: &Document,
+        signers: &[String],
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let content = self
+            .http
+            .get(&document.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let extension = document
+            .title
+            .rsplit_once('.')
+            .map(|(_, extension)| extension)
+            .unwrap_or("pdf");
+        let signers: Vec<_> = signers
+            .iter()
+            .enumerate()
+            .map(|(i, email)| {
+                json!({
+                    "email": email,
+                    "name": email,
+                    "recipientId": (i + 1).to_string(),
+                    "routingOrder": (i + 1).to_string(),
+                })
+            })
+            .collect();
+        let envelope: serde_json::Value = self
+            .http
+            .post(self.envelope_url(""))
+            .bearer_auth(&self.access_token)
+            .json(&json!({
+                "emailSubject": format!("Ter ondertekening: {}", document.title),
+                "documents": [{
+                    "documentId": "1",
+                    "name": document.title,
+                    "fileExtension": extension,
+                    "documentBase64": base64::engine::general_purpose::STANDARD.encode(&content),
+                }],
+                "recipients": { "signers": signers },
+                "status": "sent",
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(envelope
+            .get("envelopeId")
+            .and_then(|id| id.as_str())
+            .ok_or("no envelopeId in the response")?
+            .to_string())
+    }
+
+    fn verify(&self, headers: &HeaderMap, body: &[u8]) -> bool {
+        let expected = crate::hooks::hmac_sha256(&self.connect_secret, body);
+        // Connect sends one signature per configured key
+        (1..=10)
+            .filter_map(|i| headers.get(format!("x-docusign-signature-{}", i)))
+            .filter_map(|v| v.to_str().ok())
+            .filter_map(|v| base64::engine::general_purpose::STANDARD.decode(v).ok())
+            .any(|given| {
+                // Compare every byte, so the time taken doesn't reveal the matching prefix
+                given.len() == expected.len()
+                    && given.iter().zip(&expected).fold(0, |d, (a, b)| d | (a ^ b)) == 0
+            })
+    }
+
+    fn parse_callback(&self, body: &[u8]) -> Result<Option<SigningCallback>, String> {
+        let payload: serde_json::Value =
+            serde_json::from_slice(body).map_err(|e| format!("invalid JSON: {}", e))?;
+        let status = match payload.get("event").and_then(|e| e.as_str()) {
+            Some("envelope-completed") => SigningStatus::Signed,
+            Some("envelope-declined") => SigningStatus::Declined,
+            Some("envelope-voided") => SigningStatus::Voided,
+            Some(_) => return Ok(None),
+            None => return Err("missing event".to_string()),
+        };
+        let reference = payload
+            .pointer("/data/envelopeId")
+            .and_then(|id| id.as_str())
+            .ok_or("missing data.envelopeId")?;
+        Ok(Some(SigningCallback {
+            reference: reference.to_string(),
+            status,
+        }))
+    }
+
+    async fn signed_document(
+        &self,
+        reference: &str,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+        let content = self
+            .http
+            .get(self.envelope_url(&format!("/{}/documents/combined", reference)))
+            .bearer_auth(&self.access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(content.to_vec())
+    }
+}
+
+/// The provider configured in the environment (`DOCUSIGN_BASE_URL`, `DOCUSIGN_ACCOUNT_ID`,
+/// `DOCUSIGN_ACCESS_TOKEN` and `DOCUSIGN_CONNECT_SECRET`), if any.
+pub fn provider_from_env() -> Option<Arc<dyn SigningProvider>> {
+    let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    Some(Arc::new(DocuSignProvider::new(
+        var("DOCUSIGN_BASE_URL")?,
+        var("DOCUSIGN_ACCOUNT_ID")?,
+        var("DOCUSIGN_ACCESS_TOKEN")?,
+        var("DOCUSIGN_CONNECT_SECRET")?,
+    )))
+}
+
+fn signing_id(reference: &str) -> String {
+    format!("signing-{}", reference)
+}
+
+/// The `SigningRequest` with ID `id`, if there is one.
+async fn load_request(
+    state: &AppState,
+    id: &str,
+) -> Result<Option<SigningRequest>, Box<dyn std::error::Error + Send + Sync>> {
+    if state.storage.get_resource_type(id).await?.as_deref() != Some("SigningRequest") {
+        return Ok(None);
+    }
+    Ok(state
+        .storage
+        .get_resource(id)
+        .await?
+        .map(serde_json::from_value)
+        .transpose()?)
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct StartSigningRequest {
+    /// A Document in the issue's thread
+    pub document_id: String,
+    /// Emails of the signers, in the order they sign
+    pub signers: Vec<String>,
+}
+
+/// Send a document of `issue_id` to `provider` and record the request.
+pub async fn start_signing(
+    state: &AppState,
+    provider: &dyn SigningProvider,
+    user: &str,
+    headers: &HeaderMap,
+    issue_id: &str,
+    request: StartSigningRequest,
+) -> Result<Response, StatusCode> {
+    authorize_issue(state, user, issue_id).await?;
+    if request.signers.is_empty() || request.signers.iter().any(|s| !s.contains('@')) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("[signing] failed to start signing in {}: {}", issue_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let in_thread = crate::portal::thread_items(state, issue_id)
+        .await
+        .map_err(internal)?
+        .iter()
+        .any(|item| item.id == request.document_id);
+    let is_document = state
+        .storage
+        .get_resource_type(&request.document_id)
+        .await
+        .map_err(internal)?
+        .as_deref()
+        == Some("Document");
+    if !in_thread || !is_document {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let document: Document = state
+        .storage
+        .get_resource(&request.document_id)
+        .await
+        .map_err(internal)?
+        .and_then(|value| serde_json::from_value(value).ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let reference = provider
+        .start(&document, &request.signers)
+        .await
+        .map_err(|e| {
+            eprintln!(
+                "[signing] {} refused {}: {}",
+                provider.name(),
+                request.document_id,
+                e
+            );
+            StatusCode::BAD_GATEWAY
+        })?;
+    let signing = SigningRequest {
+        issue_id: issue_id.to_string(),
+        document_id: request.document_id,
+        signers: request.signers,
+        provider: provider.name().to_string(),
+        reference: reference.clone(),
+        status: SigningStatus::Sent,
+        completed_at: None,
+    };
+    let commit = CommitBuilder::create(signing_id(&reference), &signing)
+        .actor(user)
+        .build();
+    submit_commit(state, headers, issue_id, commit).await
+}
+
+/// Apply a status change reported by `provider`: replace the document by the signed version
+/// if it was signed, then record the new status. Changes to requests that already ended
+/// are ignored, so repeated callbacks are harmless. Returns whether anything changed.
+pub async fn record_callback(
+    state: &AppState,
+    provider: &dyn SigningProvider,
+    callback: &SigningCallback,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let id = signing_id(&callback.reference);
+    let Some(signing) = load_request(state, &id).await? else {
+        return Ok(false);
+    };
+    if signing.status != SigningStatus::Sent || callback.status == SigningStatus::Sent {
+        return Ok(false);
+    }
+    let actor = format!("signing/{}", provider.name());
+    let submit = |commit| {
+        let event = CloudEventBuilder::commit(signing.issue_id.clone(), &commit)
+            .source(actor.clone())
+            .build();
+        async move { submit_event(state, event).await }
+    };
+
+    if callback.status == SigningStatus::Signed {
+        let signed = provider.signed_document(&callback.reference).await?;
+        let base_url =
+            std::env::var("BASE_URL").unwrap_or_else(|_| "https://zaakchat.nl".to_string());
+        let commit = CommitBuilder::patch::<Document>(
+            signing.document_id.clone(),
+            json!({
+                "url": format!("{}/signing/{}/document", base_url.trim_end_matches('/'), id),
+                "size": signed.len(),
+            }),
+        )
+        .actor(actor.clone())
+        .build();
+        submit(commit).await?;
+    }
+    let commit = CommitBuilder::patch::<SigningRequest>(
+        id,
+        json!({
+            "status": callback.status,
+            "completed_at": chrono::Utc::now().to_rfc3339(),
+        }),
+    )
+    .actor(actor.clone())
+    .build();
+    submit(commit).await?;
+    Ok(true)
+}
+
+/// Verify, parse and record a callback request.
+pub async fn handle_callback(
+    state: &AppState,
+    provider: &dyn SigningProvider,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> StatusCode {
+    if !provider.verify(headers, body) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let callback = match provider.parse_callback(body) {
+        Ok(Some(callback)) => callback,
+        Ok(None) => return StatusCode::OK,
+        Err(e) => {
+            eprintln!("[signing] invalid callback from {}: {}", provider.name(), e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    match record_callback(state, provider, &callback).await {
+        Ok(true) => StatusCode::OK,
+        // Not ours, or already recorded: nothing for the provider to retry
+        Ok(false) => {
+            eprintln!(
+                "[signing] ignored {:?} callback for {}",
+                callback.status, callback.reference
+            );
+            StatusCode::OK
+        }
+        Err(e) => {
+            eprintln!(
+                "[signing] failed to record callback for {}: {}",
+                callback.reference, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// POST /issues/{id}/signing - Send a document of an issue out for signing
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/signing",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    request_body = StartSigningRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Sent; the SigningRequest's create commit", body = crate::schemas::CloudEvent),
+        (status = 400, description = "No signers, or an invalid email"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue, or no such document in it"),
+        (status = 502, description = "The signing service refused the document"),
+        (status = 503, description = "No signing service configured"),
+    )
+)]
+pub async fn start_signing_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(issue_id): Path<String>,
+    Json(request): Json<StartSigningRequest>,
+) -> Result<Response, StatusCode> {
+    let provider = provider_from_env().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    start_signing(
+        &state,
+        provider.as_ref(),
+        &auth_user.user_id,
+        &headers,
+        &issue_id,
+        request,
+    )
+    .await
+}
+
+/// POST /signing/callback - Status notifications of the signing service
+#[utoipa::path(
+    post,
+    path = "/signing/callback",
+    tag = "resources",
+    request_body(content = String, description = "The provider's notification, signed as it specifies"),
+    responses(
+        (status = 200, description = "Recorded, or nothing to record"),
+        (status = 400, description = "Unreadable notification"),
+        (status = 401, description = "Invalid signature"),
+        (status = 503, description = "No signing service configured"),
+    )
+)]
+pub async fn signing_callback_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    match provider_from_env() {
+        Some(provider) => handle_callback(&state, provider.as_ref(), &headers, &body).await,
+        None => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+/// GET /signing/{id}/document - The signed version of a document
+#[utoipa::path(
+    get,
+    path = "/signing/{id}/document",
+    tag = "resources",
+    params(("id" = String, Path, description = "SigningRequest ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The signed document", content_type = "application/pdf"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown signing request, or not signed"),
+        (status = 502, description = "The signing service did not return the document"),
+        (status = 503, description = "No signing service configured"),
+    )
+)]
+pub async fn signed_document_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Response, StatusCode> {
+    let signing = load_request(&state, &id)
+        .await
+        .map_err(|e| {
+            eprintln!("[signing] failed to load {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .filter(|signing| signing.status == SigningStatus::Signed)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    authorize_issue(&state, &auth_user.user_id, &signing.issue_id).await?;
+    let provider = provider_from_env().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let content = provider
+        .signed_document(&signing.reference)
+        .await
+        .map_err(|e| {
+            eprintln!("[signing] failed to fetch signed {}: {}", id, e);
+            StatusCode::BAD_GATEWAY
+        })?;
+    Ok(([(header::CONTENT_TYPE, "application/pdf")], content).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{
+        auth_user as user, create_issue, issue, submit_commit_event, test_state,
+    };
+
+    struct MockProvider;
+
+    #[async_trait]
+    impl SigningProvider for MockProvider {
+        fn name(&self) -> &'static str {
+            "mock"
+        }
+
+        async fn start(
+            &self,
+            _document: &Document,
+            _signers: &[String],
+        ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            Ok("env-1".to_string())
+        }
+
+        fn verify(&self, headers: &HeaderMap, _body: &[u8]) -> bool {
+            headers.get("x-mock-signature").is_some()
+        }
+
+        fn parse_callback(&self, body: &[u8]) -> Result<Option<SigningCallback>, String> {
+            let payload: serde_json::Value =
+                serde_json::from_slice(body).map_err(|e| e.to_string())?;
+            Ok(Some(SigningCallback {
+                reference: payload["reference"].as_str().unwrap().to_string(),
+                status: serde_json::from_value(payload["status"].clone()).unwrap(),
+            }))
+        }
+
+        async fn signed_document(
+            &self,
+            _reference: &str,
+        ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(b"%PDF-getekend".to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signing_flow() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let vergunning = issue("Omgevingsvergunning", &[alice]);
+        create_issue(&state, "issue-1", &vergunning, alice).await;
+        let document = Document {
+            title: "Besluit.pdf".to_string(),
+            url: "https://files.example/besluit.pdf".to_string(),
+            size: 1200,
+        };
+        let commit = CommitBuilder::create("doc-1", &document).build();
+        submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+
+        let no_headers = HeaderMap::new();
+        let start_as = |user: &'static str, document_id: &str, signer: &str| {
+            start_signing(
+                &state,
+                &MockProvider,
+                user,
+                &no_headers,
+                "issue-1",
+                StartSigningRequest {
+                    document_id: document_id.to_string(),
+                    signers: vec![signer.to_string()],
+                },
+            )
+        };
+        let start = |document_id: &str| start_as(alice, document_id, "burger@example.com");
+        assert_eq!(start("issue-1").await.unwrap_err(), StatusCode::NOT_FOUND);
+        let outsider = start_as("mallory@example.com", "doc-1", "burger@example.com");
+        assert_eq!(outsider.await.unwrap_err(), StatusCode::FORBIDDEN);
+        let no_email = start_as(alice, "doc-1", "burger");
+        assert_eq!(no_email.await.unwrap_err(), StatusCode::BAD_REQUEST);
+        assert_eq!(start("doc-1").await.unwrap().status(), StatusCode::ACCEPTED);
+        let signing = load_request(&state, "signing-env-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(signing.status, SigningStatus::Sent);
+        assert_eq!(signing.provider, "mock");
+
+        let body = serde_json::to_vec(&json!({"reference": "env-1", "status": "signed"})).unwrap();
+        assert_eq!(
+            handle_callback(&state, &MockProvider, &no_headers, &body).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("x-mock-signature", "ok".parse().unwrap());
+        assert_eq!(
+            handle_callback(&state, &MockProvider, &headers, &body).await,
+            StatusCode::OK
+        );
+        let signing = load_request(&state, "signing-env-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(signing.status, SigningStatus::Signed);
+        assert!(signing.completed_at.is_some());
+        let document = state.storage.get_resource("doc-1").await.unwrap().unwrap();
+        assert!(document["url"]
+            .as_str()
+            .unwrap()
+            .ends_with("/signing/signing-env-1/document"));
+        assert_eq!(document["size"], 13);
+        let outsider = signed_document_handler(
+            State(state.clone()),
+            user("mallory@example.com"),
+            Path("signing-env-1".to_string()),
+        );
+        assert_eq!(outsider.await.unwrap_err(), StatusCode::FORBIDDEN);
+
+        // A repeated callback changes nothing
+        let events = state
+            .storage
+            .list_subject_events("issue-1")
+            .await
+            .unwrap()
+            .len();
+        let callback = SigningCallback {
+            reference: "env-1".to_string(),
+            status: SigningStatus::Declined,
+        };
+        assert!(!record_callback(&state, &MockProvider, &callback)
+            .await
+            .unwrap());
+        assert_eq!(
+            state
+                .storage
+                .list_subject_events("issue-1")
+                .await
+                .unwrap()
+                .len(),
+            events
+        );
+    }
+
+    #[test]
+    fn test_docusign_connect_callback() {
+        let provider = DocuSignProvider::new(
+            "https://demo.docusign.net/restapi".to_string(),
+            "account".to_string(),
+            "token".to_string(),
+            "connect-geheim".to_string(),
+        );
+        let body = serde_json::to_vec(&json!({
+            "event": "envelope-completed",
+            "data": {"accountId": "account", "envelopeId": "93be49ab"},
+        }))
+        .unwrap();
+        let signature = base64::engine::general_purpose::STANDARD
+            .encode(crate::hooks::hmac_sha256("connect-geheim", &body));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-docusign-signature-1", signature.parse().unwrap());
+        assert!(provider.verify(&headers, &body));
+        assert!(!provider.verify(&HeaderMap::new(), &body));
+        assert_eq!(
+            provider.parse_callback(&body).unwrap(),
+            Some(SigningCallback {
+                reference: "93be49ab".to_string(),
+                status: SigningStatus::Signed,
+            })
+        );
+        let delivered = br#"{"event": "envelope-delivered", "data": {"envelopeId": "93be49ab"}}"#;
+        assert_eq!(provider.parse_callback(delivered).unwrap(), None);
+    }
+}
@@ -0,0 +1,343 @@
+//! Anonymous status lookup: `GET /status/{zaaknummer}/{code}` for citizens without an account.
+//!
+//! The zaaknummer is the issue ID. The verification code is derived from it with an HMAC
+//! keyed by the JWT secret, so it needs no storage and cannot be guessed from the number;
+//! behandelaars get it from `GET /issues/{id}/status-code` to pass on to the citizen. The
+//! answer contains only the status and planning progress (no title, names or other personal
+//! data), and lookups are rate limited per client address (see `allowlist` for how it is
+//! found behind a proxy). Limiting per zaaknummer would let anyone lock its citizen out; the
+//! verification codes are too many to guess at the per-client rate.
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use base64::Engine;
+use dashmap::DashMap;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::allowlist::ClientIp;
+use crate::auth::AuthUser;
+use crate::handlers::{authorize_issue, AppState};
+use crate::schemas::{Issue, IssueStatus, Planning, PlanningStatus};
+
+/// Lookups allowed per client within [`LOOKUP_WINDOW`]
+pub const MAX_LOOKUPS: u32 = 10;
+pub const LOOKUP_WINDOW: Duration = Duration::from_secs(60);
+
+/// Unambiguous characters for verification codes (no 0/O, 1/I/L)
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 8;
+
+/// Most keys with a window at once; new keys are refused while this many are live
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+/// Fixed-window counters of status lookups, per key.
+#[derive(Debug, Default)]
+pub struct StatusLookupLimiter {
+    windows: DashMap<String, (Instant, u32)>,
+}
+
+impl StatusLookupLimiter {
+    /// Count a lookup for `key`. Returns false if the key is over its limit, or is new while
+    /// [`MAX_TRACKED_KEYS`] others are being tracked.
+    pub fn allow(&self, key: &str) -> bool {
+        if self.windows.len() >= MAX_TRACKED_KEYS && !self.windows.contains_key(key) {
+            self.windows
+                .retain(|_, (start, _)| start.elapsed() < LOOKUP_WINDOW);
+            if self.windows.len() >= MAX_TRACKED_KEYS {
+                return false;
+            }
+        }
+        let mut window = self
+            .windows
+            .entry(key.to_string())
+            .or_insert((Instant::now(), 0));
+        if window.0.elapsed() >= LOOKUP_WINDOW {
+            *window = (Instant::now(), 0);
+        }
+        window.1 += 1;
+        window.1 <= MAX_LOOKUPS
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PublicStatus {
+    #[schema(value_type = String)]
+    pub status: IssueStatus,
+    /// Title of the planning step that is currently being carried out
+    pub current_moment: Option<String>,
+    /// Planned date of the last planning step (the decision), while it is not completed
+    pub expected_decision_date: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StatusLink {
+    pub zaaknummer: String,
+    pub verification_code: String,
+    /// Public URL of the status page
+    pub url: String,
+}
+
+/// The verification code of a zaaknummer
+pub fn verification_code(zaaknummer: &str) -> String {
+    let secret = std::env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string());
+    let key = jsonwebtoken::EncodingKey::from_secret(secret.as_bytes());
+    let message = format!("status:{}", zaaknummer);
+    let signature =
+        jsonwebtoken::crypto::sign(message.as_bytes(), &key, jsonwebtoken::Algorithm::HS256)
+            .and_then(|s| {
+                base64::engine::general_purpose::URL_SAFE_NO_PAD
+                    .decode(s)
+                    .map_err(|e| jsonwebtoken::errors::ErrorKind::Base64(e).into())
+            })
+            .expect("HMAC signing does not fail");
+    signature
+        .iter()
+        .take(CODE_LENGTH)
+        .map(|b| CODE_ALPHABET[*b as usize % CODE_ALPHABET.len()] as char)
+        .collect()
+}
+
+/// Is `code` the verification code of `zaaknummer`? Case and spaces don't matter.
+pub fn verify_code(zaaknummer: &str, code: &str) -> bool {
+    let given: Vec<u8> = code
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b'-')
+        .map(|b| b.to_ascii_uppercase())
+        .collect();
+    let expected = verification_code(zaaknummer).into_bytes();
+    // Compare every byte, so the time taken doesn't reveal the matching prefix
+    given.len() == expected.len()
+        && given.iter().zip(&expected).fold(0, |d, (a, b)| d | (a ^ b)) == 0
+}
+
+/// Status view of an issue's planning: the current step, and the date of the last step
+/// if it is still to come.
+pub fn planning_progress(planning: &[Planning]) -> (Option<String>, Option<String>) {
+    let current = planning
+        .iter()
+        .flat_map(|p| &p.moments)
+        .find(|m| matches!(m.status, PlanningStatus::Current))
+        .map(|m| m.title.clone());
+    let decision = planning
+        .iter()
+        .filter_map(|p| p.moments.last())
+        .next_back()
+        .filter(|m| !matches!(m.status, PlanningStatus::Completed))
+        .and_then(|m| m.date.clone());
+    (current, decision)
+}
+
+/// The rate limiting key of a client. IPv6 clients get a /64 network, the least a
+/// subscriber is given, so they can't pick a fresh address per lookup.
+fn client_key(client: Option<IpAddr>) -> String {
+    match client {
+        Some(IpAddr::V6(ip)) if ip.to_ipv4_mapped().is_none() => {
+            let segments = ip.segments();
+            format!(
+                "client:{:x}:{:x}:{:x}:{:x}::/64",
+                segments[0], segments[1], segments[2], segments[3]
+            )
+        }
+        Some(IpAddr::V6(ip)) => format!("client:{}", ip.to_canonical()),
+        Some(ip) => format!("client:{}", ip),
+        None => "client:unknown".to_string(),
+    }
+}
+
+/// GET /status/{zaaknummer}/{code} - Status of a zaak, without logging in
+#[utoipa::path(
+    get,
+    path = "/status/{zaaknummer}/{code}",
+    tag = "portal",
+    params(
+        ("zaaknummer" = String, Path, description = "Zaaknummer (issue ID)"),
+        ("code" = String, Path, description = "Verification code handed out with the zaaknummer"),
+    ),
+    responses(
+        (status = 200, description = "Status and planning progress of the zaak", body = PublicStatus),
+        (status = 404, description = "Unknown zaaknummer or wrong verification code"),
+        (status = 429, description = "Too many lookups; try again in a minute"),
+    )
+)]
+pub async fn public_status_handler(
+    State(state): State<AppState>,
+    client: Option<Extension<ClientIp>>,
+    Path((zaaknummer, code)): Path<(String, String)>,
+) -> Result<Json<PublicStatus>, StatusCode> {
+    let client = client.map(|Extension(ClientIp(ip))| ip);
+    if !state.status_lookups.allow(&client_key(client)) {
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+    if !verify_code(&zaaknummer, &code) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("[status] failed to look up {}: {}", zaaknummer, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let issue: Issue = state
+        .storage
+        .get_resource(&zaaknummer)
+        .await
+        .map_err(internal)?
+        .and_then(|value| serde_json::from_value(value).ok())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let planning: Vec<Planning> = crate::portal::thread_items(&state, &zaaknummer)
+        .await
+        .map_err(internal)?
+        .into_iter()
+        .filter_map(|item| serde_json::from_value(item.value).ok())
+        .collect();
+    let (current_moment, expected_decision_date) = planning_progress(&planning);
+
+    Ok(Json(PublicStatus {
+        status: issue.status,
+        current_moment,
+        expected_decision_date,
+    }))
+}
+
+/// GET /issues/{id}/status-code - The verification code to give the citizen
+#[utoipa::path(
+    get,
+    path = "/issues/{id}/status-code",
+    tag = "portal",
+    params(("id" = String, Path, description = "Issue ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Zaaknummer, verification code and status page URL", body = StatusLink),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn status_code_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+) -> Result<Json<StatusLink>, StatusCode> {
+    authorize_issue(&state, &auth_user.user_id, &issue_id).await?;
+    let verification_code = verification_code(&issue_id);
+    let base_url = std::env::var("BASE_URL").unwrap_or_else(|_| "https://zaakchat.nl".to_string());
+    Ok(Json(StatusLink {
+        url: format!("{}/status/{}/{}", base_url, issue_id, verification_code),
+        zaaknummer: issue_id,
+        verification_code,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{create_issue, issue, submit_commit_event, test_state};
+    use crate::schemas::{CommitBuilder, PlanningMoment};
+
+    #[test]
+    fn test_verification_code() {
+        let code = verification_code("issue-1");
+        assert_eq!(code.len(), CODE_LENGTH);
+        assert_ne!(code, verification_code("issue-2"));
+        assert!(verify_code("issue-1", &code.to_lowercase()));
+        assert!(!verify_code("issue-2", &code));
+        assert!(!verify_code("issue-1", &code[..4]));
+    }
+
+    #[tokio::test]
+    async fn test_public_status_lookup() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+
+        let burger = "jan@example.com";
+        let issue = Issue {
+            status: IssueStatus::InProgress,
+            assignee: Some("alice@gemeente.nl".to_string()),
+            ..issue("Kapvergunning Dorpsstraat 12", &[burger])
+        };
+        create_issue(&state, "issue-1", &issue, burger).await;
+        let moment = |title: &str, date: &str, status: PlanningStatus| PlanningMoment {
+            date: Some(date.to_string()),
+            title: title.to_string(),
+            status,
+        };
+        let planning = Planning {
+            title: None,
+            description: None,
+            moments: vec![
+                moment(
+                    "Aanvraag ontvangen",
+                    "2024-03-01",
+                    PlanningStatus::Completed,
+                ),
+                moment("Beoordeling", "2024-03-10", PlanningStatus::Current),
+                moment("Besluit", "2024-04-12", PlanningStatus::Planned),
+            ],
+        };
+        let commit = CommitBuilder::create("planning-1", &planning).build();
+        submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+
+        let from = |ip: &str, code: String| {
+            public_status_handler(
+                State(state.clone()),
+                Some(Extension(ClientIp(ip.parse().unwrap()))),
+                Path(("issue-1".to_string(), code)),
+            )
+        };
+        let lookup = |code: String| from("203.0.113.7", code);
+        let Json(status) = lookup(verification_code("issue-1")).await.unwrap();
+        assert_eq!(status.current_moment.as_deref(), Some("Beoordeling"));
+        assert_eq!(status.expected_decision_date.as_deref(), Some("2024-04-12"));
+        let body = serde_json::to_string(&status).unwrap();
+        assert!(!body.contains("Dorpsstraat") && !body.contains('@'));
+
+        assert_eq!(
+            lookup("AAAAAAAA".to_string()).await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        // A valid code only opens its own zaak
+        let other = public_status_handler(
+            State(state.clone()),
+            Some(Extension(ClientIp("203.0.113.7".parse().unwrap()))),
+            Path(("issue-2".to_string(), verification_code("issue-1"))),
+        );
+        assert_eq!(other.await.unwrap_err(), StatusCode::NOT_FOUND);
+        for _ in 3..MAX_LOOKUPS {
+            let _ = lookup("AAAAAAAA".to_string()).await;
+        }
+        assert_eq!(
+            lookup(verification_code("issue-1")).await.unwrap_err(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        // Guessing from one client doesn't lock the citizen out
+        assert!(from("198.51.100.1", verification_code("issue-1"))
+            .await
+            .is_ok());
+
+        // IPv6 clients are limited per /64
+        assert_eq!(
+            client_key(Some("2001:db8:1:2:aaaa::1".parse().unwrap())),
+            client_key(Some("2001:db8:1:2:bbbb::2".parse().unwrap()))
+        );
+        assert_eq!(
+            client_key(Some("::ffff:192.0.2.1".parse().unwrap())),
+            "client:192.0.2.1"
+        );
+
+        // The number of tracked clients is bounded
+        let limiter = StatusLookupLimiter::default();
+        for i in 0..MAX_TRACKED_KEYS {
+            assert!(limiter.allow(&format!("client:{}", i)));
+        }
+        assert!(!limiter.allow("client:new"));
+        assert!(limiter.allow("client:0"));
+        assert_eq!(limiter.windows.len(), MAX_TRACKED_KEYS);
+    }
+}
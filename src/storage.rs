@@ -8,21 +8,181 @@
 /// Notes:
 /// - Events are stored under a sequence-keyed table so iteration returns server-ordered events.
 /// - Resource records are stored under their resource id.
-use redb::{Database, ReadableTable, TableDefinition};
+use redb::{
+    Database, ReadableTable, ReadableTableMetadata, TableDefinition, TableHandle, WriteTransaction,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
+use std::collections::HashSet;
+use std::ops::Bound;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{RwLock, RwLockReadGuard};
 
 use crate::schemas::CloudEvent;
 
+mod migrations;
+pub use migrations::{latest_version, MigrationReport, MigrationStep};
+
 // Define redb tables
 // EVENTS_BY_SEQ maps zero-padded sequence keys to serialized event records so iteration is lexicographic by sequence
 const EVENTS_BY_SEQ_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("events_by_seq");
 const RESOURCES_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("resources");
+/// EVENT_IDS maps event ids to their sequence key in EVENTS_BY_SEQ, so lookups by id don't scan the log
+const EVENT_IDS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("event_ids");
+/// ACCESS_LOG maps `{actor}\0{time}\0{id}` to access log entries, so each actor's entries form
+/// one contiguous, time-ordered key range
+const ACCESS_LOG_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("access_log");
+/// COMMENT_REPLIES maps `{parent_id}\0{seq}` to the ID of a comment that replies to (quotes)
+/// the parent, so a comment's replies form one contiguous range in creation order
+const COMMENT_REPLIES_TABLE: TableDefinition<&str, &str> = TableDefinition::new("comment_replies");
+/// SUBJECT_EVENTS maps `{subject}\0{seq}` to the event id, so the events about one issue form
+/// one contiguous range in sequence order
+const SUBJECT_EVENTS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("subject_events");
+/// RESOURCE_EVENTS maps `{resource_id}\0{seq}` to the subject of each commit to a resource,
+/// so the commits to one resource form one contiguous range, whatever subject they were sent with
+const RESOURCE_EVENTS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("resource_events");
+/// ISSUE_RELATIONS maps `{issue_id}\0{relation_id}` to the relation ID, for both issues a
+/// relation links, so an issue's relations form one contiguous range
+const ISSUE_RELATIONS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("issue_relations");
+/// READ_MARKERS maps `{user}\0{subject}` to the sequence key of the last event the user has seen
+const READ_MARKERS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("read_markers");
+/// WATCHERS maps `{issue}\0{user}` to the user, for users who watch an issue
+const WATCHERS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("watchers");
+/// MUTES maps `{issue}\0{user}` to when the mute ends (RFC 3339), or "" for a mute until undone
+const MUTES_TABLE: TableDefinition<&str, &str> = TableDefinition::new("mutes");
+/// USERS maps the email of every user seen as actor, involved or assignee to when they were last seen
+const USERS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("users");
+/// CALENDARS maps a user to their CalDAV settings (JSON). Kept out of the event log, as it
+/// holds credentials.
+const CALENDARS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("calendars");
+/// CALENDAR_ENTRIES maps `{user}\0{uid}` to the iCalendar object last pushed to the user's
+/// calendar, to find what changed or was removed since
+const CALENDAR_ENTRIES_TABLE: TableDefinition<&str, &str> =
+    TableDefinition::new("calendar_entries");
+/// CALENDAR_FEEDS maps a user to the id of their current calendar feed token; tokens with
+/// another id are revoked
+const CALENDAR_FEEDS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("calendar_feeds");
+/// UPLOADS maps an upload ID to its session (JSON); the bytes are in `{data_dir}/uploads/{id}`
+const UPLOADS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("uploads");
+/// INTEGRATIONS maps an inbound webhook integration name to its settings (JSON), which
+/// hold the shared secret and are therefore not stored as resources
+const INTEGRATIONS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("integrations");
+/// OUTBOX maps an event's sequence key to its [`OutboxEntry`], written together with the
+/// event and removed once every outgoing integration has received it
+const OUTBOX_TABLE: TableDefinition<&str, &str> = TableDefinition::new("outbox");
+/// CHANGELOG maps an event's sequence key to its [`ChangelogEntry`], written together with
+/// the event and its resource change, and removed once indexing has caught up with it
+const CHANGELOG_TABLE: TableDefinition<&str, &str> = TableDefinition::new("changelog");
+/// PROJECTIONS maps `{projection}\0{key}` to a value (JSON) of a named projection's read model
+const PROJECTIONS_TABLE: TableDefinition<&str, &str> = TableDefinition::new("projections");
+/// PROJECTION_CURSORS maps a projection name to the sequence key of the last event it processed
+const PROJECTION_CURSORS_TABLE: TableDefinition<&str, &str> =
+    TableDefinition::new("projection_cursors");
+/// SCHEMA_REGISTRY maps `{event_type}\0{version:010}` to a registered event schema version
+const SCHEMA_REGISTRY_TABLE: TableDefinition<&str, &str> = TableDefinition::new("schema_registry");
+/// RESOURCE_TYPES maps a schema URL to the resource type registered for it at runtime
+const RESOURCE_TYPES_TABLE: TableDefinition<&str, &str> = TableDefinition::new("resource_types");
 /// Meta table for storing counters and small metadata (e.g. last assigned sequence)
 const META_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("meta");
 
+/// Delivery state of an event in the outbox
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub event_id: String,
+    /// Names of the sinks that received the event
+    #[serde(default)]
+    pub delivered: Vec<String>,
+    /// Failed delivery rounds so far
+    #[serde(default)]
+    pub attempts: u32,
+    /// Not before this time (RFC 3339) is delivery retried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<String>,
+}
+
+impl OutboxEntry {
+    pub fn new(event_id: &str) -> Self {
+        Self {
+            event_id: event_id.to_string(),
+            delivered: Vec::new(),
+            attempts: 0,
+            retry_at: None,
+        }
+    }
+}
+
+/// A committed event whose effects on the search index and the derived indexes (replies,
+/// relations, users) have not been applied yet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangelogEntry {
+    pub event_id: String,
+    /// The resource the event changed, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource: Option<ResourceChange>,
+    /// Replays that failed so far
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub failed_replays: u32,
+    /// Why the last replay failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceChange {
+    pub id: String,
+    pub resource_type: String,
+    /// State before the event (`None`: did not exist)
+    pub old: Option<JsonValue>,
+    /// State after the event (`None`: deleted)
+    pub new: Option<JsonValue>,
+}
+
+/// The resource change an event makes, committed in the same transaction as the event
+pub struct Projection<'a> {
+    pub resource_id: String,
+    pub resource_type: String,
+    /// The new state from the current one (`None`: absent / delete). An error aborts the
+    /// transaction, so neither the event nor the change is stored.
+    pub apply: ApplyFn<'a>,
+}
+
+/// How a [`Projection`] turns the current state of its resource into the new one
+pub type ApplyFn<'a> = Box<
+    dyn FnOnce(Option<JsonValue>) -> Result<Option<JsonValue>, ProjectionError> + Send + Sync + 'a,
+>;
+
+/// The event could not be projected onto its resource as stored
+#[derive(Debug)]
+pub struct ProjectionError(pub String);
+
+impl std::fmt::Display for ProjectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ProjectionError {}
+
+/// An event with the same `source` and `id` is already stored: per CloudEvents, the same event
+#[derive(Debug)]
+pub struct DuplicateEvent(pub Box<CloudEvent>);
+
+impl std::fmt::Display for DuplicateEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "event {} from {} is already stored",
+            self.0.id, self.0.source
+        )
+    }
+}
+
+impl std::error::Error for DuplicateEvent {}
+
 /// Record for storing events
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventRecord {
@@ -33,6 +193,170 @@ pub struct EventRecord {
     pub time: Option<String>,
     pub sequence: Option<String>,
     pub data: String, // JSON serialized
+    /// When the server received the event; absent in records stored before it was kept
+    pub time_received: Option<String>,
+}
+
+/// Event records as stored before `time_received` was added
+#[derive(Serialize, Deserialize)]
+struct LegacyEventRecord {
+    id: String,
+    event_type: String,
+    source: String,
+    subject: Option<String>,
+    time: Option<String>,
+    sequence: Option<String>,
+    data: String,
+}
+
+impl From<EventRecord> for LegacyEventRecord {
+    fn from(rec: EventRecord) -> Self {
+        LegacyEventRecord {
+            id: rec.id,
+            event_type: rec.event_type,
+            source: rec.source,
+            subject: rec.subject,
+            time: rec.time,
+            sequence: rec.sequence,
+            data: rec.data,
+        }
+    }
+}
+
+impl EventRecord {
+    /// Read a stored record, also in the format from before `time_received`.
+    fn decode(bytes: &[u8]) -> Result<Self, bincode::Error> {
+        bincode::deserialize(bytes).or_else(|e| {
+            let legacy: LegacyEventRecord = bincode::deserialize(bytes).map_err(|_| e)?;
+            Ok(EventRecord {
+                id: legacy.id,
+                event_type: legacy.event_type,
+                source: legacy.source,
+                subject: legacy.subject,
+                time: legacy.time,
+                sequence: legacy.sequence,
+                data: legacy.data,
+                time_received: None,
+            })
+        })
+    }
+
+    /// Rebuild the CloudEvent envelope from a persisted record.
+    fn into_cloud_event(self) -> Result<CloudEvent, serde_json::Error> {
+        let data: Option<JsonValue> = serde_json::from_str(&self.data)?;
+        let mut event = CloudEvent {
+            specversion: "1.0".to_string(),
+            id: self.id,
+            source: self.source,
+            subject: self.subject.unwrap_or_else(|| "unknown".to_string()),
+            event_type: self.event_type,
+            time: self.time,
+            time_received: self.time_received,
+            datacontenttype: Some("application/json".to_string()),
+            dataschema: None,
+            dataref: None,
+            sequence: None,
+            sequencenumber: None,
+            sequencetype: None,
+            data,
+        };
+        // Older records stored the bare number; always hand out the zero-padded key so it
+        // can be passed back as `after_seq` and compared with live (broadcast) events.
+        if let Some(s) = self.sequence {
+            match s.parse::<u64>() {
+                Ok(n) => event.set_sequence(format!("{:020}", n)),
+                Err(_) => event.sequence = Some(s),
+            }
+        }
+        Ok(event)
+    }
+}
+
+/// Sequence numbers `from..=to` that were assigned but have no stored event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct SequenceGap {
+    pub from: u64,
+    pub to: u64,
+}
+
+/// How much of the database file one table takes
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct TableSize {
+    pub name: String,
+    pub entries: u64,
+    /// Bytes of keys and values
+    pub stored_bytes: u64,
+    /// Bytes of branch pages and other b-tree metadata
+    pub metadata_bytes: u64,
+    /// Unused bytes in the table's pages
+    pub fragmented_bytes: u64,
+}
+
+/// How the database file is used
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct StorageReport {
+    /// Size of the file on disk
+    pub file_bytes: u64,
+    /// Bytes of the pages in use
+    pub allocated_bytes: u64,
+    pub stored_bytes: u64,
+    pub metadata_bytes: u64,
+    /// Unused bytes within the pages in use
+    pub fragmented_bytes: u64,
+    /// Share of the file that holds neither data nor metadata (0 to 1); compaction
+    /// gives most of it back
+    pub fragmentation: f64,
+    /// Largest first
+    pub tables: Vec<TableSize>,
+}
+
+/// What a vacuum removed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct VacuumReport {
+    /// Deleted resources whose leftovers were looked for
+    pub deleted_resources: usize,
+    /// Removed entries per table
+    pub removed: std::collections::BTreeMap<String, u64>,
+}
+
+/// The resource a commit event changes, from its type and data
+fn committed_resource<'a>(event_type: &str, data: &'a JsonValue) -> Option<&'a str> {
+    if event_type != "json.commit" && event_type != "nl.vng.zaken.json-commit.v1" {
+        return None;
+    }
+    data.get("resource_id").and_then(|v| v.as_str())
+}
+
+/// Remove the entries of `definition` for which `dead(key, value)` holds. Returns how many.
+fn remove_where(
+    write_txn: &WriteTransaction,
+    definition: TableDefinition<&str, &str>,
+    dead: impl Fn(&str, &str) -> bool,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let mut table = write_txn.open_table(definition)?;
+    let mut keys = Vec::new();
+    for item in table.iter()? {
+        let (key, value) = item?;
+        if dead(key.value(), value.value()) {
+            keys.push(key.value().to_string());
+        }
+    }
+    for key in &keys {
+        table.remove(key.as_str())?;
+    }
+    Ok(keys.len() as u64)
+}
+
+/// An authenticated API request, kept for accountability (who looked at what, and when)
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct AccessLogEntry {
+    pub actor: String,
+    /// RFC 3339 timestamp in UTC with microsecond precision (sortable as a string)
+    pub time: String,
+    pub method: String,
+    /// Request path, without the query string (which may hold a token)
+    pub path: String,
+    pub status: u16,
 }
 
 /// Record for storing resources
@@ -47,15 +371,27 @@ pub struct ResourceRecord {
 /// Storage layer combining redb K/V store.
 /// Search/indexing responsibilities live in the separate `search` module (src/search.rs).
 pub struct Storage {
-    db: Arc<Database>,
+    /// Locked for writing only by compaction, which needs the database to itself
+    db: RwLock<Database>,
     /// Absolute path to the data directory used by this storage instance (e.g. ./data).
     /// Kept so higher-level modules (e.g. the search subsystem) can locate index files.
     pub data_dir: std::path::PathBuf,
 }
 
 impl Storage {
-    /// Create a new storage instance
+    fn db(&self) -> RwLockReadGuard<'_, Database> {
+        self.db.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Create a new storage instance, migrating the data to the current schema version
     pub async fn new(data_dir: &Path) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let storage = Self::open(data_dir).await?;
+        storage.migrate(false)?;
+        Ok(storage)
+    }
+
+    /// Open the storage in `data_dir` without migrating it (see [`Storage::migrate`])
+    pub async fn open(data_dir: &Path) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         // Create directories
         let db_path = data_dir.join("data.redb");
         let index_path = data_dir.join("search_index");
@@ -72,6 +408,27 @@ impl Storage {
             let _ = write_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
             let _ = write_txn.open_table(RESOURCES_TABLE)?;
             let _ = write_txn.open_table(META_TABLE)?;
+            let _ = write_txn.open_table(EVENT_IDS_TABLE)?;
+            let _ = write_txn.open_table(ACCESS_LOG_TABLE)?;
+            let _ = write_txn.open_table(COMMENT_REPLIES_TABLE)?;
+            let _ = write_txn.open_table(SUBJECT_EVENTS_TABLE)?;
+            let _ = write_txn.open_table(RESOURCE_EVENTS_TABLE)?;
+            let _ = write_txn.open_table(READ_MARKERS_TABLE)?;
+            let _ = write_txn.open_table(ISSUE_RELATIONS_TABLE)?;
+            let _ = write_txn.open_table(WATCHERS_TABLE)?;
+            let _ = write_txn.open_table(MUTES_TABLE)?;
+            let _ = write_txn.open_table(USERS_TABLE)?;
+            let _ = write_txn.open_table(CALENDARS_TABLE)?;
+            let _ = write_txn.open_table(CALENDAR_ENTRIES_TABLE)?;
+            let _ = write_txn.open_table(CALENDAR_FEEDS_TABLE)?;
+            let _ = write_txn.open_table(UPLOADS_TABLE)?;
+            let _ = write_txn.open_table(INTEGRATIONS_TABLE)?;
+            let _ = write_txn.open_table(OUTBOX_TABLE)?;
+            let _ = write_txn.open_table(CHANGELOG_TABLE)?;
+            let _ = write_txn.open_table(PROJECTIONS_TABLE)?;
+            let _ = write_txn.open_table(PROJECTION_CURSORS_TABLE)?;
+            let _ = write_txn.open_table(SCHEMA_REGISTRY_TABLE)?;
+            let _ = write_txn.open_table(RESOURCE_TYPES_TABLE)?;
         }
         write_txn.commit()?;
 
@@ -82,133 +439,305 @@ impl Storage {
         //
         // Initialize storage return value (only DB reference is kept here).
         Ok(Self {
-            db: Arc::new(db),
+            db: RwLock::new(db),
             data_dir: data_dir.to_path_buf(),
         })
     }
 
+    /// The schema version of the stored data
+    pub fn schema_version(&self) -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+        migrations::schema_version(&self.db())
+    }
+
+    /// Run the migrations the data has not had yet. A dry run reports what would change
+    /// without writing.
+    pub fn migrate(
+        &self,
+        dry_run: bool,
+    ) -> Result<MigrationReport, Box<dyn std::error::Error + Send + Sync>> {
+        migrations::migrate(&self.db(), dry_run)
+    }
+
+    /// Undo the migrations above `to_version`, for going back to an older build.
+    pub fn rollback(
+        &self,
+        to_version: u32,
+        dry_run: bool,
+    ) -> Result<MigrationReport, Box<dyn std::error::Error + Send + Sync>> {
+        migrations::rollback(&self.db(), to_version, dry_run)
+    }
+
     /// Store an event in the K/V store (with diagnostic logging) and assign a monotonically increasing sequence.
     /// Returns the assigned sequence string (zero-padded) on success.
     pub async fn store_event(
         &self,
         event: &CloudEvent,
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.store_projected_event(event, None).await?.0)
+    }
+
+    /// Store an event together with the resource change it makes, in one transaction: the
+    /// sequence, the event and its indexes, the resource, the outbox entry and the changelog
+    /// entry are all written, or none are. Returns the sequence key and the changelog entry,
+    /// or a [`DuplicateEvent`] with the stored event when it has the same source and id.
+    pub async fn store_projected_event(
+        &self,
+        event: &CloudEvent,
+        projection: Option<Projection<'_>>,
+    ) -> Result<(String, ChangelogEntry), Box<dyn std::error::Error + Send + Sync>> {
         // Diagnostic: log attempt to store event
         println!(
             "[storage] attempt store_event: id={} type={} source={}",
             event.id, event.event_type, event.source
         );
 
-        // Atomically get the next sequence number using the META_TABLE.
-        // We perform this with a small write transaction that reads the last_seq value,
-        // increments it, writes it back and commits. The new sequence is then returned.
-        let seq = {
-            // start a write txn to update the counter atomically
-            let wtx = self.db.begin_write()?;
-            // Read, compute and write within an inner scope so the table guard is dropped
-            // before committing the transaction (avoids borrow conflicts).
-            let next = {
-                let mut meta = wtx.open_table(META_TABLE)?;
-
-                // Try to read the last sequence value and convert to owned bytes immediately.
-                let last_seq_bytes = match meta.get("last_seq")? {
-                    Some(g) => Some(g.value().to_vec()),
-                    None => None,
-                };
-
-                // Compute next sequence (u128) robustly
-                let next_seq: u128 = if let Some(bytes) = last_seq_bytes {
-                    match std::str::from_utf8(&bytes)
-                        .ok()
-                        .and_then(|s| s.parse::<u128>().ok())
-                    {
-                        Some(val) => val + 1,
-                        None => 1u128,
+        let write_txn = self.db().begin_write()?;
+        let (seq_key, entry) = {
+            // A retried event is not stored twice
+            let mut id_table = write_txn.open_table(EVENT_IDS_TABLE)?;
+            let stored = match id_table.get(event.id.as_str())? {
+                Some(seq_key) => {
+                    let seq_table = write_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+                    let record = seq_table.get(seq_key.value())?;
+                    match record {
+                        Some(bytes) => Some(EventRecord::decode(bytes.value())?),
+                        None => None,
                     }
-                } else {
-                    1u128
-                };
+                }
+                None => None,
+            };
+            if let Some(stored) = stored.filter(|stored| stored.source == event.source) {
+                return Err(Box::new(DuplicateEvent(Box::new(
+                    stored.into_cloud_event()?,
+                ))));
+            }
 
-                // store back the new last_seq as bytes
-                meta.insert("last_seq", next_seq.to_string().as_bytes())?;
+            // Next sequence number; assigned in this transaction, so a failed store leaves no gap
+            let mut meta = write_txn.open_table(META_TABLE)?;
+            let last_seq = meta
+                .get("last_seq")?
+                .and_then(|g| {
+                    std::str::from_utf8(g.value())
+                        .ok()
+                        .and_then(|s| s.parse::<u128>().ok())
+                })
+                .unwrap_or(0);
+            let seq = last_seq + 1;
+            meta.insert("last_seq", seq.to_string().as_bytes())?;
 
-                // drop meta (end of inner scope) so we can commit safely
-                next_seq
+            // create sequence key with fixed width (e.g. 020 digits) to ensure lexicographic ordering
+            let seq_key = format!("{:020}", seq);
+            let record = EventRecord {
+                id: event.id.clone(),
+                event_type: event.event_type.clone(),
+                source: event.source.clone(),
+                subject: Some(event.subject.clone()),
+                time: event.time.clone(),
+                sequence: Some(seq_key.clone()),
+                data: serde_json::to_string(&event.data)?,
+                time_received: event.time_received.clone(),
             };
+            let serialized = bincode::serialize(&record)?;
 
-            // commit the write transaction after the table guard has been dropped
-            wtx.commit()?;
+            // Write seq->record mapping and the id and subject indexes
+            let mut seq_table = write_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+            seq_table.insert(seq_key.as_str(), serialized.as_slice())?;
+            id_table.insert(event.id.as_str(), seq_key.as_str())?;
+            let mut subject_table = write_txn.open_table(SUBJECT_EVENTS_TABLE)?;
+            let subject_key = format!("{}\0{}", event.subject, seq_key);
+            subject_table.insert(subject_key.as_str(), event.id.as_str())?;
+            if let Some(resource_id) = event
+                .data
+                .as_ref()
+                .and_then(|data| committed_resource(&event.event_type, data))
+            {
+                let mut resource_table = write_txn.open_table(RESOURCE_EVENTS_TABLE)?;
+                let resource_key = format!("{}\0{}", resource_id, seq_key);
+                resource_table.insert(resource_key.as_str(), event.subject.as_str())?;
+            }
 
-            next
-        };
+            // Project the event onto its resource
+            let resource = match projection {
+                Some(projection) => {
+                    let mut resources = write_txn.open_table(RESOURCES_TABLE)?;
+                    let old = match resources.get(projection.resource_id.as_str())? {
+                        Some(bytes) => {
+                            let rec: ResourceRecord = bincode::deserialize(bytes.value())?;
+                            Some(serde_json::from_str::<JsonValue>(&rec.data)?)
+                        }
+                        None => None,
+                    };
+                    let new = (projection.apply)(old.clone())?;
+                    match &new {
+                        Some(data) => {
+                            let record = ResourceRecord {
+                                id: projection.resource_id.clone(),
+                                resource_type: projection.resource_type.clone(),
+                                data: serde_json::to_string(data)?,
+                                updated_at: chrono::Utc::now().to_rfc3339(),
+                            };
+                            resources.insert(
+                                projection.resource_id.as_str(),
+                                bincode::serialize(&record)?.as_slice(),
+                            )?;
+                        }
+                        None => {
+                            resources.remove(projection.resource_id.as_str())?;
+                        }
+                    }
+                    Some(ResourceChange {
+                        id: projection.resource_id,
+                        resource_type: projection.resource_type,
+                        old,
+                        new,
+                    })
+                }
+                None => None,
+            };
 
-        // Create record and include sequence as string
-        let record = EventRecord {
-            id: event.id.clone(),
-            event_type: event.event_type.clone(),
-            source: event.source.clone(),
-            subject: Some(event.subject.clone()),
-            time: event.time.clone(),
-            sequence: Some(seq.to_string()),
-            data: serde_json::to_string(&event.data)?,
+            // Outgoing integrations get the event if and only if it is stored
+            let mut outbox_table = write_txn.open_table(OUTBOX_TABLE)?;
+            let outbox_entry = serde_json::to_string(&OutboxEntry::new(&event.id))?;
+            outbox_table.insert(seq_key.as_str(), outbox_entry.as_str())?;
+            // ... and so does indexing
+            let entry = ChangelogEntry {
+                event_id: event.id.clone(),
+                resource,
+                failed_replays: 0,
+                error: None,
+            };
+            let mut changelog = write_txn.open_table(CHANGELOG_TABLE)?;
+            changelog.insert(seq_key.as_str(), serde_json::to_string(&entry)?.as_str())?;
+            (seq_key, entry)
         };
-
-        let serialized = bincode::serialize(&record)?;
-
-        // Write seq->record mapping (we store only sequence keyed records for ordered iteration)
-        let write_txn = self.db.begin_write()?;
-        {
-            let mut seq_table = write_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
-            // create sequence key with fixed width (e.g. 020 digits) to ensure lexicographic ordering
-            let seq_key = format!("{:020}", seq);
-            seq_table.insert(seq_key.as_str(), serialized.as_slice())?;
-        }
         write_txn.commit()?;
 
         // Diagnostic: confirm persisted to DB
-        let seq_key = format!("{:020}", seq);
         println!(
             "[storage] persisted event to DB: id={} seq={}",
             event.id, seq_key
         );
 
-        // Return the assigned sequence key to the caller
-        Ok(seq_key)
+        Ok((seq_key, entry))
+    }
+
+    /// Changelog entries not yet applied to the indexes, oldest first.
+    pub async fn list_changelog(
+        &self,
+    ) -> Result<Vec<(String, ChangelogEntry)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(CHANGELOG_TABLE)?;
+        let mut entries = Vec::new();
+        for item in table.iter()? {
+            let (key, value) = item?;
+            entries.push((
+                key.value().to_string(),
+                serde_json::from_str(value.value())?,
+            ));
+        }
+        Ok(entries)
+    }
+
+    /// Replace the changelog entry of the event at `seq_key`, e.g. to record a failed replay.
+    pub async fn set_changelog_entry(
+        &self,
+        seq_key: &str,
+        entry: &ChangelogEntry,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(CHANGELOG_TABLE)?;
+            table.insert(seq_key, serde_json::to_string(entry)?.as_str())?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Mark the changelog entry of the event at `seq_key` as applied.
+    pub async fn remove_changelog_entry(
+        &self,
+        seq_key: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(CHANGELOG_TABLE)?;
+            table.remove(seq_key)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Latest assigned event sequence as a zero-padded key, or `None` when no events exist yet.
+    pub async fn latest_sequence(
+        &self,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let meta = read_txn.open_table(META_TABLE)?;
+        let last = meta.get("last_seq")?.and_then(|g| {
+            std::str::from_utf8(g.value())
+                .ok()
+                .and_then(|s| s.parse::<u128>().ok())
+        });
+        Ok(last.map(|n| format!("{:020}", n)))
+    }
+
+    /// Runs of sequence numbers up to the latest assigned one that have no stored event,
+    /// e.g. after a partial import or a restore that lost part of the log.
+    pub async fn sequence_gaps(
+        &self,
+    ) -> Result<Vec<SequenceGap>, Box<dyn std::error::Error + Send + Sync>> {
+        let latest = match self.latest_sequence().await? {
+            Some(key) => key.parse::<u64>()?,
+            None => return Ok(Vec::new()),
+        };
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+        let mut gaps = Vec::new();
+        let mut expected = 1u64;
+        for item in table.iter()? {
+            let (key, _) = item?;
+            let Ok(seq) = key.value().parse::<u64>() else {
+                continue;
+            };
+            if seq > expected {
+                gaps.push(SequenceGap {
+                    from: expected,
+                    to: seq - 1,
+                });
+            }
+            expected = expected.max(seq + 1);
+        }
+        if expected <= latest {
+            gaps.push(SequenceGap {
+                from: expected,
+                to: latest,
+            });
+        }
+        Ok(gaps)
     }
 
-    /// Get an event by ID (scan events_by_seq and return the matching event)
+    /// Get an event by ID (resolves the sequence key via the id index)
     #[allow(dead_code)]
     pub async fn get_event(
         &self,
         id: &str,
-    ) -> Result<Option<CloudEvent>, Box<dyn std::error::Error>> {
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+    ) -> Result<Option<CloudEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let id_table = read_txn.open_table(EVENT_IDS_TABLE)?;
 
-        let iter = table.iter()?;
-        for item in iter {
-            let (_key, value) = item?;
-            let rec: EventRecord = bincode::deserialize(value.value())?;
-            if rec.id == id {
-                let data: Option<JsonValue> = serde_json::from_str(&rec.data)?;
-                return Ok(Some(CloudEvent {
-                    specversion: "1.0".to_string(),
-                    id: rec.id,
-                    source: rec.source,
-                    subject: rec.subject.unwrap_or_else(|| "unknown".to_string()),
-                    event_type: rec.event_type,
-                    time: rec.time,
-                    datacontenttype: Some("application/json".to_string()),
-                    dataschema: None,
-                    dataref: None,
-                    sequence: rec.sequence,
-                    sequencetype: None,
-                    data,
-                }));
+        let seq_key = match id_table.get(id)? {
+            Some(g) => g.value().to_string(),
+            None => return Ok(None),
+        };
+
+        let seq_table = read_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+        match seq_table.get(seq_key.as_str())? {
+            Some(bytes) => {
+                let rec = EventRecord::decode(bytes.value())?;
+                Ok(Some(rec.into_cloud_event()?))
             }
+            None => Ok(None),
         }
-
-        Ok(None)
     }
 
     /// Store a resource in the K/V store (with diagnostic logging)
@@ -235,7 +764,7 @@ impl Storage {
 
         let serialized = bincode::serialize(&record)?;
 
-        let write_txn = self.db.begin_write()?;
+        let write_txn = self.db().begin_write()?;
         {
             let mut table = write_txn.open_table(RESOURCES_TABLE)?;
             table.insert(id, serialized.as_slice())?;
@@ -253,7 +782,7 @@ impl Storage {
         &self,
         id: &str,
     ) -> Result<Option<JsonValue>, Box<dyn std::error::Error + Send + Sync>> {
-        let read_txn = self.db.begin_read()?;
+        let read_txn = self.db().begin_read()?;
         let table = read_txn.open_table(RESOURCES_TABLE)?;
 
         let result = table.get(id)?;
@@ -268,12 +797,29 @@ impl Storage {
         }
     }
 
+    /// The type a resource was stored with (e.g. "Issue")
+    pub async fn get_resource_type(
+        &self,
+        id: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(RESOURCES_TABLE)?;
+
+        match table.get(id)? {
+            Some(bytes) => {
+                let rec: ResourceRecord = bincode::deserialize(bytes.value())?;
+                Ok(Some(rec.resource_type))
+            }
+            None => Ok(None),
+        }
+    }
+
     /// Delete a resource
     pub async fn delete_resource(
         &self,
         id: &str,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let write_txn = self.db.begin_write()?;
+        let write_txn = self.db().begin_write()?;
         {
             let mut table = write_txn.open_table(RESOURCES_TABLE)?;
             table.remove(id)?;
@@ -288,7 +834,7 @@ impl Storage {
 
     /// Clear all data from storage (events, resources, and metadata)
     pub async fn clear(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let write_txn = self.db.begin_write()?;
+        let write_txn = self.db().begin_write()?;
         {
             // Clear events table
             let mut events_table = write_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
@@ -311,11 +857,78 @@ impl Storage {
                 resources_table.remove(key.as_str())?;
             }
 
+            // Clear event id index
+            let mut ids_table = write_txn.open_table(EVENT_IDS_TABLE)?;
+            let keys: Vec<String> = ids_table
+                .iter()?
+                .map(|r| r.map(|(k, _)| k.value().to_string()))
+                .collect::<Result<_, _>>()?;
+            for key in keys {
+                ids_table.remove(key.as_str())?;
+            }
+
+            // Clear access log
+            let mut access_table = write_txn.open_table(ACCESS_LOG_TABLE)?;
+            let keys: Vec<String> = access_table
+                .iter()?
+                .map(|r| r.map(|(k, _)| k.value().to_string()))
+                .collect::<Result<_, _>>()?;
+            for key in keys {
+                access_table.remove(key.as_str())?;
+            }
+
+            // Clear comment reply index
+            let mut replies_table = write_txn.open_table(COMMENT_REPLIES_TABLE)?;
+            let keys: Vec<String> = replies_table
+                .iter()?
+                .map(|r| r.map(|(k, _)| k.value().to_string()))
+                .collect::<Result<_, _>>()?;
+            for key in keys {
+                replies_table.remove(key.as_str())?;
+            }
+
+            // Clear subject index, read markers, relation index, watchers, mutes, seen users,
+            // calendar sync state and upload sessions
+            for table in [
+                SUBJECT_EVENTS_TABLE,
+                RESOURCE_EVENTS_TABLE,
+                READ_MARKERS_TABLE,
+                ISSUE_RELATIONS_TABLE,
+                WATCHERS_TABLE,
+                MUTES_TABLE,
+                USERS_TABLE,
+                CALENDARS_TABLE,
+                CALENDAR_ENTRIES_TABLE,
+                CALENDAR_FEEDS_TABLE,
+                UPLOADS_TABLE,
+                INTEGRATIONS_TABLE,
+                OUTBOX_TABLE,
+                CHANGELOG_TABLE,
+                PROJECTIONS_TABLE,
+                PROJECTION_CURSORS_TABLE,
+                SCHEMA_REGISTRY_TABLE,
+                RESOURCE_TYPES_TABLE,
+            ] {
+                let mut table = write_txn.open_table(table)?;
+                let keys: Vec<String> = table
+                    .iter()?
+                    .map(|r| r.map(|(k, _)| k.value().to_string()))
+                    .collect::<Result<_, _>>()?;
+                for key in keys {
+                    table.remove(key.as_str())?;
+                }
+            }
+
             // Reset meta table (sequence counter)
             let mut meta_table = write_txn.open_table(META_TABLE)?;
             meta_table.remove("last_seq")?;
         }
         write_txn.commit()?;
+        // The uploaded bytes go with their sessions
+        match tokio::fs::remove_dir_all(self.data_dir.join("uploads")).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
 
         println!("[storage] cleared all data");
         Ok(())
@@ -363,13 +976,12 @@ impl Storage {
     ) -> Result<Vec<(String, JsonValue)>, Box<dyn std::error::Error + Send + Sync>> {
         let mut results = Vec::new();
 
-        let read_txn = self.db.begin_read()?;
+        let read_txn = self.db().begin_read()?;
         let table = read_txn.open_table(RESOURCES_TABLE)?;
 
-        let mut count = 0;
         let iter = table.iter()?;
 
-        for item in iter {
+        for (count, item) in iter.enumerate() {
             let (key, value) = item?;
 
             if count >= offset {
@@ -381,97 +993,1006 @@ impl Storage {
                     break;
                 }
             }
-            count += 1;
         }
 
         Ok(results)
     }
 
-    /// List events by sequence with pagination after a given sequence key.
-    ///
-    /// This function returns events in backend processing order (ascending by sequence).
-    /// Use `after_seq` to fetch events after a particular zero-padded sequence key
-    /// (e.g. "00000000000000000042"). If `after_seq` is `None`, iteration starts at the beginning.
-    pub async fn list_events_after(
+    /// Like `list_resources`, with the type each resource was stored with.
+    pub async fn list_typed_resources(
         &self,
-        after_seq: Option<String>,
+        offset: usize,
         limit: usize,
-    ) -> Result<Vec<CloudEvent>, Box<dyn std::error::Error + Send + Sync>> {
-        // Read events by sequence lexicographic order from EVENTS_BY_SEQ_TABLE (ensures server processing order).
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+    ) -> Result<Vec<(String, String, JsonValue)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(RESOURCES_TABLE)?;
 
-        let mut results: Vec<CloudEvent> = Vec::new();
+        let mut results = Vec::new();
+        for item in table.iter()?.skip(offset).take(limit) {
+            let (key, value) = item?;
+            let rec: ResourceRecord = bincode::deserialize(value.value())?;
+            let data: JsonValue = serde_json::from_str(&rec.data)?;
+            results.push((key.value().to_string(), rec.resource_type, data));
+        }
+        Ok(results)
+    }
 
-        let iter = table.iter()?;
+    /// Like `list_typed_resources`, but from the first ID after `after`, in ID order. Seeks
+    /// instead of skipping, for reading all resources page by page.
+    pub async fn list_typed_resources_after(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, String, JsonValue)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(RESOURCES_TABLE)?;
+        let lower = match after {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
 
-        for item in iter {
+        let mut results = Vec::new();
+        for item in table.range::<&str>((lower, Bound::Unbounded))?.take(limit) {
             let (key, value) = item?;
-            // If after_seq is provided, skip until key > after_seq
-            if let Some(ref after) = after_seq {
-                // key.value() returns &str; compare lexicographically
-                if key.value() <= after.as_str() {
-                    continue;
-                }
-            }
+            let rec: ResourceRecord = bincode::deserialize(value.value())?;
+            let data: JsonValue = serde_json::from_str(&rec.data)?;
+            results.push((key.value().to_string(), rec.resource_type, data));
+        }
+        Ok(results)
+    }
 
-            let rec: EventRecord = bincode::deserialize(value.value())?;
-            let data: Option<JsonValue> = serde_json::from_str(&rec.data)?;
-
-            let event = CloudEvent {
-                specversion: "1.0".to_string(),
-                id: rec.id,
-                source: rec.source,
-                subject: rec.subject.unwrap_or_else(|| "unknown".to_string()),
-                event_type: rec.event_type,
-                time: rec.time,
-                datacontenttype: Some("application/json".to_string()),
-                dataschema: None,
-                dataref: None,
-                sequence: rec.sequence,
-                sequencetype: None,
-                data,
-            };
+    /// Resources whose ID starts with `prefix`, in ID order.
+    pub async fn list_resources_with_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(String, JsonValue)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(RESOURCES_TABLE)?;
 
-            results.push(event);
-            if results.len() >= limit {
+        let mut results = Vec::new();
+        for item in table.range::<&str>(prefix..)? {
+            let (key, value) = item?;
+            if !key.value().starts_with(prefix) {
                 break;
             }
+            let rec: ResourceRecord = bincode::deserialize(value.value())?;
+            results.push((key.value().to_string(), serde_json::from_str(&rec.data)?));
         }
-
         Ok(results)
     }
 
-    /// Backwards-compatible wrapper: list events by offset (legacy).
-    /// This calls `list_events_after` by computing `after_seq` from offset = number to skip.
-    /// Note: this wrapper is less efficient for large offsets and is provided for compatibility.
-    pub async fn list_events(
+    /// Append an entry to the access log.
+    pub async fn append_access_log(
         &self,
-        offset: usize,
-        limit: usize,
-    ) -> Result<Vec<CloudEvent>, Box<dyn std::error::Error + Send + Sync>> {
-        // If offset is zero, simply return first `limit` events
-        if offset == 0 {
-            return self.list_events_after(None, limit).await;
+        entry: &AccessLogEntry,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let key = format!("{}\0{}\0{}", entry.actor, entry.time, uuid::Uuid::new_v4());
+        let serialized = bincode::serialize(entry)?;
+
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(ACCESS_LOG_TABLE)?;
+            table.insert(key.as_str(), serialized.as_slice())?;
         }
+        write_txn.commit()?;
+        Ok(())
+    }
 
-        // Otherwise, we need to skip `offset` keys - iterate and find the key at position `offset - 1`
-        let read_txn = self.db.begin_read()?;
-        let table = read_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
-        let iter = table.iter()?;
+    /// Access log entries of `actor`, oldest first, optionally limited to `from..=to`
+    /// (timestamps in the same format as `AccessLogEntry::time`).
+    pub async fn list_access_log(
+        &self,
+        actor: &str,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<Vec<AccessLogEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(ACCESS_LOG_TABLE)?;
+
+        // '\0' < any timestamp character < '\u{1}', so these bounds cover exactly the
+        // actor's entries within the time range.
+        let lower = format!("{}\0{}", actor, from.unwrap_or(""));
+        let upper = match to {
+            Some(to) => format!("{}\0{}\u{1}", actor, to),
+            None => format!("{}\u{1}", actor),
+        };
 
-        let mut seq_to_start: Option<String> = None;
-        for (i, item) in iter.enumerate() {
-            let (key, _value) = item?;
-            if i + 1 == offset {
-                seq_to_start = Some(key.value().to_string());
-                break;
-            }
+        let mut entries = Vec::new();
+        for item in table.range::<&str>(lower.as_str()..upper.as_str())? {
+            let (_key, value) = item?;
+            entries.push(bincode::deserialize(value.value())?);
         }
-
-        // If we found the sequence key at offset-1, start after it; otherwise start from beginning
-        let after_seq = seq_to_start.map(|s| s);
-        self.list_events_after(after_seq, limit).await
+        Ok(entries)
+    }
+
+    /// Record that `child_id` replies to `parent_id`; `seq` is the sequence key of the
+    /// event that created the reply, which orders a comment's replies.
+    pub async fn add_comment_reply(
+        &self,
+        parent_id: &str,
+        seq: &str,
+        child_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let key = format!("{}\0{}", parent_id, seq);
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(COMMENT_REPLIES_TABLE)?;
+            table.insert(key.as_str(), child_id)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// IDs of the direct replies to a comment, oldest first.
+    pub async fn list_comment_replies(
+        &self,
+        parent_id: &str,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(COMMENT_REPLIES_TABLE)?;
+
+        let lower = format!("{}\0", parent_id);
+        let upper = format!("{}\u{1}", parent_id);
+        let mut replies = Vec::new();
+        for item in table.range::<&str>(lower.as_str()..upper.as_str())? {
+            let (_key, value) = item?;
+            let child = value.value().to_string();
+            // A reply that was moved away and back is indexed twice; list it once
+            if !replies.contains(&child) {
+                replies.push(child);
+            }
+        }
+        Ok(replies)
+    }
+
+    /// Record that `relation_id` links `source_id` and `target_id`, under both issues.
+    pub async fn add_issue_relation(
+        &self,
+        relation_id: &str,
+        source_id: &str,
+        target_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(ISSUE_RELATIONS_TABLE)?;
+            for issue_id in [source_id, target_id] {
+                let key = format!("{}\0{}", issue_id, relation_id);
+                table.insert(key.as_str(), relation_id)?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// IDs of the relations that were ever created on an issue. Unlinked relations are
+    /// deleted resources, so callers skip IDs that no longer resolve.
+    pub async fn list_issue_relations(
+        &self,
+        issue_id: &str,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(ISSUE_RELATIONS_TABLE)?;
+
+        let lower = format!("{}\0", issue_id);
+        let upper = format!("{}\u{1}", issue_id);
+        let mut relations = Vec::new();
+        for item in table.range::<&str>(lower.as_str()..upper.as_str())? {
+            let (_key, value) = item?;
+            relations.push(value.value().to_string());
+        }
+        Ok(relations)
+    }
+
+    /// Start or stop `user` watching `issue_id`.
+    pub async fn set_watching(
+        &self,
+        issue_id: &str,
+        user: &str,
+        watching: bool,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let key = format!("{}\0{}", issue_id, user);
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(WATCHERS_TABLE)?;
+            if watching {
+                table.insert(key.as_str(), user)?;
+            } else {
+                table.remove(key.as_str())?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Mute `issue_id` for `user` until `until` (RFC 3339; `None`: until unmuted).
+    pub async fn set_mute(
+        &self,
+        issue_id: &str,
+        user: &str,
+        until: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let key = format!("{}\0{}", issue_id, user);
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(MUTES_TABLE)?;
+            table.insert(key.as_str(), until.unwrap_or_default())?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Remove the mute of `issue_id` for `user`, if any.
+    pub async fn remove_mute(
+        &self,
+        issue_id: &str,
+        user: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let key = format!("{}\0{}", issue_id, user);
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(MUTES_TABLE)?;
+            table.remove(key.as_str())?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// The mute of `issue_id` for `user`: `Some(until)`, with "" for a mute until undone.
+    /// Expired mutes are returned too.
+    pub async fn get_mute(
+        &self,
+        issue_id: &str,
+        user: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let key = format!("{}\0{}", issue_id, user);
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(MUTES_TABLE)?;
+        Ok(table.get(key.as_str())?.map(|v| v.value().to_string()))
+    }
+
+    /// Whether `user` watches `issue_id`.
+    pub async fn is_watching(
+        &self,
+        issue_id: &str,
+        user: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let key = format!("{}\0{}", issue_id, user);
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(WATCHERS_TABLE)?;
+        Ok(table.get(key.as_str())?.is_some())
+    }
+
+    /// Users watching `issue_id`, in ID order.
+    pub async fn list_watchers(
+        &self,
+        issue_id: &str,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(WATCHERS_TABLE)?;
+
+        let lower = format!("{}\0", issue_id);
+        let upper = format!("{}\u{1}", issue_id);
+        let mut watchers = Vec::new();
+        for item in table.range::<&str>(lower.as_str()..upper.as_str())? {
+            let (_key, value) = item?;
+            watchers.push(value.value().to_string());
+        }
+        Ok(watchers)
+    }
+
+    /// Record that `users` were seen at `seen` (RFC 3339). Earlier sightings never
+    /// overwrite later ones.
+    pub async fn record_users(
+        &self,
+        users: &[&str],
+        seen: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(USERS_TABLE)?;
+            for user in users {
+                let newer = table.get(*user)?.is_none_or(|last| last.value() < seen);
+                if newer {
+                    table.insert(*user, seen)?;
+                }
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Every user seen so far with when they were last seen, in email order.
+    pub async fn list_users(
+        &self,
+    ) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(USERS_TABLE)?;
+        let mut users = Vec::new();
+        for item in table.iter()? {
+            let (key, value) = item?;
+            users.push((key.value().to_string(), value.value().to_string()));
+        }
+        Ok(users)
+    }
+
+    /// Store (or with `None`, remove) the calendar settings of `user`.
+    pub async fn set_calendar(
+        &self,
+        user: &str,
+        settings: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(CALENDARS_TABLE)?;
+            match settings {
+                Some(settings) => {
+                    table.insert(user, settings)?;
+                }
+                None => {
+                    table.remove(user)?;
+                }
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// The calendar settings of every user who configured them, in user order.
+    pub async fn list_calendars(
+        &self,
+    ) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(CALENDARS_TABLE)?;
+        let mut calendars = Vec::new();
+        for item in table.iter()? {
+            let (key, value) = item?;
+            calendars.push((key.value().to_string(), value.value().to_string()));
+        }
+        Ok(calendars)
+    }
+
+    /// Store (or with `None`, remove) the id of `user`'s calendar feed token.
+    pub async fn set_calendar_feed(
+        &self,
+        user: &str,
+        token_id: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(CALENDAR_FEEDS_TABLE)?;
+            match token_id {
+                Some(token_id) => {
+                    table.insert(user, token_id)?;
+                }
+                None => {
+                    table.remove(user)?;
+                }
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// The id of `user`'s current calendar feed token.
+    pub async fn get_calendar_feed(
+        &self,
+        user: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(CALENDAR_FEEDS_TABLE)?;
+        Ok(table.get(user)?.map(|v| v.value().to_string()))
+    }
+
+    /// Store (or with `None`, remove) the session of upload `id`.
+    pub async fn set_upload(
+        &self,
+        id: &str,
+        session: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(UPLOADS_TABLE)?;
+            match session {
+                Some(session) => {
+                    table.insert(id, session)?;
+                }
+                None => {
+                    table.remove(id)?;
+                }
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// The session of upload `id`, if any.
+    pub async fn get_upload(
+        &self,
+        id: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(UPLOADS_TABLE)?;
+        Ok(table.get(id)?.map(|v| v.value().to_string()))
+    }
+
+    /// All upload sessions, in ID order.
+    pub async fn list_uploads(
+        &self,
+    ) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(UPLOADS_TABLE)?;
+        let mut uploads = Vec::new();
+        for item in table.iter()? {
+            let (key, value) = item?;
+            uploads.push((key.value().to_string(), value.value().to_string()));
+        }
+        Ok(uploads)
+    }
+
+    /// Record (or with `None`, forget) the iCalendar object pushed as `uid` for `user`.
+    pub async fn set_calendar_entry(
+        &self,
+        user: &str,
+        uid: &str,
+        ics: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let key = format!("{}\0{}", user, uid);
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(CALENDAR_ENTRIES_TABLE)?;
+            match ics {
+                Some(ics) => {
+                    table.insert(key.as_str(), ics)?;
+                }
+                None => {
+                    table.remove(key.as_str())?;
+                }
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// The iCalendar objects pushed for `user`, by UID.
+    pub async fn list_calendar_entries(
+        &self,
+        user: &str,
+    ) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(CALENDAR_ENTRIES_TABLE)?;
+
+        let lower = format!("{}\0", user);
+        let upper = format!("{}\u{1}", user);
+        let mut entries = Vec::new();
+        for item in table.range::<&str>(lower.as_str()..upper.as_str())? {
+            let (key, value) = item?;
+            if let Some((_, uid)) = key.value().split_once('\0') {
+                entries.push((uid.to_string(), value.value().to_string()));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// The oldest `limit` outbox entries, by sequence key.
+    pub async fn list_outbox(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<(String, OutboxEntry)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(OUTBOX_TABLE)?;
+        let mut entries = Vec::new();
+        for item in table.iter()?.take(limit) {
+            let (key, value) = item?;
+            entries.push((
+                key.value().to_string(),
+                serde_json::from_str(value.value())?,
+            ));
+        }
+        Ok(entries)
+    }
+
+    /// Update (or with `None`, remove) the outbox entry of the event at `seq_key`.
+    pub async fn set_outbox_entry(
+        &self,
+        seq_key: &str,
+        entry: Option<&OutboxEntry>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(OUTBOX_TABLE)?;
+            match entry {
+                Some(entry) => {
+                    table.insert(seq_key, serde_json::to_string(entry)?.as_str())?;
+                }
+                None => {
+                    table.remove(seq_key)?;
+                }
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// A value of the read model of `projection`.
+    pub async fn get_projection_value(
+        &self,
+        projection: &str,
+        key: &str,
+    ) -> Result<Option<JsonValue>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(PROJECTIONS_TABLE)?;
+        let key = format!("{}\0{}", projection, key);
+        match table.get(key.as_str())? {
+            Some(value) => Ok(Some(serde_json::from_str(value.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// All keys and values of the read model of `projection`, ordered by key.
+    pub async fn list_projection_values(
+        &self,
+        projection: &str,
+    ) -> Result<Vec<(String, JsonValue)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(PROJECTIONS_TABLE)?;
+        let prefix = format!("{}\0", projection);
+        let mut values = Vec::new();
+        for item in table.range(prefix.as_str()..)? {
+            let (key, value) = item?;
+            let Some(key) = key
+                .value()
+                .strip_prefix(prefix.as_str())
+                .map(str::to_string)
+            else {
+                break;
+            };
+            values.push((key, serde_json::from_str(value.value())?));
+        }
+        Ok(values)
+    }
+
+    /// Sequence key of the last event `projection` processed.
+    pub async fn projection_cursor(
+        &self,
+        projection: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(PROJECTION_CURSORS_TABLE)?;
+        Ok(table.get(projection)?.map(|v| v.value().to_string()))
+    }
+
+    /// Write the changes (`None` removes a key) `projection` made for the event at `seq_key`
+    /// and move its cursor there, in one transaction.
+    pub async fn apply_projection_changes(
+        &self,
+        projection: &str,
+        seq_key: &str,
+        changes: &[(String, Option<JsonValue>)],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(PROJECTIONS_TABLE)?;
+            for (key, value) in changes {
+                let key = format!("{}\0{}", projection, key);
+                match value {
+                    Some(value) => {
+                        table.insert(key.as_str(), serde_json::to_string(value)?.as_str())?;
+                    }
+                    None => {
+                        table.remove(key.as_str())?;
+                    }
+                }
+            }
+            let mut cursors = write_txn.open_table(PROJECTION_CURSORS_TABLE)?;
+            cursors.insert(projection, seq_key)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Remove the read model and the cursor of `projection`, so it is rebuilt from the start.
+    pub async fn clear_projection(
+        &self,
+        projection: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(PROJECTIONS_TABLE)?;
+            let prefix = format!("{}\0", projection);
+            let keys: Vec<String> = table
+                .range(prefix.as_str()..)?
+                .map(|r| r.map(|(k, _)| k.value().to_string()))
+                .take_while(|k| k.as_ref().map_or(true, |k| k.starts_with(prefix.as_str())))
+                .collect::<Result<_, _>>()?;
+            for key in keys {
+                table.remove(key.as_str())?;
+            }
+            let mut cursors = write_txn.open_table(PROJECTION_CURSORS_TABLE)?;
+            cursors.remove(projection)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Store version `version` of the schema of `event_type`.
+    pub async fn put_schema_version(
+        &self,
+        event_type: &str,
+        version: u32,
+        record: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let key = format!("{}\0{:010}", event_type, version);
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(SCHEMA_REGISTRY_TABLE)?;
+            table.insert(key.as_str(), record)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// The registered schema versions of `event_type`, oldest first.
+    pub async fn list_schema_versions(
+        &self,
+        event_type: &str,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(SCHEMA_REGISTRY_TABLE)?;
+
+        let lower = format!("{}\0", event_type);
+        let upper = format!("{}\u{1}", event_type);
+        let mut versions = Vec::new();
+        for item in table.range::<&str>(lower.as_str()..upper.as_str())? {
+            let (_, value) = item?;
+            versions.push(value.value().to_string());
+        }
+        Ok(versions)
+    }
+
+    /// The event types with registered schemas, in order.
+    pub async fn list_schema_event_types(
+        &self,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(SCHEMA_REGISTRY_TABLE)?;
+        let mut event_types: Vec<String> = Vec::new();
+        for item in table.iter()? {
+            let (key, _) = item?;
+            if let Some((event_type, _)) = key.value().split_once('\0') {
+                if event_types.last().map(String::as_str) != Some(event_type) {
+                    event_types.push(event_type.to_string());
+                }
+            }
+        }
+        Ok(event_types)
+    }
+
+    /// Register `resource_type` as the type of the resources with schema `schema_url`.
+    pub async fn put_resource_type(
+        &self,
+        schema_url: &str,
+        resource_type: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(RESOURCE_TYPES_TABLE)?;
+            table.insert(schema_url, resource_type)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// The resource type registered for `schema_url`, if any.
+    pub async fn get_resource_type_for_schema(
+        &self,
+        schema_url: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(RESOURCE_TYPES_TABLE)?;
+        Ok(table.get(schema_url)?.map(|v| v.value().to_string()))
+    }
+
+    /// The registered schema URLs with their resource types, by URL.
+    pub async fn list_resource_types(
+        &self,
+    ) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(RESOURCE_TYPES_TABLE)?;
+        let mut types = Vec::new();
+        for item in table.iter()? {
+            let (key, value) = item?;
+            types.push((key.value().to_string(), value.value().to_string()));
+        }
+        Ok(types)
+    }
+
+    /// Store (or with `None`, remove) the settings of an inbound webhook integration.
+    pub async fn set_integration(
+        &self,
+        name: &str,
+        settings: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_txn = self.db().begin_write()?;
+        {
+            let mut table = write_txn.open_table(INTEGRATIONS_TABLE)?;
+            match settings {
+                Some(settings) => {
+                    table.insert(name, settings)?;
+                }
+                None => {
+                    table.remove(name)?;
+                }
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// The settings of an inbound webhook integration.
+    pub async fn get_integration(
+        &self,
+        name: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(INTEGRATIONS_TABLE)?;
+        Ok(table.get(name)?.map(|v| v.value().to_string()))
+    }
+
+    /// All inbound webhook integrations with their settings, in name order.
+    pub async fn list_integrations(
+        &self,
+    ) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(INTEGRATIONS_TABLE)?;
+        let mut integrations = Vec::new();
+        for item in table.iter()? {
+            let (key, value) = item?;
+            integrations.push((key.value().to_string(), value.value().to_string()));
+        }
+        Ok(integrations)
+    }
+
+    /// Sequence key of the latest event about `subject`.
+    pub async fn latest_subject_sequence(
+        &self,
+        subject: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(SUBJECT_EVENTS_TABLE)?;
+
+        let lower = format!("{}\0", subject);
+        let upper = format!("{}\u{1}", subject);
+        let last = table
+            .range::<&str>(lower.as_str()..upper.as_str())?
+            .next_back();
+        match last {
+            Some(item) => {
+                let (key, _value) = item?;
+                Ok(key.value().split_once('\0').map(|(_, seq)| seq.to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Events about `subject`, in sequence order.
+    pub async fn list_subject_events(
+        &self,
+        subject: &str,
+    ) -> Result<Vec<CloudEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let subject_table = read_txn.open_table(SUBJECT_EVENTS_TABLE)?;
+        let seq_table = read_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+
+        let lower = format!("{}\0", subject);
+        let upper = format!("{}\u{1}", subject);
+        let mut events = Vec::new();
+        for item in subject_table.range::<&str>(lower.as_str()..upper.as_str())? {
+            let (key, _value) = item?;
+            let Some((_, seq)) = key.value().split_once('\0') else {
+                continue;
+            };
+            if let Some(bytes) = seq_table.get(seq)? {
+                let rec = EventRecord::decode(bytes.value())?;
+                events.push(rec.into_cloud_event()?);
+            }
+        }
+        Ok(events)
+    }
+
+    /// Subject of the first commit to `resource_id`: the issue the resource belongs to.
+    pub async fn resource_subject(
+        &self,
+        resource_id: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(RESOURCE_EVENTS_TABLE)?;
+
+        let lower = format!("{}\0", resource_id);
+        let upper = format!("{}\u{1}", resource_id);
+        let first = table.range::<&str>(lower.as_str()..upper.as_str())?.next();
+        match first {
+            Some(item) => {
+                let (_key, value) = item?;
+                Ok(Some(value.value().to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Commits to `resource_id`, whatever their subject, in sequence order.
+    pub async fn list_resource_events(
+        &self,
+        resource_id: &str,
+    ) -> Result<Vec<CloudEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let resource_table = read_txn.open_table(RESOURCE_EVENTS_TABLE)?;
+        let seq_table = read_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+
+        let lower = format!("{}\0", resource_id);
+        let upper = format!("{}\u{1}", resource_id);
+        let mut events = Vec::new();
+        for item in resource_table.range::<&str>(lower.as_str()..upper.as_str())? {
+            let (key, _value) = item?;
+            let Some((_, seq)) = key.value().split_once('\0') else {
+                continue;
+            };
+            if let Some(bytes) = seq_table.get(seq)? {
+                let rec = EventRecord::decode(bytes.value())?;
+                events.push(rec.into_cloud_event()?);
+            }
+        }
+        Ok(events)
+    }
+
+    /// Number of stored events and resources.
+    pub async fn record_counts(
+        &self,
+    ) -> Result<(u64, u64), Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let events = read_txn.open_table(EVENTS_BY_SEQ_TABLE)?.len()?;
+        let resources = read_txn.open_table(RESOURCES_TABLE)?.len()?;
+        Ok((events, resources))
+    }
+
+    /// Number of events about `subject` with a sequence after `after_seq` (all when `None`).
+    pub async fn count_subject_events_after(
+        &self,
+        subject: &str,
+        after_seq: Option<&str>,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(SUBJECT_EVENTS_TABLE)?;
+
+        let lower = match after_seq {
+            Some(after) => Bound::Excluded(format!("{}\0{}", subject, after)),
+            None => Bound::Included(format!("{}\0", subject)),
+        };
+        let upper = Bound::Excluded(format!("{}\u{1}", subject));
+        let lower = lower.as_ref().map(String::as_str);
+        let upper = upper.as_ref().map(String::as_str);
+
+        let mut count = 0;
+        for item in table.range::<&str>((lower, upper))? {
+            item?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Move `user`'s read marker for `subject` forward to `seq`. Markers never move back;
+    /// returns the marker after the update.
+    pub async fn mark_read(
+        &self,
+        user: &str,
+        subject: &str,
+        seq: &str,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let key = format!("{}\0{}", user, subject);
+        let write_txn = self.db().begin_write()?;
+        let marker = {
+            let mut table = write_txn.open_table(READ_MARKERS_TABLE)?;
+            let current = table.get(key.as_str())?.map(|v| v.value().to_string());
+            match current {
+                Some(current) if current.as_str() >= seq => current,
+                _ => {
+                    table.insert(key.as_str(), seq)?;
+                    seq.to_string()
+                }
+            }
+        };
+        write_txn.commit()?;
+        Ok(marker)
+    }
+
+    /// Sequence key of the last event about `subject` that `user` has seen.
+    pub async fn get_read_marker(
+        &self,
+        user: &str,
+        subject: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let key = format!("{}\0{}", user, subject);
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(READ_MARKERS_TABLE)?;
+        Ok(table.get(key.as_str())?.map(|v| v.value().to_string()))
+    }
+
+    /// Number of events about `subject` that `user` has not seen yet.
+    pub async fn unread_count(
+        &self,
+        user: &str,
+        subject: &str,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let marker = self.get_read_marker(user, subject).await?;
+        self.count_subject_events_after(subject, marker.as_deref())
+            .await
+    }
+
+    /// List events by sequence with pagination after a given sequence key.
+    ///
+    /// This function returns events in backend processing order (ascending by sequence).
+    /// Use `after_seq` to fetch events after a particular zero-padded sequence key
+    /// (e.g. "00000000000000000042"). If `after_seq` is `None`, iteration starts at the beginning.
+    pub async fn list_events_after(
+        &self,
+        after_seq: Option<String>,
+        limit: usize,
+    ) -> Result<Vec<CloudEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        // Read events by sequence lexicographic order from EVENTS_BY_SEQ_TABLE (ensures server processing order).
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+
+        let mut results: Vec<CloudEvent> = Vec::new();
+
+        // Seek directly to the first key after `after_seq` using redb's range API,
+        // so pagination costs O(limit) instead of O(total events).
+        let lower = match after_seq.as_deref() {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
+        let iter = table.range::<&str>((lower, Bound::Unbounded))?;
+
+        for item in iter {
+            let (_key, value) = item?;
+
+            let rec = EventRecord::decode(value.value())?;
+            results.push(rec.into_cloud_event()?);
+            if results.len() >= limit {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Backwards-compatible wrapper: list events by offset (legacy).
+    /// This calls `list_events_after` by computing `after_seq` from offset = number to skip.
+    /// Note: this wrapper is less efficient for large offsets and is provided for compatibility.
+    pub async fn list_events(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<CloudEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        // If offset is zero, simply return first `limit` events
+        if offset == 0 {
+            return self.list_events_after(None, limit).await;
+        }
+
+        // Otherwise, we need to skip `offset` keys - iterate and find the key at position `offset - 1`
+        let read_txn = self.db().begin_read()?;
+        let table = read_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+        let iter = table.iter()?;
+
+        let mut seq_to_start: Option<String> = None;
+        for (i, item) in iter.enumerate() {
+            let (key, _value) = item?;
+            if i + 1 == offset {
+                seq_to_start = Some(key.value().to_string());
+                break;
+            }
+        }
+
+        // If we found the sequence key at offset-1, start after it; otherwise start from beginning
+        self.list_events_after(seq_to_start, limit).await
     }
 }
 
@@ -479,7 +2000,7 @@ impl Storage {
 ///
 /// `content` is optional and will be omitted when a structured `event` or `resource`
 /// is present. Clients should prefer `event` or `resource` when available.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct SearchResult {
     /// The identifier for the match. For resources this is the resource id; for events it's the event id.
     pub id: String,
@@ -498,11 +2019,133 @@ pub struct SearchResult {
     /// this will contain the parsed JSON resource.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub resource: Option<JsonValue>,
+    /// For issues the caller is involved in: number of events since their last visit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unread: Option<usize>,
+}
+
+impl Storage {
+    /// Sizes and fragmentation of the database file and its tables.
+    pub fn storage_report(
+        &self,
+    ) -> Result<StorageReport, Box<dyn std::error::Error + Send + Sync>> {
+        let file_bytes = std::fs::metadata(self.data_dir.join("data.redb"))?.len();
+        let db = self.db();
+        let write_txn = db.begin_write()?;
+        let stats = write_txn.stats()?;
+        write_txn.abort()?;
+
+        let read_txn = db.begin_read()?;
+        let mut tables = Vec::new();
+        for handle in read_txn.list_tables()? {
+            let name = handle.name().to_string();
+            let table = read_txn.open_untyped_table(handle)?;
+            let table_stats = table.stats()?;
+            tables.push(TableSize {
+                name,
+                entries: table.len()?,
+                stored_bytes: table_stats.stored_bytes(),
+                metadata_bytes: table_stats.metadata_bytes(),
+                fragmented_bytes: table_stats.fragmented_bytes(),
+            });
+        }
+        tables.sort_by_key(|t| std::cmp::Reverse(t.stored_bytes));
+
+        let used = stats.stored_bytes() + stats.metadata_bytes();
+        Ok(StorageReport {
+            file_bytes,
+            allocated_bytes: stats.allocated_pages() * stats.page_size() as u64,
+            stored_bytes: stats.stored_bytes(),
+            metadata_bytes: stats.metadata_bytes(),
+            fragmented_bytes: stats.fragmented_bytes(),
+            fragmentation: match file_bytes {
+                0 => 0.0,
+                _ => file_bytes.saturating_sub(used) as f64 / file_bytes as f64,
+            },
+            tables,
+        })
+    }
+
+    /// Compact the database file, giving free pages back to the file system. Blocks all
+    /// other storage access while it runs, and fails while a transaction is still open.
+    /// Returns whether anything was compacted.
+    pub fn compact(&self) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut db = self.db.write().unwrap_or_else(|e| e.into_inner());
+        Ok(db.compact()?)
+    }
+
+    /// Remove what deleted resources left behind in the indexes (watchers, read markers,
+    /// relations, replies), and the read models of projections that are no longer
+    /// registered.
+    pub async fn vacuum(
+        &self,
+        deleted: &HashSet<String>,
+        projections: &[&str],
+    ) -> Result<VacuumReport, Box<dyn std::error::Error + Send + Sync>> {
+        let is_deleted = |id: &str| deleted.contains(id);
+        let unregistered = |key: &str| {
+            let name = key.split('\0').next().unwrap_or_default();
+            !projections.contains(&name)
+        };
+        let write_txn = self.db().begin_write()?;
+        let removed = [
+            (
+                WATCHERS_TABLE,
+                remove_where(&write_txn, WATCHERS_TABLE, |key, _| {
+                    key.split('\0').next().is_some_and(is_deleted)
+                })?,
+            ),
+            (
+                MUTES_TABLE,
+                remove_where(&write_txn, MUTES_TABLE, |key, _| {
+                    key.split('\0').next().is_some_and(is_deleted)
+                })?,
+            ),
+            (
+                READ_MARKERS_TABLE,
+                remove_where(&write_txn, READ_MARKERS_TABLE, |key, _| {
+                    key.split_once('\0')
+                        .is_some_and(|(_, subject)| is_deleted(subject))
+                })?,
+            ),
+            (
+                ISSUE_RELATIONS_TABLE,
+                remove_where(&write_txn, ISSUE_RELATIONS_TABLE, |key, relation| {
+                    key.split('\0').next().is_some_and(is_deleted) || is_deleted(relation)
+                })?,
+            ),
+            (
+                COMMENT_REPLIES_TABLE,
+                remove_where(&write_txn, COMMENT_REPLIES_TABLE, |key, child| {
+                    key.split('\0').next().is_some_and(is_deleted) || is_deleted(child)
+                })?,
+            ),
+            (
+                PROJECTIONS_TABLE,
+                remove_where(&write_txn, PROJECTIONS_TABLE, |key, _| unregistered(key))?,
+            ),
+            (
+                PROJECTION_CURSORS_TABLE,
+                remove_where(&write_txn, PROJECTION_CURSORS_TABLE, |key, _| {
+                    unregistered(key)
+                })?,
+            ),
+        ];
+        write_txn.commit()?;
+        Ok(VacuumReport {
+            deleted_resources: deleted.len(),
+            removed: removed
+                .into_iter()
+                .map(|(table, count)| (table.name().to_string(), count))
+                .collect(),
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::handlers::tests::event;
     use tempfile::TempDir;
 
     #[tokio::test]
@@ -517,19 +2160,166 @@ mod tests {
             subject: "test-subject".to_string(),
             event_type: "test.event".to_string(),
             time: Some(chrono::Utc::now().to_rfc3339()),
+            time_received: Some("2026-01-05T09:00:00+00:00".to_string()),
             datacontenttype: Some("application/json".to_string()),
             dataschema: None,
             dataref: None,
             sequence: Some("1".to_string()),
+            sequencenumber: None,
             sequencetype: None,
             data: Some(serde_json::json!({"key": "value"})),
         };
 
         let _seq = storage.store_event(&event).await.unwrap();
 
-        let retrieved = storage.get_event("test-event-1").await.unwrap();
-        assert!(retrieved.is_some());
-        assert_eq!(retrieved.unwrap().id, "test-event-1");
+        let retrieved = storage.get_event("test-event-1").await.unwrap().unwrap();
+        assert_eq!(retrieved.id, "test-event-1");
+        assert_eq!(
+            retrieved.time_received.as_deref(),
+            Some("2026-01-05T09:00:00+00:00")
+        );
+
+        assert!(storage.get_event("missing").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_legacy_event_records_decode() {
+        // Records from before `time_received`: the same fields, without it
+        let legacy = bincode::serialize(&(
+            "event-1",
+            "json.commit",
+            "test",
+            Some("issue-1"),
+            Some("2025-06-01T12:00:00+00:00"),
+            Some("00000000000000000001"),
+            "null",
+        ))
+        .unwrap();
+        let record = EventRecord::decode(&legacy).unwrap();
+        assert_eq!(record.id, "event-1");
+        assert_eq!(record.sequence.as_deref(), Some("00000000000000000001"));
+        assert_eq!(record.time_received, None);
+        assert!(EventRecord::decode(b"garbage").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_event_id_index_backfilled_on_open() {
+        let temp_dir = TempDir::new().unwrap();
+        {
+            let storage = Storage::new(temp_dir.path()).await.unwrap();
+            let event = event("legacy-event", "test-subject");
+            storage.store_event(&event).await.unwrap();
+
+            // Simulate a database written before the id index (and migrations) existed
+            let write_txn = storage.db().begin_write().unwrap();
+            write_txn.delete_table(EVENT_IDS_TABLE).unwrap();
+            write_txn
+                .open_table(META_TABLE)
+                .unwrap()
+                .remove("schema_version")
+                .unwrap();
+            write_txn.commit().unwrap();
+        }
+
+        let storage = Storage::new(temp_dir.path()).await.unwrap();
+        let retrieved = storage.get_event("legacy-event").await.unwrap();
+        assert_eq!(retrieved.unwrap().id, "legacy-event");
+        assert_eq!(storage.schema_version().unwrap(), latest_version());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_dry_run_and_rollback() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(temp_dir.path()).await.unwrap();
+        assert_eq!(storage.schema_version().unwrap(), latest_version());
+        assert!(storage.migrate(false).unwrap().steps.is_empty());
+        let event = crate::schemas::CloudEventBuilder::new("test.event", "issue-1")
+            .id("event-1")
+            .build();
+        storage.store_event(&event).await.unwrap();
+
+        // Back to a record from before `time_received`, at the version before that migration
+        let stored = |storage: &Storage| {
+            let read_txn = storage.db().begin_read().unwrap();
+            let table = read_txn.open_table(EVENTS_BY_SEQ_TABLE).unwrap();
+            let bytes = table.get("00000000000000000001").unwrap().unwrap();
+            bytes.value().to_vec()
+        };
+        let is_legacy = |bytes: &[u8]| bincode::deserialize::<EventRecord>(bytes).is_err();
+        let report = storage.rollback(3, false).unwrap();
+        assert_eq!(report.steps.len(), 2);
+        assert_eq!(report.steps[1].changed, 1);
+        assert_eq!(storage.schema_version().unwrap(), 3);
+        assert!(is_legacy(&stored(&storage)));
+
+        let report = storage.migrate(true).unwrap();
+        assert!(report.dry_run);
+        assert_eq!((report.from_version, report.to_version), (3, 5));
+        assert_eq!(report.steps[0].changed, 1);
+        assert_eq!(storage.schema_version().unwrap(), 3);
+        assert!(is_legacy(&stored(&storage)));
+
+        storage.migrate(false).unwrap();
+        assert_eq!(storage.schema_version().unwrap(), 5);
+        assert!(!is_legacy(&stored(&storage)));
+        assert_eq!(
+            storage.get_event("event-1").await.unwrap().unwrap().subject,
+            "issue-1"
+        );
+
+        // Rolling back the indexes empties them; migrating rebuilds them
+        let report = storage.rollback(0, true).unwrap();
+        assert_eq!(report.steps.len(), 5);
+        assert_eq!(storage.schema_version().unwrap(), 5);
+        storage.rollback(0, false).unwrap();
+        assert!(storage.get_event("event-1").await.unwrap().is_none());
+        storage.migrate(false).unwrap();
+        assert!(storage.get_event("event-1").await.unwrap().is_some());
+
+        // Data of a newer build is not touched
+        let write_txn = storage.db().begin_write().unwrap();
+        write_txn
+            .open_table(META_TABLE)
+            .unwrap()
+            .insert("schema_version", b"99".as_slice())
+            .unwrap();
+        write_txn.commit().unwrap();
+        assert!(storage.migrate(false).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_integer_sequences_and_gaps() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(temp_dir.path()).await.unwrap();
+        assert!(storage.sequence_gaps().await.unwrap().is_empty());
+        for i in 1..=4 {
+            let event = crate::schemas::CloudEventBuilder::new("test.event", "test-subject")
+                .id(format!("event-{}", i))
+                .build();
+            storage.store_event(&event).await.unwrap();
+        }
+        let event = storage.get_event("event-3").await.unwrap().unwrap();
+        assert_eq!(event.sequence.as_deref(), Some("00000000000000000003"));
+        assert_eq!(event.sequencenumber, Some(3));
+        assert_eq!(event.sequencetype.as_deref(), Some("Integer"));
+
+        // Lose event 2, and assign 5 and 6 without storing them (e.g. a failed import)
+        let write_txn = storage.db().begin_write().unwrap();
+        {
+            let mut events = write_txn.open_table(EVENTS_BY_SEQ_TABLE).unwrap();
+            events.remove("00000000000000000002").unwrap();
+            let mut meta = write_txn.open_table(META_TABLE).unwrap();
+            meta.insert("last_seq", "6".as_bytes()).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        assert_eq!(
+            storage.sequence_gaps().await.unwrap(),
+            vec![
+                SequenceGap { from: 2, to: 2 },
+                SequenceGap { from: 5, to: 6 }
+            ]
+        );
     }
 
     #[tokio::test]
@@ -620,6 +2410,38 @@ mod tests {
         assert_eq!(page2.len(), 2);
     }
 
+    #[tokio::test]
+    async fn test_list_events_after_seeks_past_sequence() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(temp_dir.path()).await.unwrap();
+
+        let mut seqs = Vec::new();
+        for i in 1..=5 {
+            let event = event(&format!("evt-{}", i), "subject");
+            seqs.push(storage.store_event(&event).await.unwrap());
+        }
+
+        let all = storage.list_events_after(None, 10).await.unwrap();
+        assert_eq!(all.len(), 5);
+        assert_eq!(
+            storage.latest_sequence().await.unwrap().as_deref(),
+            Some(seqs[4].as_str())
+        );
+
+        let after_two = storage
+            .list_events_after(Some(seqs[1].clone()), 2)
+            .await
+            .unwrap();
+        let ids: Vec<_> = after_two.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["evt-3", "evt-4"]);
+
+        let after_last = storage
+            .list_events_after(Some(seqs[4].clone()), 10)
+            .await
+            .unwrap();
+        assert!(after_last.is_empty());
+    }
+
     #[tokio::test]
     async fn test_delete_resource() {
         let temp_dir = TempDir::new().unwrap();
@@ -0,0 +1,362 @@
+//! Versioned data migrations.
+//!
+//! The META table records the schema version of the data (`schema_version`; absent is 0).
+//! When a database is opened, every migration in [`MIGRATIONS`] above that version runs in
+//! order, each in its own transaction together with the new version, so a failing migration
+//! leaves the database at the last version that completed. A database of a newer version
+//! than this build knows is refused instead of being misread.
+//!
+//! A dry run applies the pending migrations in one transaction that is then discarded, to
+//! see what they would change. Migrations with a `down` step can be rolled back to an
+//! earlier version (`storage_maintenance rollback`), e.g. before going back to an older
+//! build.
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition, WriteTransaction};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+use super::{
+    committed_resource, EventRecord, LegacyEventRecord, COMMENT_REPLIES_TABLE, EVENTS_BY_SEQ_TABLE,
+    EVENT_IDS_TABLE, META_TABLE, RESOURCE_EVENTS_TABLE, SUBJECT_EVENTS_TABLE,
+};
+
+type StorageError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A step in a migration; returns the number of records it changed
+type Step = fn(&WriteTransaction) -> Result<usize, StorageError>;
+
+const VERSION_KEY: &str = "schema_version";
+
+pub struct Migration {
+    /// The schema version after the migration
+    pub version: u32,
+    pub name: &'static str,
+    up: Step,
+    /// Undoes `up`; `None` when it can't be undone
+    down: Option<Step>,
+}
+
+/// All migrations, by version
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "event id index",
+        up: build_event_ids,
+        down: Some(clear_event_ids),
+    },
+    Migration {
+        version: 2,
+        name: "comment reply index",
+        up: build_comment_replies,
+        down: Some(clear_comment_replies),
+    },
+    Migration {
+        version: 3,
+        name: "subject index",
+        up: build_subject_events,
+        down: Some(clear_subject_events),
+    },
+    Migration {
+        version: 4,
+        name: "time_received in event records",
+        up: event_records_with_time_received,
+        down: Some(legacy_event_records),
+    },
+    Migration {
+        version: 5,
+        name: "resource commit index",
+        up: build_resource_events,
+        down: Some(clear_resource_events),
+    },
+];
+
+/// The version this build migrates to
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// A migration that ran (or would run, in a dry run)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MigrationStep {
+    pub version: u32,
+    pub name: String,
+    /// "up" or "down"
+    pub direction: &'static str,
+    /// Records written or removed
+    pub changed: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// Nothing was written
+    pub dry_run: bool,
+    pub steps: Vec<MigrationStep>,
+}
+
+fn read_version(write_txn: &WriteTransaction) -> Result<u32, StorageError> {
+    let meta = write_txn.open_table(META_TABLE)?;
+    let version = meta.get(VERSION_KEY)?.and_then(|v| {
+        std::str::from_utf8(v.value())
+            .ok()
+            .and_then(|s| s.parse().ok())
+    });
+    Ok(version.unwrap_or(0))
+}
+
+fn write_version(write_txn: &WriteTransaction, version: u32) -> Result<(), StorageError> {
+    let mut meta = write_txn.open_table(META_TABLE)?;
+    meta.insert(VERSION_KEY, version.to_string().as_bytes())?;
+    Ok(())
+}
+
+/// The schema version of the data in `db`.
+pub fn schema_version(db: &Database) -> Result<u32, StorageError> {
+    let write_txn = db.begin_write()?;
+    let version = read_version(&write_txn)?;
+    write_txn.abort()?;
+    Ok(version)
+}
+
+/// Run `steps` (a step, the version after it, and its report), each in its own transaction;
+/// in a dry run all in one transaction that is discarded.
+fn run(
+    db: &Database,
+    steps: Vec<(Step, u32, MigrationStep)>,
+    dry_run: bool,
+) -> Result<Vec<MigrationStep>, StorageError> {
+    let mut done = Vec::new();
+    let mut write_txn = db.begin_write()?;
+    for (step, version, mut report) in steps {
+        report.changed = step(&write_txn)?;
+        write_version(&write_txn, version)?;
+        if !dry_run {
+            write_txn.commit()?;
+            println!(
+                "[storage] migration {} ({}, {}): {} records",
+                report.version, report.name, report.direction, report.changed
+            );
+            write_txn = db.begin_write()?;
+        }
+        done.push(report);
+    }
+    write_txn.abort()?;
+    Ok(done)
+}
+
+/// Bring `db` to [`latest_version`].
+pub fn migrate(db: &Database, dry_run: bool) -> Result<MigrationReport, StorageError> {
+    let from_version = schema_version(db)?;
+    let latest = latest_version();
+    if from_version > latest {
+        return Err(format!(
+            "the data is at schema version {}, newer than this build knows ({}); \
+             roll back with the newer build first",
+            from_version, latest
+        )
+        .into());
+    }
+    let steps = MIGRATIONS
+        .iter()
+        .filter(|m| m.version > from_version)
+        .map(|m| {
+            let report = MigrationStep {
+                version: m.version,
+                name: m.name.to_string(),
+                direction: "up",
+                changed: 0,
+            };
+            (m.up, m.version, report)
+        })
+        .collect();
+    Ok(MigrationReport {
+        from_version,
+        to_version: latest,
+        dry_run,
+        steps: run(db, steps, dry_run)?,
+    })
+}
+
+/// Undo the migrations above `to_version`, newest first.
+pub fn rollback(
+    db: &Database,
+    to_version: u32,
+    dry_run: bool,
+) -> Result<MigrationReport, StorageError> {
+    let from_version = schema_version(db)?;
+    let mut steps = Vec::new();
+    for migration in MIGRATIONS.iter().rev() {
+        if migration.version <= to_version || migration.version > from_version {
+            continue;
+        }
+        let Some(down) = migration.down else {
+            return Err(format!(
+                "migration {} ({}) can't be rolled back",
+                migration.version, migration.name
+            )
+            .into());
+        };
+        let report = MigrationStep {
+            version: migration.version,
+            name: migration.name.to_string(),
+            direction: "down",
+            changed: 0,
+        };
+        steps.push((down, migration.version - 1, report));
+    }
+    Ok(MigrationReport {
+        from_version,
+        to_version: to_version.min(from_version),
+        dry_run,
+        steps: run(db, steps, dry_run)?,
+    })
+}
+
+/// Remove every entry of an index table. Returns how many there were.
+fn clear(
+    write_txn: &WriteTransaction,
+    definition: TableDefinition<&str, &str>,
+) -> Result<usize, StorageError> {
+    let mut table = write_txn.open_table(definition)?;
+    let count = table.len()? as usize;
+    table.retain(|_, _| false)?;
+    Ok(count)
+}
+
+/// EVENT_IDS: event id to sequence key
+fn build_event_ids(write_txn: &WriteTransaction) -> Result<usize, StorageError> {
+    let seq_table = write_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+    let mut id_table = write_txn.open_table(EVENT_IDS_TABLE)?;
+    let mut count = 0;
+    for item in seq_table.iter()? {
+        let (key, value) = item?;
+        let rec = EventRecord::decode(value.value())?;
+        id_table.insert(rec.id.as_str(), key.value())?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn clear_event_ids(write_txn: &WriteTransaction) -> Result<usize, StorageError> {
+    clear(write_txn, EVENT_IDS_TABLE)
+}
+
+/// COMMENT_REPLIES: the comments that quote another, from the commits that created them
+fn build_comment_replies(write_txn: &WriteTransaction) -> Result<usize, StorageError> {
+    let seq_table = write_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+    let mut replies_table = write_txn.open_table(COMMENT_REPLIES_TABLE)?;
+    let mut count = 0;
+    for item in seq_table.iter()? {
+        let (key, value) = item?;
+        let rec = EventRecord::decode(value.value())?;
+        let Ok(data) = serde_json::from_str::<JsonValue>(&rec.data) else {
+            continue;
+        };
+        let child = data.get("resource_id").and_then(|v| v.as_str());
+        let parent = data
+            .get("resource_data")
+            .and_then(|r| r.get("quote_comment"))
+            .and_then(|v| v.as_str());
+        if let (Some(child), Some(parent)) = (child, parent) {
+            let reply_key = format!("{}\0{}", parent, key.value());
+            replies_table.insert(reply_key.as_str(), child)?;
+            count += 1;
+        }
+    }
+    // Set by the backfill this migration replaces
+    write_txn
+        .open_table(META_TABLE)?
+        .remove("comment_replies_backfilled")?;
+    Ok(count)
+}
+
+fn clear_comment_replies(write_txn: &WriteTransaction) -> Result<usize, StorageError> {
+    clear(write_txn, COMMENT_REPLIES_TABLE)
+}
+
+/// SUBJECT_EVENTS: the events per subject, in sequence order
+fn build_subject_events(write_txn: &WriteTransaction) -> Result<usize, StorageError> {
+    let seq_table = write_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+    let mut subject_table = write_txn.open_table(SUBJECT_EVENTS_TABLE)?;
+    let mut count = 0;
+    for item in seq_table.iter()? {
+        let (key, value) = item?;
+        let rec = EventRecord::decode(value.value())?;
+        if let Some(subject) = &rec.subject {
+            let subject_key = format!("{}\0{}", subject, key.value());
+            subject_table.insert(subject_key.as_str(), rec.id.as_str())?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn clear_subject_events(write_txn: &WriteTransaction) -> Result<usize, StorageError> {
+    clear(write_txn, SUBJECT_EVENTS_TABLE)
+}
+
+/// RESOURCE_EVENTS: the commits per resource, in sequence order
+fn build_resource_events(write_txn: &WriteTransaction) -> Result<usize, StorageError> {
+    let seq_table = write_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+    let mut resource_table = write_txn.open_table(RESOURCE_EVENTS_TABLE)?;
+    let mut count = 0;
+    for item in seq_table.iter()? {
+        let (key, value) = item?;
+        let rec = EventRecord::decode(value.value())?;
+        let Ok(data) = serde_json::from_str::<JsonValue>(&rec.data) else {
+            continue;
+        };
+        if let (Some(resource_id), Some(subject)) =
+            (committed_resource(&rec.event_type, &data), &rec.subject)
+        {
+            let resource_key = format!("{}\0{}", resource_id, key.value());
+            resource_table.insert(resource_key.as_str(), subject.as_str())?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn clear_resource_events(write_txn: &WriteTransaction) -> Result<usize, StorageError> {
+    clear(write_txn, RESOURCE_EVENTS_TABLE)
+}
+
+/// Rewrite the event records for which `convert` returns new bytes.
+fn rewrite_events(
+    write_txn: &WriteTransaction,
+    convert: impl Fn(&[u8]) -> Result<Option<Vec<u8>>, StorageError>,
+) -> Result<usize, StorageError> {
+    let mut seq_table = write_txn.open_table(EVENTS_BY_SEQ_TABLE)?;
+    let mut rewritten = Vec::new();
+    for item in seq_table.iter()? {
+        let (key, value) = item?;
+        if let Some(bytes) = convert(value.value())? {
+            rewritten.push((key.value().to_string(), bytes));
+        }
+    }
+    for (key, bytes) in &rewritten {
+        seq_table.insert(key.as_str(), bytes.as_slice())?;
+    }
+    Ok(rewritten.len())
+}
+
+/// Records from before `time_received` in the current format
+fn event_records_with_time_received(write_txn: &WriteTransaction) -> Result<usize, StorageError> {
+    rewrite_events(write_txn, |bytes| {
+        if bincode::deserialize::<EventRecord>(bytes).is_ok() {
+            return Ok(None);
+        }
+        Ok(Some(bincode::serialize(&EventRecord::decode(bytes)?)?))
+    })
+}
+
+/// All records in the format from before `time_received`, which is dropped
+fn legacy_event_records(write_txn: &WriteTransaction) -> Result<usize, StorageError> {
+    rewrite_events(write_txn, |bytes| {
+        if bincode::deserialize::<EventRecord>(bytes).is_err() {
+            return Ok(None);
+        }
+        let legacy = LegacyEventRecord::from(EventRecord::decode(bytes)?);
+        Ok(Some(bincode::serialize(&legacy)?))
+    })
+}
@@ -0,0 +1,324 @@
+//! Offline-first delta sync: `POST /sync`, one round trip to reconcile a client that was
+//! offline.
+//!
+//! The client sends the sequence of the last event it has, and the events (usually commits)
+//! it queued while offline, in the order it made them. Each queued event is submitted through
+//! the event pipeline like `POST /events`, on its own: a commit made on a stale version comes
+//! back with its `MergeConflict` (see `conflicts`), without stopping the others. Events that
+//! are already stored, because an earlier sync got through but its answer did not, are not
+//! stored twice: storage refuses a second event with the same `source` and `id` in the
+//! transaction that would store it. Such an event counts as the caller's only if its commit
+//! names them as actor; otherwise the queued event gets a 409. The answer lists the outcome per queued event, followed by the events after
+//! the client's sequence that it may see, including its own just applied ones.
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::conflicts::MergeConflict;
+use crate::handlers::{commit_of, may_see_event, AppState};
+use crate::pipeline::{EventContext, ProcessError};
+use crate::schemas::CloudEvent;
+
+/// Most queued events in one sync
+pub const MAX_SYNC_EVENTS: usize = 500;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SyncRequest {
+    /// Sequence of the last event the client has; everything when absent
+    #[serde(default)]
+    pub after_seq: Option<String>,
+    /// Events queued while offline, oldest first
+    #[serde(default)]
+    pub events: Vec<CloudEvent>,
+    /// Most missed events in the answer
+    #[serde(default = "default_sync_limit")]
+    pub limit: usize,
+}
+
+fn default_sync_limit() -> usize {
+    500
+}
+
+/// The outcome of one queued event
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SyncResult {
+    /// ID of the queued event
+    pub id: String,
+    /// 202 when applied, 200 when the caller's event was already stored, 409 when someone
+    /// else's event has its source and id, otherwise the HTTP status `POST /events` would
+    /// have answered with
+    pub status: u16,
+    /// Sequence number of the stored event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The conflicting fields, for a 409
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<MergeConflict>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SyncResponse {
+    /// One result per queued event, in request order
+    pub results: Vec<SyncResult>,
+    /// Events after `after_seq` the caller may see, in sequence order
+    pub events: Vec<CloudEvent>,
+    /// Pass this as `after_seq` in the next sync
+    pub after_seq: Option<String>,
+    /// There are more events; sync again to get them
+    pub has_more: bool,
+}
+
+impl SyncResult {
+    fn stored(id: &str, status: StatusCode, event: &CloudEvent) -> Self {
+        SyncResult {
+            id: id.to_string(),
+            status: status.as_u16(),
+            sequence: event.sequencenumber,
+            error: None,
+            conflict: None,
+        }
+    }
+
+    fn failed(id: &str, status: StatusCode, error: impl Into<String>) -> Self {
+        SyncResult {
+            id: id.to_string(),
+            status: status.as_u16(),
+            sequence: None,
+            error: Some(error.into()),
+            conflict: None,
+        }
+    }
+}
+
+/// The answer for a queued event of `user` whose source and id `stored` already has.
+fn already_stored(id: &str, user: &str, stored: &CloudEvent) -> SyncResult {
+    match commit_of(stored).is_some_and(|commit| commit.actor == user) {
+        true => SyncResult::stored(id, StatusCode::OK, stored),
+        false => SyncResult::failed(id, StatusCode::CONFLICT, "event id already in use"),
+    }
+}
+
+async fn apply(state: &AppState, user: &str, event: CloudEvent) -> SyncResult {
+    let id = event.id.clone();
+    // Answer a retry without running the pipeline (where it would conflict with itself);
+    // storage checks again when a retry races this
+    match state.storage.get_event(&id).await {
+        Ok(Some(stored)) if stored.source == event.source => {
+            return already_stored(&id, user, &stored)
+        }
+        Ok(_) => {}
+        Err(e) => return SyncResult::failed(&id, StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+    let ctx = EventContext::new(event).inbound(Some(user.to_string()));
+    match state.pipeline.submit(state, ctx).await {
+        Ok(event) => SyncResult::stored(&id, StatusCode::ACCEPTED, &event),
+        Err(ProcessError::Duplicate(stored)) => already_stored(&id, user, &stored),
+        Err(ProcessError::Conflict(conflict)) => SyncResult {
+            conflict: Some(*conflict),
+            ..SyncResult::failed(&id, StatusCode::CONFLICT, "stale base_sequence")
+        },
+        Err(e) => {
+            eprintln!("[sync] queued event {} not applied: {}", id, e);
+            SyncResult::failed(&id, e.status(), e.to_string())
+        }
+    }
+}
+
+/// POST /sync - Apply queued events and catch up, in one request
+#[utoipa::path(
+    post,
+    path = "/sync",
+    tag = "events",
+    request_body = SyncRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The outcome per queued event, and the missed events", body = SyncResponse),
+        (status = 400, description = "More than 500 queued events, or a limit of 0"),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn sync_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(request): Json<SyncRequest>,
+) -> Result<Json<SyncResponse>, StatusCode> {
+    let user = auth_user.user_id;
+    if request.events.len() > MAX_SYNC_EVENTS || request.limit == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let queued = request.events.len();
+    let mut results = Vec::with_capacity(queued);
+    for event in request.events {
+        results.push(apply(&state, &user, event).await);
+    }
+
+    // Read after applying, so the client gets the sequences of its own events too
+    let page = state
+        .storage
+        .list_events_after(request.after_seq.clone(), request.limit)
+        .await
+        .map_err(|e| {
+            eprintln!("[sync] failed to list events: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let has_more = page.len() == request.limit;
+    let mut after_seq = request.after_seq;
+    let mut events = Vec::new();
+    for event in page {
+        if event.sequence.is_some() {
+            after_seq = event.sequence.clone();
+        }
+        if may_see_event(&state.storage, &user, &event).await {
+            events.push(event);
+        }
+    }
+    println!(
+        "[sync] {} synced {} queued events, {} events back",
+        user,
+        queued,
+        events.len()
+    );
+    Ok(Json(SyncResponse {
+        results,
+        events,
+        after_seq,
+        has_more,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{
+        submit_event,
+        tests::{auth_user as user, create_issue, issue, submit_commit_event, test_state},
+    };
+    use crate::schemas::{CloudEventBuilder, CommitBuilder, Issue};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_offline_sync() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let created =
+            create_issue(&state, "issue-1", &issue("Lantaarnpaal", &[alice]), alice).await;
+        let base = created.sequencenumber.unwrap();
+        let seen = created.sequence.clone();
+
+        // Someone else changes the title while alice is offline
+        let commit = CommitBuilder::patch::<Issue>("issue-1", json!({"title": "Lantaarn kapot"}))
+            .base_sequence(base)
+            .build();
+        submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+
+        // Offline, alice closed the issue and renamed it on the old version
+        let queued = |patch: serde_json::Value| {
+            let commit = CommitBuilder::patch::<Issue>("issue-1", patch)
+                .actor(alice)
+                .base_sequence(base)
+                .build();
+            CloudEventBuilder::commit("issue-1", &commit).build()
+        };
+        let close = queued(json!({"status": "closed"}));
+        let rename = queued(json!({"title": "Lantaarnpaal Dorpsstraat"}));
+        let request = |events: Vec<CloudEvent>| {
+            Json(SyncRequest {
+                after_seq: seen.clone(),
+                events,
+                limit: 100,
+            })
+        };
+        let Json(response) = sync_handler(
+            State(state.clone()),
+            user(alice),
+            request(vec![close.clone(), rename]),
+        )
+        .await
+        .unwrap();
+        let statuses: Vec<u16> = response.results.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, vec![202, 409]);
+        let conflict = response.results[1].conflict.as_ref().unwrap();
+        assert_eq!(conflict.conflicts[0].path, "/title");
+        // The missed change, and alice's own close
+        assert_eq!(response.events.len(), 2);
+        assert_eq!(response.events[1].id, close.id);
+        assert!(!response.has_more);
+
+        // Retrying after a lost answer does not apply the close twice
+        let Json(retry) = sync_handler(
+            State(state.clone()),
+            user(alice),
+            request(vec![close.clone()]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(retry.results[0].status, 200);
+        assert_eq!(retry.results[0].sequence, response.results[0].sequence);
+        assert_eq!(retry.events.len(), 2);
+        assert_eq!(retry.after_seq, response.after_seq);
+
+        // Someone else's event id doesn't get mallory its sequence
+        let mallory = "mallory@evil.com";
+        let Json(stolen) = sync_handler(State(state.clone()), user(mallory), request(vec![close]))
+            .await
+            .unwrap();
+        assert_eq!(stolen.results[0].status, 409);
+        assert_eq!(stolen.results[0].sequence, None);
+        // Nor does syncing get her into, or let her see, an issue she has no access to
+        assert!(stolen.events.is_empty());
+        let commit = CommitBuilder::patch::<Issue>("issue-1", json!({"title": "Van mij"}))
+            .actor(mallory)
+            .build();
+        let intrusion = CloudEventBuilder::commit("issue-1", &commit).build();
+        let Json(refused) = sync_handler(
+            State(state.clone()),
+            user(mallory),
+            request(vec![intrusion]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(refused.results[0].status, 403);
+
+        let no_limit = Json(SyncRequest {
+            after_seq: None,
+            events: vec![],
+            limit: 0,
+        });
+        let refused = sync_handler(State(state), user(alice), no_limit).await;
+        assert_eq!(refused.unwrap_err(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_are_refused_in_the_store_transaction() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let commit = CommitBuilder::create("issue-1", &issue("Lantaarnpaal", &[])).build();
+        let event = CloudEventBuilder::commit("issue-1", &commit).build();
+        let stored = submit_event(&state, event.clone()).await.unwrap();
+
+        // Even when the lookup before it missed the first one
+        let again = state
+            .pipeline
+            .submit(&state, EventContext::new(event.clone()));
+        match again.await {
+            Err(ProcessError::Duplicate(duplicate)) => {
+                assert_eq!(duplicate.sequence, stored.sequence)
+            }
+            other => panic!("expected a duplicate, got {:?}", other.map(|e| e.id)),
+        }
+        // The same id from another source is another event
+        let other = CloudEvent {
+            source: "elders".to_string(),
+            ..CloudEventBuilder::commit("issue-2", &commit)
+                .id(&event.id)
+                .build()
+        };
+        assert!(state.storage.store_event(&other).await.is_ok());
+    }
+}
@@ -0,0 +1,549 @@
+//! Teams: named groups of behandelaars within a tenant, optionally under a department.
+//!
+//! A `Team` is a resource `team-{tenant}-{name}` maintained with commits, like labels. An
+//! issue can be assigned to a team (`team` on the issue) instead of, or before, a person.
+//! Team members then have access to the issue as if they were involved: `check_access`, the
+//! search authorization filter, assignment suggestions and kanban boards all take the
+//! caller's teams into account.
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::auth::{tenant_of, AuthUser};
+use crate::handlers::{authorize_issue, internal_error, submit_commit, AppState, ResourceResponse};
+use crate::labels::{slug, valid_name};
+use crate::schemas::{CloudEvent, CommitBuilder, Issue, IssueStatus, Team};
+use crate::storage::Storage;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TeamRequest {
+    pub name: String,
+    pub department: Option<String>,
+    /// Initial members; the creator is always added
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MemberRequest {
+    /// Email of the colleague to add
+    pub user: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct IssueTeamRequest {
+    /// Team ID, or null to take the issue away from its team
+    pub team: Option<String>,
+}
+
+/// Issue counts of a team
+#[derive(Debug, Default, PartialEq, Serialize, ToSchema)]
+pub struct TeamStats {
+    pub open: usize,
+    pub in_progress: usize,
+    pub closed: usize,
+    /// Not closed, and not yet picked up by a team member
+    pub unassigned: usize,
+}
+
+/// Resource ID of a tenant's team. Names are unique per tenant, ignoring case.
+pub fn team_id(tenant: &str, name: &str) -> String {
+    format!("team-{}-{}", tenant, slug(name))
+}
+
+/// The team with this ID, if it exists.
+pub async fn get_team(
+    storage: &Storage,
+    id: &str,
+) -> Result<Option<Team>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(storage
+        .get_resource(id)
+        .await?
+        .and_then(|value| serde_json::from_value(value).ok()))
+}
+
+/// Is `user` a member of the team `id`? False for anything that is not a team.
+pub async fn is_member(storage: &Storage, id: &str, user: &str) -> bool {
+    match get_team(storage, id).await {
+        Ok(Some(team)) => team.members.iter().any(|m| m == user),
+        Ok(None) => false,
+        Err(e) => {
+            eprintln!("[teams] failed to load team {}: {}", id, e);
+            false
+        }
+    }
+}
+
+/// The teams of a tenant, by ID.
+pub async fn tenant_teams(
+    storage: &Storage,
+    tenant: &str,
+) -> Result<Vec<(String, Team)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut teams = Vec::new();
+    for (id, value) in storage
+        .list_resources_with_prefix(&format!("team-{}-", tenant))
+        .await?
+    {
+        match serde_json::from_value::<Team>(value) {
+            // The prefix also matches tenants that extend this one ("gemeente.nl-x")
+            Ok(team) if team.tenant == tenant => teams.push((id, team)),
+            Ok(_) => {}
+            Err(e) => eprintln!("[teams] skipping malformed team {}: {}", id, e),
+        }
+    }
+    Ok(teams)
+}
+
+/// IDs of the teams `user` is a member of.
+pub async fn user_teams(
+    storage: &Storage,
+    user: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(tenant_teams(storage, tenant_of(user))
+        .await?
+        .into_iter()
+        .filter(|(_, team)| team.members.iter().any(|m| m == user))
+        .map(|(id, _)| id)
+        .collect())
+}
+
+/// Load a team of the caller's tenant: 404 if unknown, 403 if another tenant's.
+async fn own_team(state: &AppState, user: &str, id: &str) -> Result<Team, StatusCode> {
+    let team = get_team(&state.storage, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if team.tenant != tenant_of(user) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(team)
+}
+
+/// GET /teams - The teams of the caller's tenant
+#[utoipa::path(
+    get,
+    path = "/teams",
+    tag = "resources",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The tenant's teams", body = [ResourceResponse]),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn list_teams_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> Result<Json<Vec<ResourceResponse>>, StatusCode> {
+    let teams = tenant_teams(&state.storage, tenant_of(&auth_user.user_id))
+        .await
+        .map_err(internal_error)?;
+    let response = teams
+        .into_iter()
+        .map(|(id, team)| ResourceResponse {
+            id,
+            resource_type: "team".to_string(),
+            data: serde_json::to_value(team).unwrap_or_default(),
+            unread: None,
+        })
+        .collect();
+    Ok(Json(response))
+}
+
+/// POST /teams - Create a team in the caller's tenant
+#[utoipa::path(
+    post,
+    path = "/teams",
+    tag = "resources",
+    request_body = TeamRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Team commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Invalid name, or a member from another tenant"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 409, description = "The tenant already has a team with this name"),
+    )
+)]
+pub async fn create_team_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Json(request): Json<TeamRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    let tenant = tenant_of(&user).to_string();
+    if !valid_name(&request.name) || request.members.iter().any(|m| tenant_of(m) != tenant) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let id = team_id(&tenant, &request.name);
+    if state
+        .storage
+        .get_resource(&id)
+        .await
+        .map_err(internal_error)?
+        .is_some()
+    {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let mut members = vec![user.clone()];
+    for member in request.members {
+        if !members.contains(&member) {
+            members.push(member);
+        }
+    }
+    let team = Team {
+        name: request.name,
+        tenant,
+        department: request.department.filter(|d| !d.trim().is_empty()),
+        members,
+    };
+    let commit = CommitBuilder::create(id.clone(), &team).actor(user).build();
+    submit_commit(&state, &headers, &id, commit).await
+}
+
+/// POST /teams/{id}/members - Add a colleague to a team
+#[utoipa::path(
+    post,
+    path = "/teams/{id}/members",
+    tag = "resources",
+    params(("id" = String, Path, description = "Team ID")),
+    request_body = MemberRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Patch commit stored, processed and broadcast", body = CloudEvent),
+        (status = 204, description = "Already a member"),
+        (status = 400, description = "The colleague is from another tenant"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The team belongs to another tenant"),
+        (status = 404, description = "Unknown team"),
+    )
+)]
+pub async fn add_member_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(request): Json<MemberRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    let mut team = own_team(&state, &user, &id).await?;
+    if tenant_of(&request.user) != team.tenant {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if team.members.contains(&request.user) {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    team.members.push(request.user);
+    let commit = CommitBuilder::patch::<Team>(id.clone(), json!({ "members": team.members }))
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &id, commit).await
+}
+
+/// DELETE /teams/{id}/members/{user} - Remove someone from a team
+#[utoipa::path(
+    delete,
+    path = "/teams/{id}/members/{user}",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Team ID"),
+        ("user" = String, Path, description = "Email of the member"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Patch commit stored, processed and broadcast", body = CloudEvent),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The team belongs to another tenant"),
+        (status = 404, description = "Unknown team, or not a member"),
+    )
+)]
+pub async fn remove_member_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path((id, member)): Path<(String, String)>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    let mut team = own_team(&state, &user, &id).await?;
+    let before = team.members.len();
+    team.members.retain(|m| *m != member);
+    if team.members.len() == before {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let commit = CommitBuilder::patch::<Team>(id.clone(), json!({ "members": team.members }))
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &id, commit).await
+}
+
+/// PUT /issues/{id}/team - Assign an issue to a team
+#[utoipa::path(
+    put,
+    path = "/issues/{id}/team",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    request_body = IssueTeamRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Patch commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Unknown team, or a team of another tenant"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn assign_team_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(issue_id): Path<String>,
+    Json(request): Json<IssueTeamRequest>,
+) -> Result<Response, StatusCode> {
+    let user = auth_user.user_id;
+    authorize_issue(&state, &user, &issue_id).await?;
+    if let Some(team) = &request.team {
+        own_team(&state, &user, team)
+            .await
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+    }
+    let commit = CommitBuilder::patch::<Issue>(issue_id.clone(), json!({ "team": request.team }))
+        .actor(user)
+        .build();
+    submit_commit(&state, &headers, &issue_id, commit).await
+}
+
+/// GET /teams/{id}/stats - Issue counts of a team
+#[utoipa::path(
+    get,
+    path = "/teams/{id}/stats",
+    tag = "resources",
+    params(("id" = String, Path, description = "Team ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Issues assigned to the team, by status", body = TeamStats),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "The team belongs to another tenant"),
+        (status = 404, description = "Unknown team"),
+    )
+)]
+pub async fn team_stats_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Json<TeamStats>, StatusCode> {
+    own_team(&state, &auth_user.user_id, &id).await?;
+    let resources = state
+        .storage
+        .list_resources(0, usize::MAX)
+        .await
+        .map_err(internal_error)?;
+
+    let mut stats = TeamStats::default();
+    for (_, value) in resources {
+        let Ok(issue) = serde_json::from_value::<Issue>(value) else {
+            continue;
+        };
+        if issue.team.as_deref() != Some(id.as_str()) {
+            continue;
+        }
+        match issue.status {
+            IssueStatus::Open => stats.open += 1,
+            IssueStatus::InProgress => stats.in_progress += 1,
+            IssueStatus::Closed => stats.closed += 1,
+        }
+        if issue.assignee.is_none() && !matches!(issue.status, IssueStatus::Closed) {
+            stats.unassigned += 1;
+        }
+    }
+    Ok(Json(stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::check_access;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state};
+
+    #[tokio::test]
+    async fn test_team_scope() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let bob = "bob@gemeente.nl";
+        let melder = "piet@example.com";
+
+        let created = create_team_handler(
+            State(state.clone()),
+            user(alice),
+            HeaderMap::new(),
+            Json(TeamRequest {
+                name: "Handhaving".to_string(),
+                department: Some("Openbare Ruimte".to_string()),
+                members: vec![],
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(created.status(), StatusCode::ACCEPTED);
+        let id = team_id("gemeente.nl", "Handhaving");
+        assert_eq!(id, "team-gemeente.nl-handhaving");
+
+        let issue = issue("Fout geparkeerd", &[alice, melder]);
+        create_issue(&state, "issue-1", &issue, melder).await;
+        assert!(!check_access(&state.storage, bob, "issue-1").await);
+
+        let assign = |team: Option<&str>| {
+            assign_team_handler(
+                State(state.clone()),
+                user(alice),
+                HeaderMap::new(),
+                Path("issue-1".to_string()),
+                Json(IssueTeamRequest {
+                    team: team.map(str::to_string),
+                }),
+            )
+        };
+        assert_eq!(
+            assign(Some("team-gemeente.nl-onbekend")).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            assign(Some(&id)).await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+
+        // Bob gains access by joining the team
+        let added = add_member_handler(
+            State(state.clone()),
+            user(alice),
+            HeaderMap::new(),
+            Path(id.clone()),
+            Json(MemberRequest {
+                user: bob.to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(added.status(), StatusCode::ACCEPTED);
+        assert!(check_access(&state.storage, bob, "issue-1").await);
+        assert_eq!(
+            user_teams(&state.storage, bob).await.unwrap(),
+            vec![id.clone()]
+        );
+        let suggestions = crate::assignment::suggest(&state.storage, "issue-1", "gemeente.nl")
+            .await
+            .unwrap();
+        let users: Vec<&str> = suggestions.iter().map(|s| s.user.as_str()).collect();
+        assert_eq!(users, vec![alice, bob]);
+
+        let Json(stats) = team_stats_handler(State(state.clone()), user(bob), Path(id.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            stats,
+            TeamStats {
+                open: 1,
+                unassigned: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            team_stats_handler(State(state.clone()), user(melder), Path(id.clone()))
+                .await
+                .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+
+        let removed = remove_member_handler(
+            State(state.clone()),
+            user(alice),
+            HeaderMap::new(),
+            Path((id.clone(), bob.to_string())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(removed.status(), StatusCode::ACCEPTED);
+        assert!(!check_access(&state.storage, bob, "issue-1").await);
+    }
+
+    #[tokio::test]
+    async fn test_teams_of_another_tenant() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let mallory = "mallory@evil.com";
+        let request = |members: Vec<String>| {
+            Json(TeamRequest {
+                name: "Handhaving".to_string(),
+                department: None,
+                members,
+            })
+        };
+
+        // Colleagues only: nobody can be put in a team of another tenant
+        assert_eq!(
+            create_team_handler(
+                State(state.clone()),
+                user(mallory),
+                HeaderMap::new(),
+                request(vec![alice.to_string()]),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        create_team_handler(
+            State(state.clone()),
+            user(alice),
+            HeaderMap::new(),
+            request(vec![]),
+        )
+        .await
+        .unwrap();
+        let id = team_id("gemeente.nl", "Handhaving");
+
+        let add = |actor: &str, member: &str| {
+            add_member_handler(
+                State(state.clone()),
+                user(actor),
+                HeaderMap::new(),
+                Path(id.clone()),
+                Json(MemberRequest {
+                    user: member.to_string(),
+                }),
+            )
+        };
+        assert_eq!(
+            add(mallory, mallory).await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            add(alice, mallory).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            remove_member_handler(
+                State(state.clone()),
+                user(mallory),
+                HeaderMap::new(),
+                Path((id.clone(), alice.to_string())),
+            )
+            .await
+            .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            team_stats_handler(State(state.clone()), user(mallory), Path(id.clone()))
+                .await
+                .unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+    }
+}
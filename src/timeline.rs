@@ -0,0 +1,342 @@
+//! The timeline of an issue: every event about it, in sequence order, with comment
+//! reactions aggregated per comment and notification deliveries per triggering event,
+//! instead of listed as separate events. With `?order=thread`, replies are moved up to
+//! follow the comment they reply to.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::AuthUser;
+use crate::comments::is_reaction_commit;
+use crate::delivery::{aggregate_deliveries, Delivery, DELIVERY_EVENT_TYPE};
+use crate::handlers::{check_access, commit_of, AppState, CATCHUP_PAGE_SIZE};
+use crate::schemas::{CloudEvent, Reaction};
+
+/// Order of the timeline events
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineOrder {
+    /// Sequence (processing) order
+    #[default]
+    Sequence,
+    /// Sequence order, except that replies follow the comment they reply to, depth-first
+    Thread,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TimelineParams {
+    #[serde(default)]
+    pub order: TimelineOrder,
+}
+
+/// All reactions with one emoji on a comment
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReactionSummary {
+    pub emoji: String,
+    pub count: usize,
+    /// Emails of the people who reacted, in the order they reacted
+    pub actors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IssueTimeline {
+    pub issue_id: String,
+    /// Events with the issue as subject, in sequence order (reaction commits and delivery
+    /// events excluded)
+    pub events: Vec<CloudEvent>,
+    /// Current reactions per comment ID, emojis in order of first use
+    pub reactions: BTreeMap<String, Vec<ReactionSummary>>,
+    /// Per event ID: who was notified of it, and whether that succeeded
+    pub deliveries: BTreeMap<String, Vec<Delivery>>,
+}
+
+/// Group reactions by comment, then by emoji.
+pub fn aggregate_reactions(
+    reactions: impl IntoIterator<Item = Reaction>,
+) -> BTreeMap<String, Vec<ReactionSummary>> {
+    let mut by_comment: BTreeMap<String, Vec<ReactionSummary>> = BTreeMap::new();
+    for reaction in reactions {
+        let summaries = by_comment.entry(reaction.comment_id).or_default();
+        match summaries.iter_mut().find(|s| s.emoji == reaction.emoji) {
+            Some(summary) => {
+                summary.count += 1;
+                summary.actors.push(reaction.actor);
+            }
+            None => summaries.push(ReactionSummary {
+                emoji: reaction.emoji,
+                count: 1,
+                actors: vec![reaction.actor],
+            }),
+        }
+    }
+    by_comment
+}
+
+/// ID of the comment created by this event, if it is a comment-creating commit
+fn created_comment(event: &CloudEvent) -> Option<String> {
+    let commit = commit_of(event)?;
+    commit.resource_data.as_ref()?.get("content")?;
+    Some(commit.resource_id)
+}
+
+/// Move the event that created each reply to directly after its parent's thread, so
+/// replies follow the comment they reply to, depth-first. `replies` maps a comment ID to
+/// its direct replies in creation order. All other events keep their relative order.
+pub fn thread_order(
+    events: Vec<CloudEvent>,
+    replies: &HashMap<String, Vec<String>>,
+) -> Vec<CloudEvent> {
+    let created: HashMap<String, usize> = events
+        .iter()
+        .enumerate()
+        .filter_map(|(i, event)| created_comment(event).map(|id| (id, i)))
+        .collect();
+    // Replies whose parent is also in this timeline; they are emitted with the parent
+    let nested: HashSet<&str> = replies
+        .iter()
+        .filter(|(parent, _)| created.contains_key(*parent))
+        .flat_map(|(_, children)| children.iter().map(String::as_str))
+        .filter(|child| created.contains_key(*child))
+        .collect();
+
+    let mut slots: Vec<Option<CloudEvent>> = events.into_iter().map(Some).collect();
+    let mut ordered = Vec::with_capacity(slots.len());
+    for i in 0..slots.len() {
+        let is_nested = slots[i]
+            .as_ref()
+            .and_then(created_comment)
+            .is_some_and(|id| nested.contains(id.as_str()));
+        if is_nested {
+            continue;
+        }
+        let Some(event) = slots[i].take() else {
+            continue;
+        };
+        let root = created_comment(&event);
+        ordered.push(event);
+
+        let Some(root) = root else {
+            continue;
+        };
+        let mut stack: Vec<&String> = replies.get(&root).into_iter().flatten().rev().collect();
+        while let Some(child) = stack.pop() {
+            // `take` also stops `quote_comment` cycles: every event is emitted once
+            let Some(event) = created.get(child).and_then(|&j| slots[j].take()) else {
+                continue;
+            };
+            ordered.push(event);
+            stack.extend(replies.get(child).into_iter().flatten().rev());
+        }
+    }
+
+    // Replies caught in a cycle have no root to hang from; keep them at the end
+    ordered.extend(slots.into_iter().flatten());
+    ordered
+}
+
+/// GET /issues/{id}/timeline - Events of an issue with aggregated comment reactions
+#[utoipa::path(
+    get,
+    path = "/issues/{id}/timeline",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID"), TimelineParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The issue's timeline", body = IssueTimeline),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn issue_timeline_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+    Query(params): Query<TimelineParams>,
+) -> Result<Json<IssueTimeline>, StatusCode> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!(
+            "[timeline] failed to build timeline for {}: {}",
+            issue_id, e
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    state
+        .storage
+        .get_resource(&issue_id)
+        .await
+        .map_err(internal)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if !check_access(&state.storage, &auth_user.user_id, &issue_id).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut events = Vec::new();
+    let mut delivery_events = Vec::new();
+    let mut reaction_ids: Vec<String> = Vec::new();
+    let mut after = None;
+    loop {
+        let page = state
+            .storage
+            .list_events_after(after, CATCHUP_PAGE_SIZE)
+            .await
+            .map_err(internal)?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = last.sequence.clone();
+
+        for event in page.into_iter().filter(|e| e.subject == issue_id) {
+            if event.event_type == DELIVERY_EVENT_TYPE {
+                delivery_events.push(event);
+                continue;
+            }
+            match commit_of(&event) {
+                Some(commit) if is_reaction_commit(&commit) => {
+                    if !reaction_ids.contains(&commit.resource_id) {
+                        reaction_ids.push(commit.resource_id);
+                    }
+                }
+                _ => events.push(event),
+            }
+        }
+    }
+
+    if params.order == TimelineOrder::Thread {
+        let mut replies = HashMap::new();
+        for comment_id in events.iter().filter_map(created_comment) {
+            let children = state
+                .storage
+                .list_comment_replies(&comment_id)
+                .await
+                .map_err(internal)?;
+            if !children.is_empty() {
+                replies.insert(comment_id, children);
+            }
+        }
+        events = thread_order(events, &replies);
+    }
+
+    // Removed reactions are deleted resources, so the current state is what storage holds
+    let mut reactions = Vec::new();
+    for id in &reaction_ids {
+        if let Some(value) = state.storage.get_resource(id).await.map_err(internal)? {
+            match serde_json::from_value::<Reaction>(value) {
+                Ok(reaction) => reactions.push(reaction),
+                Err(e) => eprintln!("[timeline] skipping malformed reaction {}: {}", id, e),
+            }
+        }
+    }
+
+    Ok(Json(IssueTimeline {
+        issue_id,
+        events,
+        reactions: aggregate_reactions(reactions),
+        deliveries: aggregate_deliveries(&delivery_events),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comments::{add_reaction_handler, remove_reaction_handler, ReactionRequest};
+    use crate::handlers::tests::{
+        auth_user as user, create_issue, issue, submit_commit_event, test_state,
+    };
+    use crate::schemas::{Comment, CommitBuilder};
+    use axum::http::HeaderMap;
+
+    #[tokio::test]
+    async fn test_reactions_are_aggregated_in_timeline() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let (alice, bob) = ("alice@gemeente.nl", "bob@gemeente.nl");
+
+        let paspoort = issue("Paspoort aanvragen", &[alice, bob]);
+        create_issue(&state, "issue-1", &paspoort, alice).await;
+        let comment = Comment {
+            content: "Documenten zijn ingestuurd".to_string(),
+            quote_comment: None,
+            mentions: None,
+            edited_at: None,
+            internal: None,
+        };
+        let commit = CommitBuilder::create("comment-1", &comment).build();
+        submit_commit_event(&state, "issue-1", &commit)
+            .await
+            .unwrap();
+
+        let react = |who: &str, emoji: &str| {
+            add_reaction_handler(
+                State(state.clone()),
+                user(who),
+                HeaderMap::new(),
+                Path("comment-1".to_string()),
+                Json(ReactionRequest {
+                    emoji: emoji.to_string(),
+                }),
+            )
+        };
+        assert_eq!(
+            react(alice, "👍").await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            react(bob, "👍").await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            react(bob, "✅").await.unwrap().status(),
+            StatusCode::ACCEPTED
+        );
+        // Reacting twice with the same emoji is a no-op
+        assert_eq!(
+            react(alice, "👍").await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            react("mallory@example.com", "👎").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+
+        let removed = remove_reaction_handler(
+            State(state.clone()),
+            user(bob),
+            HeaderMap::new(),
+            Path(("comment-1".to_string(), "✅".to_string())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(removed.status(), StatusCode::ACCEPTED);
+
+        let timeline_of = |who: &str, id: &str| {
+            issue_timeline_handler(
+                State(state.clone()),
+                user(who),
+                Path(id.to_string()),
+                Query(TimelineParams::default()),
+            )
+        };
+        let outsider = timeline_of("mallory@example.com", "issue-1").await;
+        assert_eq!(outsider.unwrap_err(), StatusCode::FORBIDDEN);
+        let unknown = timeline_of(alice, "issue-9").await;
+        assert_eq!(unknown.unwrap_err(), StatusCode::NOT_FOUND);
+        let Json(timeline) = timeline_of(alice, "issue-1").await.unwrap();
+
+        // Only the issue and comment commits; reaction commits are folded in
+        assert_eq!(timeline.events.len(), 2);
+        let summaries = &timeline.reactions["comment-1"];
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].emoji, "👍");
+        assert_eq!(summaries[0].count, 2);
+        assert_eq!(summaries[0].actors, vec![alice, bob]);
+    }
+}
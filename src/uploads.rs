@@ -0,0 +1,815 @@
+//! Resumable uploads over the tus protocol (1.0.0, with the `creation`, `termination` and
+//! `expiration` extensions).
+//!
+//! Construction drawings and video evidence are too large to survive a single request over
+//! municipal Wi-Fi. A client creates an upload with `POST /uploads` (`Upload-Length`, and
+//! optionally `Upload-Metadata` with `filename` and `filetype`), then sends the bytes with
+//! `PATCH /uploads/{id}`, resuming from the `Upload-Offset` that `HEAD /uploads/{id}` reports
+//! after an interruption. Bytes are written to disk as they arrive, so an interrupted request
+//! keeps what reached the server. Sessions are kept in storage and survive restarts;
+//! unfinished uploads expire [`UPLOAD_EXPIRY_HOURS`] after they were created.
+//!
+//! A finished upload is served by `GET /uploads/{id}`, the URL to give the Document that
+//! refers to it. Only the user who created an upload can append to or delete it; it can be
+//! downloaded by them and by whoever has access to the issue of a Document they pointed at it.
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+};
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::auth::AuthUser;
+use crate::handlers::{check_access, commit_of, internal_error, AppState};
+use crate::storage::Storage;
+
+/// The protocol version spoken
+pub const TUS_VERSION: &str = "1.0.0";
+/// Unfinished uploads are removed this long after they were created
+pub const UPLOAD_EXPIRY_HOURS: i64 = 24;
+/// Largest upload without `UPLOAD_MAX_BYTES`: 10 GiB
+const DEFAULT_MAX_BYTES: u64 = 10 << 30;
+/// Resources read at a time when looking for the documents that point to an upload
+const DOWNLOAD_SCAN_PAGE: usize = 500;
+
+/// An upload in progress, or finished
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSession {
+    /// Email of the user who created it
+    pub owner: String,
+    /// Total size in bytes
+    pub length: u64,
+    /// Bytes received so far
+    pub offset: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// Media type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filetype: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl UploadSession {
+    pub fn is_complete(&self) -> bool {
+        self.offset >= self.length
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.created_at + Duration::hours(UPLOAD_EXPIRY_HOURS)
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        !self.is_complete() && self.expires_at() <= now
+    }
+}
+
+/// The uploads being appended to, so two requests cannot write to one upload at once.
+#[derive(Default)]
+pub struct UploadLocks(Mutex<HashSet<String>>);
+
+/// Holds an upload's lock until dropped
+pub struct UploadGuard<'a> {
+    locks: &'a UploadLocks,
+    id: String,
+}
+
+impl UploadLocks {
+    /// Lock upload `id`, unless it is locked already.
+    pub fn try_lock(&self, id: &str) -> Option<UploadGuard<'_>> {
+        let mut locked = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        locked.insert(id.to_string()).then(|| UploadGuard {
+            locks: self,
+            id: id.to_string(),
+        })
+    }
+}
+
+impl Drop for UploadGuard<'_> {
+    fn drop(&mut self) {
+        let mut locked = self.locks.0.lock().unwrap_or_else(|e| e.into_inner());
+        locked.remove(&self.id);
+    }
+}
+
+fn max_bytes() -> u64 {
+    std::env::var("UPLOAD_MAX_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BYTES)
+}
+
+/// The pairs of an `Upload-Metadata` header: `key base64value,key2 base64value2`.
+/// Keys without value map to "", undecodable values are left out.
+pub fn parse_metadata(header: &str) -> HashMap<String, String> {
+    header
+        .split(',')
+        .filter_map(|pair| {
+            let mut parts = pair.trim().splitn(2, ' ');
+            let key = parts.next().filter(|key| !key.is_empty())?;
+            let value = match parts.next() {
+                Some(encoded) => base64::engine::general_purpose::STANDARD
+                    .decode(encoded.trim())
+                    .ok()
+                    .and_then(|bytes| String::from_utf8(bytes).ok())?,
+                None => String::new(),
+            };
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+fn file_path(state: &AppState, id: &str) -> PathBuf {
+    state.storage.data_dir.join("uploads").join(id)
+}
+
+async fn load(
+    state: &AppState,
+    id: &str,
+) -> Result<Option<UploadSession>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(state
+        .storage
+        .get_upload(id)
+        .await?
+        .map(|session| serde_json::from_str(&session))
+        .transpose()?)
+}
+
+/// The ID of the upload `url` points to: `/uploads/{id}`, possibly as a full URL.
+pub fn upload_id(url: &str) -> Option<&str> {
+    let (_, id) = url.rsplit_once("/uploads/")?;
+    (!id.is_empty() && !id.contains(['/', '?', '#'])).then_some(id)
+}
+
+/// The size of the finished upload `url` points to; `None` when it points elsewhere, or to
+/// an upload that is not (or no longer) there.
+pub async fn finished_length(
+    storage: &Storage,
+    url: &str,
+) -> Result<Option<u64>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(id) = upload_id(url) else {
+        return Ok(None);
+    };
+    let session = storage
+        .get_upload(id)
+        .await?
+        .map(|session| serde_json::from_str::<UploadSession>(&session))
+        .transpose()?;
+    Ok(session
+        .filter(UploadSession::is_complete)
+        .map(|session| session.length))
+}
+
+async fn save(
+    state: &AppState,
+    id: &str,
+    session: &UploadSession,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let session = serde_json::to_string(session)?;
+    state.storage.set_upload(id, Some(&session)).await
+}
+
+async fn remove(
+    state: &AppState,
+    id: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match tokio::fs::remove_file(file_path(state, id)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    state.storage.set_upload(id, None).await
+}
+
+/// Remove the unfinished uploads that expired at `now`. Returns how many were removed.
+pub async fn remove_expired(
+    state: &AppState,
+    now: DateTime<Utc>,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let mut removed = 0;
+    for (id, session) in state.storage.list_uploads().await? {
+        let Ok(session) = serde_json::from_str::<UploadSession>(&session) else {
+            continue;
+        };
+        if session.is_expired(now) {
+            remove(state, &id).await?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// A response with the `Tus-Resumable` header every tus response carries
+fn tus_response(status: StatusCode) -> axum::http::response::Builder {
+    Response::builder()
+        .status(status)
+        .header("Tus-Resumable", TUS_VERSION)
+}
+
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// The response to requests that don't speak our version of the protocol
+fn version_mismatch(headers: &HeaderMap) -> Option<Response> {
+    if header_value(headers, "tus-resumable") == Some(TUS_VERSION) {
+        return None;
+    }
+    Some(
+        tus_response(StatusCode::PRECONDITION_FAILED)
+            .header("Tus-Version", TUS_VERSION)
+            .body(Body::empty())
+            .unwrap(),
+    )
+}
+
+/// The session of upload `id` if `user` created it and it has not expired
+async fn owned_session(
+    state: &AppState,
+    user: &str,
+    id: &str,
+) -> Result<UploadSession, StatusCode> {
+    let session = load(state, id)
+        .await
+        .map_err(internal_error)?
+        .filter(|session| session.owner == user)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if session.is_expired(Utc::now()) {
+        remove(state, id).await.map_err(internal_error)?;
+        return Err(StatusCode::GONE);
+    }
+    Ok(session)
+}
+
+/// OPTIONS /uploads - What the tus server supports
+#[utoipa::path(
+    options,
+    path = "/uploads",
+    tag = "resources",
+    responses(
+        (status = 204, description = "`Tus-Version`, `Tus-Extension` and `Tus-Max-Size` headers"),
+    )
+)]
+pub async fn options_handler() -> Response {
+    tus_response(StatusCode::NO_CONTENT)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", "creation,termination,expiration")
+        .header("Tus-Max-Size", max_bytes())
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// POST /uploads - Start a resumable upload
+#[utoipa::path(
+    post,
+    path = "/uploads",
+    tag = "resources",
+    params(
+        ("Tus-Resumable" = String, Header, description = "Protocol version, 1.0.0"),
+        ("Upload-Length" = u64, Header, description = "Size of the file in bytes"),
+        ("Upload-Metadata" = Option<String>, Header, description = "`filename` and `filetype`, base64 encoded"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "Created; `Location` is the upload's URL"),
+        (status = 400, description = "Missing or invalid Upload-Length"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 412, description = "Unsupported protocol version"),
+        (status = 413, description = "Larger than Tus-Max-Size, or over the caller's or their tenant's document byte quota", body = crate::quotas::QuotaExceeded),
+    )
+)]
+pub async fn create_upload_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    if let Some(response) = version_mismatch(&headers) {
+        return Ok(response);
+    }
+    let length: u64 = header_value(&headers, "upload-length")
+        .and_then(|v| v.parse().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    if length > max_bytes() {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    // The length is fixed here, so appending can't go over a quota later
+    let quotas = crate::quotas::Quotas::from_env();
+    let exceeded = crate::quotas::check_upload(&state.storage, &quotas, &auth_user.user_id, length)
+        .await
+        .map_err(internal_error)?;
+    if let Some(exceeded) = exceeded {
+        eprintln!("[uploads] upload refused: {}", exceeded);
+        let body = serde_json::to_vec(&exceeded).map_err(internal_error)?;
+        return Ok(tus_response(StatusCode::PAYLOAD_TOO_LARGE)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap());
+    }
+    let mut metadata = header_value(&headers, "upload-metadata")
+        .map(parse_metadata)
+        .unwrap_or_default();
+    let now = Utc::now();
+    remove_expired(&state, now).await.map_err(internal_error)?;
+
+    let id = uuid::Uuid::now_v7().to_string();
+    let session = UploadSession {
+        owner: auth_user.user_id,
+        length,
+        offset: 0,
+        filename: metadata.remove("filename").filter(|v| !v.is_empty()),
+        filetype: metadata.remove("filetype").filter(|v| !v.is_empty()),
+        created_at: now,
+    };
+    let path = file_path(&state, &id);
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(internal_error)?;
+    }
+    tokio::fs::File::create(&path)
+        .await
+        .map_err(internal_error)?;
+    save(&state, &id, &session).await.map_err(internal_error)?;
+    Ok(tus_response(StatusCode::CREATED)
+        .header(header::LOCATION, format!("/uploads/{}", id))
+        .header("Upload-Expires", http_date(session.expires_at()))
+        .body(Body::empty())
+        .unwrap())
+}
+
+/// HEAD /uploads/{id} - How much of an upload was received
+#[utoipa::path(
+    head,
+    path = "/uploads/{id}",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Upload ID"),
+        ("Tus-Resumable" = String, Header, description = "Protocol version, 1.0.0"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "`Upload-Offset` and `Upload-Length` headers"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "Unknown upload, or not the caller's"),
+        (status = 410, description = "Expired before it was finished"),
+        (status = 412, description = "Unsupported protocol version"),
+    )
+)]
+pub async fn upload_offset_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, StatusCode> {
+    if let Some(response) = version_mismatch(&headers) {
+        return Ok(response);
+    }
+    let session = owned_session(&state, &auth_user.user_id, &id).await?;
+    let mut response = tus_response(StatusCode::OK)
+        .header("Upload-Offset", session.offset)
+        .header("Upload-Length", session.length)
+        .header(header::CACHE_CONTROL, "no-store");
+    if !session.is_complete() {
+        response = response.header("Upload-Expires", http_date(session.expires_at()));
+    }
+    Ok(response.body(Body::empty()).unwrap())
+}
+
+/// PATCH /uploads/{id} - Append bytes to an upload
+#[utoipa::path(
+    patch,
+    path = "/uploads/{id}",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Upload ID"),
+        ("Tus-Resumable" = String, Header, description = "Protocol version, 1.0.0"),
+        ("Upload-Offset" = u64, Header, description = "Where the bytes go: the offset HEAD reports"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/offset+octet-stream"),
+    security(("bearer" = [])),
+    responses(
+        (status = 204, description = "Appended; `Upload-Offset` is the new offset"),
+        (status = 400, description = "Missing Upload-Offset, or the request broke off"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "Unknown upload, or not the caller's"),
+        (status = 409, description = "Upload-Offset is not the upload's offset"),
+        (status = 410, description = "Expired before it was finished"),
+        (status = 412, description = "Unsupported protocol version"),
+        (status = 413, description = "More bytes than Upload-Length"),
+        (status = 415, description = "Not application/offset+octet-stream"),
+        (status = 423, description = "Another request is appending to the upload"),
+    )
+)]
+pub async fn append_upload_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    body: Body,
+) -> Result<Response, StatusCode> {
+    if let Some(response) = version_mismatch(&headers) {
+        return Ok(response);
+    }
+    if header_value(&headers, "content-type") != Some("application/offset+octet-stream") {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    let offset: u64 = header_value(&headers, "upload-offset")
+        .and_then(|v| v.parse().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let _guard = state.uploads.try_lock(&id).ok_or(StatusCode::LOCKED)?;
+    let mut session = owned_session(&state, &auth_user.user_id, &id).await?;
+    if offset != session.offset {
+        return Err(StatusCode::CONFLICT);
+    }
+    let announced: Option<u64> =
+        header_value(&headers, "content-length").and_then(|v| v.parse().ok());
+    if announced.is_some_and(|n| offset + n > session.length) {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(file_path(&state, &id))
+        .await
+        .map_err(internal_error)?;
+    file.seek(std::io::SeekFrom::Start(offset))
+        .await
+        .map_err(internal_error)?;
+    let mut stream = body.into_data_stream();
+    let mut outcome = Ok(());
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // Keep what arrived; the client resumes from there
+                eprintln!("[uploads] upload {} broke off: {}", id, e);
+                outcome = Err(StatusCode::BAD_REQUEST);
+                break;
+            }
+        };
+        let room = session.length - session.offset;
+        let take = chunk.len().min(usize::try_from(room).unwrap_or(usize::MAX));
+        file.write_all(&chunk[..take])
+            .await
+            .map_err(internal_error)?;
+        session.offset += take as u64;
+        if take < chunk.len() {
+            outcome = Err(StatusCode::PAYLOAD_TOO_LARGE);
+            break;
+        }
+    }
+    file.sync_data().await.map_err(internal_error)?;
+    save(&state, &id, &session).await.map_err(internal_error)?;
+    outcome?;
+
+    let mut response = tus_response(StatusCode::NO_CONTENT).header("Upload-Offset", session.offset);
+    if !session.is_complete() {
+        response = response.header("Upload-Expires", http_date(session.expires_at()));
+    }
+    Ok(response.body(Body::empty()).unwrap())
+}
+
+/// DELETE /uploads/{id} - Abandon an upload, or remove a finished one
+#[utoipa::path(
+    delete,
+    path = "/uploads/{id}",
+    tag = "resources",
+    params(
+        ("id" = String, Path, description = "Upload ID"),
+        ("Tus-Resumable" = String, Header, description = "Protocol version, 1.0.0"),
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 204, description = "Removed"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "Unknown upload, or not the caller's"),
+        (status = 412, description = "Unsupported protocol version"),
+        (status = 423, description = "A request is appending to the upload"),
+    )
+)]
+pub async fn delete_upload_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, StatusCode> {
+    if let Some(response) = version_mismatch(&headers) {
+        return Ok(response);
+    }
+    let _guard = state.uploads.try_lock(&id).ok_or(StatusCode::LOCKED)?;
+    load(&state, &id)
+        .await
+        .map_err(internal_error)?
+        .filter(|session| session.owner == auth_user.user_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    remove(&state, &id).await.map_err(internal_error)?;
+    Ok(tus_response(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap())
+}
+
+/// May `user` download the upload `id` of `owner`? The owner may, and so may everyone with
+/// access to the issue of a document the owner pointed at it.
+async fn may_download(
+    state: &AppState,
+    user: &str,
+    id: &str,
+    owner: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    if user == owner {
+        return Ok(true);
+    }
+    let mut after: Option<String> = None;
+    loop {
+        let page = state
+            .storage
+            .list_typed_resources_after(after.as_deref(), DOWNLOAD_SCAN_PAGE)
+            .await?;
+        let full = page.len() == DOWNLOAD_SCAN_PAGE;
+        for (document, _, data) in page {
+            let points_here =
+                data.get("url").and_then(|v| v.as_str()).and_then(upload_id) == Some(id);
+            if points_here && document_grants(state, user, &document, owner).await? {
+                return Ok(true);
+            }
+            after = Some(document);
+        }
+        if !full {
+            return Ok(false);
+        }
+    }
+}
+
+/// Did `owner` create `document`, and may `user` see the issue it was filed under?
+async fn document_grants(
+    state: &AppState,
+    user: &str,
+    document: &str,
+    owner: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let created = state.storage.list_resource_events(document).await?;
+    let creator = created.first().and_then(commit_of).map(|c| c.actor);
+    if creator.as_deref() != Some(owner) {
+        return Ok(false);
+    }
+    Ok(match state.storage.resource_subject(document).await? {
+        Some(issue) => check_access(&state.storage, user, &issue).await,
+        None => false,
+    })
+}
+
+/// GET /uploads/{id} - Download a finished upload
+#[utoipa::path(
+    get,
+    path = "/uploads/{id}",
+    tag = "resources",
+    params(("id" = String, Path, description = "Upload ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The file, with the media type it was uploaded with"),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "Unknown or unfinished upload, or one the caller may not see"),
+    )
+)]
+pub async fn download_upload_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Response, StatusCode> {
+    let session = load(&state, &id)
+        .await
+        .map_err(internal_error)?
+        .filter(UploadSession::is_complete)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if !may_download(&state, &auth_user.user_id, &id, &session.owner)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let mut file = tokio::fs::File::open(file_path(&state, &id))
+        .await
+        .map_err(internal_error)?;
+    let content = async_stream::try_stream! {
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buffer).await?;
+            if n == 0 {
+                break;
+            }
+            yield axum::body::Bytes::copy_from_slice(&buffer[..n]);
+        }
+    };
+    let content: std::pin::Pin<
+        Box<dyn futures_util::Stream<Item = Result<axum::body::Bytes, std::io::Error>> + Send>,
+    > = Box::pin(content);
+
+    let mut response = Response::builder()
+        .header(
+            header::CONTENT_TYPE,
+            session
+                .filetype
+                .as_deref()
+                .unwrap_or("application/octet-stream"),
+        )
+        .header(header::CONTENT_LENGTH, session.length);
+    if let Some(filename) = &session.filename {
+        let filename = filename.replace(['"', '\\', '\r', '\n'], "_");
+        response = response.header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        );
+    }
+    response
+        .body(Body::from_stream(content))
+        .map_err(internal_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{
+        auth_user as user, create_issue, issue, submit_commit_event, test_state,
+    };
+    use crate::schemas::{CommitBuilder, Document};
+
+    fn tus_headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("tus-resumable", TUS_VERSION.parse().unwrap());
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+
+        let metadata = format!(
+            "filename {},filetype {}",
+            base64::engine::general_purpose::STANDARD.encode("bouwtekening.pdf"),
+            base64::engine::general_purpose::STANDARD.encode("application/pdf"),
+        );
+        let response = create_upload_handler(
+            State(state.clone()),
+            user(alice),
+            tus_headers(&[("upload-length", "11"), ("upload-metadata", &metadata)]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let id = location.rsplit('/').next().unwrap().to_string();
+
+        let append = |email: &str, offset: &str, bytes: &'static [u8]| {
+            append_upload_handler(
+                State(state.clone()),
+                user(email),
+                tus_headers(&[
+                    ("upload-offset", offset),
+                    ("content-type", "application/offset+octet-stream"),
+                    ("content-length", &bytes.len().to_string()),
+                ]),
+                Path(id.clone()),
+                Body::from(bytes),
+            )
+        };
+        let response = append(alice, "0", b"%PDF-").await.unwrap();
+        assert_eq!(response.headers()["upload-offset"], "5");
+        // Resuming from the wrong offset, or someone else's upload
+        assert_eq!(
+            append(alice, "0", b"%PDF-").await.unwrap_err(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            append("bob@gemeente.nl", "5", b"tekening")
+                .await
+                .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+
+        let response = upload_offset_handler(
+            State(state.clone()),
+            user(alice),
+            tus_headers(&[]),
+            Path(id.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.headers()["upload-offset"], "5");
+        assert_eq!(response.headers()["upload-length"], "11");
+
+        // Without protocol version
+        let response = upload_offset_handler(
+            State(state.clone()),
+            user(alice),
+            HeaderMap::new(),
+            Path(id.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+        assert_eq!(
+            download_upload_handler(State(state.clone()), user(alice), Path(id.clone()))
+                .await
+                .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        // More than announced in Upload-Length
+        assert_eq!(
+            append(alice, "5", b"tekening").await.unwrap_err(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        let response = append(alice, "5", b"tekend").await.unwrap();
+        assert_eq!(response.headers()["upload-offset"], "11");
+        assert!(load(&state, &id).await.unwrap().unwrap().is_complete());
+
+        let response = download_upload_handler(State(state.clone()), user(alice), Path(id.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/pdf");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..], b"%PDF-tekend");
+
+        // Others may download it once alice files it in an issue they can see, not when
+        // someone else points a document at it
+        let download = |email: &str| {
+            download_upload_handler(State(state.clone()), user(email), Path(id.clone()))
+        };
+        let file = |issue_id: &str, actor: &str, involved: &[&str]| {
+            let issue = issue("Bouwtekening", involved);
+            let document = Document {
+                title: "bouwtekening.pdf".to_string(),
+                url: location.to_string(),
+                size: 11,
+            };
+            let state = state.clone();
+            let (issue_id, actor) = (issue_id.to_string(), actor.to_string());
+            async move {
+                create_issue(&state, &issue_id, &issue, &actor).await;
+                let commit = CommitBuilder::create(format!("{}-doc", issue_id), &document)
+                    .actor(&actor)
+                    .build();
+                submit_commit_event(&state, &issue_id, &commit)
+                    .await
+                    .unwrap();
+            }
+        };
+        let mallory = "mallory@example.com";
+        assert_eq!(download(mallory).await.unwrap_err(), StatusCode::NOT_FOUND);
+        file("issue-mallory", mallory, &[mallory]).await;
+        assert_eq!(download(mallory).await.unwrap_err(), StatusCode::NOT_FOUND);
+        file("issue-1", alice, &[alice, "bob@gemeente.nl"]).await;
+        assert!(download("bob@gemeente.nl").await.is_ok());
+        assert_eq!(download(mallory).await.unwrap_err(), StatusCode::NOT_FOUND);
+
+        // Unfinished uploads expire
+        let response = create_upload_handler(
+            State(state.clone()),
+            user(alice),
+            tus_headers(&[("upload-length", "100")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let later = Utc::now() + Duration::hours(UPLOAD_EXPIRY_HOURS + 1);
+        assert_eq!(remove_expired(&state, later).await.unwrap(), 1);
+        assert!(load(&state, &id).await.unwrap().is_some());
+
+        // Only the owner removes an upload
+        let delete = |email: &str| {
+            delete_upload_handler(
+                State(state.clone()),
+                user(email),
+                tus_headers(&[]),
+                Path(id.clone()),
+            )
+        };
+        assert_eq!(
+            delete("bob@gemeente.nl").await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            delete(alice).await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+        assert!(load(&state, &id).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,287 @@
+//! User directory, for @-mention autocomplete and for showing names instead of emails.
+//!
+//! The directory combines two sources: every user `process_event` has seen as commit actor,
+//! involved person or assignee, and explicit `Profile` resources (`user-{email}`) with a
+//! display name and department, which users maintain themselves with `PUT /users/me`.
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::{tenant_of, AuthUser};
+use crate::handlers::{authorize_issue, internal_error, submit_commit, AppState};
+use crate::schemas::{CloudEvent, CommitBuilder, Profile};
+use crate::storage::Storage;
+
+/// Default number of users returned by `GET /users`
+const DEFAULT_LIMIT: usize = 10;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UserQueryParams {
+    /// Part of a name or email address; matches everyone if empty
+    #[serde(default)]
+    pub query: String,
+    /// Also include the people involved in this issue, when they are from another organisation
+    pub issue_id: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ProfileRequest {
+    pub display_name: String,
+    pub department: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct DirectoryEntry {
+    pub email: String,
+    /// The profile's display name, or the part of the email before the @
+    pub display_name: String,
+    pub department: Option<String>,
+    /// When the user last made or appeared in a commit (RFC 3339)
+    pub last_seen: Option<String>,
+}
+
+/// Resource ID of a user's profile
+pub fn profile_id(email: &str) -> String {
+    format!("user-{}", email)
+}
+
+/// All known users, seen or with a profile, in email order.
+pub async fn directory(
+    storage: &Storage,
+) -> Result<Vec<DirectoryEntry>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut entries: BTreeMap<String, DirectoryEntry> = BTreeMap::new();
+    for (email, last_seen) in storage.list_users().await? {
+        let display_name = email.split('@').next().unwrap_or(&email).to_string();
+        entries.insert(
+            email.clone(),
+            DirectoryEntry {
+                email,
+                display_name,
+                department: None,
+                last_seen: Some(last_seen),
+            },
+        );
+    }
+    for (id, value) in storage.list_resources_with_prefix("user-").await? {
+        let profile: Profile = match serde_json::from_value(value) {
+            Ok(profile) => profile,
+            Err(e) => {
+                eprintln!("[users] skipping malformed profile {}: {}", id, e);
+                continue;
+            }
+        };
+        let entry = entries
+            .entry(profile.email.clone())
+            .or_insert_with(|| DirectoryEntry {
+                email: profile.email.clone(),
+                display_name: String::new(),
+                department: None,
+                last_seen: None,
+            });
+        entry.display_name = profile.display_name;
+        entry.department = profile.department;
+    }
+    Ok(entries.into_values().collect())
+}
+
+/// How well `entry` matches the lowercased `query`: 0 for a name or email starting with
+/// it, 1 for containing it, `None` for no match.
+fn match_rank(entry: &DirectoryEntry, query: &str) -> Option<u8> {
+    let name = entry.display_name.to_lowercase();
+    let email = entry.email.to_lowercase();
+    let starts = name.split_whitespace().any(|w| w.starts_with(query)) || email.starts_with(query);
+    if starts {
+        Some(0)
+    } else if name.contains(query) || email.contains(query) {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+/// GET /users - Find users to mention
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "resources",
+    params(UserQueryParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Matching users of the caller's organisation (and of the issue), best match first", body = [DirectoryEntry]),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller is not involved in the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn search_users_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<UserQueryParams>,
+) -> Result<Json<Vec<DirectoryEntry>>, StatusCode> {
+    let user = auth_user.user_id;
+    let mut visible = Vec::new();
+    if let Some(issue_id) = &params.issue_id {
+        authorize_issue(&state, &user, issue_id).await?;
+        let issue = state
+            .storage
+            .get_resource(issue_id)
+            .await
+            .map_err(internal_error)?
+            .unwrap_or_default();
+        if let Some(involved) = issue.get("involved").and_then(|v| v.as_array()) {
+            visible.extend(
+                involved
+                    .iter()
+                    .filter_map(|v| v.as_str().map(str::to_string)),
+            );
+        }
+    }
+
+    let query = params.query.trim().to_lowercase();
+    let mut matches: Vec<(u8, DirectoryEntry)> = directory(&state.storage)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .filter(|e| tenant_of(&e.email) == tenant_of(&user) || visible.contains(&e.email))
+        .filter_map(|e| Some((match_rank(&e, &query)?, e)))
+        .collect();
+    matches.sort_by(|(a_rank, a), (b_rank, b)| {
+        a_rank.cmp(b_rank).then_with(|| {
+            a.display_name
+                .to_lowercase()
+                .cmp(&b.display_name.to_lowercase())
+        })
+    });
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    Ok(Json(
+        matches.into_iter().take(limit).map(|(_, e)| e).collect(),
+    ))
+}
+
+/// PUT /users/me - Set the caller's display name and department
+#[utoipa::path(
+    put,
+    path = "/users/me",
+    tag = "resources",
+    request_body = ProfileRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Profile commit stored, processed and broadcast", body = CloudEvent),
+        (status = 400, description = "Empty display name"),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn update_profile_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    headers: HeaderMap,
+    Json(request): Json<ProfileRequest>,
+) -> Result<Response, StatusCode> {
+    let display_name = request.display_name.trim().to_string();
+    if display_name.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let department = request
+        .department
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty());
+
+    let user = auth_user.user_id;
+    let id = profile_id(&user);
+    let existing = state
+        .storage
+        .get_resource(&id)
+        .await
+        .map_err(internal_error)?;
+    let commit = match existing {
+        Some(_) => CommitBuilder::patch::<Profile>(
+            id.clone(),
+            json!({ "display_name": display_name, "department": department }),
+        ),
+        None => CommitBuilder::create(
+            id.clone(),
+            &Profile {
+                email: user.clone(),
+                display_name,
+                department,
+            },
+        ),
+    }
+    .actor(user)
+    .build();
+    submit_commit(&state, &headers, &id, commit).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state};
+    use crate::schemas::Issue;
+
+    #[tokio::test]
+    async fn test_directory_and_mention_search() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let melder = "piet@example.com";
+
+        let issue = Issue {
+            assignee: Some("bob@gemeente.nl".to_string()),
+            ..issue("Overlast", &[alice, melder])
+        };
+        create_issue(&state, "issue-1", &issue, melder).await;
+
+        let updated = update_profile_handler(
+            State(state.clone()),
+            user("bob@gemeente.nl"),
+            HeaderMap::new(),
+            Json(ProfileRequest {
+                display_name: "Bob Alibaba".to_string(),
+                department: Some("Handhaving".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.status(), StatusCode::ACCEPTED);
+
+        let search_as = |who: &str, query: &str, issue_id: Option<&str>| {
+            search_users_handler(
+                State(state.clone()),
+                user(who),
+                Query(UserQueryParams {
+                    query: query.to_string(),
+                    issue_id: issue_id.map(str::to_string),
+                    limit: None,
+                }),
+            )
+        };
+        let search = |query: &str, issue_id: Option<&str>| search_as(alice, query, issue_id);
+        let Json(found) = search("ali", None).await.unwrap();
+        let emails: Vec<&str> = found.iter().map(|e| e.email.as_str()).collect();
+        // Both start a name with "ali"; the citizen is from another organisation
+        assert_eq!(emails, vec!["alice@gemeente.nl", "bob@gemeente.nl"]);
+        assert_eq!(found[0].display_name, "alice");
+        assert_eq!(found[1].display_name, "Bob Alibaba");
+        assert_eq!(found[1].department.as_deref(), Some("Handhaving"));
+
+        // Within an issue, its other participants can be mentioned too
+        let Json(found) = search("piet", Some("issue-1")).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].last_seen.is_some());
+        let Json(found) = search("piet", None).await.unwrap();
+        assert!(found.is_empty());
+        // Not through an issue one isn't involved in
+        let outsider = search_as("carol@gemeente.nl", "piet", Some("issue-1"));
+        assert_eq!(outsider.await.unwrap_err(), StatusCode::FORBIDDEN);
+    }
+}
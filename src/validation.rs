@@ -0,0 +1,244 @@
+//! Validation of the CloudEvents envelope itself, before an event enters the pipeline.
+//!
+//! Deserializing a `CloudEvent` only checks that the attributes are there and are strings;
+//! `validate_envelope` checks what the CloudEvents 1.0 spec requires of their values: a
+//! supported `specversion`, non-empty `id` and `type`, a `source` that is a URI-reference,
+//! a `time` in RFC 3339 format, and a `datacontenttype` that is a media type matching the
+//! `data`. Every violation is reported, each naming the attribute, so a client can fix the
+//! event in one go.
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::schemas::CloudEvent;
+
+/// The CloudEvents versions this server accepts
+pub const SUPPORTED_SPECVERSIONS: &[&str] = &["1.0"];
+
+/// One invalid envelope attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvelopeError {
+    /// Name of the attribute, as in the CloudEvents spec
+    pub attribute: &'static str,
+    pub message: String,
+}
+
+impl EnvelopeError {
+    fn new(attribute: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            attribute,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.attribute, self.message)
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+/// Check the envelope attributes of `event`; all violations are returned.
+pub fn validate_envelope(event: &CloudEvent) -> Result<(), Vec<EnvelopeError>> {
+    let mut errors = Vec::new();
+    if !SUPPORTED_SPECVERSIONS.contains(&event.specversion.as_str()) {
+        errors.push(EnvelopeError::new(
+            "specversion",
+            format!(
+                "unsupported version {:?}; supported: {}",
+                event.specversion,
+                SUPPORTED_SPECVERSIONS.join(", ")
+            ),
+        ));
+    }
+    if event.id.trim().is_empty() {
+        errors.push(EnvelopeError::new("id", "must not be empty"));
+    }
+    if event.event_type.trim().is_empty() {
+        errors.push(EnvelopeError::new("type", "must not be empty"));
+    }
+    if let Err(e) = check_uri_reference(&event.source) {
+        errors.push(EnvelopeError::new(
+            "source",
+            format!("{:?} is not a URI-reference: {}", event.source, e),
+        ));
+    }
+    if let Some(time) = &event.time {
+        if let Err(e) = chrono::DateTime::parse_from_rfc3339(time) {
+            errors.push(EnvelopeError::new(
+                "time",
+                format!("{:?} is not an RFC 3339 timestamp: {}", time, e),
+            ));
+        }
+    }
+    if let Some(content_type) = &event.datacontenttype {
+        match media_type(content_type) {
+            Err(e) => errors.push(EnvelopeError::new(
+                "datacontenttype",
+                format!("{:?} is not a media type: {}", content_type, e),
+            )),
+            // Structured JSON data can only be described by a JSON media type; other types
+            // need their content as a string
+            Ok(essence) if !is_json_media_type(&essence) => {
+                if let Some(data) = event.data.as_ref().filter(|d| !d.is_string()) {
+                    errors.push(EnvelopeError::new(
+                        "datacontenttype",
+                        format!(
+                            "{:?} does not describe JSON data, but data is {}",
+                            content_type,
+                            json_kind(data)
+                        ),
+                    ));
+                }
+            }
+            Ok(_) => {}
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Check `value` against the URI-reference grammar of RFC 3986: only URI characters,
+/// well-formed percent-encodings, and a valid scheme if there is one.
+fn check_uri_reference(value: &str) -> Result<(), String> {
+    if value.is_empty() {
+        return Err("must not be empty".to_string());
+    }
+    let mut chars = value.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '%' => {
+                let hex = value.get(i + 1..i + 3).unwrap_or_default();
+                if hex.len() != 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!("invalid percent-encoding at position {}", i));
+                }
+                chars.nth(1);
+            }
+            c if c.is_ascii_alphanumeric() || "-._~:/?#[]@!$&'()*+,;=".contains(c) => {}
+            c => return Err(format!("invalid character {:?} at position {}", c, i)),
+        }
+    }
+    // A colon before the first '/', '?' or '#' ends the scheme
+    let first = value.split(['/', '?', '#']).next().unwrap_or_default();
+    if let Some((scheme, _)) = first.split_once(':') {
+        let mut scheme_chars = scheme.chars();
+        let valid = scheme_chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            && scheme_chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+        if !valid {
+            return Err(format!("invalid scheme {:?}", scheme));
+        }
+    }
+    Ok(())
+}
+
+/// The lowercase `type/subtype` of a media type with optional parameters.
+fn media_type(value: &str) -> Result<String, String> {
+    let essence = value.split(';').next().unwrap_or_default().trim();
+    let (kind, subtype) = essence
+        .split_once('/')
+        .ok_or_else(|| "expected type/subtype".to_string())?;
+    let is_token = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+    };
+    if !is_token(kind) || !is_token(subtype) {
+        return Err("expected type/subtype".to_string());
+    }
+    Ok(essence.to_ascii_lowercase())
+}
+
+fn is_json_media_type(essence: &str) -> bool {
+    matches!(essence, "application/json" | "text/json") || essence.ends_with("+json")
+}
+
+fn json_kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{
+        submit_event,
+        tests::{issue, test_state},
+    };
+    use crate::pipeline::ProcessError;
+    use crate::schemas::{CloudEventBuilder, CommitBuilder};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_validate_envelope() {
+        let commit = CommitBuilder::create("issue-1", &issue("Paspoort", &[])).build();
+        let event = CloudEventBuilder::commit("issue-1", &commit).build();
+        assert_eq!(validate_envelope(&event), Ok(()));
+
+        for source in [
+            "urn:nl:gemeente",
+            "https://zaken.example/api",
+            "/sources/a%20b",
+        ] {
+            assert!(check_uri_reference(source).is_ok(), "{}", source);
+        }
+        for source in ["", "zaak systeem", "1http://x", "/a%2"] {
+            assert!(check_uri_reference(source).is_err(), "{}", source);
+        }
+
+        let mut bad = event.clone();
+        bad.specversion = "0.3".to_string();
+        bad.id = " ".to_string();
+        bad.source = "mijn bron".to_string();
+        bad.time = Some("15-10-2026 12:00".to_string());
+        bad.datacontenttype = Some("text/plain".to_string());
+        let errors = validate_envelope(&bad).unwrap_err();
+        let attributes: Vec<_> = errors.iter().map(|e| e.attribute).collect();
+        assert_eq!(
+            attributes,
+            ["specversion", "id", "source", "time", "datacontenttype"]
+        );
+
+        let mut text = event.clone();
+        text.datacontenttype = Some("text/plain; charset=utf-8".to_string());
+        text.data = Some(json!("hallo"));
+        assert_eq!(validate_envelope(&text), Ok(()));
+        text.datacontenttype = Some("json".to_string());
+        assert_eq!(
+            validate_envelope(&text).unwrap_err()[0].attribute,
+            "datacontenttype"
+        );
+
+        // Malformed envelopes are rejected before anything is stored
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let result = state
+            .pipeline
+            .submit(&state, crate::pipeline::EventContext::new(bad))
+            .await;
+        assert!(matches!(result, Err(ProcessError::Invalid(m)) if m.contains("specversion")));
+        assert!(state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .is_none());
+        submit_event(&state, event).await.unwrap();
+        assert!(state
+            .storage
+            .get_resource("issue-1")
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
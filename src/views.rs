@@ -0,0 +1,668 @@
+//! Materialized views: built-in projections that answer the common "which issues / tasks"
+//! questions without scanning every resource.
+//!
+//! Both views are [`NamedProjection`]s, so the event pipeline keeps them up to date with
+//! every commit and they can be rebuilt like any other projection:
+//! - `issues-by-status`: `status/{status}` holds the sorted IDs of the issues with that
+//!   status, `issue/{id}` the status of each issue;
+//! - `tasks-by-assignee`: `assignee/{email}` holds the open tasks assigned to someone, ordered
+//!   by deadline (tasks without one last), `task/{id}` what the view knows of each task.
+//!
+//! Served by `GET /views/issues-by-status/{status}` and `GET /views/tasks-by-assignee/{assignee}`.
+//! `GET /me/tasks` (the caller's werkvoorraad) uses the `task/{id}` entries, so it also finds
+//! completed tasks and the unassigned tasks of the issues assigned to the caller.
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::AuthUser;
+use crate::handlers::{check_access, commit_of, internal_error};
+use crate::handlers::{AppState, ResourceResponse};
+use crate::projections::{ModelView, NamedProjection};
+use crate::resource_types::resolve;
+use crate::schemas::CloudEvent;
+use crate::storage::Storage;
+
+pub const ISSUES_BY_STATUS: &str = "issues-by-status";
+pub const TASKS_BY_ASSIGNEE: &str = "tasks-by-assignee";
+
+/// Issue IDs per status
+pub struct IssuesByStatus;
+
+#[async_trait]
+impl NamedProjection for IssuesByStatus {
+    fn name(&self) -> &'static str {
+        ISSUES_BY_STATUS
+    }
+
+    async fn reduce(
+        &self,
+        model: &mut ModelView<'_>,
+        event: &CloudEvent,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(commit) = commit_of(event) else {
+            return Ok(());
+        };
+        if resolve(model.storage(), &commit.schema).await? != "Issue" {
+            return Ok(());
+        }
+        let issue_key = format!("issue/{}", commit.resource_id);
+        let previous = model
+            .get(&issue_key)
+            .await?
+            .and_then(|v| v.as_str().map(str::to_string));
+        let status = |v: &Value| v.get("status").and_then(Value::as_str).map(str::to_string);
+
+        let next = if commit.deleted == Some(true) {
+            None
+        } else if let Some(data) = &commit.resource_data {
+            Some(status(data).unwrap_or_else(|| "open".to_string()))
+        } else if let Some(patch) = &commit.merge_patch() {
+            match (status(patch), &previous) {
+                (Some(status), _) => Some(status),
+                (None, Some(previous)) => Some(previous.clone()),
+                (None, None) => return Ok(()),
+            }
+        } else {
+            return Ok(());
+        };
+        if previous == next {
+            return Ok(());
+        }
+
+        if let Some(previous) = &previous {
+            let key = format!("status/{}", previous);
+            let mut ids: Vec<String> = list(model.get(&key).await?);
+            ids.retain(|id| id != &commit.resource_id);
+            match ids.is_empty() {
+                true => model.remove(key),
+                false => model.put(key, json!(ids)),
+            }
+        }
+        match next {
+            Some(status) => {
+                let key = format!("status/{}", status);
+                let mut ids: Vec<String> = list(model.get(&key).await?);
+                if let Err(at) = ids.binary_search(&commit.resource_id) {
+                    ids.insert(at, commit.resource_id.clone());
+                }
+                model.put(key, json!(ids));
+                model.put(issue_key, json!(status));
+            }
+            None => model.remove(issue_key),
+        }
+        Ok(())
+    }
+}
+
+/// An open task in someone's list
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct AssignedTask {
+    pub task_id: String,
+    /// The issue the task belongs to
+    pub issue_id: String,
+    /// YYYY-MM-DD
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<String>,
+}
+
+impl AssignedTask {
+    /// Deadline first, tasks without one last
+    fn order(&self) -> (bool, Option<&str>, &str) {
+        (
+            self.deadline.is_none(),
+            self.deadline.as_deref(),
+            &self.task_id,
+        )
+    }
+}
+
+/// What the view knows of a task
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TaskState {
+    #[serde(default)]
+    issue_id: String,
+    #[serde(default)]
+    assignee: Option<String>,
+    #[serde(default)]
+    deadline: Option<String>,
+    #[serde(default)]
+    completed: bool,
+}
+
+/// Open tasks per assignee, by deadline
+pub struct TasksByAssignee;
+
+#[async_trait]
+impl NamedProjection for TasksByAssignee {
+    fn name(&self) -> &'static str {
+        TASKS_BY_ASSIGNEE
+    }
+
+    async fn reduce(
+        &self,
+        model: &mut ModelView<'_>,
+        event: &CloudEvent,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(commit) = commit_of(event) else {
+            return Ok(());
+        };
+        if resolve(model.storage(), &commit.schema).await? != "Task" {
+            return Ok(());
+        }
+        let task_key = format!("task/{}", commit.resource_id);
+        let previous: Option<TaskState> = model
+            .get(&task_key)
+            .await?
+            .and_then(|v| serde_json::from_value(v).ok());
+
+        let next = if commit.deleted == Some(true) {
+            None
+        } else if let Some(data) = &commit.resource_data {
+            let mut task: TaskState = serde_json::from_value(data.clone()).unwrap_or_default();
+            task.issue_id = event.subject.clone();
+            Some(task)
+        } else if let (Some(patch), Some(previous)) = (&commit.merge_patch(), &previous) {
+            let field = |name: &str| patch.get(name).map(|v| v.as_str().map(str::to_string));
+            Some(TaskState {
+                issue_id: previous.issue_id.clone(),
+                assignee: field("assignee").unwrap_or_else(|| previous.assignee.clone()),
+                deadline: field("deadline").unwrap_or_else(|| previous.deadline.clone()),
+                completed: match patch.get("completed") {
+                    Some(completed) => completed.as_bool().unwrap_or(false),
+                    None => previous.completed,
+                },
+            })
+        } else {
+            return Ok(());
+        };
+
+        if let Some(TaskState {
+            assignee: Some(assignee),
+            completed: false,
+            ..
+        }) = &previous
+        {
+            let key = format!("assignee/{}", assignee);
+            let mut tasks: Vec<AssignedTask> = list(model.get(&key).await?);
+            tasks.retain(|t| t.task_id != commit.resource_id);
+            match tasks.is_empty() {
+                true => model.remove(key),
+                false => model.put(key, json!(tasks)),
+            }
+        }
+        match next {
+            Some(task) => {
+                if let (Some(assignee), false) = (&task.assignee, task.completed) {
+                    let key = format!("assignee/{}", assignee);
+                    let mut tasks: Vec<AssignedTask> = list(model.get(&key).await?);
+                    let entry = AssignedTask {
+                        task_id: commit.resource_id.clone(),
+                        issue_id: task.issue_id.clone(),
+                        deadline: task.deadline.clone(),
+                    };
+                    let at = tasks
+                        .binary_search_by(|t| t.order().cmp(&entry.order()))
+                        .unwrap_or_else(|at| at);
+                    tasks.insert(at, entry);
+                    model.put(key, json!(tasks));
+                }
+                model.put(task_key, serde_json::to_value(&task)?);
+            }
+            None => model.remove(task_key),
+        }
+        Ok(())
+    }
+}
+
+fn list<T: serde::de::DeserializeOwned>(value: Option<Value>) -> Vec<T> {
+    value
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default()
+}
+
+/// IDs of the issues with `status` ("open", "in_progress", "closed"), sorted.
+pub async fn issues_with_status(
+    storage: &Storage,
+    status: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(list(
+        storage
+            .get_projection_value(ISSUES_BY_STATUS, &format!("status/{}", status))
+            .await?,
+    ))
+}
+
+/// The open tasks assigned to `assignee`, by deadline.
+pub async fn tasks_assigned_to(
+    storage: &Storage,
+    assignee: &str,
+) -> Result<Vec<AssignedTask>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(list(
+        storage
+            .get_projection_value(TASKS_BY_ASSIGNEE, &format!("assignee/{}", assignee))
+            .await?,
+    ))
+}
+
+/// Which of someone's tasks to list
+#[derive(Debug, Default, Clone)]
+pub struct TaskFilter {
+    /// Only tasks with a deadline before this date (YYYY-MM-DD)
+    pub due_before: Option<String>,
+    /// Only completed (true) or open (false) tasks; both when absent
+    pub completed: Option<bool>,
+}
+
+/// The tasks of `user` matching `filter`, by deadline: those assigned to them, and those
+/// without assignee in issues assigned to them. With their completion state.
+pub async fn tasks_of(
+    storage: &Storage,
+    user: &str,
+    filter: &TaskFilter,
+) -> Result<Vec<(AssignedTask, bool)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut issue_assignees: HashMap<String, Option<String>> = HashMap::new();
+    let mut tasks = Vec::new();
+    for (key, value) in storage.list_projection_values(TASKS_BY_ASSIGNEE).await? {
+        let Some(task_id) = key.strip_prefix("task/") else {
+            continue;
+        };
+        let Ok(task) = serde_json::from_value::<TaskState>(value) else {
+            continue;
+        };
+        if filter.completed.is_some_and(|c| c != task.completed) {
+            continue;
+        }
+        if let Some(due_before) = &filter.due_before {
+            if task.deadline.as_ref().is_none_or(|d| d >= due_before) {
+                continue;
+            }
+        }
+        let mine = match &task.assignee {
+            Some(assignee) => assignee == user,
+            None => {
+                if !issue_assignees.contains_key(&task.issue_id) {
+                    let assignee = storage
+                        .get_resource(&task.issue_id)
+                        .await?
+                        .and_then(|issue| issue.get("assignee")?.as_str().map(str::to_string));
+                    issue_assignees.insert(task.issue_id.clone(), assignee);
+                }
+                issue_assignees[&task.issue_id].as_deref() == Some(user)
+            }
+        };
+        if mine {
+            let entry = AssignedTask {
+                task_id: task_id.to_string(),
+                issue_id: task.issue_id,
+                deadline: task.deadline,
+            };
+            tasks.push((entry, task.completed));
+        }
+    }
+    tasks.sort_by(|(a, _), (b, _)| a.order().cmp(&b.order()));
+    Ok(tasks)
+}
+
+/// Query parameters for paging through a view
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ViewParams {
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    50
+}
+
+/// An open task with its current data
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TaskViewEntry {
+    #[serde(flatten)]
+    pub task: AssignedTask,
+    #[schema(value_type = Object)]
+    pub data: Value,
+}
+
+/// GET /views/issues-by-status/{status} - The issues with a status that the caller can see
+#[utoipa::path(
+    get,
+    path = "/views/issues-by-status/{status}",
+    tag = "resources",
+    params(
+        ("status" = String, Path, description = "open, in_progress or closed"),
+        ViewParams,
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Issues ordered by ID", body = Vec<ResourceResponse>),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn issues_by_status_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(status): Path<String>,
+    Query(params): Query<ViewParams>,
+) -> Result<Json<Vec<ResourceResponse>>, StatusCode> {
+    let ids = issues_with_status(&state.storage, &status)
+        .await
+        .map_err(internal_error)?;
+    let mut issues = Vec::new();
+    for id in ids {
+        if !check_access(&state.storage, &auth_user.user_id, &id).await {
+            continue;
+        }
+        if let Some(data) = state
+            .storage
+            .get_resource(&id)
+            .await
+            .map_err(internal_error)?
+        {
+            issues.push(ResourceResponse {
+                id,
+                resource_type: "issue".to_string(),
+                data,
+                unread: None,
+            });
+        }
+    }
+    Ok(Json(
+        issues
+            .into_iter()
+            .skip(params.offset)
+            .take(params.limit)
+            .collect(),
+    ))
+}
+
+/// GET /views/tasks-by-assignee/{assignee} - Open tasks of an assignee ("me" for the caller)
+#[utoipa::path(
+    get,
+    path = "/views/tasks-by-assignee/{assignee}",
+    tag = "resources",
+    params(
+        ("assignee" = String, Path, description = "Email of the assignee, or \"me\""),
+        ViewParams,
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Open tasks by deadline, those without one last; for others' tasks only those in issues the caller can see", body = Vec<TaskViewEntry>),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn tasks_by_assignee_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(assignee): Path<String>,
+    Query(params): Query<ViewParams>,
+) -> Result<Json<Vec<TaskViewEntry>>, StatusCode> {
+    let assignee = match assignee.as_str() {
+        "me" => auth_user.user_id.clone(),
+        _ => assignee,
+    };
+    let own = assignee == auth_user.user_id;
+    let mut entries = Vec::new();
+    for task in tasks_assigned_to(&state.storage, &assignee)
+        .await
+        .map_err(internal_error)?
+    {
+        if !own && !check_access(&state.storage, &auth_user.user_id, &task.issue_id).await {
+            continue;
+        }
+        if let Some(data) = state
+            .storage
+            .get_resource(&task.task_id)
+            .await
+            .map_err(internal_error)?
+        {
+            entries.push(TaskViewEntry { task, data });
+        }
+    }
+    Ok(Json(
+        entries
+            .into_iter()
+            .skip(params.offset)
+            .take(params.limit)
+            .collect(),
+    ))
+}
+
+/// Query parameters of `GET /me/tasks`
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MyTasksParams {
+    /// Only tasks with a deadline before this date (YYYY-MM-DD)
+    #[serde(default)]
+    pub due_before: Option<String>,
+    /// Only completed (true) or open (false) tasks; both when absent
+    #[serde(default)]
+    pub completed: Option<bool>,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+/// GET /me/tasks - The caller's tasks across issues
+#[utoipa::path(
+    get,
+    path = "/me/tasks",
+    tag = "resources",
+    params(MyTasksParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Tasks assigned to the caller, and unassigned tasks of issues assigned to the caller, by deadline (those without one last)", body = Vec<TaskViewEntry>),
+        (status = 401, description = "Missing or invalid token"),
+    )
+)]
+pub async fn my_tasks_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Query(params): Query<MyTasksParams>,
+) -> Result<Json<Vec<TaskViewEntry>>, StatusCode> {
+    let filter = TaskFilter {
+        due_before: params.due_before,
+        completed: params.completed,
+    };
+    let tasks = tasks_of(&state.storage, &auth_user.user_id, &filter)
+        .await
+        .map_err(internal_error)?;
+    let mut entries = Vec::new();
+    for (task, _) in tasks.into_iter().skip(params.offset).take(params.limit) {
+        if let Some(data) = state
+            .storage
+            .get_resource(&task.task_id)
+            .await
+            .map_err(internal_error)?
+        {
+            entries.push(TaskViewEntry { task, data });
+        }
+    }
+    Ok(Json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, issue, submit_commit_event, test_state};
+    use crate::schemas::{CommitBuilder, Issue, JSONCommit, Task};
+
+    async fn submit(state: &AppState, subject: &str, commit: JSONCommit) {
+        submit_commit_event(state, subject, &commit).await.unwrap();
+    }
+
+    fn task(assignee: &str, deadline: Option<&str>) -> Task {
+        Task {
+            cta: "Documenten aanleveren".to_string(),
+            description: String::new(),
+            url: String::new(),
+            completed: false,
+            deadline: deadline.map(str::to_string),
+            assignee: Some(assignee.to_string()),
+            recurrence: None,
+        }
+    }
+
+    fn ids(tasks: Vec<AssignedTask>) -> Vec<String> {
+        tasks.into_iter().map(|t| t.task_id).collect()
+    }
+
+    #[tokio::test]
+    async fn test_issue_and_task_views() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        for id in ["issue-1", "issue-2"] {
+            let issue = issue(id, &[]);
+            submit(&state, id, CommitBuilder::create(id, &issue).build()).await;
+        }
+        let start = json!({"status": "in_progress"});
+        submit(
+            &state,
+            "issue-2",
+            CommitBuilder::patch::<Issue>("issue-2", start).build(),
+        )
+        .await;
+        assert_eq!(
+            issues_with_status(&state.storage, "open").await.unwrap(),
+            vec!["issue-1"]
+        );
+        assert_eq!(
+            issues_with_status(&state.storage, "in_progress")
+                .await
+                .unwrap(),
+            vec!["issue-2"]
+        );
+
+        let alice = "alice@example.com";
+        for (id, deadline) in [
+            ("task-1", None),
+            ("task-2", Some("2026-03-01")),
+            ("task-3", Some("2026-02-01")),
+        ] {
+            let task = task(alice, deadline);
+            submit(&state, "issue-1", CommitBuilder::create(id, &task).build()).await;
+        }
+        assert_eq!(
+            ids(tasks_assigned_to(&state.storage, alice).await.unwrap()),
+            vec!["task-3", "task-2", "task-1"]
+        );
+
+        let done = json!({"completed": true});
+        submit(
+            &state,
+            "issue-1",
+            CommitBuilder::patch::<Task>("task-3", done).build(),
+        )
+        .await;
+        let reassign = json!({"assignee": "bob@example.com"});
+        submit(
+            &state,
+            "issue-1",
+            CommitBuilder::patch::<Task>("task-1", reassign).build(),
+        )
+        .await;
+        let tasks = tasks_assigned_to(&state.storage, alice).await.unwrap();
+        assert_eq!(
+            tasks,
+            vec![AssignedTask {
+                task_id: "task-2".to_string(),
+                issue_id: "issue-1".to_string(),
+                deadline: Some("2026-03-01".to_string()),
+            }]
+        );
+        assert_eq!(
+            ids(tasks_assigned_to(&state.storage, "bob@example.com")
+                .await
+                .unwrap()),
+            vec!["task-1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_my_tasks() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let (alice, bob) = ("alice@example.com", "bob@example.com");
+        let paspoort = Issue {
+            assignee: Some(alice.to_string()),
+            ..issue("Paspoort", &[])
+        };
+        submit(
+            &state,
+            "issue-1",
+            CommitBuilder::create("issue-1", &paspoort).build(),
+        )
+        .await;
+        let rijbewijs = issue("Rijbewijs", &[]);
+        submit(
+            &state,
+            "issue-2",
+            CommitBuilder::create("issue-2", &rijbewijs).build(),
+        )
+        .await;
+
+        let mut unassigned = task(alice, Some("2026-01-15"));
+        unassigned.assignee = None;
+        let mut done = task(alice, Some("2026-01-01"));
+        done.completed = true;
+        for (id, issue, task) in [
+            ("task-1", "issue-1", unassigned.clone()),
+            ("task-2", "issue-2", task(alice, Some("2026-03-01"))),
+            ("task-3", "issue-2", task(alice, None)),
+            ("task-4", "issue-2", done),
+            ("task-5", "issue-1", task(bob, Some("2026-01-02"))),
+            // Not alice's: nobody's task in an issue not assigned to her
+            ("task-6", "issue-2", unassigned),
+        ] {
+            submit(&state, issue, CommitBuilder::create(id, &task).build()).await;
+        }
+
+        let tasks_of = |who: &str, due_before: Option<&str>, completed: Option<bool>| {
+            let state = state.clone();
+            let who = user(who);
+            let params = MyTasksParams {
+                due_before: due_before.map(str::to_string),
+                completed,
+                offset: 0,
+                limit: 50,
+            };
+            async move {
+                let Json(entries) = my_tasks_handler(State(state), who, Query(params))
+                    .await
+                    .unwrap();
+                entries
+                    .into_iter()
+                    .map(|e| e.task.task_id)
+                    .collect::<Vec<_>>()
+            }
+        };
+        let my_tasks = |due_before, completed| tasks_of(alice, due_before, completed);
+        assert_eq!(
+            my_tasks(None, None).await,
+            vec!["task-4", "task-1", "task-2", "task-3"]
+        );
+        assert_eq!(
+            my_tasks(None, Some(false)).await,
+            vec!["task-1", "task-2", "task-3"]
+        );
+        assert_eq!(
+            my_tasks(Some("2026-02-01"), Some(false)).await,
+            vec!["task-1"]
+        );
+        assert_eq!(my_tasks(None, Some(true)).await, vec!["task-4"]);
+        // Only her own: bob sees his task in alice's issue, and nothing of alice's
+        assert_eq!(tasks_of(bob, None, None).await, vec!["task-5"]);
+    }
+}
@@ -0,0 +1,153 @@
+//! Watching issues: explicit per-user subscriptions, separate from `involved`.
+//!
+//! Watching is a user preference, not part of the issue, so it is stored per user and issue
+//! like read markers instead of as commits. Watchers get the same comment notifications as
+//! involved users, plus a notification when the issue itself is updated. Issue reads report
+//! whether the caller watches the issue.
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::{authorize_issue, AppState};
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WatchStatus {
+    pub issue_id: String,
+    pub watching: bool,
+}
+
+async fn set_watching(
+    state: &AppState,
+    user: &str,
+    issue_id: String,
+    watching: bool,
+) -> Result<Json<WatchStatus>, StatusCode> {
+    authorize_issue(state, user, &issue_id).await?;
+    state
+        .storage
+        .set_watching(&issue_id, user, watching)
+        .await
+        .map_err(|e| {
+            eprintln!("[watch] failed to update watch on {}: {}", issue_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(WatchStatus { issue_id, watching }))
+}
+
+/// POST /issues/{id}/watch - Get notified about an issue
+#[utoipa::path(
+    post,
+    path = "/issues/{id}/watch",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The caller now watches the issue", body = WatchStatus),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn watch_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+) -> Result<Json<WatchStatus>, StatusCode> {
+    set_watching(&state, &auth_user.user_id, issue_id, true).await
+}
+
+/// DELETE /issues/{id}/watch - Stop watching an issue
+#[utoipa::path(
+    delete,
+    path = "/issues/{id}/watch",
+    tag = "resources",
+    params(("id" = String, Path, description = "Issue ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The caller no longer watches the issue", body = WatchStatus),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Caller has no access to the issue"),
+        (status = 404, description = "Unknown issue"),
+    )
+)]
+pub async fn unwatch_handler(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Path(issue_id): Path<String>,
+) -> Result<Json<WatchStatus>, StatusCode> {
+    set_watching(&state, &auth_user.user_id, issue_id, false).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user as user, create_issue, issue, test_state};
+
+    #[tokio::test]
+    async fn test_watch_and_unwatch() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let alice = "alice@gemeente.nl";
+        let issue = issue("Lantaarnpaal kapot", &[alice]);
+        create_issue(&state, "issue-1", &issue, alice).await;
+
+        let watching = || async {
+            let Json(issue) = crate::handlers::get_resource(
+                State(state.clone()),
+                Some(user(alice)),
+                Path("issue-1".to_string()),
+                axum::extract::Query(Default::default()),
+            )
+            .await
+            .unwrap();
+            issue["watching"].clone()
+        };
+        assert_eq!(watching().await, false);
+
+        let Json(status) = watch_handler(
+            State(state.clone()),
+            user(alice),
+            Path("issue-1".to_string()),
+        )
+        .await
+        .unwrap();
+        assert!(status.watching);
+        assert_eq!(watching().await, true);
+        assert_eq!(
+            state.storage.list_watchers("issue-1").await.unwrap(),
+            vec![alice]
+        );
+
+        let bob = "bob@gemeente.nl";
+        let watch = |who: &str, id: &str| {
+            watch_handler(State(state.clone()), user(who), Path(id.to_string()))
+        };
+        assert_eq!(
+            watch(bob, "issue-1").await.unwrap_err(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            watch(alice, "issue-9").await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+
+        let unwatch = |who: &str| {
+            unwatch_handler(State(state.clone()), user(who), Path("issue-1".to_string()))
+        };
+        assert_eq!(unwatch(bob).await.unwrap_err(), StatusCode::FORBIDDEN);
+        let Json(status) = unwatch(alice).await.unwrap();
+        assert!(!status.watching);
+        assert_eq!(watching().await, false);
+        assert!(state
+            .storage
+            .list_watchers("issue-1")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
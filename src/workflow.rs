@@ -0,0 +1,241 @@
+//! The process of a zaaktype as a graph.
+//!
+//! A `Zaaktype` resource lists the states its cases go through and the transitions between
+//! them. `GET /zaaktypes/{id}/workflow` returns that as a graph the frontend can draw: every
+//! state with its responsible role, SLA and the states that can follow it, so the portal can
+//! tell citizens "wat gebeurt er hierna".
+use std::collections::BTreeSet;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::auth::AuthUser;
+use crate::handlers::AppState;
+use crate::schemas::Zaaktype;
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct WorkflowState {
+    pub key: String,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sla_hours: Option<u64>,
+    /// The states a transition leads to from here
+    pub next: Vec<String>,
+    /// The first state of the process
+    pub initial: bool,
+    /// No transition leaves this state
+    #[serde(rename = "final")]
+    pub is_final: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct WorkflowTransition {
+    pub from: String,
+    pub to: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Workflow {
+    pub zaaktype_id: String,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// In the order of the zaaktype
+    pub states: Vec<WorkflowState>,
+    pub transitions: Vec<WorkflowTransition>,
+    /// The roles responsible for a state or allowed to make a transition
+    pub roles: Vec<String>,
+}
+
+/// The graph of `zaaktype`; fails when states are defined twice or transitions name unknown
+/// states.
+pub fn workflow(zaaktype_id: &str, zaaktype: &Zaaktype) -> Result<Workflow, String> {
+    let mut keys = BTreeSet::new();
+    for state in &zaaktype.states {
+        if !keys.insert(state.key.as_str()) {
+            return Err(format!("state {:?} is defined twice", state.key));
+        }
+    }
+    for transition in &zaaktype.transitions {
+        for key in [&transition.from, &transition.to] {
+            if !keys.contains(key.as_str()) {
+                return Err(format!("transition to or from unknown state {:?}", key));
+            }
+        }
+    }
+
+    let states = zaaktype
+        .states
+        .iter()
+        .enumerate()
+        .map(|(i, state)| {
+            let mut next = Vec::new();
+            for transition in &zaaktype.transitions {
+                if transition.from == state.key && !next.contains(&transition.to) {
+                    next.push(transition.to.clone());
+                }
+            }
+            WorkflowState {
+                key: state.key.clone(),
+                title: state.title.clone(),
+                description: state.description.clone(),
+                role: state.role.clone(),
+                sla_hours: state.sla_hours,
+                is_final: next.is_empty(),
+                next,
+                initial: i == 0,
+            }
+        })
+        .collect();
+    let transitions = zaaktype
+        .transitions
+        .iter()
+        .map(|transition| WorkflowTransition {
+            from: transition.from.clone(),
+            to: transition.to.clone(),
+            label: transition.label.clone(),
+            role: transition.role.clone(),
+        })
+        .collect();
+    let roles: BTreeSet<String> = zaaktype
+        .states
+        .iter()
+        .filter_map(|state| state.role.clone())
+        .chain(zaaktype.transitions.iter().filter_map(|t| t.role.clone()))
+        .collect();
+    Ok(Workflow {
+        zaaktype_id: zaaktype_id.to_string(),
+        title: zaaktype.title.clone(),
+        description: zaaktype.description.clone(),
+        states,
+        transitions,
+        roles: roles.into_iter().collect(),
+    })
+}
+
+/// GET /zaaktypes/{id}/workflow - The states and transitions of a zaaktype
+#[utoipa::path(
+    get,
+    path = "/zaaktypes/{id}/workflow",
+    tag = "resources",
+    params(("id" = String, Path, description = "Zaaktype ID")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "States with role, SLA and next states, and the transitions", body = Workflow),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 404, description = "Unknown zaaktype"),
+        (status = 422, description = "The zaaktype's transitions name unknown states"),
+    )
+)]
+pub async fn workflow_handler(
+    State(state): State<AppState>,
+    _auth_user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Json<Workflow>, StatusCode> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| {
+        eprintln!("[workflow] failed to read zaaktype {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    if state
+        .storage
+        .get_resource_type(&id)
+        .await
+        .map_err(internal)?
+        .as_deref()
+        != Some("Zaaktype")
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let data = state
+        .storage
+        .get_resource(&id)
+        .await
+        .map_err(internal)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let zaaktype: Zaaktype = serde_json::from_value(data).map_err(|e| {
+        eprintln!("[workflow] zaaktype {} is invalid: {}", id, e);
+        StatusCode::UNPROCESSABLE_ENTITY
+    })?;
+    workflow(&id, &zaaktype).map(Json).map_err(|e| {
+        eprintln!("[workflow] zaaktype {} is invalid: {}", id, e);
+        StatusCode::UNPROCESSABLE_ENTITY
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::tests::{auth_user, submit_commit_event, test_state};
+    use crate::schemas::CommitBuilder;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_workflow_graph() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = test_state(dir.path()).await;
+        let zaaktype: Zaaktype = serde_json::from_value(json!({
+            "title": "Paspoort aanvragen",
+            "states": [
+                {"key": "ontvangen", "title": "Aanvraag ontvangen", "role": "balie", "sla_hours": 24},
+                {"key": "beoordeling", "title": "Aanvraag wordt beoordeeld", "role": "behandelaar", "sla_hours": 120},
+                {"key": "afgerond", "title": "Paspoort ligt klaar"},
+                {"key": "afgewezen", "title": "Aanvraag afgewezen"},
+            ],
+            "transitions": [
+                {"from": "ontvangen", "to": "beoordeling"},
+                {"from": "beoordeling", "to": "afgerond", "label": "Goedkeuren"},
+                {"from": "beoordeling", "to": "afgewezen", "label": "Afwijzen", "role": "teamleider"},
+                {"from": "beoordeling", "to": "ontvangen", "label": "Aanvullen"},
+            ],
+        }))
+        .unwrap();
+        let commit = CommitBuilder::create("zaaktype-paspoort", &zaaktype).build();
+        submit_commit_event(&state, "zaaktype-paspoort", &commit)
+            .await
+            .unwrap();
+
+        let user = || auth_user("alice@example.com");
+        let Json(graph) = workflow_handler(
+            State(state.clone()),
+            user(),
+            Path("zaaktype-paspoort".to_string()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(graph.states.len(), 4);
+        let ontvangen = &graph.states[0];
+        assert!(ontvangen.initial && !ontvangen.is_final);
+        assert_eq!(ontvangen.sla_hours, Some(24));
+        assert_eq!(
+            graph.states[1].next,
+            vec!["afgerond", "afgewezen", "ontvangen"]
+        );
+        assert!(graph.states[2].is_final && graph.states[3].is_final);
+        assert_eq!(graph.roles, vec!["balie", "behandelaar", "teamleider"]);
+        assert_eq!(graph.transitions.len(), 4);
+
+        assert_eq!(
+            workflow_handler(State(state.clone()), user(), Path("issue-1".to_string()))
+                .await
+                .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+
+        let mut broken = zaaktype.clone();
+        broken.transitions[0].to = "verzonden".to_string();
+        assert!(workflow("zaaktype-paspoort", &broken).is_err());
+    }
+}